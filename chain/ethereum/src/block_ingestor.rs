@@ -1,6 +1,8 @@
 use lazy_static;
 use std::time::Duration;
 
+use graph::components::ethereum::health::ProviderHealthRegistry;
+use graph::components::ethereum::provider_id;
 use graph::prelude::*;
 use web3::types::*;
 
@@ -9,8 +11,30 @@ lazy_static! {
         .ok()
         .map(|s| s.eq_ignore_ascii_case("true"))
         .unwrap_or(false);
+
+    /// Reorgs that replace more than this many blocks are logged as warnings
+    static ref REORG_WARNING_DEPTH: u64 = std::env::var("GRAPH_ETHEREUM_REORG_WARNING_DEPTH")
+        .map(|s| s
+            .parse::<u64>()
+            .expect("invalid GRAPH_ETHEREUM_REORG_WARNING_DEPTH"))
+        .unwrap_or(3);
+
+    /// How often to poll for a new chain head while the provider announces
+    /// new heads through a subscription, in case the subscription silently
+    /// stops delivering them
+    static ref SUBSCRIPTION_POLLING_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ETHEREUM_SUBSCRIPTION_POLLING_INTERVAL")
+            .map(|s| s
+                .parse::<u64>()
+                .expect("invalid GRAPH_ETHEREUM_SUBSCRIPTION_POLLING_INTERVAL"))
+            .unwrap_or(30)
+    );
 }
 
+type NewHeads = futures03::compat::Compat01As03<
+    Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>,
+>;
+
 pub struct BlockIngestorMetrics {
     chain_head_number: Box<GaugeVec>,
 }
@@ -35,6 +59,99 @@ impl BlockIngestorMetrics {
     }
 }
 
+/// Metrics for the reorgs that block ingestors see. They are shared by the
+/// block ingestors of all networks
+pub struct ChainReorgMetrics {
+    reorgs: Box<CounterVec>,
+    reorg_depth: Box<HistogramVec>,
+}
+
+impl ChainReorgMetrics {
+    pub fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        Self {
+            reorgs: registry
+                .new_counter_vec(
+                    "ethereum_chain_reorgs",
+                    "Counts the reorgs of the chain head of each network",
+                    vec![String::from("network")],
+                )
+                .unwrap(),
+            reorg_depth: registry
+                .new_histogram_vec(
+                    "ethereum_chain_reorg_depth",
+                    "The number of blocks that reorgs of the chain head replaced",
+                    vec![String::from("network")],
+                    vec![1.0, 2.0, 3.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0],
+                )
+                .unwrap(),
+        }
+    }
+
+    fn observe_reorg(&self, network_name: &str, depth: u64) {
+        self.reorgs
+            .with_label_values(vec![network_name].as_slice())
+            .inc();
+        self.reorg_depth
+            .with_label_values(vec![network_name].as_slice())
+            .observe(depth as f64);
+    }
+}
+
+/// A change of the chain head that was not just an extension of the chain
+#[derive(Clone, Debug, PartialEq)]
+struct Reorg {
+    /// The number of blocks on the old chain that are not on the new chain
+    depth: u64,
+    /// The most recent block that the old and the new chain have in common
+    common_ancestor: EthereumBlockPointer,
+}
+
+/// Compare the chains ending in `old_head` and `new_head` going back at
+/// most `max_depth` blocks from the lower of the two to find their common
+/// ancestor. Return `None` if `new_head` descends from `old_head`, or if we
+/// can not tell since the common ancestor is further back or blocks are
+/// missing from the store
+fn find_reorg<S: ChainStore + ?Sized>(
+    chain_store: &S,
+    old_head: &EthereumBlockPointer,
+    new_head: &EthereumBlockPointer,
+    max_depth: u64,
+) -> Result<Option<Reorg>, Error> {
+    // The hash of the block with `number` on the chain ending in `head`
+    let ancestor_hash = |head: &EthereumBlockPointer, number: u64| -> Result<_, Error> {
+        if number == head.number {
+            return Ok(Some(head.hash));
+        }
+        Ok(chain_store
+            .ancestor_block(*head, head.number - number)?
+            .and_then(|block| block.block.hash))
+    };
+
+    let top = old_head.number.min(new_head.number);
+    let bottom = top.saturating_sub(max_depth);
+    for number in (bottom..=top).rev() {
+        match (
+            ancestor_hash(old_head, number)?,
+            ancestor_hash(new_head, number)?,
+        ) {
+            (Some(old_hash), Some(new_hash)) if old_hash == new_hash => {
+                let depth = old_head.number - number;
+                return Ok(if depth == 0 {
+                    None
+                } else {
+                    Some(Reorg {
+                        depth,
+                        common_ancestor: EthereumBlockPointer::from((old_hash, number)),
+                    })
+                });
+            }
+            (Some(_), Some(_)) => continue,
+            _ => return Ok(None),
+        }
+    }
+    Ok(None)
+}
+
 pub struct BlockIngestor<S>
 where
     S: ChainStore,
@@ -42,9 +159,14 @@ where
     chain_store: Arc<S>,
     eth_adapter: Arc<dyn EthereumAdapter>,
     ancestor_count: u64,
-    _network_name: String,
+    network_name: String,
+    /// The `provider_id` of the provider of `eth_adapter`
+    provider: String,
     logger: Logger,
     polling_interval: Duration,
+    reorg_metrics: Arc<ChainReorgMetrics>,
+    /// Where we record the heads that `eth_adapter` announces
+    provider_health: Arc<ProviderHealthRegistry>,
 }
 
 impl<S> BlockIngestor<S>
@@ -58,6 +180,8 @@ where
         network_name: String,
         logger_factory: &LoggerFactory,
         polling_interval: Duration,
+        reorg_metrics: Arc<ChainReorgMetrics>,
+        provider_health: Arc<ProviderHealthRegistry>,
     ) -> Result<BlockIngestor<S>, Error> {
         let logger = logger_factory.component_logger(
             "BlockIngestor",
@@ -68,19 +192,27 @@ where
             }),
         );
 
-        let logger = logger.new(o!("network_name" => network_name.clone()));
+        let provider = provider_id(eth_adapter.url_hostname());
+        let logger = logger.new(o!(
+            "network_name" => network_name.clone(),
+            "provider" => provider.clone(),
+        ));
 
         Ok(BlockIngestor {
             chain_store,
             eth_adapter,
             ancestor_count,
-            _network_name: network_name,
+            network_name,
+            provider,
             logger,
             polling_interval,
+            reorg_metrics,
+            provider_health,
         })
     }
 
     pub async fn into_polling_stream(self) {
+        let mut new_heads = None;
         loop {
             match self.do_poll().await {
                 // Some polls will fail due to transient issues
@@ -104,10 +236,47 @@ where
                 self.cleanup_cached_blocks()
             }
 
-            tokio::time::delay_for(self.polling_interval).await;
+            self.wait_for_new_head(&mut new_heads, *SUBSCRIPTION_POLLING_INTERVAL)
+                .await;
         }
     }
 
+    /// Wait until the provider announces a new chain head through the
+    /// subscription in `new_heads`, but at most for `max_wait`. Without a
+    /// subscription, wait for the polling interval and try to subscribe the
+    /// next time around; providers that do not support subscriptions are
+    /// simply polled
+    async fn wait_for_new_head(&self, new_heads: &mut Option<NewHeads>, max_wait: Duration) {
+        if new_heads.is_none() {
+            *new_heads = self.eth_adapter.new_heads().map(|heads| heads.compat());
+            if new_heads.is_some() {
+                debug!(self.logger, "Subscribed to new chain heads");
+            }
+        }
+
+        let lost = match new_heads {
+            None => {
+                tokio::time::delay_for(self.polling_interval).await;
+                return;
+            }
+            Some(heads) => match tokio::time::timeout(max_wait, heads.next()).await {
+                // No new head for a while; poll anyway
+                Err(_) => return,
+                Ok(Some(Ok(()))) => return,
+                Ok(Some(Err(e))) => e.to_string(),
+                Ok(None) => "the provider closed the subscription".to_owned(),
+            },
+        };
+
+        warn!(
+            self.logger,
+            "Lost subscription to new chain heads, polling until subscribing again";
+            "error" => lost
+        );
+        *new_heads = None;
+        tokio::time::delay_for(self.polling_interval).await;
+    }
+
     fn cleanup_cached_blocks(&self) {
         match self.chain_store.cleanup_cached_blocks(self.ancestor_count) {
             Ok((min_block, count)) => {
@@ -143,8 +312,15 @@ where
             .compat()
             .await?;
 
+        let latest_block: EthereumBlockPointer = latest_block.into();
+        self.provider_health.record_head(
+            &self.network_name,
+            self.eth_adapter.url_hostname(),
+            latest_block.clone(),
+        );
+
         // If latest block matches head block in store, nothing needs to be done
-        if Some(latest_block.into()) == head_block_ptr_opt {
+        if Some(latest_block) == head_block_ptr_opt {
             return Ok(());
         }
 
@@ -220,6 +396,46 @@ where
             let missing_blocks = self.get_blocks(&missing_block_hashes);
             missing_block_hashes = self.ingest_blocks(missing_blocks).await?;
         }
+
+        if let Some(old_head) = head_block_ptr_opt {
+            self.check_for_reorg(old_head)?;
+        }
+        Ok(())
+    }
+
+    /// Check whether the chain head moved from `old_head` to a block that
+    /// is not one of its descendants, and record the reorg if it did
+    fn check_for_reorg(&self, old_head: EthereumBlockPointer) -> Result<(), Error> {
+        let new_head = match self.chain_store.chain_head_ptr()? {
+            Some(new_head) if new_head != old_head => new_head,
+            _ => return Ok(()),
+        };
+        let reorg = match find_reorg(
+            self.chain_store.as_ref(),
+            &old_head,
+            &new_head,
+            self.ancestor_count,
+        )? {
+            Some(reorg) => reorg,
+            None => return Ok(()),
+        };
+
+        self.reorg_metrics
+            .observe_reorg(&self.network_name, reorg.depth);
+        let logger = self.logger.new(o!(
+            "depth" => reorg.depth,
+            "old_head_number" => old_head.number,
+            "old_head_hash" => format!("{:x}", old_head.hash),
+            "new_head_number" => new_head.number,
+            "new_head_hash" => format!("{:x}", new_head.hash),
+            "common_ancestor_number" => reorg.common_ancestor.number,
+            "common_ancestor_hash" => format!("{:x}", reorg.common_ancestor.hash),
+        ));
+        if reorg.depth > *REORG_WARNING_DEPTH {
+            warn!(logger, "Deep reorg of the chain head");
+        } else {
+            info!(logger, "Reorg of the chain head");
+        }
         Ok(())
     }
 
@@ -232,7 +448,10 @@ where
         &self,
         blocks: B,
     ) -> Result<Vec<H256>, EthereumAdapterError> {
-        self.chain_store.upsert_blocks(blocks).compat().await?;
+        self.chain_store
+            .upsert_blocks(self.provider.clone(), blocks)
+            .compat()
+            .await?;
 
         self.chain_store
             .attempt_chain_head_update(self.ancestor_count)
@@ -267,3 +486,211 @@ where
         Box::new(stream::futures_unordered(block_futures))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::store::MockChainStore;
+    use graph::mock::MockEthereumAdapter;
+    use std::collections::HashMap;
+
+    fn hash(fork: u64, number: u64) -> H256 {
+        H256::from_low_u64_be(fork << 32 | number)
+    }
+
+    fn ptr(fork: u64, number: u64) -> EthereumBlockPointer {
+        (hash(fork, number), number).into()
+    }
+
+    /// A chain store with the blocks `0..=10` of the main chain, and a fork
+    /// with blocks `7..=9` that branches off after block 6
+    fn forked_chain_store() -> MockChainStore {
+        let mut blocks = HashMap::new();
+        let mut add_block = |block_hash: H256, number: u64, parent_hash: H256| {
+            let block = EthereumBlock {
+                block: LightEthereumBlock {
+                    hash: Some(block_hash),
+                    number: Some(U64::from(number)),
+                    parent_hash,
+                    ..Default::default()
+                },
+                transaction_receipts: vec![],
+            };
+            blocks.insert(block_hash, block);
+        };
+        for number in 0..=10 {
+            add_block(hash(0, number), number, hash(0, number.saturating_sub(1)));
+        }
+        add_block(hash(1, 7), 7, hash(0, 6));
+        for number in 8..=9 {
+            add_block(hash(1, number), number, hash(1, number - 1));
+        }
+
+        let mut store = MockChainStore::new();
+        store.expect_ancestor_block().returning(move |ptr, offset| {
+            let mut block = blocks.get(&ptr.hash);
+            for _ in 0..offset {
+                block = block.and_then(|block| blocks.get(&block.block.parent_hash));
+            }
+            Ok(block.cloned())
+        });
+        store
+    }
+
+    #[test]
+    fn find_reorg_on_forked_chain() {
+        let store = forked_chain_store();
+
+        assert_eq!(
+            Some(Reorg {
+                depth: 4,
+                common_ancestor: ptr(0, 6),
+            }),
+            find_reorg(&store, &ptr(0, 10), &ptr(1, 9), 50).unwrap()
+        );
+        assert_eq!(
+            Some(Reorg {
+                depth: 2,
+                common_ancestor: ptr(0, 6),
+            }),
+            find_reorg(&store, &ptr(1, 8), &ptr(0, 10), 50).unwrap()
+        );
+        // Moving along the same chain is not a reorg
+        assert_eq!(
+            None,
+            find_reorg(&store, &ptr(0, 8), &ptr(0, 10), 50).unwrap()
+        );
+        // The common ancestor is too far back
+        assert_eq!(
+            None,
+            find_reorg(&store, &ptr(0, 10), &ptr(1, 9), 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn reorg_metrics_are_per_network() {
+        let logger = Logger::root(slog::Discard, o!());
+        let registry = Arc::new(graph_core::MetricsRegistry::new(
+            logger,
+            Arc::new(Registry::new()),
+        ));
+        let metrics = ChainReorgMetrics::new(registry);
+        metrics.observe_reorg("mainnet", 4);
+        metrics.observe_reorg("mainnet", 1);
+        metrics.observe_reorg("ropsten", 2);
+
+        assert_eq!(2.0, metrics.reorgs.with_label_values(&["mainnet"]).get());
+        assert_eq!(1.0, metrics.reorgs.with_label_values(&["ropsten"]).get());
+        let depth = metrics.reorg_depth.with_label_values(&["mainnet"]);
+        assert_eq!(2, depth.get_sample_count());
+        assert_eq!(5.0, depth.get_sample_sum());
+    }
+
+    fn new_ingestor(mut adapter: MockEthereumAdapter) -> BlockIngestor<MockChainStore> {
+        adapter
+            .expect_url_hostname()
+            .return_const("localhost".to_owned());
+        let logger = Logger::root(slog::Discard, o!());
+        let registry = Arc::new(graph_core::MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(Registry::new()),
+        ));
+        BlockIngestor::new(
+            Arc::new(MockChainStore::new()),
+            Arc::new(adapter),
+            50,
+            "mainnet".to_owned(),
+            &LoggerFactory::new(logger, None),
+            Duration::from_millis(10),
+            Arc::new(ChainReorgMetrics::new(registry)),
+            Arc::new(ProviderHealthRegistry::new()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resubscribes_to_new_heads() {
+        let mut adapter = MockEthereumAdapter::new();
+        let mut seq = mockall::Sequence::new();
+        // The first subscription announces one head and then disconnects,
+        // the second one announces one head, fails and then stays silent
+        adapter
+            .expect_new_heads()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|| Some(Box::new(stream::iter_ok(vec![()]))));
+        adapter
+            .expect_new_heads()
+            .times(1)
+            .in_sequence(&mut seq)
+            .returning(|| {
+                Some(Box::new(
+                    stream::iter_result(vec![
+                        Ok(()),
+                        Err(EthereumAdapterError::Unknown(format_err!(
+                            "connection reset"
+                        ))),
+                    ])
+                    .chain(stream::poll_fn(|| Ok(Async::NotReady))),
+                ))
+            });
+        let ingestor = new_ingestor(adapter);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut new_heads = None;
+            let max_wait = Duration::from_millis(50);
+
+            ingestor.wait_for_new_head(&mut new_heads, max_wait).await;
+            assert!(new_heads.is_some(), "announced a head");
+            ingestor.wait_for_new_head(&mut new_heads, max_wait).await;
+            assert!(new_heads.is_none(), "disconnected");
+
+            ingestor.wait_for_new_head(&mut new_heads, max_wait).await;
+            assert!(new_heads.is_some(), "resubscribed and announced a head");
+            // A failed subscription is dropped just like a closed one
+            ingestor.wait_for_new_head(&mut new_heads, max_wait).await;
+            assert!(new_heads.is_none(), "subscription failed");
+        });
+
+        // A subscription that stays silent is kept, and we poll whenever
+        // `max_wait` passes
+        let mut adapter = MockEthereumAdapter::new();
+        adapter
+            .expect_new_heads()
+            .times(1)
+            .returning(|| Some(Box::new(stream::poll_fn(|| Ok(Async::NotReady)))));
+        let ingestor = new_ingestor(adapter);
+        runtime.block_on(async {
+            let mut new_heads = None;
+            for _ in 0..3 {
+                ingestor
+                    .wait_for_new_head(&mut new_heads, Duration::from_millis(10))
+                    .await;
+                assert!(new_heads.is_some());
+            }
+        });
+    }
+
+    #[test]
+    fn polls_without_subscriptions() {
+        let mut adapter = MockEthereumAdapter::new();
+        // Transports without subscriptions are asked every time
+        adapter.expect_new_heads().times(2).returning(|| None);
+        let ingestor = new_ingestor(adapter);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut new_heads = None;
+            let start = std::time::Instant::now();
+            for _ in 0..2 {
+                ingestor
+                    .wait_for_new_head(&mut new_heads, Duration::from_secs(60))
+                    .await;
+                assert!(new_heads.is_none());
+            }
+            // Each wait takes the polling interval
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        });
+    }
+}
@@ -2,11 +2,12 @@ use std::cmp;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::mem;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
-use graph::components::ethereum::{
-    blocks_with_triggers, triggers_in_block, EthereumNetworks, NodeCapabilities,
+use graph::components::ethereum::stream_status::{
+    BlockStreamStatus, BlockStreamStatusHandle, BlockStreamStatusRegistry,
 };
+use graph::components::ethereum::{replay, EthereumNetworks, NodeCapabilities};
 use graph::data::subgraph::schema::{
     SubgraphDeploymentEntity, SubgraphEntity, SubgraphVersionEntity,
 };
@@ -14,6 +15,11 @@ use graph::prelude::{
     BlockStream as BlockStreamTrait, BlockStreamBuilder as BlockStreamBuilderTrait, *,
 };
 
+use crate::chain::{
+    blocks_with_triggers, triggers_in_block, EthereumBlockWithTriggers, EthereumChain,
+    EthereumTriggerFilter,
+};
+
 lazy_static! {
     /// Maximum number of blocks to request in each chunk.
     static ref MAX_BLOCK_RANGE_SIZE: u64 = std::env::var("GRAPH_ETHEREUM_MAX_BLOCK_RANGE_SIZE")
@@ -42,9 +48,17 @@ enum BlockStreamState {
     /// The BlockStream is emitting blocks that must be processed in order to bring the subgraph
     /// store up to date with the chain store.
     ///
-    /// Valid next states: Reconciliation
+    /// Valid next states: YieldedBlocks
     YieldingBlocks(VecDeque<EthereumBlockWithTriggers>),
 
+    /// The BlockStream has emitted all blocks from the last reconciliation and
+    /// told the consumer about it. The next reconciliation starts when the stream
+    /// is polled again, which gives the consumer a chance to write outstanding
+    /// blocks to the store first.
+    ///
+    /// Valid next states: Reconciliation
+    YieldedBlocks,
+
     /// The BlockStream experienced an error and is pausing before attempting to produce
     /// blocks again.
     ///
@@ -109,6 +123,7 @@ struct BlockStreamContext<S, C> {
     include_calls_in_blocks: bool,
     logger: Logger,
     metrics: Arc<BlockStreamMetrics>,
+    status: Arc<BlockStreamStatusHandle>,
     previous_triggers_per_block: f64,
     previous_block_range_size: u64,
     max_block_range_size: u64,
@@ -130,6 +145,7 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
             include_calls_in_blocks: self.include_calls_in_blocks,
             logger: self.logger.clone(),
             metrics: self.metrics.clone(),
+            status: self.status.clone(),
             previous_triggers_per_block: self.previous_triggers_per_block,
             previous_block_range_size: self.previous_block_range_size,
             max_block_range_size: self.max_block_range_size,
@@ -140,7 +156,10 @@ impl<S, C> Clone for BlockStreamContext<S, C> {
 pub struct BlockStream<S, C> {
     state: Mutex<BlockStreamState>,
     consecutive_err_count: u32,
+    /// The number of blocks we reverted since we last moved forward
+    revert_depth: u64,
     chain_head_update_stream: ChainHeadUpdateStream,
+    statuses: Arc<BlockStreamStatusRegistry>,
     ctx: BlockStreamContext<S, C>,
 }
 
@@ -153,7 +172,7 @@ enum NextBlocks {
 
 impl<S, C> BlockStream<S, C>
 where
-    S: Store,
+    S: EntityStore,
     C: ChainStore,
 {
     pub fn new(
@@ -170,11 +189,15 @@ where
         reorg_threshold: u64,
         logger: Logger,
         metrics: Arc<BlockStreamMetrics>,
+        statuses: Arc<BlockStreamStatusRegistry>,
     ) -> Self {
+        let status = statuses.register(&subgraph_id, &logger);
         BlockStream {
             state: Mutex::new(BlockStreamState::New),
             consecutive_err_count: 0,
+            revert_depth: 0,
             chain_head_update_stream: chain_store.chain_head_updates(),
+            statuses,
             ctx: BlockStreamContext {
                 subgraph_store,
                 chain_store,
@@ -189,6 +212,7 @@ where
                 start_blocks,
                 include_calls_in_blocks,
                 metrics,
+                status,
 
                 // A high number here forces a slow start, with a range of 1.
                 previous_triggers_per_block: 1_000_000.0,
@@ -201,7 +225,7 @@ where
 
 impl<S, C> BlockStreamContext<S, C>
 where
-    S: Store,
+    S: EntityStore,
     C: ChainStore,
 {
     /// Perform reconciliation steps until there are blocks to yield or we are up-to-date.
@@ -327,6 +351,22 @@ where
         if subgraph_ptr.is_none()
             || (head_ptr.number - subgraph_ptr.unwrap().number) > reorg_threshold
         {
+            // An earlier block stream, usually from before the node was
+            // restarted, may already have scanned the blocks after the
+            // subgraph ptr without finding triggers in them
+            let resume_from = subgraph_ptr.and_then(|ptr| {
+                ctx.subgraph_store
+                    .scan_watermark(&ctx.subgraph_id)
+                    .unwrap_or_else(|e| {
+                        warn!(ctx.logger, "Failed to load scanned blocks"; "error" => e.to_string());
+                        None
+                    })
+                    .and_then(|watermark| watermark.resume_from(&ptr))
+            });
+            let store = ctx.subgraph_store.cheap_clone();
+            let subgraph_id = ctx.subgraph_id.clone();
+            let logger = ctx.logger.clone();
+
             // Since we are beyond the reorg threshold, the Ethereum node knows what block has
             // been permanently assigned this block number.
             // This allows us to ask the node: does subgraph_ptr point to a block that was
@@ -374,8 +414,23 @@ where
                             // threshold.
 
                             // Start with first block after subgraph ptr; if the ptr is None,
-                            // then we start with the genesis block
-                            let from = subgraph_ptr.map_or(0, |ptr| ptr.number + 1);
+                            // then we start with the genesis block. Blocks that were
+                            // scanned before are skipped; processing still continues
+                            // from the subgraph ptr
+                            let from = match resume_from {
+                                Some(resume_from)
+                                    if resume_from <= head_ptr.number - reorg_threshold =>
+                                {
+                                    info!(
+                                        ctx.logger,
+                                        "Skipping blocks that were already scanned";
+                                        "from" => subgraph_ptr.map_or(0, |ptr| ptr.number + 1),
+                                        "to" => resume_from - 1,
+                                    );
+                                    resume_from
+                                }
+                                _ => subgraph_ptr.map_or(0, |ptr| ptr.number + 1),
+                            };
 
                             // Get the next subsequent data source start block to ensure the block range
                             // is aligned with data source.
@@ -418,6 +473,8 @@ where
                             };
                             let to = cmp::min(from + range_size - 1, to_limit);
 
+                            ctx.status
+                                .set(BlockStreamStatus::ScanningBlocks { from, to });
                             let section = ctx.metrics.stopwatch.start_section("scan_blocks");
                             info!(
                                 ctx.logger,
@@ -438,6 +495,18 @@ where
                                 )
                                 .map_ok(move |blocks| {
                                     section.end();
+                                    // There are no triggers between the subgraph ptr and
+                                    // the first block we found
+                                    if let (Some(ptr), Some(first)) = (subgraph_ptr, blocks.first())
+                                    {
+                                        record_scanned(
+                                            store.as_ref(),
+                                            &subgraph_id,
+                                            &logger,
+                                            ptr.number,
+                                            first.block.number(),
+                                        );
+                                    }
                                     ReconciliationStep::ProcessDescendantBlocks(blocks, range_size)
                                 })
                                 .boxed()
@@ -496,6 +565,9 @@ where
                         // so instead we will advance the subgraph ptr by one block.
                         // Note that head_ancestor is a child of subgraph_ptr.
                         let eth_adapter = self.eth_adapter.clone();
+                        ctx.status.set(BlockStreamStatus::FetchingBlock {
+                            number: subgraph_ptr.number + 1,
+                        });
 
                         let block_with_calls = if !self.include_calls_in_blocks
                             || head_ancestor.transaction_receipts.is_empty()
@@ -566,6 +638,9 @@ where
             ReconciliationStep::RevertBlock(subgraph_ptr) => {
                 let metrics = self.metrics.clone();
                 let reverted_block_number = subgraph_ptr.number as f64;
+                self.status.set(BlockStreamStatus::RevertingBlock {
+                    number: subgraph_ptr.number,
+                });
 
                 // We would like to move to the parent of the current block.
                 // This means we need to revert this block.
@@ -652,7 +727,7 @@ where
             let versions = self
                 .subgraph_store
                 .find(SubgraphVersionEntity::query().filter(EntityFilter::Equal(
-                    "deployment".to_owned(),
+                    "deployment".into(),
                     self.subgraph_id.to_string().into(),
                 )))?;
             let version_ids = versions
@@ -662,7 +737,7 @@ where
             let version_id_values = version_ids.iter().map(Value::from).collect::<Vec<_>>();
             ops.push(SubgraphVersionEntity::abort_unless(
                 "The same subgraph version entities must point to this deployment",
-                EntityFilter::Equal("deployment".to_owned(), self.subgraph_id.to_string().into()),
+                EntityFilter::Equal("deployment".into(), self.subgraph_id.to_string().into()),
                 version_ids.clone(),
             ));
 
@@ -670,7 +745,7 @@ where
             let subgraphs_to_update =
                 self.subgraph_store
                     .find(SubgraphEntity::query().filter(EntityFilter::In(
-                        "pendingVersion".to_owned(),
+                        "pendingVersion".into(),
                         version_id_values.clone(),
                     )))?;
             let subgraph_ids_to_update = subgraphs_to_update
@@ -679,7 +754,7 @@ where
                 .collect();
             ops.push(SubgraphEntity::abort_unless(
                 "The same subgraph entities must have these versions pending",
-                EntityFilter::In("pendingVersion".to_owned(), version_id_values.clone()),
+                EntityFilter::In("pendingVersion".into(), version_id_values.clone()),
                 subgraph_ids_to_update,
             ));
 
@@ -769,10 +844,52 @@ where
     }
 }
 
-impl<S: Store, C: ChainStore> BlockStreamTrait for BlockStream<S, C> {}
+/// Record that the block stream scanned all blocks between `after` and
+/// `next` without finding any triggers
+fn record_scanned<S: EntityStore>(
+    store: &S,
+    subgraph_id: &SubgraphDeploymentId,
+    logger: &Logger,
+    after: u64,
+    next: u64,
+) {
+    if next <= after + 1 {
+        return;
+    }
+    let watermark = ScanWatermark {
+        block: after as BlockNumber,
+        scanned_up_to: (next - 1) as BlockNumber,
+    };
+    if let Err(e) = store.set_scan_watermark(subgraph_id, watermark) {
+        // The blocks will simply be scanned again after a restart
+        warn!(logger, "Failed to record scanned blocks"; "error" => e.to_string());
+    }
+}
+
+impl<S, C> BlockStream<S, C> {
+    /// Record the reorg we just got through, if we reverted any blocks for
+    /// it; only once we move forward again do we know how deep it was
+    fn finish_revert(&mut self) {
+        if self.revert_depth > 0 {
+            debug!(self.ctx.logger, "Reverted blocks to get back to main chain";
+                   "depth" => self.revert_depth);
+            self.ctx.metrics.observe_revert(self.revert_depth);
+            self.revert_depth = 0;
+        }
+    }
+}
+
+impl<S, C> Drop for BlockStream<S, C> {
+    fn drop(&mut self) {
+        self.statuses
+            .remove(&self.ctx.subgraph_id, &self.ctx.status);
+    }
+}
+
+impl<S: EntityStore, C: ChainStore> BlockStreamTrait<EthereumChain> for BlockStream<S, C> {}
 
-impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
-    type Item = BlockStreamEvent;
+impl<S: EntityStore, C: ChainStore> Stream for BlockStream<S, C> {
+    type Item = BlockStreamEvent<EthereumChain>;
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
@@ -813,6 +930,7 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                                 );
                             }
                             self.consecutive_err_count = 0;
+                            self.finish_revert();
 
                             let total_triggers =
                                 next_blocks.iter().map(|b| b.triggers.len()).sum::<usize>();
@@ -834,8 +952,10 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                         Ok(Async::Ready(NextBlocks::Done)) => {
                             // Reset error count
                             self.consecutive_err_count = 0;
+                            self.finish_revert();
 
                             // Switch to idle
+                            self.ctx.status.set(BlockStreamStatus::WaitingForChainHead);
                             state = BlockStreamState::Idle;
 
                             // Poll for chain head update
@@ -843,6 +963,7 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                         }
 
                         Ok(Async::Ready(NextBlocks::Revert)) => {
+                            self.revert_depth += 1;
                             state = BlockStreamState::Reconciliation(self.ctx.next_blocks());
                             break Ok(Async::Ready(Some(BlockStreamEvent::Revert)));
                         }
@@ -861,6 +982,10 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
 
                             // Pause before trying again
                             let secs = (5 * self.consecutive_err_count).max(120) as u64;
+                            self.ctx.status.set(BlockStreamStatus::BackingOff {
+                                error: e.to_string(),
+                                until: SystemTime::now() + Duration::from_secs(secs),
+                            });
                             state = BlockStreamState::RetryAfterDelay(Box::new(
                                 tokio::time::delay_for(Duration::from_secs(secs))
                                     .map(Ok)
@@ -876,22 +1001,47 @@ impl<S: Store, C: ChainStore> Stream for BlockStream<S, C> {
                     match next_blocks.pop_front() {
                         // Yield one block
                         Some(next_block) => {
+                            // Blocks that come from the same range scan have no
+                            // triggers in between them. Should the node stop once
+                            // this block is processed, the next block stream can
+                            // continue scanning from the block after it
+                            if let Some(following) = next_blocks.front() {
+                                record_scanned(
+                                    self.ctx.subgraph_store.as_ref(),
+                                    &self.ctx.subgraph_id,
+                                    &self.ctx.logger,
+                                    next_block.block.number(),
+                                    following.block.number(),
+                                );
+                            }
+
+                            // The consumer processes the block before it
+                            // polls us again
+                            self.ctx.status.set(BlockStreamStatus::ProcessingBlock {
+                                number: next_block.block.number(),
+                            });
                             state = BlockStreamState::YieldingBlocks(next_blocks);
                             break Ok(Async::Ready(Some(BlockStreamEvent::Block(next_block))));
                         }
 
                         // Done yielding blocks
                         None => {
-                            // Restart reconciliation until more blocks or done
-                            let next_blocks_future = self.ctx.next_blocks();
-                            state = BlockStreamState::Reconciliation(next_blocks_future);
-
-                            // Poll the next_blocks() future
-                            continue;
+                            state = BlockStreamState::YieldedBlocks;
+                            break Ok(Async::Ready(Some(BlockStreamEvent::EndOfBlocks)));
                         }
                     }
                 }
 
+                // The consumer has seen all blocks
+                BlockStreamState::YieldedBlocks => {
+                    // Restart reconciliation until more blocks or done
+                    let next_blocks_future = self.ctx.next_blocks();
+                    state = BlockStreamState::Reconciliation(next_blocks_future);
+
+                    // Poll the next_blocks() future
+                    continue;
+                }
+
                 // Pausing after an error, before looking for more blocks
                 BlockStreamState::RetryAfterDelay(mut delay) => match delay.poll() {
                     Ok(Async::Ready(())) | Err(_) => {
@@ -959,6 +1109,7 @@ pub struct BlockStreamBuilder<S, C, M> {
     node_id: NodeId,
     reorg_threshold: u64,
     metrics_registry: Arc<M>,
+    statuses: Arc<BlockStreamStatusRegistry>,
 }
 
 impl<S, C, M> Clone for BlockStreamBuilder<S, C, M> {
@@ -970,13 +1121,14 @@ impl<S, C, M> Clone for BlockStreamBuilder<S, C, M> {
             node_id: self.node_id.clone(),
             reorg_threshold: self.reorg_threshold,
             metrics_registry: self.metrics_registry.clone(),
+            statuses: self.statuses.clone(),
         }
     }
 }
 
 impl<S, C, M> BlockStreamBuilder<S, C, M>
 where
-    S: Store,
+    S: EntityStore,
     C: ChainStore,
     M: MetricsRegistry,
 {
@@ -987,6 +1139,7 @@ where
         node_id: NodeId,
         reorg_threshold: u64,
         metrics_registry: Arc<M>,
+        statuses: Arc<BlockStreamStatusRegistry>,
     ) -> Self {
         BlockStreamBuilder {
             subgraph_store,
@@ -995,16 +1148,18 @@ where
             node_id,
             reorg_threshold,
             metrics_registry,
+            statuses,
         }
     }
 }
 
 impl<S, C, M> BlockStreamBuilderTrait for BlockStreamBuilder<S, C, M>
 where
-    S: Store,
+    S: EntityStore,
     C: ChainStore,
     M: MetricsRegistry,
 {
+    type Chain = EthereumChain;
     type Stream = BlockStream<S, C>;
 
     fn build(
@@ -1013,12 +1168,16 @@ where
         deployment_id: SubgraphDeploymentId,
         network_name: String,
         start_blocks: Vec<u64>,
-        log_filter: EthereumLogFilter,
-        call_filter: EthereumCallFilter,
-        block_filter: EthereumBlockFilter,
-        include_calls_in_blocks: bool,
+        filter: EthereumTriggerFilter,
         metrics: Arc<BlockStreamMetrics>,
     ) -> Self::Stream {
+        let EthereumTriggerFilter {
+            log: log_filter,
+            call: call_filter,
+            block: block_filter,
+            include_calls_in_blocks,
+        } = filter;
+
         let logger = logger.new(o!(
             "component" => "BlockStream",
         ));
@@ -1043,13 +1202,25 @@ where
             .expect(&format!(
                 "no eth adapter that supports network: {} with {}",
                 &network_name, &requirements
-            ));
+            ))
+            .clone();
+
+        // The instance manager already got the triggers adapter of the
+        // deployment, which loaded its recording if it is replayed, so this
+        // only fails if the recording directory is no longer writable
+        let eth_adapter = replay::adapter_for_deployment(&deployment_id, eth_adapter)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "failed to record or replay the Ethereum requests of {}: {}",
+                    deployment_id, e
+                )
+            });
 
         // Create the actual subgraph-specific block stream
         BlockStream::new(
             self.subgraph_store.clone(),
             chain_store,
-            eth_adapter.clone(),
+            eth_adapter,
             self.node_id.clone(),
             deployment_id,
             log_filter,
@@ -1060,6 +1231,371 @@ where
             self.reorg_threshold,
             logger,
             metrics,
+            self.statuses.clone(),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::ethereum::EthGetLogsFilter;
+    use graph::components::store::MockChainStore;
+    use graph::mock::MockEthereumAdapter;
+    use graph::prelude::web3::types::{Address, Bytes, Log, H256, U64};
+    use std::collections::BTreeMap;
+
+    /// The chain head; the stream scans ranges up to `HEAD - REORG_THRESHOLD`
+    const HEAD: u64 = 1000;
+    const REORG_THRESHOLD: u64 = 50;
+
+    struct NoopRegistry;
+
+    impl MetricsRegistry for NoopRegistry {
+        fn register(&self, _name: &str, _c: Box<dyn Collector>) {}
+
+        fn global_counter(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Counter, PrometheusError> {
+            Counter::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn global_gauge(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Gauge, PrometheusError> {
+            Gauge::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn unregister(&self, _: Box<dyn Collector>) {}
+    }
+
+    /// A store that only remembers the block pointer and the scan
+    /// watermark of the deployment, which is all a block stream needs
+    #[derive(Default)]
+    struct PtrStore {
+        ptr: Mutex<Option<EthereumBlockPointer>>,
+        watermark: Mutex<Option<ScanWatermark>>,
+    }
+
+    impl EntityStore for PtrStore {
+        fn block_ptr(
+            &self,
+            _subgraph_id: SubgraphDeploymentId,
+        ) -> Result<Option<EthereumBlockPointer>, Error> {
+            Ok(*self.ptr.lock().unwrap())
+        }
+
+        fn supports_proof_of_indexing<'a>(
+            &'a self,
+            _subgraph_id: &'a SubgraphDeploymentId,
+        ) -> DynTryFuture<'a, bool> {
+            unimplemented!()
+        }
+
+        fn get_proof_of_indexing<'a>(
+            &'a self,
+            _subgraph_id: &'a SubgraphDeploymentId,
+            _indexer: &'a Option<Address>,
+            _block_hash: H256,
+        ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+            unimplemented!()
+        }
+
+        fn get(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn get_many(
+            &self,
+            _subgraph_id: &SubgraphDeploymentId,
+            _ids_for_type: BTreeMap<&str, Vec<&str>>,
+        ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+            unimplemented!()
+        }
+
+        fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn find_ens_name(&self, _hash: &str) -> Result<Option<String>, QueryExecutionError> {
+            unimplemented!()
+        }
+
+        fn transact_block_operations(
+            &self,
+            _subgraph_id: SubgraphDeploymentId,
+            block_ptr_to: EthereumBlockPointer,
+            _mods: Vec<EntityModification>,
+            _stopwatch: StopwatchMetrics,
+        ) -> Result<bool, StoreError> {
+            *self.ptr.lock().unwrap() = Some(block_ptr_to);
+            Ok(false)
+        }
+
+        fn revert_block_operations(
+            &self,
+            _subgraph_id: SubgraphDeploymentId,
+            _block_ptr_from: EthereumBlockPointer,
+            _block_ptr_to: EthereumBlockPointer,
+        ) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+            unimplemented!()
+        }
+
+        fn scan_watermark(
+            &self,
+            _subgraph_id: &SubgraphDeploymentId,
+        ) -> Result<Option<ScanWatermark>, StoreError> {
+            Ok(*self.watermark.lock().unwrap())
+        }
+
+        fn set_scan_watermark(
+            &self,
+            _subgraph_id: &SubgraphDeploymentId,
+            watermark: ScanWatermark,
+        ) -> Result<(), StoreError> {
+            *self.watermark.lock().unwrap() = Some(watermark);
+            Ok(())
+        }
+
+        fn block_number(
+            &self,
+            _subgraph_id: &SubgraphDeploymentId,
+            _block_hash: H256,
+        ) -> Result<Option<BlockNumber>, StoreError> {
+            unimplemented!()
+        }
+
+        fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+            unimplemented!()
+        }
+    }
+
+    fn deployment() -> SubgraphDeploymentId {
+        SubgraphDeploymentId::new("QmScanWatermark").unwrap()
+    }
+
+    fn hash(number: u64) -> H256 {
+        H256::from_low_u64_be(number + 1)
+    }
+
+    fn log(number: u64) -> Log {
+        Log {
+            address: Address::from_low_u64_be(1),
+            topics: vec![H256::from_low_u64_be(2)],
+            data: Bytes::default(),
+            block_hash: Some(hash(number)),
+            block_number: Some(U64::from(number)),
+            transaction_hash: Some(H256::zero()),
+            transaction_index: Some(0.into()),
+            log_index: Some(0.into()),
+            transaction_log_index: Some(0.into()),
+            log_type: Some("".into()),
+            removed: Some(false),
+        }
+    }
+
+    /// The ranges that were scanned for logs
+    type Scans = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    /// An adapter for a chain that has logs in the blocks `with_logs`
+    fn adapter(with_logs: &'static [u64], scans: Scans) -> Arc<dyn EthereumAdapter> {
+        let mut adapter = MockEthereumAdapter::new();
+        adapter
+            .expect_is_on_main_chain()
+            .returning(|_, _, _, _| Box::new(future::ok(true)));
+        adapter
+            .expect_logs_in_block_range()
+            .returning(move |_, _, from, to, _| {
+                scans.lock().unwrap().push((from, to));
+                let logs = with_logs
+                    .iter()
+                    .filter(|number| (from..=to).contains(*number))
+                    .map(|number| log(*number))
+                    .collect();
+                futures03::future::ok::<_, Error>(logs).boxed()
+            });
+        adapter
+            .expect_block_hash_by_block_number()
+            .returning(|_, _, number, _| Box::new(future::ok(Some(hash(number)))));
+        adapter.expect_load_blocks().returning(|_, _, hashes| {
+            let blocks: Vec<_> = hashes
+                .into_iter()
+                .map(|hash| LightEthereumBlock {
+                    hash: Some(hash),
+                    number: Some(U64::from(hash.to_low_u64_be() - 1)),
+                    ..Default::default()
+                })
+                .collect();
+            Box::new(stream::iter_ok(blocks))
+        });
+        Arc::new(adapter)
+    }
+
+    fn block_stream(
+        store: Arc<PtrStore>,
+        adapter: Arc<dyn EthereumAdapter>,
+    ) -> BlockStream<PtrStore, MockChainStore> {
+        let mut chain_store = MockChainStore::new();
+        chain_store
+            .expect_chain_head_ptr()
+            .returning(|| Ok(Some((hash(HEAD), HEAD).into())));
+        chain_store
+            .expect_chain_head_updates()
+            .returning(|| Box::new(stream::empty()));
+
+        let logger = graph::log::logger(false);
+        let id = deployment();
+        let registry = Arc::new(NoopRegistry);
+        let metrics = Arc::new(BlockStreamMetrics::new(
+            registry.clone(),
+            Arc::new(SubgraphEthRpcMetrics::new(registry.clone(), id.as_str())),
+            &id,
+            StopwatchMetrics::new(logger.clone(), id.clone(), registry),
+        ));
+        let log_filter = EthereumLogFilter::from(EthGetLogsFilter {
+            contracts: vec![Address::from_low_u64_be(1)],
+            event_signatures: vec![H256::from_low_u64_be(2)],
+        });
+
+        BlockStream::new(
+            store,
+            Arc::new(chain_store),
+            adapter,
+            NodeId::new("test").unwrap(),
+            id,
+            log_filter,
+            EthereumCallFilter::from_iter(vec![]),
+            EthereumBlockFilter::default(),
+            vec![],
+            false,
+            REORG_THRESHOLD,
+            logger,
+            metrics,
+            Arc::new(BlockStreamStatusRegistry::new()),
+        )
+    }
+
+    /// Process blocks from `stream` like the instance manager does, until
+    /// the deployment is at block `stop_at`, and return the number and the
+    /// triggers of each processed block
+    fn process_until(
+        stream: BlockStream<PtrStore, MockChainStore>,
+        store: &PtrStore,
+        stop_at: u64,
+    ) -> Vec<(u64, Vec<EthereumTrigger>)> {
+        let stopwatch = StopwatchMetrics::new(
+            graph::log::logger(false),
+            deployment(),
+            Arc::new(NoopRegistry),
+        );
+        let mut processed = vec![];
+        for event in stream.wait() {
+            if let BlockStreamEvent::Block(block) = event.unwrap() {
+                let ptr = block.ptr();
+                store
+                    .transact_block_operations(deployment(), ptr, vec![], stopwatch.clone())
+                    .unwrap();
+                processed.push((ptr.number, block.triggers));
+                if ptr.number >= stop_at {
+                    break;
+                }
+            }
+        }
+        processed
+    }
+
+    #[test]
+    fn restart_does_not_rescan_blocks_without_triggers() {
+        const WITH_LOGS: &[u64] = &[5, 100, 400];
+
+        let store = Arc::new(PtrStore::default());
+        let scans = Scans::default();
+
+        // Stop at block 100; the scan that found it also found block 110
+        process_until(
+            block_stream(store.clone(), adapter(WITH_LOGS, scans.clone())),
+            &store,
+            100,
+        );
+        assert_eq!(Some(&(11, 110)), scans.lock().unwrap().last());
+        let scanned_before = scans.lock().unwrap().len();
+
+        // Simulate a restart with a new block stream
+        process_until(
+            block_stream(store.clone(), adapter(WITH_LOGS, scans.clone())),
+            &store,
+            400,
+        );
+        let scans = scans.lock().unwrap().clone();
+        assert_eq!((110, 110), scans[scanned_before]);
+
+        // Only block 110, which ends the range that the first stream
+        // stopped in, was scanned twice
+        let mut scanned: Vec<u64> = scans.iter().flat_map(|(from, to)| *from..=*to).collect();
+        scanned.sort();
+        let twice: Vec<u64> = scanned
+            .windows(2)
+            .filter(|pair| pair[0] == pair[1])
+            .map(|pair| pair[0])
+            .collect();
+        assert_eq!(vec![110], twice);
+        scanned.dedup();
+        assert_eq!((0..=950).collect::<Vec<_>>(), scanned);
+    }
+
+    #[test]
+    fn replays_recorded_block_stream() {
+        use graph::components::ethereum::replay::{
+            Recorder, Recording, RecordingEthereumAdapter, ReplayEthereumAdapter,
+        };
+
+        const WITH_LOGS: &[u64] = &[5, 100, 400];
+
+        let path = std::env::temp_dir().join(format!(
+            "block-stream-recording-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        // Index up to block 400 while recording the requests to Ethereum
+        let scans = Scans::default();
+        let recorder = Arc::new(Recorder::create(&path).unwrap());
+        let recording: Arc<dyn EthereumAdapter> = Arc::new(RecordingEthereumAdapter::new(
+            adapter(WITH_LOGS, scans.clone()),
+            recorder,
+        ));
+        let store = Arc::new(PtrStore::default());
+        let recorded = process_until(block_stream(store.clone(), recording), &store, 400);
+        assert!(!scans.lock().unwrap().is_empty());
+
+        // Index the same blocks again, but only from the recording
+        let replay: Arc<dyn EthereumAdapter> = Arc::new(ReplayEthereumAdapter::new(Arc::new(
+            Recording::load(&path).unwrap(),
+        )));
+        let store = Arc::new(PtrStore::default());
+        let replayed = process_until(block_stream(store.clone(), replay), &store, 400);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(recorded, replayed);
+        let with_triggers: Vec<u64> = replayed
+            .iter()
+            .filter(|(_, triggers)| !triggers.is_empty())
+            .map(|(number, _)| *number)
+            .collect();
+        assert_eq!(WITH_LOGS.to_vec(), with_triggers);
+    }
+}
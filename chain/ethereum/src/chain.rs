@@ -0,0 +1,442 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use graph::blockchain::{Block, BlockWithTriggers};
+use graph::components::ethereum::{replay, EthereumNetworks};
+use graph::prelude::web3::types::{Log, H256};
+use graph::prelude::*;
+
+/// Ethereum, the chain of the data sources of kind `ethereum/contract`
+#[derive(Clone)]
+pub struct EthereumChain {
+    eth_networks: EthereumNetworks,
+    chain_stores: HashMap<String, Arc<dyn ChainStore>>,
+}
+
+impl EthereumChain {
+    pub fn new(
+        eth_networks: EthereumNetworks,
+        chain_stores: HashMap<String, Arc<dyn ChainStore>>,
+    ) -> Self {
+        EthereumChain {
+            eth_networks,
+            chain_stores,
+        }
+    }
+}
+
+impl fmt::Debug for EthereumChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EthereumChain")
+            .field("networks", &self.chain_stores.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Blockchain for EthereumChain {
+    const KIND: &'static str = "ethereum/contract";
+
+    type Pointer = EthereumBlockPointer;
+    type Block = BlockFinality;
+    type MappingBlock = LightEthereumBlock;
+    type DataSource = DataSource;
+    type TriggerData = EthereumTrigger;
+    type TriggerFilter = EthereumTriggerFilter;
+
+    fn data_source(data_source: &DataSource) -> Result<DataSource, Error> {
+        Ok(data_source.clone())
+    }
+
+    fn triggers_adapter(
+        &self,
+        manifest: &SubgraphManifest,
+        metrics: &BlockStreamMetrics,
+    ) -> Result<Arc<dyn TriggersAdapter<Self>>, Error> {
+        let network = manifest.network_name();
+        let required_capabilities = manifest.required_ethereum_capabilities();
+        let eth_adapter = self
+            .eth_networks
+            .adapter_with_capabilities(network.clone(), &required_capabilities)
+            .map_err(|e| {
+                format_err!(
+                    "no Ethereum adapter for network {} with required capabilities {}: {}",
+                    network,
+                    required_capabilities,
+                    e
+                )
+            })?
+            .clone();
+        let eth_adapter = replay::adapter_for_deployment(&manifest.id, eth_adapter)?;
+        let chain_store = self
+            .chain_stores
+            .get(&network)
+            .ok_or_else(|| format_err!("no chain store for network {}", network))?
+            .clone();
+        Ok(Arc::new(EthereumTriggersAdapter::new(
+            eth_adapter,
+            chain_store,
+            metrics.ethrpc_metrics.clone(),
+        )))
+    }
+}
+
+pub type EthereumBlockWithTriggers = BlockWithTriggers<EthereumChain>;
+
+impl Block<EthereumChain> for BlockFinality {
+    fn ptr(&self) -> EthereumBlockPointer {
+        EthereumBlockPointer::from(self)
+    }
+
+    fn parent_ptr(&self) -> Option<EthereumBlockPointer> {
+        match self {
+            BlockFinality::Final(block) => block.parent_ptr(),
+            BlockFinality::NonFinal(block) => block.ethereum_block.block.parent_ptr(),
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        match self {
+            BlockFinality::Final(_) => true,
+            BlockFinality::NonFinal(_) => false,
+        }
+    }
+
+    fn mapping_block(&self) -> LightEthereumBlock {
+        self.light_block()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EthereumTriggerFilter {
+    pub log: EthereumLogFilter,
+    pub call: EthereumCallFilter,
+    pub block: EthereumBlockFilter,
+
+    /// Whether blocks need to be fetched with the calls in them, which
+    /// requires an Ethereum node that supports traces
+    pub include_calls_in_blocks: bool,
+}
+
+impl TriggerFilter<EthereumChain> for EthereumTriggerFilter {
+    fn from_manifest(manifest: &SubgraphManifest) -> Result<Self, Error> {
+        Ok(EthereumTriggerFilter {
+            // Mappings with call handlers or block handlers with call
+            // filters need the calls in all blocks
+            include_calls_in_blocks: manifest.requires_traces(),
+            ..Self::from_data_sources(manifest.data_sources.iter())
+        })
+    }
+
+    fn from_data_sources<'a>(data_sources: impl Iterator<Item = &'a DataSource> + Clone) -> Self {
+        EthereumTriggerFilter {
+            log: EthereumLogFilter::from_data_sources(data_sources.clone()),
+            call: EthereumCallFilter::from_data_sources(data_sources.clone()),
+            block: EthereumBlockFilter::from_data_sources(data_sources),
+            include_calls_in_blocks: false,
+        }
+    }
+
+    fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a DataSource> + Clone) {
+        self.log
+            .extend(EthereumLogFilter::from_data_sources(data_sources.clone()));
+        self.call
+            .extend(EthereumCallFilter::from_data_sources(data_sources.clone()));
+        self.block
+            .extend(EthereumBlockFilter::from_data_sources(data_sources));
+    }
+}
+
+pub struct EthereumTriggersAdapter {
+    eth_adapter: Arc<dyn EthereumAdapter>,
+    chain_store: Arc<dyn ChainStore>,
+    ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+}
+
+impl EthereumTriggersAdapter {
+    pub fn new(
+        eth_adapter: Arc<dyn EthereumAdapter>,
+        chain_store: Arc<dyn ChainStore>,
+        ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
+    ) -> Self {
+        EthereumTriggersAdapter {
+            eth_adapter,
+            chain_store,
+            ethrpc_metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl TriggersAdapter<EthereumChain> for EthereumTriggersAdapter {
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: BlockFinality,
+        filter: EthereumTriggerFilter,
+    ) -> Result<EthereumBlockWithTriggers, Error> {
+        triggers_in_block(
+            self.eth_adapter.clone(),
+            logger.clone(),
+            self.chain_store.clone(),
+            self.ethrpc_metrics.clone(),
+            filter.log,
+            filter.call,
+            filter.block,
+            block,
+        )
+        .await
+    }
+}
+
+fn parse_log_triggers(
+    log_filter: EthereumLogFilter,
+    block: &EthereumBlock,
+) -> Vec<EthereumTrigger> {
+    block
+        .transaction_receipts
+        .iter()
+        .flat_map(move |receipt| {
+            let log_filter = log_filter.clone();
+            receipt
+                .logs
+                .iter()
+                .filter(move |log| log_filter.matches(log))
+                .map(move |log| EthereumTrigger::Log(log.clone()))
+        })
+        .collect()
+}
+
+fn parse_call_triggers(
+    call_filter: EthereumCallFilter,
+    block: &EthereumBlockWithCalls,
+) -> Vec<EthereumTrigger> {
+    block
+        .calls
+        .iter()
+        .filter(move |call| call_filter.matches(call))
+        .map(move |call| EthereumTrigger::Call(call.clone()))
+        .collect()
+}
+
+fn parse_block_triggers(
+    block_filter: EthereumBlockFilter,
+    block: &EthereumBlockWithCalls,
+) -> Vec<EthereumTrigger> {
+    let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+    let trigger_every_block = block_filter.trigger_every_block;
+    let call_filter = EthereumCallFilter::from(block_filter);
+    let mut triggers = block
+        .calls
+        .iter()
+        .filter(move |call| call_filter.matches(call))
+        .map(move |call| {
+            EthereumTrigger::Block(block_ptr, EthereumBlockTriggerType::WithCallTo(call.to))
+        })
+        .collect::<Vec<EthereumTrigger>>();
+    if trigger_every_block {
+        triggers.push(EthereumTrigger::Block(
+            block_ptr,
+            EthereumBlockTriggerType::Every,
+        ));
+    }
+    triggers
+}
+
+pub async fn triggers_in_block(
+    adapter: Arc<dyn EthereumAdapter>,
+    logger: Logger,
+    chain_store: Arc<dyn ChainStore>,
+    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+    log_filter: EthereumLogFilter,
+    call_filter: EthereumCallFilter,
+    block_filter: EthereumBlockFilter,
+    ethereum_block: BlockFinality,
+) -> Result<EthereumBlockWithTriggers, Error> {
+    match &ethereum_block {
+        BlockFinality::Final(block) => {
+            let mut blocks = blocks_with_triggers(
+                adapter,
+                logger,
+                chain_store,
+                subgraph_metrics,
+                block.number(),
+                block.number(),
+                log_filter,
+                call_filter,
+                block_filter,
+            )
+            .await?;
+            assert!(blocks.len() <= 1);
+
+            Ok(blocks
+                .pop()
+                .unwrap_or_else(|| EthereumBlockWithTriggers::new(ethereum_block, vec![])))
+        }
+        BlockFinality::NonFinal(full_block) => {
+            let mut triggers = Vec::new();
+            triggers.append(&mut parse_log_triggers(
+                log_filter,
+                &full_block.ethereum_block,
+            ));
+            triggers.append(&mut parse_call_triggers(call_filter, &full_block));
+            triggers.append(&mut parse_block_triggers(block_filter, &full_block));
+            Ok(EthereumBlockWithTriggers::new(ethereum_block, triggers))
+        }
+    }
+}
+
+/// Returns blocks with triggers, corresponding to the specified range and filters.
+/// If a block contains no triggers, there may be no corresponding item in the stream.
+/// However the `to` block will always be present, even if triggers are empty.
+///
+/// Careful: don't use this function without considering race conditions.
+/// Chain reorgs could happen at any time, and could affect the answer received.
+/// Generally, it is only safe to use this function with blocks that have received enough
+/// confirmations to guarantee no further reorgs, **and** where the Ethereum node is aware of
+/// those confirmations.
+/// If the Ethereum node is far behind in processing blocks, even old blocks can be subject to
+/// reorgs.
+/// It is recommended that `to` be far behind the block number of latest block the Ethereum
+/// node is aware of.
+pub async fn blocks_with_triggers(
+    adapter: Arc<dyn EthereumAdapter>,
+    logger: Logger,
+    chain_store: Arc<dyn ChainStore>,
+    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+    from: u64,
+    to: u64,
+    log_filter: EthereumLogFilter,
+    call_filter: EthereumCallFilter,
+    block_filter: EthereumBlockFilter,
+) -> Result<Vec<EthereumBlockWithTriggers>, Error> {
+    // Each trigger filter needs to be queried for the same block range
+    // and the blocks yielded need to be deduped. If any error occurs
+    // while searching for a trigger type, the entire operation fails.
+    let eth = adapter.clone();
+    let mut trigger_futs: futures::stream::FuturesUnordered<
+        Box<dyn Future<Item = Vec<EthereumTrigger>, Error = Error> + Send>,
+    > = futures::stream::FuturesUnordered::new();
+
+    // Scan the block range from triggers to find relevant blocks
+    if !log_filter.is_empty() {
+        trigger_futs.push(Box::new(
+            eth.logs_in_block_range(&logger, subgraph_metrics.clone(), from, to, log_filter)
+                .map_ok(|logs: Vec<Log>| logs.into_iter().map(EthereumTrigger::Log).collect())
+                .compat(),
+        ))
+    }
+
+    if !call_filter.is_empty() {
+        trigger_futs.push(Box::new(
+            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
+                .map(EthereumTrigger::Call)
+                .collect(),
+        ));
+    }
+
+    if block_filter.trigger_every_block {
+        trigger_futs.push(Box::new(
+            adapter
+                .block_range_to_ptrs(logger.clone(), from, to)
+                .map(move |ptrs| {
+                    ptrs.into_iter()
+                        .map(|ptr| EthereumTrigger::Block(ptr, EthereumBlockTriggerType::Every))
+                        .collect()
+                }),
+        ))
+    } else if !block_filter.contract_addresses.is_empty() {
+        // To determine which blocks include a call to addresses
+        // in the block filter, transform the `block_filter` into
+        // a `call_filter` and run `blocks_with_calls`
+        let call_filter = EthereumCallFilter::from(block_filter);
+        trigger_futs.push(Box::new(
+            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
+                .map(|call| {
+                    EthereumTrigger::Block(
+                        EthereumBlockPointer::from(&call),
+                        EthereumBlockTriggerType::WithCallTo(call.to),
+                    )
+                })
+                .collect(),
+        ));
+    }
+
+    let logger1 = logger.cheap_clone();
+    let logger2 = logger.cheap_clone();
+    let eth_clone = eth.cheap_clone();
+    let (triggers, to_hash) = trigger_futs
+        .concat2()
+        .join(
+            adapter
+                .clone()
+                .block_hash_by_block_number(&logger, chain_store.clone(), to, true)
+                .then(move |to_hash| match to_hash {
+                    Ok(n) => n.ok_or_else(|| {
+                        warn!(logger2,
+                                "Ethereum endpoint is behind";
+                                "url" => eth_clone.url_hostname()
+                        );
+                        format_err!("Block {} not found in the chain", to)
+                    }),
+                    Err(e) => Err(e),
+                }),
+        )
+        .compat()
+        .await?;
+
+    let mut block_hashes: HashSet<H256> =
+        triggers.iter().map(EthereumTrigger::block_hash).collect();
+    let mut triggers_by_block: HashMap<u64, Vec<EthereumTrigger>> =
+        triggers.into_iter().fold(HashMap::new(), |mut map, t| {
+            map.entry(t.block_number()).or_default().push(t);
+            map
+        });
+
+    debug!(logger, "Found {} relevant block(s)", block_hashes.len());
+
+    // Make sure `to` is included, even if empty.
+    block_hashes.insert(to_hash);
+    triggers_by_block.entry(to).or_insert(Vec::new());
+
+    let mut blocks = adapter
+        .load_blocks(logger1, chain_store, block_hashes)
+        .and_then(
+            move |block| match triggers_by_block.remove(&block.number()) {
+                Some(triggers) => Ok(EthereumBlockWithTriggers::new(
+                    BlockFinality::Final(block),
+                    triggers,
+                )),
+                None => Err(format_err!(
+                    "block {:?} not found in `triggers_by_block`",
+                    block
+                )),
+            },
+        )
+        .collect()
+        .compat()
+        .await?;
+
+    blocks.sort_by_key(|block| block.block.number());
+
+    // Sanity check that the returned blocks are in the correct range.
+    // Unwrap: `blocks` always includes at least `to`.
+    let first = blocks.first().unwrap().block.number();
+    let last = blocks.last().unwrap().block.number();
+    if first < from {
+        return Err(format_err!(
+            "block {} returned by the Ethereum node is before {}, the first block of the requested range",
+            first,
+            from,
+        ));
+    }
+    if last > to {
+        return Err(format_err!(
+            "block {} returned by the Ethereum node is after {}, the last block of the requested range",
+            last,
+            to,
+        ));
+    }
+
+    Ok(blocks)
+}
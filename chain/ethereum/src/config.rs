@@ -29,9 +29,19 @@ pub struct EthereumRpcConfig {
     pub http_headers: HeaderMap,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct EthereumNetworkConfig {
+    /// The most blocks to request traces for with one `trace_filter` call.
+    /// Defaults to `ETHEREUM_TRACE_STREAM_STEP_SIZE`
+    pub trace_filter_max_block_range: Option<u64>,
+}
+
 #[derive(Debug, Default, Deserialize)]
 pub struct EthereumConfig {
+    #[serde(default)]
     pub rpc: HashMap<String, EthereumRpcConfig>,
+    #[serde(default)]
+    pub network: HashMap<String, EthereumNetworkConfig>,
 }
 
 lazy_static! {
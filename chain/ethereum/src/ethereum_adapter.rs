@@ -5,26 +5,34 @@ use lazy_static::lazy_static;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use ethabi::ParamType;
 use graph::components::ethereum::{EthereumAdapter as EthereumAdapterTrait, *};
 use graph::prelude::{
     debug, err_msg, error, ethabi, format_err,
-    futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
-    hex, retry, stream, tiny_keccak, trace, warn, web3, ChainStore, CheapClone, DynTryFuture,
-    Error, EthereumCallCache, Logger, TimeoutError,
+    futures03::{
+        self, compat::Future01CompatExt, FutureExt, StreamExt, TryFutureExt, TryStreamExt,
+    },
+    hex, retry, stream, tokio, trace, warn, web3, ChainStore, CheapClone, DynTryFuture, Error,
+    EthereumCallCache, Logger, TimeoutError,
 };
 use web3::api::Web3;
 use web3::transports::batch::Batch;
 use web3::types::{Filter, *};
 
+use crate::config::ETHEREUM_CONFIG;
+use crate::trace_ranges::{scan_traces, EmptyTraceRanges, TraceFilterError};
+use crate::transport::NewHeads;
+
 #[derive(Clone)]
 pub struct EthereumAdapter<T: web3::Transport> {
     url_hostname: Arc<String>,
     web3: Arc<Web3<T>>,
     metrics: Arc<ProviderEthRpcMetrics>,
     is_ganache: bool,
+    /// The most blocks to request traces for with one `trace_filter` call
+    trace_step_size: u64,
+    empty_trace_ranges: Arc<EmptyTraceRanges>,
 }
 
 lazy_static! {
@@ -78,6 +86,8 @@ impl<T: web3::Transport> CheapClone for EthereumAdapter<T> {
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
             is_ganache: self.is_ganache,
+            trace_step_size: self.trace_step_size,
+            empty_trace_ranges: self.empty_trace_ranges.cheap_clone(),
         }
     }
 }
@@ -89,16 +99,16 @@ where
     T::Out: Send,
 {
     pub async fn new(
+        network_name: &str,
         url: &str,
         transport: T,
         provider_metrics: Arc<ProviderEthRpcMetrics>,
     ) -> Self {
-        // Unwrap: The transport was constructed with this url, so it is valid and has a host.
+        // IPC connections go to a socket file and have no host
         let hostname = graph::url::Url::parse(url)
-            .unwrap()
-            .host_str()
-            .unwrap()
-            .to_string();
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| "localhost".to_owned());
 
         let web3 = Arc::new(Web3::new(transport));
 
@@ -112,11 +122,19 @@ where
             .map(|s| s.contains("TestRPC"))
             .unwrap_or(false);
 
+        let trace_step_size = ETHEREUM_CONFIG
+            .network
+            .get(network_name)
+            .and_then(|network| network.trace_filter_max_block_range)
+            .unwrap_or(*TRACE_STREAM_STEP_SIZE);
+
         EthereumAdapter {
             url_hostname: Arc::new(hostname),
             web3,
             metrics: provider_metrics,
             is_ganache,
+            trace_step_size,
+            empty_trace_ranges: Arc::new(EmptyTraceRanges::default()),
         }
     }
 
@@ -127,13 +145,20 @@ where
         from: u64,
         to: u64,
         addresses: Vec<H160>,
-    ) -> impl Future<Item = Vec<Trace>, Error = Error> {
+    ) -> impl Future<Item = Vec<Trace>, Error = TraceFilterError> {
         let eth = self.clone();
         let logger = logger.to_owned();
 
         retry("trace_filter RPC call", &logger)
+            .when(move |res: &Result<_, TraceFilterError>| match res {
+                Ok(_) => false,
+                // Ranges that time out are split by `trace_stream` instead,
+                // unless they can't be split any further
+                Err(TraceFilterError::Timeout(_)) => from == to,
+                Err(TraceFilterError::Failed(_)) => true,
+            })
             .limit(*REQUEST_RETRIES)
-            .timeout_secs(*JSON_RPC_TIMEOUT)
+            .no_timeout()
             .run(move || {
                 let trace_filter: TraceFilter = match addresses.len() {
                     0 => TraceFilterBuilder::default()
@@ -152,7 +177,8 @@ where
                 let start = Instant::now();
                 let subgraph_metrics = subgraph_metrics.clone();
                 let provider_metrics = eth.metrics.clone();
-                eth.web3
+                let request = eth
+                    .web3
                     .trace()
                     .filter(trace_filter)
                     .map(move |traces| {
@@ -176,7 +202,6 @@ where
                         }
                         traces
                     })
-                    .from_err()
                     .then(move |result| {
                         let elapsed = start.elapsed().as_secs_f64();
                         provider_metrics.observe_request(elapsed, "trace_filter");
@@ -193,17 +218,21 @@ where
                             );
                         }
                         result
+                    });
+
+                tokio::time::timeout(Duration::from_secs(*JSON_RPC_TIMEOUT), request.compat())
+                    .map(move |result| match result {
+                        Ok(Ok(traces)) => Ok(traces),
+                        Ok(Err(e)) => Err(TraceFilterError::from_provider(e)),
+                        Err(_) => Err(TraceFilterError::Timeout(format_err!(
+                            "Ethereum node took too long to respond to trace_filter \
+                             (from block {}, to block {})",
+                            from,
+                            to
+                        ))),
                     })
-            })
-            .map_err(move |e| {
-                e.into_inner().unwrap_or_else(move || {
-                    format_err!(
-                        "Ethereum node took too long to respond to trace_filter \
-                         (from block {}, to block {})",
-                        from,
-                        to
-                    )
-                })
+                    .boxed()
+                    .compat()
             })
     }
 
@@ -254,6 +283,9 @@ where
             })
     }
 
+    /// Stream the traces for the blocks `[from, to]`. Ranges without
+    /// traces are remembered in `empty_trace_ranges` if it is given, which
+    /// must only be done for blocks that can not be reorged anymore
     fn trace_stream(
         self,
         logger: &Logger,
@@ -261,6 +293,7 @@ where
         from: u64,
         to: u64,
         addresses: Vec<H160>,
+        empty_trace_ranges: Option<Arc<EmptyTraceRanges>>,
     ) -> impl Stream<Item = Trace, Error = Error> + Send {
         if from > to {
             panic!(
@@ -271,25 +304,21 @@ where
 
         // Filters with no address can be more expensive, so use a reduced step size.
         let step_size = match addresses.is_empty() {
-            false => *TRACE_STREAM_STEP_SIZE,
-            true => *TRACE_STREAM_STEP_SIZE / 4,
+            false => self.trace_step_size,
+            true => self.trace_step_size / 4,
         }
         .max(1);
 
         let eth = self.clone();
         let logger = logger.to_owned();
-        stream::unfold(from, move |start| {
-            if start > to {
-                return None;
-            }
-            let end = (start + step_size - 1).min(to);
-            let new_start = end + 1;
-            if start == end {
-                debug!(logger, "Requesting traces for block {}", start);
-            } else {
-                debug!(logger, "Requesting traces for blocks [{}, {}]", start, end);
-            }
-            Some(
+        scan_traces(
+            logger.clone(),
+            from,
+            to,
+            step_size,
+            addresses.clone(),
+            empty_trace_ranges,
+            move |start, end| {
                 eth.traces(
                     &logger,
                     subgraph_metrics.clone(),
@@ -297,9 +326,11 @@ where
                     end,
                     addresses.clone(),
                 )
-                .map(move |traces| (traces, new_start)),
-            )
-        })
+                .compat()
+            },
+        )
+        .boxed()
+        .compat()
         .map(stream::iter_ok)
         .flatten()
     }
@@ -431,115 +462,9 @@ where
                     value: None,
                     data: Some(call_data.clone()),
                 };
-                web3.eth().call(req, Some(block_id)).then(|result| {
-                    // Try to check if the call was reverted. The JSON-RPC response for
-                    // reverts is not standardized, the current situation for the tested
-                    // clients is:
-                    //
-                    // - Parity returns a reliable RPC error response for reverts.
-                    // - Ganache also returns a reliable RPC error.
-                    // - Geth now also returns an RPC error. It used to return `0x` on a
-                    //   revert with no reason string, or a Solidity encoded `Error(string)`
-                    //   call from `revert` and `require` calls with a reason string. We
-                    //   still have support for those but that can be removed on the next
-                    //   hard fork (Berlin).
-
-                    // 0xfe is the "designated bad instruction" of the EVM, and Solidity
-                    // uses it for asserts.
-                    const PARITY_BAD_INSTRUCTION_FE: &str = "Bad instruction fe";
-
-                    // 0xfd is REVERT, but on some contracts, and only on older blocks,
-                    // this happens. Makes sense to consider it a revert as well.
-                    const PARITY_BAD_INSTRUCTION_FD: &str = "Bad instruction fd";
-
-                    const PARITY_BAD_JUMP_PREFIX: &str = "Bad jump";
-                    const GANACHE_VM_EXECUTION_ERROR: i64 = -32000;
-                    const GANACHE_REVERT_MESSAGE: &str =
-                        "VM Exception while processing transaction: revert";
-                    const PARITY_VM_EXECUTION_ERROR: i64 = -32015;
-                    const PARITY_REVERT_PREFIX: &str = "Reverted 0x";
-
-                    // Deterministic Geth execution errors. We might need to expand this as
-                    // subgraphs come across other errors. See
-                    // https://github.com/ethereum/go-ethereum/blob/cd57d5cd38ef692de8fbedaa56598b4e9fbfbabc/core/vm/errors.go
-                    const GETH_EXECUTION_ERRORS: &[&str] = &[
-                        "execution reverted",
-                        "invalid jump destination",
-                        "invalid opcode",
-                    ];
-
-                    let as_solidity_revert_with_reason = |bytes: &[u8]| {
-                        let solidity_revert_function_selector =
-                            &tiny_keccak::keccak256(b"Error(string)")[..4];
-
-                        match bytes.len() >= 4 && &bytes[..4] == solidity_revert_function_selector {
-                            false => None,
-                            true => ethabi::decode(&[ParamType::String], &bytes[4..])
-                                .ok()
-                                .and_then(|tokens| tokens[0].clone().to_string()),
-                        }
-                    };
-
-                    match result {
-                        // Check for old Geth revert with reason.
-                        Ok(bytes) => match as_solidity_revert_with_reason(&bytes.0) {
-                            None => Ok(bytes),
-                            Some(reason) => Err(EthereumContractCallError::Revert(reason)),
-                        },
-
-                        // Check for Geth revert.
-                        Err(web3::Error::Rpc(rpc_error))
-                            if GETH_EXECUTION_ERRORS
-                                .iter()
-                                .any(|e| rpc_error.message.contains(e)) =>
-                        {
-                            Err(EthereumContractCallError::Revert(rpc_error.message))
-                        }
-
-                        // Check for Parity revert.
-                        Err(web3::Error::Rpc(ref rpc_error))
-                            if rpc_error.code.code() == PARITY_VM_EXECUTION_ERROR =>
-                        {
-                            match rpc_error.data.as_ref().and_then(|d| d.as_str()) {
-                                Some(data)
-                                    if data.starts_with(PARITY_REVERT_PREFIX)
-                                        || data.starts_with(PARITY_BAD_JUMP_PREFIX)
-                                        || data == PARITY_BAD_INSTRUCTION_FE
-                                        || data == PARITY_BAD_INSTRUCTION_FD =>
-                                {
-                                    let reason = if data == PARITY_BAD_INSTRUCTION_FE {
-                                        PARITY_BAD_INSTRUCTION_FE.to_owned()
-                                    } else {
-                                        let payload = data.trim_start_matches(PARITY_REVERT_PREFIX);
-                                        hex::decode(payload)
-                                            .ok()
-                                            .and_then(|payload| {
-                                                as_solidity_revert_with_reason(&payload)
-                                            })
-                                            .unwrap_or("no reason".to_owned())
-                                    };
-                                    Err(EthereumContractCallError::Revert(reason))
-                                }
-
-                                // The VM execution error was not identified as a revert.
-                                _ => Err(EthereumContractCallError::Web3Error(web3::Error::Rpc(
-                                    rpc_error.clone(),
-                                ))),
-                            }
-                        }
-
-                        // Check for Ganache revert.
-                        Err(web3::Error::Rpc(ref rpc_error))
-                            if rpc_error.code.code() == GANACHE_VM_EXECUTION_ERROR
-                                && rpc_error.message.starts_with(GANACHE_REVERT_MESSAGE) =>
-                        {
-                            Err(EthereumContractCallError::Revert(rpc_error.message.clone()))
-                        }
-
-                        // The error was not identified as a revert.
-                        Err(err) => Err(EthereumContractCallError::Web3Error(err)),
-                    }
-                })
+                web3.eth()
+                    .call(req, Some(block_id))
+                    .then(classify_call_result)
             })
             .map_err(|e| e.into_inner().unwrap_or(EthereumContractCallError::Timeout))
     }
@@ -607,9 +532,118 @@ where
     }
 }
 
+/// Try to check if a call was reverted. The JSON-RPC response for reverts
+/// is not standardized, the current situation for the tested clients is:
+///
+/// - Parity returns a reliable RPC error response for reverts, with what the
+///   contract reverted with in the error data.
+/// - Ganache also returns a reliable RPC error.
+/// - Geth now also returns an RPC error, with what the contract reverted with
+///   in the error data. It used to return `0x` on a revert with no reason
+///   string, or a Solidity encoded `Error(string)` call from `revert` and
+///   `require` calls with a reason string. We still have support for those
+///   but that can be removed on the next hard fork (Berlin).
+fn classify_call_result(
+    result: Result<Bytes, web3::Error>,
+) -> Result<Bytes, EthereumContractCallError> {
+    // 0xfe is the "designated bad instruction" of the EVM, and Solidity
+    // uses it for asserts.
+    const PARITY_BAD_INSTRUCTION_FE: &str = "Bad instruction fe";
+
+    // 0xfd is REVERT, but on some contracts, and only on older blocks,
+    // this happens. Makes sense to consider it a revert as well.
+    const PARITY_BAD_INSTRUCTION_FD: &str = "Bad instruction fd";
+
+    const PARITY_BAD_JUMP_PREFIX: &str = "Bad jump";
+    const GANACHE_VM_EXECUTION_ERROR: i64 = -32000;
+    const GANACHE_REVERT_MESSAGE: &str = "VM Exception while processing transaction: revert";
+    const PARITY_VM_EXECUTION_ERROR: i64 = -32015;
+    const PARITY_REVERT_PREFIX: &str = "Reverted 0x";
+
+    // Deterministic Geth execution errors. We might need to expand this as
+    // subgraphs come across other errors. See
+    // https://github.com/ethereum/go-ethereum/blob/cd57d5cd38ef692de8fbedaa56598b4e9fbfbabc/core/vm/errors.go
+    const GETH_EXECUTION_ERRORS: &[&str] = &[
+        "execution reverted",
+        "invalid jump destination",
+        "invalid opcode",
+    ];
+
+    match result {
+        // Check for old Geth revert with reason.
+        Ok(bytes) => match EthereumCallRevert::from_standard_data(&bytes.0) {
+            None => Ok(bytes),
+            Some(revert) => Err(EthereumContractCallError::Revert(revert)),
+        },
+
+        // Check for Geth revert. Newer versions of Geth pass what the
+        // contract reverted with as a hex string in the error data.
+        Err(web3::Error::Rpc(rpc_error))
+            if GETH_EXECUTION_ERRORS
+                .iter()
+                .any(|e| rpc_error.message.contains(e)) =>
+        {
+            let data = rpc_error
+                .data
+                .as_ref()
+                .and_then(|data| data.as_str())
+                .and_then(|data| hex::decode(data.trim_start_matches("0x")).ok())
+                .filter(|data| !data.is_empty());
+            let revert = match data {
+                Some(data) => EthereumCallRevert::from_data(data),
+                None => EthereumCallRevert::with_reason(rpc_error.message),
+            };
+            Err(EthereumContractCallError::Revert(revert))
+        }
+
+        // Check for Parity revert.
+        Err(web3::Error::Rpc(ref rpc_error))
+            if rpc_error.code.code() == PARITY_VM_EXECUTION_ERROR =>
+        {
+            match rpc_error.data.as_ref().and_then(|d| d.as_str()) {
+                Some(data)
+                    if data.starts_with(PARITY_REVERT_PREFIX)
+                        || data.starts_with(PARITY_BAD_JUMP_PREFIX)
+                        || data == PARITY_BAD_INSTRUCTION_FE
+                        || data == PARITY_BAD_INSTRUCTION_FD =>
+                {
+                    let revert = if data == PARITY_BAD_INSTRUCTION_FE {
+                        EthereumCallRevert::with_reason(PARITY_BAD_INSTRUCTION_FE)
+                    } else {
+                        let payload = data.trim_start_matches(PARITY_REVERT_PREFIX);
+                        hex::decode(payload)
+                            .ok()
+                            .map(EthereumCallRevert::from_data)
+                            .unwrap_or_else(|| EthereumCallRevert::with_reason("no reason"))
+                    };
+                    Err(EthereumContractCallError::Revert(revert))
+                }
+
+                // The VM execution error was not identified as a revert.
+                _ => Err(EthereumContractCallError::Web3Error(web3::Error::Rpc(
+                    rpc_error.clone(),
+                ))),
+            }
+        }
+
+        // Check for Ganache revert.
+        Err(web3::Error::Rpc(ref rpc_error))
+            if rpc_error.code.code() == GANACHE_VM_EXECUTION_ERROR
+                && rpc_error.message.starts_with(GANACHE_REVERT_MESSAGE) =>
+        {
+            Err(EthereumContractCallError::Revert(
+                EthereumCallRevert::with_reason(rpc_error.message.clone()),
+            ))
+        }
+
+        // The error was not identified as a revert.
+        Err(err) => Err(EthereumContractCallError::Web3Error(err)),
+    }
+}
+
 impl<T> EthereumAdapterTrait for EthereumAdapter<T>
 where
-    T: web3::BatchTransport + Send + Sync + 'static,
+    T: web3::BatchTransport + NewHeads + Send + Sync + 'static,
     T::Batch: Send,
     T::Out: Send,
 {
@@ -694,6 +728,17 @@ where
         )
     }
 
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>> {
+        self.web3.transport().new_heads().map(|heads| {
+            Box::new(heads.map_err(|e| {
+                EthereumAdapterError::Unknown(format_err!(
+                    "subscription to new heads failed: {}",
+                    e
+                ))
+            })) as Box<dyn Stream<Item = _, Error = _> + Send>
+        })
+    }
+
     fn latest_block(
         &self,
         logger: &Logger,
@@ -795,6 +840,39 @@ where
         )
     }
 
+    fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        let web3 = self.web3.clone();
+        let logger = logger.clone();
+
+        Box::new(
+            retry("eth_getBlockTransactionCountByHash RPC call", &logger)
+                .limit(*REQUEST_RETRIES)
+                .timeout_secs(*JSON_RPC_TIMEOUT)
+                .run(move || {
+                    web3.eth()
+                        .block_transaction_count(BlockId::Hash(block_hash))
+                        .from_err::<Error>()
+                        .and_then(move |count| {
+                            count.map(|count| count.as_u64()).ok_or_else(|| {
+                                format_err!("Ethereum node did not find block {:?}", block_hash)
+                            })
+                        })
+                })
+                .map_err(move |e| {
+                    e.into_inner().unwrap_or_else(move || {
+                        format_err!(
+                            "Ethereum node took too long to return the transaction count of block {}",
+                            block_hash
+                        )
+                    })
+                }),
+        )
+    }
+
     fn load_full_block(
         &self,
         logger: &Logger,
@@ -1096,6 +1174,8 @@ where
                 block_number,
                 block_number,
                 addresses,
+                // The block may still be reorged
+                None,
             )
             .collect()
             .and_then(move |traces| {
@@ -1177,15 +1257,22 @@ where
             .into_iter()
             .collect::<Vec<H160>>();
         Box::new(
-            eth.trace_stream(&logger, subgraph_metrics, from, to, addresses)
-                .filter_map(|trace| EthereumCall::try_from_trace(&trace))
-                .filter(move |call| {
-                    // `trace_filter` can only filter by calls `to` an address and
-                    // a block range. Since subgraphs are subscribing to calls
-                    // for a specific contract function an additional filter needs
-                    // to be applied
-                    call_filter.matches(&call)
-                }),
+            eth.trace_stream(
+                &logger,
+                subgraph_metrics,
+                from,
+                to,
+                addresses,
+                Some(self.empty_trace_ranges.cheap_clone()),
+            )
+            .filter_map(|trace| EthereumCall::try_from_trace(&trace))
+            .filter(move |call| {
+                // `trace_filter` can only filter by calls `to` an address and
+                // a block range. Since subgraphs are subscribing to calls
+                // for a specific contract function an additional filter needs
+                // to be applied
+                call_filter.matches(&call)
+            }),
         )
     }
 
@@ -1230,13 +1317,19 @@ where
                 .ok()
                 .flatten()
             {
-                Some(result) => {
+                Some(Ok(result)) => {
                     Box::new(future::ok(result)) as Box<dyn Future<Item = _, Error = _> + Send>
                 }
+                // Reverts are deterministic, so replaying a cached revert
+                // gives mappings the same reason they got the first time
+                Some(Err(revert)) => {
+                    Box::new(future::err(EthereumContractCallError::Revert(revert)))
+                }
                 None => {
                     let cache = cache.clone();
                     let call = call.clone();
                     let logger = logger.clone();
+                    let provider = provider_id(&self.url_hostname);
                     Box::new(
                         self.call(
                             logger.clone(),
@@ -1244,14 +1337,30 @@ where
                             Bytes(call_data.clone()),
                             call.block_ptr,
                         )
-                        .map(move |result| {
-                            let _ = cache
-                                .set_call(call.address, &call_data, call.block_ptr, &result.0)
-                                .map_err(|e| {
-                                    error!(logger, "call cache set error";
-                                                   "error" => e.to_string())
-                                });
-                            result.0
+                        .then(move |result| {
+                            let cached = match &result {
+                                Ok(output) => cache.set_call(
+                                    &provider,
+                                    call.address,
+                                    &call_data,
+                                    call.block_ptr,
+                                    &output.0,
+                                ),
+                                Err(EthereumContractCallError::Revert(revert)) => cache
+                                    .set_call_revert(
+                                        &provider,
+                                        call.address,
+                                        &call_data,
+                                        call.block_ptr,
+                                        revert,
+                                    ),
+                                Err(_) => Ok(()),
+                            };
+                            let _ = cached.map_err(|e| {
+                                error!(logger, "call cache set error";
+                                               "error" => e.to_string())
+                            });
+                            result.map(|output| output.0)
                         }),
                     )
                 }
@@ -1262,12 +1371,17 @@ where
                     // We got a `0x` response. For old Geth, this can mean a revert. It can also be
                     // that the contract actually returned an empty response. A view call is meant
                     // to return something, so we treat empty responses the same as reverts.
-                    Err(EthereumContractCallError::Revert("empty response".into()))
+                    Err(EthereumContractCallError::Revert(
+                        EthereumCallRevert::with_reason("empty response"),
+                    ))
                 } else {
                     // Decode failures are reverts. The reasoning is that if Solidity fails to
                     // decode an argument, that's a revert, so the same goes for the output.
                     call.function.decode_output(&output).map_err(|e| {
-                        EthereumContractCallError::Revert(format!("failed to decode output: {}", e))
+                        EthereumContractCallError::Revert(EthereumCallRevert::with_reason(format!(
+                            "failed to decode output: {}",
+                            e
+                        )))
                     })
                 }
             }),
@@ -1295,11 +1409,12 @@ where
 
         // Return a stream that lazily loads batches of blocks.
         debug!(logger, "Requesting {} block(s)", missing_blocks.len());
+        let provider = provider_id(&self.url_hostname);
         Box::new(
             self.load_blocks_rpc(logger.clone(), missing_blocks.into_iter().collect())
                 .collect()
                 .map(move |new_blocks| {
-                    if let Err(e) = chain_store.upsert_light_blocks(new_blocks.clone()) {
+                    if let Err(e) = chain_store.upsert_light_blocks(&provider, new_blocks.clone()) {
                         error!(logger, "Error writing to block cache {}", e);
                     }
                     blocks.extend(new_blocks);
@@ -1326,3 +1441,148 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::serde_json::Value;
+    use jsonrpc_core::{Error as RpcError, ErrorCode};
+
+    /// `revert("not the owner")`
+    const NOT_THE_OWNER: &str = "08c379a0\
+        0000000000000000000000000000000000000000000000000000000000000020\
+        000000000000000000000000000000000000000000000000000000000000000d\
+        6e6f7420746865206f776e657200000000000000000000000000000000000000";
+
+    /// `assert(false)` in Solidity 0.8
+    const ASSERT_FAILED: &str = "4e487b71\
+        0000000000000000000000000000000000000000000000000000000000000001";
+
+    /// `error InsufficientBalance(uint256 available)` with `available = 7`
+    const INSUFFICIENT_BALANCE: &str = "92665351\
+        0000000000000000000000000000000000000000000000000000000000000007";
+
+    fn rpc_error(code: i64, message: &str, data: Option<&str>) -> Result<Bytes, web3::Error> {
+        Err(web3::Error::Rpc(RpcError {
+            code: ErrorCode::ServerError(code),
+            message: message.to_owned(),
+            data: data.map(|data| Value::String(data.to_owned())),
+        }))
+    }
+
+    fn as_revert(result: Result<Bytes, web3::Error>) -> EthereumCallRevert {
+        match classify_call_result(result) {
+            Err(EthereumContractCallError::Revert(revert)) => revert,
+            other => panic!("expected a revert, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn geth_revert_with_reason() {
+        let data = format!("0x{}", NOT_THE_OWNER);
+        let revert = as_revert(rpc_error(
+            3,
+            "execution reverted: not the owner",
+            Some(&data),
+        ));
+        assert_eq!("not the owner", revert.reason);
+        assert_eq!(hex::decode(NOT_THE_OWNER).unwrap(), revert.data.unwrap().0);
+    }
+
+    #[test]
+    fn geth_revert_without_data() {
+        let revert = as_revert(rpc_error(-32000, "execution reverted", None));
+        assert_eq!(
+            EthereumCallRevert::with_reason("execution reverted"),
+            revert
+        );
+
+        let revert = as_revert(rpc_error(-32000, "invalid opcode", Some("0x")));
+        assert_eq!(EthereumCallRevert::with_reason("invalid opcode"), revert);
+    }
+
+    #[test]
+    fn geth_panic() {
+        let data = format!("0x{}", ASSERT_FAILED);
+        let revert = as_revert(rpc_error(3, "execution reverted", Some(&data)));
+        assert_eq!("panic 0x1: assertion failed", revert.reason);
+        assert_eq!(hex::decode(ASSERT_FAILED).unwrap(), revert.data.unwrap().0);
+    }
+
+    #[test]
+    fn geth_custom_error() {
+        let data = format!("0x{}", INSUFFICIENT_BALANCE);
+        let revert = as_revert(rpc_error(3, "execution reverted", Some(&data)));
+        assert_eq!("custom error 0x92665351", revert.reason);
+        assert_eq!(
+            hex::decode(INSUFFICIENT_BALANCE).unwrap(),
+            revert.data.unwrap().0
+        );
+    }
+
+    #[test]
+    fn old_geth_revert_with_reason() {
+        let revert = as_revert(Ok(Bytes(hex::decode(NOT_THE_OWNER).unwrap())));
+        assert_eq!("not the owner", revert.reason);
+
+        // Other return values are not reverts, even if they could be
+        // custom errors
+        let output = Bytes(hex::decode(INSUFFICIENT_BALANCE).unwrap());
+        assert_eq!(output, classify_call_result(Ok(output.clone())).unwrap());
+    }
+
+    #[test]
+    fn parity_revert() {
+        let data = format!("Reverted 0x{}", NOT_THE_OWNER);
+        let revert = as_revert(rpc_error(-32015, "VM execution error.", Some(&data)));
+        assert_eq!("not the owner", revert.reason);
+        assert_eq!(hex::decode(NOT_THE_OWNER).unwrap(), revert.data.unwrap().0);
+
+        let data = format!("Reverted 0x{}", INSUFFICIENT_BALANCE);
+        let revert = as_revert(rpc_error(-32015, "VM execution error.", Some(&data)));
+        assert_eq!("custom error 0x92665351", revert.reason);
+
+        let revert = as_revert(rpc_error(
+            -32015,
+            "VM execution error.",
+            Some("Reverted 0x"),
+        ));
+        assert_eq!(EthereumCallRevert::with_reason("no reason"), revert);
+
+        let revert = as_revert(rpc_error(
+            -32015,
+            "VM execution error.",
+            Some("Bad instruction fe"),
+        ));
+        assert_eq!(
+            EthereumCallRevert::with_reason("Bad instruction fe"),
+            revert
+        );
+
+        let revert = as_revert(rpc_error(
+            -32015,
+            "VM execution error.",
+            Some("Bad jump 12"),
+        ));
+        assert_eq!(EthereumCallRevert::with_reason("no reason"), revert);
+    }
+
+    #[test]
+    fn ganache_revert() {
+        let message = "VM Exception while processing transaction: revert not the owner";
+        let revert = as_revert(rpc_error(-32000, message, None));
+        assert_eq!(EthereumCallRevert::with_reason(message), revert);
+    }
+
+    #[test]
+    fn other_errors_are_not_reverts() {
+        match classify_call_result(rpc_error(-32015, "VM execution error.", Some("Out of gas"))) {
+            Err(EthereumContractCallError::Web3Error(_)) => (),
+            other => panic!("expected a web3 error, got {:?}", other),
+        }
+        match classify_call_result(rpc_error(-32000, "header not found", None)) {
+            Err(EthereumContractCallError::Web3Error(_)) => (),
+            other => panic!("expected a web3 error, got {:?}", other),
+        }
+    }
+}
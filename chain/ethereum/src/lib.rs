@@ -3,12 +3,18 @@ extern crate lazy_static;
 
 mod block_ingestor;
 mod block_stream;
+mod chain;
 mod config;
 mod ethereum_adapter;
 pub mod network_indexer;
+mod trace_ranges;
 mod transport;
 
-pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics};
+pub use self::block_ingestor::{BlockIngestor, BlockIngestorMetrics, ChainReorgMetrics};
 pub use self::block_stream::{BlockStream, BlockStreamBuilder};
+pub use self::chain::{
+    blocks_with_triggers, triggers_in_block, EthereumBlockWithTriggers, EthereumChain,
+    EthereumTriggerFilter, EthereumTriggersAdapter,
+};
 pub use self::ethereum_adapter::EthereumAdapter;
 pub use self::transport::{EventLoopHandle, Transport};
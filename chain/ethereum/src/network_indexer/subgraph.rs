@@ -89,6 +89,9 @@ fn create_subgraph(
             .expect("valid Ethereum network subgraph schema"),
         data_sources: vec![],
         graft: None,
+        history_blocks: None,
+        strict_bytes: false,
+        computed: None,
         templates: vec![],
     };
 
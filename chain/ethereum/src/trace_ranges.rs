@@ -0,0 +1,269 @@
+//! Scanning block ranges with `trace_filter`. Providers often can not
+//! answer `trace_filter` requests over large block ranges before they time
+//! out; such ranges are split into halves, down to single blocks, before we
+//! give up. Ranges that turned out to have no matching traces are
+//! remembered so that retrying a scan after an error does not request them
+//! again
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+
+use graph::prelude::futures03::stream::{self, Stream};
+use graph::prelude::*;
+use web3::types::H160;
+
+/// The most block ranges without matching traces that we remember. When
+/// there are more, we forget all of them and start over
+const MAX_EMPTY_RANGES: usize = 100_000;
+
+/// How a `trace_filter` request failed
+#[derive(Debug)]
+pub(crate) enum TraceFilterError {
+    /// The provider did not answer in time, which a smaller range may fix
+    Timeout(Error),
+    /// Any other error
+    Failed(Error),
+}
+
+impl TraceFilterError {
+    /// Classify an error from the provider. Some providers give up on
+    /// expensive requests themselves and say so in the error message
+    pub(crate) fn from_provider(e: web3::Error) -> Self {
+        let msg = e.to_string().to_lowercase();
+        if msg.contains("timeout") || msg.contains("timed out") {
+            TraceFilterError::Timeout(format_err!("{}", e))
+        } else {
+            TraceFilterError::Failed(format_err!("{}", e))
+        }
+    }
+
+    fn into_inner(self) -> Error {
+        match self {
+            TraceFilterError::Timeout(e) | TraceFilterError::Failed(e) => e,
+        }
+    }
+}
+
+/// The block ranges that have no traces to any of a set of addresses
+#[derive(Default)]
+pub(crate) struct EmptyTraceRanges {
+    ranges: Mutex<HashMap<Vec<H160>, HashSet<(u64, u64)>>>,
+}
+
+impl EmptyTraceRanges {
+    fn key(addresses: &[H160]) -> Vec<H160> {
+        let mut key = addresses.to_vec();
+        key.sort();
+        key
+    }
+
+    fn contains(&self, addresses: &[H160], from: u64, to: u64) -> bool {
+        self.ranges
+            .lock()
+            .unwrap()
+            .get(&Self::key(addresses))
+            .map_or(false, |ranges| ranges.contains(&(from, to)))
+    }
+
+    fn insert(&self, addresses: &[H160], from: u64, to: u64) {
+        let mut ranges = self.ranges.lock().unwrap();
+        if ranges.values().map(HashSet::len).sum::<usize>() >= MAX_EMPTY_RANGES {
+            ranges.clear();
+        }
+        ranges
+            .entry(Self::key(addresses))
+            .or_default()
+            .insert((from, to));
+    }
+}
+
+/// Request the traces for the blocks `[from, to]` with `fetch`, in ranges
+/// of at most `step_size` blocks. Ranges for which `fetch` times out are
+/// split into halves until they consist of a single block. If `empty_ranges`
+/// is given, ranges without traces are recorded there, and ranges that are
+/// already recorded there are skipped. That must only be used for blocks
+/// that can not be reorged anymore
+pub(crate) fn scan_traces<T, F, R>(
+    logger: Logger,
+    from: u64,
+    to: u64,
+    step_size: u64,
+    addresses: Vec<H160>,
+    empty_ranges: Option<Arc<EmptyTraceRanges>>,
+    fetch: F,
+) -> impl Stream<Item = Result<Vec<T>, Error>>
+where
+    F: Fn(u64, u64) -> R,
+    R: futures03::Future<Output = Result<Vec<T>, TraceFilterError>>,
+{
+    let step_size = step_size.max(1);
+    let pending: VecDeque<_> = (0..)
+        .map(|step| from + step * step_size)
+        .take_while(|start| *start <= to)
+        .map(|start| (start, (start + step_size - 1).min(to)))
+        .collect();
+    let fetch = Arc::new(fetch);
+
+    stream::try_unfold(pending, move |mut pending| {
+        let logger = logger.clone();
+        let addresses = addresses.clone();
+        let empty_ranges = empty_ranges.clone();
+        let fetch = fetch.clone();
+        async move {
+            while let Some((start, end)) = pending.pop_front() {
+                if let Some(empty_ranges) = &empty_ranges {
+                    if empty_ranges.contains(&addresses, start, end) {
+                        continue;
+                    }
+                }
+
+                if start == end {
+                    debug!(logger, "Requesting traces for block {}", start);
+                } else {
+                    debug!(logger, "Requesting traces for blocks [{}, {}]", start, end);
+                }
+                match fetch(start, end).await {
+                    Ok(traces) => {
+                        if traces.is_empty() {
+                            if let Some(empty_ranges) = &empty_ranges {
+                                empty_ranges.insert(&addresses, start, end);
+                            }
+                        }
+                        return Ok(Some((traces, pending)));
+                    }
+                    Err(TraceFilterError::Timeout(e)) if start < end => {
+                        let middle = start + (end - start) / 2;
+                        debug!(logger, "Splitting block range after trace_filter timed out";
+                               "from" => start,
+                               "to" => end,
+                               "error" => e.to_string());
+                        pending.push_front((middle + 1, end));
+                        pending.push_front((start, middle));
+                    }
+                    Err(e) => return Err(e.into_inner()),
+                }
+            }
+            Ok(None)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures03::executor::block_on;
+    use futures03::stream::TryStreamExt;
+
+    /// A provider that has a trace in every block divisible by 3 and times
+    /// out for ranges of more than `max_range` blocks. Requests for the
+    /// block `fail` fail once
+    struct MockProvider {
+        max_range: u64,
+        fail: Option<u64>,
+        requests: Mutex<Vec<(u64, u64)>>,
+    }
+
+    impl MockProvider {
+        fn new(max_range: u64, fail: Option<u64>) -> Arc<Self> {
+            Arc::new(MockProvider {
+                max_range,
+                fail,
+                requests: Mutex::new(vec![]),
+            })
+        }
+
+        fn trace_filter(&self, from: u64, to: u64) -> Result<Vec<u64>, TraceFilterError> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push((from, to));
+            match self.fail {
+                Some(block) if from <= block && block <= to => {
+                    if requests
+                        .iter()
+                        .filter(|(from, to)| *from <= block && block <= *to)
+                        .count()
+                        == 1
+                    {
+                        return Err(TraceFilterError::Failed(format_err!("connection reset")));
+                    }
+                }
+                _ => {}
+            }
+            if to - from + 1 > self.max_range {
+                return Err(TraceFilterError::Timeout(format_err!("timed out")));
+            }
+            Ok((from..=to).filter(|block| block % 3 == 0).collect())
+        }
+
+        fn requests(&self) -> Vec<(u64, u64)> {
+            std::mem::take(&mut *self.requests.lock().unwrap())
+        }
+    }
+
+    fn scan(
+        provider: &Arc<MockProvider>,
+        from: u64,
+        to: u64,
+        step_size: u64,
+        empty_ranges: Option<Arc<EmptyTraceRanges>>,
+    ) -> Result<Vec<u64>, Error> {
+        let logger = Logger::root(slog::Discard, o!());
+        let provider = provider.clone();
+        let traces = scan_traces(
+            logger,
+            from,
+            to,
+            step_size,
+            vec![H160::from_low_u64_be(1)],
+            empty_ranges,
+            move |from, to| futures03::future::ready(provider.trace_filter(from, to)),
+        );
+        block_on(traces.try_concat())
+    }
+
+    #[test]
+    fn splits_ranges_that_time_out() {
+        let provider = MockProvider::new(2, None);
+        assert_eq!(vec![0, 3, 6, 9], scan(&provider, 0, 9, 10, None).unwrap());
+        assert_eq!(
+            vec![
+                (0, 9),
+                (0, 4),
+                (0, 2),
+                (0, 1),
+                (2, 2),
+                (3, 4),
+                (5, 9),
+                (5, 7),
+                (5, 6),
+                (7, 7),
+                (8, 9)
+            ],
+            provider.requests()
+        );
+
+        // A single block that times out can not be split any further
+        let provider = MockProvider::new(0, None);
+        assert!(scan(&provider, 4, 5, 10, None).is_err());
+        assert_eq!(vec![(4, 5), (4, 4)], provider.requests());
+    }
+
+    #[test]
+    fn resumes_after_error() {
+        let empty_ranges = Arc::new(EmptyTraceRanges::default());
+        let provider = MockProvider::new(10, Some(8));
+
+        // The range with block 8 fails after the ranges before it have
+        // been scanned
+        assert!(scan(&provider, 0, 9, 2, Some(empty_ranges.clone())).is_err());
+        assert_eq!(
+            vec![(0, 1), (2, 3), (4, 5), (6, 7), (8, 9)],
+            provider.requests()
+        );
+
+        // Retrying skips the ranges that had no traces
+        assert_eq!(
+            vec![0, 3, 6, 9],
+            scan(&provider, 0, 9, 2, Some(empty_ranges)).unwrap()
+        );
+        assert_eq!(vec![(0, 1), (2, 3), (6, 7), (8, 9)], provider.requests());
+    }
+}
@@ -1,10 +1,13 @@
 use jsonrpc_core::types::Call;
 use serde_json::Value;
 use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
 
 pub use web3::transports::EventLoopHandle;
 use web3::transports::{http, ipc, ws};
-use web3::RequestId;
+use web3::{DuplexTransport, RequestId};
 
 use graph::prelude::*;
 
@@ -14,22 +17,24 @@ use super::config::ETHEREUM_CONFIG;
 #[derive(Clone, Debug)]
 pub enum Transport {
     RPC(http::Http),
-    IPC(ipc::Ipc),
-    WS(ws::WebSocket),
+    IPC(Duplex<ipc::Ipc>),
+    WS(Duplex<ws::WebSocket>),
 }
 
 impl Transport {
     /// Creates an IPC transport.
-    pub fn new_ipc(ipc: &str) -> (EventLoopHandle, Self) {
-        ipc::Ipc::new(ipc)
-            .map(|(event_loop, transport)| (event_loop, Transport::IPC(transport)))
+    pub fn new_ipc(ipc: &str) -> Self {
+        let path = ipc.to_owned();
+        Duplex::new(move || ipc::Ipc::new(&path))
+            .map(Transport::IPC)
             .expect("Failed to connect to Ethereum IPC")
     }
 
     /// Creates a WebSocket transport.
-    pub fn new_ws(ws: &str) -> (EventLoopHandle, Self) {
-        ws::WebSocket::new(ws)
-            .map(|(event_loop, transport)| (event_loop, Transport::WS(transport)))
+    pub fn new_ws(ws: &str) -> Self {
+        let url = ws.to_owned();
+        Duplex::new(move || ws::WebSocket::new(&url))
+            .map(Transport::WS)
             .expect("Failed to connect to Ethereum WS")
     }
 
@@ -37,7 +42,7 @@ impl Transport {
     ///
     /// Note: JSON-RPC over HTTP doesn't always support subscribing to new
     /// blocks (one such example is Infura's HTTP endpoint).
-    pub fn new_rpc(rpc: &str) -> (EventLoopHandle, Self) {
+    pub fn new_rpc(rpc: &str) -> Self {
         let max_parallel_http: usize = env::var_os("ETHEREUM_RPC_MAX_PARALLEL_REQUESTS")
             .map(|s| s.to_str().unwrap().parse().unwrap())
             .unwrap_or(64);
@@ -45,9 +50,192 @@ impl Transport {
         let cfg = ETHEREUM_CONFIG.rpc.get(rpc);
         let headers = cfg.map(|cfg| cfg.http_headers.clone()).unwrap_or_default();
 
-        http::Http::with_max_parallel_and_headers(rpc, max_parallel_http, headers)
-            .map(|(event_loop, transport)| (event_loop, Transport::RPC(transport)))
-            .expect("Failed to connect to Ethereum RPC")
+        let (event_loop, transport) =
+            http::Http::with_max_parallel_and_headers(rpc, max_parallel_http, headers)
+                .expect("Failed to connect to Ethereum RPC");
+
+        // If we drop the event loop the transport will stop working.
+        // For now it's fine to just leak it.
+        std::mem::forget(event_loop);
+
+        Transport::RPC(transport)
+    }
+}
+
+/// A WebSocket or IPC connection to an Ethereum node that is established
+/// again once it is lost. The connection counts as lost when the
+/// subscription to new heads on it ends or fails, or when a request fails
+/// because the transport could not reach the node. The next subscription
+/// then connects again, and all requests from then on use the new
+/// connection
+pub struct Duplex<T> {
+    connect: Arc<dyn Fn() -> Result<(EventLoopHandle, T), web3::Error> + Send + Sync>,
+    connection: Arc<RwLock<Connection<T>>>,
+    /// Keeps the event loop of the current connection running
+    event_loop: Arc<Mutex<EventLoopHandle>>,
+}
+
+struct Connection<T> {
+    transport: T,
+    /// Set once this connection is lost
+    lost: Arc<AtomicBool>,
+}
+
+impl<T> Clone for Duplex<T> {
+    fn clone(&self) -> Self {
+        Duplex {
+            connect: self.connect.clone(),
+            connection: self.connection.clone(),
+            event_loop: self.event_loop.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Duplex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Duplex")
+            .field(&self.connection.read().unwrap().transport)
+            .finish()
+    }
+}
+
+/// Whether `error` means that the connection to the node is gone, as
+/// opposed to the node answering with an error
+fn is_connection_error(error: &web3::Error) -> bool {
+    matches!(
+        error,
+        web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_)
+    )
+}
+
+impl<T> Duplex<T>
+where
+    T: DuplexTransport + web3::BatchTransport + Clone + Send + 'static,
+    T::Out: Send,
+    T::Batch: Send,
+    T::NotificationStream: Send,
+{
+    fn new(
+        connect: impl Fn() -> Result<(EventLoopHandle, T), web3::Error> + Send + Sync + 'static,
+    ) -> Result<Self, web3::Error> {
+        let (event_loop, transport) = connect()?;
+        Ok(Duplex {
+            connect: Arc::new(connect),
+            connection: Arc::new(RwLock::new(Connection {
+                transport,
+                lost: Arc::new(AtomicBool::new(false)),
+            })),
+            event_loop: Arc::new(Mutex::new(event_loop)),
+        })
+    }
+
+    /// The transport of the current connection, and the flag to set when
+    /// it is lost
+    fn current(&self) -> (T, Arc<AtomicBool>) {
+        let connection = self.connection.read().unwrap();
+        (connection.transport.clone(), connection.lost.clone())
+    }
+
+    /// Replace the current connection with a new one if it was lost
+    fn reconnect_if_lost(&self) -> Result<(), web3::Error> {
+        let mut connection = self.connection.write().unwrap();
+        if !connection.lost.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let (event_loop, transport) = (self.connect)()?;
+        *connection = Connection {
+            transport,
+            lost: Arc::new(AtomicBool::new(false)),
+        };
+        // Dropping the event loop of the old connection stops it
+        *self.event_loop.lock().unwrap() = event_loop;
+        Ok(())
+    }
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.current().0.prepare(method, params)
+    }
+
+    fn send(
+        &self,
+        id: RequestId,
+        request: Call,
+    ) -> Box<dyn Future<Item = Value, Error = web3::Error> + Send> {
+        let (transport, lost) = self.current();
+        Box::new(transport.send(id, request).map_err(move |e| {
+            if is_connection_error(&e) {
+                lost.store(true, Ordering::SeqCst);
+            }
+            e
+        }))
+    }
+
+    fn send_batch<I>(
+        &self,
+        requests: I,
+    ) -> Box<dyn Future<Item = Vec<Result<Value, web3::Error>>, Error = web3::Error> + Send>
+    where
+        I: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let (transport, lost) = self.current();
+        Box::new(transport.send_batch(requests).map_err(move |e| {
+            if is_connection_error(&e) {
+                lost.store(true, Ordering::SeqCst);
+            }
+            e
+        }))
+    }
+
+    /// Subscribe to new heads, connecting again first if the current
+    /// connection was lost. The subscription ends once the connection is
+    /// lost, even if that was noticed by a failed request, since
+    /// subscriptions on a dropped connection do not always end on their own
+    fn new_heads(&self) -> Box<dyn Stream<Item = (), Error = web3::Error> + Send> {
+        if let Err(e) = self.reconnect_if_lost() {
+            return Box::new(stream::once(Err(e)));
+        }
+
+        let (transport, lost) = self.current();
+        let mut heads = web3::Web3::new(transport)
+            .eth_subscribe()
+            .subscribe_new_heads()
+            .map(|heads| heads.map(|_| ()))
+            .flatten_stream();
+        Box::new(stream::poll_fn(move || {
+            if lost.load(Ordering::SeqCst) {
+                return Ok(Async::Ready(None));
+            }
+            match heads.poll() {
+                Ok(Async::Ready(None)) => {
+                    lost.store(true, Ordering::SeqCst);
+                    Ok(Async::Ready(None))
+                }
+                Err(e) => {
+                    lost.store(true, Ordering::SeqCst);
+                    Err(e)
+                }
+                poll => poll,
+            }
+        }))
+    }
+}
+
+/// Transports that may be able to tell us about new chain heads as soon as
+/// the Ethereum node sees them
+pub trait NewHeads {
+    /// Subscribe to new chain heads with `eth_subscribe("newHeads")`, or
+    /// return `None` if the transport does not support subscriptions
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = web3::Error> + Send>>;
+}
+
+impl NewHeads for Transport {
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = web3::Error> + Send>> {
+        match self {
+            Transport::RPC(_) => None,
+            Transport::IPC(ipc) => Some(ipc.new_heads()),
+            Transport::WS(ws) => Some(ws.new_heads()),
+        }
     }
 }
 
@@ -65,8 +253,8 @@ impl web3::Transport for Transport {
     fn send(&self, id: RequestId, request: Call) -> Self::Out {
         match self {
             Transport::RPC(http) => Box::new(http.send(id, request)),
-            Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
-            Transport::WS(ws) => Box::new(ws.send(id, request)),
+            Transport::IPC(ipc) => ipc.send(id, request),
+            Transport::WS(ws) => ws.send(id, request),
         }
     }
 }
@@ -83,8 +271,99 @@ impl web3::BatchTransport for Transport {
     {
         match self {
             Transport::RPC(http) => Box::new(http.send_batch(requests)),
-            Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
-            Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+            Transport::IPC(ipc) => ipc.send_batch(requests),
+            Transport::WS(ws) => ws.send_batch(requests),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::thread;
+    use std::time::Duration;
+    use web3::Transport as _;
+    use websocket::sync::Server;
+    use websocket::OwnedMessage;
+
+    /// A WebSocket server that drops the first connection right after the
+    /// client subscribed to new heads, and answers all requests on later
+    /// connections; subscriptions get the id `0x1` and all other requests
+    /// return `0x2a`. Returns the URL of the server and how often a client
+    /// subscribed
+    fn flaky_server() -> (String, Arc<AtomicUsize>) {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", server.local_addr().unwrap());
+        let subscriptions = Arc::new(AtomicUsize::new(0));
+        let subscribed = subscriptions.clone();
+
+        thread::spawn(move || {
+            for (n, upgrade) in server.filter_map(Result::ok).enumerate() {
+                let mut client = upgrade.accept().unwrap();
+                while let Ok(OwnedMessage::Text(message)) = client.recv_message() {
+                    let request: serde_json::Value = serde_json::from_str(&message).unwrap();
+                    let subscribe = request["method"] == "eth_subscribe";
+                    if subscribe {
+                        subscribed.fetch_add(1, Ordering::SeqCst);
+                    }
+                    let response = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": if subscribe { "0x1" } else { "0x2a" },
+                    });
+                    client
+                        .send_message(&OwnedMessage::Text(response.to_string()))
+                        .unwrap();
+                    if subscribe && n == 0 {
+                        break;
+                    }
+                }
+                client.shutdown().ok();
+            }
+        });
+        (url, subscriptions)
+    }
+
+    #[test]
+    fn reconnects_after_the_connection_drops() {
+        let (url, subscriptions) = flaky_server();
+        let transport = Transport::new_ws(&url);
+
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let timeout = Duration::from_millis(500);
+            let block_number = || transport.execute("eth_blockNumber", vec![]).compat();
+
+            let mut heads = transport.new_heads().unwrap().compat();
+            tokio::time::timeout(timeout, heads.next()).await.ok();
+            assert_eq!(1, subscriptions.load(Ordering::SeqCst));
+
+            // Requests on the dropped connection fail at some point; the
+            // first ones may not get an answer at all
+            let mut failed = false;
+            for _ in 0..20 {
+                if let Ok(Err(e)) = tokio::time::timeout(timeout, block_number()).await {
+                    assert!(is_connection_error(&e), "{}", e);
+                    failed = true;
+                    break;
+                }
+            }
+            assert!(failed, "requests on the dropped connection fail");
+            assert!(
+                heads.next().await.is_none(),
+                "the subscription ends with the connection"
+            );
+
+            // Subscribing again connects again, and requests use the new
+            // connection
+            let mut heads = transport.new_heads().unwrap().compat();
+            tokio::time::timeout(timeout, heads.next()).await.ok();
+            assert_eq!(
+                serde_json::Value::from("0x2a"),
+                block_number().await.unwrap()
+            );
+            assert_eq!(2, subscriptions.load(Ordering::SeqCst));
+        });
+    }
+}
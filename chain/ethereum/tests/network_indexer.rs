@@ -98,11 +98,8 @@ where
 {
     let store = STORE.clone();
 
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
+    // This forces sequential test execution
+    let mut runtime = TEST_STORE.lock_runtime();
 
     runtime
         .block_on(
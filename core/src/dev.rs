@@ -0,0 +1,202 @@
+//! Support for `--dev`, where the node deploys a subgraph straight from a
+//! manifest on disk, and deploys it again whenever the manifest or one of
+//! the files it refers to changes
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use graph::prelude::*;
+
+/// Deployment ids of dev deployments start with this prefix, which IPFS
+/// hashes never do
+pub const DEV_DEPLOYMENT_PREFIX: &str = "dev";
+
+/// The name under which the dev subgraph is deployed
+pub const DEV_SUBGRAPH_NAME: &str = "dev";
+
+/// Return `true` if `id` is the id of a dev deployment
+pub fn is_dev_deployment(id: &SubgraphDeploymentId) -> bool {
+    id.as_str().starts_with(DEV_DEPLOYMENT_PREFIX)
+}
+
+/// When each file of a `DevManifest` was last modified and how big it is,
+/// or `None` if the file can not be read; when that changes, the
+/// subgraph needs to be deployed again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStamps(Vec<(PathBuf, Option<(SystemTime, u64)>)>);
+
+/// A subgraph manifest on disk. Links in it that do not point to IPFS are
+/// paths relative to the manifest
+#[derive(Clone, Debug)]
+pub struct DevManifest {
+    path: PathBuf,
+}
+
+impl DevManifest {
+    pub fn new(path: PathBuf) -> Self {
+        DevManifest { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The manifest and all the files it refers to with a `file` key, like
+    /// the schema, ABIs and WASM modules. Links to IPFS are left out
+    pub fn files(&self) -> Result<Vec<PathBuf>, Error> {
+        let text = fs::read_to_string(&self.path)
+            .map_err(|e| format_err!("failed to read {}: {}", self.path.display(), e))?;
+        let manifest: serde_yaml::Value = serde_yaml::from_str(&text)
+            .map_err(|e| format_err!("failed to parse {}: {}", self.path.display(), e))?;
+
+        let mut files = vec![self.path.clone()];
+        collect_links(&manifest, &mut |link| {
+            if let Some(path) = self.path_for(link) {
+                files.push(path);
+            }
+        });
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// The id of the deployment for the current contents of the files. It
+    /// only depends on what is in the files, not on where they are
+    pub fn deployment_id(&self) -> Result<SubgraphDeploymentId, Error> {
+        let mut hasher = tiny_keccak::Keccak::new_keccak256();
+        for file in self.files()? {
+            let contents = fs::read(&file)
+                .map_err(|e| format_err!("failed to read {}: {}", file.display(), e))?;
+            hasher.update(&(contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+        let mut hash = [0u8; 32];
+        hasher.finalize(&mut hash);
+
+        let id = format!("{}{}", DEV_DEPLOYMENT_PREFIX, &hex::encode(hash)[..40]);
+        SubgraphDeploymentId::new(id).map_err(|id| format_err!("invalid deployment id `{}`", id))
+    }
+
+    /// The current stamps of the files of the manifest. If the manifest can
+    /// not be parsed, only the manifest itself is watched
+    pub fn stamps(&self) -> FileStamps {
+        let files = self.files().unwrap_or_else(|_| vec![self.path.clone()]);
+        FileStamps(
+            files
+                .into_iter()
+                .map(|file| {
+                    let stamp = fs::metadata(&file)
+                        .and_then(|meta| Ok((meta.modified()?, meta.len())))
+                        .ok();
+                    (file, stamp)
+                })
+                .collect(),
+        )
+    }
+
+    /// The file that `link` refers to: the manifest for links to dev
+    /// deployments, and a path relative to the manifest for links that do
+    /// not point to IPFS. Return `None` for links to IPFS
+    pub fn path_for(&self, link: &str) -> Option<PathBuf> {
+        match link.strip_prefix("/ipfs/") {
+            Some(hash) if hash.starts_with(DEV_DEPLOYMENT_PREFIX) => Some(self.path.clone()),
+            Some(_) => None,
+            None => Some(
+                self.path
+                    .parent()
+                    .unwrap_or_else(|| Path::new("."))
+                    .join(link),
+            ),
+        }
+    }
+}
+
+/// Call `f` with every link under a `file` key in `value`; links are
+/// either strings or IPLD links like `{ "/": "..." }`
+fn collect_links(value: &serde_yaml::Value, f: &mut impl FnMut(&str)) {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Mapping(mapping) => {
+            for (key, value) in mapping {
+                if key.as_str() == Some("file") {
+                    match value {
+                        Value::String(link) => f(link),
+                        Value::Mapping(ipld) => {
+                            if let Some(link) =
+                                ipld.get(&Value::from("/")).and_then(|link| link.as_str())
+                            {
+                                f(link)
+                            }
+                        }
+                        _ => {}
+                    }
+                } else {
+                    collect_links(value, f)
+                }
+            }
+        }
+        Value::Sequence(values) => values.iter().for_each(|value| collect_links(value, f)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = "
+specVersion: 0.0.2
+schema:
+  file: ./schema.graphql
+dataSources:
+  - kind: ethereum/contract
+    name: Token
+    mapping:
+      abis:
+        - name: Token
+          file:
+            /: abis/Token.json
+        - name: ERC20
+          file:
+            /: /ipfs/QmERC20
+      file: Token/Token.wasm
+";
+
+    #[test]
+    fn files_and_deployment_id() {
+        let dir = std::env::temp_dir().join(format!("graph-dev-{}", std::process::id()));
+        fs::create_dir_all(dir.join("abis")).unwrap();
+        fs::create_dir_all(dir.join("Token")).unwrap();
+        fs::write(dir.join("subgraph.yaml"), MANIFEST).unwrap();
+        fs::write(dir.join("schema.graphql"), "type Token @entity { id: ID! }").unwrap();
+        fs::write(dir.join("abis/Token.json"), "[]").unwrap();
+        fs::write(dir.join("Token/Token.wasm"), "wasm").unwrap();
+
+        let manifest = DevManifest::new(dir.join("subgraph.yaml"));
+        let mut expected = vec![
+            dir.join("subgraph.yaml"),
+            dir.join("./schema.graphql"),
+            dir.join("abis/Token.json"),
+            dir.join("Token/Token.wasm"),
+        ];
+        expected.sort();
+        assert_eq!(expected, manifest.files().unwrap());
+
+        let id = manifest.deployment_id().unwrap();
+        assert!(is_dev_deployment(&id));
+        assert_eq!(id, manifest.deployment_id().unwrap());
+        assert_eq!(
+            Some(manifest.path().to_owned()),
+            manifest.path_for(&format!("/ipfs/{}", id))
+        );
+        assert_eq!(None, manifest.path_for("/ipfs/QmERC20"));
+
+        let stamps = manifest.stamps();
+        fs::write(dir.join("Token/Token.wasm"), "changed wasm").unwrap();
+        assert_ne!(stamps, manifest.stamps());
+        assert_ne!(id, manifest.deployment_id().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::fs;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -14,6 +15,8 @@ use serde_json::Value;
 
 use graph::prelude::{LinkResolver as LinkResolverTrait, *};
 
+use crate::dev::DevManifest;
+
 /// Environment variable for limiting the `ipfs.map` file size limit.
 const MAX_IPFS_MAP_FILE_SIZE_VAR: &'static str = "GRAPH_MAX_IPFS_MAP_FILE_SIZE";
 
@@ -129,6 +132,23 @@ pub struct LinkResolver {
     cache: Arc<Mutex<LruCache<String, Vec<u8>>>>,
     timeout: Duration,
     retry: bool,
+    /// With `--dev`, links that do not point to IPFS are read from disk
+    dev_manifest: Option<Arc<DevManifest>>,
+}
+
+impl LinkResolver {
+    /// Read links to dev deployments and links that do not point to IPFS
+    /// from the files of `manifest`
+    pub fn with_dev_manifest(mut self, manifest: DevManifest) -> Self {
+        self.dev_manifest = Some(Arc::new(manifest));
+        self
+    }
+
+    fn dev_path(&self, link: &Link) -> Option<std::path::PathBuf> {
+        self.dev_manifest
+            .as_ref()
+            .and_then(|manifest| manifest.path_for(&link.link))
+    }
 }
 
 impl From<IpfsClient> for LinkResolver {
@@ -146,6 +166,7 @@ impl From<Vec<IpfsClient>> for LinkResolver {
             ))),
             timeout: *IPFS_TIMEOUT,
             retry: false,
+            dev_manifest: None,
         }
     }
 }
@@ -164,6 +185,12 @@ impl LinkResolverTrait for LinkResolver {
 
     /// Supports links of the form `/ipfs/ipfs_hash` or just `ipfs_hash`.
     async fn cat(&self, logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        if let Some(path) = self.dev_path(link) {
+            // Files change while the node runs, and are never cached
+            return fs::read(&path)
+                .map_err(|e| format_err!("failed to read {}: {}", path.display(), e));
+        }
+
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/").to_owned();
 
@@ -220,6 +247,25 @@ impl LinkResolverTrait for LinkResolver {
     }
 
     async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        if let Some(path) = self.dev_path(link) {
+            let text = fs::read_to_string(&path)
+                .map_err(|e| format_err!("failed to read {}: {}", path.display(), e))?;
+            let values: Vec<_> = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| {
+                    serde_json::from_str::<Value>(line)
+                        .map(|value| JsonStreamValue {
+                            value,
+                            line: index + 1,
+                        })
+                        .map_err(|e| format_err!("{}: {}", path.display(), e))
+                })
+                .collect();
+            return Ok(Box::pin(futures03::stream::iter(values)));
+        }
+
         // Discard the `/ipfs/` prefix (if present) to get the hash.
         let path = link.link.trim_start_matches("/ipfs/");
 
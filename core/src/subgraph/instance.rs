@@ -8,7 +8,8 @@ use std::str::FromStr;
 
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
-use web3::types::{Log, H256};
+use graph_chain_ethereum::EthereumChain;
+use web3::types::H256;
 
 lazy_static! {
     static ref MAX_DATA_SOURCES: Option<usize> = env::var("GRAPH_SUBGRAPH_MAX_DATA_SOURCES")
@@ -31,6 +32,10 @@ pub struct SubgraphInstance<T: RuntimeHostBuilder> {
 
     /// Maps the hash of a module to a channel to the thread in which the module is instantiated.
     module_cache: HashMap<[u8; 32], Sender<T::Req>>,
+
+    /// The memory limit for handlers, in bytes, if it was changed from the
+    /// default for this deployment
+    max_memory: Option<u64>,
 }
 
 impl<T> SubgraphInstance<T>
@@ -42,6 +47,7 @@ where
         manifest: SubgraphManifest,
         host_builder: T,
         host_metrics: Arc<HostMetrics>,
+        max_memory: Option<u64>,
     ) -> Result<Self, Error> {
         let subgraph_id = manifest.id.clone();
         let network = manifest.network_name();
@@ -53,6 +59,7 @@ where
             network,
             hosts: Vec::new(),
             module_cache: HashMap::new(),
+            max_memory,
         };
 
         // Create a new runtime host for each data source in the subgraph manifest;
@@ -104,6 +111,7 @@ where
                     logger,
                     self.subgraph_id.clone(),
                     host_metrics.clone(),
+                    self.max_memory,
                 )?;
                 self.module_cache.insert(module_hash, sender.clone());
                 sender
@@ -123,15 +131,10 @@ where
 }
 
 #[async_trait]
-impl<T> SubgraphInstanceTrait<T::Host> for SubgraphInstance<T>
+impl<T> SubgraphInstanceTrait<EthereumChain, T::Host> for SubgraphInstance<T>
 where
     T: RuntimeHostBuilder,
 {
-    /// Returns true if the subgraph has a handler for an Ethereum event.
-    fn matches_log(&self, log: &Log) -> bool {
-        self.hosts.iter().any(|host| host.matches_log(log))
-    }
-
     async fn process_trigger(
         &self,
         logger: &Logger,
@@ -298,3 +301,58 @@ where
         })
     }
 }
+
+/// Indexes the `MockChain` by writing a `Trigger` entity with the number of
+/// the block for each trigger, so that tests can drive the code that is
+/// generic over the chain without running mappings
+#[cfg(test)]
+#[async_trait]
+impl<T> SubgraphInstanceTrait<graph_mock::MockChain, T::Host> for SubgraphInstance<T>
+where
+    T: RuntimeHostBuilder,
+{
+    async fn process_trigger(
+        &self,
+        _: &Logger,
+        block: &Arc<graph_mock::MockBlock>,
+        trigger: graph_mock::MockTrigger,
+        mut state: BlockState,
+        _: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        let key = EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: "Trigger".into(),
+            entity_id: trigger.0.to_string(),
+        };
+        let entity = entity! {
+            id: trigger.0.to_string(),
+            block: block.number() as i32,
+        };
+        state
+            .entity_cache
+            .set(key, entity)
+            .map_err(|e| MappingError::Unknown(e.into()))?;
+        Ok(state)
+    }
+
+    async fn process_trigger_in_runtime_hosts(
+        _: &Logger,
+        _: &[Arc<T::Host>],
+        _: &Arc<graph_mock::MockBlock>,
+        _: graph_mock::MockTrigger,
+        _: BlockState,
+        _: SharedProofOfIndexing,
+    ) -> Result<BlockState, MappingError> {
+        unreachable!("the mock chain has no dynamic data sources")
+    }
+
+    fn add_dynamic_data_source(
+        &mut self,
+        _: &Logger,
+        _: DataSource,
+        _: Arc<Vec<DataSourceTemplate>>,
+        _: Arc<HostMetrics>,
+    ) -> Result<Option<Arc<T::Host>>, anyhow::Error> {
+        anyhow::bail!("the mock chain has no dynamic data sources")
+    }
+}
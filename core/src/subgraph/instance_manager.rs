@@ -1,18 +1,22 @@
 use atomic_refcell::AtomicRefCell;
 use futures01::sync::mpsc::{channel, Receiver, Sender};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
-use graph::components::ethereum::{triggers_in_block, EthereumNetworks};
+use graph::blockchain::Block as _;
 use graph::components::store::ModificationsAndCache;
+use graph::components::subgraph::handler_stats::{DeploymentHandlerStats, HandlerStatsRegistry};
 use graph::components::subgraph::{MappingError, ProofOfIndexing, SharedProofOfIndexing};
+use graph::components::trace;
+use graph::data::graphql::ttl::expiring_entity_types;
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::schema::{
     queries::LazyMetadata, DynamicEthereumContractDataSourceEntity, SubgraphError, SubgraphHealth,
     POI_OBJECT,
 };
+use graph::prelude::tokio::task::JoinHandle;
 use graph::prelude::{SubgraphInstance as SubgraphInstanceTrait, *};
 use graph::util::lfu_cache::LfuCache;
 
@@ -26,37 +30,103 @@ lazy_static! {
             .unwrap_or("10000".into())
             .parse::<usize>()
             .expect("invalid GRAPH_ENTITY_CACHE_SIZE");
+
+    /// Maximum number of consecutive blocks beyond the reorg threshold whose
+    /// changes are written to the store in a single transaction. The default
+    /// of 1 writes every block in its own transaction.
+    pub static ref STORE_WRITE_BATCH_SIZE: usize = std::env::var("GRAPH_STORE_WRITE_BATCH_SIZE")
+        .unwrap_or("1".into())
+        .parse::<usize>()
+        .expect("invalid GRAPH_STORE_WRITE_BATCH_SIZE");
+
+    /// Size limit of the changes that are held back for a batched write, in bytes.
+    // Multiplied by 1000 because the env var is in KB.
+    pub static ref STORE_WRITE_BATCH_MEMORY: usize = 1000
+        * std::env::var("GRAPH_STORE_WRITE_BATCH_MEMORY")
+            .unwrap_or("10000".into())
+            .parse::<usize>()
+            .expect("invalid GRAPH_STORE_WRITE_BATCH_MEMORY");
+
+    /// The deployments whose store events list the fields that changed in
+    /// updated entities, as a comma separated list of deployment ids. `*`
+    /// turns this on for all deployments
+    static ref FIELD_CHANGE_DEPLOYMENTS: Vec<String> =
+        std::env::var("GRAPH_STORE_EVENT_FIELD_CHANGES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty())
+            .collect();
+}
+
+fn tracks_field_changes(deployment_id: &SubgraphDeploymentId) -> bool {
+    FIELD_CHANGE_DEPLOYMENTS
+        .iter()
+        .any(|id| id == "*" || id == deployment_id.as_str())
 }
 
 type SharedInstanceKeepAliveMap = Arc<RwLock<HashMap<SubgraphDeploymentId, CancelGuard>>>;
 
-struct IndexingInputs<B, S> {
+struct IndexingInputs<B: BlockStreamBuilder, S> {
     deployment_id: SubgraphDeploymentId,
     network_name: String,
     start_blocks: Vec<u64>,
     store: Arc<S>,
-    eth_adapter: Arc<dyn EthereumAdapter>,
+    triggers_adapter: Arc<dyn TriggersAdapter<B::Chain>>,
     stream_builder: B,
-    include_calls_in_blocks: bool,
     top_level_templates: Arc<Vec<DataSourceTemplate>>,
+
+    /// The entity types of the subgraph that have a `@ttl`
+    expiring_entity_types: Arc<BTreeSet<String>>,
 }
 
-struct IndexingState<T: RuntimeHostBuilder> {
+struct IndexingState<C: Blockchain, T: RuntimeHostBuilder> {
     logger: Logger,
     instance: SubgraphInstance<T>,
     instances: SharedInstanceKeepAliveMap,
-    log_filter: EthereumLogFilter,
-    call_filter: EthereumCallFilter,
-    block_filter: EthereumBlockFilter,
+    filter: C::TriggerFilter,
     entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
 }
 
-struct IndexingContext<B, T: RuntimeHostBuilder, S> {
+/// Changes of blocks that have been processed but not written to the store
+/// yet, so that they can be written in a single transaction. Only blocks
+/// beyond the reorg threshold are ever held back.
+#[derive(Default)]
+struct PendingBlocks {
+    blocks: Vec<(EthereumBlockPointer, Vec<EntityModification>)>,
+    weight: usize,
+}
+
+impl PendingBlocks {
+    fn push(&mut self, block_ptr: EthereumBlockPointer, mods: Vec<EntityModification>) {
+        self.weight += mods.iter().map(|m| m.weight()).sum::<usize>();
+        self.blocks.push((block_ptr, mods));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.blocks.len() >= *STORE_WRITE_BATCH_SIZE || self.weight >= *STORE_WRITE_BATCH_MEMORY
+    }
+
+    fn last_block_ptr(&self) -> Option<EthereumBlockPointer> {
+        self.blocks.last().map(|(block_ptr, _)| *block_ptr)
+    }
+
+    fn take(&mut self) -> Vec<(EthereumBlockPointer, Vec<EntityModification>)> {
+        self.weight = 0;
+        std::mem::take(&mut self.blocks)
+    }
+}
+
+struct IndexingContext<B: BlockStreamBuilder, T: RuntimeHostBuilder, S> {
     /// Read only inputs that are needed while indexing a subgraph.
     pub inputs: IndexingInputs<B, S>,
 
     /// Mutable state that may be modified while indexing a subgraph.
-    pub state: IndexingState<T>,
+    pub state: IndexingState<B::Chain, T>,
 
     /// Sensors to measure the execution of the subgraph instance
     pub subgraph_metrics: Arc<SubgraphInstanceMetrics>,
@@ -64,9 +134,6 @@ struct IndexingContext<B, T: RuntimeHostBuilder, S> {
     /// Sensors to measure the execution of the subgraph's runtime hosts
     pub host_metrics: Arc<HostMetrics>,
 
-    /// Sensors to measure the execution of eth rpc calls
-    pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
-
     pub block_stream_metrics: Arc<BlockStreamMetrics>,
 }
 
@@ -92,26 +159,11 @@ impl SubgraphInstanceManagerMetrics {
     }
 }
 
-enum TriggerType {
-    Event,
-    Call,
-    Block,
-}
-
-impl TriggerType {
-    fn label_value(&self) -> &str {
-        match self {
-            TriggerType::Event => "event",
-            TriggerType::Call => "call",
-            TriggerType::Block => "block",
-        }
-    }
-}
-
-struct SubgraphInstanceMetrics {
+pub(super) struct SubgraphInstanceMetrics {
     pub block_trigger_count: Box<Histogram>,
     pub block_processing_duration: Box<Histogram>,
     pub block_ops_transaction_duration: Box<Histogram>,
+    pub entity_cache_cached_misses: Box<Counter>,
 
     trigger_processing_duration: Box<HistogramVec>,
 }
@@ -151,18 +203,26 @@ impl SubgraphInstanceMetrics {
                 vec![0.01, 0.05, 0.1, 0.3, 0.7, 2.0],
             )
             .expect("failed to create `deployment_transact_block_operations_duration_{}");
+        let entity_cache_cached_misses = registry
+            .new_deployment_counter(
+                "deployment_entity_cache_cached_misses",
+                "Counts the lookups of nonexistent entities that the entity cache answered without querying the store",
+                subgraph_hash,
+            )
+            .expect("failed to create `deployment_entity_cache_cached_misses` counter");
 
         Self {
             block_trigger_count,
             block_processing_duration,
             trigger_processing_duration,
             block_ops_transaction_duration,
+            entity_cache_cached_misses,
         }
     }
 
-    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger: TriggerType) {
+    pub fn observe_trigger_processing_duration(&self, duration: f64, trigger_kind: &str) {
         self.trigger_processing_duration
-            .with_label_values(vec![trigger.label_value()].as_slice())
+            .with_label_values(vec![trigger_kind].as_slice())
             .observe(duration);
     }
 
@@ -171,24 +231,29 @@ impl SubgraphInstanceMetrics {
         registry.unregister(self.block_trigger_count.clone());
         registry.unregister(self.trigger_processing_duration.clone());
         registry.unregister(self.block_ops_transaction_duration.clone());
+        registry.unregister(self.entity_cache_cached_misses.clone());
     }
 }
 
 impl SubgraphInstanceManager {
     /// Creates a new runtime manager.
-    pub fn new<B, S, M>(
+    pub fn new<C, B, S, M, T>(
         logger_factory: &LoggerFactory,
         stores: HashMap<String, Arc<S>>,
-        eth_networks: EthereumNetworks,
-        host_builder: impl RuntimeHostBuilder,
+        chain: Arc<C>,
+        host_builder: T,
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        handler_stats: Arc<HandlerStatsRegistry>,
     ) -> Self
     where
+        C: Blockchain,
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
-        B: BlockStreamBuilder,
+        B: BlockStreamBuilder<Chain = C>,
         M: MetricsRegistry,
+        T: RuntimeHostBuilder,
+        SubgraphInstance<T>: SubgraphInstanceTrait<C, T::Host>,
     {
         let logger = logger_factory.component_logger("SubgraphInstanceManager", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -201,11 +266,12 @@ impl SubgraphInstanceManager {
             logger_factory,
             subgraph_receiver,
             stores,
-            eth_networks,
+            chain,
             host_builder,
             block_stream_builder,
             metrics_registry.clone(),
             graphql_runner,
+            handler_stats,
         );
 
         SubgraphInstanceManager {
@@ -215,19 +281,23 @@ impl SubgraphInstanceManager {
     }
 
     /// Handle incoming events from subgraph providers.
-    fn handle_subgraph_events<B, S, M>(
+    fn handle_subgraph_events<C, B, S, M, T>(
         logger_factory: LoggerFactory,
         receiver: Receiver<SubgraphAssignmentProviderEvent>,
         stores: HashMap<String, Arc<S>>,
-        eth_networks: EthereumNetworks,
-        host_builder: impl RuntimeHostBuilder,
+        chain: Arc<C>,
+        host_builder: T,
         block_stream_builder: B,
         metrics_registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
+        handler_stats: Arc<HandlerStatsRegistry>,
     ) where
+        C: Blockchain,
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
-        B: BlockStreamBuilder,
+        B: BlockStreamBuilder<Chain = C>,
         M: MetricsRegistry,
+        T: RuntimeHostBuilder,
+        SubgraphInstance<T>: SubgraphInstanceTrait<C, T::Host>,
     {
         let metrics_registry_for_manager = metrics_registry.clone();
         let metrics_registry_for_subgraph = metrics_registry.clone();
@@ -236,6 +306,9 @@ impl SubgraphInstanceManager {
         // Subgraph instance shutdown senders
         let instances: SharedInstanceKeepAliveMap = Default::default();
 
+        // The tasks that index the subgraphs that were started
+        let mut runs: HashMap<SubgraphDeploymentId, JoinHandle<Result<(), ()>>> = HashMap::new();
+
         // Blocking due to store interactions. Won't be blocking after #905.
         graph::spawn_blocking(async move {
             let mut assignment_stream = receiver.compat();
@@ -253,48 +326,79 @@ impl SubgraphInstanceManager {
                             "data_sources" => manifest.data_sources.len()
                         );
                         let network = manifest.network_name();
-                        let required_capabilities = manifest.required_ethereum_capabilities();
-
-                        match Self::start_subgraph(
-                            logger.clone(),
-                            instances.clone(),
-                            host_builder.clone(),
-                            block_stream_builder.clone(),
-                            stores
-                                .get(&network)
-                                .expect(&format!(
-                                    "expected store that matches subgraph network: {}",
-                                    &network
-                                ))
-                                .clone(),
-                            eth_networks
-                                .adapter_with_capabilities(
-                                    network.clone(),
-                                    &required_capabilities)
-                                .expect(&format!(
-                                    "expected eth adapter that matches subgraph network {} with required capabilities: {}",
-                                    &network,
-                                    &required_capabilities))
-                                .clone(),
-                            manifest,
-                            metrics_registry_for_subgraph.clone(),
-                            graphql_runner.clone(),
-                        )
-                        .await
-                        {
-                            Ok(()) => manager_metrics.subgraph_count.inc(),
-                            Err(err) => error!(
+                        let deployment_id = manifest.id.clone();
+
+                        // A subgraph that is restarted was just stopped, but
+                        // its previous run may still be processing a block.
+                        // Wait for it so that the two runs never write to the
+                        // store at the same time, and so that the new run
+                        // starts from the block the previous one stopped at
+                        if let Some(previous_run) = runs.remove(&deployment_id) {
+                            debug!(
                                 logger,
-                                "Failed to start subgraph";
-                                "error" => format!("{}", err),
-                                "code" => LogCode::SubgraphStartFailure
-                            ),
+                                "Waiting for the previous run of the subgraph to stop"
+                            );
+                            previous_run.await.ok();
+                        }
+
+                        let store = stores
+                            .get(&network)
+                            .expect(&format!(
+                                "expected store that matches subgraph network: {}",
+                                &network
+                            ))
+                            .clone();
+
+                        // The kind of the data sources decides which chain
+                        // the subgraph indexes
+                        let kind = manifest
+                            .data_sources
+                            .first()
+                            .map(|data_source| data_source.kind.clone())
+                            .unwrap_or_default();
+
+                        let result = if kind == C::KIND {
+                            Self::start_subgraph(
+                                logger.clone(),
+                                instances.clone(),
+                                chain.clone(),
+                                host_builder.clone(),
+                                block_stream_builder.clone(),
+                                store,
+                                manifest,
+                                metrics_registry_for_subgraph.clone(),
+                                graphql_runner.clone(),
+                                handler_stats.register(&deployment_id),
+                            )
+                            .await
+                        } else {
+                            Err(format_err!(
+                                "data sources of kind `{}` are not supported",
+                                kind
+                            ))
+                        };
+
+                        match result {
+                            Ok(run) => {
+                                runs.insert(deployment_id, run);
+                                manager_metrics.subgraph_count.inc()
+                            }
+                            Err(err) => {
+                                handler_stats.remove(&deployment_id);
+                                error!(
+                                    logger,
+                                    "Failed to start subgraph";
+                                    "error" => format!("{}", err),
+                                    "code" => LogCode::SubgraphStartFailure
+                                )
+                            }
                         }
                     }
                     SubgraphStop(id) => {
                         let logger = logger_factory.subgraph_logger(&id);
                         info!(logger, "Stop subgraph");
 
+                        handler_stats.remove(&id);
                         Self::stop_subgraph(instances.clone(), id);
                         manager_metrics.subgraph_count.dec();
                     }
@@ -303,21 +407,25 @@ impl SubgraphInstanceManager {
         });
     }
 
-    async fn start_subgraph<B, S, M>(
+    async fn start_subgraph<C, B, T, S, M>(
         logger: Logger,
         instances: SharedInstanceKeepAliveMap,
-        host_builder: impl RuntimeHostBuilder,
+        chain: Arc<C>,
+        host_builder: T,
         stream_builder: B,
         store: Arc<S>,
-        eth_adapter: Arc<dyn EthereumAdapter>,
         manifest: SubgraphManifest,
         registry: Arc<M>,
         graphql_runner: Arc<impl GraphQlRunner>,
-    ) -> Result<(), Error>
+        handler_stats: Arc<DeploymentHandlerStats>,
+    ) -> Result<JoinHandle<Result<(), ()>>, Error>
     where
-        B: BlockStreamBuilder,
+        C: Blockchain,
+        B: BlockStreamBuilder<Chain = C>,
+        T: RuntimeHostBuilder,
         S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
         M: MetricsRegistry,
+        SubgraphInstance<T>: SubgraphInstanceTrait<C, T::Host>,
     {
         // If a subgraph had a fatal error the last time we tried to run it, we can reset it to the
         // previous health status in hopes that it doesn't fail again.
@@ -352,17 +460,11 @@ impl SubgraphInstanceManager {
         let network_name = manifest.network_name();
 
         // Obtain filters from the manifest
-        let log_filter = EthereumLogFilter::from_data_sources(&manifest.data_sources);
-        let call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
-        let block_filter = EthereumBlockFilter::from_data_sources(&manifest.data_sources);
+        let filter = C::TriggerFilter::from_manifest(&manifest)?;
         let start_blocks = manifest.start_blocks();
 
-        // Identify whether there are mappings with call handlers or
-        // block handlers with call filters; in this case, we need to
-        // include calls in all blocks
-        let include_calls_in_blocks = manifest.requires_traces();
-
         let top_level_templates = Arc::new(manifest.templates.clone());
+        let expiring_entity_types = Arc::new(expiring_entity_types(&manifest.schema.document));
 
         // Create a subgraph instance from the manifest; this moves
         // ownership of the manifest and host builder into the new instance
@@ -377,16 +479,27 @@ impl SubgraphInstanceManager {
             registry.clone(),
             deployment_id.as_str(),
             stopwatch_metrics.clone(),
+            handler_stats,
+        ));
+        let ethrpc_metrics = Arc::new(SubgraphEthRpcMetrics::new(
+            registry.clone(),
+            deployment_id.as_str(),
         ));
-        let ethrpc_metrics = Arc::new(SubgraphEthRpcMetrics::new(registry.clone(), &deployment_id));
         let block_stream_metrics = Arc::new(BlockStreamMetrics::new(
             registry.clone(),
-            ethrpc_metrics.clone(),
+            ethrpc_metrics,
             &deployment_id,
             stopwatch_metrics,
         ));
-        let instance =
-            SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
+        let triggers_adapter = chain.triggers_adapter(&manifest, &block_stream_metrics)?;
+        let max_memory = store.memory_limit(&deployment_id)?;
+        let instance = SubgraphInstance::from_manifest(
+            &logger,
+            manifest,
+            host_builder,
+            host_metrics.clone(),
+            max_memory,
+        )?;
 
         // The subgraph state tracks the state of the subgraph instance over time
         let ctx = IndexingContext {
@@ -395,23 +508,20 @@ impl SubgraphInstanceManager {
                 network_name,
                 start_blocks,
                 store,
-                eth_adapter,
+                triggers_adapter,
                 stream_builder,
-                include_calls_in_blocks,
                 top_level_templates,
+                expiring_entity_types,
             },
             state: IndexingState {
                 logger,
                 instance,
                 instances,
-                log_filter,
-                call_filter,
-                block_filter,
+                filter,
                 entity_lfu_cache: LfuCache::new(),
             },
             subgraph_metrics,
             host_metrics,
-            ethrpc_metrics,
             block_stream_metrics,
         };
 
@@ -422,13 +532,11 @@ impl SubgraphInstanceManager {
         // forward; this is easier than updating the existing block stream.
         //
         // This task has many calls to the store, so mark it as `blocking`.
-        graph::spawn_blocking(async move {
+        Ok(graph::spawn_blocking(async move {
             let res = run_subgraph(ctx).await;
             subgraph_metrics_unregister.unregister(registry);
             res
-        });
-
-        Ok(())
+        }))
     }
 
     fn stop_subgraph(instances: SharedInstanceKeepAliveMap, id: SubgraphDeploymentId) {
@@ -455,12 +563,19 @@ where
     B: BlockStreamBuilder,
     T: RuntimeHostBuilder,
     S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
+    SubgraphInstance<T>: SubgraphInstanceTrait<B::Chain, T::Host>,
 {
     // Clone a few things for different parts of the async processing
     let subgraph_metrics = ctx.subgraph_metrics.cheap_clone();
     let store_for_err = ctx.inputs.store.cheap_clone();
     let logger = ctx.state.logger.cheap_clone();
     let id_for_err = ctx.inputs.deployment_id.clone();
+    let stopwatch = ctx.host_metrics.stopwatch.clone();
+
+    // Blocks that were held back to be written together with the blocks
+    // that follow them. This lives outside of `ctx` so that these blocks
+    // can still be written when processing a later block fails.
+    let mut pending = PendingBlocks::default();
 
     loop {
         debug!(logger, "Starting or restarting subgraph");
@@ -475,10 +590,7 @@ where
                 ctx.inputs.deployment_id.clone(),
                 ctx.inputs.network_name.clone(),
                 ctx.inputs.start_blocks.clone(),
-                ctx.state.log_filter.clone(),
-                ctx.state.call_filter.clone(),
-                ctx.state.block_filter.clone(),
-                ctx.inputs.include_calls_in_blocks,
+                ctx.state.filter.clone(),
                 ctx.block_stream_metrics.clone(),
             )
             .map_err(CancelableError::Error)
@@ -504,6 +616,43 @@ where
                     ctx.state.entity_lfu_cache = LfuCache::new();
                     continue;
                 }
+                Some(Ok(BlockStreamEvent::EndOfBlocks)) => {
+                    // The block stream looks at the subgraph block pointer
+                    // when it is polled next, which must be up to date by then
+                    let block_ptr = pending.last_block_ptr();
+                    match write_pending_blocks(
+                        &logger,
+                        store_for_err.as_ref(),
+                        &id_for_err,
+                        &mut pending,
+                        &subgraph_metrics,
+                        &stopwatch,
+                    ) {
+                        Ok(()) => {
+                            ctx.state.entity_lfu_cache.evict(*ENTITY_CACHE_SIZE);
+                            continue;
+                        }
+                        Err(e) => {
+                            fail_subgraph(
+                                &logger,
+                                store_for_err.as_ref(),
+                                &id_for_err,
+                                e.into(),
+                                block_ptr,
+                            );
+                            return Err(());
+                        }
+                    }
+                }
+                // The subgraph was stopped
+                Some(Err(_)) if block_stream_cancel_handle.is_canceled() => {
+                    debug!(
+                        &logger,
+                        "Subgraph block stream shut down cleanly";
+                        "id" => id_for_err.to_string(),
+                    );
+                    return Err(());
+                }
                 // Log and drop the errors from the block_stream
                 // The block stream will continue attempting to produce blocks
                 Some(Err(e)) => {
@@ -517,7 +666,7 @@ where
                 None => unreachable!("The block stream stopped producing blocks"),
             };
 
-            let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
+            let block_ptr = store_block_ptr::<B::Chain>(block.ptr()).ok();
 
             if block.triggers.len() > 0 {
                 subgraph_metrics
@@ -529,10 +678,10 @@ where
 
             let res = process_block(
                 &logger,
-                ctx.inputs.eth_adapter.cheap_clone(),
                 ctx,
                 block_stream_cancel_handle.clone(),
                 block,
+                &mut pending,
             )
             .await;
 
@@ -543,6 +692,25 @@ where
                 Ok((c, needs_restart)) => {
                     ctx = c;
                     if needs_restart {
+                        // The new block stream starts from the subgraph block pointer
+                        if let Err(e) = write_pending_blocks(
+                            &logger,
+                            store_for_err.as_ref(),
+                            &id_for_err,
+                            &mut pending,
+                            &subgraph_metrics,
+                            &stopwatch,
+                        ) {
+                            fail_subgraph(
+                                &logger,
+                                store_for_err.as_ref(),
+                                &id_for_err,
+                                e.into(),
+                                block_ptr,
+                            );
+                            return Err(());
+                        }
+
                         // Cancel the stream for real
                         ctx.state
                             .instances
@@ -564,31 +732,24 @@ where
                 }
                 // Handle unexpected stream errors by marking the subgraph as failed.
                 Err(CancelableError::Error(e)) => {
-                    error!(
+                    // The blocks before this one were processed successfully
+                    // and should not have to be processed again
+                    if let Err(e) = write_pending_blocks(
                         &logger,
-                        "Subgraph instance failed to run: {}", e;
-                        "id" => id_for_err.to_string(),
-                        "code" => LogCode::SubgraphSyncingFailure
-                    );
-
-                    let error = SubgraphError {
-                        subgraph_id: id_for_err.clone(),
-                        message: e.to_string(),
-                        block_ptr: Some(block_ptr),
-                        handler: None,
-                        deterministic: e.is_deterministic(),
-                    };
-
-                    // Set subgraph status to Failed
-                    let status_ops = SubgraphDeploymentEntity::fail_operations(&id_for_err, error);
-                    if let Err(e) = store_for_err.apply_metadata_operations(status_ops) {
+                        store_for_err.as_ref(),
+                        &id_for_err,
+                        &mut pending,
+                        &subgraph_metrics,
+                        &stopwatch,
+                    ) {
                         error!(
                             &logger,
-                            "Failed to set subgraph status to Failed: {}", e;
+                            "Failed to write blocks before the failed block: {}", e;
                             "id" => id_for_err.to_string(),
-                            "code" => LogCode::SubgraphSyncingFailureNotRecorded
                         );
                     }
+
+                    fail_subgraph(&logger, store_for_err.as_ref(), &id_for_err, e, block_ptr);
                     return Err(());
                 }
             }
@@ -596,6 +757,85 @@ where
     }
 }
 
+/// The pointer to a block of the chain `C` in the form in which the store
+/// records the pointer of a subgraph
+fn store_block_ptr<C: Blockchain>(ptr: C::Pointer) -> Result<EthereumBlockPointer, Error> {
+    let ptr: BlockPtr = ptr.into();
+    EthereumBlockPointer::try_from(ptr)
+}
+
+/// Log the error that stopped a subgraph and mark the subgraph as failed in the store.
+fn fail_subgraph<S: DeploymentStore>(
+    logger: &Logger,
+    store: &S,
+    id: &SubgraphDeploymentId,
+    e: BlockProcessingError,
+    block_ptr: Option<EthereumBlockPointer>,
+) {
+    error!(
+        logger,
+        "Subgraph instance failed to run: {}", e;
+        "id" => id.to_string(),
+        "code" => LogCode::SubgraphSyncingFailure
+    );
+
+    let error = SubgraphError {
+        subgraph_id: id.clone(),
+        message: e.to_string(),
+        block_ptr,
+        handler: None,
+        deterministic: e.is_deterministic(),
+        count: 1,
+    };
+
+    // Set subgraph status to Failed
+    if let Err(e) = store.fail_subgraph(id, error) {
+        error!(
+            logger,
+            "Failed to set subgraph status to Failed: {}", e;
+            "id" => id.to_string(),
+            "code" => LogCode::SubgraphSyncingFailureNotRecorded
+        );
+    }
+}
+
+/// Write the changes of all blocks that were held back to the store in a
+/// single transaction, and advance the subgraph block pointer to the last
+/// of them.
+fn write_pending_blocks<S: EntityStore>(
+    logger: &Logger,
+    store: &S,
+    id: &SubgraphDeploymentId,
+    pending: &mut PendingBlocks,
+    metrics: &SubgraphInstanceMetrics,
+    stopwatch: &StopwatchMetrics,
+) -> Result<(), Error> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let blocks = pending.take();
+    if blocks.len() > 1 {
+        debug!(logger, "Writing {} blocks in one transaction", blocks.len());
+    }
+
+    // Transact entity operations into the store and update the
+    // subgraph's block stream pointer
+    let _section = stopwatch.start_section("transact_block");
+    let start = Instant::now();
+
+    let migrate_at = store
+        .transact_block_operations_batch(id.clone(), blocks, stopwatch.clone())
+        .map_err(|e| format_err!("Error while processing block stream for a subgraph: {}", e))?;
+
+    let elapsed = start.elapsed().as_secs_f64();
+    metrics.block_ops_transaction_duration.observe(elapsed);
+    if let Some(block_ptr) = migrate_at {
+        store.migrate_subgraph_deployment(logger, id, &block_ptr);
+    }
+    Ok(())
+}
+
 #[derive(thiserror::Error, Debug)]
 enum BlockProcessingError {
     #[error("{0:#}")]
@@ -624,22 +864,25 @@ impl From<failure::Error> for BlockProcessingError {
 /// whether new dynamic data sources have been added to the subgraph.
 async fn process_block<B: BlockStreamBuilder, T: RuntimeHostBuilder, S>(
     logger: &Logger,
-    eth_adapter: Arc<dyn EthereumAdapter>,
     mut ctx: IndexingContext<B, T, S>,
     block_stream_cancel_handle: CancelHandle,
-    block: EthereumBlockWithTriggers,
+    block: BlockWithTriggers<B::Chain>,
+    pending: &mut PendingBlocks,
 ) -> Result<(IndexingContext<B, T, S>, bool), CancelableError<BlockProcessingError>>
 where
     S: ChainStore + Store + EthereumCallCache + SubgraphDeploymentStore,
+    SubgraphInstance<T>: SubgraphInstanceTrait<B::Chain, T::Host>,
 {
     let triggers = block.triggers;
-    let block = block.ethereum_block;
+    let block = block.block;
 
-    let block_ptr = EthereumBlockPointer::from(&block);
+    let block_ptr = store_block_ptr::<B::Chain>(block.ptr())?;
     let logger = logger.new(o!(
         "block_number" => format!("{:?}", block_ptr.number),
         "block_hash" => format!("{:?}", block_ptr.hash)
     ));
+    let span = trace::block_span(&ctx.inputs.deployment_id, &block_ptr);
+    span.set_int("triggers", triggers.len() as i64);
 
     if triggers.len() == 1 {
         info!(&logger, "1 trigger found in this block for this subgraph");
@@ -652,8 +895,8 @@ where
     }
 
     // Obtain current and new block pointer (after this block is processed)
-    let light_block = Arc::new(block.light_block());
-    let block_ptr_after = EthereumBlockPointer::from(&block);
+    let mapping_block = Arc::new(block.mapping_block());
+    let block_ptr_after = block_ptr;
     let block_ptr_for_new_data_sources = block_ptr_after.clone();
 
     let metrics = ctx.subgraph_metrics.clone();
@@ -671,32 +914,48 @@ where
         None
     };
 
+    // Mappings see entities as of the block they process, so that entities
+    // that expired at this block are missing for them
+    let mut block_state = BlockState::new(
+        ctx.inputs.store.clone(),
+        std::mem::take(&mut ctx.state.entity_lfu_cache),
+    );
+    block_state.entity_cache.set_block(
+        block_ptr.number as BlockNumber,
+        &ctx.inputs.expiring_entity_types,
+    );
+
     // Process events one after the other, passing in entity operations
-    // collected previously to every new event being processed
-    let mut block_state = match process_triggers(
-        &logger,
-        BlockState::new(
-            ctx.inputs.store.clone(),
-            std::mem::take(&mut ctx.state.entity_lfu_cache),
+    // collected previously to every new event being processed. The handlers
+    // pick up the block's span from the context of the future
+    let mut block_state = match trace::instrument(
+        process_triggers(
+            &logger,
+            block_state,
+            proof_of_indexing.cheap_clone(),
+            ctx.subgraph_metrics.clone(),
+            &ctx.state.instance,
+            block_ptr,
+            &mapping_block,
+            triggers,
         ),
-        proof_of_indexing.cheap_clone(),
-        ctx.subgraph_metrics.clone(),
-        &ctx.state.instance,
-        &light_block,
-        triggers,
+        &span,
     )
     .await
     {
         Ok(block_state) => block_state,
         Err(MappingError::Unknown(e)) => {
-            return Err(CancelableError::Error(BlockProcessingError::Unknown(e)))
+            span.set_error(&e);
+            return Err(CancelableError::Error(BlockProcessingError::Unknown(e)));
         }
         Err(MappingError::Deterministic(e)) => {
+            span.set_error(&e);
             return Err(CancelableError::Error(BlockProcessingError::Deterministic(
                 e,
-            )))
+            )));
         }
         Err(MappingError::PossibleReorg(e)) => {
+            span.set_error(&e);
             info!(ctx.state.logger,
                     "Possible reorg detected, retrying";
                     "error" => format!("{:?}", e.to_string()),
@@ -738,17 +997,22 @@ where
         .compat_err()?;
 
         // Reprocess the triggers from this block that match the new data sources
-        let block_with_triggers = triggers_in_block(
-            eth_adapter.clone(),
-            logger.cheap_clone(),
-            ctx.inputs.store.clone(),
-            ctx.ethrpc_metrics.clone(),
-            EthereumLogFilter::from_data_sources(data_sources.iter()),
-            EthereumCallFilter::from_data_sources(data_sources.iter()),
-            EthereumBlockFilter::from_data_sources(data_sources.iter()),
-            block.clone(),
-        )
-        .await?;
+        let chain_data_sources = data_sources
+            .iter()
+            .map(<B::Chain as Blockchain>::data_source)
+            .collect::<Result<Vec<_>, _>>()?;
+        let fetch_span = span.child("trigger_fetch");
+        fetch_span.set_int("data_sources", data_sources.len() as i64);
+        let block_with_triggers = ctx
+            .inputs
+            .triggers_adapter
+            .triggers_in_block(
+                &logger,
+                block.clone(),
+                TriggerFilter::from_data_sources(chain_data_sources.iter()),
+            )
+            .await?;
+        fetch_span.end();
 
         let triggers = block_with_triggers.triggers;
 
@@ -778,28 +1042,29 @@ where
         // Process the triggers in each host in the same order the
         // corresponding data sources have been created.
         for trigger in triggers.into_iter() {
-            block_state = SubgraphInstance::<T>::process_trigger_in_runtime_hosts(
+            let process = <SubgraphInstance<T> as SubgraphInstanceTrait<B::Chain, T::Host>>::process_trigger_in_runtime_hosts(
                 &logger,
                 &runtime_hosts,
-                &light_block,
+                &mapping_block,
                 trigger,
                 block_state,
                 proof_of_indexing.cheap_clone(),
-            )
-            .await
-            .map_err(|e| {
-                // This treats a `PossibleReorg` as an ordinary error which will fail the subgraph.
-                // This can cause an unecessary subgraph failure, to fix it we need to figure out a
-                // way to revert the effect of `create_dynamic_data_sources` so we may return a
-                // clean context as in b21fa73b-6453-4340-99fb-1a78ec62efb1.
-                match e {
-                    MappingError::PossibleReorg(e) | MappingError::Unknown(e) => {
-                        BlockProcessingError::Unknown(e)
+            );
+            block_state = trace::instrument(process, &span)
+                .await
+                .map_err(|e| {
+                    // This treats a `PossibleReorg` as an ordinary error which will fail the subgraph.
+                    // This can cause an unecessary subgraph failure, to fix it we need to figure out a
+                    // way to revert the effect of `create_dynamic_data_sources` so we may return a
+                    // clean context as in b21fa73b-6453-4340-99fb-1a78ec62efb1.
+                    match e {
+                        MappingError::PossibleReorg(e) | MappingError::Unknown(e) => {
+                            BlockProcessingError::Unknown(e)
+                        }
+                        MappingError::Deterministic(e) => BlockProcessingError::Deterministic(e),
                     }
-                    MappingError::Deterministic(e) => BlockProcessingError::Deterministic(e),
-                }
-            })
-            .map_err(CancelableError::Error)?;
+                })
+                .map_err(CancelableError::Error)?;
         }
     }
 
@@ -821,6 +1086,13 @@ where
         .await?;
     }
 
+    ctx.subgraph_metrics
+        .entity_cache_cached_misses
+        .inc_by(block_state.entity_cache.cached_misses() as f64);
+
+    block_state
+        .entity_cache
+        .track_field_changes(tracks_field_changes(&ctx.inputs.deployment_id));
     let section = ctx.host_metrics.stopwatch.start_section("as_modifications");
     let ModificationsAndCache {
         modifications: mods,
@@ -836,6 +1108,48 @@ where
         })?;
     section.end();
 
+    if !mods.is_empty() {
+        info!(&logger, "Applying {} entity operation(s)", mods.len());
+    }
+
+    // Blocks beyond the reorg threshold can be held back and written together
+    // with the blocks that follow them. All other blocks are written on their
+    // own, after any blocks that were held back
+    let is_final = block.is_final();
+    let write_span = span.child("store_write");
+    if !is_final {
+        write_pending_blocks(
+            &logger,
+            ctx.inputs.store.as_ref(),
+            &ctx.inputs.deployment_id,
+            pending,
+            &metrics,
+            &ctx.host_metrics.stopwatch,
+        )?;
+    }
+    pending.push(block_ptr_after, mods);
+
+    // The next block drops the entities of expiring types from the cache,
+    // which would lose their changes if they were held back
+    if is_final && !pending.is_full() && ctx.inputs.expiring_entity_types.is_empty() {
+        // The changes of the blocks that were held back are only in the
+        // cache, so nothing can be evicted until they have been written
+        write_span.set_str("outcome", "held back");
+        assert!(ctx.state.entity_lfu_cache.is_empty());
+        ctx.state.entity_lfu_cache = cache;
+        return Ok((ctx, needs_restart));
+    }
+
+    write_pending_blocks(
+        &logger,
+        ctx.inputs.store.as_ref(),
+        &ctx.inputs.deployment_id,
+        pending,
+        &metrics,
+        &ctx.host_metrics.stopwatch,
+    )?;
+    write_span.end();
+
     let section = ctx
         .host_metrics
         .stopwatch
@@ -847,43 +1161,12 @@ where
     assert!(ctx.state.entity_lfu_cache.is_empty());
     ctx.state.entity_lfu_cache = cache;
 
-    if !mods.is_empty() {
-        info!(&logger, "Applying {} entity operation(s)", mods.len());
-    }
-
-    // Transact entity operations into the store and update the
-    // subgraph's block stream pointer
-    let _section = ctx.host_metrics.stopwatch.start_section("transact_block");
-    let subgraph_id = ctx.inputs.deployment_id.clone();
-    let stopwatch = ctx.host_metrics.stopwatch.clone();
-    let start = Instant::now();
-
-    match ctx
-        .inputs
-        .store
-        .transact_block_operations(subgraph_id, block_ptr_after, mods, stopwatch)
-    {
-        Ok(should_migrate) => {
-            let elapsed = start.elapsed().as_secs_f64();
-            metrics.block_ops_transaction_duration.observe(elapsed);
-            if should_migrate {
-                ctx.inputs.store.migrate_subgraph_deployment(
-                    &logger,
-                    &ctx.inputs.deployment_id,
-                    &block_ptr_after,
-                );
-            }
-            Ok((ctx, needs_restart))
-        }
-        Err(e) => {
-            Err(format_err!("Error while processing block stream for a subgraph: {}", e).into())
-        }
-    }
+    Ok((ctx, needs_restart))
 }
 
 /// Transform the proof of indexing changes into entity updates that will be
 /// inserted when as_modifications is called.
-async fn update_proof_of_indexing(
+pub(super) async fn update_proof_of_indexing(
     proof_of_indexing: ProofOfIndexing,
     stopwatch: &StopwatchMetrics,
     deployment_id: &SubgraphDeploymentId,
@@ -897,7 +1180,7 @@ async fn update_proof_of_indexing(
         // Create the special POI entity key specific to this causality_region
         let entity_key = EntityKey {
             subgraph_id: deployment_id.clone(),
-            entity_type: POI_OBJECT.to_owned(),
+            entity_type: POI_OBJECT.into(),
             entity_id: causality_region,
         };
 
@@ -928,27 +1211,22 @@ async fn update_proof_of_indexing(
     Ok(())
 }
 
-async fn process_triggers(
+pub(super) async fn process_triggers<C: Blockchain, T: RuntimeHostBuilder>(
     logger: &Logger,
     mut block_state: BlockState,
     proof_of_indexing: SharedProofOfIndexing,
     subgraph_metrics: Arc<SubgraphInstanceMetrics>,
-    instance: &SubgraphInstance<impl RuntimeHostBuilder>,
-    block: &Arc<LightEthereumBlock>,
-    triggers: Vec<EthereumTrigger>,
-) -> Result<BlockState, MappingError> {
+    instance: &SubgraphInstance<T>,
+    block_ptr: EthereumBlockPointer,
+    block: &Arc<C::MappingBlock>,
+    triggers: Vec<C::TriggerData>,
+) -> Result<BlockState, MappingError>
+where
+    SubgraphInstance<T>: SubgraphInstanceTrait<C, T::Host>,
+{
     for trigger in triggers.into_iter() {
-        let block_ptr = EthereumBlockPointer::from(block.as_ref());
-        let trigger_type = match trigger {
-            EthereumTrigger::Log(_) => TriggerType::Event,
-            EthereumTrigger::Call(_) => TriggerType::Call,
-            EthereumTrigger::Block(..) => TriggerType::Block,
-        };
-        let transaction_id = match &trigger {
-            EthereumTrigger::Log(log) => log.transaction_hash,
-            EthereumTrigger::Call(call) => call.transaction_hash,
-            EthereumTrigger::Block(..) => None,
-        };
+        let trigger_kind = trigger.kind();
+        let location = trigger.location();
         let start = Instant::now();
         block_state = instance
             .process_trigger(
@@ -960,16 +1238,16 @@ async fn process_triggers(
             )
             .await
             .map_err(move |e| {
-                e.context(match transaction_id {
-                    Some(tx_hash) => format!(
-                        "Failed to process trigger in block {}, transaction {:x}",
-                        block_ptr, tx_hash
+                e.context(match location {
+                    Some(location) => format!(
+                        "Failed to process trigger in block {}, {}",
+                        block_ptr, location
                     ),
                     None => "Failed to process trigger".to_string(),
                 })
             })?;
         let elapsed = start.elapsed().as_secs_f64();
-        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_type);
+        subgraph_metrics.observe_trigger_processing_duration(elapsed, trigger_kind);
     }
     Ok(block_state)
 }
@@ -983,6 +1261,7 @@ fn create_dynamic_data_sources<B, T: RuntimeHostBuilder, S>(
 where
     B: BlockStreamBuilder,
     S: ChainStore + Store + SubgraphDeploymentStore + EthereumCallCache,
+    SubgraphInstance<T>: SubgraphInstanceTrait<B::Chain, T::Host>,
 {
     let mut data_sources = vec![];
     let mut runtime_hosts = vec![];
@@ -1058,20 +1337,276 @@ where
         entity_cache.append(operations)?;
     }
 
-    // Merge log filters from data sources into the block stream builder
-    ctx.state
-        .log_filter
-        .extend(EthereumLogFilter::from_data_sources(&data_sources));
+    // Merge the filters of the data sources into the block stream builder
+    let data_sources = data_sources
+        .iter()
+        .map(<B::Chain as Blockchain>::data_source)
+        .collect::<Result<Vec<_>, _>>()?;
+    ctx.state.filter.extend(data_sources.iter());
 
-    // Merge call filters from data sources into the block stream builder
-    ctx.state
-        .call_filter
-        .extend(EthereumCallFilter::from_data_sources(&data_sources));
+    Ok(())
+}
 
-    // Merge block filters from data sources into the block stream builder
-    ctx.state
-        .block_filter
-        .extend(EthereumBlockFilter::from_data_sources(&data_sources));
+#[cfg(test)]
+mod tests {
+    use futures01::sync::mpsc;
+    use graph::data::subgraph::{Mapping, Source};
+    use graph::prelude::web3::types::{Log, Transaction};
+    use graph_graphql::prelude::GraphQlRunner as RealGraphQlRunner;
+    use graph_mock::{MockBlockStreamBuilder, MockMetricsRegistry};
+    use test_store::{
+        create_test_subgraph, remove_subgraphs, run_test_sequentially, LOAD_MANAGER, LOGGER, STORE,
+    };
 
-    Ok(())
+    use super::*;
+
+    const SCHEMA: &str = "type Trigger @entity { id: ID!, block: Int! }";
+
+    /// The mock chain does not run mappings, so its hosts never see a
+    /// trigger
+    #[derive(Debug, PartialEq)]
+    struct TestHost;
+
+    #[async_trait]
+    impl RuntimeHost for TestHost {
+        fn matches_log(&self, _: &Log) -> bool {
+            false
+        }
+
+        fn matches_call(&self, _: &EthereumCall) -> bool {
+            false
+        }
+
+        fn matches_block(&self, _: &EthereumBlockTriggerType, _: u64) -> bool {
+            false
+        }
+
+        async fn process_log(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<Log>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unreachable!()
+        }
+
+        async fn process_call(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<EthereumCall>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unreachable!()
+        }
+
+        async fn process_block(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &EthereumBlockTriggerType,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unreachable!()
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestHostBuilder;
+
+    impl RuntimeHostBuilder for TestHostBuilder {
+        type Host = TestHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _: String,
+            _: SubgraphDeploymentId,
+            _: DataSource,
+            _: Arc<Vec<DataSourceTemplate>>,
+            _: mpsc::Sender<()>,
+            _: Arc<HostMetrics>,
+        ) -> Result<TestHost, Error> {
+            Ok(TestHost)
+        }
+
+        fn spawn_mapping(
+            _: Vec<u8>,
+            _: Logger,
+            _: SubgraphDeploymentId,
+            _: Arc<HostMetrics>,
+            _: Option<u64>,
+        ) -> Result<mpsc::Sender<()>, anyhow::Error> {
+            let (sender, _) = mpsc::channel(1);
+            Ok(sender)
+        }
+    }
+
+    fn setup(name: &str) -> SubgraphDeploymentId {
+        remove_subgraphs();
+        create_test_subgraph(name, SCHEMA);
+        SubgraphDeploymentId::new(name).unwrap()
+    }
+
+    /// A manifest with one data source on the mock chain
+    fn manifest(id: &SubgraphDeploymentId) -> SubgraphManifest {
+        let mapping = Mapping {
+            kind: "mock/blocks".to_owned(),
+            api_version: "0.0.4".to_owned(),
+            language: "wasm/assemblyscript".to_owned(),
+            entities: vec!["Trigger".to_owned()],
+            abis: vec![],
+            block_handlers: vec![],
+            call_handlers: vec![],
+            event_handlers: vec![],
+            runtime: Arc::new(vec![]),
+            link: Link {
+                link: "link".to_owned(),
+            },
+        };
+        SubgraphManifest {
+            id: id.clone(),
+            location: String::new(),
+            spec_version: "0.0.2".to_owned(),
+            description: None,
+            repository: None,
+            schema: Schema::parse(SCHEMA, id.clone()).unwrap(),
+            data_sources: vec![DataSource {
+                kind: graph_mock::MockChain::KIND.to_owned(),
+                network: Some("mainnet".to_owned()),
+                name: "mock".to_owned(),
+                source: Source {
+                    address: None,
+                    addresses: vec![],
+                    abi: String::new(),
+                    start_block: 0,
+                },
+                mapping,
+                context: None,
+                templates: vec![],
+            }],
+            graft: None,
+            history_blocks: None,
+            strict_bytes: false,
+            computed: None,
+            templates: vec![],
+        }
+    }
+
+    fn instance_manager<S: EntityStore>(
+        block_stream_builder: MockBlockStreamBuilder<S>,
+    ) -> SubgraphInstanceManager {
+        let stores: HashMap<_, _> = vec![("mainnet".to_owned(), STORE.clone())]
+            .into_iter()
+            .collect();
+        SubgraphInstanceManager::new(
+            &LoggerFactory::new(LOGGER.clone(), None),
+            stores,
+            Arc::new(graph_mock::MockChain),
+            TestHostBuilder,
+            block_stream_builder,
+            Arc::new(MockMetricsRegistry::new()),
+            Arc::new(RealGraphQlRunner::new(
+                &*LOGGER,
+                STORE.clone(),
+                LOAD_MANAGER.clone(),
+            )),
+            Arc::new(HandlerStatsRegistry::new()),
+        )
+    }
+
+    async fn send(manager: &SubgraphInstanceManager, event: SubgraphAssignmentProviderEvent) {
+        manager.event_sink().send(event).compat().await.unwrap();
+    }
+
+    /// Wait until the subgraph `id` has processed block `number`
+    async fn wait_for_block(id: &SubgraphDeploymentId, number: u64) {
+        for _ in 0..100 {
+            let ptr = STORE.block_ptr(id.clone()).unwrap();
+            if ptr.map(|ptr| ptr.number) == Some(number) {
+                return;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+        panic!("subgraph {} did not process block {}", id, number);
+    }
+
+    /// The number of the block in which the trigger `trigger` was
+    /// processed last
+    fn trigger_block(id: &SubgraphDeploymentId, trigger: u64) -> Option<Value> {
+        STORE
+            .get(EntityKey {
+                subgraph_id: id.clone(),
+                entity_type: "Trigger".into(),
+                entity_id: trigger.to_string(),
+            })
+            .unwrap()
+            .and_then(|entity| entity.get("block").cloned())
+    }
+
+    #[test]
+    fn indexes_mock_chain() {
+        run_test_sequentially(
+            || setup("instanceManagerMockChain"),
+            |_, id| async move {
+                let block_stream_builder = MockBlockStreamBuilder::new(STORE.clone(), 3);
+                let manager = instance_manager(block_stream_builder.clone());
+
+                send(
+                    &manager,
+                    SubgraphAssignmentProviderEvent::SubgraphStart(manifest(&id)),
+                )
+                .await;
+                wait_for_block(&id, 3).await;
+
+                // Block `n` has the triggers `0..n`
+                assert_eq!(Some(Value::Int(3)), trigger_block(&id, 0));
+                assert_eq!(Some(Value::Int(3)), trigger_block(&id, 2));
+                assert_eq!(None, trigger_block(&id, 3));
+                assert_eq!(vec![0], block_stream_builder.starts());
+            },
+        )
+    }
+
+    #[test]
+    fn restarted_subgraph_resumes_after_last_block() {
+        run_test_sequentially(
+            || setup("instanceManagerRestart"),
+            |_, id| async move {
+                let block_stream_builder = MockBlockStreamBuilder::new(STORE.clone(), 3);
+                let manager = instance_manager(block_stream_builder.clone());
+
+                send(
+                    &manager,
+                    SubgraphAssignmentProviderEvent::SubgraphStart(manifest(&id)),
+                )
+                .await;
+                wait_for_block(&id, 3).await;
+
+                // Restart the subgraph while the chain moves on
+                send(
+                    &manager,
+                    SubgraphAssignmentProviderEvent::SubgraphStop(id.clone()),
+                )
+                .await;
+                block_stream_builder.set_head(5);
+                send(
+                    &manager,
+                    SubgraphAssignmentProviderEvent::SubgraphStart(manifest(&id)),
+                )
+                .await;
+                wait_for_block(&id, 5).await;
+
+                // The second run starts after the last block of the first
+                assert_eq!(vec![0, 4], block_stream_builder.starts());
+                assert_eq!(Some(Value::Int(5)), trigger_block(&id, 4));
+            },
+        )
+    }
 }
@@ -18,7 +18,7 @@ pub struct DataSourceLoader<L, Q, S> {
 impl<L, Q, S> DataSourceLoader<L, Q, S>
 where
     L: LinkResolver,
-    S: Store + SubgraphDeploymentStore,
+    S: SubgraphDeploymentStore,
     Q: GraphQlRunner,
 {
     pub fn new(store: Arc<S>, link_resolver: Arc<L>, graphql_runner: Arc<Q>) -> Self {
@@ -47,6 +47,7 @@ where
             query deployment($id: ID!, $skip: Int!) {
               subgraphDeployment(id: $id) {
                 dynamicDataSources(orderBy: id, skip: $skip) {
+                  ethereumBlockNumber
                   kind
                   network
                   name
@@ -97,11 +98,13 @@ where
         Ok(Query::new(schema, document, variables, None))
     }
 
+    /// Parse the dynamic data sources in `query_result`, together with the
+    /// number of the block in which each of them was created
     fn parse_data_sources(
         &self,
         deployment_id: &SubgraphDeploymentId,
         query_result: q::Value,
-    ) -> Result<Vec<UnresolvedDataSource>, Error> {
+    ) -> Result<Vec<(u64, UnresolvedDataSource)>, Error> {
         let data = match query_result {
             q::Value::Object(obj) => Ok(obj),
             _ => Err(format_err!(
@@ -142,7 +145,11 @@ where
 
         // Parse the raw data sources into typed entities
         let entities = values.iter().try_fold(vec![], |mut entities, value| {
-            entities.push(UnresolvedDataSource::try_from_value(value)?);
+            let block = match value {
+                q::Value::Object(obj) => obj.get_required("ethereumBlockNumber")?,
+                _ => unreachable!("all dynamic data sources are objects"),
+            };
+            entities.push((block, UnresolvedDataSource::try_from_value(value)?));
             Ok(entities)
         });
 
@@ -168,42 +175,42 @@ where
         }
         Ok(result)
     }
-}
 
-#[async_trait]
-impl<L, Q, S> DataSourceLoaderTrait for DataSourceLoader<L, Q, S>
-where
-    L: LinkResolver,
-    Q: GraphQlRunner,
-    S: Store + SubgraphDeploymentStore,
-{
-    async fn load_dynamic_data_sources(
+    /// Load the dynamic data sources of `deployment_id`, or only the ones
+    /// that were created before block `before` if it is given
+    async fn load(
         &self,
         deployment_id: SubgraphDeploymentId,
+        before: Option<u64>,
         logger: Logger,
     ) -> Result<Vec<DataSource>, Error> {
         let start_time = Instant::now();
 
         let mut data_sources = vec![];
+        let mut skip = 0;
 
         loop {
-            let skip = data_sources.len() as i32;
             let query = self.dynamic_data_sources_query(&deployment_id, skip)?;
             let query_result = self
                 .graphql_runner
                 .cheap_clone()
                 .query_metadata(query)
                 .await?;
-            let unresolved_data_sources =
-                self.parse_data_sources(&deployment_id, query_result.as_ref().clone())?;
-            let next_data_sources = self
-                .resolve_data_sources(unresolved_data_sources, &logger)
-                .await?;
+            let parsed = self.parse_data_sources(&deployment_id, query_result.as_ref().clone())?;
 
-            if next_data_sources.is_empty() {
+            if parsed.is_empty() {
                 break;
             }
+            skip += parsed.len() as i32;
 
+            let unresolved_data_sources = parsed
+                .into_iter()
+                .filter(|(block, _)| before.map_or(true, |before| *block < before))
+                .map(|(_, data_source)| data_source)
+                .collect();
+            let next_data_sources = self
+                .resolve_data_sources(unresolved_data_sources, &logger)
+                .await?;
             data_sources.extend(next_data_sources);
         }
 
@@ -216,3 +223,28 @@ where
         Ok(data_sources)
     }
 }
+
+#[async_trait]
+impl<L, Q, S> DataSourceLoaderTrait for DataSourceLoader<L, Q, S>
+where
+    L: LinkResolver,
+    Q: GraphQlRunner,
+    S: SubgraphDeploymentStore,
+{
+    async fn load_dynamic_data_sources(
+        &self,
+        deployment_id: SubgraphDeploymentId,
+        logger: Logger,
+    ) -> Result<Vec<DataSource>, Error> {
+        self.load(deployment_id, None, logger).await
+    }
+
+    async fn load_dynamic_data_sources_before(
+        &self,
+        deployment_id: SubgraphDeploymentId,
+        block: u64,
+        logger: Logger,
+    ) -> Result<Vec<DataSource>, Error> {
+        self.load(deployment_id, Some(block), logger).await
+    }
+}
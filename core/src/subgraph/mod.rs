@@ -3,9 +3,11 @@ mod instance_manager;
 mod loader;
 mod provider;
 mod registrar;
+mod verifier;
 
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::loader::DataSourceLoader;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
+pub use self::verifier::{ProofOfIndexingVerifier, SandboxStore};
@@ -28,7 +28,7 @@ impl<L, Q, S> SubgraphAssignmentProvider<L, Q, S>
 where
     L: LinkResolver + Clone,
     Q: GraphQlRunner,
-    S: Store,
+    S: DeploymentStore,
 {
     pub fn new(
         logger_factory: &LoggerFactory,
@@ -78,7 +78,7 @@ impl<L, Q, S> SubgraphAssignmentProviderTrait for SubgraphAssignmentProvider<L,
 where
     L: LinkResolver + Clone,
     Q: GraphQlRunner,
-    S: Store + SubgraphDeploymentStore,
+    S: DeploymentStore + SubgraphDeploymentStore,
 {
     async fn start(
         &self,
@@ -174,11 +174,10 @@ where
                 block_ptr: None,
                 handler: None,
                 deterministic: false,
+                count: 1,
             };
 
-            let _ignore_error = store.apply_metadata_operations(
-                SubgraphDeploymentEntity::fail_operations(&subgraph_id, error),
-            );
+            let _ignore_error = store.fail_subgraph(&subgraph_id, error);
             e
         })
         .await
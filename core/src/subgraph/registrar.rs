@@ -5,13 +5,15 @@ use std::{env, iter};
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 
-use graph::components::ethereum::EthereumNetworks;
+use graph::components::ethereum::repair::{repair_chain, ChainRepair};
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
 use graph::data::subgraph::schema::{
     generate_entity_id, SubgraphDeploymentAssignmentEntity, SubgraphDeploymentEntity,
     SubgraphEntity, SubgraphVersionEntity, TypedEntity,
 };
 use graph::prelude::{
-    CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
+    CreateSubgraphResult, ProofOfIndexingVerifier as ProofOfIndexingVerifierTrait,
+    SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
 
@@ -25,7 +27,7 @@ lazy_static! {
     );
 }
 
-pub struct SubgraphRegistrar<L, P, S, CS> {
+pub struct SubgraphRegistrar<L, P, S, CS, V> {
     logger: Logger,
     logger_factory: LoggerFactory,
     resolver: Arc<L>,
@@ -33,17 +35,19 @@ pub struct SubgraphRegistrar<L, P, S, CS> {
     store: Arc<S>,
     chain_stores: HashMap<String, Arc<CS>>,
     ethereum_networks: EthereumNetworks,
+    poi_verifier: Arc<V>,
     node_id: NodeId,
     version_switching_mode: SubgraphVersionSwitchingMode,
     assignment_event_stream_cancel_guard: CancelGuard, // cancels on drop
 }
 
-impl<L, P, S, CS> SubgraphRegistrar<L, P, S, CS>
+impl<L, P, S, CS, V> SubgraphRegistrar<L, P, S, CS, V>
 where
     L: LinkResolver + Clone,
     P: SubgraphAssignmentProviderTrait,
     S: Store + SubgraphDeploymentStore,
     CS: ChainStore,
+    V: ProofOfIndexingVerifierTrait,
 {
     pub fn new(
         logger_factory: &LoggerFactory,
@@ -52,6 +56,7 @@ where
         store: Arc<S>,
         chain_stores: HashMap<String, Arc<CS>>,
         ethereum_networks: EthereumNetworks,
+        poi_verifier: Arc<V>,
         node_id: NodeId,
         version_switching_mode: SubgraphVersionSwitchingMode,
     ) -> Self {
@@ -72,6 +77,7 @@ where
             store,
             chain_stores,
             ethereum_networks,
+            poi_verifier,
             node_id,
             version_switching_mode,
             assignment_event_stream_cancel_guard: CancelGuard::new(),
@@ -281,12 +287,13 @@ where
 }
 
 #[async_trait]
-impl<L, P, S, CS> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS>
+impl<L, P, S, CS, V> SubgraphRegistrarTrait for SubgraphRegistrar<L, P, S, CS, V>
 where
     L: LinkResolver,
     P: SubgraphAssignmentProviderTrait,
     S: Store + SubgraphDeploymentStore,
     CS: ChainStore,
+    V: ProofOfIndexingVerifierTrait,
 {
     async fn create_subgraph(
         &self,
@@ -314,6 +321,9 @@ where
         let (manifest, validation_warnings) = unvalidated
             .validate(self.store.clone())
             .map_err(SubgraphRegistrarError::ManifestValidationError)?;
+        for warning in &validation_warnings {
+            warn!(&logger, "{}", warning; "subgraph_hash" => hash.to_string());
+        }
 
         let network_name = manifest.network_name();
 
@@ -369,6 +379,189 @@ where
     ) -> Result<(), SubgraphRegistrarError> {
         reassign_subgraph(self.store.clone(), hash, node_id)
     }
+
+    async fn restore_unused_deployment(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if self.store.restore_unused_deployment(&hash)? {
+            info!(self.logger, "Restored unused deployment"; "deployment" => hash.as_str());
+            Ok(())
+        } else {
+            Err(SubgraphRegistrarError::DeploymentNotUnused(
+                hash.to_string(),
+            ))
+        }
+    }
+
+    async fn set_memory_limit(
+        &self,
+        hash: SubgraphDeploymentId,
+        limit: Option<u64>,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if self.store.set_memory_limit(&hash, limit)? {
+            info!(self.logger, "Changed memory limit of deployment";
+                  "deployment" => hash.as_str(),
+                  "max_memory" => limit.map(|limit| limit.to_string()).unwrap_or("default".to_owned()));
+            Ok(())
+        } else {
+            Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))
+        }
+    }
+
+    async fn flag_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        note: String,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if self.store.set_deployment_flag(&hash, Some(note.clone()))? {
+            warn!(self.logger, "Flagged deployment as unhealthy";
+                  "deployment" => hash.as_str(),
+                  "note" => note);
+            Ok(())
+        } else {
+            Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))
+        }
+    }
+
+    async fn unflag_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError> {
+        if self.store.set_deployment_flag(&hash, None)? {
+            info!(self.logger, "Removed unhealthy flag from deployment";
+                  "deployment" => hash.as_str());
+            Ok(())
+        } else {
+            Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()))
+        }
+    }
+
+    async fn restart_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        reason: Option<String>,
+    ) -> Result<RestartSubgraphResult, SubgraphRegistrarError> {
+        let node_id = match self
+            .store
+            .get(SubgraphDeploymentAssignmentEntity::key(hash.clone()))?
+            .as_ref()
+            .and_then(|assignment| assignment.get("nodeId"))
+        {
+            Some(Value::String(node_id)) => node_id.clone(),
+            _ => return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string())),
+        };
+        if node_id != self.node_id.to_string() {
+            return Ok(RestartSubgraphResult {
+                restarted: false,
+                node_id,
+            });
+        }
+
+        let logger = self.logger_factory.subgraph_logger(&hash);
+        info!(logger, "Restarting subgraph";
+              "reason" => reason.as_deref().unwrap_or("none given"));
+
+        // A deployment that failed to start is not running, but restarting
+        // it should still start it
+        match self.provider.stop(hash.clone()).await {
+            Ok(()) | Err(SubgraphAssignmentProviderError::NotRunning(_)) => {}
+            Err(e) => return Err(SubgraphRegistrarError::Unknown(e.into())),
+        }
+        self.provider
+            .start(&hash)
+            .await
+            .map_err(|e| SubgraphRegistrarError::Unknown(e.into()))?;
+
+        Ok(RestartSubgraphResult {
+            restarted: true,
+            node_id,
+        })
+    }
+
+    async fn repair_block_ranges(
+        &self,
+        hash: SubgraphDeploymentId,
+        dry_run: bool,
+    ) -> Result<RepairBlockRangesResult, SubgraphRegistrarError> {
+        if self
+            .store
+            .get(SubgraphDeploymentEntity::key(hash.clone()))?
+            .is_none()
+        {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+
+        let overlaps = if dry_run {
+            self.store.check_block_ranges(&hash)?
+        } else {
+            self.store.repair_block_ranges(&hash)?
+        };
+        let repaired = !dry_run && !overlaps.is_empty();
+        if repaired {
+            info!(self.logger, "Repaired overlapping block ranges";
+                  "deployment" => hash.as_str(),
+                  "entities" => overlaps.len());
+        }
+
+        Ok(RepairBlockRangesResult { overlaps, repaired })
+    }
+
+    async fn repair_chain(
+        &self,
+        network: String,
+        from: u64,
+        to: u64,
+    ) -> Result<ChainRepair, SubgraphRegistrarError> {
+        let chain_store = self
+            .chain_stores
+            .get(&network)
+            .ok_or_else(|| SubgraphRegistrarError::NetworkNotSupported(network.clone()))?
+            .clone();
+        // Any Ethereum node can give us the blocks we need
+        let adapter = self
+            .ethereum_networks
+            .adapter_with_capabilities(
+                network.clone(),
+                &NodeCapabilities {
+                    archive: false,
+                    traces: false,
+                },
+            )
+            .map_err(|_| SubgraphRegistrarError::NetworkNotSupported(network.clone()))?
+            .clone();
+        let logger = self.logger.new(o!("network" => network));
+
+        repair_chain(&logger, chain_store, adapter, from, to)
+            .await
+            .map_err(SubgraphRegistrarError::Unknown)
+    }
+
+    async fn verify_proof_of_indexing(
+        &self,
+        hash: SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<PoiVerificationResult, SubgraphRegistrarError> {
+        if self
+            .store
+            .get(SubgraphDeploymentEntity::key(hash.clone()))?
+            .is_none()
+        {
+            return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string()));
+        }
+
+        // A replay makes many calls to the store, so it runs on a blocking
+        // task. A panic during the replay only fails this verification
+        let poi_verifier = self.poi_verifier.clone();
+        graph::spawn_blocking_allow_panic(move || {
+            graph::block_on(poi_verifier.verify(&hash, from, to))
+        })
+        .await
+        .map_err(|e| format_err!("the replay panicked: {}", e))
+        .and_then(|result| result)
+        .map_err(SubgraphRegistrarError::Unknown)
+    }
 }
 
 async fn handle_assignment_event(
@@ -895,7 +1088,23 @@ fn create_subgraph_version(
                     );
                     deployment_store
                         .create_subgraph_deployment(&manifest.schema, ops)
-                        .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))
+                        .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))?;
+                    if let Some(history_blocks) = manifest.history_blocks {
+                        deployment_store
+                            .set_history_blocks(&manifest.id, Some(history_blocks))
+                            .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))?;
+                    }
+                    if manifest.strict_bytes {
+                        deployment_store
+                            .set_strict_bytes(&manifest.id, true)
+                            .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))?;
+                    }
+                    if let Some(computed) = &manifest.computed {
+                        deployment_store
+                            .set_computed_module(&manifest.id, computed.runtime.as_ref())
+                            .map_err(|e| SubgraphRegistrarError::SubgraphDeploymentError(e))?;
+                    }
+                    Ok(())
                 }
             })
         }),
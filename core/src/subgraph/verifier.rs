@@ -0,0 +1,1087 @@
+//! Verifying the proofs of indexing of a deployment by replaying blocks.
+//!
+//! A replay runs the mappings of a deployment for a range of blocks against
+//! a `SandboxStore`, which starts out with the entities as the store had
+//! them before the first block of the range and keeps all changes in
+//! memory. Blocks come from the block cache and `ethereum.call`s are
+//! answered from the call cache, so that nothing is sent to an Ethereum
+//! node; a replay fails if something it needs is not cached. After each
+//! block, the proofs of indexing and the entities that the block changed
+//! are compared with what the store has for that block, and the replay
+//! stops at the first block where they differ.
+
+use atomic_refcell::AtomicRefCell;
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Deref;
+use std::sync::RwLock;
+
+use graph::blockchain::Block as _;
+use graph::components::ethereum::replay::ReplayEthereumAdapter;
+use graph::components::ethereum::EthereumBlockWithCalls;
+use graph::components::subgraph::handler_stats::DeploymentHandlerStats;
+use graph::components::subgraph::{MappingError, ProofOfIndexing};
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::data::subgraph::{EntityDivergence, PoiDigestDivergence, PoiDivergence};
+use graph::prelude::web3::types::{Address, H256};
+use graph::prelude::{
+    DataSourceLoader as DataSourceLoaderTrait,
+    ProofOfIndexingVerifier as ProofOfIndexingVerifierTrait,
+    SubgraphInstance as SubgraphInstanceTrait, *,
+};
+use graph::util::lfu_cache::LfuCache;
+use graph_chain_ethereum::{EthereumChain, EthereumTriggerFilter, EthereumTriggersAdapter};
+
+use super::instance_manager::{
+    process_triggers, update_proof_of_indexing, SubgraphInstanceMetrics,
+};
+use super::registrar::IPFS_SUBGRAPH_LOADING_TIMEOUT;
+use super::{DataSourceLoader, SubgraphInstance};
+use crate::MetricsRegistry;
+
+lazy_static! {
+    /// The largest number of blocks that one verification may replay
+    static ref POI_VERIFY_MAX_BLOCKS: u64 = std::env::var("GRAPH_POI_VERIFY_MAX_BLOCKS")
+        .map(|s| s
+            .parse::<u64>()
+            .expect("invalid GRAPH_POI_VERIFY_MAX_BLOCKS"))
+        .unwrap_or(1000);
+}
+
+pub struct ProofOfIndexingVerifier<L, Q, S, T> {
+    logger: Logger,
+    resolver: Arc<L>,
+    graphql_runner: Arc<Q>,
+    stores: HashMap<String, Arc<S>>,
+    host_builder: T,
+}
+
+impl<L, Q, S, T> ProofOfIndexingVerifier<L, Q, S, T>
+where
+    L: LinkResolver + Clone,
+{
+    /// The mappings of replayed blocks run in hosts from `host_builder`,
+    /// which must answer `ethereum.call`s without an Ethereum node, for
+    /// example because it was built with the adapters from
+    /// `replay::call_cache_networks`
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        resolver: Arc<L>,
+        graphql_runner: Arc<Q>,
+        stores: HashMap<String, Arc<S>>,
+        host_builder: T,
+    ) -> Self {
+        let logger = logger_factory.component_logger("ProofOfIndexingVerifier", None);
+
+        ProofOfIndexingVerifier {
+            logger,
+            resolver: Arc::new(
+                resolver
+                    .as_ref()
+                    .clone()
+                    .with_timeout(*IPFS_SUBGRAPH_LOADING_TIMEOUT)
+                    .with_retries(),
+            ),
+            graphql_runner,
+            stores,
+            host_builder,
+        }
+    }
+}
+
+#[async_trait]
+impl<L, Q, S, T> ProofOfIndexingVerifierTrait for ProofOfIndexingVerifier<L, Q, S, T>
+where
+    L: LinkResolver + Clone,
+    Q: GraphQlRunner,
+    S: Store + ChainStore + SubgraphDeploymentStore + EthereumCallCache,
+    T: RuntimeHostBuilder,
+{
+    async fn verify(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<PoiVerificationResult, Error> {
+        if from > to {
+            return Err(format_err!(
+                "the range of blocks to replay is empty: block {} comes after block {}",
+                from,
+                to
+            ));
+        }
+        if to - from >= *POI_VERIFY_MAX_BLOCKS {
+            return Err(format_err!(
+                "can not replay more than {} blocks at once, but was asked to replay blocks {} to {}",
+                *POI_VERIFY_MAX_BLOCKS,
+                from,
+                to
+            ));
+        }
+
+        let logger = self.logger.new(o!(
+            "subgraph_id" => deployment.to_string(),
+            "from" => from,
+            "to" => to,
+        ));
+
+        let mut manifest =
+            SubgraphManifest::resolve(deployment.to_ipfs_link(), self.resolver.deref(), &logger)
+                .await
+                .map_err(|e| format_err!("failed to resolve the subgraph manifest: {}", e))?;
+
+        if manifest.requires_traces() {
+            return Err(format_err!(
+                "deployment `{}` has call handlers or block handlers with a call filter, \
+                 which need traces from an Ethereum node and can not be replayed",
+                deployment
+            ));
+        }
+        let kind = manifest
+            .data_sources
+            .first()
+            .map(|data_source| data_source.kind.clone())
+            .unwrap_or_default();
+        if kind != EthereumChain::KIND {
+            return Err(format_err!(
+                "data sources of kind `{}` can not be replayed",
+                kind
+            ));
+        }
+
+        let network = manifest.network_name();
+        let store = self
+            .stores
+            .get(&network)
+            .ok_or_else(|| format_err!("network `{}` is not supported", network))?
+            .clone();
+        if !store.uses_relational_schema(deployment)? {
+            return Err(format_err!(
+                "deployment `{}` does not keep the history of its entities",
+                deployment
+            ));
+        }
+        if !store.supports_proof_of_indexing(deployment).await? {
+            return Err(format_err!(
+                "deployment `{}` does not have a proof of indexing",
+                deployment
+            ));
+        }
+        match store.block_ptr(deployment.clone())? {
+            Some(ptr) if ptr.number >= to => (),
+            _ => {
+                return Err(format_err!(
+                    "deployment `{}` has not processed block {} yet",
+                    deployment,
+                    to
+                ))
+            }
+        }
+
+        // Only the data sources that existed before the first block are
+        // loaded; the replay creates the ones that were created later
+        let loader = DataSourceLoader::new(
+            store.clone(),
+            self.resolver.clone(),
+            self.graphql_runner.clone(),
+        );
+        manifest.data_sources.extend(
+            loader
+                .load_dynamic_data_sources_before(deployment.clone(), from, logger.clone())
+                .await?,
+        );
+
+        let max_memory = store.memory_limit(deployment)?;
+        let mut replay = Replay::new(
+            logger,
+            manifest,
+            self.host_builder.clone(),
+            store,
+            from,
+            max_memory,
+        )?;
+        replay.verify(from, to).await
+    }
+}
+
+/// The block with number `number` from the block cache. If the cache has
+/// several blocks with that number, the one on the main chain is used
+fn cached_block(store: &impl ChainStore, number: u64) -> Result<BlockFinality, Error> {
+    let hash = store
+        .canonical_block_hash_by_block_number(number)?
+        .ok_or_else(|| format_err!("block {} is not in the block cache", number))?;
+    let block = store
+        .ancestor_block(EthereumBlockPointer { hash, number }, 0)?
+        .ok_or_else(|| format_err!("block {} is not in the block cache", number))?;
+    if block.transaction_receipts.len() != block.block.transactions.len() {
+        return Err(format_err!(
+            "the block cache does not have the transaction receipts of block {} ({:x})",
+            number,
+            hash
+        ));
+    }
+
+    // Replayed blocks are treated like blocks close to the chain head,
+    // whose triggers are found in the block itself
+    Ok(BlockFinality::NonFinal(EthereumBlockWithCalls {
+        ethereum_block: block,
+        calls: vec![],
+    }))
+}
+
+/// The state of a replay that carries over from block to block
+struct Replay<S, T: RuntimeHostBuilder> {
+    logger: Logger,
+    store: Arc<S>,
+    deployment: SubgraphDeploymentId,
+    filter: EthereumTriggerFilter,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    instance: SubgraphInstance<T>,
+    triggers_adapter: EthereumTriggersAdapter,
+    host_metrics: Arc<HostMetrics>,
+    subgraph_metrics: Arc<SubgraphInstanceMetrics>,
+    sandbox: Arc<SandboxStore<S>>,
+}
+
+impl<S, T> Replay<S, T>
+where
+    S: EntityStore + ChainStore,
+    T: RuntimeHostBuilder,
+{
+    /// Set up the replay of the deployment of `manifest` from block `from`
+    /// on. The manifest must already have the data sources that were
+    /// created before that block
+    fn new(
+        logger: Logger,
+        manifest: SubgraphManifest,
+        host_builder: T,
+        store: Arc<S>,
+        from: u64,
+        max_memory: Option<u64>,
+    ) -> Result<Self, Error> {
+        let deployment = manifest.id.clone();
+
+        // The metrics of the replay go to a registry of their own so that
+        // they do not clash with the metrics of the deployment itself
+        let registry = Arc::new(MetricsRegistry::new(
+            logger.clone(),
+            Arc::new(Registry::new()),
+        ));
+        let stopwatch = StopwatchMetrics::new(logger.clone(), deployment.clone(), registry.clone());
+        let host_metrics = Arc::new(HostMetrics::new(
+            registry.clone(),
+            deployment.as_str(),
+            stopwatch,
+            Arc::new(DeploymentHandlerStats::new()),
+        ));
+        let subgraph_metrics = Arc::new(SubgraphInstanceMetrics::new(
+            registry.clone(),
+            deployment.as_str(),
+        ));
+        let triggers_adapter = EthereumTriggersAdapter::new(
+            Arc::new(ReplayEthereumAdapter::call_cache()),
+            store.clone(),
+            Arc::new(SubgraphEthRpcMetrics::new(registry, deployment.as_str())),
+        );
+
+        Ok(Replay {
+            logger: logger.clone(),
+            store: store.clone(),
+            deployment: deployment.clone(),
+            filter: EthereumTriggerFilter::from_manifest(&manifest)?,
+            templates: Arc::new(manifest.templates.clone()),
+            instance: SubgraphInstance::from_manifest(
+                &logger,
+                manifest,
+                host_builder,
+                host_metrics.clone(),
+                max_memory,
+            )?,
+            triggers_adapter,
+            host_metrics,
+            subgraph_metrics,
+            sandbox: Arc::new(SandboxStore::new(
+                store,
+                deployment,
+                from as BlockNumber - 1,
+            )),
+        })
+    }
+
+    /// Replay the blocks `from` to `to` from the block cache and compare
+    /// each of them with the store. The replay stops at the first block
+    /// that diverges from the store
+    async fn verify(&mut self, from: u64, to: u64) -> Result<PoiVerificationResult, Error> {
+        info!(
+            self.logger,
+            "Replaying blocks to verify the proof of indexing"
+        );
+        for number in from..=to {
+            let block = cached_block(self.store.as_ref(), number)?;
+            let block_ptr = block.ptr();
+            let mods = self
+                .block(block)
+                .await
+                .map_err(|e| format_err!("failed to replay block {}: {}", block_ptr, e))?;
+
+            if let Some(divergence) = self.sandbox.apply_block(block_ptr, mods)? {
+                warn!(self.logger, "Replay diverges from the store";
+                      "block_number" => number,
+                      "block_hash" => &divergence.block_hash,
+                      "digests" => divergence.digests.len(),
+                      "entities" => divergence.entities.len());
+                return Ok(PoiVerificationResult {
+                    blocks: number - from + 1,
+                    divergence: Some(divergence),
+                });
+            }
+        }
+        info!(self.logger, "Replay matches the store");
+
+        Ok(PoiVerificationResult {
+            blocks: to - from + 1,
+            divergence: None,
+        })
+    }
+
+    /// Run the mappings for `block` and return the entity changes they
+    /// make, including the changes to the proof of indexing. Data sources
+    /// that are created in the block are added to the replay, but, unlike
+    /// during indexing, not stored
+    async fn block(&mut self, block: BlockFinality) -> Result<Vec<EntityModification>, Error> {
+        let block_ptr = block.ptr();
+        let logger = self.logger.new(o!("block_number" => block_ptr.number));
+        let mapping_block = Arc::new(block.mapping_block());
+        let proof_of_indexing =
+            Arc::new(AtomicRefCell::new(ProofOfIndexing::new(block_ptr.number)));
+
+        let triggers = self
+            .triggers_adapter
+            .triggers_in_block(&logger, block.clone(), self.filter.clone())
+            .await?
+            .triggers;
+        let mut block_state = process_triggers::<EthereumChain, T>(
+            &logger,
+            BlockState::new(self.sandbox.clone(), LfuCache::new()),
+            Some(proof_of_indexing.clone()),
+            self.subgraph_metrics.clone(),
+            &self.instance,
+            block_ptr,
+            &mapping_block,
+            triggers,
+        )
+        .await
+        .map_err(mapping_error)?;
+
+        while !block_state.created_data_sources.is_empty() {
+            let mut data_sources = vec![];
+            let mut runtime_hosts = vec![];
+            for info in block_state.created_data_sources.drain(..) {
+                let data_source = DataSource::try_from(info).compat_err()?;
+                let host = self
+                    .instance
+                    .add_dynamic_data_source(
+                        &logger,
+                        data_source.clone(),
+                        self.templates.clone(),
+                        self.host_metrics.clone(),
+                    )
+                    .compat_err()?;
+                if let Some(host) = host {
+                    data_sources.push(data_source);
+                    runtime_hosts.push(host);
+                }
+            }
+
+            let triggers = self
+                .triggers_adapter
+                .triggers_in_block(
+                    &logger,
+                    block.clone(),
+                    EthereumTriggerFilter::from_data_sources(data_sources.iter()),
+                )
+                .await?
+                .triggers;
+            self.filter.extend(data_sources.iter());
+
+            for trigger in triggers {
+                block_state = <SubgraphInstance<T> as SubgraphInstanceTrait<
+                    EthereumChain,
+                    T::Host,
+                >>::process_trigger_in_runtime_hosts(
+                    &logger,
+                    &runtime_hosts,
+                    &mapping_block,
+                    trigger,
+                    block_state,
+                    Some(proof_of_indexing.clone()),
+                )
+                .await
+                .map_err(mapping_error)?;
+            }
+        }
+
+        let proof_of_indexing = Arc::try_unwrap(proof_of_indexing).unwrap().into_inner();
+        update_proof_of_indexing(
+            proof_of_indexing,
+            &self.host_metrics.stopwatch,
+            &self.deployment,
+            &mut block_state.entity_cache,
+        )
+        .await?;
+
+        Ok(block_state
+            .entity_cache
+            .as_modifications(self.sandbox.as_ref())
+            .map_err(|e| format_err!("{}", e))?
+            .modifications)
+    }
+}
+
+fn mapping_error(e: MappingError) -> Error {
+    match e {
+        MappingError::Unknown(e)
+        | MappingError::PossibleReorg(e)
+        | MappingError::Deterministic(e) => format_err!("{:#}", e),
+    }
+}
+
+/// An `EntityStore` for replaying the blocks of a deployment. It starts out
+/// with the entities of the deployment as they were at `base_block` and
+/// keeps the changes of replayed blocks in memory; writing to it fails.
+/// Everything else, including the entities of other deployments, comes
+/// straight from the underlying store
+pub struct SandboxStore<S> {
+    store: Arc<S>,
+    deployment: SubgraphDeploymentId,
+    base_block: BlockNumber,
+    /// The entities that replayed blocks changed, `None` for entities that
+    /// they removed
+    changes: RwLock<HashMap<EntityKey, Option<Entity>>>,
+}
+
+impl<S: EntityStore> SandboxStore<S> {
+    pub fn new(store: Arc<S>, deployment: SubgraphDeploymentId, base_block: BlockNumber) -> Self {
+        SandboxStore {
+            store,
+            deployment,
+            base_block,
+            changes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The entities of type `entity_type` with one of the given ids as the
+    /// store has them at `block`
+    fn stored(
+        &self,
+        block: BlockNumber,
+        entity_type: &str,
+        ids: &[&str],
+    ) -> Result<Vec<Entity>, QueryExecutionError> {
+        if block < 0 || ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let query = EntityQuery::new(
+            self.deployment.clone(),
+            block,
+            EntityCollection::All(vec![entity_type.to_owned()]),
+        )
+        .filter(EntityFilter::In(
+            "id".into(),
+            ids.iter().map(|id| Value::String(id.to_string())).collect(),
+        ))
+        .range(EntityRange {
+            first: None,
+            skip: 0,
+        });
+        self.store.find(query)
+    }
+
+    /// Apply the changes `mods` that replaying `block_ptr` made and compare
+    /// them with the store. The block diverges from the store if the
+    /// proofs of indexing after the block differ, or if an entity that
+    /// `mods` changes differs from the version the store has for the block
+    pub fn apply_block(
+        &self,
+        block_ptr: EthereumBlockPointer,
+        mods: Vec<EntityModification>,
+    ) -> Result<Option<PoiDivergence>, Error> {
+        let block = block_ptr.number as BlockNumber;
+
+        let mut replayed = BTreeMap::new();
+        {
+            let mut changes = self.changes.write().unwrap();
+            let changed = mods
+                .into_iter()
+                .flat_map(|modification| match modification {
+                    EntityModification::Insert { key, data }
+                    | EntityModification::Overwrite { key, data, .. } => vec![(key, Some(data))],
+                    EntityModification::Remove { key } => vec![(key, None)],
+                    EntityModification::RemoveMany { keys } => {
+                        keys.into_iter().map(|key| (key, None)).collect()
+                    }
+                });
+            for (key, data) in changed {
+                if key.subgraph_id != self.deployment {
+                    continue;
+                }
+                changes.insert(key.clone(), data.clone());
+                if key.entity_type != POI_OBJECT {
+                    replayed.insert((key.entity_type.to_string(), key.entity_id), data);
+                }
+            }
+        }
+
+        let mut ids_for_type: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (entity_type, entity_id) in replayed.keys() {
+            ids_for_type
+                .entry(entity_type.as_str())
+                .or_default()
+                .push(entity_id.as_str());
+        }
+        let mut stored = HashMap::new();
+        for (entity_type, ids) in ids_for_type {
+            for entity in self.stored(block, entity_type, &ids)? {
+                stored.insert((entity_type.to_owned(), entity.id()?), entity);
+            }
+        }
+        let entities: Vec<_> = replayed
+            .into_iter()
+            .filter_map(|((entity_type, entity_id), replayed)| {
+                let stored = normalize(stored.remove(&(entity_type.clone(), entity_id.clone())));
+                let replayed = normalize(replayed);
+                if stored == replayed {
+                    None
+                } else {
+                    Some(EntityDivergence {
+                        entity_type,
+                        entity_id,
+                        stored,
+                        replayed,
+                    })
+                }
+            })
+            .collect();
+
+        let query = EntityQuery::new(
+            self.deployment.clone(),
+            block,
+            EntityCollection::All(vec![POI_OBJECT.to_owned()]),
+        )
+        .range(EntityRange {
+            first: None,
+            skip: 0,
+        });
+        let mut stored_digests = BTreeMap::new();
+        for entity in self.store.find(query)? {
+            stored_digests.insert(entity.id()?, digest(&entity));
+        }
+        let causality_regions: BTreeSet<_> = self
+            .changes
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|key| key.entity_type == POI_OBJECT)
+            .map(|key| key.entity_id.clone())
+            .chain(stored_digests.keys().cloned())
+            .collect();
+        let mut digests = vec![];
+        for causality_region in causality_regions {
+            let key = EntityKey {
+                subgraph_id: self.deployment.clone(),
+                entity_type: POI_OBJECT.into(),
+                entity_id: causality_region.clone(),
+            };
+            let stored = stored_digests.remove(&causality_region).flatten();
+            let replayed = self.get(key)?.as_ref().and_then(digest);
+            if stored != replayed {
+                digests.push(PoiDigestDivergence {
+                    causality_region,
+                    stored,
+                    replayed,
+                });
+            }
+        }
+
+        if digests.is_empty() && entities.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(PoiDivergence {
+                block_number: block_ptr.number,
+                block_hash: format!("{:x}", block_ptr.hash),
+                digests,
+                entities,
+            }))
+        }
+    }
+}
+
+/// The digest of a `Poi$` entity in hex
+fn digest(entity: &Entity) -> Option<String> {
+    match entity.get("digest") {
+        Some(Value::Bytes(digest)) => Some(digest.to_string()),
+        _ => None,
+    }
+}
+
+/// Remove what the store adds to entities, and attributes that are not
+/// set, so that entities from the store can be compared with entities from
+/// the entity cache
+fn normalize(entity: Option<Entity>) -> Option<Entity> {
+    entity.map(|mut entity| {
+        entity.remove("__typename");
+        entity.retain(|_, value| *value != Value::Null);
+        entity
+    })
+}
+
+impl<S: EntityStore> EntityStore for SandboxStore<S> {
+    fn block_ptr(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+    ) -> Result<Option<EthereumBlockPointer>, Error> {
+        self.store.block_ptr(subgraph_id)
+    }
+
+    fn supports_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+    ) -> DynTryFuture<'a, bool> {
+        self.store.supports_proof_of_indexing(subgraph_id)
+    }
+
+    fn get_proof_of_indexing<'a>(
+        &'a self,
+        subgraph_id: &'a SubgraphDeploymentId,
+        indexer: &'a Option<Address>,
+        block_hash: H256,
+    ) -> DynTryFuture<'a, Option<[u8; 32]>> {
+        self.store
+            .get_proof_of_indexing(subgraph_id, indexer, block_hash)
+    }
+
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        if key.subgraph_id != self.deployment {
+            return self.store.get(key);
+        }
+        if let Some(entity) = self.changes.read().unwrap().get(&key) {
+            return Ok(entity.clone());
+        }
+        Ok(self
+            .stored(self.base_block, &key.entity_type, &[&key.entity_id])?
+            .pop())
+    }
+
+    fn get_many(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        if subgraph_id != &self.deployment {
+            return self.store.get_many(subgraph_id, ids_for_type);
+        }
+
+        let changes = self.changes.read().unwrap();
+        let mut entities_for_type = BTreeMap::new();
+        for (entity_type, ids) in ids_for_type {
+            let mut entities = vec![];
+            let mut unchanged = vec![];
+            for id in ids {
+                let key = EntityKey {
+                    subgraph_id: self.deployment.clone(),
+                    entity_type: entity_type.into(),
+                    entity_id: id.to_owned(),
+                };
+                match changes.get(&key) {
+                    Some(entity) => entities.extend(entity.clone()),
+                    None => unchanged.push(id),
+                }
+            }
+            entities.extend(self.stored(self.base_block, entity_type, &unchanged)?);
+            if !entities.is_empty() {
+                entities_for_type.insert(entity_type.to_owned(), entities);
+            }
+        }
+        Ok(entities_for_type)
+    }
+
+    fn find(&self, _query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries during a replay".to_owned(),
+        ))
+    }
+
+    fn find_one(&self, _query: EntityQuery) -> Result<Option<Entity>, QueryExecutionError> {
+        Err(QueryExecutionError::NotSupported(
+            "queries during a replay".to_owned(),
+        ))
+    }
+
+    fn find_ens_name(&self, hash: &str) -> Result<Option<String>, QueryExecutionError> {
+        self.store.find_ens_name(hash)
+    }
+
+    fn transact_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        _block_ptr_to: EthereumBlockPointer,
+        _mods: Vec<EntityModification>,
+        _stopwatch: StopwatchMetrics,
+    ) -> Result<bool, StoreError> {
+        Err(StoreError::Unknown(format_err!(
+            "the replay of deployment `{}` can not write to the store",
+            subgraph_id
+        )))
+    }
+
+    fn revert_block_operations(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        _block_ptr_from: EthereumBlockPointer,
+        _block_ptr_to: EthereumBlockPointer,
+    ) -> Result<(), StoreError> {
+        Err(StoreError::Unknown(format_err!(
+            "the replay of deployment `{}` can not write to the store",
+            subgraph_id
+        )))
+    }
+
+    fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        self.store.subscribe(entities)
+    }
+
+    fn scan_watermark(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<ScanWatermark>, StoreError> {
+        // A replay scans the blocks it replays from scratch
+        Ok(None)
+    }
+
+    fn set_scan_watermark(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _watermark: ScanWatermark,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    fn block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        self.store.block_number(subgraph_id, block_hash)
+    }
+
+    fn query_store(self: Arc<Self>, for_subscription: bool) -> Arc<dyn QueryStore + Send + Sync> {
+        self.store.clone().query_store(for_subscription)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures01::sync::mpsc;
+    use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
+    use graph::data::subgraph::{Mapping, MappingBlockHandler, Source};
+    use graph::prelude::web3::types::{Log, Transaction, U64};
+    use graph_mock::MockMetricsRegistry;
+    use test_store::block_store::GENESIS_BLOCK;
+    use test_store::{create_test_subgraph, remove_subgraphs, LOGGER, TEST_STORE};
+
+    use super::*;
+
+    const SCHEMA: &str = "type User @entity { id: ID!, name: String!, block: Int! }";
+
+    const CAUSALITY_REGION: &str = "ethereum/mainnet";
+
+    /// A mapping that sets user `1` in every block, with the name `Alice`
+    /// and the number of the block
+    #[derive(Debug, PartialEq)]
+    struct TestHost;
+
+    #[async_trait]
+    impl RuntimeHost for TestHost {
+        fn matches_log(&self, _: &Log) -> bool {
+            false
+        }
+
+        fn matches_call(&self, _: &EthereumCall) -> bool {
+            false
+        }
+
+        fn matches_block(&self, _: &EthereumBlockTriggerType, _: u64) -> bool {
+            true
+        }
+
+        async fn process_log(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<Log>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unreachable!()
+        }
+
+        async fn process_call(
+            &self,
+            _: &Logger,
+            _: &Arc<LightEthereumBlock>,
+            _: &Arc<Transaction>,
+            _: &Arc<EthereumCall>,
+            _: BlockState,
+            _: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            unreachable!()
+        }
+
+        async fn process_block(
+            &self,
+            logger: &Logger,
+            block: &Arc<LightEthereumBlock>,
+            _: &EthereumBlockTriggerType,
+            mut state: BlockState,
+            proof_of_indexing: SharedProofOfIndexing,
+        ) -> Result<BlockState, MappingError> {
+            let mut data = HashMap::new();
+            data.insert("id".to_owned(), Value::from("1"));
+            data.insert("name".to_owned(), Value::from("Alice"));
+            data.insert(
+                "block".to_owned(),
+                Value::Int(block.number.unwrap().as_u64() as i32),
+            );
+            if let Some(proof_of_indexing) = proof_of_indexing {
+                proof_of_indexing.borrow_mut().write(
+                    logger,
+                    CAUSALITY_REGION,
+                    &ProofOfIndexingEvent::SetEntity {
+                        entity_type: "User",
+                        id: "1",
+                        data: &data,
+                    },
+                );
+            }
+            let key = EntityKey {
+                subgraph_id: SubgraphDeploymentId::new(DEPLOYMENT).unwrap(),
+                entity_type: "User".into(),
+                entity_id: "1".to_owned(),
+            };
+            state.entity_cache.set(key, Entity::from(data)).unwrap();
+            Ok(state)
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestHostBuilder;
+
+    impl RuntimeHostBuilder for TestHostBuilder {
+        type Host = TestHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _: String,
+            _: SubgraphDeploymentId,
+            _: DataSource,
+            _: Arc<Vec<DataSourceTemplate>>,
+            _: mpsc::Sender<()>,
+            _: Arc<HostMetrics>,
+        ) -> Result<TestHost, Error> {
+            Ok(TestHost)
+        }
+
+        fn spawn_mapping(
+            _: Vec<u8>,
+            _: Logger,
+            _: SubgraphDeploymentId,
+            _: Arc<HostMetrics>,
+            _: Option<u64>,
+        ) -> Result<mpsc::Sender<()>, anyhow::Error> {
+            let (sender, _) = mpsc::channel(1);
+            Ok(sender)
+        }
+    }
+
+    const DEPLOYMENT: &str = "verifierReplay";
+
+    /// A manifest with one data source that has a block handler
+    fn manifest(id: &SubgraphDeploymentId) -> SubgraphManifest {
+        let mapping = Mapping {
+            kind: "ethereum/events".to_owned(),
+            api_version: "0.0.4".to_owned(),
+            language: "wasm/assemblyscript".to_owned(),
+            entities: vec!["User".to_owned()],
+            abis: vec![],
+            block_handlers: vec![MappingBlockHandler {
+                handler: "handleBlock".to_owned(),
+                filter: None,
+            }],
+            call_handlers: vec![],
+            event_handlers: vec![],
+            runtime: Arc::new(vec![]),
+            link: Link {
+                link: "link".to_owned(),
+            },
+        };
+        SubgraphManifest {
+            id: id.clone(),
+            location: String::new(),
+            spec_version: "0.0.2".to_owned(),
+            description: None,
+            repository: None,
+            schema: Schema::parse(SCHEMA, id.clone()).unwrap(),
+            data_sources: vec![DataSource {
+                kind: EthereumChain::KIND.to_owned(),
+                network: Some("mainnet".to_owned()),
+                name: "Contract".to_owned(),
+                source: Source {
+                    address: None,
+                    addresses: vec![],
+                    abi: String::new(),
+                    start_block: 0,
+                },
+                mapping,
+                context: None,
+                templates: vec![],
+            }],
+            graft: None,
+            history_blocks: None,
+            strict_bytes: false,
+            computed: None,
+            templates: vec![],
+        }
+    }
+
+    fn block(number: u64) -> LightEthereumBlock {
+        LightEthereumBlock {
+            hash: Some(H256::from_low_u64_be(number + 1)),
+            parent_hash: H256::from_low_u64_be(number),
+            number: Some(U64::from(number)),
+            ..Default::default()
+        }
+    }
+
+    fn replay<S: EntityStore + ChainStore>(
+        store: Arc<S>,
+        id: &SubgraphDeploymentId,
+        from: u64,
+    ) -> Replay<S, TestHostBuilder> {
+        Replay::new(
+            LOGGER.clone(),
+            manifest(id),
+            TestHostBuilder,
+            store,
+            from,
+            None,
+        )
+        .unwrap()
+    }
+
+    /// Index blocks 1 to 3 by replaying them and writing what they change
+    /// to the store. At `perturbed_block`, the store gets the wrong name
+    /// for the user
+    async fn index<S: EntityStore + ChainStore>(
+        store: Arc<S>,
+        perturbed_block: Option<u64>,
+    ) -> SubgraphDeploymentId {
+        remove_subgraphs();
+        create_test_subgraph(DEPLOYMENT, SCHEMA);
+        let id = SubgraphDeploymentId::new(DEPLOYMENT).unwrap();
+        store
+            .upsert_light_blocks("test", (1..=3).map(block).collect())
+            .unwrap();
+
+        let mut indexing = replay(store.clone(), &id, 1);
+        for number in 1..=3 {
+            let block = cached_block(store.as_ref(), number).unwrap();
+            let block_ptr = block.ptr();
+            let mut mods = indexing.block(block).await.unwrap();
+            indexing
+                .sandbox
+                .apply_block(block_ptr, mods.clone())
+                .unwrap();
+
+            if perturbed_block == Some(number) {
+                for modification in &mut mods {
+                    match modification {
+                        EntityModification::Insert { key, data }
+                        | EntityModification::Overwrite { key, data, .. }
+                            if key.entity_type == "User" =>
+                        {
+                            data.insert("name", Value::from("Mallory"));
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            let stopwatch = StopwatchMetrics::new(
+                LOGGER.clone(),
+                id.clone(),
+                Arc::new(MockMetricsRegistry::new()),
+            );
+            store
+                .transact_block_operations(id.clone(), block_ptr, mods, stopwatch)
+                .unwrap();
+        }
+        id
+    }
+
+    #[test]
+    fn cached_block_refuses_ommers() {
+        TEST_STORE.run_chain_test(vec![&*GENESIS_BLOCK], |store| async move {
+            // There is no chain head that tells us which block 2 is canonical
+            let mut ommer = block(2);
+            ommer.hash = Some(H256::from_low_u64_be(100));
+            store
+                .upsert_light_blocks("test", vec![block(1), block(2), ommer])
+                .unwrap();
+
+            assert!(cached_block(store.as_ref(), 1).is_ok());
+            assert!(cached_block(store.as_ref(), 2).is_err());
+        })
+    }
+
+    #[test]
+    fn replay_matches_store() {
+        TEST_STORE.run_chain_test(vec![&*GENESIS_BLOCK], |store| async move {
+            let id = index(store.clone(), None).await;
+
+            let result = replay(store, &id, 1).verify(1, 3).await.unwrap();
+            assert_eq!(3, result.blocks);
+            assert!(result.divergence.is_none());
+        })
+    }
+
+    #[test]
+    fn replay_flags_block_with_perturbed_entity() {
+        TEST_STORE.run_chain_test(vec![&*GENESIS_BLOCK], |store| async move {
+            let id = index(store.clone(), Some(2)).await;
+
+            let result = replay(store, &id, 1).verify(1, 3).await.unwrap();
+            assert_eq!(2, result.blocks);
+            let divergence = result.divergence.expect("block 2 diverges");
+            assert_eq!(2, divergence.block_number);
+            assert_eq!(
+                format!("{:x}", block(2).hash.unwrap()),
+                divergence.block_hash
+            );
+            assert!(divergence.digests.is_empty());
+            assert_eq!(1, divergence.entities.len());
+
+            let entity = &divergence.entities[0];
+            assert_eq!("User", entity.entity_type);
+            assert_eq!("1", entity.entity_id);
+            assert_eq!(
+                Some(&Value::from("Mallory")),
+                entity.stored.as_ref().unwrap().get("name")
+            );
+            assert_eq!(
+                Some(&Value::from("Alice")),
+                entity.replayed.as_ref().unwrap().get("name")
+            );
+        })
+    }
+}
@@ -1,6 +1,7 @@
 // Tests for graphql interfaces.
 
 use pretty_assertions::assert_eq;
+use std::collections::BTreeMap;
 
 use graph::prelude::*;
 use graph_graphql::prelude::object;
@@ -22,7 +23,7 @@ fn insert_and_query(
         .map(|(data, entity_type)| EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: subgraph_id.clone(),
-                entity_type: entity_type.to_owned(),
+                entity_type: entity_type.into(),
                 entity_id: data["id"].clone().as_string().unwrap(),
             },
             data,
@@ -1193,3 +1194,219 @@ fn nested_interface_fragments_overlapping() {
         }
     );
 }
+
+const ASSET_SCHEMA: &str = "
+    interface Asset { id: ID!, symbol: String! }
+    type Account @entity {
+      id: ID!
+      name: String!
+      assets: [Asset!]!
+    }
+    type Token implements Asset @entity {
+      id: ID!
+      symbol: String!
+      decimals: Int!
+      owner: Account!
+    }
+    type Nft implements Asset @entity {
+      id: ID!
+      symbol: String!
+      uri: String!
+      rarity: Int!
+    }
+    type Share implements Asset @entity {
+      id: ID!
+      symbol: String!
+      percent: Int!
+      issuer: Account!
+    }";
+
+fn assets() -> Vec<(Entity, &'static str)> {
+    vec![
+        (
+            entity!(id: "alice", name: "alice", assets: vec!["dai", "punk", "acme", "usdc"]),
+            "Account",
+        ),
+        (
+            entity!(id: "bob", name: "bob", assets: vec!["ape"]),
+            "Account",
+        ),
+        (
+            entity!(id: "dai", symbol: "DAI", decimals: 18, owner: "alice"),
+            "Token",
+        ),
+        (
+            entity!(id: "usdc", symbol: "USDC", decimals: 6, owner: "bob"),
+            "Token",
+        ),
+        (
+            entity!(id: "punk", symbol: "PUNK", uri: "ipfs://punk", rarity: 3),
+            "Nft",
+        ),
+        (
+            entity!(id: "ape", symbol: "APE", uri: "ipfs://ape", rarity: 5),
+            "Nft",
+        ),
+        (
+            entity!(id: "acme", symbol: "ACME", percent: 10, issuer: "bob"),
+            "Share",
+        ),
+    ]
+}
+
+#[test]
+fn interface_fragments_per_type() {
+    let subgraph_id = "InterfaceFragmentsPerType";
+    let query = "query {
+                    assets(orderBy: symbol) {
+                        __typename
+                        symbol
+                        ... on Token { decimals owner { name } }
+                        ... on Nft { uri }
+                        ...shareFields
+                    }
+                 }
+                 fragment shareFields on Share { percent issuer { name } }";
+
+    let res = insert_and_query(subgraph_id, ASSET_SCHEMA, assets(), query).unwrap();
+    let data = extract_data!(res).unwrap();
+    assert_eq!(
+        data,
+        object! {
+            assets: vec![
+                object! {
+                    __typename: "Share",
+                    symbol: "ACME",
+                    percent: 10,
+                    issuer: object! { name: "bob" },
+                },
+                object! { __typename: "Nft", symbol: "APE", uri: "ipfs://ape" },
+                object! {
+                    __typename: "Token",
+                    symbol: "DAI",
+                    decimals: 18,
+                    owner: object! { name: "alice" },
+                },
+                object! { __typename: "Nft", symbol: "PUNK", uri: "ipfs://punk" },
+                object! {
+                    __typename: "Token",
+                    symbol: "USDC",
+                    decimals: 6,
+                    owner: object! { name: "bob" },
+                },
+            ]
+        }
+    );
+
+    // Querying the type directly fetches all its attributes, and gives the
+    // same result for it
+    let query = "query {
+                    tokens(orderBy: symbol) { __typename symbol decimals owner { name } }
+                 }";
+    let res = insert_and_query(subgraph_id, ASSET_SCHEMA, vec![], query).unwrap();
+    let tokens = match extract_data!(res).unwrap() {
+        q::Value::Object(mut map) => map.remove("tokens").unwrap(),
+        _ => unreachable!(),
+    };
+    let assets = match data {
+        q::Value::Object(mut map) => match map.remove("assets").unwrap() {
+            q::Value::List(assets) => assets,
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    };
+    let tokens_in_assets: Vec<_> = assets
+        .into_iter()
+        .filter(|asset| match asset {
+            q::Value::Object(map) => map["__typename"] == q::Value::String("Token".to_owned()),
+            _ => false,
+        })
+        .collect();
+    assert_eq!(tokens, q::Value::List(tokens_in_assets));
+}
+
+#[test]
+fn interface_fragments_per_type_in_children() {
+    let subgraph_id = "InterfaceFragmentsPerTypeInChildren";
+    let query = "query {
+                    accounts(orderBy: name) {
+                        name
+                        assets(orderBy: symbol) {
+                            symbol
+                            ... on Nft { rarity }
+                            ... on Asset { id }
+                        }
+                    }
+                 }";
+
+    let res = insert_and_query(subgraph_id, ASSET_SCHEMA, assets(), query).unwrap();
+    let data = extract_data!(res).unwrap();
+    assert_eq!(
+        data,
+        object! {
+            accounts: vec![
+                object! {
+                    name: "alice",
+                    assets: vec![
+                        object! { id: "acme", symbol: "ACME" },
+                        object! { id: "dai", symbol: "DAI" },
+                        object! { id: "punk", symbol: "PUNK", rarity: 3 },
+                        object! { id: "usdc", symbol: "USDC" },
+                    ],
+                },
+                object! {
+                    name: "bob",
+                    assets: vec![object! { id: "ape", symbol: "APE", rarity: 5 }],
+                },
+            ]
+        }
+    );
+}
+
+#[test]
+fn interface_fragment_with_many_fields() {
+    let subgraph_id = "InterfaceFragmentWithManyFields";
+    let fields: Vec<_> = (0..60).map(|i| format!("f{}", i)).collect();
+    let schema = format!(
+        "interface Wide {{ id: ID! }}
+         type Narrow implements Wide @entity {{ id: ID!, f0: Int! }}
+         type Broad implements Wide @entity {{ id: ID!, {} }}",
+        fields
+            .iter()
+            .map(|field| format!("{}: Int!", field))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    let mut broad = Entity::new();
+    broad.set("id", "broad");
+    for (i, field) in fields.iter().enumerate() {
+        broad.set(field.as_str(), i as i32);
+    }
+    let narrow = entity!(id: "narrow", f0: 7);
+
+    let query = format!(
+        "query {{ wides(orderBy: id) {{ id ... on Broad {{ {} }} }} }}",
+        fields.join(" ")
+    );
+    let res = insert_and_query(
+        subgraph_id,
+        &schema,
+        vec![(broad, "Broad"), (narrow, "Narrow")],
+        &query,
+    )
+    .unwrap();
+    let data = extract_data!(res).unwrap();
+
+    let mut expected = BTreeMap::new();
+    expected.insert("id".to_owned(), q::Value::String("broad".to_owned()));
+    for (i, field) in fields.iter().enumerate() {
+        expected.insert(field.clone(), q::Value::Int((i as i32).into()));
+    }
+    assert_eq!(
+        data,
+        object! {
+            wides: vec![q::Value::Object(expected), object! { id: "narrow" }]
+        }
+    );
+}
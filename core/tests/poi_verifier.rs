@@ -0,0 +1,177 @@
+// Tests for comparing replayed blocks with the store.
+
+use graph::data::store::scalar::Bytes;
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::entity;
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+use graph_core::SandboxStore;
+use test_store::*;
+
+const SCHEMA: &str = "type User @entity { id: ID!, name: String!, age: Int }";
+
+const CAUSALITY_REGION: &str = "ethereum/mainnet";
+
+fn block_ptr(number: u64) -> EthereumBlockPointer {
+    EthereumBlockPointer {
+        hash: H256::from_low_u64_be(number + 1),
+        number,
+    }
+}
+
+fn user_key(id: &SubgraphDeploymentId) -> EntityKey {
+    EntityKey {
+        subgraph_id: id.clone(),
+        entity_type: "User".into(),
+        entity_id: "1".to_owned(),
+    }
+}
+
+fn poi_key(id: &SubgraphDeploymentId) -> EntityKey {
+    EntityKey {
+        subgraph_id: id.clone(),
+        entity_type: POI_OBJECT.into(),
+        entity_id: CAUSALITY_REGION.to_owned(),
+    }
+}
+
+fn user(age: i32, name: &str) -> Entity {
+    entity! { id: "1", name: name, age: age }
+}
+
+fn poi(digest: u8) -> Entity {
+    entity! { id: CAUSALITY_REGION, digest: Bytes::from(&[digest][..]) }
+}
+
+/// The changes that indexing block `number` of the test subgraph makes
+/// when the user is called `name`
+fn block_ops(id: &SubgraphDeploymentId, number: u8, name: &str) -> Vec<EntityOperation> {
+    vec![
+        EntityOperation::Set {
+            key: user_key(id),
+            data: user(30 + number as i32, name),
+        },
+        EntityOperation::Set {
+            key: poi_key(id),
+            data: poi(number + 1),
+        },
+    ]
+}
+
+/// Create a deployment with blocks 0 to 3. The user is called `Alice`
+/// except at `perturbed_block`, where the store has `Mallory`
+fn setup(subgraph_id: &str, perturbed_block: u8) -> SubgraphDeploymentId {
+    create_test_subgraph(subgraph_id, SCHEMA);
+    let id = SubgraphDeploymentId::new(subgraph_id).unwrap();
+
+    for number in 0..4 {
+        let name = if number == perturbed_block {
+            "Mallory"
+        } else {
+            "Alice"
+        };
+        transact_entity_operations(
+            &STORE,
+            id.clone(),
+            block_ptr(number as u64),
+            block_ops(&id, number, name),
+        )
+        .unwrap();
+    }
+    id
+}
+
+/// The changes that replaying block `number` makes, which always call the
+/// user `Alice`
+fn replayed_mods(id: &SubgraphDeploymentId, number: u8, digest: u8) -> Vec<EntityModification> {
+    vec![
+        EntityModification::Overwrite {
+            key: user_key(id),
+            data: user(30 + number as i32, "Alice"),
+        },
+        EntityModification::Overwrite {
+            key: poi_key(id),
+            data: poi(digest),
+        },
+    ]
+}
+
+#[test]
+fn sandbox_reads_from_base_block() {
+    let id = setup("sandboxReadsFromBaseBlock", 4);
+    let sandbox = SandboxStore::new(STORE.clone(), id.clone(), 1);
+
+    let stored = sandbox.get(user_key(&id)).unwrap().unwrap();
+    assert_eq!(Some(&Value::Int(31)), stored.get("age"));
+
+    sandbox
+        .apply_block(block_ptr(2), replayed_mods(&id, 2, 3))
+        .unwrap();
+    let replayed = sandbox.get(user_key(&id)).unwrap().unwrap();
+    assert_eq!(Some(&Value::Int(32)), replayed.get("age"));
+
+    let err = sandbox
+        .transact_block_operations(
+            id.clone(),
+            block_ptr(4),
+            vec![],
+            StopwatchMetrics::new(
+                LOGGER.clone(),
+                id.clone(),
+                Arc::new(graph_mock::MockMetricsRegistry::new()),
+            ),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("can not write"));
+}
+
+#[test]
+fn flags_block_with_perturbed_entity() {
+    let id = setup("flagsBlockWithPerturbedEntity", 2);
+    let sandbox = SandboxStore::new(STORE.clone(), id.clone(), 0);
+
+    let divergence = sandbox
+        .apply_block(block_ptr(1), replayed_mods(&id, 1, 2))
+        .unwrap();
+    assert!(divergence.is_none());
+
+    let divergence = sandbox
+        .apply_block(block_ptr(2), replayed_mods(&id, 2, 3))
+        .unwrap()
+        .expect("block 2 diverges");
+    assert_eq!(2, divergence.block_number);
+    assert_eq!(format!("{:x}", block_ptr(2).hash), divergence.block_hash);
+    assert!(divergence.digests.is_empty());
+    assert_eq!(1, divergence.entities.len());
+
+    let entity = &divergence.entities[0];
+    assert_eq!("User", entity.entity_type);
+    assert_eq!("1", entity.entity_id);
+    assert_eq!(
+        Some(&Value::from("Mallory")),
+        entity.stored.as_ref().unwrap().get("name")
+    );
+    assert_eq!(
+        Some(&Value::from("Alice")),
+        entity.replayed.as_ref().unwrap().get("name")
+    );
+}
+
+#[test]
+fn flags_block_with_different_digest() {
+    let id = setup("flagsBlockWithDifferentDigest", 4);
+    let sandbox = SandboxStore::new(STORE.clone(), id.clone(), 0);
+
+    let divergence = sandbox
+        .apply_block(block_ptr(1), replayed_mods(&id, 1, 42))
+        .unwrap()
+        .expect("block 1 diverges");
+    assert_eq!(1, divergence.block_number);
+    assert!(divergence.entities.is_empty());
+    assert_eq!(1, divergence.digests.len());
+
+    let digest = &divergence.digests[0];
+    assert_eq!(CAUSALITY_REGION, digest.causality_region);
+    assert_eq!(Some("0x02"), digest.stored.as_deref());
+    assert_eq!(Some("0x2a"), digest.replayed.as_deref());
+}
@@ -0,0 +1,188 @@
+// Tests for restarting deployments through the registrar.
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use graph::components::ethereum::EthereumNetworks;
+use graph::data::subgraph::schema::SubgraphDeploymentAssignmentEntity;
+use graph::prelude::{
+    ProofOfIndexingVerifier as ProofOfIndexingVerifierTrait,
+    SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
+    SubgraphRegistrar as SubgraphRegistrarTrait, *,
+};
+use graph_core::{LinkResolver, SubgraphRegistrar};
+use test_store::*;
+
+const SCHEMA: &str = "type User @entity { id: ID!, name: String }";
+
+/// A provider that records the deployments it is asked to stop and start
+struct RecordingProvider {
+    running: Mutex<Vec<SubgraphDeploymentId>>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl RecordingProvider {
+    fn new(running: Vec<SubgraphDeploymentId>) -> Self {
+        Self {
+            running: Mutex::new(running),
+            calls: Mutex::new(vec![]),
+        }
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+impl EventProducer<SubgraphAssignmentProviderEvent> for RecordingProvider {
+    fn take_event_stream(
+        &mut self,
+    ) -> Option<Box<dyn Stream<Item = SubgraphAssignmentProviderEvent, Error = ()> + Send>> {
+        None
+    }
+}
+
+#[async_trait]
+impl SubgraphAssignmentProviderTrait for RecordingProvider {
+    async fn start(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        self.calls.lock().unwrap().push(format!("start {}", id));
+        let mut running = self.running.lock().unwrap();
+        if running.contains(id) {
+            return Err(SubgraphAssignmentProviderError::AlreadyRunning(id.clone()));
+        }
+        running.push(id.clone());
+        Ok(())
+    }
+
+    async fn stop(&self, id: SubgraphDeploymentId) -> Result<(), SubgraphAssignmentProviderError> {
+        self.calls.lock().unwrap().push(format!("stop {}", id));
+        let mut running = self.running.lock().unwrap();
+        match running.iter().position(|running| running == &id) {
+            Some(pos) => {
+                running.remove(pos);
+                Ok(())
+            }
+            None => Err(SubgraphAssignmentProviderError::NotRunning(id)),
+        }
+    }
+}
+
+struct NoVerifier;
+
+#[async_trait]
+impl ProofOfIndexingVerifierTrait for NoVerifier {
+    async fn verify(
+        &self,
+        _: &SubgraphDeploymentId,
+        _: u64,
+        _: u64,
+    ) -> Result<PoiVerificationResult, Error> {
+        unreachable!("restarting a deployment does not verify it")
+    }
+}
+
+/// Create the deployment `name` and assign it to `node`, if it is given
+fn setup(name: &str, node: Option<&str>) -> SubgraphDeploymentId {
+    remove_subgraphs();
+    create_test_subgraph(name, SCHEMA);
+    let id = SubgraphDeploymentId::new(name).unwrap();
+    if let Some(node) = node {
+        let ops = SubgraphDeploymentAssignmentEntity::new(NodeId::new(node).unwrap())
+            .write_operations(&id);
+        STORE.apply_metadata_operations(ops).unwrap();
+    }
+    id
+}
+
+/// Restart `id` through a registrar on the node `test` whose provider runs
+/// the deployments in `running`, and return the result of the restart and
+/// the calls the registrar made to the provider
+async fn restart(
+    id: &SubgraphDeploymentId,
+    running: Vec<SubgraphDeploymentId>,
+) -> (
+    Result<RestartSubgraphResult, SubgraphRegistrarError>,
+    Vec<String>,
+) {
+    let provider = Arc::new(RecordingProvider::new(running));
+    let chain_stores: HashMap<String, _> = vec![("mainnet".to_owned(), STORE.clone())]
+        .into_iter()
+        .collect();
+    let registrar = SubgraphRegistrar::new(
+        &LoggerFactory::new(LOGGER.clone(), None),
+        Arc::new(LinkResolver::from(ipfs_api::IpfsClient::default())),
+        provider.clone(),
+        STORE.clone(),
+        chain_stores,
+        EthereumNetworks::new(),
+        Arc::new(NoVerifier),
+        NodeId::new("test").unwrap(),
+        SubgraphVersionSwitchingMode::Instant,
+    );
+    let result = registrar
+        .restart_subgraph(id.clone(), Some("testing".to_owned()))
+        .await;
+    (result, provider.calls())
+}
+
+#[test]
+fn restarts_running_deployment() {
+    run_test_sequentially(
+        || setup("restartRunning", Some("test")),
+        |_, id| async move {
+            let (result, calls) = restart(&id, vec![id.clone()]).await;
+            let result = result.unwrap();
+            assert!(result.restarted);
+            assert_eq!("test", result.node_id);
+            assert_eq!(vec![format!("stop {}", id), format!("start {}", id)], calls);
+        },
+    )
+}
+
+#[test]
+fn restart_starts_deployment_that_is_not_running() {
+    run_test_sequentially(
+        || setup("restartNotRunning", Some("test")),
+        |_, id| async move {
+            let (result, calls) = restart(&id, vec![]).await;
+            assert!(result.unwrap().restarted);
+            assert_eq!(vec![format!("stop {}", id), format!("start {}", id)], calls);
+        },
+    )
+}
+
+#[test]
+fn does_not_restart_deployment_of_other_node() {
+    run_test_sequentially(
+        || setup("restartOtherNode", Some("other")),
+        |_, id| async move {
+            let (result, calls) = restart(&id, vec![id.clone()]).await;
+            let result = result.unwrap();
+            assert!(!result.restarted);
+            assert_eq!("other", result.node_id);
+            assert!(calls.is_empty());
+        },
+    )
+}
+
+#[test]
+fn restart_of_unassigned_deployment_fails() {
+    run_test_sequentially(
+        || setup("restartUnassigned", None),
+        |_, id| async move {
+            let (result, calls) = restart(&id, vec![]).await;
+            match result {
+                Err(SubgraphRegistrarError::DeploymentNotFound(hash)) => {
+                    assert_eq!(id.to_string(), hash)
+                }
+                Err(e) => panic!("unexpected error {}", e),
+                Ok(_) => panic!("restarted a deployment that is not assigned"),
+            }
+            assert!(calls.is_empty());
+        },
+    )
+}
@@ -0,0 +1,148 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::time::{Duration, Instant};
+
+use structopt::StructOpt;
+
+use graph::data::store::{Entity, Value};
+use graph::prelude::Word;
+
+// Use a custom allocator that tracks how much memory the program has
+// allocated overall, without subtracting what it freed
+
+struct Counter;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ret = System.alloc(layout);
+        if !ret.is_null() {
+            ALLOCATED.fetch_add(layout.size(), SeqCst);
+        }
+        return ret;
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static A: Counter = Counter;
+
+/// An entity of some type as a query sees it
+trait Row: Clone {
+    // Build the entity from the columns of a row that the store loaded
+    fn build(entity_type: &str, names: &[String], values: &[Value]) -> Self;
+
+    // Read the attribute `name` like a resolver does
+    fn read(&self, name: &str) -> Option<&Value>;
+}
+
+/// Entities the way they were before interning: every entity allocates
+/// its type name and the names of all of its attributes
+impl Row for (String, HashMap<String, Value>) {
+    fn build(entity_type: &str, names: &[String], values: &[Value]) -> Self {
+        let entity = names.iter().cloned().zip(values.iter().cloned()).collect();
+        (entity_type.to_owned(), entity)
+    }
+
+    fn read(&self, name: &str) -> Option<&Value> {
+        self.1.get(name)
+    }
+}
+
+impl Row for (Word, Entity) {
+    fn build(entity_type: &str, names: &[String], values: &[Value]) -> Self {
+        let mut entity = Entity::new();
+        for (name, value) in names.iter().zip(values) {
+            entity.insert(name.as_str(), value.clone());
+        }
+        (Word::new(entity_type), entity)
+    }
+
+    fn read(&self, name: &str) -> Option<&Value> {
+        self.1.get(name)
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(short, long, default_value = "10")]
+    niter: usize,
+    #[structopt(short, long, default_value = "10000")]
+    entities: usize,
+    #[structopt(short, long, default_value = "20")]
+    attributes: usize,
+}
+
+fn run<R: Row>(opt: &Opt, names: &[String]) -> (Duration, usize) {
+    let values: Vec<_> = (0..names.len()).map(|i| Value::Int(i as i32)).collect();
+
+    let start = Instant::now();
+    let before = ALLOCATED.load(SeqCst);
+    let mut reads = 0;
+    for _ in 0..opt.niter {
+        let rows: Vec<_> = (0..opt.entities)
+            .map(|_| R::build("Thing", names, &values))
+            .collect();
+        // Query results are cloned into the query cache
+        let cached = rows.clone();
+        for row in &cached {
+            for name in names {
+                if row.read(name).is_some() {
+                    reads += 1;
+                }
+            }
+        }
+    }
+    assert_eq!(opt.niter * opt.entities * names.len(), reads);
+    (start.elapsed(), ALLOCATED.load(SeqCst) - before)
+}
+
+fn print(name: &str, opt: &Opt, (duration, allocated): (Duration, usize)) {
+    let entities = (opt.niter * opt.entities) as f64;
+    println!(
+        "{:>12}: {:8.2}ms  {:8.0}ns/entity  {:8.0}bytes/entity",
+        name,
+        duration.as_secs_f64() * 1000.0,
+        duration.as_nanos() as f64 / entities,
+        allocated as f64 / entities,
+    );
+}
+
+/// This program mimics what a hot query does with the entities it loads
+/// from the store: it builds `entities` entities with `attributes`
+/// attributes each, clones them into a cache, and reads every attribute of
+/// every entity, `niter` times over. It does that with attribute and
+/// entity type names that are `String`s, with `Word`s whose text is not
+/// interned, and with `Word`s for the interned names of a schema, and
+/// prints how long that took and how much memory was allocated per entity.
+///
+/// Run it with `cargo run --release --example intern`
+pub fn main() {
+    let opt = Opt::from_args();
+    let names: Vec<_> = (0..opt.attributes)
+        .map(|i| format!("attribute{}", i))
+        .collect();
+
+    println!(
+        "entities: {} attributes: {} iterations: {}",
+        opt.entities, opt.attributes, opt.niter
+    );
+    print(
+        "string",
+        &opt,
+        run::<(String, HashMap<String, Value>)>(&opt, &names),
+    );
+    print("uninterned", &opt, run::<(Word, Entity)>(&opt, &names));
+
+    // This is what validating the schema of a deployment does
+    Word::intern("Thing");
+    for name in &names {
+        Word::intern(name);
+    }
+    print("interned", &opt, run::<(Word, Entity)>(&opt, &names));
+}
@@ -0,0 +1,35 @@
+use failure::Error;
+use futures::Stream;
+
+use super::{BlockWithTriggers, Blockchain};
+use crate::prelude::*;
+
+pub enum BlockStreamEvent<C: Blockchain> {
+    Block(BlockWithTriggers<C>),
+
+    /// Signals that a revert happened and was processed.
+    Revert,
+
+    /// Signals that all blocks found by the last reconciliation have been
+    /// yielded. The stream reads the subgraph block pointer from the store
+    /// the next time it is polled, so consumers that hold back writes must
+    /// write them before polling the stream again.
+    EndOfBlocks,
+}
+
+pub trait BlockStream<C: Blockchain>: Stream<Item = BlockStreamEvent<C>, Error = Error> {}
+
+pub trait BlockStreamBuilder: Clone + Send + Sync + 'static {
+    type Chain: Blockchain;
+    type Stream: BlockStream<Self::Chain> + Send + 'static;
+
+    fn build(
+        &self,
+        logger: Logger,
+        deployment_id: SubgraphDeploymentId,
+        network_name: String,
+        start_blocks: Vec<u64>,
+        filter: <Self::Chain as Blockchain>::TriggerFilter,
+        ethrpc_metrics: Arc<BlockStreamMetrics>,
+    ) -> Self::Stream;
+}
@@ -0,0 +1,137 @@
+//! The traits that indexing uses to abstract over the blockchains that
+//! subgraphs can index. Each chain implements `Blockchain` in a crate of its
+//! own, like `graph-chain-ethereum` for Ethereum; `graph-mock` has a toy
+//! chain for tests of the code that is generic over `Blockchain`.
+
+mod block_stream;
+
+use async_trait::async_trait;
+use failure::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::components::ethereum::BlockStreamMetrics;
+use crate::data::store::scalar::Bytes;
+use crate::prelude::{DataSource, Debug, Logger, SubgraphManifest};
+
+pub use self::block_stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
+
+/// A blockchain that subgraphs can index
+pub trait Blockchain: Clone + Debug + Sized + Send + Sync + 'static {
+    /// The `kind` of the data sources in a subgraph manifest that are
+    /// indexed on this chain
+    const KIND: &'static str;
+
+    /// Identifies a block on this chain
+    type Pointer: Clone + Debug + fmt::Display + PartialEq + Send + Sync + Into<BlockPtr>;
+
+    /// A block as it is passed from the block stream to the subgraph
+    type Block: Block<Self>;
+
+    /// The part of a block that mappings get to see when they process a
+    /// trigger from the block
+    type MappingBlock: Send + Sync + 'static;
+
+    /// A data source of the subgraph manifest, in the form that this
+    /// chain's trigger filters need
+    type DataSource: Clone + Debug + Send + Sync + 'static;
+
+    /// Something in a block that causes a mapping handler to run
+    type TriggerData: TriggerData;
+
+    /// Decides which triggers in a block are relevant for a subgraph
+    type TriggerFilter: TriggerFilter<Self>;
+
+    /// Read the data source of this chain from a `data_source` of the
+    /// manifest whose `kind` is `KIND`
+    fn data_source(data_source: &DataSource) -> Result<Self::DataSource, Error>;
+
+    /// The adapter that finds triggers in the blocks of the network of
+    /// `manifest` for that subgraph
+    fn triggers_adapter(
+        &self,
+        manifest: &SubgraphManifest,
+        metrics: &BlockStreamMetrics,
+    ) -> Result<Arc<dyn TriggersAdapter<Self>>, Error>;
+}
+
+/// A pointer to a block on any chain. This is the form in which the store
+/// records how far a subgraph has indexed its chain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockPtr {
+    pub hash: Bytes,
+    pub number: u64,
+}
+
+impl fmt::Display for BlockPtr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} ({})", self.number, self.hash)
+    }
+}
+
+pub trait Block<C: Blockchain>: Clone + Debug + Send + Sync + 'static {
+    fn ptr(&self) -> C::Pointer;
+
+    /// The pointer to the parent of this block, `None` for the genesis block
+    fn parent_ptr(&self) -> Option<C::Pointer>;
+
+    /// Whether the block is far enough behind the chain head that it will
+    /// never be reverted
+    fn is_final(&self) -> bool;
+
+    fn mapping_block(&self) -> C::MappingBlock;
+}
+
+/// Triggers are processed in the order given by `Ord`
+pub trait TriggerData: Clone + Debug + Ord + Send + Sync + 'static {
+    /// The kind of trigger, used to label metrics
+    fn kind(&self) -> &'static str;
+
+    /// Where in the block the trigger happened if that is more precise than
+    /// the block itself, for example `transaction 0x...`; used in error
+    /// messages
+    fn location(&self) -> Option<String>;
+}
+
+pub trait TriggerFilter<C: Blockchain>: Clone + Send + Sync + 'static {
+    /// The filter for all data sources and templates in `manifest`
+    fn from_manifest(manifest: &SubgraphManifest) -> Result<Self, Error>;
+
+    /// The filter for just the given data sources
+    fn from_data_sources<'a>(data_sources: impl Iterator<Item = &'a C::DataSource> + Clone)
+        -> Self;
+
+    /// Also match the triggers of `data_sources`
+    fn extend<'a>(&mut self, data_sources: impl Iterator<Item = &'a C::DataSource> + Clone);
+}
+
+/// Finds triggers in blocks outside of the block stream
+#[async_trait]
+pub trait TriggersAdapter<C: Blockchain>: Send + Sync {
+    /// The triggers in `block` that match `filter`. This is used to process
+    /// a block for data sources that were created while processing it
+    async fn triggers_in_block(
+        &self,
+        logger: &Logger,
+        block: C::Block,
+        filter: C::TriggerFilter,
+    ) -> Result<BlockWithTriggers<C>, Error>;
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockWithTriggers<C: Blockchain> {
+    pub block: C::Block,
+    pub triggers: Vec<C::TriggerData>,
+}
+
+impl<C: Blockchain> BlockWithTriggers<C> {
+    pub fn new(block: C::Block, mut triggers: Vec<C::TriggerData>) -> Self {
+        triggers.sort();
+
+        BlockWithTriggers { block, triggers }
+    }
+
+    pub fn ptr(&self) -> C::Pointer {
+        self.block.ptr()
+    }
+}
@@ -1,7 +1,6 @@
 use ethabi::{Bytes, Error as ABIError, Function, ParamType, Token};
 use failure::SyncFailure;
 use futures::Future;
-use futures03::future::TryFutureExt;
 use mockall::predicate::*;
 use mockall::*;
 use petgraph::graphmap::GraphMap;
@@ -62,9 +61,108 @@ pub enum EthereumContractCallError {
     #[fail(display = "call error: {}", _0)]
     Web3Error(web3::Error),
     #[fail(display = "call reverted: {}", _0)]
-    Revert(String),
+    Revert(EthereumCallRevert),
     #[fail(display = "ethereum node took too long to perform call")]
     Timeout,
+    /// The call is not part of the recording that is being replayed, or
+    /// not in the call cache when replaying from it
+    #[fail(display = "call can not be replayed: {}", _0)]
+    NotRecorded(String),
+}
+
+/// Why a contract call reverted
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EthereumCallRevert {
+    /// The message of an `Error(string)` revert, a description of the code
+    /// of a `Panic(uint256)` revert, the selector of a custom error, or what
+    /// the Ethereum node said about the revert
+    pub reason: String,
+
+    /// What the contract reverted with, if the Ethereum node passed it
+    /// along. For custom errors, this is the error selector followed by
+    /// the ABI encoded error arguments
+    pub data: Option<web3::types::Bytes>,
+}
+
+impl EthereumCallRevert {
+    /// A revert without the data the contract reverted with
+    pub fn with_reason(reason: impl Into<String>) -> Self {
+        EthereumCallRevert {
+            reason: reason.into(),
+            data: None,
+        }
+    }
+
+    /// Decode what a contract reverted with
+    pub fn from_data(data: Vec<u8>) -> Self {
+        if data.is_empty() {
+            return Self::with_reason("no reason");
+        }
+        let reason = match Self::standard_reason(&data) {
+            Some(reason) => reason,
+            None if data.len() >= 4 => format!("custom error 0x{}", hex::encode(&data[..4])),
+            None => format!("invalid revert data 0x{}", hex::encode(&data)),
+        };
+        EthereumCallRevert {
+            reason,
+            data: Some(web3::types::Bytes(data)),
+        }
+    }
+
+    /// Decode `data` if it is one of the reverts that Solidity generates
+    /// itself, an `Error(string)` from `require` and `revert`, or a
+    /// `Panic(uint256)` from failed assertions and arithmetic errors
+    pub fn from_standard_data(data: &[u8]) -> Option<Self> {
+        Self::standard_reason(data).map(|reason| EthereumCallRevert {
+            reason,
+            data: Some(web3::types::Bytes(data.to_vec())),
+        })
+    }
+
+    fn standard_reason(data: &[u8]) -> Option<String> {
+        if data.len() < 4 {
+            return None;
+        }
+        let (selector, payload) = data.split_at(4);
+        if selector == &keccak256(b"Error(string)")[..4] {
+            ethabi::decode(&[ParamType::String], payload)
+                .ok()
+                .and_then(|tokens| tokens.into_iter().next())
+                .and_then(Token::to_string)
+        } else if selector == &keccak256(b"Panic(uint256)")[..4] {
+            ethabi::decode(&[ParamType::Uint(256)], payload)
+                .ok()
+                .and_then(|tokens| tokens.into_iter().next())
+                .and_then(Token::to_uint)
+                .map(|code| {
+                    // See https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require
+                    let description = match code.low_u64() {
+                        _ if code > U256::from(u64::MAX) => "unknown panic code",
+                        0x00 => "generic compiler panic",
+                        0x01 => "assertion failed",
+                        0x11 => "arithmetic overflow or underflow",
+                        0x12 => "division or modulo by zero",
+                        0x21 => "invalid enum value",
+                        0x22 => "invalid storage byte array encoding",
+                        0x31 => "pop on empty array",
+                        0x32 => "array index out of bounds",
+                        0x41 => "out of memory",
+                        0x51 => "call to uninitialized function",
+                        _ => "unknown panic code",
+                    };
+                    format!("panic 0x{:x}: {}", code, description)
+                })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for EthereumCallRevert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
 }
 
 impl From<ABIError> for EthereumContractCallError {
@@ -101,7 +199,7 @@ enum LogFilterNode {
 }
 
 /// Corresponds to an `eth_getLogs` call.
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct EthGetLogsFilter {
     pub contracts: Vec<Address>,
     pub event_signatures: Vec<EventSignature>,
@@ -129,6 +227,28 @@ impl fmt::Display for EthGetLogsFilter {
     }
 }
 
+/// A log filter that results in exactly one `eth_getLogs` call with
+/// `filter`
+impl From<EthGetLogsFilter> for EthereumLogFilter {
+    fn from(filter: EthGetLogsFilter) -> Self {
+        let mut this = EthereumLogFilter::default();
+        if filter.contracts.is_empty() {
+            this.wildcard_events.extend(filter.event_signatures);
+            return this;
+        }
+        for contract in &filter.contracts {
+            for event_sig in &filter.event_signatures {
+                this.contracts_and_events_graph.add_edge(
+                    LogFilterNode::Contract(*contract),
+                    LogFilterNode::Event(*event_sig),
+                    (),
+                );
+            }
+        }
+        this
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct EthereumLogFilter {
     /// Log filters can be represented as a bipartite graph between contracts and events. An edge
@@ -174,18 +294,17 @@ impl EthereumLogFilter {
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         let mut this = EthereumLogFilter::default();
         for ds in iter {
+            let contracts = ds.source.contracts();
             for event_sig in ds.mapping.event_handlers.iter().map(|e| e.topic0()) {
-                match ds.source.address {
-                    Some(contract) => {
-                        this.contracts_and_events_graph.add_edge(
-                            LogFilterNode::Contract(contract),
-                            LogFilterNode::Event(event_sig),
-                            (),
-                        );
-                    }
-                    None => {
-                        this.wildcard_events.insert(event_sig);
-                    }
+                if contracts.is_empty() {
+                    this.wildcard_events.insert(event_sig);
+                }
+                for (contract, _) in &contracts {
+                    this.contracts_and_events_graph.add_edge(
+                        LogFilterNode::Contract(*contract),
+                        LogFilterNode::Event(event_sig),
+                        (),
+                    );
                 }
             }
         }
@@ -314,9 +433,12 @@ impl EthereumCallFilter {
 
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         iter.into_iter()
-            .filter_map(|data_source| data_source.source.address.map(|addr| (addr, data_source)))
-            .map(|(contract_addr, data_source)| {
-                let start_block = data_source.source.start_block;
+            .flat_map(|data_source| {
+                data_source.source.contracts().into_iter().map(
+                    move |(contract_addr, start_block)| (contract_addr, start_block, data_source),
+                )
+            })
+            .map(|(contract_addr, start_block, data_source)| {
                 data_source
                     .mapping
                     .call_handlers
@@ -418,7 +540,7 @@ pub struct EthereumBlockFilter {
 impl EthereumBlockFilter {
     pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
         iter.into_iter()
-            .filter(|data_source| data_source.source.address.is_some())
+            .filter(|data_source| !data_source.source.is_wildcard())
             .fold(Self::default(), |mut filter_opt, data_source| {
                 let has_block_handler_with_call_filter = data_source
                     .mapping
@@ -440,12 +562,12 @@ impl EthereumBlockFilter {
                 filter_opt.extend(Self {
                     trigger_every_block: has_block_handler_without_filter,
                     contract_addresses: if has_block_handler_with_call_filter {
-                        vec![(
-                            data_source.source.start_block,
-                            data_source.source.address.unwrap().to_owned(),
-                        )]
-                        .into_iter()
-                        .collect()
+                        data_source
+                            .source
+                            .contracts()
+                            .into_iter()
+                            .map(|(address, start_block)| (start_block, address))
+                            .collect()
                     } else {
                         HashSet::default()
                     },
@@ -573,6 +695,11 @@ pub struct BlockStreamMetrics {
     pub ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
     pub blocks_behind: Box<Gauge>,
     pub reverted_blocks: Box<Gauge>,
+    /// How often the deployment had to revert blocks to get back to the
+    /// main chain
+    pub revert_count: Box<Counter>,
+    /// How many blocks the deployment reverted each time
+    pub revert_depth: Box<Histogram>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -597,13 +724,45 @@ impl BlockStreamMetrics {
                 deployment_id.as_str(),
             )
             .expect("Failed to create `deployment_reverted_blocks` gauge");
+        let revert_count = registry
+            .new_deployment_counter(
+                "deployment_revert_count",
+                "Counts how often a subgraph deployment reverted blocks because of a reorg",
+                deployment_id.as_str(),
+            )
+            .expect("Failed to create `deployment_revert_count` counter");
+        let revert_depth = registry
+            .new_deployment_histogram(
+                "deployment_revert_depth",
+                "The number of blocks a subgraph deployment reverted for each reorg",
+                deployment_id.as_str(),
+                vec![1.0, 2.0, 3.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0],
+            )
+            .expect("Failed to create `deployment_revert_depth` histogram");
         Self {
             ethrpc_metrics,
             blocks_behind,
             reverted_blocks,
+            revert_count,
+            revert_depth,
             stopwatch,
         }
     }
+
+    /// Record that the deployment reverted `depth` blocks to get back to
+    /// the main chain
+    pub fn observe_revert(&self, depth: u64) {
+        self.revert_count.inc();
+        self.revert_depth.observe(depth as f64);
+    }
+}
+
+/// How we identify the provider with `hostname` in the block and call
+/// caches and in logs: the first 8 bytes of the Keccak-256 hash of the
+/// hostname in hex. That is enough to tell providers apart when indexers
+/// compare their data without revealing which providers an indexer uses
+pub fn provider_id(hostname: &str) -> String {
+    hex::encode(&keccak256(hostname.as_bytes())[..8])
 }
 
 /// Common trait for components that watch and manage access to Ethereum.
@@ -633,6 +792,12 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         logger: &Logger,
     ) -> Box<dyn Future<Item = web3::types::Block<H256>, Error = EthereumAdapterError> + Send>;
 
+    /// Subscribe to the chain heads that the Ethereum node announces. The
+    /// stream yields once for every new chain head, and ends or fails when
+    /// the subscription is lost. Returns `None` if the connection to the
+    /// node does not support subscriptions, which is the case for HTTP.
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>>;
+
     fn load_block(
         &self,
         logger: &Logger,
@@ -669,6 +834,15 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_number: u64,
     ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send>;
 
+    /// The number of transactions in the block with `block_hash`, from
+    /// `eth_getBlockTransactionCountByHash`. Blocks that were fetched without
+    /// their transactions only know it from this.
+    fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send>;
+
     /// Load full information for the specified `block` (in particular, transaction receipts).
     fn load_full_block(
         &self,
@@ -766,261 +940,9 @@ pub trait EthereumAdapter: Send + Sync + 'static {
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send>;
 }
 
-fn parse_log_triggers(
-    log_filter: EthereumLogFilter,
-    block: &EthereumBlock,
-) -> Vec<EthereumTrigger> {
-    block
-        .transaction_receipts
-        .iter()
-        .flat_map(move |receipt| {
-            let log_filter = log_filter.clone();
-            receipt
-                .logs
-                .iter()
-                .filter(move |log| log_filter.matches(log))
-                .map(move |log| EthereumTrigger::Log(log.clone()))
-        })
-        .collect()
-}
-
-fn parse_call_triggers(
-    call_filter: EthereumCallFilter,
-    block: &EthereumBlockWithCalls,
-) -> Vec<EthereumTrigger> {
-    block
-        .calls
-        .iter()
-        .filter(move |call| call_filter.matches(call))
-        .map(move |call| EthereumTrigger::Call(call.clone()))
-        .collect()
-}
-
-fn parse_block_triggers(
-    block_filter: EthereumBlockFilter,
-    block: &EthereumBlockWithCalls,
-) -> Vec<EthereumTrigger> {
-    let block_ptr = EthereumBlockPointer::from(&block.ethereum_block);
-    let trigger_every_block = block_filter.trigger_every_block;
-    let call_filter = EthereumCallFilter::from(block_filter);
-    let mut triggers = block
-        .calls
-        .iter()
-        .filter(move |call| call_filter.matches(call))
-        .map(move |call| {
-            EthereumTrigger::Block(block_ptr, EthereumBlockTriggerType::WithCallTo(call.to))
-        })
-        .collect::<Vec<EthereumTrigger>>();
-    if trigger_every_block {
-        triggers.push(EthereumTrigger::Block(
-            block_ptr,
-            EthereumBlockTriggerType::Every,
-        ));
-    }
-    triggers
-}
-
-pub async fn triggers_in_block(
-    adapter: Arc<dyn EthereumAdapter>,
-    logger: Logger,
-    chain_store: Arc<dyn ChainStore>,
-    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
-    log_filter: EthereumLogFilter,
-    call_filter: EthereumCallFilter,
-    block_filter: EthereumBlockFilter,
-    ethereum_block: BlockFinality,
-) -> Result<EthereumBlockWithTriggers, Error> {
-    match &ethereum_block {
-        BlockFinality::Final(block) => {
-            let mut blocks = blocks_with_triggers(
-                adapter,
-                logger,
-                chain_store,
-                subgraph_metrics,
-                block.number(),
-                block.number(),
-                log_filter,
-                call_filter,
-                block_filter,
-            )
-            .await?;
-            assert!(blocks.len() <= 1);
-
-            Ok(blocks
-                .pop()
-                .unwrap_or_else(|| EthereumBlockWithTriggers::new(vec![], ethereum_block)))
-        }
-        BlockFinality::NonFinal(full_block) => {
-            let mut triggers = Vec::new();
-            triggers.append(&mut parse_log_triggers(
-                log_filter,
-                &full_block.ethereum_block,
-            ));
-            triggers.append(&mut parse_call_triggers(call_filter, &full_block));
-            triggers.append(&mut parse_block_triggers(block_filter, &full_block));
-            Ok(EthereumBlockWithTriggers::new(triggers, ethereum_block))
-        }
-    }
-}
-
-/// Returns blocks with triggers, corresponding to the specified range and filters.
-/// If a block contains no triggers, there may be no corresponding item in the stream.
-/// However the `to` block will always be present, even if triggers are empty.
-///
-/// Careful: don't use this function without considering race conditions.
-/// Chain reorgs could happen at any time, and could affect the answer received.
-/// Generally, it is only safe to use this function with blocks that have received enough
-/// confirmations to guarantee no further reorgs, **and** where the Ethereum node is aware of
-/// those confirmations.
-/// If the Ethereum node is far behind in processing blocks, even old blocks can be subject to
-/// reorgs.
-/// It is recommended that `to` be far behind the block number of latest block the Ethereum
-/// node is aware of.
-pub async fn blocks_with_triggers(
-    adapter: Arc<dyn EthereumAdapter>,
-    logger: Logger,
-    chain_store: Arc<dyn ChainStore>,
-    subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
-    from: u64,
-    to: u64,
-    log_filter: EthereumLogFilter,
-    call_filter: EthereumCallFilter,
-    block_filter: EthereumBlockFilter,
-) -> Result<Vec<EthereumBlockWithTriggers>, Error> {
-    // Each trigger filter needs to be queried for the same block range
-    // and the blocks yielded need to be deduped. If any error occurs
-    // while searching for a trigger type, the entire operation fails.
-    let eth = adapter.clone();
-    let mut trigger_futs: futures::stream::FuturesUnordered<
-        Box<dyn Future<Item = Vec<EthereumTrigger>, Error = Error> + Send>,
-    > = futures::stream::FuturesUnordered::new();
-
-    // Scan the block range from triggers to find relevant blocks
-    if !log_filter.is_empty() {
-        trigger_futs.push(Box::new(
-            eth.logs_in_block_range(&logger, subgraph_metrics.clone(), from, to, log_filter)
-                .map_ok(|logs: Vec<Log>| logs.into_iter().map(EthereumTrigger::Log).collect())
-                .compat(),
-        ))
-    }
-
-    if !call_filter.is_empty() {
-        trigger_futs.push(Box::new(
-            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
-                .map(EthereumTrigger::Call)
-                .collect(),
-        ));
-    }
-
-    if block_filter.trigger_every_block {
-        trigger_futs.push(Box::new(
-            adapter
-                .block_range_to_ptrs(logger.clone(), from, to)
-                .map(move |ptrs| {
-                    ptrs.into_iter()
-                        .map(|ptr| EthereumTrigger::Block(ptr, EthereumBlockTriggerType::Every))
-                        .collect()
-                }),
-        ))
-    } else if !block_filter.contract_addresses.is_empty() {
-        // To determine which blocks include a call to addresses
-        // in the block filter, transform the `block_filter` into
-        // a `call_filter` and run `blocks_with_calls`
-        let call_filter = EthereumCallFilter::from(block_filter);
-        trigger_futs.push(Box::new(
-            eth.calls_in_block_range(&logger, subgraph_metrics.clone(), from, to, call_filter)
-                .map(|call| {
-                    EthereumTrigger::Block(
-                        EthereumBlockPointer::from(&call),
-                        EthereumBlockTriggerType::WithCallTo(call.to),
-                    )
-                })
-                .collect(),
-        ));
-    }
-
-    let logger1 = logger.cheap_clone();
-    let logger2 = logger.cheap_clone();
-    let eth_clone = eth.cheap_clone();
-    let (triggers, to_hash) = trigger_futs
-        .concat2()
-        .join(
-            adapter
-                .clone()
-                .block_hash_by_block_number(&logger, chain_store.clone(), to, true)
-                .then(move |to_hash| match to_hash {
-                    Ok(n) => n.ok_or_else(|| {
-                        warn!(logger2,
-                                "Ethereum endpoint is behind";
-                                "url" => eth_clone.url_hostname()
-                        );
-                        format_err!("Block {} not found in the chain", to)
-                    }),
-                    Err(e) => Err(e),
-                }),
-        )
-        .compat()
-        .await?;
-
-    let mut block_hashes: HashSet<H256> =
-        triggers.iter().map(EthereumTrigger::block_hash).collect();
-    let mut triggers_by_block: HashMap<u64, Vec<EthereumTrigger>> =
-        triggers.into_iter().fold(HashMap::new(), |mut map, t| {
-            map.entry(t.block_number()).or_default().push(t);
-            map
-        });
-
-    debug!(logger, "Found {} relevant block(s)", block_hashes.len());
-
-    // Make sure `to` is included, even if empty.
-    block_hashes.insert(to_hash);
-    triggers_by_block.entry(to).or_insert(Vec::new());
-
-    let mut blocks = adapter
-        .load_blocks(logger1, chain_store, block_hashes)
-        .and_then(
-            move |block| match triggers_by_block.remove(&block.number()) {
-                Some(triggers) => Ok(EthereumBlockWithTriggers::new(
-                    triggers,
-                    BlockFinality::Final(block),
-                )),
-                None => Err(format_err!(
-                    "block {:?} not found in `triggers_by_block`",
-                    block
-                )),
-            },
-        )
-        .collect()
-        .compat()
-        .await?;
-
-    blocks.sort_by_key(|block| block.ethereum_block.number());
-
-    // Sanity check that the returned blocks are in the correct range.
-    // Unwrap: `blocks` always includes at least `to`.
-    let first = blocks.first().unwrap().ethereum_block.number();
-    let last = blocks.last().unwrap().ethereum_block.number();
-    if first < from {
-        return Err(format_err!(
-            "block {} returned by the Ethereum node is before {}, the first block of the requested range",
-            first,
-            from,
-        ));
-    }
-    if last > to {
-        return Err(format_err!(
-            "block {} returned by the Ethereum node is after {}, the last block of the requested range",
-            last,
-            to,
-        ));
-    }
-
-    Ok(blocks)
-}
-
 #[cfg(test)]
 mod tests {
-    use super::EthereumCallFilter;
+    use super::{EthereumCallFilter, EthereumCallRevert};
 
     use web3::types::Address;
 
@@ -1071,4 +993,61 @@ mod tests {
             Some(&(1, HashSet::from_iter(vec![[1u8; 4]])))
         );
     }
+
+    fn revert(data: &str) -> EthereumCallRevert {
+        EthereumCallRevert::from_data(hex::decode(data).unwrap())
+    }
+
+    fn revert_reason(data: &str) -> String {
+        revert(data).reason
+    }
+
+    #[test]
+    fn decode_error_revert() {
+        // `require(false, "Ownable: caller is not the owner")`
+        let data = "08c379a0\
+            0000000000000000000000000000000000000000000000000000000000000020\
+            0000000000000000000000000000000000000000000000000000000000000020\
+            4f776e61626c653a2063616c6c6572206973206e6f7420746865206f776e6572";
+        let revert = revert(data);
+        assert_eq!("Ownable: caller is not the owner", revert.reason);
+        assert_eq!(hex::decode(data).unwrap(), revert.data.unwrap().0);
+    }
+
+    #[test]
+    fn decode_panic_revert() {
+        let panic = |code: &str| revert_reason(&format!("4e487b71{:0>64}", code));
+        assert_eq!("panic 0x1: assertion failed", panic("1"));
+        assert_eq!("panic 0x11: arithmetic overflow or underflow", panic("11"));
+        assert_eq!("panic 0x12: division or modulo by zero", panic("12"));
+        assert_eq!("panic 0x32: array index out of bounds", panic("32"));
+        assert_eq!("panic 0x99: unknown panic code", panic("99"));
+        assert_eq!(
+            "panic 0x10000000000000001: unknown panic code",
+            panic("10000000000000001")
+        );
+    }
+
+    #[test]
+    fn decode_custom_error_revert() {
+        // `error InsufficientBalance(uint256 available, uint256 required)`
+        // with `available = 1` and `required = 2`
+        let data = "cf479181\
+            0000000000000000000000000000000000000000000000000000000000000001\
+            0000000000000000000000000000000000000000000000000000000000000002";
+        let revert = revert(data);
+        assert_eq!("custom error 0xcf479181", revert.reason);
+        assert_eq!(hex::decode(data).unwrap(), revert.data.unwrap().0);
+
+        // Data that is not an ABI encoded error is passed along as it is
+        assert_eq!("invalid revert data 0x0102", revert_reason("0102"));
+        // A malformed `Error(string)` is treated like a custom error
+        assert_eq!("custom error 0x08c379a0", revert_reason("08c379a0ff"));
+    }
+
+    #[test]
+    fn decode_empty_revert() {
+        assert_eq!(EthereumCallRevert::with_reason("no reason"), revert(""));
+        assert_eq!(None, EthereumCallRevert::from_standard_data(&[]));
+    }
 }
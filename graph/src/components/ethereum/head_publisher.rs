@@ -0,0 +1,583 @@
+//! Publish chain head updates and the progress of deployments to systems
+//! outside of graph-node. The publisher subscribes to the same events as
+//! the rest of the node, namely chain head updates for each network and
+//! store events for `SubgraphDeployment` entities, and turns them into small
+//! JSON messages that it posts to a webhook or publishes on a NATS subject.
+//!
+//! Publishing never holds up the write path: messages go into a bounded
+//! queue, and a separate task sends them in batches. If the sink can not
+//! keep up or is down, the oldest messages are dropped, and the number of
+//! dropped messages is counted and logged.
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::EthereumBlockPointer;
+use crate::prelude::*;
+
+lazy_static! {
+    /// Where to publish head updates; off when not set
+    static ref HEAD_PUBLISHER_URL: Option<String> =
+        std::env::var("GRAPH_HEAD_PUBLISHER_URL").ok();
+    static ref HEAD_PUBLISHER_BATCH_SIZE: usize =
+        env_usize("GRAPH_HEAD_PUBLISHER_BATCH_SIZE", 100);
+    static ref HEAD_PUBLISHER_QUEUE_SIZE: usize =
+        env_usize("GRAPH_HEAD_PUBLISHER_QUEUE_SIZE", 10_000);
+    static ref HEAD_PUBLISHER_MAX_RETRIES: usize =
+        env_usize("GRAPH_HEAD_PUBLISHER_MAX_RETRIES", 5);
+}
+
+/// The longest we wait between two attempts to send a batch
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The port that NATS servers listen on unless the URL says otherwise
+const NATS_DEFAULT_PORT: u16 = 4222;
+
+fn env_usize(var: &str, default: usize) -> usize {
+    std::env::var(var)
+        .map(|s| {
+            s.parse::<usize>()
+                .unwrap_or_else(|_| panic!("invalid {}", var))
+        })
+        .unwrap_or(default)
+}
+
+/// A message for external consumers
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HeadMessage {
+    /// The chain head of `network` moved to a new block
+    #[serde(rename_all = "camelCase")]
+    ChainHead {
+        network: String,
+        number: u64,
+        hash: String,
+    },
+    /// The deployment `deployment` finished processing a new block
+    #[serde(rename_all = "camelCase")]
+    DeploymentBlock {
+        deployment: String,
+        number: u64,
+        hash: String,
+    },
+}
+
+impl HeadMessage {
+    pub fn chain_head(network: &str, ptr: &EthereumBlockPointer) -> Self {
+        HeadMessage::ChainHead {
+            network: network.to_owned(),
+            number: ptr.number,
+            hash: ptr.hash_hex(),
+        }
+    }
+
+    pub fn deployment_block(deployment: &SubgraphDeploymentId, ptr: &EthereumBlockPointer) -> Self {
+        HeadMessage::DeploymentBlock {
+            deployment: deployment.to_string(),
+            number: ptr.number,
+            hash: ptr.hash_hex(),
+        }
+    }
+}
+
+/// Where messages are sent
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeadSink {
+    /// POST each batch as a JSON array to the URL
+    Webhook(String),
+    /// Publish each message as its own NATS message on `subject`
+    Nats { address: String, subject: String },
+}
+
+impl FromStr for HeadSink {
+    type Err = Error;
+
+    /// Parse `http(s)://...` as a webhook and `nats://host[:port]/subject`
+    /// as a NATS subject
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let url = url::Url::parse(s).map_err(|e| format_err!("invalid URL `{}`: {}", s, e))?;
+        match url.scheme() {
+            "http" | "https" => Ok(HeadSink::Webhook(s.to_owned())),
+            "nats" => {
+                let host = url
+                    .host_str()
+                    .ok_or_else(|| format_err!("the NATS URL `{}` has no host", s))?;
+                let port = url.port().unwrap_or(NATS_DEFAULT_PORT);
+                let subject = url.path().trim_start_matches('/');
+                if subject.is_empty() || subject.contains(char::is_whitespace) {
+                    return Err(format_err!(
+                        "the NATS URL `{}` must have a subject without spaces as its path",
+                        s
+                    ));
+                }
+                Ok(HeadSink::Nats {
+                    address: format!("{}:{}", host, port),
+                    subject: subject.to_owned(),
+                })
+            }
+            scheme => Err(format_err!(
+                "unsupported scheme `{}` in `{}`; use http, https or nats",
+                scheme,
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HeadSink {
+    /// Only show the host of webhooks since their URLs often contain
+    /// secrets
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeadSink::Webhook(url) => {
+                let host = url::Url::parse(url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_owned))
+                    .unwrap_or_default();
+                write!(f, "webhook on {}", host)
+            }
+            HeadSink::Nats { address, subject } => {
+                write!(f, "NATS subject {} on {}", subject, address)
+            }
+        }
+    }
+}
+
+impl HeadSink {
+    async fn send(&self, client: &reqwest::Client, batch: &[HeadMessage]) -> Result<(), Error> {
+        match self {
+            HeadSink::Webhook(url) => {
+                let body = serde_json::to_string(batch)?;
+                client
+                    .post(url.as_str())
+                    .header("Content-Type", "application/json")
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| format_err!("{}", e))?;
+                Ok(())
+            }
+            HeadSink::Nats { address, subject } => nats_publish(address, subject, batch).await,
+        }
+    }
+}
+
+/// Publish `batch` to a NATS server with the plain text protocol. We only
+/// ever publish, so that one connection per batch and a `PING` at the end,
+/// whose `PONG` tells us that the server has processed all messages, is
+/// all we need
+async fn nats_publish(address: &str, subject: &str, batch: &[HeadMessage]) -> Result<(), Error> {
+    let stream = TcpStream::connect(address).await?;
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    if !line.starts_with("INFO") {
+        return Err(format_err!(
+            "unexpected greeting from NATS: {}",
+            line.trim()
+        ));
+    }
+
+    let mut out = String::from("CONNECT {\"verbose\":false,\"pedantic\":false}\r\n");
+    for msg in batch {
+        let payload = serde_json::to_string(msg)?;
+        out.push_str(&format!(
+            "PUB {} {}\r\n{}\r\n",
+            subject,
+            payload.len(),
+            payload
+        ));
+    }
+    out.push_str("PING\r\n");
+    stream.get_mut().write_all(out.as_bytes()).await?;
+
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(format_err!("NATS closed the connection"));
+        }
+        match line.trim_end() {
+            "PONG" => return Ok(()),
+            "PING" => stream.get_mut().write_all(b"PONG\r\n").await?,
+            l if l.starts_with("-ERR") => return Err(format_err!("NATS error: {}", l)),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HeadPublisherConfig {
+    pub sink: HeadSink,
+    /// The most messages we send at once
+    pub batch_size: usize,
+    /// The most messages we hold on to while the sink is unavailable
+    pub queue_size: usize,
+    /// How often we try again to send a batch before giving up for now
+    pub max_retries: usize,
+    /// How long we wait before the first retry; the wait doubles with
+    /// every retry up to `MAX_BACKOFF`
+    pub backoff: Duration,
+    /// How often we send what has been queued
+    pub flush_interval: Duration,
+}
+
+impl HeadPublisherConfig {
+    /// The configuration from `GRAPH_HEAD_PUBLISHER_*`, or `None` if
+    /// publishing is not turned on
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let url = match HEAD_PUBLISHER_URL.as_ref() {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        Ok(Some(HeadPublisherConfig {
+            sink: url.parse()?,
+            batch_size: (*HEAD_PUBLISHER_BATCH_SIZE).max(1),
+            queue_size: (*HEAD_PUBLISHER_QUEUE_SIZE).max(1),
+            max_retries: *HEAD_PUBLISHER_MAX_RETRIES,
+            backoff: Duration::from_millis(500),
+            flush_interval: Duration::from_secs(1),
+        }))
+    }
+}
+
+/// Queues messages and sends them to a `HeadSink`
+pub struct HeadPublisher {
+    logger: Logger,
+    config: HeadPublisherConfig,
+    client: reqwest::Client,
+    queue: Mutex<VecDeque<HeadMessage>>,
+    dropped: AtomicU64,
+}
+
+impl HeadPublisher {
+    pub fn new(logger: &Logger, config: HeadPublisherConfig) -> Self {
+        HeadPublisher {
+            logger: logger.new(o!("component" => "HeadPublisher")),
+            config,
+            client: reqwest::Client::new(),
+            queue: Mutex::new(VecDeque::new()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue `msg` for sending. Drops the oldest queued message if the
+    /// queue is full
+    pub fn publish(&self, msg: HeadMessage) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.push_back(msg);
+        self.trim(&mut queue);
+    }
+
+    /// How many messages were dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// How many messages are waiting to be sent
+    pub fn queued(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    fn trim(&self, queue: &mut VecDeque<HeadMessage>) {
+        let excess = queue.len().saturating_sub(self.config.queue_size);
+        if excess > 0 {
+            queue.drain(..excess);
+            let dropped = self.dropped.fetch_add(excess as u64, Ordering::SeqCst) + excess as u64;
+            warn!(self.logger, "Head publisher queue is full, dropped the oldest messages";
+                  "dropped" => excess,
+                  "dropped_total" => dropped);
+        }
+    }
+
+    fn take_batch(&self) -> Vec<HeadMessage> {
+        let mut queue = self.queue.lock().unwrap();
+        let len = queue.len().min(self.config.batch_size);
+        queue.drain(..len).collect()
+    }
+
+    /// Put a batch that we could not send back at the front of the queue.
+    /// Messages that arrived in the meantime take precedence over it
+    fn requeue(&self, batch: Vec<HeadMessage>) {
+        let mut queue = self.queue.lock().unwrap();
+        for msg in batch.into_iter().rev() {
+            queue.push_front(msg);
+        }
+        self.trim(&mut queue);
+    }
+
+    async fn send_with_retries(&self, batch: &[HeadMessage]) -> Result<(), Error> {
+        let mut backoff = self.config.backoff;
+        let mut attempt = 0;
+        loop {
+            match self.config.sink.send(&self.client, batch).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.config.max_retries => {
+                    debug!(self.logger, "Failed to send head updates, retrying";
+                           "attempt" => attempt + 1,
+                           "backoff_ms" => backoff.as_millis() as u64,
+                           "error" => e.to_string());
+                    tokio::time::delay_for(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Send everything that is queued in batches of at most `batch_size`.
+    /// If a batch can not be sent even after retrying, it goes back into
+    /// the queue and we stop
+    pub async fn flush(&self) -> Result<(), Error> {
+        loop {
+            let batch = self.take_batch();
+            if batch.is_empty() {
+                return Ok(());
+            }
+            if let Err(e) = self.send_with_retries(&batch).await {
+                self.requeue(batch);
+                return Err(e);
+            }
+        }
+    }
+
+    /// Keep sending queued messages, every `flush_interval`
+    pub async fn run(self: Arc<Self>) {
+        info!(self.logger, "Publishing head updates"; "sink" => self.config.sink.to_string());
+        loop {
+            if let Err(e) = self.flush().await {
+                warn!(self.logger, "Failed to send head updates, will try again";
+                      "queued" => self.queued(),
+                      "error" => e.to_string());
+            }
+            tokio::time::delay_for(self.config.flush_interval).await;
+        }
+    }
+}
+
+/// Publish a message whenever the head of `network` in `store` changes
+pub async fn publish_chain_heads(
+    publisher: Arc<HeadPublisher>,
+    network: String,
+    store: Arc<dyn ChainStore>,
+) {
+    let mut updates = store.chain_head_updates().compat();
+    let mut last = None;
+    while let Some(Ok(())) = updates.next().await {
+        match store.chain_head_ptr() {
+            Ok(Some(ptr)) if last.as_ref() != Some(&ptr) => {
+                publisher.publish(HeadMessage::chain_head(&network, &ptr));
+                last = Some(ptr);
+            }
+            Ok(_) => {}
+            Err(e) => warn!(publisher.logger, "Failed to get chain head pointer";
+                            "network" => &network,
+                            "error" => e.to_string()),
+        }
+    }
+}
+
+/// Publish a message whenever a deployment in `store` processes a block.
+/// The store announces that with a change to the deployment's
+/// `SubgraphDeployment` entity
+pub async fn publish_deployment_blocks(publisher: Arc<HeadPublisher>, store: Arc<dyn EntityStore>) {
+    let mut events = store
+        .subscribe(vec![SubgraphDeploymentEntity::subgraph_entity_pair()])
+        .compat();
+    let mut last: HashMap<SubgraphDeploymentId, EthereumBlockPointer> = HashMap::new();
+    let deployment_type = SubgraphDeploymentEntity::TYPENAME.as_str();
+    while let Some(Ok(event)) = events.next().await {
+        for change in &event.changes {
+            if change.entity_type != deployment_type
+                || change.operation != EntityChangeOperation::Set
+            {
+                continue;
+            }
+            let id = match SubgraphDeploymentId::new(change.entity_id.as_str()) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            match store.block_ptr(id.clone()) {
+                Ok(Some(ptr)) if last.get(&id) != Some(&ptr) => {
+                    publisher.publish(HeadMessage::deployment_block(&id, &ptr));
+                    last.insert(id, ptr);
+                }
+                Ok(_) => {}
+                Err(e) => warn!(publisher.logger, "Failed to get deployment block pointer";
+                                "deployment" => id.to_string(),
+                                "error" => e.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use web3::types::H256;
+
+    /// A webhook that answers the first `failures` requests with a 500 and
+    /// all others with a 200, and remembers the bodies of all requests
+    async fn http_sink(failures: usize) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/heads", listener.local_addr().unwrap());
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let received = bodies.clone();
+        tokio::spawn(async move {
+            let mut requests = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let body = loop {
+                    let mut chunk = [0u8; 4096];
+                    let n = socket.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let len = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                let l = l.to_lowercase();
+                                l.strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if buf.len() >= end + 4 + len {
+                            break buf[end + 4..end + 4 + len].to_vec();
+                        }
+                    }
+                };
+                received
+                    .lock()
+                    .unwrap()
+                    .push(serde_json::from_slice(&body).unwrap());
+                let status = if requests < failures {
+                    "500 Internal Server Error"
+                } else {
+                    "200 OK"
+                };
+                requests += 1;
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    fn publisher(url: &str, batch_size: usize, queue_size: usize) -> HeadPublisher {
+        let config = HeadPublisherConfig {
+            sink: url.parse().unwrap(),
+            batch_size,
+            queue_size,
+            max_retries: 3,
+            backoff: Duration::from_millis(10),
+            flush_interval: Duration::from_millis(10),
+        };
+        HeadPublisher::new(&crate::log::logger(false), config)
+    }
+
+    fn head(number: u64) -> HeadMessage {
+        HeadMessage::chain_head("mainnet", &(H256::from_low_u64_be(number), number).into())
+    }
+
+    #[test]
+    fn parses_sinks() {
+        assert_eq!(
+            HeadSink::Webhook("https://example.com/heads".to_owned()),
+            "https://example.com/heads".parse().unwrap()
+        );
+        assert_eq!(
+            HeadSink::Nats {
+                address: "localhost:4222".to_owned(),
+                subject: "graph.heads".to_owned()
+            },
+            "nats://localhost/graph.heads".parse().unwrap()
+        );
+        assert!("nats://localhost:4222".parse::<HeadSink>().is_err());
+        assert!("ftp://example.com".parse::<HeadSink>().is_err());
+    }
+
+    #[test]
+    fn serializes_messages() {
+        assert_eq!(
+            serde_json::json!({
+                "type": "chainHead",
+                "network": "mainnet",
+                "number": 1,
+                "hash": format!("{:x}", H256::from_low_u64_be(1)),
+            }),
+            serde_json::to_value(head(1)).unwrap()
+        );
+    }
+
+    #[test]
+    fn drops_oldest_messages_when_full() {
+        let publisher = publisher("http://localhost/heads", 10, 3);
+        for number in 0..5 {
+            publisher.publish(head(number));
+        }
+        assert_eq!(2, publisher.dropped());
+        assert_eq!(vec![head(2), head(3), head(4)], publisher.take_batch());
+    }
+
+    #[tokio::test]
+    async fn sends_in_batches() {
+        let (url, bodies) = http_sink(0).await;
+        let publisher = publisher(&url, 2, 100);
+        for number in 0..5 {
+            publisher.publish(head(number));
+        }
+
+        publisher.flush().await.unwrap();
+
+        let bodies = bodies.lock().unwrap();
+        let sizes: Vec<_> = bodies.iter().map(|b| b.as_array().unwrap().len()).collect();
+        assert_eq!(vec![2, 2, 1], sizes);
+        assert_eq!(serde_json::to_value(head(4)).unwrap(), bodies[2][0]);
+        assert_eq!(0, publisher.queued());
+    }
+
+    #[tokio::test]
+    async fn retries_failed_batches() {
+        let (url, bodies) = http_sink(2).await;
+        let publisher = publisher(&url, 10, 100);
+        publisher.publish(head(1));
+
+        publisher.flush().await.unwrap();
+
+        // Two failures and one success, all with the same batch
+        let bodies = bodies.lock().unwrap();
+        assert_eq!(3, bodies.len());
+        assert!(bodies.iter().all(|body| body == &bodies[0]));
+        assert_eq!(0, publisher.queued());
+    }
+
+    #[tokio::test]
+    async fn requeues_when_retries_run_out() {
+        let (url, bodies) = http_sink(10).await;
+        let publisher = publisher(&url, 10, 100);
+        publisher.publish(head(1));
+        publisher.publish(head(2));
+
+        assert!(publisher.flush().await.is_err());
+
+        // The first attempt and three retries
+        assert_eq!(4, bodies.lock().unwrap().len());
+        assert_eq!(vec![head(1), head(2)], publisher.take_batch());
+        assert_eq!(0, publisher.dropped());
+    }
+}
@@ -0,0 +1,421 @@
+//! The health of the Ethereum providers a node is configured with.
+//! `poll_provider_heads` regularly asks every provider for its latest block
+//! and records the answer, or the fact that there was none, in a
+//! `ProviderHealthRegistry`. The registry also remembers the last few heads
+//! that each provider announced so that providers that fall behind the
+//! others on their network stand out; `persist_head_announcements` saves
+//! those announcements in the store so that they survive a restart
+use lazy_static::lazy_static;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{EthereumAdapter, EthereumBlockPointer, EthereumNetworks};
+use crate::prelude::*;
+
+lazy_static! {
+    /// How often `poll_provider_heads` asks providers for their latest block
+    static ref PROVIDER_HEALTH_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ETHEREUM_PROVIDER_HEALTH_INTERVAL")
+            .map(|s| s
+                .parse::<u64>()
+                .expect("invalid GRAPH_ETHEREUM_PROVIDER_HEALTH_INTERVAL"))
+            .unwrap_or(30)
+    );
+
+    /// How many of the heads a provider announced we remember
+    static ref PROVIDER_HEAD_HISTORY: usize = std::env::var("GRAPH_ETHEREUM_PROVIDER_HEAD_HISTORY")
+        .map(|s| s
+            .parse::<usize>()
+            .expect("invalid GRAPH_ETHEREUM_PROVIDER_HEAD_HISTORY"))
+        .unwrap_or(20);
+
+    /// How often `persist_head_announcements` saves the announced heads
+    static ref PROVIDER_HEADS_PERSIST_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_ETHEREUM_PROVIDER_HEADS_PERSIST_INTERVAL")
+            .map(|s| s
+                .parse::<u64>()
+                .expect("invalid GRAPH_ETHEREUM_PROVIDER_HEADS_PERSIST_INTERVAL"))
+            .unwrap_or(60)
+    );
+}
+
+/// What we know about one provider
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProviderHealth {
+    /// The hostname of the provider. We never keep the full URL since it
+    /// often contains an API key
+    pub provider: String,
+    /// The latest block that the provider reported
+    pub latest_block: Option<EthereumBlockPointer>,
+    /// How often asking the provider for its latest block failed
+    pub errors: u64,
+    /// The most recent heads that the provider announced, oldest first. We
+    /// only add an announcement when the head changes
+    pub announcements: VecDeque<HeadAnnouncement>,
+}
+
+impl ProviderHealth {
+    fn new(provider: &str) -> Self {
+        ProviderHealth {
+            provider: provider.to_owned(),
+            latest_block: None,
+            errors: 0,
+            announcements: VecDeque::new(),
+        }
+    }
+
+    fn announce(&mut self, announcement: HeadAnnouncement, capacity: usize) {
+        self.announcements.push_back(announcement);
+        while self.announcements.len() > capacity {
+            self.announcements.pop_front();
+        }
+    }
+}
+
+/// The health of all providers, by network. Providers are identified by
+/// their hostname; providers for the same network that are on the same host
+/// share their entry
+#[derive(Default)]
+pub struct ProviderHealthRegistry {
+    networks: RwLock<BTreeMap<String, Vec<ProviderHealth>>>,
+    /// How many blocks each provider is behind the provider with the
+    /// highest head on its network
+    drift: Option<Box<GaugeVec>>,
+}
+
+impl ProviderHealthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry that also reports the `provider_head_drift_blocks` gauge
+    /// to `registry`
+    pub fn with_metrics(registry: Arc<dyn MetricsRegistry>) -> Self {
+        let drift = registry
+            .new_gauge_vec(
+                "provider_head_drift_blocks",
+                "How many blocks a provider's head is behind the highest head \
+                 of any provider on the same network",
+                vec![String::from("network"), String::from("provider")],
+            )
+            .expect("failed to create `provider_head_drift_blocks` gauge");
+        ProviderHealthRegistry {
+            networks: RwLock::new(BTreeMap::new()),
+            drift: Some(drift),
+        }
+    }
+
+    /// Make `provider` known for `network` so that it is listed even before
+    /// we heard from it
+    pub fn register(&self, network: &str, provider: &str) {
+        self.update(network, provider, |_| ());
+    }
+
+    /// Record that `provider` reported `block` as its latest block
+    pub fn record_head(&self, network: &str, provider: &str, block: EthereumBlockPointer) {
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.record_announcement(network, provider, HeadAnnouncement { block, received_at });
+    }
+
+    /// Record that `provider` announced the head in `announcement`
+    pub fn record_announcement(
+        &self,
+        network: &str,
+        provider: &str,
+        announcement: HeadAnnouncement,
+    ) {
+        self.update(network, provider, |health| {
+            if health.latest_block.as_ref() != Some(&announcement.block) {
+                health.latest_block = Some(announcement.block.clone());
+                health.announce(announcement, *PROVIDER_HEAD_HISTORY);
+            }
+        });
+        self.report_drift(network);
+    }
+
+    /// Put the `announcements` we saved for `provider` back, before the
+    /// announcements we recorded since. Providers that have not been
+    /// registered for `network` are ignored since the node is no longer
+    /// configured with them
+    pub fn restore(&self, network: &str, provider: &str, announcements: Vec<HeadAnnouncement>) {
+        let mut networks = self.networks.write().unwrap();
+        if let Some(health) = networks
+            .get_mut(network)
+            .and_then(|providers| providers.iter_mut().find(|h| h.provider == provider))
+        {
+            let recent = std::mem::replace(&mut health.announcements, announcements.into());
+            health.announcements.extend(recent);
+            while health.announcements.len() > *PROVIDER_HEAD_HISTORY {
+                health.announcements.pop_front();
+            }
+        }
+    }
+
+    /// How many blocks each provider for `network` that reported a head is
+    /// behind the highest head of any provider for `network`
+    pub fn drift(&self, network: &str) -> Vec<(String, u64)> {
+        let providers = self.providers(network);
+        let highest = providers
+            .iter()
+            .filter_map(|health| health.latest_block.as_ref().map(|block| block.number))
+            .max();
+        match highest {
+            Some(highest) => providers
+                .iter()
+                .filter_map(|health| {
+                    health
+                        .latest_block
+                        .as_ref()
+                        .map(|block| (health.provider.clone(), highest - block.number))
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn report_drift(&self, network: &str) {
+        if let Some(gauge) = &self.drift {
+            for (provider, drift) in self.drift(network) {
+                gauge
+                    .with_label_values(&[network, &provider])
+                    .set(drift as f64);
+            }
+        }
+    }
+
+    /// Record that we could not get the latest block from `provider`
+    pub fn record_error(&self, network: &str, provider: &str) {
+        self.update(network, provider, |health| health.errors += 1);
+    }
+
+    /// The names of all networks with providers, in alphabetical order
+    pub fn networks(&self) -> Vec<String> {
+        self.networks.read().unwrap().keys().cloned().collect()
+    }
+
+    /// The providers for `network` in the order in which they were
+    /// registered
+    pub fn providers(&self, network: &str) -> Vec<ProviderHealth> {
+        self.networks
+            .read()
+            .unwrap()
+            .get(network)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn update(&self, network: &str, provider: &str, f: impl FnOnce(&mut ProviderHealth)) {
+        let mut networks = self.networks.write().unwrap();
+        let providers = networks.entry(network.to_owned()).or_default();
+        match providers
+            .iter_mut()
+            .find(|health| health.provider == provider)
+        {
+            Some(health) => f(health),
+            None => {
+                let mut health = ProviderHealth::new(provider);
+                f(&mut health);
+                providers.push(health);
+            }
+        }
+    }
+}
+
+/// Ask every provider in `networks` once for its latest block and record
+/// the answers in `registry`. A provider that does not answer within
+/// `timeout` counts as an error
+pub async fn poll_provider_heads_once(
+    logger: &Logger,
+    registry: &ProviderHealthRegistry,
+    networks: &EthereumNetworks,
+    timeout: Duration,
+) {
+    for (network, _, adapter) in networks.flatten() {
+        let provider = adapter.url_hostname().to_owned();
+        registry.register(&network, &provider);
+
+        let head = tokio::time::timeout(timeout, adapter.latest_block_header(logger).compat())
+            .await
+            .map_err(|_| format_err!("no answer after {}s", timeout.as_secs()))
+            .and_then(|res| res.map_err(|e| format_err!("{}", e)))
+            .and_then(|block| match (block.hash, block.number) {
+                (Some(hash), Some(number)) => {
+                    Ok(EthereumBlockPointer::from((hash, number.as_u64())))
+                }
+                _ => Err(format_err!("latest block has no hash or number")),
+            });
+        match head {
+            Ok(block) => registry.record_head(&network, &provider, block),
+            Err(e) => {
+                debug!(logger, "Failed to get latest block from provider";
+                       "network" => &network,
+                       "provider" => &provider,
+                       "error" => e.to_string());
+                registry.record_error(&network, &provider);
+            }
+        }
+    }
+}
+
+/// Keep polling the heads of all providers in `networks`, every
+/// `GRAPH_ETHEREUM_PROVIDER_HEALTH_INTERVAL` seconds
+pub async fn poll_provider_heads(
+    logger: Logger,
+    registry: Arc<ProviderHealthRegistry>,
+    networks: EthereumNetworks,
+) {
+    let logger = logger.new(o!("component" => "ProviderHealth"));
+    loop {
+        poll_provider_heads_once(&logger, &registry, &networks, *PROVIDER_HEALTH_INTERVAL).await;
+        tokio::time::delay_for(*PROVIDER_HEALTH_INTERVAL).await;
+    }
+}
+
+/// Register the providers in `networks` and restore the announcements that
+/// `persist_head_announcements` saved for them in `stores`
+pub fn restore_head_announcements(
+    logger: &Logger,
+    registry: &ProviderHealthRegistry,
+    networks: &EthereumNetworks,
+    stores: &HashMap<String, Arc<dyn ChainStore>>,
+) {
+    for (network, _, adapter) in networks.flatten() {
+        registry.register(&network, adapter.url_hostname());
+    }
+    for (network, store) in stores {
+        match store.head_announcements() {
+            Ok(saved) => {
+                for (provider, announcements) in saved {
+                    registry.restore(network, &provider, announcements);
+                }
+            }
+            Err(e) => warn!(logger, "Failed to load head announcements of providers";
+                            "network" => network,
+                            "error" => e.to_string()),
+        }
+    }
+}
+
+/// Save the announcements in `registry` to `stores` every
+/// `GRAPH_ETHEREUM_PROVIDER_HEADS_PERSIST_INTERVAL` seconds
+pub async fn persist_head_announcements(
+    logger: Logger,
+    registry: Arc<ProviderHealthRegistry>,
+    stores: HashMap<String, Arc<dyn ChainStore>>,
+) {
+    let logger = logger.new(o!("component" => "ProviderHealth"));
+    loop {
+        tokio::time::delay_for(*PROVIDER_HEADS_PERSIST_INTERVAL).await;
+        for (network, store) in &stores {
+            for health in registry.providers(network) {
+                let announcements: Vec<_> = health.announcements.into_iter().collect();
+                if let Err(e) = store.save_head_announcements(&health.provider, &announcements) {
+                    warn!(logger, "Failed to save head announcements of provider";
+                          "network" => network,
+                          "provider" => &health.provider,
+                          "error" => e.to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::H256;
+
+    #[test]
+    fn records_heads_and_errors() {
+        let registry = ProviderHealthRegistry::new();
+        registry.register("mainnet", "a.example.com");
+        registry.record_error("mainnet", "b.example.com");
+        registry.record_head("mainnet", "a.example.com", (H256::zero(), 7u64).into());
+        registry.record_error("mainnet", "b.example.com");
+        registry.register("ropsten", "a.example.com");
+
+        assert_eq!(vec!["mainnet", "ropsten"], registry.networks());
+        let providers = registry.providers("mainnet");
+        assert_eq!(2, providers.len());
+        assert_eq!("a.example.com", providers[0].provider);
+        assert_eq!(Some((H256::zero(), 7u64).into()), providers[0].latest_block);
+        assert_eq!(0, providers[0].errors);
+        assert_eq!(1, providers[0].announcements.len());
+        assert_eq!(
+            ProviderHealth {
+                errors: 2,
+                ..ProviderHealth::new("b.example.com")
+            },
+            providers[1]
+        );
+        assert_eq!(
+            vec![ProviderHealth::new("a.example.com")],
+            registry.providers("ropsten")
+        );
+        assert!(registry.providers("kovan").is_empty());
+    }
+
+    #[test]
+    fn announcements_and_drift() {
+        fn head(number: u64, received_at: u64) -> HeadAnnouncement {
+            HeadAnnouncement {
+                block: (H256::from_low_u64_be(number), number).into(),
+                received_at,
+            }
+        }
+
+        let registry = ProviderHealthRegistry::new();
+        registry.register("mainnet", "a.example.com");
+        registry.register("mainnet", "b.example.com");
+        assert!(registry.drift("mainnet").is_empty());
+
+        // A head that did not change is not announced again
+        registry.record_announcement("mainnet", "a.example.com", head(10, 100));
+        registry.record_announcement("mainnet", "a.example.com", head(10, 101));
+        registry.record_announcement("mainnet", "a.example.com", head(12, 102));
+        registry.record_announcement("mainnet", "b.example.com", head(9, 103));
+
+        let providers = registry.providers("mainnet");
+        assert_eq!(
+            vec![head(10, 100), head(12, 102)],
+            providers[0]
+                .announcements
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                ("a.example.com".to_owned(), 0),
+                ("b.example.com".to_owned(), 3)
+            ],
+            registry.drift("mainnet")
+        );
+
+        // The history is bounded
+        for number in 13..(13 + *PROVIDER_HEAD_HISTORY as u64) {
+            registry.record_announcement("mainnet", "a.example.com", head(number, 200));
+        }
+        let announcements = &registry.providers("mainnet")[0].announcements;
+        assert_eq!(*PROVIDER_HEAD_HISTORY, announcements.len());
+        assert_eq!(Some(&head(13, 200)), announcements.front());
+
+        // Saved announcements are only restored for known providers
+        registry.restore("mainnet", "b.example.com", vec![head(5, 50)]);
+        registry.restore("mainnet", "c.example.com", vec![head(5, 50)]);
+        let providers = registry.providers("mainnet");
+        assert_eq!(2, providers.len());
+        assert_eq!(
+            vec![head(5, 50), head(9, 103)],
+            providers[1]
+                .announcements
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,22 +1,41 @@
 mod adapter;
+pub mod head_publisher;
+pub mod health;
 mod listener;
 mod network;
-mod stream;
+pub mod reload;
+pub mod repair;
+pub mod replay;
+pub mod shared_logs;
+pub mod stream_status;
 mod types;
 
+use lazy_static::lazy_static;
+use std::env;
+use std::str::FromStr;
+
 pub use self::adapter::{
-    blocks_with_triggers, triggers_in_block, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter,
-    EthereumAdapterError, EthereumBlockFilter, EthereumCallFilter, EthereumContractCall,
+    provider_id, BlockStreamMetrics, EthGetLogsFilter, EthereumAdapter, EthereumAdapterError,
+    EthereumBlockFilter, EthereumCallFilter, EthereumCallRevert, EthereumContractCall,
     EthereumContractCallError, EthereumContractState, EthereumContractStateError,
     EthereumContractStateRequest, EthereumLogFilter, EthereumNetworkIdentifier,
     MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
 };
 pub use self::listener::{ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream};
 pub use self::network::{EthereumNetworkAdapters, EthereumNetworks, NodeCapabilities};
-pub use self::stream::{BlockStream, BlockStreamBuilder, BlockStreamEvent};
 pub use self::types::{
     BlockFinality, EthereumBlock, EthereumBlockData, EthereumBlockPointer,
-    EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumBlockWithTriggers, EthereumCall,
-    EthereumCallData, EthereumEventData, EthereumTransactionData, EthereumTrigger,
-    LightEthereumBlock, LightEthereumBlockExt,
+    EthereumBlockTriggerType, EthereumBlockWithCalls, EthereumCall, EthereumCallData,
+    EthereumEventData, EthereumTransactionData, EthereumTrigger, LightEthereumBlock,
+    LightEthereumBlockExt,
 };
+
+lazy_static! {
+    /// Blocks that are more than this many blocks behind the chain head are
+    /// considered final and are never reverted
+    pub static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
+        .ok()
+        .map(|s| u64::from_str(&s)
+            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
+        .unwrap_or(50);
+}
@@ -0,0 +1,860 @@
+//! Changing the providers of a network while the node is running.
+//!
+//! When providers are read from a file, the providers of each network that
+//! have the same capabilities are put into one `ProviderSet`, which is what
+//! the rest of the node sees as the adapter for them. Providers are tried in
+//! the order in which they appear in the file: each request goes to the
+//! first provider that is healthy, and a provider whose last request failed
+//! only gets requests again after `FAILED_PROVIDER_RETRY`, or when all
+//! providers of the set failed. Since whoever sent the failed request
+//! retries it, the retry goes to the next provider.
+//!
+//! Reloading the file changes the providers in each set in place and puts
+//! them in the order of the file: providers that are no longer in the file
+//! get no new requests, but requests that were already sent to them finish,
+//! and providers that are new in the file get requests right away if they
+//! come first. A provider whose URL changed is removed and added again.
+//!
+//! Networks or capabilities that were not in the file when the node started
+//! need a network store and adapters that the rest of the node does not
+//! know about, and can therefore not be added by a reload.
+use ethabi::Token;
+use futures::Future;
+use std::collections::HashSet;
+use std::fmt;
+use std::marker::Unpin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use web3::types::{Block, Log, H256};
+
+use super::adapter::*;
+use super::network::{EthereumNetworks, NodeCapabilities};
+use super::types::*;
+use crate::prelude::*;
+
+/// How long a provider whose last request failed is skipped while there
+/// are other providers in its set that have not failed
+const FAILED_PROVIDER_RETRY: Duration = Duration::from_secs(30);
+
+/// One provider as it appears in the providers file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderConfig {
+    pub network: String,
+    pub capabilities: NodeCapabilities,
+    pub url: String,
+}
+
+struct Provider {
+    url: String,
+    adapter: Arc<dyn EthereumAdapter>,
+    /// Set once the provider is removed from its set so that subscriptions
+    /// to its new heads end
+    removed: Arc<AtomicBool>,
+    health: Arc<ProviderHealth>,
+}
+
+/// When the last request to a provider failed, if none succeeded since
+#[derive(Default)]
+struct ProviderHealth {
+    failed_at: Mutex<Option<Instant>>,
+}
+
+/// The providers of a network that have the same capabilities
+pub struct ProviderSet {
+    network: String,
+    capabilities: NodeCapabilities,
+    /// How the set is shown in logs, the index node API, and the provenance
+    /// of cached data, since it can not be pinned to one provider
+    name: String,
+    providers: RwLock<Vec<Provider>>,
+}
+
+impl fmt::Debug for ProviderSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ProviderSet({})", self.name)
+    }
+}
+
+/// Put all `providers` into sets by network and capabilities, and return
+/// the sets together with the networks that use them as their adapters
+pub fn provider_sets(
+    providers: Vec<(ProviderConfig, Arc<dyn EthereumAdapter>)>,
+) -> (Vec<Arc<ProviderSet>>, EthereumNetworks) {
+    let mut grouped: Vec<(
+        String,
+        NodeCapabilities,
+        Vec<(String, Arc<dyn EthereumAdapter>)>,
+    )> = Vec::new();
+    for (config, adapter) in providers {
+        match grouped.iter_mut().find(|(network, capabilities, _)| {
+            network == &config.network && capabilities == &config.capabilities
+        }) {
+            Some((_, _, members)) => members.push((config.url, adapter)),
+            None => grouped.push((
+                config.network,
+                config.capabilities,
+                vec![(config.url, adapter)],
+            )),
+        }
+    }
+
+    let mut networks = EthereumNetworks::new();
+    let sets = grouped
+        .into_iter()
+        .map(|(network, capabilities, members)| {
+            let set = Arc::new(ProviderSet::new(network.clone(), capabilities, members));
+            networks.insert(network, capabilities, set.clone());
+            set
+        })
+        .collect();
+    networks.sort();
+    (sets, networks)
+}
+
+impl ProviderSet {
+    /// Create a set with the given providers, which are pairs of their URL
+    /// and their adapter. There must be at least one provider
+    pub fn new(
+        network: String,
+        capabilities: NodeCapabilities,
+        providers: Vec<(String, Arc<dyn EthereumAdapter>)>,
+    ) -> Self {
+        assert!(
+            !providers.is_empty(),
+            "a provider set needs at least one provider"
+        );
+        let name = format!("{}:{}", network, capabilities).replace(' ', "");
+        ProviderSet {
+            network,
+            capabilities,
+            name,
+            providers: RwLock::new(
+                providers
+                    .into_iter()
+                    .map(|(url, adapter)| Provider::new(url, adapter))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    pub fn capabilities(&self) -> NodeCapabilities {
+        self.capabilities
+    }
+
+    /// The URLs of the providers in the set, in the order in which they
+    /// are tried
+    pub fn urls(&self) -> Vec<String> {
+        self.providers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|provider| provider.url.clone())
+            .collect()
+    }
+
+    /// Make the set consist of the providers with `urls`, in that order.
+    /// Providers that are in the set already keep their adapter and their
+    /// health, the others must be in `added`, and providers that are not in
+    /// `urls` stop getting requests. Nothing is changed if that would leave
+    /// the set without providers
+    pub fn update(
+        &self,
+        logger: &Logger,
+        urls: &[String],
+        mut added: Vec<(String, Arc<dyn EthereumAdapter>)>,
+    ) -> Result<(), Error> {
+        let mut providers = self.providers.write().unwrap();
+        if urls.is_empty() {
+            return Err(format_err!(
+                "this would remove all providers of `{}`",
+                self.name
+            ));
+        }
+        if let Some(url) = urls.iter().find(|url| {
+            !providers.iter().any(|provider| &provider.url == *url)
+                && !added.iter().any(|(added, _)| added == *url)
+        }) {
+            return Err(format_err!(
+                "there is no adapter for provider `{}` of `{}`",
+                url,
+                self.name
+            ));
+        }
+
+        let mut old = std::mem::take(&mut *providers);
+        for url in urls {
+            match old.iter().position(|provider| &provider.url == url) {
+                Some(pos) => providers.push(old.remove(pos)),
+                None => {
+                    let pos = added.iter().position(|(added, _)| added == url).unwrap();
+                    let (url, adapter) = added.remove(pos);
+                    info!(logger, "Added Ethereum provider";
+                          "provider_set" => &self.name,
+                          "provider" => adapter.url_hostname());
+                    providers.push(Provider::new(url, adapter));
+                }
+            }
+        }
+        for provider in old {
+            provider.removed.store(true, Ordering::SeqCst);
+            info!(logger, "Removed Ethereum provider";
+                  "provider_set" => &self.name,
+                  "provider" => provider.adapter.url_hostname());
+        }
+        Ok(())
+    }
+
+    /// The provider that gets the next request: the first one that is
+    /// healthy, or the first one if none of them are
+    fn provider(
+        &self,
+    ) -> (
+        Arc<dyn EthereumAdapter>,
+        Arc<AtomicBool>,
+        Arc<ProviderHealth>,
+    ) {
+        let providers = self.providers.read().unwrap();
+        let now = Instant::now();
+        let provider = providers
+            .iter()
+            .find(|provider| provider.health.is_healthy(now))
+            .unwrap_or(&providers[0]);
+        (
+            provider.adapter.clone(),
+            provider.removed.clone(),
+            provider.health.clone(),
+        )
+    }
+
+    fn adapter(&self) -> (Arc<dyn EthereumAdapter>, Arc<ProviderHealth>) {
+        let (adapter, _, health) = self.provider();
+        (adapter, health)
+    }
+}
+
+impl Provider {
+    fn new(url: String, adapter: Arc<dyn EthereumAdapter>) -> Self {
+        Provider {
+            url,
+            adapter,
+            removed: Arc::new(AtomicBool::new(false)),
+            health: Arc::new(ProviderHealth::default()),
+        }
+    }
+}
+
+impl ProviderHealth {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.failed_at.lock().unwrap() {
+            Some(failed_at) => now.saturating_duration_since(failed_at) >= FAILED_PROVIDER_RETRY,
+            None => true,
+        }
+    }
+
+    fn record(&self, ok: bool) {
+        *self.failed_at.lock().unwrap() = if ok { None } else { Some(Instant::now()) };
+    }
+
+    /// Record whether `future` succeeds, counting only errors for which
+    /// `failed` is true against the provider
+    fn watch_with<F>(
+        self: Arc<Self>,
+        future: F,
+        failed: fn(&F::Error) -> bool,
+    ) -> impl Future<Item = F::Item, Error = F::Error> + Send + Unpin
+    where
+        F: Future + Send + Unpin,
+        F::Item: Send,
+        F::Error: Send,
+    {
+        future.then(move |result| {
+            self.record(result.as_ref().err().map_or(true, |e| !failed(e)));
+            result
+        })
+    }
+
+    fn watch<F>(
+        self: Arc<Self>,
+        future: F,
+    ) -> impl Future<Item = F::Item, Error = F::Error> + Send + Unpin
+    where
+        F: Future + Send + Unpin,
+        F::Item: Send,
+        F::Error: Send,
+    {
+        self.watch_with(future, |_| true)
+    }
+
+    fn watch_stream<S>(
+        self: Arc<Self>,
+        stream: S,
+    ) -> impl Stream<Item = S::Item, Error = S::Error> + Send
+    where
+        S: Stream + Send,
+        S::Item: Send,
+        S::Error: Send,
+    {
+        stream.then(move |result| {
+            self.record(result.is_ok());
+            result
+        })
+    }
+}
+
+/// The changes that reloading the providers file makes to one set
+#[derive(Debug)]
+pub struct ProviderSetChange {
+    pub set: Arc<ProviderSet>,
+    /// The URLs of the providers of the set after the reload, in the order
+    /// in which they are tried
+    pub urls: Vec<String>,
+    /// The URLs of the providers that are removed
+    pub removed: Vec<String>,
+    /// The providers that are added
+    pub added: Vec<ProviderConfig>,
+}
+
+/// Compare the providers in `configs` with the providers in `sets` and
+/// find out what needs to change in each set. A reload that would need a
+/// new set or that would leave a set without providers is rejected as a
+/// whole
+pub fn plan_reload(
+    sets: &[Arc<ProviderSet>],
+    configs: &[ProviderConfig],
+) -> Result<Vec<ProviderSetChange>, Error> {
+    if let Some(config) = configs.iter().find(|config| {
+        !sets
+            .iter()
+            .any(|set| set.network == config.network && set.capabilities == config.capabilities)
+    }) {
+        return Err(format_err!(
+            "there are no providers for network `{}` with capabilities `{}` yet; \
+             adding them needs a restart",
+            config.network,
+            config.capabilities
+        ));
+    }
+
+    let mut changes = Vec::new();
+    for set in sets {
+        let urls = set.urls();
+        let mut wanted: Vec<&ProviderConfig> = Vec::new();
+        for config in configs.iter().filter(|config| {
+            config.network == set.network && config.capabilities == set.capabilities
+        }) {
+            if !wanted.iter().any(|wanted| wanted.url == config.url) {
+                wanted.push(config);
+            }
+        }
+        if wanted.is_empty() {
+            return Err(format_err!(
+                "the reload would remove all providers for network `{}` with \
+                 capabilities `{}`",
+                set.network,
+                set.capabilities
+            ));
+        }
+
+        let removed: Vec<_> = urls
+            .iter()
+            .filter(|url| !wanted.iter().any(|config| &&config.url == url))
+            .cloned()
+            .collect();
+        let added: Vec<_> = wanted
+            .iter()
+            .filter(|config| !urls.contains(&config.url))
+            .map(|config| (*config).clone())
+            .collect();
+        let wanted: Vec<_> = wanted
+            .into_iter()
+            .map(|config| config.url.clone())
+            .collect();
+        if wanted != urls {
+            changes.push(ProviderSetChange {
+                set: set.clone(),
+                urls: wanted,
+                removed,
+                added,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+impl EthereumAdapter for ProviderSet {
+    fn url_hostname(&self) -> &str {
+        &self.name
+    }
+
+    fn net_identifiers(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.net_identifiers(logger)))
+    }
+
+    fn latest_block(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.latest_block(logger)))
+    }
+
+    fn latest_block_header(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.latest_block_header(logger)))
+    }
+
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>> {
+        // End the subscription once the provider is removed so that the
+        // subscriber subscribes again, to a provider that is still in the set
+        let (adapter, removed, _) = self.provider();
+        adapter.new_heads().map(|heads| {
+            Box::new(heads.take_while(move |_| Ok(!removed.load(Ordering::SeqCst))))
+                as Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>
+        })
+    }
+
+    fn load_block(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.load_block(logger, block_hash)))
+    }
+
+    fn load_blocks(
+        &self,
+        logger: Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch_stream(adapter.load_blocks(logger, chain_store, block_hashes)))
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        logger: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_range_to_ptrs(logger, from, to)))
+    }
+
+    fn block_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_by_hash(logger, block_hash)))
+    }
+
+    fn block_by_number(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_by_number(logger, block_number)))
+    }
+
+    fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_transaction_count(logger, block_hash)))
+    }
+
+    fn load_full_block(
+        &self,
+        logger: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.load_full_block(logger, block)))
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_pointer_from_number(logger, chain_store, block_number)))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+        block_is_final: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.block_hash_by_block_number(
+            logger,
+            chain_store,
+            block_number,
+            block_is_final,
+        )))
+    }
+
+    fn uncles(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.uncles(logger, block)))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        logger: &Logger,
+        metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.is_on_main_chain(logger, metrics, chain_store, block_ptr)))
+    }
+
+    fn calls_in_block(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch(adapter.calls_in_block(
+            logger,
+            subgraph_metrics,
+            block_number,
+            block_hash,
+        )))
+    }
+
+    fn logs_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let (adapter, health) = self.adapter();
+        Box::pin(
+            adapter
+                .logs_in_block_range(logger, subgraph_metrics, from, to, log_filter)
+                .inspect(move |result| health.record(result.is_ok())),
+        )
+    }
+
+    fn calls_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(health.watch_stream(adapter.calls_in_block_range(
+            logger,
+            subgraph_metrics,
+            from,
+            to,
+            call_filter,
+        )))
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        let (adapter, health) = self.adapter();
+        Box::new(
+            health.watch_with(adapter.contract_call(logger, call, cache), |e| {
+                matches!(
+                    e,
+                    EthereumContractCallError::Web3Error(_) | EthereumContractCallError::Timeout
+                )
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockEthereumAdapter;
+    use std::sync::atomic::AtomicUsize;
+
+    const ARCHIVE: NodeCapabilities = NodeCapabilities {
+        archive: true,
+        traces: false,
+    };
+
+    /// A provider that counts its requests, and answers each of them after
+    /// `delay` with a network identifier whose `net_version` is `url`
+    fn provider(url: &str, delay: Duration) -> (Arc<dyn EthereumAdapter>, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests2 = requests.clone();
+        let version = url.to_owned();
+        let mut provider = MockEthereumAdapter::new();
+        provider.expect_url_hostname().return_const(url.to_owned());
+        provider.expect_net_identifiers().returning(move |_| {
+            requests2.fetch_add(1, Ordering::SeqCst);
+            let version = version.clone();
+            Box::new(
+                async move {
+                    tokio::time::delay_for(delay).await;
+                    Ok(EthereumNetworkIdentifier {
+                        net_version: version,
+                        genesis_block_hash: H256::zero(),
+                    })
+                }
+                .boxed()
+                .compat(),
+            )
+        });
+        (Arc::new(provider), requests)
+    }
+
+    /// A provider that fails all requests, and counts them
+    fn failing_provider(url: &str) -> (Arc<dyn EthereumAdapter>, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let requests2 = requests.clone();
+        let mut provider = MockEthereumAdapter::new();
+        provider.expect_url_hostname().return_const(url.to_owned());
+        provider.expect_net_identifiers().returning(move |_| {
+            requests2.fetch_add(1, Ordering::SeqCst);
+            Box::new(future::err(format_err!("connection refused")))
+        });
+        (Arc::new(provider), requests)
+    }
+
+    fn config(network: &str, url: &str) -> ProviderConfig {
+        ProviderConfig {
+            network: network.to_owned(),
+            capabilities: ARCHIVE,
+            url: url.to_owned(),
+        }
+    }
+
+    async fn version(set: &ProviderSet) -> String {
+        let logger = crate::log::logger(false);
+        set.net_identifiers(&logger)
+            .compat()
+            .await
+            .unwrap()
+            .net_version
+    }
+
+    #[tokio::test]
+    async fn swap_providers_under_load() {
+        let logger = crate::log::logger(false);
+        let delay = Duration::from_millis(20);
+        let (old, old_requests) = provider("old", delay);
+        let (kept, _) = provider("kept", delay);
+        let set = Arc::new(ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("old".to_owned(), old), ("kept".to_owned(), kept)],
+        ));
+
+        // Keep a steady stream of requests going while the providers change
+        let load = (0..8)
+            .map(|_| {
+                let set = set.clone();
+                tokio::spawn(async move {
+                    let mut answers = Vec::new();
+                    while answers.len() < 50 {
+                        answers.push(version(&set).await);
+                    }
+                    answers
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // A request that the old provider is working on when it is removed
+        let in_flight = set.net_identifiers(&logger).compat();
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        let (new, new_requests) = provider("new", delay);
+        let changes = plan_reload(
+            &[set.clone()],
+            &[config("mainnet", "new"), config("mainnet", "kept")],
+        )
+        .unwrap();
+        assert_eq!(1, changes.len());
+        assert_eq!(vec!["new".to_owned(), "kept".to_owned()], changes[0].urls);
+        assert_eq!(vec!["old".to_owned()], changes[0].removed);
+        assert_eq!(vec![config("mainnet", "new")], changes[0].added);
+        set.update(&logger, &changes[0].urls, vec![("new".to_owned(), new)])
+            .unwrap();
+        let old_count = old_requests.load(Ordering::SeqCst);
+
+        // The request that was in flight finishes with the old provider
+        assert_eq!("old", in_flight.await.unwrap().net_version);
+
+        let mut answers = Vec::new();
+        for task in load {
+            answers.extend(task.await.unwrap());
+        }
+
+        // No requests went to the old provider after it was removed, and the
+        // new provider, which comes first now, got requests right away
+        assert_eq!(old_count, old_requests.load(Ordering::SeqCst));
+        assert!(new_requests.load(Ordering::SeqCst) > 0);
+        assert!(answers.iter().any(|answer| answer == "old"));
+        assert!(answers.iter().any(|answer| answer == "new"));
+        assert_eq!(vec!["new".to_owned(), "kept".to_owned()], set.urls());
+        for _ in 0..4 {
+            assert_eq!("new", version(&set).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn providers_are_tried_in_order() {
+        let logger = crate::log::logger(false);
+        let (first, first_requests) = provider("first", Duration::from_millis(0));
+        let (second, second_requests) = provider("second", Duration::from_millis(0));
+        let set = ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("first".to_owned(), first), ("second".to_owned(), second)],
+        );
+
+        // All requests go to the first provider while it is healthy
+        for _ in 0..4 {
+            assert_eq!("first", version(&set).await);
+        }
+        assert_eq!(4, first_requests.load(Ordering::SeqCst));
+        assert_eq!(0, second_requests.load(Ordering::SeqCst));
+
+        // Once a provider fails, the retry goes to the next one
+        let (failing, failing_requests) = failing_provider("failing");
+        let (healthy, _) = provider("healthy", Duration::from_millis(0));
+        let set = ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![
+                ("failing".to_owned(), failing),
+                ("healthy".to_owned(), healthy),
+            ],
+        );
+        assert!(set.net_identifiers(&logger).compat().await.is_err());
+        for _ in 0..4 {
+            assert_eq!("healthy", version(&set).await);
+        }
+        assert_eq!(1, failing_requests.load(Ordering::SeqCst));
+
+        // When all providers failed, requests go to the first one again
+        let (a, a_requests) = failing_provider("a");
+        let (b, b_requests) = failing_provider("b");
+        let set = ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("a".to_owned(), a), ("b".to_owned(), b)],
+        );
+        for _ in 0..3 {
+            assert!(set.net_identifiers(&logger).compat().await.is_err());
+        }
+        assert_eq!(2, a_requests.load(Ordering::SeqCst));
+        assert_eq!(1, b_requests.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn reordered_providers_are_changed_in_place() {
+        let logger = crate::log::logger(false);
+        let (a, _) = provider("a", Duration::from_millis(0));
+        let (b, _) = provider("b", Duration::from_millis(0));
+        let set = Arc::new(ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("a".to_owned(), a), ("b".to_owned(), b)],
+        ));
+
+        let changes = plan_reload(
+            &[set.clone()],
+            &[config("mainnet", "b"), config("mainnet", "a")],
+        )
+        .unwrap();
+        assert_eq!(1, changes.len());
+        assert!(changes[0].removed.is_empty());
+        assert!(changes[0].added.is_empty());
+        set.update(&logger, &changes[0].urls, vec![]).unwrap();
+        assert_eq!(vec!["b".to_owned(), "a".to_owned()], set.urls());
+    }
+
+    #[test]
+    fn changed_url_is_removed_and_added() {
+        let (a, _) = provider("a", Duration::from_millis(0));
+        let set = Arc::new(ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("https://a/key1".to_owned(), a)],
+        ));
+
+        let changes = plan_reload(&[set.clone()], &[config("mainnet", "https://a/key2")]).unwrap();
+        assert_eq!(vec!["https://a/key1".to_owned()], changes[0].removed);
+        assert_eq!(vec![config("mainnet", "https://a/key2")], changes[0].added);
+
+        // Nothing changes when the file is the same
+        assert!(
+            plan_reload(&[set.clone()], &[config("mainnet", "https://a/key1")])
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn reloads_that_need_a_restart_are_rejected() {
+        let logger = crate::log::logger(false);
+        let (a, _) = provider("a", Duration::from_millis(0));
+        let set = Arc::new(ProviderSet::new(
+            "mainnet".to_owned(),
+            ARCHIVE,
+            vec![("a".to_owned(), a)],
+        ));
+
+        // A network the node does not index from yet
+        assert!(plan_reload(
+            &[set.clone()],
+            &[config("mainnet", "a"), config("ropsten", "b")]
+        )
+        .is_err());
+        // Capabilities that no provider of the network had at startup
+        let mut traces = config("mainnet", "b");
+        traces.capabilities.traces = true;
+        assert!(plan_reload(&[set.clone()], &[config("mainnet", "a"), traces]).is_err());
+        // Removing all providers of a set
+        assert!(plan_reload(&[set.clone()], &[]).is_err());
+        assert!(set.update(&logger, &[], vec![]).is_err());
+        // A provider without an adapter
+        assert!(set.update(&logger, &["b".to_owned()], vec![]).is_err());
+        assert_eq!(vec!["a".to_owned()], set.urls());
+    }
+}
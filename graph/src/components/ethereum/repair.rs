@@ -0,0 +1,144 @@
+//! Repairing gaps in the blocks that a `ChainStore` has cached, which
+//! provider incidents can leave behind and which make subgraphs that sync
+//! through the affected range fail with "block not found"
+use lazy_static::lazy_static;
+
+use super::adapter::{provider_id, EthereumAdapter};
+use crate::prelude::*;
+
+lazy_static! {
+    /// The most blocks that one repair may look at; larger ranges have to
+    /// be repaired in several steps
+    static ref CHAIN_REPAIR_MAX_BLOCKS: u64 = std::env::var("GRAPH_CHAIN_REPAIR_MAX_BLOCKS")
+        .unwrap_or("100000".into())
+        .parse::<u64>()
+        .expect("invalid GRAPH_CHAIN_REPAIR_MAX_BLOCKS env var");
+
+    /// How many blocks to request from the provider at once
+    static ref BLOCK_BATCH_SIZE: usize = std::env::var("ETHEREUM_BLOCK_BATCH_SIZE")
+        .unwrap_or("10".into())
+        .parse::<usize>()
+        .expect("invalid ETHEREUM_BLOCK_BATCH_SIZE env var");
+}
+
+/// What `repair_chain` did; each list contains block numbers
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ChainRepair {
+    /// The number of gaps that were found
+    pub gaps: usize,
+    /// Blocks that were fetched from the provider and stored
+    pub repaired: Vec<u64>,
+    /// Blocks that the provider did not have
+    pub unavailable: Vec<u64>,
+    /// Blocks that the provider returned but that were not stored since
+    /// they are not the parent of the blocks we already have after them
+    pub mismatched: Vec<u64>,
+}
+
+/// Find the gaps in the blocks between `from` and `to` with
+/// `ChainStore::find_gaps` and fill them with blocks from `adapter`: the
+/// block for each missing height, and the parent of each block whose
+/// parent is missing. We only store a block if it actually is the parent
+/// of the blocks we already have for the next height.
+///
+/// The range may contain at most `GRAPH_CHAIN_REPAIR_MAX_BLOCKS` blocks, and
+/// blocks are requested from `adapter` `ETHEREUM_BLOCK_BATCH_SIZE` at a time
+pub async fn repair_chain(
+    logger: &Logger,
+    chain_store: Arc<dyn ChainStore>,
+    adapter: Arc<dyn EthereumAdapter>,
+    from: u64,
+    to: u64,
+) -> Result<ChainRepair, Error> {
+    if to < from {
+        return Err(format_err!(
+            "the range from {} to {} is empty; `to` must not be less than `from`",
+            from,
+            to
+        ));
+    }
+    if to - from >= *CHAIN_REPAIR_MAX_BLOCKS {
+        return Err(format_err!(
+            "the range from {} to {} contains {} blocks, but at most {} blocks can be \
+             repaired at once",
+            from,
+            to,
+            to - from + 1,
+            *CHAIN_REPAIR_MAX_BLOCKS
+        ));
+    }
+
+    let gaps = chain_store.find_gaps(from, to)?;
+    info!(logger, "Repairing block cache";
+          "from" => from, "to" => to, "gaps" => gaps.len());
+
+    let mut repair = ChainRepair {
+        gaps: gaps.len(),
+        ..Default::default()
+    };
+    for batch in gaps.chunks(*BLOCK_BATCH_SIZE) {
+        let blocks = futures03::future::try_join_all(
+            batch
+                .iter()
+                .map(|gap| fetch_block(logger, adapter.as_ref(), gap)),
+        )
+        .await?;
+
+        for (number, block) in blocks {
+            let block = match block {
+                Some(block) if block.number.map(|n| n.as_u64()) == Some(number) => block,
+                _ => {
+                    debug!(logger, "Provider does not have block"; "number" => number);
+                    repair.unavailable.push(number);
+                    continue;
+                }
+            };
+            let hash = block
+                .hash
+                .ok_or_else(|| format_err!("block {} from provider has no hash", number))?;
+
+            let children = chain_store.block_hashes_and_parents_by_block_number(number + 1)?;
+            if !children.is_empty() && !children.iter().any(|(_, parent)| parent == &hash) {
+                warn!(logger, "Block from provider is not the parent of any stored block";
+                      "number" => number, "hash" => format!("{:x}", hash));
+                repair.mismatched.push(number);
+                continue;
+            }
+
+            chain_store.upsert_light_blocks(&provider_id(adapter.url_hostname()), vec![block])?;
+            repair.repaired.push(number);
+        }
+    }
+
+    info!(logger, "Repaired block cache";
+          "from" => from,
+          "to" => to,
+          "gaps" => repair.gaps,
+          "repaired" => repair.repaired.len(),
+          "unavailable" => repair.unavailable.len(),
+          "mismatched" => repair.mismatched.len());
+    Ok(repair)
+}
+
+/// The number of the block that fills `gap`, and that block if the
+/// provider has it
+async fn fetch_block(
+    logger: &Logger,
+    adapter: &dyn EthereumAdapter,
+    gap: &BlockGap,
+) -> Result<(u64, Option<LightEthereumBlock>), Error> {
+    match gap {
+        BlockGap::Missing(number) => Ok((
+            *number,
+            adapter.block_by_number(logger, *number).compat().await?,
+        )),
+        BlockGap::BrokenLink {
+            number,
+            parent_hash,
+            ..
+        } => Ok((
+            number - 1,
+            adapter.block_by_hash(logger, *parent_hash).compat().await?,
+        )),
+    }
+}
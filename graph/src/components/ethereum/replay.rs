@@ -0,0 +1,1441 @@
+//! Recording and replaying the requests a deployment makes to Ethereum.
+//!
+//! When a deployment is listed in `GRAPH_ETHEREUM_RECORD`, the adapters its
+//! mappings and its block stream use are wrapped in a
+//! `RecordingEthereumAdapter` that appends every contract call, block
+//! fetch and scan of a block range for logs and calls, together with the
+//! response, to `<GRAPH_ETHEREUM_RECORDING_DIR>/<deployment>.jsonl`. A
+//! deployment listed in `GRAPH_ETHEREUM_REPLAY` instead uses a
+//! `ReplayEthereumAdapter` that answers exclusively from that file, which
+//! makes it possible to re-run a failing block without access to an
+//! Ethereum node.
+//!
+//! Replaying blocks to verify proofs of indexing uses `call_cache_networks`
+//! instead, whose adapters answer contract calls from the call cache only.
+use ethabi::Token;
+use futures::Future;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::marker::Unpin;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use web3::types::{Address, Block, Bytes, Log, H256};
+
+use super::adapter::*;
+use super::network::{EthereumNetworks, NodeCapabilities};
+use super::types::*;
+use crate::prelude::*;
+
+lazy_static! {
+    /// Deployments whose Ethereum requests should be recorded
+    static ref RECORD_DEPLOYMENTS: HashSet<String> = deployment_list("GRAPH_ETHEREUM_RECORD");
+
+    /// Deployments that should be served from a recording instead of an
+    /// Ethereum node
+    static ref REPLAY_DEPLOYMENTS: HashSet<String> = deployment_list("GRAPH_ETHEREUM_REPLAY");
+
+    static ref RECORDING_DIR: PathBuf = std::env::var("GRAPH_ETHEREUM_RECORDING_DIR")
+        .unwrap_or_else(|_| String::from("recordings"))
+        .into();
+
+    /// All hosts of a deployment share one recorder, and one recording when
+    /// replaying, so that they append to and read from the same file
+    static ref RECORDERS: Mutex<HashMap<SubgraphDeploymentId, Arc<Recorder>>> =
+        Mutex::new(HashMap::new());
+    static ref RECORDINGS: Mutex<HashMap<SubgraphDeploymentId, Arc<Recording>>> =
+        Mutex::new(HashMap::new());
+}
+
+fn deployment_list(var: &str) -> HashSet<String> {
+    std::env::var(var)
+        .map(|ids| {
+            ids.split(',')
+                .map(|id| id.trim().to_owned())
+                .filter(|id| !id.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Load the recordings of all deployments in `GRAPH_ETHEREUM_REPLAY` so that
+/// a missing or malformed recording stops the node at startup rather than
+/// when the deployment starts, and log which deployments are recorded and
+/// which are replayed
+pub fn init(logger: &Logger) -> Result<(), Error> {
+    for id in REPLAY_DEPLOYMENTS.iter() {
+        let deployment = SubgraphDeploymentId::new(id.as_str()).map_err(|id| {
+            format_err!(
+                "GRAPH_ETHEREUM_REPLAY contains an invalid deployment: {}",
+                id
+            )
+        })?;
+        let path = RECORDING_DIR.join(format!("{}.jsonl", deployment));
+        recording(&deployment)
+            .map_err(|e| format_err!("failed to load the recording `{}`: {}", path.display(), e))?;
+        info!(logger, "Replaying Ethereum requests from a recording";
+              "deployment" => &deployment.to_string(),
+              "recording" => path.display().to_string());
+    }
+    for id in RECORD_DEPLOYMENTS.iter() {
+        info!(logger, "Recording Ethereum requests";
+              "deployment" => id,
+              "recording" => RECORDING_DIR.join(format!("{}.jsonl", id)).display().to_string());
+    }
+    Ok(())
+}
+
+/// The recording of `deployment`, which is loaded on first use
+fn recording(deployment: &SubgraphDeploymentId) -> Result<Arc<Recording>, Error> {
+    let mut recordings = RECORDINGS.lock().unwrap();
+    match recordings.get(deployment) {
+        Some(recording) => Ok(recording.clone()),
+        None => {
+            let path = RECORDING_DIR.join(format!("{}.jsonl", deployment));
+            let recording = Arc::new(Recording::load(&path)?);
+            recordings.insert(deployment.clone(), recording.clone());
+            Ok(recording)
+        }
+    }
+}
+
+/// Wrap `adapter` in a recording or replaying adapter if the environment
+/// asks for that for `deployment`; otherwise, return `adapter` unchanged.
+/// The mappings and the block stream of a deployment each wrap their own
+/// adapter, but they share the recorder and the recording
+pub fn adapter_for_deployment(
+    deployment: &SubgraphDeploymentId,
+    adapter: Arc<dyn EthereumAdapter>,
+) -> Result<Arc<dyn EthereumAdapter>, Error> {
+    if REPLAY_DEPLOYMENTS.contains(deployment.as_str()) {
+        return Ok(Arc::new(ReplayEthereumAdapter::new(recording(deployment)?)));
+    }
+
+    if RECORD_DEPLOYMENTS.contains(deployment.as_str()) {
+        let mut recorders = RECORDERS.lock().unwrap();
+        let recorder = match recorders.get(deployment) {
+            Some(recorder) => recorder.clone(),
+            None => {
+                fs::create_dir_all(RECORDING_DIR.as_path())?;
+                let path = RECORDING_DIR.join(format!("{}.jsonl", deployment));
+                let recorder = Arc::new(Recorder::create(&path)?);
+                recorders.insert(deployment.clone(), recorder.clone());
+                recorder
+            }
+        };
+        return Ok(Arc::new(RecordingEthereumAdapter::new(adapter, recorder)));
+    }
+
+    Ok(adapter)
+}
+
+/// Networks with the same names as `networks` whose only adapter answers
+/// contract calls from the call cache and fails all other requests, for
+/// replaying blocks without an Ethereum node
+pub fn call_cache_networks(networks: &EthereumNetworks) -> EthereumNetworks {
+    let mut call_cache_networks = EthereumNetworks::new();
+    for name in networks.networks.keys() {
+        call_cache_networks.insert(
+            name.clone(),
+            NodeCapabilities {
+                archive: true,
+                traces: true,
+            },
+            Arc::new(ReplayEthereumAdapter::call_cache()),
+        );
+    }
+    call_cache_networks
+}
+
+/// The response to a contract call. Only deterministic outcomes are
+/// recorded; calls that fail for other reasons, e.g. a timeout, would be
+/// retried anyway.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum CallOutcome {
+    /// The ABI encoded return values
+    Output(Bytes),
+    Revert(EthereumCallRevert),
+}
+
+/// A request and its response, one per line of a recording
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "camelCase")]
+enum Entry {
+    #[serde(rename_all = "camelCase")]
+    ContractCall {
+        address: Address,
+        block_hash: H256,
+        block_number: u64,
+        data: Bytes,
+        outcome: CallOutcome,
+    },
+    #[serde(rename_all = "camelCase")]
+    BlockByHash {
+        hash: H256,
+        block: Option<LightEthereumBlock>,
+    },
+    #[serde(rename_all = "camelCase")]
+    BlockByNumber {
+        number: u64,
+        block: Option<LightEthereumBlock>,
+    },
+    #[serde(rename_all = "camelCase")]
+    BlockHashByNumber { number: u64, hash: Option<H256> },
+    #[serde(rename_all = "camelCase")]
+    BlockTransactionCount { hash: H256, count: u64 },
+    #[serde(rename_all = "camelCase")]
+    FullBlock { block: EthereumBlock },
+    /// The logs that matched the filter of the deployment in the blocks
+    /// `from..=to`
+    #[serde(rename_all = "camelCase")]
+    Logs { from: u64, to: u64, logs: Vec<Log> },
+    /// The calls that matched the filter of the deployment in the blocks
+    /// `from..=to`
+    #[serde(rename_all = "camelCase")]
+    Calls {
+        from: u64,
+        to: u64,
+        calls: Vec<EthereumCall>,
+    },
+    /// All calls in the block with hash `hash`
+    #[serde(rename_all = "camelCase")]
+    BlockCalls {
+        hash: H256,
+        calls: Vec<EthereumCall>,
+    },
+}
+
+/// Appends entries to a recording file. Every entry is written out right
+/// away so that nothing is lost when the node stops or crashes.
+pub struct Recorder {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format_err!("failed to open recording `{}`: {}", path.display(), e))?;
+        Ok(Recorder {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+        })
+    }
+
+    fn record(&self, logger: &Logger, entry: &Entry) {
+        let result = serde_json::to_string(entry)
+            .map_err(Error::from)
+            .and_then(|line| {
+                let mut file = self.file.lock().unwrap();
+                writeln!(file, "{}", line)?;
+                file.flush().map_err(Error::from)
+            });
+        if let Err(e) = result {
+            error!(logger, "Failed to record Ethereum request";
+                   "recording" => self.path.display().to_string(),
+                   "error" => e.to_string());
+        }
+    }
+}
+
+/// The requests and responses read from a recording file
+#[derive(Default)]
+pub struct Recording {
+    calls: HashMap<(Address, H256, Vec<u8>), CallOutcome>,
+    blocks_by_hash: HashMap<H256, Option<LightEthereumBlock>>,
+    blocks_by_number: HashMap<u64, Option<LightEthereumBlock>>,
+    block_hashes: HashMap<u64, Option<H256>>,
+    block_transaction_counts: HashMap<H256, u64>,
+    full_blocks: HashMap<H256, EthereumBlock>,
+    /// The recorded logs and calls of each block that was scanned for
+    /// them. A block that was scanned but had no matching logs or calls
+    /// maps to an empty list
+    logs: HashMap<u64, Vec<Log>>,
+    calls_by_number: HashMap<u64, Vec<EthereumCall>>,
+    block_calls: HashMap<H256, Vec<EthereumCall>>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)
+            .map_err(|e| format_err!("failed to open recording `{}`: {}", path.display(), e))?;
+        let mut recording = Recording::default();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line).map_err(|e| {
+                format_err!(
+                    "invalid entry on line {} of recording `{}`: {}",
+                    number + 1,
+                    path.display(),
+                    e
+                )
+            })?;
+            recording.add(entry);
+        }
+        Ok(recording)
+    }
+
+    fn add(&mut self, entry: Entry) {
+        match entry {
+            Entry::ContractCall {
+                address,
+                block_hash,
+                block_number: _,
+                data,
+                outcome,
+            } => {
+                self.calls.insert((address, block_hash, data.0), outcome);
+            }
+            Entry::BlockByHash { hash, block } => {
+                self.blocks_by_hash.insert(hash, block);
+            }
+            Entry::BlockByNumber { number, block } => {
+                self.blocks_by_number.insert(number, block);
+            }
+            Entry::BlockHashByNumber { number, hash } => {
+                self.block_hashes.insert(number, hash);
+            }
+            Entry::BlockTransactionCount { hash, count } => {
+                self.block_transaction_counts.insert(hash, count);
+            }
+            Entry::FullBlock { block } => {
+                if let Some(hash) = block.block.hash {
+                    self.full_blocks.insert(hash, block);
+                }
+            }
+            Entry::Logs { from, to, logs } => {
+                for number in from..=to {
+                    self.logs.entry(number).or_insert_with(|| {
+                        logs.iter()
+                            .filter(|log| log.block_number.map(|n| n.as_u64()) == Some(number))
+                            .cloned()
+                            .collect()
+                    });
+                }
+            }
+            Entry::Calls { from, to, calls } => {
+                for number in from..=to {
+                    self.calls_by_number.entry(number).or_insert_with(|| {
+                        calls
+                            .iter()
+                            .filter(|call| call.block_number == number)
+                            .cloned()
+                            .collect()
+                    });
+                }
+            }
+            Entry::BlockCalls { hash, calls } => {
+                self.block_calls.insert(hash, calls);
+            }
+        }
+    }
+
+    /// The recorded items of the blocks `from..=to` in `by_number` that
+    /// `matches` accepts, or an error naming the first block for which
+    /// nothing was recorded. This only finds everything a request asks for
+    /// if its filter matches no more than the filter during recording did
+    fn in_range<T: Clone>(
+        by_number: &HashMap<u64, Vec<T>>,
+        what: &str,
+        from: u64,
+        to: u64,
+        matches: impl Fn(&T) -> bool,
+    ) -> Result<Vec<T>, Error> {
+        let mut items = vec![];
+        for number in from..=to {
+            let recorded = by_number.get(&number).ok_or_else(|| {
+                format_err!(
+                    "the recording does not contain the {} of block number {}",
+                    what,
+                    number
+                )
+            })?;
+            items.extend(recorded.iter().filter(|item| matches(item)).cloned());
+        }
+        Ok(items)
+    }
+}
+
+/// An adapter that passes all requests through to another adapter and
+/// records contract calls and block fetches along with their responses.
+pub struct RecordingEthereumAdapter {
+    adapter: Arc<dyn EthereumAdapter>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingEthereumAdapter {
+    pub fn new(adapter: Arc<dyn EthereumAdapter>, recorder: Arc<Recorder>) -> Self {
+        RecordingEthereumAdapter { adapter, recorder }
+    }
+}
+
+impl EthereumAdapter for RecordingEthereumAdapter {
+    fn url_hostname(&self) -> &str {
+        self.adapter.url_hostname()
+    }
+
+    fn net_identifiers(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        self.adapter.net_identifiers(logger)
+    }
+
+    fn latest_block(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        self.adapter.latest_block(logger)
+    }
+
+    fn latest_block_header(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        self.adapter.latest_block_header(logger)
+    }
+
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>> {
+        self.adapter.new_heads()
+    }
+
+    fn load_block(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .load_block(&logger, block_hash)
+                .inspect(move |block| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockByHash {
+                            hash: block_hash,
+                            block: Some(block.clone()),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn load_blocks(
+        &self,
+        logger: Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .load_blocks(logger.clone(), chain_store, block_hashes)
+                .inspect(move |block| {
+                    if let Some(hash) = block.hash {
+                        recorder.record(
+                            &logger,
+                            &Entry::BlockByHash {
+                                hash,
+                                block: Some(block.clone()),
+                            },
+                        )
+                    }
+                }),
+        )
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        logger: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_range_to_ptrs(logger.clone(), from, to)
+                .inspect(move |ptrs| {
+                    for ptr in ptrs {
+                        recorder.record(
+                            &logger,
+                            &Entry::BlockHashByNumber {
+                                number: ptr.number,
+                                hash: Some(ptr.hash),
+                            },
+                        )
+                    }
+                }),
+        )
+    }
+
+    fn block_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_by_hash(&logger, block_hash)
+                .inspect(move |block| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockByHash {
+                            hash: block_hash,
+                            block: block.clone(),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn block_by_number(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_by_number(&logger, block_number)
+                .inspect(move |block| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockByNumber {
+                            number: block_number,
+                            block: block.clone(),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_transaction_count(&logger, block_hash)
+                .inspect(move |count| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockTransactionCount {
+                            hash: block_hash,
+                            count: *count,
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn load_full_block(
+        &self,
+        logger: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .load_full_block(&logger, block)
+                .inspect(move |block| {
+                    recorder.record(
+                        &logger,
+                        &Entry::FullBlock {
+                            block: block.clone(),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_pointer_from_number(&logger, chain_store, block_number)
+                .inspect(move |ptr| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockHashByNumber {
+                            number: ptr.number,
+                            hash: Some(ptr.hash),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+        block_is_final: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .block_hash_by_block_number(&logger, chain_store, block_number, block_is_final)
+                .inspect(move |hash| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockHashByNumber {
+                            number: block_number,
+                            hash: *hash,
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn uncles(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        self.adapter.uncles(logger, block)
+    }
+
+    fn is_on_main_chain(
+        &self,
+        logger: &Logger,
+        metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        self.adapter
+            .is_on_main_chain(logger, metrics, chain_store, block_ptr)
+    }
+
+    fn calls_in_block(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .calls_in_block(&logger, subgraph_metrics, block_number, block_hash)
+                .inspect(move |calls| {
+                    recorder.record(
+                        &logger,
+                        &Entry::BlockCalls {
+                            hash: block_hash,
+                            calls: calls.clone(),
+                        },
+                    )
+                }),
+        )
+    }
+
+    fn logs_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        self.adapter
+            .logs_in_block_range(&logger, subgraph_metrics, from, to, log_filter)
+            .inspect_ok(move |logs| {
+                recorder.record(
+                    &logger,
+                    &Entry::Logs {
+                        from,
+                        to,
+                        logs: logs.clone(),
+                    },
+                )
+            })
+            .boxed()
+    }
+
+    fn calls_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        // The calls are only recorded once the whole range was scanned, so
+        // that a partial scan never looks complete in the recording
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        Box::new(
+            self.adapter
+                .calls_in_block_range(&logger, subgraph_metrics, from, to, call_filter)
+                .collect()
+                .map(move |calls| {
+                    recorder.record(
+                        &logger,
+                        &Entry::Calls {
+                            from,
+                            to,
+                            calls: calls.clone(),
+                        },
+                    );
+                    stream::iter_ok(calls)
+                })
+                .flatten_stream(),
+        )
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        let data = match call.function.encode_input(&call.args) {
+            Ok(data) => data,
+            Err(e) => return Box::new(future::err(EthereumContractCallError::EncodingError(e))),
+        };
+        let logger = logger.clone();
+        let recorder = self.recorder.clone();
+        let address = call.address;
+        let block_ptr = call.block_ptr;
+        Box::new(
+            self.adapter
+                .contract_call(&logger, call, cache)
+                .then(move |result| {
+                    let outcome = match &result {
+                        Ok(tokens) => Some(CallOutcome::Output(Bytes(ethabi::encode(tokens)))),
+                        Err(EthereumContractCallError::Revert(reason)) => {
+                            Some(CallOutcome::Revert(reason.clone()))
+                        }
+                        Err(_) => None,
+                    };
+                    if let Some(outcome) = outcome {
+                        recorder.record(
+                            &logger,
+                            &Entry::ContractCall {
+                                address,
+                                block_hash: block_ptr.hash,
+                                block_number: block_ptr.number,
+                                data: Bytes(data),
+                                outcome,
+                            },
+                        );
+                    }
+                    result
+                }),
+        )
+    }
+}
+
+/// An adapter that serves requests exclusively from a recording, or from
+/// the call cache. Any request that is not in the recording fails with an
+/// error describing the request in full.
+pub struct ReplayEthereumAdapter {
+    recording: Arc<Recording>,
+    /// Whether to answer contract calls from the call cache that is passed
+    /// to `contract_call` instead of from the recording
+    call_cache: bool,
+}
+
+impl ReplayEthereumAdapter {
+    pub fn new(recording: Arc<Recording>) -> Self {
+        ReplayEthereumAdapter {
+            recording,
+            call_cache: false,
+        }
+    }
+
+    /// An adapter that answers contract calls from the call cache, exactly
+    /// like an adapter for an Ethereum node would for calls that are in the
+    /// cache, and fails all other requests
+    pub fn call_cache() -> Self {
+        ReplayEthereumAdapter {
+            recording: Arc::new(Recording::default()),
+            call_cache: true,
+        }
+    }
+
+    fn unsupported(method: &str) -> Error {
+        format_err!(
+            "`{}` is not available when replaying recorded Ethereum requests",
+            method
+        )
+    }
+
+    fn missing_block(hash: &H256) -> Error {
+        format_err!(
+            "the recording does not contain the block with hash {:?}",
+            hash
+        )
+    }
+
+    /// Answer `call` from `cache`. Outputs are decoded the same way as for
+    /// calls that an Ethereum node answers, so that empty outputs and
+    /// outputs that can not be decoded are reverts
+    fn cached_call(
+        call: &EthereumContractCall,
+        data: &[u8],
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Result<Vec<Token>, EthereumContractCallError> {
+        let output = match cache
+            .get_call(call.address, data, call.block_ptr)
+            .map_err(|e| {
+                EthereumContractCallError::NotRecorded(format!(
+                    "reading the call cache failed: {}",
+                    e
+                ))
+            })? {
+            Some(Ok(output)) => output,
+            Some(Err(revert)) => return Err(EthereumContractCallError::Revert(revert)),
+            None => {
+                return Err(EthereumContractCallError::NotRecorded(format!(
+                    "function `{}` of contract {:?} at block #{} ({:?}) with \
+                     arguments {:?} (call data 0x{}), which is not in the call cache",
+                    call.function.signature(),
+                    call.address,
+                    call.block_ptr.number,
+                    call.block_ptr.hash,
+                    call.args,
+                    hex::encode(data)
+                )))
+            }
+        };
+        if output.is_empty() {
+            return Err(EthereumContractCallError::Revert(
+                EthereumCallRevert::with_reason("empty response"),
+            ));
+        }
+        call.function.decode_output(&output).map_err(|e| {
+            EthereumContractCallError::Revert(EthereumCallRevert::with_reason(format!(
+                "failed to decode output: {}",
+                e
+            )))
+        })
+    }
+}
+
+impl EthereumAdapter for ReplayEthereumAdapter {
+    fn url_hostname(&self) -> &str {
+        "replay"
+    }
+
+    fn net_identifiers(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        Box::new(future::err(Self::unsupported("net_identifiers")))
+    }
+
+    fn latest_block(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        Box::new(future::err(EthereumAdapterError::Unknown(
+            Self::unsupported("latest_block"),
+        )))
+    }
+
+    fn latest_block_header(
+        &self,
+        _: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        Box::new(future::err(EthereumAdapterError::Unknown(
+            Self::unsupported("latest_block_header"),
+        )))
+    }
+
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>> {
+        None
+    }
+
+    fn load_block(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .blocks_by_hash
+                .get(&block_hash)
+                .cloned()
+                .flatten()
+                .ok_or_else(|| Self::missing_block(&block_hash)),
+        ))
+    }
+
+    fn load_blocks(
+        &self,
+        _: Logger,
+        _: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        let blocks: Vec<Result<LightEthereumBlock, Error>> = block_hashes
+            .iter()
+            .map(|hash| {
+                self.recording
+                    .blocks_by_hash
+                    .get(hash)
+                    .cloned()
+                    .flatten()
+                    .ok_or_else(|| Self::missing_block(hash))
+            })
+            .collect();
+        Box::new(stream::iter_result(blocks))
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        _: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        let ptrs = (from..=to)
+            .map(|number| {
+                self.recording
+                    .block_hashes
+                    .get(&number)
+                    .cloned()
+                    .flatten()
+                    .map(|hash| EthereumBlockPointer { hash, number })
+                    .ok_or_else(|| {
+                        format_err!(
+                            "the recording does not contain the hash of block number {}",
+                            number
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>();
+        Box::new(future::result(ptrs))
+    }
+
+    fn block_by_hash(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .blocks_by_hash
+                .get(&block_hash)
+                .cloned()
+                .ok_or_else(|| Self::missing_block(&block_hash)),
+        ))
+    }
+
+    fn block_by_number(
+        &self,
+        _: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .blocks_by_number
+                .get(&block_number)
+                .cloned()
+                .ok_or_else(|| {
+                    format_err!(
+                        "the recording does not contain the block with number {}",
+                        block_number
+                    )
+                }),
+        ))
+    }
+
+    fn block_transaction_count(
+        &self,
+        _: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .block_transaction_counts
+                .get(&block_hash)
+                .cloned()
+                .ok_or_else(|| {
+                    format_err!(
+                        "the recording does not contain the transaction count of the block with hash {:?}",
+                        block_hash
+                    )
+                }),
+        ))
+    }
+
+    fn load_full_block(
+        &self,
+        _: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        let hash = block.hash.unwrap_or_default();
+        Box::new(future::result(
+            self.recording
+                .full_blocks
+                .get(&hash)
+                .cloned()
+                .ok_or_else(|| {
+                    EthereumAdapterError::Unknown(format_err!(
+                        "the recording does not contain receipts for the block with hash {:?}",
+                        hash
+                    ))
+                }),
+        ))
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        Box::new(future::result(
+            self.recording
+                .block_hashes
+                .get(&block_number)
+                .cloned()
+                .flatten()
+                .map(|hash| EthereumBlockPointer {
+                    hash,
+                    number: block_number,
+                })
+                .ok_or_else(|| {
+                    EthereumAdapterError::Unknown(format_err!(
+                        "the recording does not contain the hash of block number {}",
+                        block_number
+                    ))
+                }),
+        ))
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        _: &Logger,
+        _: Arc<dyn ChainStore>,
+        block_number: u64,
+        _: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .block_hashes
+                .get(&block_number)
+                .cloned()
+                .ok_or_else(|| {
+                    format_err!(
+                        "the recording does not contain the hash of block number {}",
+                        block_number
+                    )
+                }),
+        ))
+    }
+
+    fn uncles(
+        &self,
+        _: &Logger,
+        _: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        Box::new(future::err(Self::unsupported("uncles")))
+    }
+
+    fn is_on_main_chain(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: Arc<dyn ChainStore>,
+        _: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        // A recording only ever contains blocks from the main chain
+        Box::new(future::ok(true))
+    }
+
+    fn calls_in_block(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        _: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        Box::new(future::result(
+            self.recording
+                .block_calls
+                .get(&block_hash)
+                .cloned()
+                .ok_or_else(|| {
+                    format_err!(
+                        "the recording does not contain the calls of the block with hash {:?}",
+                        block_hash
+                    )
+                }),
+        ))
+    }
+
+    fn logs_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let logs = Recording::in_range(&self.recording.logs, "logs", from, to, |log| {
+            log_filter.matches(log)
+        });
+        Box::pin(futures03::future::ready(logs))
+    }
+
+    fn calls_in_block_range(
+        &self,
+        _: &Logger,
+        _: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        match Recording::in_range(&self.recording.calls_by_number, "calls", from, to, |call| {
+            call_filter.matches(call)
+        }) {
+            Ok(calls) => Box::new(stream::iter_ok(calls)),
+            Err(e) => Box::new(stream::once(Err(e))),
+        }
+    }
+
+    fn contract_call(
+        &self,
+        _: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        let data = match call.function.encode_input(&call.args) {
+            Ok(data) => data,
+            Err(e) => return Box::new(future::err(EthereumContractCallError::EncodingError(e))),
+        };
+        if self.call_cache {
+            return Box::new(future::result(Self::cached_call(&call, &data, cache)));
+        }
+        let key = (call.address, call.block_ptr.hash, data);
+        let result = match self.recording.calls.get(&key) {
+            Some(CallOutcome::Output(output)) => call
+                .function
+                .decode_output(&output.0)
+                .map_err(EthereumContractCallError::from),
+            Some(CallOutcome::Revert(reason)) => {
+                Err(EthereumContractCallError::Revert(reason.clone()))
+            }
+            None => Err(EthereumContractCallError::NotRecorded(format!(
+                "function `{}` of contract {:?} at block #{} ({:?}) with \
+                 arguments {:?} (call data 0x{})",
+                call.function.signature(),
+                call.address,
+                call.block_ptr.number,
+                call.block_ptr.hash,
+                call.args,
+                hex::encode(&key.2)
+            ))),
+        };
+        Box::new(future::result(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::Contract;
+    use web3::types::U64;
+
+    struct NoCache;
+
+    impl EthereumCallCache for NoCache {
+        fn get_call(
+            &self,
+            _: Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+        ) -> Result<Option<Result<Vec<u8>, EthereumCallRevert>>, Error> {
+            Ok(None)
+        }
+
+        fn set_call(
+            &self,
+            _: &str,
+            _: Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &[u8],
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn set_call_revert(
+            &self,
+            _: &str,
+            _: Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &EthereumCallRevert,
+        ) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn cached_calls(
+            &self,
+            _: u64,
+            _: Option<Address>,
+            _: usize,
+        ) -> Result<Vec<CachedEthereumCall>, Error> {
+            Ok(vec![])
+        }
+    }
+
+    /// A call cache that knows the balance of owner 2 and that asking for
+    /// the balance of owner 3 reverts
+    struct BalanceCache;
+
+    impl EthereumCallCache for BalanceCache {
+        fn get_call(
+            &self,
+            _: Address,
+            encoded_call: &[u8],
+            _: EthereumBlockPointer,
+        ) -> Result<Option<Result<Vec<u8>, EthereumCallRevert>>, Error> {
+            Ok(match encoded_call.last() {
+                Some(2) => Some(Ok(ethabi::encode(&[Token::Uint(ethabi::Uint::from(42))]))),
+                Some(3) => Some(Err(EthereumCallRevert::with_reason("unknown owner"))),
+                _ => None,
+            })
+        }
+
+        fn set_call(
+            &self,
+            _: &str,
+            _: Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &[u8],
+        ) -> Result<(), Error> {
+            unreachable!("replaying never adds to the call cache")
+        }
+
+        fn set_call_revert(
+            &self,
+            _: &str,
+            _: Address,
+            _: &[u8],
+            _: EthereumBlockPointer,
+            _: &EthereumCallRevert,
+        ) -> Result<(), Error> {
+            unreachable!("replaying never adds to the call cache")
+        }
+
+        fn cached_calls(
+            &self,
+            _: u64,
+            _: Option<Address>,
+            _: usize,
+        ) -> Result<Vec<CachedEthereumCall>, Error> {
+            Ok(vec![])
+        }
+    }
+
+    /// `revert("unknown owner")`
+    const UNKNOWN_OWNER: &str = "08c379a0\
+        0000000000000000000000000000000000000000000000000000000000000020\
+        000000000000000000000000000000000000000000000000000000000000000d\
+        756e6b6e6f776e206f776e657200000000000000000000000000000000000000";
+
+    fn balance_of(owner: u64, block_ptr: EthereumBlockPointer) -> EthereumContractCall {
+        let abi = r#"[{
+            "type": "function",
+            "name": "balanceOf",
+            "stateMutability": "view",
+            "inputs": [{ "name": "owner", "type": "address" }],
+            "outputs": [{ "name": "balance", "type": "uint256" }]
+        }]"#;
+        let function = Contract::load(abi.as_bytes())
+            .unwrap()
+            .function("balanceOf")
+            .unwrap()
+            .clone();
+        EthereumContractCall {
+            address: Address::from_low_u64_be(1),
+            block_ptr,
+            function,
+            args: vec![Token::Address(Address::from_low_u64_be(owner))],
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let logger = Logger::root(slog::Discard, o!());
+        let path =
+            std::env::temp_dir().join(format!("ethereum-recording-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let block = LightEthereumBlock {
+            hash: Some(H256::from_low_u64_be(7)),
+            number: Some(U64::from(7)),
+            ..Default::default()
+        };
+        let block_ptr = EthereumBlockPointer::from(&block);
+
+        let mut mock = MockEthereumAdapter::new();
+        mock.expect_contract_call()
+            .returning(|_, call, _| match &call.args[0] {
+                Token::Address(owner) if *owner == Address::from_low_u64_be(2) => {
+                    Box::new(future::ok(vec![Token::Uint(ethabi::Uint::from(42))]))
+                }
+                _ => Box::new(future::err(EthereumContractCallError::Revert(
+                    EthereumCallRevert::from_data(hex::decode(UNKNOWN_OWNER).unwrap()),
+                ))),
+            });
+        let mock_block = block.clone();
+        mock.expect_block_by_hash()
+            .returning(move |_, _| Box::new(future::ok(Some(mock_block.clone()))));
+
+        let recorder = Arc::new(Recorder::create(&path).unwrap());
+        let recording = RecordingEthereumAdapter::new(Arc::new(mock), recorder);
+        let cache: Arc<dyn EthereumCallCache> = Arc::new(NoCache);
+
+        let recorded_balance = recording
+            .contract_call(&logger, balance_of(2, block_ptr), cache.clone())
+            .wait()
+            .unwrap();
+        assert_eq!(vec![Token::Uint(ethabi::Uint::from(42))], recorded_balance);
+        assert!(recording
+            .contract_call(&logger, balance_of(3, block_ptr), cache.clone())
+            .wait()
+            .is_err());
+        recording
+            .block_by_hash(&logger, block_ptr.hash)
+            .wait()
+            .unwrap();
+
+        let replay = ReplayEthereumAdapter::new(Arc::new(Recording::load(&path).unwrap()));
+        fs::remove_file(&path).unwrap();
+
+        let balance = replay
+            .contract_call(&logger, balance_of(2, block_ptr), cache.clone())
+            .wait()
+            .unwrap();
+        assert_eq!(recorded_balance, balance);
+        match replay
+            .contract_call(&logger, balance_of(3, block_ptr), cache.clone())
+            .wait()
+        {
+            Err(EthereumContractCallError::Revert(revert)) => {
+                assert_eq!("unknown owner", revert.reason);
+                assert_eq!(
+                    Some(hex::decode(UNKNOWN_OWNER).unwrap()),
+                    revert.data.map(|data| data.0)
+                );
+            }
+            other => panic!("expected a revert, got {:?}", other),
+        }
+        assert_eq!(
+            Some(block),
+            replay
+                .block_by_hash(&logger, block_ptr.hash)
+                .wait()
+                .unwrap()
+        );
+
+        // Requests that were never made while recording are errors
+        match replay
+            .contract_call(&logger, balance_of(4, block_ptr), cache)
+            .wait()
+        {
+            Err(EthereumContractCallError::NotRecorded(details)) => {
+                assert!(details.contains("balanceOf(address)"), "{}", details)
+            }
+            other => panic!("expected an unrecorded call, got {:?}", other),
+        }
+        assert!(replay
+            .block_by_hash(&logger, H256::from_low_u64_be(8))
+            .wait()
+            .is_err());
+    }
+
+    #[test]
+    fn replay_from_call_cache() {
+        let logger = Logger::root(slog::Discard, o!());
+        let block_ptr = EthereumBlockPointer {
+            hash: H256::from_low_u64_be(7),
+            number: 7,
+        };
+        let mut networks = EthereumNetworks::new();
+        networks.insert(
+            "mainnet".to_owned(),
+            NodeCapabilities {
+                archive: false,
+                traces: false,
+            },
+            Arc::new(MockEthereumAdapter::new()),
+        );
+        let networks = call_cache_networks(&networks);
+        let adapter = networks
+            .adapter_with_capabilities(
+                "mainnet".to_owned(),
+                &NodeCapabilities {
+                    archive: true,
+                    traces: true,
+                },
+            )
+            .unwrap();
+        let cache: Arc<dyn EthereumCallCache> = Arc::new(BalanceCache);
+
+        assert_eq!(
+            vec![Token::Uint(ethabi::Uint::from(42))],
+            adapter
+                .contract_call(&logger, balance_of(2, block_ptr), cache.clone())
+                .wait()
+                .unwrap()
+        );
+        match adapter
+            .contract_call(&logger, balance_of(3, block_ptr), cache.clone())
+            .wait()
+        {
+            Err(EthereumContractCallError::Revert(revert)) => {
+                assert_eq!("unknown owner", revert.reason)
+            }
+            other => panic!("expected a revert, got {:?}", other),
+        }
+        match adapter
+            .contract_call(&logger, balance_of(4, block_ptr), cache)
+            .wait()
+        {
+            Err(EthereumContractCallError::NotRecorded(details)) => {
+                assert!(details.contains("not in the call cache"), "{}", details)
+            }
+            other => panic!("expected a call that is not cached, got {:?}", other),
+        }
+
+        // Nothing but contract calls can be answered
+        assert!(adapter
+            .block_by_number(&logger, block_ptr.number)
+            .wait()
+            .is_err());
+    }
+}
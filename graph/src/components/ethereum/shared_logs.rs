@@ -0,0 +1,751 @@
+//! Sharing the logs that `eth_getLogs` requests return between the
+//! deployments of a network.
+//!
+//! Deployments that index the same contracts scan the chain for the same
+//! logs. The adapters of each network are therefore wrapped in a
+//! `SharedLogsEthereumAdapter`, and all wrapped adapters of a network use
+//! the same `SharedLogs`. It splits log requests into their `eth_getLogs`
+//! filters and into aligned chunks of `GRAPH_ETHEREUM_SHARED_LOGS_CHUNK_SIZE`
+//! blocks. A chunk that another request fetched in the last
+//! `GRAPH_ETHEREUM_SHARED_LOGS_TTL` seconds, or is fetching right now, is
+//! answered from the response to that request; consecutive chunks that
+//! nobody fetched yet are fetched with one request.
+//!
+//! Only chunks that are at least `ETHEREUM_REORG_THRESHOLD` blocks behind
+//! the chain head are shared since the logs of later blocks can still
+//! change. Requests for later blocks go straight to the provider.
+use ethabi::Token;
+use futures::Future;
+use futures03::future::{try_join_all, BoxFuture, Shared};
+use std::collections::{HashMap, HashSet};
+use std::marker::Unpin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use web3::types::{Block, Log, H256};
+
+use super::adapter::*;
+use super::network::EthereumNetworks;
+use super::types::*;
+use super::REORG_THRESHOLD;
+use crate::prelude::*;
+
+lazy_static! {
+    /// How long the logs of a chunk are shared with later requests. Setting
+    /// this to `0` turns sharing logs off
+    static ref SHARED_LOGS_TTL: Duration = std::env::var("GRAPH_ETHEREUM_SHARED_LOGS_TTL")
+        .ok()
+        .map(|s| s.parse::<u64>().unwrap_or_else(|_| {
+            panic!("invalid GRAPH_ETHEREUM_SHARED_LOGS_TTL: {}", s)
+        }))
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(60));
+
+    /// The number of blocks in each chunk of shared logs
+    static ref SHARED_LOGS_CHUNK_SIZE: u64 = std::env::var("GRAPH_ETHEREUM_SHARED_LOGS_CHUNK_SIZE")
+        .ok()
+        .map(|s| match s.parse::<u64>() {
+            Ok(size) if size > 0 => size,
+            _ => panic!("invalid GRAPH_ETHEREUM_SHARED_LOGS_CHUNK_SIZE: {}", s),
+        })
+        .unwrap_or(1000);
+}
+
+/// Wrap the adapters of every network in `networks` so that they share
+/// the logs they fetch with each other. Networks without a chain store are
+/// left alone, as are all networks if sharing is turned off.
+pub fn share_logs(
+    networks: &EthereumNetworks,
+    chain_stores: &HashMap<String, Arc<dyn ChainStore>>,
+    metrics: Arc<SharedLogsMetrics>,
+) -> EthereumNetworks {
+    if *SHARED_LOGS_TTL == Duration::from_secs(0) {
+        return networks.clone();
+    }
+
+    let mut shared_networks = EthereumNetworks::new();
+    let mut shared_logs = HashMap::new();
+    for (name, capabilities, adapter) in networks.flatten() {
+        let adapter = match chain_stores.get(&name) {
+            Some(chain_store) => {
+                let shared = shared_logs
+                    .entry(name.clone())
+                    .or_insert_with(|| {
+                        Arc::new(SharedLogs::new(
+                            name.clone(),
+                            chain_store.clone(),
+                            metrics.clone(),
+                            *SHARED_LOGS_CHUNK_SIZE,
+                            *SHARED_LOGS_TTL,
+                        ))
+                    })
+                    .clone();
+                Arc::new(SharedLogsEthereumAdapter::new(adapter, shared))
+                    as Arc<dyn EthereumAdapter>
+            }
+            None => adapter,
+        };
+        shared_networks.insert(name, capabilities, adapter);
+    }
+    shared_networks.sort();
+    shared_networks
+}
+
+/// How many chunks of logs were answered from earlier requests, and how
+/// many had to be fetched from a provider
+pub struct SharedLogsMetrics {
+    chunks: Box<CounterVec>,
+}
+
+impl SharedLogsMetrics {
+    pub fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let chunks = registry
+            .new_counter_vec(
+                "ethereum_shared_logs_chunks",
+                "Counts chunks of logs that were shared (`hit`) or fetched from a provider (`miss`)",
+                vec![String::from("network"), String::from("result")],
+            )
+            .expect("failed to create `ethereum_shared_logs_chunks` counter");
+        SharedLogsMetrics { chunks }
+    }
+
+    fn observe(&self, network: &str, hits: usize, misses: usize) {
+        self.chunks
+            .with_label_values(&[network, "hit"])
+            .inc_by(hits as f64);
+        self.chunks
+            .with_label_values(&[network, "miss"])
+            .inc_by(misses as f64);
+    }
+}
+
+type SharedResult = Result<Arc<Vec<Log>>, String>;
+
+/// One request to a provider, whose response is shared by all chunks that
+/// it covers
+struct Fetch {
+    id: u64,
+    started: Instant,
+    logs: Shared<BoxFuture<'static, SharedResult>>,
+}
+
+/// The logs for the blocks `first..=last` of one `eth_getLogs` filter
+struct Chunk {
+    first: u64,
+    last: u64,
+    fetch: Arc<Fetch>,
+}
+
+/// The chunks of logs of one network that are being fetched or were
+/// fetched recently, keyed by filter and chunk number
+pub struct SharedLogs {
+    network: String,
+    chain_store: Arc<dyn ChainStore>,
+    metrics: Arc<SharedLogsMetrics>,
+    chunk_size: u64,
+    ttl: Duration,
+    next_id: AtomicU64,
+    fetches: Mutex<HashMap<(EthGetLogsFilter, u64), Arc<Fetch>>>,
+}
+
+impl SharedLogs {
+    pub fn new(
+        network: String,
+        chain_store: Arc<dyn ChainStore>,
+        metrics: Arc<SharedLogsMetrics>,
+        chunk_size: u64,
+        ttl: Duration,
+    ) -> Self {
+        SharedLogs {
+            network,
+            chain_store,
+            metrics,
+            chunk_size,
+            ttl,
+            next_id: AtomicU64::new(0),
+            fetches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The last block of the last chunk that can not be reorged anymore
+    fn last_shared_block(&self) -> Result<Option<u64>, Error> {
+        let head = match self.chain_store.chain_head_ptr()? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let chunks = match head.number.checked_sub(*REORG_THRESHOLD) {
+            Some(final_block) => (final_block + 1) / self.chunk_size,
+            None => 0,
+        };
+        Ok((chunks * self.chunk_size).checked_sub(1))
+    }
+
+    /// Find the chunks covering `from..=to` for `filter`, starting a
+    /// request to `adapter` for each run of consecutive chunks that has
+    /// not been fetched yet
+    fn chunks(
+        &self,
+        adapter: &Arc<dyn EthereumAdapter>,
+        logger: &Logger,
+        subgraph_metrics: &Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        filter: EthGetLogsFilter,
+    ) -> Vec<Chunk> {
+        let mut fetches = self.fetches.lock().unwrap();
+        let now = Instant::now();
+        let ttl = self.ttl;
+        fetches.retain(|_, fetch| now.duration_since(fetch.started) < ttl);
+
+        let mut chunks = Vec::new();
+        let mut missing = Vec::new();
+        for number in from / self.chunk_size..=to / self.chunk_size {
+            match fetches.get(&(filter.clone(), number)) {
+                Some(fetch) => chunks.push(self.chunk(number, fetch.clone())),
+                None => missing.push(number),
+            }
+        }
+        self.metrics
+            .observe(&self.network, chunks.len(), missing.len());
+
+        let mut missing = missing.into_iter().peekable();
+        while let Some(start) = missing.next() {
+            let mut end = start;
+            while missing.peek() == Some(&(end + 1)) {
+                end = missing.next().unwrap();
+            }
+
+            let logs = adapter
+                .logs_in_block_range(
+                    logger,
+                    subgraph_metrics.cheap_clone(),
+                    start * self.chunk_size,
+                    (end + 1) * self.chunk_size - 1,
+                    EthereumLogFilter::from(filter.clone()),
+                )
+                .map_ok(Arc::new)
+                .map_err(|e| e.to_string())
+                .boxed()
+                .shared();
+            let fetch = Arc::new(Fetch {
+                id: self.next_id.fetch_add(1, Ordering::SeqCst),
+                started: now,
+                logs,
+            });
+            for number in start..=end {
+                fetches.insert((filter.clone(), number), fetch.clone());
+                chunks.push(self.chunk(number, fetch.clone()));
+            }
+        }
+        chunks
+    }
+
+    fn chunk(&self, number: u64, fetch: Arc<Fetch>) -> Chunk {
+        Chunk {
+            first: number * self.chunk_size,
+            last: (number + 1) * self.chunk_size - 1,
+            fetch,
+        }
+    }
+
+    /// Forget a failed request so that the next request for its chunks
+    /// tries again
+    fn forget(&self, id: u64) {
+        self.fetches
+            .lock()
+            .unwrap()
+            .retain(|_, fetch| fetch.id != id);
+    }
+
+    /// The logs for `filter` in the blocks `from..=to`, all of which must
+    /// be at or before the block that `last_shared_block` returns
+    fn logs(
+        self: &Arc<Self>,
+        adapter: &Arc<dyn EthereumAdapter>,
+        logger: &Logger,
+        subgraph_metrics: &Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        mut filter: EthGetLogsFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        // Deployments list contracts and events in different orders
+        filter.contracts.sort();
+        filter.event_signatures.sort();
+
+        let chunks = self.chunks(adapter, logger, subgraph_metrics, from, to, filter);
+        let this = self.cheap_clone();
+
+        async move {
+            let mut ids = HashSet::new();
+            let fetches = chunks
+                .iter()
+                .filter(|chunk| ids.insert(chunk.fetch.id))
+                .map(|chunk| {
+                    let fetch = chunk.fetch.cheap_clone();
+                    let this = this.cheap_clone();
+                    fetch.logs.clone().map(move |result| match result {
+                        Ok(logs) => Ok((fetch.id, logs)),
+                        Err(e) => {
+                            this.forget(fetch.id);
+                            Err(format_err!("{}", e))
+                        }
+                    })
+                });
+            let responses: HashMap<_, _> = try_join_all(fetches).await?.into_iter().collect();
+
+            // A request covers whole chunks, but must only contribute the
+            // logs of its own chunks in the requested range
+            let mut logs = Vec::new();
+            for chunk in chunks {
+                let first = chunk.first.max(from);
+                let last = chunk.last.min(to);
+                logs.extend(
+                    responses[&chunk.fetch.id]
+                        .iter()
+                        .filter(|log| {
+                            log.block_number
+                                .map_or(false, |n| first <= n.as_u64() && n.as_u64() <= last)
+                        })
+                        .cloned(),
+                );
+            }
+            Ok(logs)
+        }
+        .boxed()
+    }
+}
+
+/// An adapter that passes all requests through to another adapter, except
+/// for log requests, which it answers from the `SharedLogs` of its network
+/// where possible
+pub struct SharedLogsEthereumAdapter {
+    adapter: Arc<dyn EthereumAdapter>,
+    shared: Arc<SharedLogs>,
+}
+
+impl SharedLogsEthereumAdapter {
+    pub fn new(adapter: Arc<dyn EthereumAdapter>, shared: Arc<SharedLogs>) -> Self {
+        SharedLogsEthereumAdapter { adapter, shared }
+    }
+}
+
+impl EthereumAdapter for SharedLogsEthereumAdapter {
+    fn url_hostname(&self) -> &str {
+        self.adapter.url_hostname()
+    }
+
+    fn net_identifiers(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = EthereumNetworkIdentifier, Error = Error> + Send> {
+        self.adapter.net_identifiers(logger)
+    }
+
+    fn latest_block(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = EthereumAdapterError> + Send + Unpin>
+    {
+        self.adapter.latest_block(logger)
+    }
+
+    fn latest_block_header(
+        &self,
+        logger: &Logger,
+    ) -> Box<dyn Future<Item = Block<H256>, Error = EthereumAdapterError> + Send> {
+        self.adapter.latest_block_header(logger)
+    }
+
+    fn new_heads(&self) -> Option<Box<dyn Stream<Item = (), Error = EthereumAdapterError> + Send>> {
+        self.adapter.new_heads()
+    }
+
+    fn load_block(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = LightEthereumBlock, Error = Error> + Send> {
+        self.adapter.load_block(logger, block_hash)
+    }
+
+    fn load_blocks(
+        &self,
+        logger: Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_hashes: HashSet<H256>,
+    ) -> Box<dyn Stream<Item = LightEthereumBlock, Error = Error> + Send> {
+        self.adapter.load_blocks(logger, chain_store, block_hashes)
+    }
+
+    fn block_range_to_ptrs(
+        &self,
+        logger: Logger,
+        from: u64,
+        to: u64,
+    ) -> Box<dyn Future<Item = Vec<EthereumBlockPointer>, Error = Error> + Send> {
+        self.adapter.block_range_to_ptrs(logger, from, to)
+    }
+
+    fn block_by_hash(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        self.adapter.block_by_hash(logger, block_hash)
+    }
+
+    fn block_by_number(
+        &self,
+        logger: &Logger,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = Option<LightEthereumBlock>, Error = Error> + Send> {
+        self.adapter.block_by_number(logger, block_number)
+    }
+
+    fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = u64, Error = Error> + Send> {
+        self.adapter.block_transaction_count(logger, block_hash)
+    }
+
+    fn load_full_block(
+        &self,
+        logger: &Logger,
+        block: LightEthereumBlock,
+    ) -> Box<dyn Future<Item = EthereumBlock, Error = EthereumAdapterError> + Send> {
+        self.adapter.load_full_block(logger, block)
+    }
+
+    fn block_pointer_from_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+    ) -> Box<dyn Future<Item = EthereumBlockPointer, Error = EthereumAdapterError> + Send> {
+        self.adapter
+            .block_pointer_from_number(logger, chain_store, block_number)
+    }
+
+    fn block_hash_by_block_number(
+        &self,
+        logger: &Logger,
+        chain_store: Arc<dyn ChainStore>,
+        block_number: u64,
+        block_is_final: bool,
+    ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send> {
+        self.adapter
+            .block_hash_by_block_number(logger, chain_store, block_number, block_is_final)
+    }
+
+    fn uncles(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Box<dyn Future<Item = Vec<Option<Block<H256>>>, Error = Error> + Send> {
+        self.adapter.uncles(logger, block)
+    }
+
+    fn is_on_main_chain(
+        &self,
+        logger: &Logger,
+        metrics: Arc<SubgraphEthRpcMetrics>,
+        chain_store: Arc<dyn ChainStore>,
+        block_ptr: EthereumBlockPointer,
+    ) -> Box<dyn Future<Item = bool, Error = Error> + Send> {
+        self.adapter
+            .is_on_main_chain(logger, metrics, chain_store, block_ptr)
+    }
+
+    fn calls_in_block(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        block_number: u64,
+        block_hash: H256,
+    ) -> Box<dyn Future<Item = Vec<EthereumCall>, Error = Error> + Send> {
+        self.adapter
+            .calls_in_block(logger, subgraph_metrics, block_number, block_hash)
+    }
+
+    fn logs_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        log_filter: EthereumLogFilter,
+    ) -> DynTryFuture<'static, Vec<Log>, Error> {
+        let last_shared = match self.shared.last_shared_block() {
+            Ok(Some(last_shared)) if last_shared >= from => last_shared,
+            Ok(_) => {
+                return self.adapter.logs_in_block_range(
+                    logger,
+                    subgraph_metrics,
+                    from,
+                    to,
+                    log_filter,
+                )
+            }
+            Err(e) => return futures03::future::err(e).boxed(),
+        };
+
+        let shared_to = to.min(last_shared);
+        let mut requests: Vec<_> = log_filter
+            .clone()
+            .eth_get_logs_filters()
+            .map(|filter| {
+                self.shared.logs(
+                    &self.adapter,
+                    logger,
+                    &subgraph_metrics,
+                    from,
+                    shared_to,
+                    filter,
+                )
+            })
+            .collect();
+        if shared_to < to {
+            requests.push(self.adapter.logs_in_block_range(
+                logger,
+                subgraph_metrics,
+                shared_to + 1,
+                to,
+                log_filter,
+            ));
+        }
+
+        try_join_all(requests)
+            .map_ok(|logs| logs.into_iter().flatten().collect())
+            .boxed()
+    }
+
+    fn calls_in_block_range(
+        &self,
+        logger: &Logger,
+        subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
+        from: u64,
+        to: u64,
+        call_filter: EthereumCallFilter,
+    ) -> Box<dyn Stream<Item = EthereumCall, Error = Error> + Send> {
+        self.adapter
+            .calls_in_block_range(logger, subgraph_metrics, from, to, call_filter)
+    }
+
+    fn contract_call(
+        &self,
+        logger: &Logger,
+        call: EthereumContractCall,
+        cache: Arc<dyn EthereumCallCache>,
+    ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
+        self.adapter.contract_call(logger, call, cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::metrics::{Collector, Counter, Gauge, Opts, PrometheusError};
+    use crate::components::store::MockChainStore;
+    use crate::mock::MockEthereumAdapter;
+    use std::sync::atomic::AtomicUsize;
+    use web3::types::{Address, Bytes, U64};
+
+    struct NoopRegistry;
+
+    impl MetricsRegistry for NoopRegistry {
+        fn register(&self, _name: &str, _c: Box<dyn Collector>) {}
+
+        fn global_counter(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Counter, PrometheusError> {
+            Counter::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn global_gauge(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Gauge, PrometheusError> {
+            Gauge::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn unregister(&self, _: Box<dyn Collector>) {}
+    }
+
+    fn log(number: u64) -> Log {
+        Log {
+            address: Address::from_low_u64_be(1),
+            topics: vec![H256::from_low_u64_be(2)],
+            data: Bytes::default(),
+            block_hash: Some(H256::from_low_u64_be(number)),
+            block_number: Some(U64::from(number)),
+            transaction_hash: Some(H256::zero()),
+            transaction_index: Some(0.into()),
+            log_index: Some(0.into()),
+            transaction_log_index: Some(0.into()),
+            log_type: Some("".into()),
+            removed: Some(false),
+        }
+    }
+
+    fn log_filter() -> EthereumLogFilter {
+        EthereumLogFilter::from(EthGetLogsFilter {
+            contracts: vec![Address::from_low_u64_be(1)],
+            event_signatures: vec![H256::from_low_u64_be(2)],
+        })
+    }
+
+    /// The ranges that were requested from a provider that has one log in
+    /// every block. The first `failures` requests fail
+    type Requests = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    /// An adapter whose chain head is at `head`, and whose provider takes
+    /// a moment for every request so that requests overlap
+    fn adapter(
+        head: u64,
+        failures: usize,
+    ) -> (Arc<dyn EthereumAdapter>, Requests, Arc<SharedLogs>) {
+        let requests: Requests = Arc::new(Mutex::new(Vec::new()));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let mut provider = MockEthereumAdapter::new();
+        let requests2 = requests.clone();
+        provider
+            .expect_logs_in_block_range()
+            .returning(move |_, _, from, to, _| {
+                requests2.lock().unwrap().push((from, to));
+                let fail = failed.fetch_add(1, Ordering::SeqCst) < failures;
+                async move {
+                    tokio::time::delay_for(Duration::from_millis(10)).await;
+                    if fail {
+                        Err(format_err!("provider is down"))
+                    } else {
+                        Ok((from..=to).map(log).collect())
+                    }
+                }
+                .boxed()
+            });
+
+        let mut chain_store = MockChainStore::new();
+        chain_store
+            .expect_chain_head_ptr()
+            .returning(move || Ok(Some((H256::from_low_u64_be(head), head).into())));
+
+        let shared = Arc::new(SharedLogs::new(
+            "mainnet".to_owned(),
+            Arc::new(chain_store),
+            Arc::new(SharedLogsMetrics::new(Arc::new(NoopRegistry))),
+            100,
+            Duration::from_secs(60),
+        ));
+        let adapter = Arc::new(SharedLogsEthereumAdapter::new(
+            Arc::new(provider),
+            shared.clone(),
+        ));
+        (adapter, requests, shared)
+    }
+
+    fn metrics() -> Arc<SubgraphEthRpcMetrics> {
+        Arc::new(SubgraphEthRpcMetrics::new(
+            Arc::new(NoopRegistry),
+            "QmSharedLogs",
+        ))
+    }
+
+    /// Scan `from..=to` in steps of `step` blocks like a block stream, and
+    /// return the block numbers of all logs
+    async fn block_stream(
+        adapter: Arc<dyn EthereumAdapter>,
+        from: u64,
+        to: u64,
+        step: u64,
+    ) -> Vec<u64> {
+        let logger = crate::log::logger(false);
+        let mut numbers = Vec::new();
+        let mut start = from;
+        while start <= to {
+            let end = (start + step - 1).min(to);
+            let logs = adapter
+                .logs_in_block_range(&logger, metrics(), start, end, log_filter())
+                .await
+                .unwrap();
+            numbers.extend(logs.iter().map(|log| log.block_number.unwrap().as_u64()));
+            start = end + 1;
+        }
+        numbers.sort();
+        numbers
+    }
+
+    fn chunk_count(shared: &SharedLogs, result: &str) -> f64 {
+        shared
+            .metrics
+            .chunks
+            .with_label_values(&["mainnet", result])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn overlapping_block_streams_share_requests() {
+        let (adapter, requests, shared) = adapter(10_000, 0);
+
+        let (first, second) = futures03::join!(
+            block_stream(adapter.clone(), 0, 1999, 100),
+            block_stream(adapter.clone(), 50, 2049, 150),
+        );
+        assert_eq!((0..=1999).collect::<Vec<_>>(), first);
+        assert_eq!((50..=2049).collect::<Vec<_>>(), second);
+
+        // Every chunk was requested from the provider exactly once
+        let mut requests = requests.lock().unwrap().clone();
+        requests.sort();
+        let mut next = 0;
+        for (from, to) in requests {
+            assert_eq!(next, from);
+            assert_eq!(99, to % 100);
+            next = to + 1;
+        }
+        assert_eq!(2100, next);
+        assert_eq!(21.0, chunk_count(&shared, "miss"));
+        assert!(chunk_count(&shared, "hit") > 0.0);
+    }
+
+    #[tokio::test]
+    async fn recent_blocks_are_not_shared() {
+        // With the reorg threshold, only blocks up to 99 can be shared
+        let (adapter, requests, _) = adapter(149 + *REORG_THRESHOLD, 0);
+
+        assert_eq!(
+            (50..=250).collect::<Vec<_>>(),
+            block_stream(adapter.clone(), 50, 250, 201).await
+        );
+        assert_eq!(
+            (100..=250).collect::<Vec<_>>(),
+            block_stream(adapter.clone(), 100, 250, 201).await
+        );
+        assert_eq!(
+            vec![(0, 99), (100, 250), (100, 250)],
+            *requests.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_retried() {
+        let (adapter, requests, _) = adapter(10_000, 1);
+        let logger = crate::log::logger(false);
+
+        assert!(adapter
+            .logs_in_block_range(&logger, metrics(), 0, 99, log_filter())
+            .await
+            .is_err());
+        assert_eq!(
+            (0..=99).collect::<Vec<_>>(),
+            block_stream(adapter.clone(), 0, 99, 100).await
+        );
+        assert_eq!(vec![(0, 99), (0, 99)], *requests.lock().unwrap());
+    }
+}
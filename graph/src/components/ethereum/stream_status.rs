@@ -0,0 +1,244 @@
+//! What the block stream of each deployment is doing. Block streams record
+//! every state they go through in a `BlockStreamStatusRegistry`, which makes
+//! it possible to tell why a deployment is not advancing without reading
+//! debug logs. Updating a status only swaps a pointer so that block streams
+//! can do it as often as they like
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use crate::prelude::*;
+
+lazy_static! {
+    /// How long the status of a block stream has to stay the same before
+    /// `log_unchanged_block_stream_statuses` logs it
+    static ref STATUS_LOG_INTERVAL: Duration = Duration::from_secs(
+        std::env::var("GRAPH_BLOCK_STREAM_STATUS_LOG_INTERVAL")
+            .map(|s| s
+                .parse::<u64>()
+                .expect("invalid GRAPH_BLOCK_STREAM_STATUS_LOG_INTERVAL"))
+            .unwrap_or(300)
+    );
+}
+
+/// The states that a block stream goes through
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockStreamStatus {
+    /// The block stream has not been polled yet
+    Starting,
+    /// The deployment has caught up with the chain head, and the block
+    /// stream waits for the chain head to move
+    WaitingForChainHead,
+    /// The block stream asks the Ethereum node for the triggers in the
+    /// blocks `from` to `to`
+    ScanningBlocks { from: u64, to: u64 },
+    /// The block stream loads the block `number` near the chain head
+    FetchingBlock { number: u64 },
+    /// The deployment processes the block `number`
+    ProcessingBlock { number: u64 },
+    /// The block stream reverts the block `number` because it is no longer
+    /// on the main chain
+    RevertingBlock { number: u64 },
+    /// The block stream failed with `error` and waits until `until` before
+    /// it tries again
+    BackingOff { error: String, until: SystemTime },
+}
+
+impl BlockStreamStatus {
+    /// The name of the state, as used by the index node API
+    pub fn state(&self) -> &'static str {
+        use BlockStreamStatus::*;
+
+        match self {
+            Starting => "starting",
+            WaitingForChainHead => "waitingForChainHead",
+            ScanningBlocks { .. } => "scanningBlocks",
+            FetchingBlock { .. } => "fetchingBlock",
+            ProcessingBlock { .. } => "processingBlock",
+            RevertingBlock { .. } => "revertingBlock",
+            BackingOff { .. } => "backingOff",
+        }
+    }
+}
+
+impl fmt::Display for BlockStreamStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use BlockStreamStatus::*;
+
+        match self {
+            Starting => write!(f, "starting"),
+            WaitingForChainHead => write!(f, "waiting for a chain head update"),
+            ScanningBlocks { from, to } => write!(f, "scanning blocks [{}, {}]", from, to),
+            FetchingBlock { number } => write!(f, "fetching block {}", number),
+            ProcessingBlock { number } => write!(f, "processing block {}", number),
+            RevertingBlock { number } => write!(f, "reverting block {}", number),
+            BackingOff { error, until } => {
+                let secs = until
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+                    .as_secs();
+                write!(f, "retrying in {}s after error: {}", secs, error)
+            }
+        }
+    }
+}
+
+/// A status together with the time at which the block stream got into it
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedBlockStreamStatus {
+    pub status: BlockStreamStatus,
+    pub since: SystemTime,
+}
+
+/// The status of one block stream. The block stream sets it, everybody
+/// else only reads it
+pub struct BlockStreamStatusHandle {
+    logger: Logger,
+    current: ArcSwap<TimedBlockStreamStatus>,
+}
+
+impl BlockStreamStatusHandle {
+    fn new(logger: Logger) -> Self {
+        BlockStreamStatusHandle {
+            logger,
+            current: ArcSwap::from_pointee(TimedBlockStreamStatus {
+                status: BlockStreamStatus::Starting,
+                since: SystemTime::now(),
+            }),
+        }
+    }
+
+    /// Record that the block stream is now in `status`. Setting the status
+    /// that the block stream is already in does not change since when it
+    /// is in that status
+    pub fn set(&self, status: BlockStreamStatus) {
+        if self.current.load().status != status {
+            self.current.store(Arc::new(TimedBlockStreamStatus {
+                status,
+                since: SystemTime::now(),
+            }));
+        }
+    }
+
+    pub fn get(&self) -> TimedBlockStreamStatus {
+        TimedBlockStreamStatus::clone(&self.current.load())
+    }
+}
+
+/// The statuses of the block streams that run on this node, by deployment
+#[derive(Default)]
+pub struct BlockStreamStatusRegistry {
+    streams: RwLock<HashMap<SubgraphDeploymentId, Arc<BlockStreamStatusHandle>>>,
+}
+
+impl BlockStreamStatusRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking the status of a new block stream for `deployment`.
+    /// Messages about the block stream go to `logger`
+    pub fn register(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        logger: &Logger,
+    ) -> Arc<BlockStreamStatusHandle> {
+        let handle = Arc::new(BlockStreamStatusHandle::new(logger.clone()));
+        self.streams
+            .write()
+            .unwrap()
+            .insert(deployment.clone(), handle.clone());
+        handle
+    }
+
+    /// Stop tracking the block stream with `handle`. When a deployment is
+    /// restarted, its new block stream may register before the old one is
+    /// gone; the status of the new block stream is kept in that case
+    pub fn remove(&self, deployment: &SubgraphDeploymentId, handle: &Arc<BlockStreamStatusHandle>) {
+        let mut streams = self.streams.write().unwrap();
+        if streams
+            .get(deployment)
+            .map_or(false, |current| Arc::ptr_eq(current, handle))
+        {
+            streams.remove(deployment);
+        }
+    }
+
+    /// The status of the block stream for `deployment`, or `None` if that
+    /// deployment does not have a block stream on this node
+    pub fn status(&self, deployment: &SubgraphDeploymentId) -> Option<TimedBlockStreamStatus> {
+        self.streams
+            .read()
+            .unwrap()
+            .get(deployment)
+            .map(|handle| handle.get())
+    }
+
+    /// Log the status of every block stream whose status has not changed
+    /// for at least `interval`
+    pub fn log_unchanged(&self, interval: Duration) {
+        let now = SystemTime::now();
+        for handle in self.streams.read().unwrap().values() {
+            let current = handle.get();
+            let unchanged = now.duration_since(current.since).unwrap_or_default();
+            if unchanged >= interval {
+                info!(handle.logger, "Block stream is {}", current.status;
+                      "state" => current.status.state(),
+                      "unchanged_for_secs" => unchanged.as_secs());
+            }
+        }
+    }
+}
+
+/// Every `GRAPH_BLOCK_STREAM_STATUS_LOG_INTERVAL` seconds, log the status of
+/// the block streams whose status has not changed for at least that long
+pub async fn log_unchanged_block_stream_statuses(registry: Arc<BlockStreamStatusRegistry>) {
+    loop {
+        tokio::time::delay_for(*STATUS_LOG_INTERVAL).await;
+        registry.log_unchanged(*STATUS_LOG_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_latest_block_stream() {
+        let logger = Logger::root(slog::Discard, o!());
+        let deployment = SubgraphDeploymentId::new("QmStatus").unwrap();
+        let registry = BlockStreamStatusRegistry::new();
+        assert_eq!(None, registry.status(&deployment));
+
+        let old = registry.register(&deployment, &logger);
+        assert_eq!(
+            BlockStreamStatus::Starting,
+            registry.status(&deployment).unwrap().status
+        );
+        old.set(BlockStreamStatus::ScanningBlocks { from: 1, to: 10 });
+        let since = registry.status(&deployment).unwrap().since;
+        old.set(BlockStreamStatus::ScanningBlocks { from: 1, to: 10 });
+        assert_eq!(
+            TimedBlockStreamStatus {
+                status: BlockStreamStatus::ScanningBlocks { from: 1, to: 10 },
+                since,
+            },
+            registry.status(&deployment).unwrap()
+        );
+
+        // A restarted deployment registers its new block stream before the
+        // old one is removed
+        let new = registry.register(&deployment, &logger);
+        new.set(BlockStreamStatus::ProcessingBlock { number: 7 });
+        registry.remove(&deployment, &old);
+        assert_eq!(
+            BlockStreamStatus::ProcessingBlock { number: 7 },
+            registry.status(&deployment).unwrap().status
+        );
+        registry.remove(&deployment, &new);
+        assert_eq!(None, registry.status(&deployment));
+    }
+}
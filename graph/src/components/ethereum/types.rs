@@ -3,19 +3,32 @@ use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
 use stable_hash::utils::AsBytes;
 use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::fmt;
 use web3::types::*;
 
-use crate::prelude::{EntityKey, SubgraphDeploymentId, ToEntityKey};
+use crate::blockchain::{BlockPtr, TriggerData};
+use crate::prelude::{format_err, EntityKey, Error, SubgraphDeploymentId, ToEntityKey};
 
 pub type LightEthereumBlock = Block<Transaction>;
 
+/// The transactions root of blocks without transactions, the root hash of
+/// an empty trie
+const EMPTY_TRANSACTIONS_ROOT: H256 = H256([
+    0x56, 0xe8, 0x1f, 0x17, 0x1b, 0xcc, 0x55, 0xa6, 0xff, 0x83, 0x45, 0xe6, 0x92, 0xc0, 0xf8, 0x6e,
+    0x5b, 0x48, 0xe0, 0x1b, 0x99, 0x6c, 0xad, 0xc0, 0x01, 0x62, 0x2f, 0xb5, 0xe3, 0x63, 0xb4, 0x21,
+]);
+
 pub trait LightEthereumBlockExt {
     fn number(&self) -> u64;
     fn transaction_for_log(&self, log: &Log) -> Option<Transaction>;
     fn transaction_for_call(&self, call: &EthereumCall) -> Option<Transaction>;
     fn parent_ptr(&self) -> Option<EthereumBlockPointer>;
     fn format(&self) -> String;
+
+    /// Whether the block was fetched without its transactions although it
+    /// has some, which is the case for blocks that only have a header
+    fn transactions_omitted(&self) -> bool;
 }
 
 impl LightEthereumBlockExt for LightEthereumBlock {
@@ -54,6 +67,10 @@ impl LightEthereumBlockExt for LightEthereumBlock {
                 .map_or(String::from("-"), |hash| format!("{:x}", hash))
         )
     }
+
+    fn transactions_omitted(&self) -> bool {
+        self.transactions.is_empty() && self.transactions_root != EMPTY_TRANSACTIONS_ROOT
+    }
 }
 
 /// This is used in `EthereumAdapter::triggers_in_block`, called when re-processing a block for
@@ -84,24 +101,6 @@ impl BlockFinality {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct EthereumBlockWithTriggers {
-    pub ethereum_block: BlockFinality,
-    pub triggers: Vec<EthereumTrigger>,
-}
-
-impl EthereumBlockWithTriggers {
-    pub fn new(mut triggers: Vec<EthereumTrigger>, ethereum_block: BlockFinality) -> Self {
-        // Sort the triggers
-        triggers.sort();
-
-        EthereumBlockWithTriggers {
-            ethereum_block,
-            triggers,
-        }
-    }
-}
-
 #[derive(Clone, Debug)]
 pub struct EthereumBlockWithCalls {
     pub ethereum_block: EthereumBlock,
@@ -114,7 +113,7 @@ pub struct EthereumBlock {
     pub transaction_receipts: Vec<TransactionReceipt>,
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct EthereumCall {
     pub from: Address,
     pub to: Address,
@@ -263,6 +262,25 @@ impl PartialOrd for EthereumTrigger {
     }
 }
 
+impl TriggerData for EthereumTrigger {
+    fn kind(&self) -> &'static str {
+        match self {
+            EthereumTrigger::Log(_) => "event",
+            EthereumTrigger::Call(_) => "call",
+            EthereumTrigger::Block(..) => "block",
+        }
+    }
+
+    fn location(&self) -> Option<String> {
+        let transaction_hash = match self {
+            EthereumTrigger::Log(log) => log.transaction_hash,
+            EthereumTrigger::Call(call) => call.transaction_hash,
+            EthereumTrigger::Block(..) => None,
+        };
+        transaction_hash.map(|hash| format!("transaction {:x}", hash))
+    }
+}
+
 /// Ethereum block data.
 #[derive(Clone, Debug, Default)]
 pub struct EthereumBlockData {
@@ -280,6 +298,10 @@ pub struct EthereumBlockData {
     pub difficulty: U256,
     pub total_difficulty: U256,
     pub size: Option<U256>,
+    /// The number of transactions in the block. For blocks that were
+    /// fetched without their transactions, it has to come from
+    /// `EthereumAdapter::block_transaction_count` instead
+    pub transaction_count: u64,
 }
 
 impl<'a, T> From<&'a Block<T>> for EthereumBlockData {
@@ -299,6 +321,7 @@ impl<'a, T> From<&'a Block<T>> for EthereumBlockData {
             difficulty: block.difficulty,
             total_difficulty: block.total_difficulty.unwrap_or_default(),
             size: block.size,
+            transaction_count: block.transactions.len() as u64,
         }
     }
 }
@@ -524,6 +547,36 @@ impl From<EthereumBlockPointer> for u64 {
     }
 }
 
+impl From<EthereumBlockPointer> for BlockPtr {
+    fn from(ptr: EthereumBlockPointer) -> Self {
+        BlockPtr {
+            hash: ptr.hash.as_bytes().into(),
+            number: ptr.number,
+        }
+    }
+}
+
+/// The store records block pointers with the 32 byte hashes that Ethereum
+/// uses, which is why only pointers with such hashes can be stored
+impl TryFrom<BlockPtr> for EthereumBlockPointer {
+    type Error = Error;
+
+    fn try_from(ptr: BlockPtr) -> Result<Self, Error> {
+        if ptr.hash.len() != H256::len_bytes() {
+            return Err(format_err!(
+                "block {} has a hash of {} bytes, but only hashes of {} bytes can be stored",
+                ptr,
+                ptr.hash.len(),
+                H256::len_bytes()
+            ));
+        }
+        Ok(EthereumBlockPointer {
+            hash: H256::from_slice(ptr.hash.as_slice()),
+            number: ptr.number,
+        })
+    }
+}
+
 impl ToEntityKey for EthereumBlockPointer {
     fn to_entity_key(&self, subgraph: SubgraphDeploymentId) -> EntityKey {
         EntityKey {
@@ -536,9 +589,46 @@ impl ToEntityKey for EthereumBlockPointer {
 
 #[cfg(test)]
 mod test {
-    use super::{EthereumBlockPointer, EthereumBlockTriggerType, EthereumCall, EthereumTrigger};
+    use super::{
+        EthereumBlockData, EthereumBlockPointer, EthereumBlockTriggerType, EthereumCall,
+        EthereumTrigger, LightEthereumBlock, LightEthereumBlockExt, EMPTY_TRANSACTIONS_ROOT,
+    };
     use web3::types::*;
 
+    #[test]
+    fn block_data_counts_transactions() {
+        // A block with just the transaction hashes, as returned by
+        // `eth_getBlockByHash(hash, false)`
+        let mut block: Block<H256> = Block::default();
+        block.hash = Some(H256::from_low_u64_be(1));
+        block.number = Some(U64::from(1));
+        block.transactions = vec![H256::from_low_u64_be(2), H256::from_low_u64_be(3)];
+        assert_eq!(2, EthereumBlockData::from(&block).transaction_count);
+
+        // A block with full transactions
+        let mut block: Block<Transaction> = Block::default();
+        block.hash = Some(H256::from_low_u64_be(1));
+        block.number = Some(U64::from(1));
+        block.transactions = vec![Transaction::default(); 3];
+        assert_eq!(3, EthereumBlockData::from(&block).transaction_count);
+
+        block.transactions = vec![];
+        assert_eq!(0, EthereumBlockData::from(&block).transaction_count);
+    }
+
+    #[test]
+    fn header_only_blocks_omit_transactions() {
+        let mut block = LightEthereumBlock::default();
+        block.transactions_root = EMPTY_TRANSACTIONS_ROOT;
+        assert!(!block.transactions_omitted());
+
+        block.transactions_root = H256::from_low_u64_be(1);
+        assert!(block.transactions_omitted());
+
+        block.transactions = vec![Transaction::default()];
+        assert!(!block.transactions_omitted());
+    }
+
     #[test]
     fn test_trigger_ordering() {
         let block1 = EthereumTrigger::Block(
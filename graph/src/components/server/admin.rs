@@ -1,6 +1,7 @@
 use std::io;
 use std::sync::Arc;
 
+use crate::components::server::auth::ServerSecurity;
 use crate::prelude::Logger;
 use crate::prelude::NodeId;
 
@@ -14,6 +15,7 @@ pub trait JsonRpcServer<P> {
         ws_port: u16,
         provider: Arc<P>,
         node_id: NodeId,
+        security: ServerSecurity,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
@@ -0,0 +1,252 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+
+use super::tls::TlsConfig;
+use crate::prelude::{CounterVec, MetricsRegistry};
+
+/// The label used for tokens that were configured without one.
+const DEFAULT_TOKEN_LABEL: &str = "default";
+
+/// A static bearer token that grants access to a protected server. The
+/// label is only used in logs so that operators can tell which of several
+/// credentials was used for a request.
+#[derive(Clone)]
+pub struct AuthToken {
+    pub label: String,
+    token: String,
+}
+
+impl AuthToken {
+    pub fn new(label: impl Into<String>, token: impl Into<String>) -> Self {
+        AuthToken {
+            label: label.into(),
+            token: token.into(),
+        }
+    }
+}
+
+impl FromStr for AuthToken {
+    type Err = anyhow::Error;
+
+    /// Parses a token in the form `LABEL:TOKEN` or just `TOKEN`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (label, token) = match s.find(':') {
+            Some(pos) => (&s[..pos], &s[pos + 1..]),
+            None => (DEFAULT_TOKEN_LABEL, s),
+        };
+        if label.is_empty() {
+            return Err(anyhow::anyhow!(
+                "the label of an auth token can not be empty"
+            ));
+        }
+        if token.is_empty() {
+            return Err(anyhow::anyhow!("auth token `{}` can not be empty", label));
+        }
+        Ok(AuthToken::new(label, token))
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the secret itself
+        f.debug_struct("AuthToken")
+            .field("label", &self.label)
+            .finish()
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AuthError {
+    #[error("missing bearer token")]
+    MissingToken,
+    #[error("malformed `Authorization` header")]
+    MalformedHeader,
+    #[error("invalid bearer token")]
+    InvalidToken,
+}
+
+/// Bearer token authentication for a server. If no tokens are configured,
+/// every request is allowed.
+#[derive(Clone, Debug, Default)]
+pub struct ServerAuth {
+    tokens: Vec<AuthToken>,
+}
+
+impl ServerAuth {
+    pub fn new(tokens: Vec<AuthToken>) -> Self {
+        ServerAuth { tokens }
+    }
+
+    /// An instance that does not check credentials at all.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    /// Check the value of the `Authorization` header of a request. On
+    /// success, return the label of the token that was used, or `None`
+    /// if authentication is disabled.
+    ///
+    /// The presented token is compared against all configured tokens in
+    /// constant time so that the comparison does not leak how much of a
+    /// token an attacker got right.
+    pub fn authorize(&self, header: Option<&str>) -> Result<Option<&str>, AuthError> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let header = header.ok_or(AuthError::MissingToken)?;
+        let mut parts = header.trim().splitn(2, ' ');
+        let presented = match (parts.next(), parts.next()) {
+            (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+            _ => return Err(AuthError::MalformedHeader),
+        };
+
+        // Deliberately check every token without short-circuiting
+        let mut matched = None;
+        for token in &self.tokens {
+            let equal: bool = token.token.as_bytes().ct_eq(presented.as_bytes()).into();
+            if equal && matched.is_none() {
+                matched = Some(token.label.as_str());
+            }
+        }
+        matched.map(Some).ok_or(AuthError::InvalidToken)
+    }
+}
+
+/// Metrics for failed authentication attempts across all servers that
+/// use `ServerAuth`
+pub struct AuthMetrics {
+    failures: Box<CounterVec>,
+}
+
+impl AuthMetrics {
+    pub fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let failures = registry
+            .new_counter_vec(
+                "server_auth_failures",
+                "Counts requests that were rejected because of missing or invalid credentials",
+                vec![String::from("server"), String::from("source")],
+            )
+            .expect("failed to create `server_auth_failures` counter");
+        AuthMetrics { failures }
+    }
+
+    /// Record a rejected request. Only the IP address of the source is
+    /// used as a label, not the port, to keep the number of series bounded.
+    pub fn auth_failed(&self, server: &str, source: Option<SocketAddr>) {
+        let source = source
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_else(|| String::from("unknown"));
+        self.failures
+            .with_label_values(&[server, source.as_str()])
+            .inc();
+    }
+}
+
+/// How an operator-facing server secures its port: which credentials it
+/// accepts, whether it terminates TLS, and where it reports rejections.
+#[derive(Clone)]
+pub struct ServerSecurity {
+    pub auth: ServerAuth,
+    pub tls: Option<TlsConfig>,
+    pub metrics: Arc<AuthMetrics>,
+}
+
+impl ServerSecurity {
+    pub fn new(auth: ServerAuth, tls: Option<TlsConfig>, metrics: Arc<AuthMetrics>) -> Self {
+        ServerSecurity { auth, tls, metrics }
+    }
+
+    /// Check the credentials of a request for `server`, recording a
+    /// failure if they are not acceptable.
+    pub fn authorize(
+        &self,
+        server: &str,
+        header: Option<&str>,
+        source: Option<SocketAddr>,
+    ) -> Result<Option<&str>, AuthError> {
+        self.auth.authorize(header).map_err(|e| {
+            self.metrics.auth_failed(server, source);
+            e
+        })
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> ServerAuth {
+        ServerAuth::new(vec![
+            "deployer:s3cr3t".parse().unwrap(),
+            "monitoring:0therT0ken".parse().unwrap(),
+        ])
+    }
+
+    #[test]
+    fn disabled_auth_allows_everything() {
+        let auth = ServerAuth::disabled();
+        assert_eq!(Ok(None), auth.authorize(None));
+        assert_eq!(Ok(None), auth.authorize(Some("Bearer whatever")));
+    }
+
+    #[test]
+    fn accepts_configured_tokens() {
+        let auth = auth();
+        assert_eq!(Ok(Some("deployer")), auth.authorize(Some("Bearer s3cr3t")));
+        assert_eq!(
+            Ok(Some("monitoring")),
+            auth.authorize(Some("bearer 0therT0ken"))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_credentials() {
+        let auth = auth();
+        assert_eq!(Err(AuthError::MissingToken), auth.authorize(None));
+        assert_eq!(
+            Err(AuthError::MalformedHeader),
+            auth.authorize(Some("s3cr3t"))
+        );
+        assert_eq!(
+            Err(AuthError::MalformedHeader),
+            auth.authorize(Some("Basic s3cr3t"))
+        );
+        assert_eq!(
+            Err(AuthError::InvalidToken),
+            auth.authorize(Some("Bearer s3cr3"))
+        );
+        assert_eq!(
+            Err(AuthError::InvalidToken),
+            auth.authorize(Some("Bearer s3cr3tt"))
+        );
+    }
+
+    #[test]
+    fn parse_tokens() {
+        let token: AuthToken = "s3cr3t".parse().unwrap();
+        assert_eq!(DEFAULT_TOKEN_LABEL, token.label);
+        let token: AuthToken = "ci:s3:cr3t".parse().unwrap();
+        assert_eq!("ci", token.label);
+        assert_eq!("s3:cr3t", token.token);
+        assert!("ci:".parse::<AuthToken>().is_err());
+        assert!(":s3cr3t".parse::<AuthToken>().is_err());
+    }
+}
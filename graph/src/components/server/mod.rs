@@ -12,3 +12,12 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Bearer token authentication for the operator-facing servers.
+pub mod auth;
+
+/// TLS termination for the operator-facing servers.
+pub mod tls;
+
+/// Per-client rate limiting of queries on the public GraphQL servers.
+pub mod rate_limit;
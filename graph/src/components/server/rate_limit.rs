@@ -0,0 +1,593 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use http::HeaderMap;
+use lazy_static::lazy_static;
+use priority_queue::PriorityQueue;
+
+use crate::data::subgraph::SubgraphDeploymentId;
+use crate::prelude::{CounterVec, MetricsRegistry};
+
+lazy_static! {
+    /// How many clients the rate limiter remembers. When there are more,
+    /// the ones that sent a query the longest time ago are forgotten,
+    /// which gives them a full bucket again
+    static ref RATE_LIMIT_CLIENTS: usize = env::var("GRAPH_QUERY_RATE_LIMIT_CLIENTS")
+        .ok()
+        .map(|s| {
+            usize::from_str(&s).unwrap_or_else(|_| {
+                panic!("failed to parse env var GRAPH_QUERY_RATE_LIMIT_CLIENTS")
+            })
+        })
+        .unwrap_or(100_000);
+}
+
+/// An IP network like `10.0.0.0/8`. A plain address is a network that
+/// only contains that address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => match ip.to_ipv4() {
+                Some(ip) => self.contains(IpAddr::V4(ip)),
+                None => false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// Whether the first `prefix` bits of `a` and `b` are the same
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full = (prefix / 8) as usize;
+    let rest = prefix % 8;
+    if a[..full] != b[..full] {
+        return false;
+    }
+    if rest == 0 {
+        return true;
+    }
+    let mask = !0u8 << (8 - rest);
+    a[full] & mask == b[full] & mask
+}
+
+impl FromStr for IpNet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.find('/') {
+            Some(pos) => (&s[..pos], Some(&s[pos + 1..])),
+            None => (s, None),
+        };
+        let addr = IpAddr::from_str(addr)
+            .map_err(|_| anyhow::anyhow!("`{}` is not an IP address or network", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => u8::from_str(prefix)
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| anyhow::anyhow!("invalid prefix length in `{}`", s))?,
+            None => max,
+        };
+        Ok(IpNet { addr, prefix })
+    }
+}
+
+/// The proxies in front of the node whose headers say which client they
+/// forward a request for. Headers from everybody else are ignored, since
+/// clients can put anything into them
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+    headers: Vec<String>,
+}
+
+impl TrustedProxies {
+    /// Trust requests from `networks` to name the client in one of
+    /// `headers`, like `X-Forwarded-For`. Only the first of the `headers`
+    /// that a request has is used
+    pub fn new(networks: Vec<IpNet>, headers: Vec<String>) -> Self {
+        TrustedProxies { networks, headers }
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(ip))
+    }
+
+    /// The IP address of the client for a request from `peer`. When the
+    /// peer is a trusted proxy, the header is a list of addresses that
+    /// each proxy appended to; walking it from the right, the client is the
+    /// first address that is not a trusted proxy itself. Anything to the
+    /// left of it was sent by the client and can not be believed, and
+    /// neither can anything to the left of an entry that is not an
+    /// address; the client is then the last trusted address we saw.
+    ///
+    /// Once a trusted proxy set the first of our headers, the other
+    /// headers are never looked at, since the client might have sent them
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let header = match self
+            .headers
+            .iter()
+            .find(|header| headers.contains_key(header.as_str()))
+        {
+            Some(header) => header,
+            None => return peer,
+        };
+        let entries: Vec<&str> = headers
+            .get_all(header.as_str())
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or("").split(','))
+            .collect();
+        let mut client = peer;
+        for entry in entries.into_iter().rev() {
+            match parse_forwarded_ip(entry.trim()) {
+                Some(ip) => {
+                    client = ip;
+                    if !self.is_trusted(ip) {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+/// Parse an address from a forwarding header, which may also include a
+/// port like `1.2.3.4:5678` or `[::1]:5678`
+fn parse_forwarded_ip(entry: &str) -> Option<IpAddr> {
+    IpAddr::from_str(entry).ok().or_else(|| {
+        std::net::SocketAddr::from_str(entry)
+            .ok()
+            .map(|addr| addr.ip())
+    })
+}
+
+/// How many queries a client may send: `per_second` on average, and up to
+/// `burst` at once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    per_second: f64,
+    burst: f64,
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    /// Parses a limit in the form `RATE` or `RATE/BURST`. Without a burst,
+    /// a client may send one second's worth of queries at once
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| {
+            f64::from_str(n)
+                .ok()
+                .filter(|n| n.is_finite() && *n > 0.0)
+                .ok_or_else(|| anyhow::anyhow!("invalid rate limit `{}`", s))
+        };
+        let (per_second, burst) = match s.find('/') {
+            Some(pos) => (parse(&s[..pos])?, parse(&s[pos + 1..])?),
+            None => (parse(s)?, parse(s)?.max(1.0)),
+        };
+        Ok(RateLimit { per_second, burst })
+    }
+}
+
+/// The limits for all deployments, and the ones for specific
+/// deployments that differ from that
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    pub default: Option<RateLimit>,
+    pub deployments: HashMap<SubgraphDeploymentId, RateLimit>,
+}
+
+impl RateLimits {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.deployments.is_empty()
+    }
+
+    fn for_deployment(&self, deployment: &SubgraphDeploymentId) -> Option<RateLimit> {
+        self.deployments.get(deployment).cloned().or(self.default)
+    }
+
+    /// Parses the limit for a deployment in the form
+    /// `DEPLOYMENT=RATE[/BURST]`
+    pub fn parse_deployment_limit(
+        s: &str,
+    ) -> Result<(SubgraphDeploymentId, RateLimit), anyhow::Error> {
+        let pos = s
+            .find('=')
+            .ok_or_else(|| anyhow::anyhow!("expected `DEPLOYMENT=RATE[/BURST]` but got `{}`", s))?;
+        let deployment = SubgraphDeploymentId::new(&s[..pos])
+            .map_err(|id| anyhow::anyhow!("invalid deployment id `{}`", id))?;
+        Ok((deployment, s[pos + 1..].parse()?))
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+type ClientKey = (IpAddr, SubgraphDeploymentId);
+
+/// The token buckets of the `capacity` clients that sent queries most
+/// recently
+struct ClientBuckets {
+    capacity: usize,
+    clients: Mutex<Clients>,
+}
+
+struct Clients {
+    buckets: HashMap<ClientKey, Bucket>,
+    recent: PriorityQueue<ClientKey, Reverse<u64>>,
+    tick: u64,
+}
+
+impl ClientBuckets {
+    fn new(capacity: usize) -> Self {
+        ClientBuckets {
+            capacity: capacity.max(1),
+            clients: Mutex::new(Clients {
+                buckets: HashMap::new(),
+                recent: PriorityQueue::new(),
+                tick: 0,
+            }),
+        }
+    }
+
+    /// Take a token from the bucket for `key` at time `now`, or return how
+    /// long until there is one
+    fn take(&self, key: ClientKey, limit: RateLimit, now: Instant) -> Result<(), Duration> {
+        let mut clients = self.clients.lock().unwrap();
+        clients.tick += 1;
+        let tick = clients.tick;
+        clients.recent.push(key.clone(), Reverse(tick));
+
+        let bucket = clients.buckets.entry(key).or_insert(Bucket {
+            tokens: limit.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst);
+        bucket.updated = now;
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.per_second,
+            ))
+        };
+
+        while clients.buckets.len() > self.capacity {
+            match clients.recent.pop() {
+                Some((oldest, _)) => {
+                    clients.buckets.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        result
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.clients.lock().unwrap().buckets.len()
+    }
+}
+
+/// Counts queries that were rejected per deployment and client network
+struct RateLimitMetrics {
+    rejected: Box<CounterVec>,
+}
+
+impl RateLimitMetrics {
+    fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let rejected = registry
+            .new_counter_vec(
+                "query_rate_limited",
+                "Counts queries that were rejected because the client exceeded its rate limit",
+                vec![String::from("deployment"), String::from("client")],
+            )
+            .expect("failed to create `query_rate_limited` counter");
+        RateLimitMetrics { rejected }
+    }
+
+    fn rejected(&self, ip: IpAddr, deployment: &SubgraphDeploymentId) {
+        self.rejected
+            .with_label_values(&[deployment.as_str(), truncated_ip(ip).as_str()])
+            .inc();
+    }
+}
+
+/// The network that `ip` is in, with a prefix that is short enough that
+/// using it as a metrics label does not create a series per client
+fn truncated_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+    }
+}
+
+/// Token bucket rate limiting of queries for each pair of client IP and
+/// deployment
+pub struct RateLimiter {
+    limits: RateLimits,
+    proxies: TrustedProxies,
+    buckets: ClientBuckets,
+    metrics: RateLimitMetrics,
+}
+
+impl RateLimiter {
+    pub fn new(
+        limits: RateLimits,
+        proxies: TrustedProxies,
+        registry: Arc<impl MetricsRegistry>,
+    ) -> Self {
+        RateLimiter {
+            limits,
+            proxies,
+            buckets: ClientBuckets::new(*RATE_LIMIT_CLIENTS),
+            metrics: RateLimitMetrics::new(registry),
+        }
+    }
+
+    /// The client for a request from `peer` with `headers`
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        self.proxies.client_ip(peer, headers)
+    }
+
+    /// Take a token from the bucket of `ip` for `deployment`. If the
+    /// bucket is empty, return how long the client has to wait before it
+    /// may send another query
+    pub fn check(&self, ip: IpAddr, deployment: &SubgraphDeploymentId) -> Result<(), Duration> {
+        let limit = match self.limits.for_deployment(deployment) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let result = self
+            .buckets
+            .take((ip, deployment.clone()), limit, Instant::now());
+        if result.is_err() {
+            self.metrics.rejected(ip, deployment);
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limits", &self.limits)
+            .field("proxies", &self.proxies)
+            .finish()
+    }
+}
+
+/// The value of a `Retry-After` header for waiting `duration`, in whole
+/// seconds and at least one
+pub fn retry_after(duration: Duration) -> String {
+    (duration.as_secs_f64().ceil() as u64).max(1).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new(
+            vec!["10.0.0.0/8".parse().unwrap(), "fd00::1".parse().unwrap()],
+            vec!["X-Forwarded-For".to_owned(), "X-Real-IP".to_owned()],
+        )
+    }
+
+    fn headers(entries: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parse_networks() {
+        let net: IpNet = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.200.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.1")));
+        let net: IpNet = "192.168.1.7".parse().unwrap();
+        assert!(net.contains(ip("192.168.1.7")));
+        assert!(!net.contains(ip("192.168.1.8")));
+        let net: IpNet = "2001:db8::/33".parse().unwrap();
+        assert!(net.contains(ip("2001:db8:7fff::1")));
+        assert!(!net.contains(ip("2001:db8:8000::1")));
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("localhost".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn headers_from_untrusted_peers_are_ignored() {
+        let proxies = proxies();
+        let spoofed = headers(&[("x-forwarded-for", "10.0.0.5, 1.2.3.4")]);
+        assert_eq!(ip("5.6.7.8"), proxies.client_ip(ip("5.6.7.8"), &spoofed));
+        let spoofed = headers(&[("x-real-ip", "1.2.3.4")]);
+        assert_eq!(ip("5.6.7.8"), proxies.client_ip(ip("5.6.7.8"), &spoofed));
+    }
+
+    #[test]
+    fn client_is_last_untrusted_address() {
+        let proxies = proxies();
+        let peer = ip("10.0.0.1");
+
+        // The client prepended a made up address to the chain
+        let chain = headers(&[("x-forwarded-for", "6.6.6.6, 1.2.3.4")]);
+        assert_eq!(ip("1.2.3.4"), proxies.client_ip(peer, &chain));
+
+        // The request went through two of our proxies
+        let chain = headers(&[
+            ("x-forwarded-for", "6.6.6.6"),
+            ("x-forwarded-for", "1.2.3.4:5678, 10.0.0.2"),
+        ]);
+        assert_eq!(ip("1.2.3.4"), proxies.client_ip(peer, &chain));
+
+        // Only proxies
+        let chain = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(ip("10.0.0.3"), proxies.client_ip(peer, &chain));
+
+        // Nothing to the left of a malformed entry is believed, and the
+        // client is the last trusted address before it
+        let chain = headers(&[("x-forwarded-for", "1.2.3.4, junk")]);
+        assert_eq!(peer, proxies.client_ip(peer, &chain));
+        let chain = headers(&[("x-forwarded-for", "1.2.3.4, junk, 10.0.0.2")]);
+        assert_eq!(ip("10.0.0.2"), proxies.client_ip(peer, &chain));
+
+        // The next header is only used when the first one is missing
+        let chain = headers(&[("x-real-ip", "[::2]:80")]);
+        assert_eq!(ip("::2"), proxies.client_ip(peer, &chain));
+        assert_eq!(peer, proxies.client_ip(peer, &headers(&[])));
+        assert_eq!(
+            ip("fd00::1"),
+            proxies.client_ip(ip("fd00::1"), &headers(&[("x-forwarded-for", "")]))
+        );
+    }
+
+    #[test]
+    fn spoofed_headers_do_not_change_the_client() {
+        let proxies = proxies();
+        let peer = ip("10.0.0.1");
+
+        // The client sent a malformed `X-Forwarded-For` that our proxy
+        // appended to, and an `X-Real-IP` that our proxy passed through
+        let spoofed = headers(&[
+            ("x-forwarded-for", "junk, 1.2.3.4"),
+            ("x-real-ip", "6.6.6.6"),
+        ]);
+        assert_eq!(ip("1.2.3.4"), proxies.client_ip(peer, &spoofed));
+
+        // A made up trusted address does not hide the client either
+        let spoofed = headers(&[
+            ("x-forwarded-for", "10.0.0.7, junk, 1.2.3.4"),
+            ("x-real-ip", "6.6.6.6"),
+        ]);
+        assert_eq!(ip("1.2.3.4"), proxies.client_ip(peer, &spoofed));
+
+        // Our proxy could not make sense of what it forwarded
+        let spoofed = headers(&[("x-forwarded-for", "junk"), ("x-real-ip", "6.6.6.6")]);
+        assert_eq!(peer, proxies.client_ip(peer, &spoofed));
+    }
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(
+            RateLimit {
+                per_second: 0.5,
+                burst: 1.0
+            },
+            "0.5".parse().unwrap()
+        );
+        assert_eq!(
+            RateLimit {
+                per_second: 10.0,
+                burst: 50.0
+            },
+            "10/50".parse().unwrap()
+        );
+        assert!("0".parse::<RateLimit>().is_err());
+        assert!("10/".parse::<RateLimit>().is_err());
+        let (deployment, limit) = RateLimits::parse_deployment_limit("QmAbc=2/4").unwrap();
+        assert_eq!("QmAbc", deployment.as_str());
+        assert_eq!("2/4".parse::<RateLimit>().unwrap(), limit);
+        assert!(RateLimits::parse_deployment_limit("QmAbc").is_err());
+    }
+
+    #[test]
+    fn limits_for_deployments() {
+        let limited = SubgraphDeploymentId::new("limited").unwrap();
+        let other = SubgraphDeploymentId::new("other").unwrap();
+        let mut limits = RateLimits::default();
+        assert!(limits.is_empty());
+        limits
+            .deployments
+            .insert(limited.clone(), "1/2".parse().unwrap());
+        assert_eq!(None, limits.for_deployment(&other));
+        limits.default = Some("5".parse().unwrap());
+        assert_eq!(
+            "1/2".parse::<RateLimit>().ok(),
+            limits.for_deployment(&limited)
+        );
+        assert_eq!("5".parse::<RateLimit>().ok(), limits.for_deployment(&other));
+    }
+
+    #[test]
+    fn token_buckets() {
+        let deployment = SubgraphDeploymentId::new("limited").unwrap();
+        let key = |s: &str| (ip(s), deployment.clone());
+        let limit: RateLimit = "1/2".parse().unwrap();
+        let buckets = ClientBuckets::new(2);
+
+        let start = Instant::now();
+        assert_eq!(Ok(()), buckets.take(key("1.2.3.4"), limit, start));
+        assert_eq!(Ok(()), buckets.take(key("1.2.3.4"), limit, start));
+        assert_eq!(
+            Err(Duration::from_secs(1)),
+            buckets.take(key("1.2.3.4"), limit, start)
+        );
+        // Other clients have their own bucket
+        assert_eq!(Ok(()), buckets.take(key("1.2.3.5"), limit, start));
+
+        // The bucket refills over time
+        let later = start + Duration::from_millis(1500);
+        assert_eq!(Ok(()), buckets.take(key("1.2.3.4"), limit, later));
+        assert!(buckets.take(key("1.2.3.4"), limit, later).is_err());
+
+        // Only the most recent clients are remembered; `1.2.3.5` is
+        // forgotten first and then `1.2.3.4`, which gets a full bucket
+        buckets.take(key("1.2.3.6"), limit, later).unwrap();
+        buckets.take(key("1.2.3.7"), limit, later).unwrap();
+        assert_eq!(2, buckets.len());
+        assert_eq!(Ok(()), buckets.take(key("1.2.3.4"), limit, later));
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up() {
+        assert_eq!("1", retry_after(Duration::from_millis(10)));
+        assert_eq!("3", retry_after(Duration::from_millis(2500)));
+    }
+
+    #[test]
+    fn truncate_ips() {
+        assert_eq!("1.2.3.0/24", truncated_ip(ip("1.2.3.4")));
+        assert_eq!("2001:db8:1::/48", truncated_ip(ip("2001:db8:1:2::1")));
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures03::channel::mpsc;
+use futures03::SinkExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::prelude::{anyhow, debug, warn, Logger};
+
+/// How long to wait before accepting connections again after an error
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Paths to a PEM encoded certificate chain and private key that a server
+/// should use to terminate TLS connections.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        TlsConfig {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+        }
+    }
+
+    /// Load the certificate and key and build an acceptor from them.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, anyhow::Error> {
+        let open = |path: &PathBuf| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| anyhow::anyhow!("failed to open `{}`: {}", path.display(), e))
+        };
+
+        let certs = certs(&mut open(&self.cert_path)?).map_err(|()| {
+            anyhow::anyhow!("invalid certificate in `{}`", self.cert_path.display())
+        })?;
+        if certs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "no certificate found in `{}`",
+                self.cert_path.display()
+            ));
+        }
+
+        // Accept both PKCS#8 and the older RSA key format
+        let mut keys = pkcs8_private_keys(&mut open(&self.key_path)?).unwrap_or_default();
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut open(&self.key_path)?).unwrap_or_default();
+        }
+        let key = keys.into_iter().next().ok_or_else(|| {
+            anyhow::anyhow!("no private key found in `{}`", self.key_path.display())
+        })?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.set_single_cert(certs, key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// A connection accepted by `incoming`, which is either plaintext or has
+/// already completed the TLS handshake.
+pub enum ServerStream {
+    Plain(TcpStream, SocketAddr),
+    Tls(TlsStream<TcpStream>, SocketAddr),
+}
+
+impl ServerStream {
+    pub fn peer_addr(&self) -> SocketAddr {
+        match self {
+            ServerStream::Plain(_, addr) | ServerStream::Tls(_, addr) => *addr,
+        }
+    }
+}
+
+impl AsyncRead for ServerStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream, _) => Pin::new(stream).poll_read(cx, buf),
+            ServerStream::Tls(stream, _) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ServerStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream, _) => Pin::new(stream).poll_write(cx, buf),
+            ServerStream::Tls(stream, _) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream, _) => Pin::new(stream).poll_flush(cx),
+            ServerStream::Tls(stream, _) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ServerStream::Plain(stream, _) => Pin::new(stream).poll_shutdown(cx),
+            ServerStream::Tls(stream, _) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Bind to `addr` and return a stream of accepted connections, suitable
+/// for `hyper::server::accept::from_stream`. If `acceptor` is given, TLS
+/// handshakes are performed in their own tasks so that a slow client can
+/// not hold up accepting other connections; connections whose handshake
+/// fails are logged and dropped.
+pub fn incoming(
+    logger: Logger,
+    addr: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+) -> Result<mpsc::Receiver<Result<ServerStream, io::Error>>, io::Error> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    let mut listener = TcpListener::from_std(listener)?;
+
+    let (sender, receiver) = mpsc::channel(100);
+    crate::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // Errors like running out of file descriptors persist
+                    // for a while; retrying right away would just spin
+                    warn!(logger, "Failed to accept connection"; "error" => e.to_string());
+                    tokio::time::delay_for(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let mut sender = sender.clone();
+            match &acceptor {
+                None => {
+                    if sender
+                        .send(Ok(ServerStream::Plain(stream, peer)))
+                        .await
+                        .is_err()
+                    {
+                        // The server is gone
+                        break;
+                    }
+                }
+                Some(acceptor) => {
+                    let acceptor = acceptor.clone();
+                    let logger = logger.clone();
+                    crate::spawn(async move {
+                        match acceptor.accept(stream).await {
+                            Ok(stream) => {
+                                let _ = sender.send(Ok(ServerStream::Tls(stream, peer))).await;
+                            }
+                            Err(e) => debug!(logger, "TLS handshake failed";
+                                             "source" => peer.to_string(),
+                                             "error" => e.to_string()),
+                        }
+                    });
+                }
+            }
+        }
+    });
+    Ok(receiver)
+}
@@ -6,7 +6,8 @@ use mockall::predicate::*;
 use mockall::*;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::TryFrom;
 use std::env;
 use std::fmt;
 use std::str::FromStr;
@@ -29,6 +30,16 @@ lazy_static! {
             )))
             .map(Duration::from_millis)
             .unwrap_or_else(|| Duration::from_millis(1000));
+
+    /// What to do when a handler writes to an entity type that its data
+    /// source does not list in `entities`
+    pub static ref UNDECLARED_ENTITY_WRITES: UndeclaredEntityWrites =
+        env::var("GRAPH_UNDECLARED_ENTITY_WRITES")
+            .ok()
+            .map(|s| s.parse().unwrap_or_else(|e| panic!(
+                "failed to parse env var GRAPH_UNDECLARED_ENTITY_WRITES: {}", e
+            )))
+            .unwrap_or(UndeclaredEntityWrites::Warn);
 }
 
 // Note: Do not modify fields without making a backward compatible change to
@@ -40,7 +51,7 @@ pub struct EntityKey {
     pub subgraph_id: SubgraphDeploymentId,
 
     /// Name of the entity type.
-    pub entity_type: String,
+    pub entity_type: Word,
 
     /// ID of the individual entity.
     pub entity_id: String,
@@ -124,9 +135,9 @@ impl EntityFilter {
 #[derive(Clone, Debug, PartialEq)]
 pub enum EntityOrder {
     /// Order ascending by the given attribute. Use `id` as a tie-breaker
-    Ascending(String, ValueType),
+    Ascending(Attribute, ValueType),
     /// Order descending by the given attribute. Use `id` as a tie-breaker
-    Descending(String, ValueType),
+    Descending(Attribute, ValueType),
     /// Order by the `id` of the entities
     Default,
     /// Do not order at all. This speeds up queries where we know that
@@ -242,6 +253,22 @@ pub enum EntityCollection {
     /// column `b`; they will be grouped by using `A.a` and `B.b` as the keys
     Window(Vec<EntityWindow>),
 }
+
+impl EntityCollection {
+    /// The entity types that this collection draws entities from
+    pub fn entity_types(&self) -> Vec<&str> {
+        match self {
+            EntityCollection::All(entity_types) => {
+                entity_types.iter().map(String::as_str).collect()
+            }
+            EntityCollection::Window(windows) => windows
+                .iter()
+                .map(|window| window.child_type.as_str())
+                .collect(),
+        }
+    }
+}
+
 /// The type we use for block numbers. This has to be a signed integer type
 /// since Postgres does not support unsigned integer types. But 2G ought to
 /// be enough for everybody
@@ -278,6 +305,11 @@ pub struct EntityQuery {
     /// A range to limit the size of the result.
     pub range: EntityRange,
 
+    /// The attributes to fetch for some of the entity types. Entities of
+    /// the types listed here only contain their `id` and these attributes;
+    /// entities of all other types contain all their attributes
+    pub attributes: BTreeMap<String, BTreeSet<String>>,
+
     /// Optional logger for anything related to this query
     pub logger: Option<Logger>,
 
@@ -299,6 +331,7 @@ impl EntityQuery {
             filter: None,
             order: EntityOrder::Default,
             range: EntityRange::first(100),
+            attributes: BTreeMap::new(),
             logger: None,
             query_id: None,
             _force_use_of_new: (),
@@ -342,10 +375,10 @@ impl EntityQuery {
                     if let EntityLink::Direct(attribute, _) = &window.link {
                         let filter = match attribute {
                             WindowAttribute::Scalar(name) => {
-                                EntityFilter::Equal(name.to_owned(), id.into())
+                                EntityFilter::Equal(name.into(), id.into())
                             }
                             WindowAttribute::List(name) => {
-                                EntityFilter::Contains(name.to_owned(), Value::from(vec![id]))
+                                EntityFilter::Contains(name.into(), Value::from(vec![id]))
                             }
                         };
                         self.filter = Some(filter.and_maybe(self.filter));
@@ -379,20 +412,26 @@ pub struct EntityChange {
     pub entity_id: String,
     /// Operation that caused the change.
     pub operation: EntityChangeOperation,
+    /// The names of the fields that an update changed, for deployments that
+    /// track field changes. `None` means that any field may have changed,
+    /// which is always the case when an entity was created or removed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed_fields: Option<BTreeSet<String>>,
 }
 
 impl EntityChange {
     pub fn from_key(key: EntityKey, operation: EntityChangeOperation) -> Self {
         Self {
             subgraph_id: key.subgraph_id,
-            entity_type: key.entity_type,
+            entity_type: key.entity_type.into(),
             entity_id: key.entity_id,
             operation,
+            changed_fields: None,
         }
     }
 
     pub fn subgraph_entity_pair(&self) -> SubgraphEntityPair {
-        (self.subgraph_id.clone(), self.entity_type.clone())
+        (self.subgraph_id.clone(), self.entity_type.to_string())
     }
 }
 
@@ -413,6 +452,18 @@ impl From<MetadataOperation> for Option<EntityChange> {
     }
 }
 
+/// An entity that has several versions whose block ranges overlap, so that
+/// more than one version of it is visible at some blocks
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockRangeOverlap {
+    pub entity_type: String,
+    pub entity_id: String,
+    /// The number of versions of the entity that overlap with another
+    /// version
+    pub versions: i64,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 /// The store emits `StoreEvents` to indicate that some entities have changed.
 /// For block-related data, at most one `StoreEvent` is emitted for each block
@@ -440,15 +491,31 @@ impl<'a> FromIterator<&'a EntityModification> for StoreEvent {
     fn from_iter<I: IntoIterator<Item = &'a EntityModification>>(mods: I) -> Self {
         let changes: Vec<_> = mods
             .into_iter()
-            .map(|op| {
+            .flat_map(|op| {
                 use self::EntityModification::*;
                 match op {
-                    Insert { key, .. } | Overwrite { key, .. } => {
-                        EntityChange::from_key(key.clone(), EntityChangeOperation::Set)
-                    }
-                    Remove { key } => {
-                        EntityChange::from_key(key.clone(), EntityChangeOperation::Removed)
-                    }
+                    Insert { key, .. } => vec![EntityChange::from_key(
+                        key.clone(),
+                        EntityChangeOperation::Set,
+                    )],
+                    Overwrite {
+                        key,
+                        changed_fields,
+                        ..
+                    } => vec![EntityChange {
+                        changed_fields: changed_fields.clone(),
+                        ..EntityChange::from_key(key.clone(), EntityChangeOperation::Set)
+                    }],
+                    Remove { key } => vec![EntityChange::from_key(
+                        key.clone(),
+                        EntityChangeOperation::Removed,
+                    )],
+                    RemoveMany { keys } => keys
+                        .iter()
+                        .map(|key| {
+                            EntityChange::from_key(key.clone(), EntityChangeOperation::Removed)
+                        })
+                        .collect(),
                 }
             })
             .collect();
@@ -706,7 +773,7 @@ impl MetadataOperation {
     pub fn entity_key(entity: MetadataType, id: String) -> EntityKey {
         EntityKey {
             subgraph_id: SUBGRAPHS_ID.clone(),
-            entity_type: entity.to_string(),
+            entity_type: entity.as_str().into(),
             entity_id: id,
         }
     }
@@ -802,8 +869,32 @@ pub enum TransactionAbortError {
     Other(String),
 }
 
-/// Common trait for store implementations.
-pub trait Store: Send + Sync + 'static {
+/// Blocks that the block stream of a deployment scanned for triggers
+/// without finding any: there are none in the blocks after `block` up to
+/// and including `scanned_up_to`. When the deployment is still at `block`
+/// after a restart, the block stream can continue scanning after
+/// `scanned_up_to`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanWatermark {
+    pub block: BlockNumber,
+    pub scanned_up_to: BlockNumber,
+}
+
+impl ScanWatermark {
+    /// The first block that still needs to be scanned when the deployment
+    /// is at `block_ptr`, if the watermark lets the scan skip ahead
+    pub fn resume_from(&self, block_ptr: &EthereumBlockPointer) -> Option<u64> {
+        if self.block as u64 == block_ptr.number && self.scanned_up_to > self.block {
+            Some(self.scanned_up_to as u64 + 1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reading and writing the entities of subgraph deployments, and moving
+/// deployments from block to block
+pub trait EntityStore: Send + Sync + 'static {
     /// Get a pointer to the most recently processed block in the subgraph.
     fn block_ptr(
         &self,
@@ -838,6 +929,28 @@ pub trait Store: Send + Sync + 'static {
         ids_for_type: BTreeMap<&str, Vec<&str>>,
     ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError>;
 
+    /// Look up an entity the way a mapping that processes block `block`
+    /// sees it. That is the latest version of the entity, unless its type
+    /// has a `@ttl` and it expired at `block`. Stores that do not support
+    /// `@ttl` can rely on the default, which is the same as `get`
+    fn get_for_block(
+        &self,
+        key: EntityKey,
+        _block: BlockNumber,
+    ) -> Result<Option<Entity>, QueryExecutionError> {
+        self.get(key)
+    }
+
+    /// Look up multiple entities like `get_for_block` does
+    fn get_many_for_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+        _block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        self.get_many(subgraph_id, ids_for_type)
+    }
+
     /// Queries the store for entities that match the store query.
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError>;
 
@@ -863,18 +976,33 @@ pub trait Store: Send + Sync + 'static {
         stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError>;
 
-    /// Apply the specified metadata operations.
-    fn apply_metadata_operations(
-        &self,
-        operations: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError>;
-
-    /// Build indexes for a set of subgraph entity attributes
-    fn build_entity_attribute_indexes(
+    /// Transact the entity changes from several consecutive blocks atomically
+    /// into the store, and update the subgraph block pointer to the last of
+    /// them. The changes are recorded per block, exactly as if each block had
+    /// been passed to `transact_block_operations` in turn, so that the
+    /// subgraph can later be reverted to any block in the batch.
+    ///
+    /// Return the block at which the subgraph should have its schema
+    /// migrated, if there is one
+    fn transact_block_operations_batch(
         &self,
-        subgraph: &SubgraphDeploymentId,
-        indexes: Vec<AttributeIndexDefinition>,
-    ) -> Result<(), SubgraphAssignmentProviderError>;
+        subgraph_id: SubgraphDeploymentId,
+        blocks: Vec<(EthereumBlockPointer, Vec<EntityModification>)>,
+        stopwatch: StopwatchMetrics,
+    ) -> Result<Option<EthereumBlockPointer>, StoreError> {
+        let mut migrate_at = None;
+        for (block_ptr_to, mods) in blocks {
+            if self.transact_block_operations(
+                subgraph_id.clone(),
+                block_ptr_to,
+                mods,
+                stopwatch.clone(),
+            )? {
+                migrate_at = Some(block_ptr_to);
+            }
+        }
+        Ok(migrate_at)
+    }
 
     /// Revert the entity changes from a single block atomically in the store, and update the
     /// subgraph block pointer from `block_ptr_from` to `block_ptr_to`.
@@ -893,6 +1021,47 @@ pub trait Store: Send + Sync + 'static {
     /// Returns a stream of store events that match the input arguments.
     fn subscribe(&self, entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox;
 
+    /// Return the blocks that the block stream of `subgraph_id` last
+    /// recorded as scanned. Reverting a block moves `scanned_up_to` back
+    /// to the block the deployment is reverted to
+    fn scan_watermark(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<ScanWatermark>, StoreError>;
+
+    /// Record that the block stream of `subgraph_id` scanned the blocks in
+    /// `watermark`, replacing what was recorded before
+    fn set_scan_watermark(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        watermark: ScanWatermark,
+    ) -> Result<(), StoreError>;
+
+    /// Return the number of the block with the given hash for the given
+    /// subgraph
+    fn block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError>;
+
+    /// Get a new `QueryStore`. A `QueryStore` is tied to a DB replica, so if Graph Node is
+    /// configured to use secondary DB servers the queries will be distributed between servers.
+    ///
+    /// If `for_subscription` is true, the main replica will always be used.
+    fn query_store(self: Arc<Self>, for_subscription: bool) -> Arc<dyn QueryStore + Send + Sync>;
+}
+
+/// Creating subgraph deployments and managing their status, errors and
+/// settings
+pub trait DeploymentStore: EntityStore {
+    /// Build indexes for a set of subgraph entity attributes
+    fn build_entity_attribute_indexes(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError>;
+
     /// Find the deployment for the current version of subgraph `name` and
     /// return details about it needed for executing queries
     fn deployment_state_from_name(&self, name: SubgraphName)
@@ -905,6 +1074,160 @@ pub trait Store: Send + Sync + 'static {
         id: SubgraphDeploymentId,
     ) -> Result<DeploymentState, StoreError>;
 
+    /// Check if the store is accepting queries for the specified subgraph.
+    /// May return true even if the specified subgraph is not currently assigned to an indexing
+    /// node, as the store will still accept queries.
+    fn is_deployed(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        // The subgraph of subgraphs is always deployed.
+        if id == &*SUBGRAPHS_ID {
+            return Ok(true);
+        }
+
+        // Check store for a deployment entity for this subgraph ID
+        self.get(SubgraphDeploymentEntity::key(id.to_owned()))
+            .map_err(|e| format_err!("Failed to query SubgraphDeployment entities: {}", e))
+            .map(|entity_opt| entity_opt.is_some())
+    }
+
+    /// Return true if the deployment with the given id is fully synced,
+    /// and return false otherwise. Errors from the store are passed back up
+    fn is_deployment_synced(&self, id: SubgraphDeploymentId) -> Result<bool, Error> {
+        let entity = self.get(SubgraphDeploymentEntity::key(id))?;
+        entity
+            .map(|entity| match entity.get("synced") {
+                Some(Value::Bool(true)) => Ok(true),
+                _ => Ok(false),
+            })
+            .unwrap_or(Ok(false))
+    }
+
+    /// Create a new subgraph deployment. The deployment must not exist yet. `ops`
+    /// needs to contain all the operations on subgraphs and subgraph deployments to
+    /// create the deployment, including any assignments as a current or pending
+    /// version
+    fn create_subgraph_deployment(
+        &self,
+        schema: &Schema,
+        ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError>;
+
+    /// Start an existing subgraph deployment. This will reset the state of
+    /// the subgraph to a known good state. `ops` needs to contain all the
+    /// operations on the subgraph of subgraphs to reset the metadata of the
+    /// subgraph
+    fn start_subgraph_deployment(
+        &self,
+        logger: &Logger,
+        subgraph_id: &SubgraphDeploymentId,
+        ops: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError>;
+
+    /// Mark the deployment `id` as failed because of `error`. If the last
+    /// error of the deployment was the same, it is not stored again; its
+    /// count and the last block in which it happened are updated instead
+    fn fail_subgraph(
+        &self,
+        id: &SubgraphDeploymentId,
+        error: SubgraphError,
+    ) -> Result<(), StoreError>;
+
+    /// Try to perform a pending migration for a subgraph schema. Even if a
+    /// subgraph has a pending schema migration, this method might not actually
+    /// perform the migration because of limits on the total number of
+    /// migrations that can happen at the same time across the whole system.
+    ///
+    /// Any errors happening during the migration will be logged as warnings
+    /// on `logger`, but otherwise ignored
+    fn migrate_subgraph_deployment(
+        &self,
+        logger: &Logger,
+        subgraph_id: &SubgraphDeploymentId,
+        block_ptr: &EthereumBlockPointer,
+    );
+
+    /// Take a deployment that was recorded as unused off that list so
+    /// that it does not get removed. Return `false` if the deployment was
+    /// not recorded as unused
+    fn restore_unused_deployment(&self, id: &SubgraphDeploymentId) -> Result<bool, StoreError>;
+
+    /// The maximum size of the WASM memory, in bytes, that the handlers of
+    /// the deployment `id` may use if it was changed from the default with
+    /// `set_memory_limit`
+    fn memory_limit(&self, id: &SubgraphDeploymentId) -> Result<Option<u64>, StoreError>;
+
+    /// Change the memory limit of the deployment `id`; `None` resets it to
+    /// the default. Return `false` if the deployment does not exist
+    fn set_memory_limit(
+        &self,
+        id: &SubgraphDeploymentId,
+        limit: Option<u64>,
+    ) -> Result<bool, StoreError>;
+
+    /// Flag the deployment `id` as unhealthy with the operator's `note`,
+    /// or remove the flag if `note` is `None`. Flagging a deployment that
+    /// is already flagged replaces its note and the time it was flagged.
+    /// Return `false` if the deployment does not exist
+    fn set_deployment_flag(
+        &self,
+        id: &SubgraphDeploymentId,
+        note: Option<String>,
+    ) -> Result<bool, StoreError>;
+
+    /// Only keep `history_blocks` blocks of entity history for the
+    /// deployment `id`; `None` keeps all history. Return `false` if the
+    /// deployment does not exist
+    fn set_history_blocks(
+        &self,
+        id: &SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<bool, StoreError>;
+
+    /// Turn strict handling of bytes and hex strings in the entities that
+    /// mappings write on or off for the deployment `id`. Return `false` if
+    /// the deployment does not exist
+    fn set_strict_bytes(
+        &self,
+        id: &SubgraphDeploymentId,
+        strict_bytes: bool,
+    ) -> Result<bool, StoreError>;
+
+    /// Remember `module` as the WASM module that computes the `@computed`
+    /// fields of the deployment `id`. Return `false` if the deployment does
+    /// not exist
+    fn set_computed_module(
+        &self,
+        id: &SubgraphDeploymentId,
+        module: &[u8],
+    ) -> Result<bool, StoreError>;
+
+    /// Find the entities of the deployment `id` that have versions whose
+    /// block ranges overlap. Deployments that use JSONB storage do not
+    /// have block ranges and never have overlaps
+    fn check_block_ranges(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError>;
+
+    /// Resolve all overlapping block ranges of the deployment `id` so that
+    /// exactly one version of each entity is visible at any block. Where
+    /// versions overlap, the version with the latest start wins, and the
+    /// block ranges of earlier versions end where it starts. Return the
+    /// overlaps that were found before the repair
+    fn repair_block_ranges(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError>;
+}
+
+/// Subgraph names, their versions, and the assignment of deployments to
+/// nodes
+pub trait SubgraphNameStore: EntityStore {
+    /// Apply the specified metadata operations.
+    fn apply_metadata_operations(
+        &self,
+        operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError>;
+
     /// Read all version entities pointing to the specified deployment IDs and
     /// determine whether they are current or pending in order to produce
     /// `SubgraphVersionSummary`s.
@@ -1089,86 +1412,19 @@ pub trait Store: Send + Sync + 'static {
 
         ops
     }
+}
 
-    /// Check if the store is accepting queries for the specified subgraph.
-    /// May return true even if the specified subgraph is not currently assigned to an indexing
-    /// node, as the store will still accept queries.
-    fn is_deployed(&self, id: &SubgraphDeploymentId) -> Result<bool, Error> {
-        // The subgraph of subgraphs is always deployed.
-        if id == &*SUBGRAPHS_ID {
-            return Ok(true);
-        }
-
-        // Check store for a deployment entity for this subgraph ID
-        self.get(SubgraphDeploymentEntity::key(id.to_owned()))
-            .map_err(|e| format_err!("Failed to query SubgraphDeployment entities: {}", e))
-            .map(|entity_opt| entity_opt.is_some())
-    }
-
-    /// Return true if the deployment with the given id is fully synced,
-    /// and return false otherwise. Errors from the store are passed back up
-    fn is_deployment_synced(&self, id: SubgraphDeploymentId) -> Result<bool, Error> {
-        let entity = self.get(SubgraphDeploymentEntity::key(id))?;
-        entity
-            .map(|entity| match entity.get("synced") {
-                Some(Value::Bool(true)) => Ok(true),
-                _ => Ok(false),
-            })
-            .unwrap_or(Ok(false))
-    }
-
-    /// Create a new subgraph deployment. The deployment must not exist yet. `ops`
-    /// needs to contain all the operations on subgraphs and subgraph deployments to
-    /// create the deployment, including any assignments as a current or pending
-    /// version
-    fn create_subgraph_deployment(
-        &self,
-        schema: &Schema,
-        ops: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError>;
-
-    /// Start an existing subgraph deployment. This will reset the state of
-    /// the subgraph to a known good state. `ops` needs to contain all the
-    /// operations on the subgraph of subgraphs to reset the metadata of the
-    /// subgraph
-    fn start_subgraph_deployment(
-        &self,
-        logger: &Logger,
-        subgraph_id: &SubgraphDeploymentId,
-        ops: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError>;
-
-    /// Try to perform a pending migration for a subgraph schema. Even if a
-    /// subgraph has a pending schema migration, this method might not actually
-    /// perform the migration because of limits on the total number of
-    /// migrations that can happen at the same time across the whole system.
-    ///
-    /// Any errors happening during the migration will be logged as warnings
-    /// on `logger`, but otherwise ignored
-    fn migrate_subgraph_deployment(
-        &self,
-        logger: &Logger,
-        subgraph_id: &SubgraphDeploymentId,
-        block_ptr: &EthereumBlockPointer,
-    );
-
-    /// Return the number of the block with the given hash for the given
-    /// subgraph
-    fn block_number(
-        &self,
-        subgraph_id: &SubgraphDeploymentId,
-        block_hash: H256,
-    ) -> Result<Option<BlockNumber>, StoreError>;
+/// Common trait for store implementations. Code that only needs some of
+/// what a store can do should depend on the narrower `EntityStore`,
+/// `DeploymentStore` or `SubgraphNameStore` instead
+pub trait Store: EntityStore + DeploymentStore + SubgraphNameStore {}
 
-    /// Get a new `QueryStore`. A `QueryStore` is tied to a DB replica, so if Graph Node is
-    /// configured to use secondary DB servers the queries will be distributed between servers.
-    ///
-    /// If `for_subscription` is true, the main replica will always be used.
-    fn query_store(self: Arc<Self>, for_subscription: bool) -> Arc<dyn QueryStore + Send + Sync>;
-}
+impl<S: EntityStore + DeploymentStore + SubgraphNameStore> Store for S {}
 
 mock! {
     pub Store {
+        fn get_mock(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError>;
+
         fn get_many_mock<'a>(
             &self,
             _subgraph_id: &SubgraphDeploymentId,
@@ -1182,7 +1438,8 @@ mock! {
 pub type PoolWaitStats = Arc<RwLock<MovingStats>>;
 
 // The store trait must be implemented manually because mockall does not support async_trait, nor borrowing from arguments.
-impl Store for MockStore {
+// The mock only implements `EntityStore` since that is all that the `EntityCache` needs.
+impl EntityStore for MockStore {
     fn block_ptr(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -1206,8 +1463,8 @@ impl Store for MockStore {
         unimplemented!();
     }
 
-    fn get(&self, _key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        unimplemented!()
+    fn get(&self, key: EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        self.get_mock(key)
     }
 
     fn get_many(
@@ -1240,21 +1497,6 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn apply_metadata_operations(
-        &self,
-        _operations: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError> {
-        unimplemented!()
-    }
-
-    fn build_entity_attribute_indexes(
-        &self,
-        _subgraph: &SubgraphDeploymentId,
-        _indexes: Vec<AttributeIndexDefinition>,
-    ) -> Result<(), SubgraphAssignmentProviderError> {
-        unimplemented!()
-    }
-
     fn revert_block_operations(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -1268,40 +1510,18 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn deployment_state_from_name(&self, _: SubgraphName) -> Result<DeploymentState, StoreError> {
-        unimplemented!()
-    }
-
-    fn deployment_state_from_id(
-        &self,
-        _: SubgraphDeploymentId,
-    ) -> Result<DeploymentState, StoreError> {
-        unimplemented!()
-    }
-
-    fn create_subgraph_deployment(
+    fn scan_watermark(
         &self,
-        _schema: &Schema,
-        _ops: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError> {
-        unimplemented!()
-    }
-
-    fn start_subgraph_deployment(
-        &self,
-        _logger: &Logger,
         _subgraph_id: &SubgraphDeploymentId,
-        _ops: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError> {
+    ) -> Result<Option<ScanWatermark>, StoreError> {
         unimplemented!()
     }
 
-    fn migrate_subgraph_deployment(
+    fn set_scan_watermark(
         &self,
-        _logger: &Logger,
         _subgraph_id: &SubgraphDeploymentId,
-        _block_ptr: &EthereumBlockPointer,
-    ) {
+        _watermark: ScanWatermark,
+    ) -> Result<(), StoreError> {
         unimplemented!()
     }
 
@@ -1336,6 +1556,218 @@ pub trait SubgraphDeploymentStore: Send + Sync + 'static {
     /// Return the name of the network that the subgraph is indexing from. The
     /// names returned are things like `mainnet` or `ropsten`
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+    /// Return `true` if the manifest of the subgraph sets `strictBytes`
+    fn strict_bytes(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error>;
+
+    /// Return the WASM module that computes the `@computed` fields of the
+    /// subgraph, if its manifest has one
+    fn computed_module(&self, subgraph_id: &SubgraphDeploymentId)
+        -> Result<Option<Vec<u8>>, Error>;
+
+    /// Return the inconsistencies in subgraph metadata that were found the
+    /// last time the metadata was checked
+    fn health_issues(&self) -> Result<Vec<HealthIssue>, Error>;
+
+    /// Return all deployments that an operator flagged as unhealthy
+    fn flagged_deployments(&self) -> Result<BTreeMap<SubgraphDeploymentId, DeploymentFlag>, Error>;
+
+    /// Return the deployments that `subgraph_id` is grafted onto and the
+    /// ones that are grafted onto it, or `None` if it does not exist
+    fn graft_lineage(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<GraftLineage>, Error>;
+}
+
+/// Which provider served data that is cached in the store, and when it was
+/// fetched from it. Data that was cached before we recorded this has an
+/// unknown provider and no fetch time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provenance {
+    /// The `provider_id` of the provider, or `unknown`
+    pub provider: String,
+    /// When the data was fetched, in seconds since the Unix epoch
+    pub fetched_at: Option<u64>,
+}
+
+impl Provenance {
+    pub const UNKNOWN_PROVIDER: &'static str = "unknown";
+
+    pub fn new(provider: Option<String>, fetched_at: Option<u64>) -> Self {
+        Provenance {
+            provider: provider.unwrap_or_else(|| Self::UNKNOWN_PROVIDER.to_owned()),
+            fetched_at,
+        }
+    }
+}
+
+/// A block in the block cache of a `ChainStore`, without its data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedBlock {
+    pub ptr: EthereumBlockPointer,
+    pub parent_hash: Option<H256>,
+    pub provenance: Provenance,
+}
+
+/// A chain head that a provider announced
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadAnnouncement {
+    pub block: EthereumBlockPointer,
+    /// When we received the announcement, in seconds since the Unix epoch
+    pub received_at: u64,
+}
+
+/// An entry in the call cache
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedEthereumCall {
+    pub contract_address: Address,
+    pub block_number: u64,
+    /// The return value of the call, or the data it reverted with
+    pub return_value: Vec<u8>,
+    pub revert_reason: Option<String>,
+    pub provenance: Provenance,
+}
+
+/// An inconsistency in the blocks that a `ChainStore` has cached
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BlockGap {
+    /// There is no block with this number
+    Missing(u64),
+    /// The block `hash` points to a parent that is not in the store even
+    /// though the store has other blocks with number `number - 1`
+    BrokenLink {
+        number: u64,
+        hash: H256,
+        parent_hash: H256,
+    },
+}
+
+impl BlockGap {
+    pub fn number(&self) -> u64 {
+        match self {
+            BlockGap::Missing(number) => *number,
+            BlockGap::BrokenLink { number, .. } => *number,
+        }
+    }
+}
+
+/// The ways in which the metadata that ties subgraph names, deployments
+/// and their storage together can be inconsistent
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HealthIssueKind {
+    /// A deployment is assigned to a node, but the deployment does not exist
+    AssignmentWithoutDeployment,
+    /// A deployment exists, but there is no database schema for its data
+    DeploymentWithoutSchema,
+    /// There is a database schema for a deployment that does not exist
+    SchemaWithoutDeployment,
+    /// A subgraph version points to a deployment that does not exist
+    VersionWithoutDeployment,
+    /// The current or pending version of a subgraph name does not exist
+    NameWithoutVersion,
+}
+
+impl HealthIssueKind {
+    pub fn as_str(&self) -> &'static str {
+        use HealthIssueKind::*;
+        match self {
+            AssignmentWithoutDeployment => "assignment_without_deployment",
+            DeploymentWithoutSchema => "deployment_without_schema",
+            SchemaWithoutDeployment => "schema_without_deployment",
+            VersionWithoutDeployment => "version_without_deployment",
+            NameWithoutVersion => "name_without_version",
+        }
+    }
+}
+
+impl FromStr for HealthIssueKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use HealthIssueKind::*;
+        match s {
+            "assignment_without_deployment" => Ok(AssignmentWithoutDeployment),
+            "deployment_without_schema" => Ok(DeploymentWithoutSchema),
+            "schema_without_deployment" => Ok(SchemaWithoutDeployment),
+            "version_without_deployment" => Ok(VersionWithoutDeployment),
+            "name_without_version" => Ok(NameWithoutVersion),
+            _ => Err(format_err!("unknown health issue kind `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for HealthIssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// What was done about a `HealthIssue` when it was found
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HealthRemediation {
+    /// Nothing; the issue needs to be fixed by hand
+    None,
+    /// The affected deployment is not started by this node
+    Skipped,
+    /// The issue was repaired
+    Repaired,
+}
+
+impl HealthRemediation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthRemediation::None => "none",
+            HealthRemediation::Skipped => "skipped",
+            HealthRemediation::Repaired => "repaired",
+        }
+    }
+}
+
+impl FromStr for HealthRemediation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(HealthRemediation::None),
+            "skipped" => Ok(HealthRemediation::Skipped),
+            "repaired" => Ok(HealthRemediation::Repaired),
+            _ => Err(format_err!("unknown health remediation `{}`", s)),
+        }
+    }
+}
+
+/// An inconsistency in subgraph metadata that the store found when it
+/// checked the metadata at startup
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HealthIssue {
+    pub kind: HealthIssueKind,
+    /// The deployment that is affected, if any
+    pub deployment: Option<String>,
+    /// The subgraph name that is affected, if any
+    pub subgraph_name: Option<String>,
+    pub message: String,
+    pub remediation: HealthRemediation,
+}
+
+/// A deployment that another deployment is grafted onto
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraftBase {
+    pub deployment: SubgraphDeploymentId,
+    /// The block up to which the data of `deployment` was copied
+    pub block: EthereumBlockPointer,
+}
+
+/// How a deployment is tied to other deployments through grafts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraftLineage {
+    /// The deployment that this deployment is grafted onto, the one that
+    /// that deployment is grafted onto, and so on
+    pub bases: Vec<GraftBase>,
+    /// The deployments that are grafted onto this deployment, directly or
+    /// through other deployments. Those that are grafted directly onto it
+    /// come first. A deployment can not be removed while it has dependents
+    pub dependents: Vec<SubgraphDeploymentId>,
 }
 
 /// Common trait for blockchain store implementations.
@@ -1345,8 +1777,10 @@ pub trait ChainStore: Send + Sync + 'static {
     fn genesis_block_ptr(&self) -> Result<EthereumBlockPointer, Error>;
 
     /// Insert blocks into the store (or update if they are already present).
+    /// The blocks were fetched from the provider with `provider_id`
     fn upsert_blocks<B, E>(
         &self,
+        _provider: String,
         _blocks: B,
     ) -> Box<dyn Future<Item = (), Error = E> + Send + 'static>
     where
@@ -1357,7 +1791,13 @@ pub trait ChainStore: Send + Sync + 'static {
         unimplemented!()
     }
 
-    fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error>;
+    /// Insert blocks without receipts that were fetched from the provider
+    /// with `provider_id`; blocks that are already present are kept
+    fn upsert_light_blocks(
+        &self,
+        provider: &str,
+        blocks: Vec<LightEthereumBlock>,
+    ) -> Result<(), Error>;
 
     /// Try to update the head block pointer to the block with the highest block number.
     ///
@@ -1408,31 +1848,89 @@ pub trait ChainStore: Send + Sync + 'static {
     /// the chain head.
     fn cleanup_cached_blocks(&self, ancestor_count: u64) -> Result<(BlockNumber, usize), Error>;
 
-    /// Return the hashes of all blocks with the given number
+    /// Return the hashes of all blocks with the given number. If one of
+    /// them is on the chain that ends in the current chain head, it comes
+    /// first; all others are ordered lexicographically by hash.
     fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
 
+    /// Like `block_hashes_by_block_number`, but return pairs of
+    /// `(hash, parent_hash)` in the same order so that callers can resolve
+    /// forks without looking up the ancestors of each candidate
+    fn block_hashes_and_parents_by_block_number(
+        &self,
+        number: u64,
+    ) -> Result<Vec<(H256, H256)>, Error>;
+
+    /// Return the hash of the block with the given number on the chain that
+    /// ends in the current chain head, or `None` if there is no block with
+    /// that number. Fails if there are several blocks with that number and
+    /// none of them is known to be on that chain, for example because there
+    /// is no chain head or the block cache has a gap between the chain head
+    /// and the block
+    fn canonical_block_hash_by_block_number(&self, number: u64) -> Result<Option<H256>, Error>;
+
     /// Confirm that block number `number` has hash `hash` and that the store
     /// may purge any other blocks with that number
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+    /// Find the inconsistencies in the cached blocks with numbers from
+    /// `from` to `to`, inclusive, ordered by block number. Parent links are
+    /// only checked for blocks after `from`
+    fn find_gaps(&self, from: u64, to: u64) -> Result<Vec<BlockGap>, Error>;
+
+    /// The block with `hash` and where it came from, if the store has it
+    fn cached_block(&self, hash: H256) -> Result<Option<CachedBlock>, Error>;
+
+    /// Replace the head announcements that are stored for the provider
+    /// with hostname `provider` with `announcements`
+    fn save_head_announcements(
+        &self,
+        provider: &str,
+        announcements: &[HeadAnnouncement],
+    ) -> Result<(), Error>;
+
+    /// The stored head announcements of each provider, oldest first
+    fn head_announcements(&self) -> Result<Vec<(String, Vec<HeadAnnouncement>)>, Error>;
 }
 
 pub trait EthereumCallCache: Send + Sync + 'static {
-    /// Cached return value.
+    /// Cached return value, or why the call reverted.
     fn get_call(
         &self,
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-    ) -> Result<Option<Vec<u8>>, Error>;
+    ) -> Result<Option<Result<Vec<u8>, EthereumCallRevert>>, Error>;
 
-    // Add entry to the cache.
+    // Add entry to the cache; `provider` is the `provider_id` of the
+    // provider that made the call.
     fn set_call(
         &self,
+        provider: &str,
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
         return_value: &[u8],
     ) -> Result<(), Error>;
+
+    // Add a call that reverted to the cache.
+    fn set_call_revert(
+        &self,
+        provider: &str,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        revert: &EthereumCallRevert,
+    ) -> Result<(), Error>;
+
+    /// The cached calls for block number `block_number`, optionally only
+    /// those to `contract_address`, ordered by contract address
+    fn cached_calls(
+        &self,
+        block_number: u64,
+        contract_address: Option<ethabi::Address>,
+        limit: usize,
+    ) -> Result<Vec<CachedEthereumCall>, Error>;
 }
 
 /// Store operations used when serving queries
@@ -1454,17 +1952,41 @@ pub trait QueryStore: Send + Sync {
 pub enum EntityModification {
     /// Insert the entity
     Insert { key: EntityKey, data: Entity },
-    /// Update the entity by overwriting it
-    Overwrite { key: EntityKey, data: Entity },
+    /// Update the entity by overwriting it. For deployments that track
+    /// field changes, `changed_fields` lists the fields that differ from
+    /// the previous version of the entity
+    Overwrite {
+        key: EntityKey,
+        data: Entity,
+        changed_fields: Option<BTreeSet<String>>,
+    },
     /// Remove the entity
     Remove { key: EntityKey },
+    /// Remove several entities of the same type in the same subgraph at
+    /// once. There are always at least two `keys`
+    RemoveMany { keys: Vec<EntityKey> },
 }
 
 impl EntityModification {
+    /// The key of the entity that is modified. For `RemoveMany`, this is
+    /// the first of its keys; the subgraph and entity type are the same
+    /// for all of them
     pub fn entity_key(&self) -> &EntityKey {
         use EntityModification::*;
         match self {
             Insert { key, .. } | Overwrite { key, .. } | Remove { key } => key,
+            RemoveMany { keys } => &keys[0],
+        }
+    }
+
+    /// The keys of all the entities that are modified
+    pub fn entity_keys(&self) -> &[EntityKey] {
+        use EntityModification::*;
+        match self {
+            Insert { key, .. } | Overwrite { key, .. } | Remove { key } => {
+                std::slice::from_ref(key)
+            }
+            RemoveMany { keys } => keys,
         }
     }
 
@@ -1475,19 +1997,73 @@ impl EntityModification {
 
     pub fn is_remove(&self) -> bool {
         match self {
-            EntityModification::Remove { .. } => true,
+            EntityModification::Remove { .. } | EntityModification::RemoveMany { .. } => true,
             _ => false,
         }
     }
 }
 
+/// What `EntityCache` does when a handler writes to an entity type that the
+/// `entities` of the handler's data source do not list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndeclaredEntityWrites {
+    /// Allow the write
+    Ignore,
+    /// Allow the write, but report it
+    Warn,
+    /// Fail the handler with a deterministic error
+    Error,
+}
+
+impl FromStr for UndeclaredEntityWrites {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(UndeclaredEntityWrites::Ignore),
+            "warn" => Ok(UndeclaredEntityWrites::Warn),
+            "error" => Ok(UndeclaredEntityWrites::Error),
+            _ => Err(format!("`{}` is not one of `ignore`, `warn` or `error`", s)),
+        }
+    }
+}
+
+/// The entity types that the data source of a handler declares, and what
+/// to do when the handler writes to others
+#[derive(Clone, Debug)]
+pub struct DeclaredEntities {
+    pub mode: UndeclaredEntityWrites,
+    pub entity_types: Arc<BTreeSet<String>>,
+}
+
+impl DeclaredEntities {
+    pub fn new(mode: UndeclaredEntityWrites, entity_types: &[String]) -> Self {
+        DeclaredEntities {
+            mode,
+            entity_types: Arc::new(entity_types.iter().cloned().collect()),
+        }
+    }
+}
+
+/// Checks entities against the schema of their subgraph. The `EntityCache`
+/// uses it for entities that a handler only set partially, once all
+/// handlers for the block have run and the entity has its final state
+pub trait EntityValidator: Send + Sync {
+    fn validate(&self, key: &EntityKey, entity: &Entity) -> Result<(), anyhow::Error>;
+}
+
 /// A cache for entities from the store that provides the basic functionality
 /// needed for the store interactions in the host exports. This struct tracks
 /// how entities are modified, and caches all entities looked up from the
-/// store. The cache makes sure that
+/// store, including the ones that do not exist. The cache makes sure that
 ///   (1) no entity appears in more than one operation
 ///   (2) only entities that will actually be changed from what they
 ///       are in the store are changed
+///
+/// Entities that the store did not have are only cached as missing until
+/// the end of the block so that they do not pile up in the cache. Entities
+/// that were removed stay cached as missing, since the removal may not have
+/// been written to the store when the cache is used for the next block
 #[derive(Clone)]
 pub struct EntityCache {
     /// The state of entities in the store. An entry of `None`
@@ -1495,10 +2071,40 @@ pub struct EntityCache {
     current: LfuCache<EntityKey, Option<Entity>>,
 
     /// The accumulated changes to an entity. An entry of `None`
-    /// means that the entity should be deleted
+    /// means that the entity should be deleted. Repeated changes to the
+    /// same entity are merged into its entry in place
     updates: BTreeMap<EntityKey, Option<Entity>>,
 
-    pub store: Arc<dyn Store>,
+    /// Entities that `as_modifications` needs to validate before it
+    /// writes them
+    unvalidated: BTreeMap<EntityKey, Arc<dyn EntityValidator>>,
+
+    /// How many lookups of entities that are not in the store were
+    /// answered by the cache without querying the store
+    cached_misses: u64,
+
+    /// The entities that lookups in the store did not find during this
+    /// block; `as_modifications` drops them from `current` again
+    store_misses: BTreeSet<EntityKey>,
+
+    /// Whether `as_modifications` records which fields of updated entities
+    /// changed
+    track_field_changes: bool,
+
+    /// The entity types that the handler that is running may write to;
+    /// `None` if writes are not checked
+    declared_entities: Option<DeclaredEntities>,
+
+    /// How often the running handler wrote to each entity type that its
+    /// data source does not declare, when such writes are only reported
+    undeclared_writes: BTreeMap<String, u64>,
+
+    /// The block that the mappings which use this cache process; entities
+    /// are read from the store as of this block. This is `BLOCK_NUMBER_MAX`
+    /// unless `set_block` was called
+    block: BlockNumber,
+
+    pub store: Arc<dyn EntityStore>,
 }
 
 impl Debug for EntityCache {
@@ -1516,51 +2122,257 @@ pub struct ModificationsAndCache {
 }
 
 impl EntityCache {
-    pub fn new(store: Arc<dyn Store>) -> Self {
+    pub fn new(store: Arc<dyn EntityStore>) -> Self {
         Self {
             current: LfuCache::new(),
             updates: BTreeMap::new(),
+            unvalidated: BTreeMap::new(),
+            cached_misses: 0,
+            store_misses: BTreeSet::new(),
+            track_field_changes: false,
+            declared_entities: None,
+            undeclared_writes: BTreeMap::new(),
+            block: BLOCK_NUMBER_MAX,
             store,
         }
     }
 
+    /// A cache for a new block that starts out with the entities in
+    /// `current`, which usually is the cache from the previous block
     pub fn with_current(
-        store: Arc<dyn Store>,
+        store: Arc<dyn EntityStore>,
         current: LfuCache<EntityKey, Option<Entity>>,
     ) -> EntityCache {
         EntityCache {
             current,
             updates: BTreeMap::new(),
+            unvalidated: BTreeMap::new(),
+            cached_misses: 0,
+            store_misses: BTreeSet::new(),
+            track_field_changes: false,
+            declared_entities: None,
+            undeclared_writes: BTreeMap::new(),
+            block: BLOCK_NUMBER_MAX,
             store,
         }
     }
 
+    /// Read entities from the store as a mapping that processes `block`
+    /// sees them, where the entities of the types in `expiring` expire.
+    /// Entities of these types that were cached in an earlier block are
+    /// dropped since they may have expired since then. The caller must make
+    /// sure that all changes to them are in the store
+    pub fn set_block(&mut self, block: BlockNumber, expiring: &BTreeSet<String>) {
+        self.block = block;
+        if !expiring.is_empty() {
+            self.current
+                .retain(|key, _| !expiring.contains(key.entity_type.as_str()));
+        }
+    }
+
+    /// Check that `set` and `remove` only write to the entity types in
+    /// `declared` until `clear_declared_entities` is called. This is meant
+    /// to be called before a handler runs
+    pub fn declare_entities(&mut self, declared: DeclaredEntities) {
+        self.declared_entities = Some(declared);
+    }
+
+    /// Stop checking writes, and return how often each undeclared entity
+    /// type was written to since `declare_entities` if these writes are
+    /// only reported
+    pub fn clear_declared_entities(&mut self) -> BTreeMap<String, u64> {
+        self.declared_entities = None;
+        std::mem::take(&mut self.undeclared_writes)
+    }
+
+    fn check_declared(&mut self, key: &EntityKey) -> Result<(), QueryExecutionError> {
+        let declared = match &self.declared_entities {
+            Some(declared) if !declared.entity_types.contains(key.entity_type.as_str()) => declared,
+            _ => return Ok(()),
+        };
+        match declared.mode {
+            UndeclaredEntityWrites::Ignore => Ok(()),
+            UndeclaredEntityWrites::Warn => {
+                *self
+                    .undeclared_writes
+                    .entry(key.entity_type.to_string())
+                    .or_insert(0) += 1;
+                Ok(())
+            }
+            UndeclaredEntityWrites::Error => Err(QueryExecutionError::UndeclaredEntityType(
+                key.entity_type.to_string(),
+            )),
+        }
+    }
+
+    /// Make `as_modifications` record which fields of updated entities
+    /// changed so that store events can report them. Since the previous
+    /// version of an entity is needed for the update anyway, this costs no
+    /// additional queries
+    pub fn track_field_changes(&mut self, track: bool) {
+        self.track_field_changes = track;
+    }
+
+    /// How many lookups of entities that are not in the store did not need
+    /// a query because the cache already knew that
+    pub fn cached_misses(&self) -> u64 {
+        self.cached_misses
+    }
+
     pub fn get(&mut self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
-        let current = self.current.get_entity(&*self.store, &key)?;
+        let (current, cached_miss) = match self.current.get(key) {
+            Some(None) => (None, true),
+            Some(Some(entity)) => (Some(entity.clone()), false),
+            None => (
+                self.current
+                    .get_entity(&*self.store, key, self.block, &mut self.store_misses)?,
+                false,
+            ),
+        };
         let updates = self.updates.get(&key).cloned();
-        match (current, updates) {
+        let entity = match (current, updates) {
             // Entity is unchanged
-            (current, None) => Ok(current),
+            (current, None) => current,
             // Entity was deleted
-            (_, Some(None)) => Ok(None),
+            (_, Some(None)) => None,
             // Entity created
-            (None, Some(updates)) => Ok(updates),
+            (None, Some(updates)) => updates,
             // Entity updated
             (Some(current), Some(Some(updates))) => {
                 let mut current = current;
                 current.merge_remove_null_fields(updates);
-                Ok(Some(current))
+                Some(current)
             }
+        };
+        if cached_miss && entity.is_none() {
+            self.cached_misses += 1;
         }
+        Ok(entity)
     }
 
-    pub fn remove(&mut self, key: EntityKey) {
+    pub fn remove(&mut self, key: EntityKey) -> Result<(), QueryExecutionError> {
+        self.check_declared(&key)?;
+        self.unvalidated.remove(&key);
         self.updates.insert(key, None);
+        Ok(())
+    }
+
+    /// Remove the entities of type `entity_type` with the given `ids`. This
+    /// is the same as calling `remove` for each of them: changes to them
+    /// that were made earlier are discarded, and an entity that is set
+    /// again afterwards, even in the same block, exists with only the data
+    /// from that later `set`
+    pub fn remove_many(
+        &mut self,
+        subgraph_id: &SubgraphDeploymentId,
+        entity_type: &str,
+        ids: Vec<String>,
+    ) -> Result<(), QueryExecutionError> {
+        for entity_id in ids {
+            let key = EntityKey {
+                subgraph_id: subgraph_id.clone(),
+                entity_type: entity_type.into(),
+                entity_id,
+            };
+            self.check_declared(&key)?;
+            self.unvalidated.remove(&key);
+            self.updates.insert(key, None);
+        }
+        Ok(())
+    }
+
+    /// Like `set`, but for an entity that is not valid on its own, for
+    /// example because it only has some of its fields. The entity is
+    /// checked with `validator` in `as_modifications`, after it has been
+    /// merged with the version in the store, so that a handler that sets
+    /// the same entity many times only pays for that once
+    pub fn set_unvalidated(
+        &mut self,
+        key: EntityKey,
+        entity: Entity,
+        validator: Arc<dyn EntityValidator>,
+    ) -> Result<(), QueryExecutionError> {
+        self.set(key.clone(), entity)?;
+        self.unvalidated.insert(key, validator);
+        Ok(())
+    }
+
+    /// Add `amount` to the numeric `field` of an existing entity without
+    /// loading the whole entity into the handler. `Int` fields fail when
+    /// the result does not fit into an `Int`
+    pub fn increment(
+        &mut self,
+        key: EntityKey,
+        field: &str,
+        amount: &BigInt,
+    ) -> Result<(), QueryExecutionError> {
+        self.check_declared(&key)?;
+
+        let invalid = |key: &EntityKey, reason: &str| {
+            QueryExecutionError::InvalidIncrement(
+                format!("{}[{}]", key.entity_type, key.entity_id),
+                field.to_owned(),
+                reason.to_owned(),
+            )
+        };
+
+        // The field is usually in the pending update already when the
+        // same entity is incremented repeatedly
+        let pending = match self.updates.get(&key) {
+            Some(None) => return Err(invalid(&key, "the entity was removed")),
+            Some(Some(update)) => update.get(field).cloned(),
+            None => None,
+        };
+        let value = match pending {
+            Some(value) => value,
+            None => match self.current.get_entity(
+                &*self.store,
+                &key,
+                self.block,
+                &mut self.store_misses,
+            )? {
+                Some(mut current) => current.remove(field).unwrap_or(Value::Null),
+                None if self.updates.contains_key(&key) => Value::Null,
+                None => return Err(invalid(&key, "the entity does not exist")),
+            },
+        };
+
+        let value = match value {
+            Value::Int(n) => i64::try_from(amount)
+                .ok()
+                .and_then(|amount| (n as i64).checked_add(amount))
+                .and_then(|sum| i32::try_from(sum).ok())
+                .map(Value::Int)
+                .ok_or_else(|| invalid(&key, "the result does not fit into an Int"))?,
+            Value::BigInt(n) => Value::BigInt(n + amount.clone()),
+            Value::BigDecimal(n) => Value::BigDecimal(n + BigDecimal::new(amount.clone(), 0)),
+            Value::Null => return Err(invalid(&key, "the field is not set")),
+            value => {
+                return Err(invalid(
+                    &key,
+                    &format!("the field has type {}, not a number", value.type_name()),
+                ))
+            }
+        };
+
+        match self.updates.get_mut(&key) {
+            Some(Some(update)) => {
+                update.insert(field, value);
+            }
+            _ => {
+                let mut update = Entity::new();
+                update.insert(field, value);
+                self.updates.insert(key, Some(update));
+            }
+        }
+        Ok(())
     }
 
     pub fn set(&mut self, key: EntityKey, mut entity: Entity) -> Result<(), QueryExecutionError> {
         use std::collections::btree_map::Entry;
 
+        self.check_declared(&key)?;
+
         let update = self.updates.entry(key.clone());
 
         match update {
@@ -1575,7 +2387,12 @@ impl EntityCache {
 
                 // Previous change was a removal, clear fields in `current`.
                 None => {
-                    if let Some(current) = self.current.get_entity(&*self.store, &key)? {
+                    if let Some(current) = self.current.get_entity(
+                        &*self.store,
+                        &key,
+                        self.block,
+                        &mut self.store_misses,
+                    )? {
                         // Entity was removed so the fields not updated need to be unset.
                         for field in current.keys().cloned() {
                             entity.entry(field).or_insert(Value::Null);
@@ -1596,7 +2413,7 @@ impl EntityCache {
                     self.set(key, data)?;
                 }
                 EntityOperation::Remove { key } => {
-                    self.remove(key);
+                    self.remove(key)?;
                 }
             }
         }
@@ -1605,10 +2422,14 @@ impl EntityCache {
 
     pub fn extend(&mut self, other: EntityCache) -> Result<(), QueryExecutionError> {
         self.current.extend(other.current);
+        self.cached_misses += other.cached_misses;
+        self.store_misses.extend(other.store_misses);
+        let mut unvalidated = other.unvalidated;
         for (key, update) in other.updates {
-            match update {
-                Some(update) => self.set(key, update)?,
-                None => self.remove(key),
+            match (update, unvalidated.remove(&key)) {
+                (Some(update), Some(validator)) => self.set_unvalidated(key, update, validator)?,
+                (Some(update), None) => self.set(key, update)?,
+                (None, _) => self.remove(key)?,
             }
         }
         Ok(())
@@ -1618,10 +2439,17 @@ impl EntityCache {
     /// `EntityModification`, making sure to only produce one when a change
     /// to the current state is actually needed.
     ///
-    /// Also returns the updated `LfuCache`.
+    /// All removals of entities of the same type are combined into one
+    /// `RemoveMany` and come before all other modifications, so that an
+    /// entity can take over the id of a removed entity of another type that
+    /// implements the same interface.
+    ///
+    /// Also returns the updated `LfuCache`. Entities that the store did not
+    /// have are dropped from it, while removed entities stay cached as
+    /// missing.
     pub fn as_modifications(
         mut self,
-        store: &(impl Store + ?Sized),
+        store: &(impl EntityStore + ?Sized),
     ) -> Result<ModificationsAndCache, QueryExecutionError> {
         // The first step is to make sure all entities being set are in `self.current`.
         // For each subgraph, we need a map of entity type to missing entity ids.
@@ -1635,13 +2463,16 @@ impl EntityCache {
             missing_by_subgraph
                 .entry(&key.subgraph_id)
                 .or_default()
-                .entry(&key.entity_type)
+                .entry(key.entity_type.as_str())
                 .or_default()
                 .push(&key.entity_id);
         }
 
         for (subgraph_id, keys) in missing_by_subgraph {
-            for (entity_type, entities) in store.get_many(subgraph_id, keys)? {
+            for (entity_type, entities) in
+                store.get_many_for_block(subgraph_id, keys, self.block)?
+            {
+                let entity_type = Word::from(entity_type);
                 for entity in entities {
                     let key = EntityKey {
                         subgraph_id: subgraph_id.clone(),
@@ -1653,7 +2484,22 @@ impl EntityCache {
             }
         }
 
+        let mut unvalidated = self.unvalidated;
+        let validate =
+            |key: &EntityKey,
+             data: &Entity,
+             unvalidated: &mut BTreeMap<EntityKey, Arc<dyn EntityValidator>>| {
+                match unvalidated.remove(key) {
+                    Some(validator) => validator
+                        .validate(key, data)
+                        .map_err(|e| QueryExecutionError::InvalidEntity(format!("{:#}", e))),
+                    None => Ok(()),
+                }
+            };
+
         let mut mods = Vec::new();
+        let mut removals: BTreeMap<(SubgraphDeploymentId, String), Vec<EntityKey>> =
+            BTreeMap::new();
         for (key, update) in self.updates {
             use EntityModification::*;
             let current = self.current.remove(&key).and_then(|entity| entity);
@@ -1663,6 +2509,7 @@ impl EntityCache {
                     // Merging with an empty entity removes null fields.
                     let mut data = Entity::new();
                     data.merge_remove_null_fields(updates);
+                    validate(&key, &data, &mut unvalidated)?;
                     self.current.insert(key.clone(), Some(data.clone()));
                     Some(Insert { key, data })
                 }
@@ -1670,9 +2517,21 @@ impl EntityCache {
                 (Some(current), Some(updates)) => {
                     let mut data = current.clone();
                     data.merge_remove_null_fields(updates);
+                    if current != data {
+                        validate(&key, &data, &mut unvalidated)?;
+                    }
                     self.current.insert(key.clone(), Some(data.clone()));
                     if current != data {
-                        Some(Overwrite { key, data })
+                        let changed_fields = if self.track_field_changes {
+                            Some(current.changed_fields(&data))
+                        } else {
+                            None
+                        };
+                        Some(Overwrite {
+                            key,
+                            data,
+                            changed_fields,
+                        })
                     } else {
                         None
                     }
@@ -1680,7 +2539,11 @@ impl EntityCache {
                 // Existing entity was deleted
                 (Some(_), None) => {
                     self.current.insert(key.clone(), None);
-                    Some(Remove { key })
+                    removals
+                        .entry((key.subgraph_id.clone(), key.entity_type.to_string()))
+                        .or_default()
+                        .push(key);
+                    None
                 }
                 // Entity was deleted, but it doesn't exist in the store
                 (None, None) => None,
@@ -1689,27 +2552,54 @@ impl EntityCache {
                 mods.push(modification)
             }
         }
+
+        let mut modifications: Vec<_> = removals
+            .into_iter()
+            .map(|(_, mut keys)| {
+                if keys.len() == 1 {
+                    EntityModification::Remove {
+                        key: keys.pop().unwrap(),
+                    }
+                } else {
+                    EntityModification::RemoveMany { keys }
+                }
+            })
+            .collect();
+        modifications.extend(mods);
+
+        for key in self.store_misses {
+            if let Some(None) = self.current.get(&key) {
+                self.current.remove(&key);
+            }
+        }
+
         Ok(ModificationsAndCache {
-            modifications: mods,
+            modifications,
             entity_lfu_cache: self.current,
         })
     }
 }
 
 impl LfuCache<EntityKey, Option<Entity>> {
-    // Helper for cached lookup of an entity.
+    // Helper for cached lookup of an entity. Entities that the store does
+    // not have are added to `misses`.
     fn get_entity(
         &mut self,
-        store: &(impl Store + ?Sized),
+        store: &(impl EntityStore + ?Sized),
         key: &EntityKey,
+        block: BlockNumber,
+        misses: &mut BTreeSet<EntityKey>,
     ) -> Result<Option<Entity>, QueryExecutionError> {
         match self.get(&key) {
             None => {
-                let mut entity = store.get(key.clone())?;
+                let mut entity = store.get_for_block(key.clone(), block)?;
                 if let Some(entity) = &mut entity {
                     // `__typename` is for queries not for mappings.
                     entity.remove("__typename");
                 }
+                if entity.is_none() {
+                    misses.insert(key.clone());
+                }
                 self.insert(key.clone(), entity.clone());
                 Ok(entity)
             }
@@ -0,0 +1,257 @@
+//! How often the handlers of the deployments on this node ran, how long
+//! they took, and how much work they did. The stats are kept in memory and
+//! start from scratch whenever a deployment is started, which is good enough
+//! to find the handlers that slow a deployment down
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+use crate::prelude::*;
+
+lazy_static! {
+    /// The most handlers that we keep stats for in each deployment. All
+    /// handlers beyond that share the stats for `OTHER_HANDLERS`
+    static ref MAX_HANDLERS: usize = std::env::var("GRAPH_HANDLER_STATS_MAX_HANDLERS")
+        .map(|s| s
+            .parse::<usize>()
+            .expect("invalid GRAPH_HANDLER_STATS_MAX_HANDLERS"))
+        .unwrap_or(100);
+}
+
+/// The name under which we keep the stats for handlers once a deployment
+/// has more than `GRAPH_HANDLER_STATS_MAX_HANDLERS` handlers
+pub const OTHER_HANDLERS: &str = "<other>";
+
+/// How many of the most recent execution times of a handler we use to
+/// compute the 95th percentile
+const RECENT_EXECUTION_TIMES: usize = 1000;
+
+/// The stats for one handler
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandlerStats {
+    pub handler: String,
+    pub invocations: u64,
+    /// The time that all invocations took together
+    pub total_time: Duration,
+    /// The 95th percentile of the execution time of recent invocations
+    pub p95_time: Duration,
+    /// How many `ethereum.call`s the handler made
+    pub eth_calls: u64,
+    /// How many times the handler loaded, saved or removed an entity
+    pub entity_ops: u64,
+}
+
+#[derive(Default)]
+struct HandlerTotals {
+    invocations: u64,
+    total_time: Duration,
+    recent_times: VecDeque<Duration>,
+    eth_calls: u64,
+    entity_ops: u64,
+}
+
+impl HandlerTotals {
+    fn record(&mut self, time: Duration, eth_calls: u64, entity_ops: u64) {
+        self.invocations += 1;
+        self.total_time += time;
+        self.eth_calls += eth_calls;
+        self.entity_ops += entity_ops;
+        if self.recent_times.len() == RECENT_EXECUTION_TIMES {
+            self.recent_times.pop_front();
+        }
+        self.recent_times.push_back(time);
+    }
+
+    fn p95_time(&self) -> Duration {
+        let mut times: Vec<_> = self.recent_times.iter().cloned().collect();
+        times.sort();
+        // The smallest time that at least 95% of the invocations did not exceed
+        let rank = (times.len() * 95 + 99) / 100;
+        times
+            .get(rank.saturating_sub(1))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// The stats for all handlers of one deployment
+pub struct DeploymentHandlerStats {
+    max_handlers: usize,
+    handlers: Mutex<HashMap<String, HandlerTotals>>,
+}
+
+impl DeploymentHandlerStats {
+    pub fn new() -> Self {
+        Self::with_max_handlers(*MAX_HANDLERS)
+    }
+
+    fn with_max_handlers(max_handlers: usize) -> Self {
+        DeploymentHandlerStats {
+            max_handlers,
+            handlers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record that `handler` ran for `time` and made `eth_calls` calls and
+    /// `entity_ops` entity operations. Returns the name under which the
+    /// invocation was recorded, which is `OTHER_HANDLERS` if there are
+    /// already too many handlers
+    pub fn record<'a>(
+        &self,
+        handler: &'a str,
+        time: Duration,
+        eth_calls: u64,
+        entity_ops: u64,
+    ) -> &'a str {
+        let mut handlers = self.handlers.lock().unwrap();
+        let name = if handlers.contains_key(handler) || handlers.len() < self.max_handlers {
+            handler
+        } else {
+            OTHER_HANDLERS
+        };
+        handlers
+            .entry(name.to_owned())
+            .or_default()
+            .record(time, eth_calls, entity_ops);
+        name
+    }
+
+    /// The stats for all handlers that ran so far, ordered by handler name
+    pub fn stats(&self) -> Vec<HandlerStats> {
+        let mut stats: Vec<_> = self
+            .handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(handler, totals)| HandlerStats {
+                handler: handler.clone(),
+                invocations: totals.invocations,
+                total_time: totals.total_time,
+                p95_time: totals.p95_time(),
+                eth_calls: totals.eth_calls,
+                entity_ops: totals.entity_ops,
+            })
+            .collect();
+        stats.sort_by(|a, b| a.handler.cmp(&b.handler));
+        stats
+    }
+}
+
+impl Default for DeploymentHandlerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The handler stats of the deployments that run on this node
+#[derive(Default)]
+pub struct HandlerStatsRegistry {
+    deployments: RwLock<HashMap<SubgraphDeploymentId, Arc<DeploymentHandlerStats>>>,
+}
+
+impl HandlerStatsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start collecting stats for `deployment`, replacing any stats that
+    /// were collected before
+    pub fn register(&self, deployment: &SubgraphDeploymentId) -> Arc<DeploymentHandlerStats> {
+        let stats = Arc::new(DeploymentHandlerStats::new());
+        self.deployments
+            .write()
+            .unwrap()
+            .insert(deployment.clone(), stats.clone());
+        stats
+    }
+
+    /// Forget the stats for `deployment`
+    pub fn remove(&self, deployment: &SubgraphDeploymentId) {
+        self.deployments.write().unwrap().remove(deployment);
+    }
+
+    /// The stats for the handlers of `deployment`, or `None` if that
+    /// deployment does not run on this node
+    pub fn stats(&self, deployment: &SubgraphDeploymentId) -> Option<Vec<HandlerStats>> {
+        self.deployments
+            .read()
+            .unwrap()
+            .get(deployment)
+            .map(|stats| stats.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn attributes_invocations_to_handlers() {
+        let deployment = SubgraphDeploymentId::new("QmHandlers").unwrap();
+        let registry = HandlerStatsRegistry::new();
+        assert_eq!(None, registry.stats(&deployment));
+
+        let stats = registry.register(&deployment);
+        for millis in 1..=100 {
+            stats.record("handleTransfer", ms(millis), 1, 2);
+        }
+        stats.record("handleApproval", ms(7), 0, 1);
+
+        assert_eq!(
+            Some(vec![
+                HandlerStats {
+                    handler: "handleApproval".to_owned(),
+                    invocations: 1,
+                    total_time: ms(7),
+                    p95_time: ms(7),
+                    eth_calls: 0,
+                    entity_ops: 1,
+                },
+                HandlerStats {
+                    handler: "handleTransfer".to_owned(),
+                    invocations: 100,
+                    total_time: ms(5050),
+                    p95_time: ms(95),
+                    eth_calls: 100,
+                    entity_ops: 200,
+                },
+            ]),
+            registry.stats(&deployment)
+        );
+
+        // Restarting the deployment starts over
+        registry.register(&deployment);
+        assert_eq!(Some(vec![]), registry.stats(&deployment));
+        registry.remove(&deployment);
+        assert_eq!(None, registry.stats(&deployment));
+    }
+
+    #[test]
+    fn limits_number_of_handlers() {
+        let stats = DeploymentHandlerStats::with_max_handlers(2);
+        assert_eq!("a", stats.record("a", ms(1), 0, 0));
+        assert_eq!("b", stats.record("b", ms(1), 0, 0));
+        assert_eq!(OTHER_HANDLERS, stats.record("c", ms(1), 0, 0));
+        assert_eq!(OTHER_HANDLERS, stats.record("d", ms(1), 0, 0));
+        assert_eq!("a", stats.record("a", ms(1), 0, 0));
+
+        let invocations: Vec<_> = stats
+            .stats()
+            .into_iter()
+            .map(|stats| (stats.handler, stats.invocations))
+            .collect();
+        assert_eq!(
+            vec![
+                (OTHER_HANDLERS.to_owned(), 2),
+                ("a".to_owned(), 2),
+                ("b".to_owned(), 1)
+            ],
+            invocations
+        );
+    }
+}
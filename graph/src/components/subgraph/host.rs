@@ -1,12 +1,14 @@
 use std::cmp::PartialEq;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use failure::Error;
 use futures::sync::mpsc;
 
-use crate::components::metrics::HistogramVec;
+use crate::components::metrics::{CounterVec, HistogramVec};
+use crate::components::subgraph::handler_stats::DeploymentHandlerStats;
 use crate::components::subgraph::SharedProofOfIndexing;
 use crate::prelude::*;
 use web3::types::{Log, Transaction};
@@ -90,9 +92,23 @@ pub trait RuntimeHost: Send + Sync + Debug + 'static {
     ) -> Result<BlockState, MappingError>;
 }
 
+lazy_static! {
+    /// Whether to also export the number of Ethereum calls and entity
+    /// operations of each handler to Prometheus. Deployments can have many
+    /// handlers, which makes for a lot of time series
+    static ref HANDLER_STATS_METRICS: bool = std::env::var("GRAPH_HANDLER_STATS_METRICS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+}
+
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    handler_memory: Box<HistogramVec>,
+    handler_eth_calls: Option<Box<CounterVec>>,
+    handler_entity_ops: Option<Box<CounterVec>>,
+    undeclared_entity_writes: Box<CounterVec>,
+    handler_stats: Arc<DeploymentHandlerStats>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -108,6 +124,7 @@ impl HostMetrics {
         registry: Arc<impl MetricsRegistry>,
         subgraph: &str,
         stopwatch: StopwatchMetrics,
+        handler_stats: Arc<DeploymentHandlerStats>,
     ) -> Self {
         let handler_execution_time = registry
             .new_deployment_histogram_vec(
@@ -127,9 +144,58 @@ impl HostMetrics {
                 vec![0.025, 0.05, 0.2, 2.0, 8.0, 20.0],
             )
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
+        let handler_memory = registry
+            .new_deployment_histogram_vec(
+                "deployment_handler_memory",
+                "Measures the peak WASM memory in bytes that handlers use",
+                subgraph,
+                vec![String::from("handler")],
+                vec![
+                    1_048_576.0,
+                    16_777_216.0,
+                    67_108_864.0,
+                    268_435_456.0,
+                    1_073_741_824.0,
+                ],
+            )
+            .expect("failed to create `deployment_handler_memory` histogram");
+        let undeclared_entity_writes = registry
+            .new_deployment_counter_vec(
+                "deployment_undeclared_entity_writes",
+                "Counts the writes of handlers to entity types that their data source does not declare",
+                subgraph,
+                vec![String::from("handler"), String::from("entity_type")],
+            )
+            .expect("failed to create `deployment_undeclared_entity_writes` counter");
+        let (handler_eth_calls, handler_entity_ops) = if *HANDLER_STATS_METRICS {
+            let eth_calls = registry
+                .new_deployment_counter_vec(
+                    "deployment_handler_eth_calls",
+                    "Counts the Ethereum calls that handlers make",
+                    subgraph,
+                    vec![String::from("handler")],
+                )
+                .expect("failed to create `deployment_handler_eth_calls` counter");
+            let entity_ops = registry
+                .new_deployment_counter_vec(
+                    "deployment_handler_entity_ops",
+                    "Counts the entities that handlers load, save and remove",
+                    subgraph,
+                    vec![String::from("handler")],
+                )
+                .expect("failed to create `deployment_handler_entity_ops` counter");
+            (Some(eth_calls), Some(entity_ops))
+        } else {
+            (None, None)
+        };
         Self {
             handler_execution_time,
             host_fn_execution_time,
+            handler_memory,
+            handler_eth_calls,
+            handler_entity_ops,
+            undeclared_entity_writes,
+            handler_stats,
             stopwatch,
         }
     }
@@ -140,6 +206,42 @@ impl HostMetrics {
             .observe(duration);
     }
 
+    pub fn observe_handler_memory(&self, bytes: u64, handler: &str) {
+        self.handler_memory
+            .with_label_values(vec![handler].as_slice())
+            .observe(bytes as f64);
+    }
+
+    /// Record one invocation of `handler` in the handler stats of the
+    /// deployment
+    pub fn observe_handler_invocation(
+        &self,
+        handler: &str,
+        time: Duration,
+        eth_calls: u64,
+        entity_ops: u64,
+    ) {
+        let handler = self
+            .handler_stats
+            .record(handler, time, eth_calls, entity_ops);
+        if let Some(counters) = &self.handler_eth_calls {
+            counters
+                .with_label_values(vec![handler].as_slice())
+                .inc_by(eth_calls as f64);
+        }
+        if let Some(counters) = &self.handler_entity_ops {
+            counters
+                .with_label_values(vec![handler].as_slice())
+                .inc_by(entity_ops as f64);
+        }
+    }
+
+    pub fn observe_undeclared_entity_writes(&self, handler: &str, entity_type: &str, writes: u64) {
+        self.undeclared_entity_writes
+            .with_label_values(vec![handler, entity_type].as_slice())
+            .inc_by(writes as f64);
+    }
+
     pub fn observe_host_fn_execution_time(&self, duration: f64, fn_name: &str) {
         self.host_fn_execution_time
             .with_label_values(vec![fn_name].as_slice())
@@ -163,11 +265,13 @@ pub trait RuntimeHostBuilder: Clone + Send + Sync + 'static {
     ) -> Result<Self::Host, Error>;
 
     /// Spawn a mapping and return a channel for mapping requests. The sender should be able to be
-    /// cached and shared among mappings that use the same wasm file.
+    /// cached and shared among mappings that use the same wasm file. Handlers may use at most
+    /// `max_memory` bytes of WASM memory, or a default amount if that is `None`.
     fn spawn_mapping(
         raw_module: Vec<u8>,
         logger: Logger,
         subgraph_id: SubgraphDeploymentId,
         metrics: Arc<HostMetrics>,
+        max_memory: Option<u64>,
     ) -> Result<mpsc::Sender<Self::Req>, anyhow::Error>;
 }
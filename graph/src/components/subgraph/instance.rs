@@ -1,5 +1,4 @@
 use async_trait::async_trait;
-use web3::types::Log;
 
 use crate::components::subgraph::{MappingError, SharedProofOfIndexing};
 use crate::prelude::*;
@@ -20,7 +19,10 @@ pub struct BlockState {
 }
 
 impl BlockState {
-    pub fn new(store: Arc<dyn Store>, lfu_cache: LfuCache<EntityKey, Option<Entity>>) -> Self {
+    pub fn new(
+        store: Arc<dyn EntityStore>,
+        lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    ) -> Self {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             created_data_sources: Vec::new(),
@@ -28,28 +30,25 @@ impl BlockState {
     }
 }
 
-/// Represents a loaded instance of a subgraph.
+/// Represents a loaded instance of a subgraph that indexes the chain `C`.
 #[async_trait]
-pub trait SubgraphInstance<H: RuntimeHost> {
-    /// Returns true if the subgraph has a handler for an Ethereum event.
-    fn matches_log(&self, log: &Log) -> bool;
-
-    /// Process and Ethereum trigger and return the resulting entity operations as a future.
+pub trait SubgraphInstance<C: Blockchain, H: RuntimeHost> {
+    /// Process a trigger and return the resulting entity operations as a future.
     async fn process_trigger(
         &self,
         logger: &Logger,
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
+        block: &Arc<C::MappingBlock>,
+        trigger: C::TriggerData,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError>;
 
-    /// Like `process_trigger` but processes an Ethereum event in a given list of hosts.
+    /// Like `process_trigger` but processes a trigger in a given list of hosts.
     async fn process_trigger_in_runtime_hosts(
         logger: &Logger,
         hosts: &[Arc<H>],
-        block: &Arc<LightEthereumBlock>,
-        trigger: EthereumTrigger,
+        block: &Arc<C::MappingBlock>,
+        trigger: C::TriggerData,
         state: BlockState,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError>;
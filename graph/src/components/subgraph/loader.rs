@@ -9,4 +9,12 @@ pub trait DataSourceLoader {
         id: SubgraphDeploymentId,
         logger: Logger,
     ) -> Result<Vec<DataSource>, Error>;
+
+    /// The dynamic data sources that were created before block `block`
+    async fn load_dynamic_data_sources_before(
+        &self,
+        id: SubgraphDeploymentId,
+        block: u64,
+        logger: Logger,
+    ) -> Result<Vec<DataSource>, Error>;
 }
@@ -1,3 +1,4 @@
+pub mod handler_stats;
 mod host;
 mod instance;
 mod instance_manager;
@@ -5,6 +6,7 @@ mod loader;
 mod proof_of_indexing;
 mod provider;
 mod registrar;
+mod verifier;
 
 pub use crate::prelude::Entity;
 
@@ -18,3 +20,4 @@ pub use self::proof_of_indexing::{
 };
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::{SubgraphRegistrar, SubgraphVersionSwitchingMode};
+pub use self::verifier::ProofOfIndexingVerifier;
@@ -1,4 +1,4 @@
-use crate::prelude::{impl_slog_value, Value};
+use crate::prelude::{impl_slog_value, BigInt, Value};
 use stable_hash::prelude::*;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt;
@@ -16,6 +16,12 @@ pub enum ProofOfIndexingEvent<'a> {
         id: &'a str,
         data: &'a HashMap<String, Value>,
     },
+    IncrementField {
+        entity_type: &'a str,
+        id: &'a str,
+        field: &'a str,
+        amount: &'a BigInt,
+    },
 }
 
 impl StableHash for ProofOfIndexingEvent<'_> {
@@ -37,6 +43,17 @@ impl StableHash for ProofOfIndexingEvent<'_> {
                 id.stable_hash(sequence_number.next_child(), state);
                 data.stable_hash(sequence_number.next_child(), state);
             }
+            IncrementField {
+                entity_type,
+                id,
+                field,
+                amount,
+            } => {
+                entity_type.stable_hash(sequence_number.next_child(), state);
+                id.stable_hash(sequence_number.next_child(), state);
+                field.stable_hash(sequence_number.next_child(), state);
+                amount.stable_hash(sequence_number.next_child(), state);
+            }
         }
     }
 }
@@ -62,6 +79,17 @@ impl fmt::Debug for ProofOfIndexingEvent<'_> {
                 builder.field("id", id);
                 builder.field("data", &data.iter().collect::<BTreeMap<_, _>>());
             }
+            Self::IncrementField {
+                entity_type,
+                id,
+                field,
+                amount,
+            } => {
+                builder.field("entity_type", entity_type);
+                builder.field("id", id);
+                builder.field("field", field);
+                builder.field("amount", amount);
+            }
         }
         builder.finish()
     }
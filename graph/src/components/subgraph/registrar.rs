@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 
+use crate::components::ethereum::repair::ChainRepair;
 use crate::prelude::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -40,4 +41,72 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: SubgraphDeploymentId,
         node_id: NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Keep a deployment that was recorded as unused from being removed
+    async fn restore_unused_deployment(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Limit the WASM memory that each handler of the deployment may use
+    /// to `limit` bytes, or go back to the default limit if `limit` is
+    /// `None`. The new limit takes effect when the deployment is started
+    /// next
+    async fn set_memory_limit(
+        &self,
+        hash: SubgraphDeploymentId,
+        limit: Option<u64>,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Flag the deployment as unhealthy with an operator's `note` so that
+    /// clients of this node can route its queries elsewhere. The flag
+    /// does not affect indexing
+    async fn flag_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        note: String,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Remove the flag that `flag_subgraph` put on the deployment
+    async fn unflag_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+    ) -> Result<(), SubgraphRegistrarError>;
+
+    /// Stop the deployment and start it again from the block pointer in
+    /// the store, which gives it a new block stream, new WASM hosts and
+    /// empty caches while leaving its data alone. Deployments that are
+    /// assigned to another node are not restarted
+    async fn restart_subgraph(
+        &self,
+        hash: SubgraphDeploymentId,
+        reason: Option<String>,
+    ) -> Result<RestartSubgraphResult, SubgraphRegistrarError>;
+
+    /// Find the entities of the deployment whose versions have overlapping
+    /// block ranges, and resolve the overlaps unless `dry_run` is set
+    async fn repair_block_ranges(
+        &self,
+        hash: SubgraphDeploymentId,
+        dry_run: bool,
+    ) -> Result<RepairBlockRangesResult, SubgraphRegistrarError>;
+
+    /// Fill the gaps in the block cache for `network` between the blocks
+    /// `from` and `to` with blocks from an Ethereum node
+    async fn repair_chain(
+        &self,
+        network: String,
+        from: u64,
+        to: u64,
+    ) -> Result<ChainRepair, SubgraphRegistrarError>;
+
+    /// Replay the blocks `from` to `to` of the deployment in a sandbox that
+    /// only uses the block and call caches, and compare the proofs of
+    /// indexing and entity changes with the ones in the store
+    async fn verify_proof_of_indexing(
+        &self,
+        hash: SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<PoiVerificationResult, SubgraphRegistrarError>;
 }
@@ -0,0 +1,18 @@
+use async_trait::async_trait;
+
+use crate::prelude::*;
+
+/// Replays blocks of deployments to check the proofs of indexing that the
+/// store has for them.
+#[async_trait]
+pub trait ProofOfIndexingVerifier: Send + Sync + 'static {
+    /// Replay the blocks `from` to `to` of `deployment` without writing to
+    /// the store or sending requests to Ethereum nodes, and compare the
+    /// resulting proofs of indexing and entity changes with the store.
+    async fn verify(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        from: u64,
+        to: u64,
+    ) -> Result<PoiVerificationResult, Error>;
+}
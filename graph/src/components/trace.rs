@@ -0,0 +1,404 @@
+//! OpenTelemetry traces for block processing and GraphQL queries.
+//!
+//! Processing a block of a deployment is traced as a root span with child
+//! spans for the triggers that are fetched for new data sources, each
+//! handler, the `ethereum.call`s that handlers make, and the store write.
+//! GraphQL queries get a span of their own that links to the span of the
+//! block that their deployment processed last.
+//!
+//! Spans are only exported when graph-node is built with the `otel` feature
+//! and `init` is called with an OTLP endpoint. Otherwise, the types in this
+//! module are empty and all their methods do nothing, so that instrumented
+//! code costs next to nothing.
+//!
+//! Spans are passed to other spans and across threads explicitly, with the
+//! exception of the mapping thread: handlers run with the context of their
+//! span attached to the thread, and spans for host exports are children of
+//! the context that is current when the host export is called.
+
+use slog::Logger;
+use std::future::Future;
+
+use crate::prelude::{EthereumBlockPointer, SubgraphDeploymentId};
+
+pub use self::imp::{Span, TraceContext, TraceContextGuard, TraceGuard};
+
+/// Set up the export of traces to the OTLP collector at `endpoint`,
+/// sampling `sample_rate` of all root spans. Child spans are sampled
+/// together with their root span. Spans are exported until the returned
+/// guard is dropped
+pub fn init(logger: &Logger, endpoint: Option<String>, sample_rate: f64) -> TraceGuard {
+    imp::init(logger, endpoint, sample_rate)
+}
+
+/// The span for processing `block` of `deployment`
+pub fn block_span(deployment: &SubgraphDeploymentId, block: &EthereumBlockPointer) -> Span {
+    imp::block_span(deployment, block)
+}
+
+/// The span for a GraphQL query against `deployment`
+pub fn query_span(deployment: &SubgraphDeploymentId) -> Span {
+    imp::query_span(deployment)
+}
+
+/// Run `fut` with the context of `span`, which makes spans that are created
+/// while polling `fut` children of `span`
+pub fn instrument<F: Future>(fut: F, span: &Span) -> impl Future<Output = F::Output> {
+    imp::instrument(fut, span)
+}
+
+#[cfg(feature = "otel")]
+mod imp {
+    use futures03::future::Either;
+    use lazy_static::lazy_static;
+    use opentelemetry::sdk::trace::{self as sdktrace, Sampler};
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::trace::{FutureExt, Link, SpanContext, StatusCode, TraceContextExt, Tracer};
+    use opentelemetry::{global, Context, ContextGuard, KeyValue};
+    use slog::{info, warn, Logger};
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use crate::prelude::{EthereumBlockPointer, SubgraphDeploymentId};
+
+    const TRACER_NAME: &str = "graph-node";
+
+    /// Whether traces are exported at all. Spans are only created when
+    /// they are
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+
+    lazy_static! {
+        /// The context of the span of the block that each deployment
+        /// processed last, for the query spans to link to
+        static ref LATEST_BLOCK_SPANS: Mutex<HashMap<SubgraphDeploymentId, SpanContext>> =
+            Mutex::new(HashMap::new());
+    }
+
+    pub struct TraceGuard(Option<opentelemetry_otlp::Uninstall>);
+
+    impl Drop for TraceGuard {
+        fn drop(&mut self) {
+            if self.0.is_some() {
+                ENABLED.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub(super) fn init(logger: &Logger, endpoint: Option<String>, sample_rate: f64) -> TraceGuard {
+        let endpoint = match endpoint {
+            Some(endpoint) => endpoint,
+            None => return TraceGuard(None),
+        };
+
+        let config = sdktrace::config()
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                sample_rate,
+            ))))
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                TRACER_NAME,
+            )]));
+        match opentelemetry_otlp::new_pipeline()
+            .with_endpoint(&endpoint)
+            .with_trace_config(config)
+            .install()
+        {
+            Ok((_, uninstall)) => {
+                info!(logger, "Exporting traces";
+                      "endpoint" => &endpoint,
+                      "sample_rate" => sample_rate);
+                ENABLED.store(true, Ordering::SeqCst);
+                TraceGuard(Some(uninstall))
+            }
+            Err(e) => {
+                warn!(logger, "Failed to set up the export of traces, not exporting traces";
+                      "endpoint" => &endpoint,
+                      "error" => e.to_string());
+                TraceGuard(None)
+            }
+        }
+    }
+
+    fn enabled() -> bool {
+        ENABLED.load(Ordering::Relaxed)
+    }
+
+    /// A span that ends when it is dropped
+    #[derive(Default)]
+    pub struct Span {
+        cx: Option<Context>,
+    }
+
+    impl Span {
+        fn start(
+            name: &'static str,
+            parent: Context,
+            attributes: Vec<KeyValue>,
+            links: Vec<Link>,
+        ) -> Self {
+            let tracer = global::tracer(TRACER_NAME);
+            let span = tracer
+                .span_builder(name)
+                .with_parent_context(parent)
+                .with_attributes(attributes)
+                .with_links(links)
+                .start(&tracer);
+            Span {
+                cx: Some(Context::current_with_span(span)),
+            }
+        }
+
+        /// A span that does not record anything
+        pub fn none() -> Self {
+            Span { cx: None }
+        }
+
+        /// A child span of this span
+        pub fn child(&self, name: &'static str) -> Span {
+            match &self.cx {
+                Some(cx) => Span::start(name, cx.clone(), vec![], vec![]),
+                None => Span::none(),
+            }
+        }
+
+        /// A child span of the context that is attached to the current
+        /// thread, or no span if there is none
+        pub fn current_child(name: &'static str) -> Span {
+            if !enabled() {
+                return Span::none();
+            }
+            let cx = Context::current();
+            if cx.span().span_context().is_valid() {
+                Span::start(name, cx, vec![], vec![])
+            } else {
+                Span::none()
+            }
+        }
+
+        pub fn set_str(&self, key: &'static str, value: &str) {
+            if let Some(cx) = &self.cx {
+                cx.span()
+                    .set_attribute(KeyValue::new(key, value.to_owned()));
+            }
+        }
+
+        pub fn set_int(&self, key: &'static str, value: i64) {
+            if let Some(cx) = &self.cx {
+                cx.span().set_attribute(KeyValue::new(key, value));
+            }
+        }
+
+        /// Mark the span as failed with `error`
+        pub fn set_error(&self, error: &dyn fmt::Display) {
+            if let Some(cx) = &self.cx {
+                cx.span().set_status(StatusCode::Error, error.to_string());
+            }
+        }
+
+        /// The context to use for spans that should be children of this
+        /// span in other places
+        pub fn context(&self) -> TraceContext {
+            TraceContext {
+                cx: self.cx.clone(),
+            }
+        }
+
+        pub fn end(self) {}
+    }
+
+    impl Drop for Span {
+        fn drop(&mut self) {
+            if let Some(cx) = &self.cx {
+                cx.span().end();
+            }
+        }
+    }
+
+    /// The context of a span, which can be used to create child spans of
+    /// it, but does not end the span when it is dropped
+    #[derive(Clone, Default)]
+    pub struct TraceContext {
+        cx: Option<Context>,
+    }
+
+    impl fmt::Debug for TraceContext {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match &self.cx {
+                Some(cx) => write!(f, "TraceContext({:?})", cx.span().span_context()),
+                None => write!(f, "TraceContext(none)"),
+            }
+        }
+    }
+
+    impl TraceContext {
+        /// A child span of the span of this context
+        pub fn child(&self, name: &'static str) -> Span {
+            match &self.cx {
+                Some(cx) => Span::start(name, cx.clone(), vec![], vec![]),
+                None => Span::none(),
+            }
+        }
+
+        /// Make this context the current context of this thread until the
+        /// guard is dropped
+        pub fn attach(&self) -> TraceContextGuard {
+            TraceContextGuard(self.cx.clone().map(Context::attach))
+        }
+    }
+
+    pub struct TraceContextGuard(Option<ContextGuard>);
+
+    pub(super) fn block_span(
+        deployment: &SubgraphDeploymentId,
+        block: &EthereumBlockPointer,
+    ) -> Span {
+        if !enabled() {
+            return Span::none();
+        }
+        let span = Span::start(
+            "process_block",
+            Context::new(),
+            vec![
+                KeyValue::new("deployment", deployment.to_string()),
+                KeyValue::new("block.number", block.number as i64),
+                KeyValue::new("block.hash", format!("{:x}", block.hash)),
+            ],
+            vec![],
+        );
+        if let Some(cx) = &span.cx {
+            let span_context = cx.span().span_context().clone();
+            if span_context.is_sampled() {
+                LATEST_BLOCK_SPANS
+                    .lock()
+                    .unwrap()
+                    .insert(deployment.clone(), span_context);
+            }
+        }
+        span
+    }
+
+    pub(super) fn query_span(deployment: &SubgraphDeploymentId) -> Span {
+        if !enabled() {
+            return Span::none();
+        }
+        let links = LATEST_BLOCK_SPANS
+            .lock()
+            .unwrap()
+            .get(deployment)
+            .map(|span_context| vec![Link::new(span_context.clone(), vec![])])
+            .unwrap_or_default();
+        Span::start(
+            "graphql_query",
+            Context::new(),
+            vec![KeyValue::new("deployment", deployment.to_string())],
+            links,
+        )
+    }
+
+    pub(super) fn instrument<F: Future>(fut: F, span: &Span) -> impl Future<Output = F::Output> {
+        match &span.cx {
+            Some(cx) => Either::Left(fut.with_context(cx.clone())),
+            None => Either::Right(fut),
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod imp {
+    use slog::{warn, Logger};
+    use std::fmt;
+    use std::future::Future;
+
+    use crate::prelude::{EthereumBlockPointer, SubgraphDeploymentId};
+
+    pub struct TraceGuard;
+
+    pub(super) fn init(logger: &Logger, endpoint: Option<String>, _sample_rate: f64) -> TraceGuard {
+        if endpoint.is_some() {
+            warn!(
+                logger,
+                "An OTLP endpoint is configured, but graph-node was built without \
+                 the `otel` feature; not exporting traces"
+            );
+        }
+        TraceGuard
+    }
+
+    /// A span that ends when it is dropped
+    #[derive(Default)]
+    pub struct Span;
+
+    impl Span {
+        #[inline]
+        pub fn none() -> Self {
+            Span
+        }
+
+        #[inline]
+        pub fn child(&self, _name: &'static str) -> Span {
+            Span
+        }
+
+        #[inline]
+        pub fn current_child(_name: &'static str) -> Span {
+            Span
+        }
+
+        #[inline]
+        pub fn set_str(&self, _key: &'static str, _value: &str) {}
+
+        #[inline]
+        pub fn set_int(&self, _key: &'static str, _value: i64) {}
+
+        #[inline]
+        pub fn set_error(&self, _error: &dyn fmt::Display) {}
+
+        #[inline]
+        pub fn context(&self) -> TraceContext {
+            TraceContext
+        }
+
+        #[inline]
+        pub fn end(self) {}
+    }
+
+    /// The context of a span, which can be used to create child spans of
+    /// it, but does not end the span when it is dropped
+    #[derive(Clone, Debug, Default)]
+    pub struct TraceContext;
+
+    impl TraceContext {
+        #[inline]
+        pub fn child(&self, _name: &'static str) -> Span {
+            Span
+        }
+
+        #[inline]
+        pub fn attach(&self) -> TraceContextGuard {
+            TraceContextGuard
+        }
+    }
+
+    pub struct TraceContextGuard;
+
+    #[inline]
+    pub(super) fn block_span(
+        _deployment: &SubgraphDeploymentId,
+        _block: &EthereumBlockPointer,
+    ) -> Span {
+        Span
+    }
+
+    #[inline]
+    pub(super) fn query_span(_deployment: &SubgraphDeploymentId) -> Span {
+        Span
+    }
+
+    #[inline]
+    pub(super) fn instrument<F: Future>(fut: F, _span: &Span) -> impl Future<Output = F::Output> {
+        fut
+    }
+}
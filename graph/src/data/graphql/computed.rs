@@ -0,0 +1,218 @@
+//! Computed fields. A scalar field with the directive
+//! `@computed(from: ["fieldA", "fieldB"])` is not stored; instead, queries
+//! evaluate a pure function over the values of the listed fields of the
+//! same entity. The functions of a deployment are the exports of the WASM
+//! module that its manifest references as `computed`; the function of a
+//! field is the export named by the `function` argument of the directive,
+//! or by the name of the field if the directive does not have one.
+use graphql_parser::query as q;
+use graphql_parser::schema::Field;
+use std::collections::BTreeMap;
+
+use super::ext::{DirectiveExt, DirectiveFinder, TypeExt, ValueExt};
+use crate::data::subgraph::SubgraphDeploymentId;
+
+/// The name of the directive that marks computed fields
+pub const COMPUTED_DIRECTIVE: &str = "computed";
+
+/// Evaluates the functions of computed fields at query time. Functions
+/// must be pure since query results may be cached
+pub trait ComputedFunctions: Send + Sync + 'static {
+    /// Start evaluating computed fields of the deployment `deployment` for
+    /// one query. The query uses the result for all the computed fields it
+    /// needs, and drops it when it is done
+    fn query(&self, deployment: &SubgraphDeploymentId) -> Result<Box<dyn ComputedQuery>, String>;
+}
+
+/// The evaluation of computed fields for one query
+pub trait ComputedQuery {
+    /// Compute the value of `field` from `args`, the values of the fields
+    /// in its `from` list, in that order. Missing values are passed as
+    /// `q::Value::Null`
+    fn compute(&mut self, field: &ComputedField, args: &[q::Value]) -> Result<q::Value, String>;
+}
+
+/// Computed functions for nodes that can not evaluate any; every attempt
+/// to compute a field fails
+pub struct NoComputedFunctions;
+
+impl ComputedFunctions for NoComputedFunctions {
+    fn query(&self, _: &SubgraphDeploymentId) -> Result<Box<dyn ComputedQuery>, String> {
+        Ok(Box::new(NoComputedFunctions))
+    }
+}
+
+impl ComputedQuery for NoComputedFunctions {
+    fn compute(&mut self, field: &ComputedField, _: &[q::Value]) -> Result<q::Value, String> {
+        Err(format!(
+            "there is no way to evaluate the function `{}` of the computed field `{}`",
+            field.function, field.name
+        ))
+    }
+}
+
+/// The parsed `@computed` directive of a field
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedField {
+    /// The name of the computed field
+    pub name: String,
+    /// The name of the scalar type of the field
+    pub field_type: String,
+    /// The name of the function that computes the field
+    pub function: String,
+    /// The fields whose values are passed to the function
+    pub from: Vec<String>,
+}
+
+impl ComputedField {
+    /// Return `true` if `field` has a `@computed` directive, whether it is
+    /// valid or not
+    pub fn is_computed(field: &Field) -> bool {
+        field
+            .find_directive(COMPUTED_DIRECTIVE.to_owned())
+            .is_some()
+    }
+
+    /// Parse the `@computed` directive of `field`. Return `None` if the
+    /// field does not have one, and an explanation if the directive is
+    /// malformed
+    pub fn from_field(field: &Field) -> Result<Option<Self>, String> {
+        let directive = match field.find_directive(COMPUTED_DIRECTIVE.to_owned()) {
+            Some(directive) => directive,
+            None => return Ok(None),
+        };
+
+        let from = directive
+            .argument("from")
+            .and_then(|from| from.as_list())
+            .ok_or_else(|| "the @computed directive must have a `from` list".to_owned())?
+            .iter()
+            .map(|name| {
+                name.as_string().cloned().ok_or_else(|| {
+                    "the @computed `from` argument must be a list of field names".to_owned()
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if from.is_empty() {
+            return Err("the @computed `from` list must not be empty".to_owned());
+        }
+
+        let function = match directive.argument("function") {
+            None => field.name.clone(),
+            Some(q::Value::String(function)) => function.clone(),
+            Some(_) => return Err("the @computed `function` argument must be a string".to_owned()),
+        };
+
+        Ok(Some(ComputedField {
+            name: field.name.clone(),
+            field_type: field.field_type.get_base_type().clone(),
+            function,
+            from,
+        }))
+    }
+
+    /// Compute the value of this field for `entity` with `query`
+    pub fn compute(
+        &self,
+        query: &mut dyn ComputedQuery,
+        entity: &BTreeMap<String, q::Value>,
+    ) -> Result<q::Value, String> {
+        let args: Vec<_> = self
+            .from
+            .iter()
+            .map(|name| entity.get(name).cloned().unwrap_or(q::Value::Null))
+            .collect();
+        query.compute(self, &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(directive: &str) -> Field {
+        let schema = format!(
+            "type Thing @entity {{ id: ID!, value: BigInt {} }}",
+            directive
+        );
+        let document = graphql_parser::parse_schema(&schema).unwrap();
+        match document.definitions.into_iter().next() {
+            Some(graphql_parser::schema::Definition::TypeDefinition(
+                graphql_parser::schema::TypeDefinition::Object(t),
+            )) => t.fields.into_iter().nth(1).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_directive() {
+        assert_eq!(Ok(None), ComputedField::from_field(&field("")));
+        assert_eq!(
+            Ok(Some(ComputedField {
+                name: "value".to_owned(),
+                field_type: "BigInt".to_owned(),
+                function: "value".to_owned(),
+                from: vec!["a".to_owned(), "b".to_owned()],
+            })),
+            ComputedField::from_field(&field("@computed(from: [\"a\", \"b\"])"))
+        );
+        assert_eq!(
+            "times",
+            ComputedField::from_field(&field("@computed(from: [\"a\"], function: \"times\")"))
+                .unwrap()
+                .unwrap()
+                .function
+        );
+        assert!(ComputedField::from_field(&field("@computed")).is_err());
+        assert!(ComputedField::from_field(&field("@computed(from: [])")).is_err());
+        assert!(ComputedField::from_field(&field("@computed(from: [1])")).is_err());
+    }
+
+    /// Concatenates its string arguments, with `-` for everything else
+    struct Concat;
+
+    impl ComputedQuery for Concat {
+        fn compute(
+            &mut self,
+            field: &ComputedField,
+            args: &[q::Value],
+        ) -> Result<q::Value, String> {
+            if field.function != "concat" {
+                return Err(format!("unknown function `{}`", field.function));
+            }
+            Ok(q::Value::String(
+                args.iter()
+                    .map(|arg| match arg {
+                        q::Value::String(s) => s.clone(),
+                        _ => "-".to_owned(),
+                    })
+                    .collect(),
+            ))
+        }
+    }
+
+    #[test]
+    fn compute() {
+        let deployment = SubgraphDeploymentId::new("computed").unwrap();
+        let mut no_functions = NoComputedFunctions.query(&deployment).unwrap();
+        let computed = ComputedField::from_field(&field(
+            "@computed(from: [\"a\", \"b\", \"c\"], function: \"concat\")",
+        ))
+        .unwrap()
+        .unwrap();
+
+        let mut entity = BTreeMap::new();
+        entity.insert("a".to_owned(), q::Value::String("x".to_owned()));
+        entity.insert("c".to_owned(), q::Value::String("y".to_owned()));
+        assert_eq!(
+            Ok(q::Value::String("x-y".to_owned())),
+            computed.compute(&mut Concat, &entity)
+        );
+
+        let unknown = ComputedField::from_field(&field("@computed(from: [\"a\"])"))
+            .unwrap()
+            .unwrap();
+        assert!(unknown.compute(&mut Concat, &entity).is_err());
+        assert!(computed.compute(no_functions.as_mut(), &entity).is_err());
+    }
+}
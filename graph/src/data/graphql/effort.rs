@@ -10,7 +10,7 @@ use std::str::FromStr;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
-use crate::components::metrics::{Counter, Gauge, MetricsRegistry};
+use crate::components::metrics::{Counter, CounterVec, Gauge, MetricsRegistry};
 use crate::components::store::PoolWaitStats;
 use crate::data::graphql::shape_hash::shape_hash;
 use crate::data::query::{CacheStatus, QueryExecutionError};
@@ -252,6 +252,7 @@ pub struct LoadManager {
     kill_state: RwLock<KillState>,
     effort_gauge: Box<Gauge>,
     query_counters: HashMap<CacheStatus, Counter>,
+    validation_violations: Box<CounterVec>,
 
     // Limits the number of graphql queries that may execute concurrently.
     query_semaphore: Arc<tokio::sync::Semaphore>,
@@ -303,6 +304,14 @@ impl LoadManager {
             })
             .collect::<HashMap<_, _>>();
 
+        let validation_violations = registry
+            .new_counter_vec(
+                "query_validation_violations",
+                "Count validation rule violations of GraphQL queries",
+                vec!["rule".to_owned(), "mode".to_owned()],
+            )
+            .expect("failed to create `query_validation_violations` counter");
+
         let semaphore_wait_gauge = registry
             .new_gauge(
                 "query_semaphore_wait_ms",
@@ -325,6 +334,7 @@ impl LoadManager {
             kill_state: RwLock::new(KillState::new()),
             effort_gauge,
             query_counters,
+            validation_violations,
             query_semaphore,
             semaphore_wait_stats: RwLock::new(MovingStats::default()),
             semaphore_wait_gauge,
@@ -343,6 +353,14 @@ impl LoadManager {
         }
     }
 
+    /// Record that a query violated the validation rule `rule`, which is
+    /// in mode `mode`
+    pub fn record_validation_violation(&self, rule: &str, mode: &str) {
+        self.validation_violations
+            .with_label_values(&[rule, mode])
+            .inc();
+    }
+
     /// Decide whether we should decline to run the query with this
     /// `ShapeHash`. This is the heart of reacting to overload situations.
     ///
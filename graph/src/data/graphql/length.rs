@@ -0,0 +1,123 @@
+//! Byte lengths of fields. The directive `@length(bytes: 20)` on a field
+//! of type `Bytes`, `String` or `ID` says that its values are that many
+//! bytes long, and that values of `String` and `ID` fields are written as
+//! `0x`-prefixed hex. The directive is only allowed in subgraphs that set
+//! `strictBytes` in their manifest; mappings of such subgraphs get a
+//! deterministic error when they write a value of the wrong length, and
+//! hex strings are stored in lowercase so that they compare equal no
+//! matter how the mapping spelled them.
+use graphql_parser::query as q;
+use graphql_parser::schema::Field;
+
+use super::ext::{DirectiveExt, DirectiveFinder};
+use crate::data::store::Value;
+
+/// The name of the directive that declares the byte length of a field
+pub const LENGTH_DIRECTIVE: &str = "length";
+
+/// Parse the `@length` directive of `field`. Return `None` if the field
+/// does not have one, and an explanation if the directive is malformed
+pub fn byte_length(field: &Field) -> Result<Option<usize>, String> {
+    let directive = match field.find_directive(LENGTH_DIRECTIVE.to_owned()) {
+        Some(directive) => directive,
+        None => return Ok(None),
+    };
+
+    match directive.argument("bytes") {
+        Some(q::Value::Int(n)) => match n.as_i64() {
+            Some(n) if n > 0 => Ok(Some(n as usize)),
+            _ => Err("the @length `bytes` argument must be a positive number".to_owned()),
+        },
+        _ => Err("the @length directive must have a `bytes` argument".to_owned()),
+    }
+}
+
+/// Return the lowercase form of the `0x`-prefixed hex string `s`, and
+/// check that it encodes exactly `length` bytes if that is given
+pub fn canonical_hex(s: &str, length: Option<usize>) -> Result<String, String> {
+    let digits = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(digits) => digits,
+        None => return Err("hex strings must start with `0x`".to_owned()),
+    };
+    let bytes = hex::decode(digits).map_err(|e| e.to_string())?;
+    match length {
+        Some(length) if bytes.len() != length => {
+            Err(format!("expected {} bytes but got {}", length, bytes.len()))
+        }
+        _ => Ok(format!("0x{}", hex::encode(&bytes))),
+    }
+}
+
+/// Lowercase the strings in `value`, which is used to filter by `field`,
+/// so that it matches the form in which values of `@length` fields are
+/// stored. Filter values are not checked since operators like
+/// `_starts_with` are used with partial values
+pub fn canonical_filter_value(field: &Field, value: Value) -> Value {
+    if field.find_directive(LENGTH_DIRECTIVE.to_owned()).is_none() {
+        return value;
+    }
+
+    match value {
+        Value::String(s) => Value::String(s.to_lowercase()),
+        Value::List(values) => Value::List(
+            values
+                .into_iter()
+                .map(|value| canonical_filter_value(field, value))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(directive: &str) -> Field {
+        let schema = format!(
+            "type Thing @entity {{ id: ID!, owner: String {} }}",
+            directive
+        );
+        let document = graphql_parser::parse_schema(&schema).unwrap();
+        match document.definitions.into_iter().next() {
+            Some(graphql_parser::schema::Definition::TypeDefinition(
+                graphql_parser::schema::TypeDefinition::Object(t),
+            )) => t.fields.into_iter().nth(1).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_directive() {
+        assert_eq!(Ok(None), byte_length(&field("")));
+        assert_eq!(Ok(Some(20)), byte_length(&field("@length(bytes: 20)")));
+        assert!(byte_length(&field("@length")).is_err());
+        assert!(byte_length(&field("@length(bytes: 0)")).is_err());
+        assert!(byte_length(&field("@length(bytes: \"20\")")).is_err());
+    }
+
+    #[test]
+    fn canonicalize() {
+        assert_eq!(
+            Ok("0xabcdef01".to_owned()),
+            canonical_hex("0xAbCdEf01", Some(4))
+        );
+        assert_eq!(Ok("0xab".to_owned()), canonical_hex("0XAB", None));
+        assert!(canonical_hex("AbCdEf01", Some(4)).is_err());
+        assert!(canonical_hex("0xAbCdEf0", None).is_err());
+        assert!(canonical_hex("0xAbCdEf01", Some(20)).is_err());
+
+        let owner = field("@length(bytes: 2)");
+        assert_eq!(
+            Value::List(vec![Value::String("0xabcd".to_owned())]),
+            canonical_filter_value(
+                &owner,
+                Value::List(vec![Value::String("0xABcd".to_owned())])
+            )
+        );
+        assert_eq!(
+            Value::String("0xABcd".to_owned()),
+            canonical_filter_value(&field(""), Value::String("0xABcd".to_owned()))
+        );
+    }
+}
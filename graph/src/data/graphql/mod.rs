@@ -23,6 +23,12 @@ pub use self::values::{
 
 pub mod shape_hash;
 
+pub mod computed;
+
+pub mod length;
+
+pub mod ttl;
+
 pub mod effort;
 
 pub mod object_or_interface;
@@ -0,0 +1,92 @@
+//! Entity types for ephemeral data. The directive `@ttl(blocks: 100)` on an
+//! entity type says that an entity of that type expires when it has not
+//! been changed for more than 100 blocks. Queries never return expired
+//! entities, and the pruning that the store does regularly removes them
+//! together with their history.
+//!
+//! Mappings that process block `B` see the same entities as a query at
+//! block `B`: an entity that expired at `B` can not be loaded, whether the
+//! store has removed it yet or not, and setting it again creates a new
+//! entity.
+//!
+//! Expiry does not change any entities; it only affects what queries and
+//! mappings see and what the store keeps. It therefore has no effect on the
+//! Proof of Indexing.
+use graphql_parser::query as q;
+use graphql_parser::schema::{Document, ObjectType};
+use std::collections::BTreeSet;
+
+use super::ext::{DirectiveExt, DirectiveFinder, DocumentExt};
+use crate::components::store::BlockNumber;
+
+/// The name of the directive that makes entities of a type expire
+pub const TTL_DIRECTIVE: &str = "ttl";
+
+/// The smallest number of blocks that a `@ttl` can have. This is a fixed
+/// part of the schema rules rather than the reorg threshold of a node so
+/// that whether a schema is valid does not depend on how the node that
+/// checks it is configured
+pub const MIN_TTL_BLOCKS: BlockNumber = 100;
+
+/// Parse the `@ttl` directive of `object_type`. Return `None` if the type
+/// does not have one, and an explanation if the directive is malformed
+pub fn ttl_blocks(object_type: &ObjectType) -> Result<Option<BlockNumber>, String> {
+    let directive = match object_type.find_directive(TTL_DIRECTIVE.to_owned()) {
+        Some(directive) => directive,
+        None => return Ok(None),
+    };
+
+    match directive.argument("blocks") {
+        Some(q::Value::Int(n)) => match n.as_i64() {
+            Some(n) if n > 0 && n <= BlockNumber::MAX as i64 => Ok(Some(n as BlockNumber)),
+            _ => Err("the @ttl `blocks` argument must be a positive number".to_owned()),
+        },
+        _ => Err("the @ttl directive must have a `blocks` argument".to_owned()),
+    }
+}
+
+/// The entity types in `document` whose entities expire
+pub fn expiring_entity_types(document: &Document) -> BTreeSet<String> {
+    document
+        .get_object_type_definitions()
+        .into_iter()
+        .filter(|object_type| matches!(ttl_blocks(object_type), Ok(Some(_))))
+        .map(|object_type| object_type.name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object_type(directive: &str) -> ObjectType {
+        let schema = format!("type Order @entity {} {{ id: ID! }}", directive);
+        let document = graphql_parser::parse_schema(&schema).unwrap();
+        match document.definitions.into_iter().next() {
+            Some(graphql_parser::schema::Definition::TypeDefinition(
+                graphql_parser::schema::TypeDefinition::Object(t),
+            )) => t,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parse_directive() {
+        assert_eq!(Ok(None), ttl_blocks(&object_type("")));
+        assert_eq!(Ok(Some(100)), ttl_blocks(&object_type("@ttl(blocks: 100)")));
+        assert!(ttl_blocks(&object_type("@ttl")).is_err());
+        assert!(ttl_blocks(&object_type("@ttl(blocks: 0)")).is_err());
+        assert!(ttl_blocks(&object_type("@ttl(blocks: \"100\")")).is_err());
+    }
+
+    #[test]
+    fn find_expiring_entity_types() {
+        let document = graphql_parser::parse_schema(
+            "type Order @entity @ttl(blocks: 100) { id: ID! }
+             type Account @entity { id: ID! }",
+        )
+        .unwrap();
+        let expected: BTreeSet<_> = vec!["Order".to_owned()].into_iter().collect();
+        assert_eq!(expected, expiring_entity_types(&document));
+    }
+}
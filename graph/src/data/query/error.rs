@@ -10,6 +10,8 @@ use std::string::FromUtf8Error;
 use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
+use crate::data::store::scalar::TimestampError;
+use crate::data::subgraph::schema::SubgraphError;
 use crate::data::subgraph::*;
 use crate::{components::store::StoreError, prelude::CacheWeight};
 
@@ -78,6 +80,31 @@ pub enum QueryExecutionError {
     EventStreamError,
     FulltextQueryRequiresFilter,
     DeploymentReverted,
+    DeploymentFailed(SubgraphError),
+    UndeclaredEntityType(String),
+    SystemEntityType(String),
+    InvalidIncrement(String, String, String),
+    InvalidEntity(String),
+    ValidationError(Pos, &'static str, String), // (position, rule, message)
+}
+
+impl QueryExecutionError {
+    /// A coarse classification of the error for logs and metrics
+    pub fn class(&self) -> &'static str {
+        use self::QueryExecutionError::*;
+
+        match self {
+            Timeout => "timeout",
+            TooComplex(..) | TooDeep(_) | TooExpensive | Throttled => "limit",
+            StoreError(_)
+            | ResolveEntityError(..)
+            | ResolveEntitiesError(_)
+            | EntityParseError(_) => "store",
+            DeploymentReverted | DeploymentFailed(_) => "deployment",
+            IncorrectPrefetchResult { .. } | Panic(_) | EventStreamError => "internal",
+            _ => "query",
+        }
+    }
 }
 
 impl Error for QueryExecutionError {
@@ -218,6 +245,25 @@ impl fmt::Display for QueryExecutionError {
             TooExpensive => write!(f, "query is too expensive"),
             Throttled=> write!(f, "service is overloaded and can not run the query right now. Please try again in a few minutes"),
             DeploymentReverted => write!(f, "the chain was reorganized while executing the query"),
+            DeploymentFailed(error) => {
+                write!(f, "the subgraph `{}` failed", error.subgraph_id)?;
+                if let Some(ptr) = &error.block_ptr {
+                    write!(f, " at block {} ({})", ptr.number, ptr.hash_hex())?;
+                }
+                write!(f, " with error `{}`; use `subgraphError: allow` to query the data \
+                           as of the failure anyway", error.message)
+            }
+            UndeclaredEntityType(entity_type) => {
+                write!(f, "entity type `{}` is not listed in the `entities` of the data source", entity_type)
+            }
+            SystemEntityType(entity_type) => {
+                write!(f, "entity type `{}` is internal to graph-node and can not be queried", entity_type)
+            }
+            InvalidIncrement(entity, field, reason) => {
+                write!(f, "can not increment field `{}` of {}: {}", field, entity, reason)
+            }
+            InvalidEntity(e) => write!(f, "{}", e),
+            ValidationError(_, _, msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -246,6 +292,12 @@ impl From<bigdecimal::ParseBigDecimalError> for QueryExecutionError {
     }
 }
 
+impl From<TimestampError> for QueryExecutionError {
+    fn from(e: TimestampError) -> Self {
+        QueryExecutionError::ValueParseError("Timestamp".to_string(), e.to_string())
+    }
+}
+
 impl From<StoreError> for QueryExecutionError {
     fn from(e: StoreError) -> Self {
         QueryExecutionError::StoreError(CloneableFailureError(Arc::new(e.into())))
@@ -260,6 +312,17 @@ pub enum QueryError {
     ExecutionError(QueryExecutionError),
 }
 
+impl QueryError {
+    /// A coarse classification of the error for logs and metrics
+    pub fn class(&self) -> &'static str {
+        match self {
+            QueryError::EncodingError(_) => "encoding",
+            QueryError::ParseError(_) => "parse",
+            QueryError::ExecutionError(e) => e.class(),
+        }
+    }
+}
+
 impl From<FromUtf8Error> for QueryError {
     fn from(e: FromUtf8Error) -> Self {
         QueryError::EncodingError(e)
@@ -301,8 +364,6 @@ impl Serialize for QueryError {
     where
         S: Serializer,
     {
-        use self::QueryExecutionError::*;
-
         let entry_count =
             if let QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult {
                 ..
@@ -313,6 +374,17 @@ impl Serialize for QueryError {
                 1
             };
         let mut map = serializer.serialize_map(Some(entry_count))?;
+        self.serialize_entries(&mut map)?;
+        map.end()
+    }
+}
+
+impl QueryError {
+    /// Serialize the entries for this error into `map`, so that the
+    /// entries can be combined with others, like the path of the field
+    /// that the error belongs to
+    pub(crate) fn serialize_entries<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        use self::QueryExecutionError::*;
 
         let msg = match self {
             // Serialize parse errors with their location (line, column) to make it easier
@@ -361,6 +433,7 @@ impl Serialize for QueryError {
             | QueryError::ExecutionError(AmbiguousDerivedFromResult(pos, _, _, _))
             | QueryError::ExecutionError(EnumCoercionError(pos, _, _, _, _))
             | QueryError::ExecutionError(ScalarCoercionError(pos, _, _, _))
+            | QueryError::ExecutionError(ValidationError(pos, _, _))
             | QueryError::ExecutionError(UnknownField(pos, _, _)) => {
                 let mut location = HashMap::new();
                 location.insert("line", pos.line);
@@ -377,8 +450,7 @@ impl Serialize for QueryError {
             _ => format!("{}", self),
         };
 
-        map.serialize_entry("message", msg.as_str())?;
-        map.end()
+        map.serialize_entry("message", msg.as_str())
     }
 }
 
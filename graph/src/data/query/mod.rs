@@ -5,5 +5,5 @@ mod result;
 
 pub use self::cache_status::CacheStatus;
 pub use self::error::{QueryError, QueryExecutionError};
-pub use self::query::{Query, QueryVariables};
-pub use self::result::QueryResult;
+pub use self::query::{new_query_id, ErrorPolicy, Query, QueryVariables};
+pub use self::result::{PathSegment, QueryResult, QUERY_ID_HEADER};
@@ -1,8 +1,11 @@
 use graphql_parser::query as q;
 use serde::de::Deserializer;
 use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
 use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use crate::data::graphql::shape_hash::shape_hash;
@@ -104,6 +107,38 @@ impl serde::ser::Serialize for QueryVariables {
     }
 }
 
+/// How a query treats a deployment that has failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Return the data as of the block at which the deployment failed
+    Allow,
+    /// Return an error with the reason for the failure instead of data
+    Deny,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::Deny
+    }
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(ErrorPolicy::Allow),
+            "deny" => Ok(ErrorPolicy::Deny),
+            _ => Err(format!("invalid subgraph error policy `{}`", s)),
+        }
+    }
+}
+
+/// A fresh id for a GraphQL request
+pub fn new_query_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
 /// A GraphQL query as submitted by a client, either directly or through a subscription.
 #[derive(Clone, Debug)]
 pub struct Query {
@@ -114,6 +149,13 @@ pub struct Query {
     pub network: Option<String>,
     pub query_text: Arc<String>,
     pub variables_text: Arc<String>,
+    /// The error policy requested for the whole query, for example through
+    /// a header. Individual fields can still allow errors with the
+    /// `subgraphError` argument
+    pub error_policy: ErrorPolicy,
+    /// A unique id for this query that is sent back to the client and
+    /// included in all log lines about the query
+    pub query_id: String,
     _force_use_of_new: (),
 }
 
@@ -146,7 +188,27 @@ impl Query {
             network,
             query_text: Arc::new(query_text),
             variables_text: Arc::new(variables_text),
+            error_policy: ErrorPolicy::default(),
+            query_id: new_query_id(),
             _force_use_of_new: (),
         }
     }
+
+    /// The variables of the query as they should appear in logs. Unless
+    /// `GRAPH_LOG_QUERY_VARIABLES` is set, that is only a hash of the
+    /// variables so that we do not log personal data
+    pub fn variables_for_log(&self) -> String {
+        // Go through `serde_json::Value` so that the keys are sorted and
+        // the same variables always produce the same hash
+        let text = serde_json::to_value(&self.variables)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        if *crate::log::LOG_QUERY_VARIABLES {
+            text
+        } else {
+            let mut hasher = DefaultHasher::new();
+            text.hash(&mut hasher);
+            format!("{:x}", hasher.finish())
+        }
+    }
 }
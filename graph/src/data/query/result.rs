@@ -1,5 +1,8 @@
 use super::error::{QueryError, QueryExecutionError};
-use crate::{data::graphql::SerializableValue, prelude::CacheWeight};
+use crate::{
+    data::graphql::SerializableValue,
+    prelude::{CacheWeight, EthereumBlockPointer},
+};
 use graphql_parser::query as q;
 use serde::ser::*;
 use serde::Serialize;
@@ -7,6 +10,9 @@ use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::sync::Arc;
 
+/// The header in which we send the id of a query back to the client
+pub const QUERY_ID_HEADER: &str = "Graph-Query-Id";
+
 fn serialize_data<S>(data: &Option<q::Value>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -31,6 +37,51 @@ where
 
 pub type Data = BTreeMap<String, q::Value>;
 
+/// One step on the path from the root of the response to a value in it:
+/// either the response key of a field or the index of an element in a list
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+/// An error in a `QueryResult`. Errors that happened while completing the
+/// value of a field carry the path to that field in the response, other
+/// errors have no path
+#[derive(Debug, Clone)]
+struct ResultError {
+    error: QueryError,
+    path: Option<Vec<PathSegment>>,
+}
+
+impl From<QueryError> for ResultError {
+    fn from(error: QueryError) -> Self {
+        ResultError { error, path: None }
+    }
+}
+
+impl Serialize for ResultError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        self.error.serialize_entries(&mut map)?;
+        if let Some(path) = &self.path {
+            map.serialize_entry("path", path)?;
+        }
+        map.end()
+    }
+}
+
+impl CacheWeight for ResultError {
+    fn indirect_weight(&self) -> usize {
+        // Errors don't have a weight since they are never cached
+        0
+    }
+}
+
 /// The result of running a query, if successful.
 #[derive(Debug, Clone, Serialize)]
 pub struct QueryResult {
@@ -40,12 +91,15 @@ pub struct QueryResult {
     )]
     data: Vec<Arc<Data>>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    errors: Vec<QueryError>,
+    errors: Vec<ResultError>,
     #[serde(
         skip_serializing_if = "Option::is_none",
         serialize_with = "serialize_data"
     )]
     pub extensions: Option<q::Value>,
+    /// The blocks at which the query read data from the store
+    #[serde(skip)]
+    blocks: Vec<EthereumBlockPointer>,
 }
 
 impl QueryResult {
@@ -55,6 +109,7 @@ impl QueryResult {
             data: Vec::new(),
             errors: Vec::new(),
             extensions: None,
+            blocks: Vec::new(),
         }
     }
 
@@ -63,6 +118,29 @@ impl QueryResult {
             data,
             errors: Vec::new(),
             extensions: None,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// A result for a query where completing some fields failed. The
+    /// value of each of these fields in `data` is `null`, or, if the field
+    /// can not be `null`, that of its nearest ancestor that can be. `data`
+    /// is `None` if that leaves nothing of the response
+    pub fn with_field_errors(
+        data: Option<Data>,
+        errors: Vec<(QueryExecutionError, Vec<PathSegment>)>,
+    ) -> Self {
+        QueryResult {
+            data: data.map(Arc::new).into_iter().collect(),
+            errors: errors
+                .into_iter()
+                .map(|(error, path)| ResultError {
+                    error: QueryError::from(error),
+                    path: Some(path),
+                })
+                .collect(),
+            extensions: None,
+            blocks: Vec::new(),
         }
     }
 
@@ -71,6 +149,18 @@ impl QueryResult {
         self
     }
 
+    /// Record that the data in this result was read at `block`
+    pub fn with_block(mut self, block: EthereumBlockPointer) -> Self {
+        self.blocks.push(block);
+        self
+    }
+
+    /// The blocks at which the data in this result was read. This is
+    /// empty for results that did not read anything from the store
+    pub fn blocks(&self) -> &[EthereumBlockPointer] {
+        &self.blocks
+    }
+
     pub fn has_errors(&self) -> bool {
         return !self.errors.is_empty();
     }
@@ -82,20 +172,64 @@ impl QueryResult {
 
         self.data.extend(other.data);
         self.errors.extend(other.errors);
+        self.blocks.extend(other.blocks);
+    }
+
+    /// The class of the first error in this result, if there are any
+    /// errors
+    pub fn error_class(&self) -> Option<&'static str> {
+        self.errors.first().map(|e| e.error.class())
     }
 
     pub fn as_http_response<T: From<String>>(&self) -> http::Response<T> {
-        let status_code = http::StatusCode::OK;
         let json =
             serde_json::to_string(&self).expect("Failed to serialize GraphQL response to JSON");
+        Self::http_response_builder().body(T::from(json)).unwrap()
+    }
+
+    /// Like `as_http_response`, but also send `query_id` back to the client
+    /// in the `Graph-Query-Id` header and as `extensions.queryId`
+    pub fn as_http_response_with_query_id<T: From<String>>(
+        &self,
+        query_id: &str,
+    ) -> http::Response<T> {
+        self.as_http_response_with_warning(query_id, None)
+    }
+
+    /// Like `as_http_response_with_query_id`, but also send `warning` to
+    /// the client as `extensions.warning` if it is set
+    pub fn as_http_response_with_warning<T: From<String>>(
+        &self,
+        query_id: &str,
+        warning: Option<&str>,
+    ) -> http::Response<T> {
+        let mut json =
+            serde_json::to_value(&self).expect("Failed to serialize GraphQL response to JSON");
+        if let Some(obj) = json.as_object_mut() {
+            let extensions = obj
+                .entry("extensions")
+                .or_insert_with(|| serde_json::Value::Object(Default::default()));
+            if let Some(extensions) = extensions.as_object_mut() {
+                extensions.insert("queryId".to_owned(), query_id.into());
+                if let Some(warning) = warning {
+                    extensions.insert("warning".to_owned(), warning.into());
+                }
+            }
+        }
+        Self::http_response_builder()
+            .header(QUERY_ID_HEADER, query_id)
+            .header("Access-Control-Expose-Headers", QUERY_ID_HEADER)
+            .body(T::from(json.to_string()))
+            .unwrap()
+    }
+
+    fn http_response_builder() -> http::response::Builder {
         http::Response::builder()
-            .status(status_code)
+            .status(http::StatusCode::OK)
             .header("Access-Control-Allow-Origin", "*")
             .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
             .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
             .header("Content-Type", "application/json")
-            .body(T::from(json))
-            .unwrap()
     }
 
     /// Combine all the data into one `q::Value`. This method might clone
@@ -126,7 +260,7 @@ impl QueryResult {
     /// reference count greater than 1. If there are errors, the data is ignored.
     pub fn to_result(self) -> Result<Option<q::Value>, Vec<QueryError>> {
         if self.has_errors() {
-            Err(self.errors)
+            Err(self.errors.into_iter().map(|e| e.error).collect())
         } else {
             Ok(self.take_data())
         }
@@ -136,7 +270,7 @@ impl QueryResult {
 impl From<QueryExecutionError> for QueryResult {
     fn from(e: QueryExecutionError) -> Self {
         let mut result = Self::new(Vec::new());
-        result.errors = vec![QueryError::from(e)];
+        result.errors = vec![ResultError::from(QueryError::from(e))];
         result
     }
 }
@@ -145,8 +279,9 @@ impl From<QueryError> for QueryResult {
     fn from(e: QueryError) -> Self {
         QueryResult {
             data: Vec::new(),
-            errors: vec![e],
+            errors: vec![ResultError::from(e)],
             extensions: None,
+            blocks: Vec::new(),
         }
     }
 }
@@ -155,8 +290,12 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
     fn from(e: Vec<QueryExecutionError>) -> Self {
         QueryResult {
             data: Vec::new(),
-            errors: e.into_iter().map(QueryError::from).collect(),
+            errors: e
+                .into_iter()
+                .map(|e| ResultError::from(QueryError::from(e)))
+                .collect(),
             extensions: None,
+            blocks: Vec::new(),
         }
     }
 }
@@ -206,7 +345,10 @@ impl CacheWeight for QueryResult {
             .sum::<usize>()
             + self.data.capacity() * std::mem::size_of::<Arc<Data>>();
 
-        data_weight + self.errors.indirect_weight() + self.extensions.indirect_weight()
+        data_weight
+            + self.errors.indirect_weight()
+            + self.extensions.indirect_weight()
+            + self.blocks.capacity() * std::mem::size_of::<EthereumBlockPointer>()
     }
 }
 
@@ -232,3 +374,33 @@ fn multiple_data_items() {
     let actual = serde_json::to_string(&res).unwrap();
     assert_eq!(expected, actual)
 }
+
+// Check that errors for fields are serialized with the path to the field
+// next to the data that is left
+#[test]
+fn field_errors_have_path() {
+    use graphql_parser::Pos;
+    use serde_json::json;
+
+    let mut data = BTreeMap::new();
+    data.insert("user".to_owned(), q::Value::Null);
+    let error = QueryExecutionError::NonNullError(Pos { line: 1, column: 9 }, "name".to_owned());
+    let path = vec![
+        PathSegment::Field("user".to_owned()),
+        PathSegment::Field("friends".to_owned()),
+        PathSegment::Index(1),
+        PathSegment::Field("name".to_owned()),
+    ];
+
+    let res = QueryResult::with_field_errors(Some(data), vec![(error, path)]);
+
+    let expected = json!({
+        "data": { "user": null },
+        "errors": [{
+            "locations": [{ "line": 1, "column": 9 }],
+            "message": "Null value resolved for non-null field `name`",
+            "path": ["user", "friends", 1, "name"],
+        }],
+    });
+    assert_eq!(expected, serde_json::to_value(&res).unwrap());
+}
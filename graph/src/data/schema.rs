@@ -1,8 +1,12 @@
-use crate::components::store::{Store, SubgraphDeploymentStore};
+use crate::components::store::SubgraphDeploymentStore;
+use crate::data::graphql::computed::ComputedField;
 use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
+use crate::data::graphql::length::byte_length;
+use crate::data::graphql::ttl::{ttl_blocks, MIN_TTL_BLOCKS};
 use crate::data::store::ValueType;
 use crate::data::subgraph::{SubgraphDeploymentId, SubgraphName};
 use crate::prelude::Fail;
+use crate::util::intern::Word;
 
 use anyhow::Context;
 use failure::Error;
@@ -28,6 +32,9 @@ pub const SCHEMA_TYPE_NAME: &str = "_Schema_";
 pub const META_FIELD_TYPE: &str = "_Meta_";
 pub const META_FIELD_NAME: &str = "_meta";
 
+pub const ERROR_POLICY_TYPE: &str = "_SubgraphErrorPolicy_";
+pub const ERROR_POLICY_ARGUMENT: &str = "subgraphError";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -57,6 +64,18 @@ pub enum SchemaValidationError {
         _1, _0, _2
     )]
     InvalidDerivedFrom(String, String, String), // (type, field, reason)
+    #[fail(
+        display = "Field `{}` in type `{}` has invalid @computed: {}",
+        _1, _0, _2
+    )]
+    InvalidComputedField(String, String, String), // (type, field, reason)
+    #[fail(
+        display = "Field `{}` in type `{}` has invalid @length: {}",
+        _1, _0, _2
+    )]
+    InvalidLength(String, String, String), // (type, field, reason)
+    #[fail(display = "Type `{}` has invalid @ttl: {}", _0, _1)]
+    InvalidTtl(String, String), // (type, reason)
     #[fail(display = "_Schema_ type is only for @imports and must not have any fields")]
     SchemaTypeWithFields,
     #[fail(display = "Imported subgraph name `{}` is invalid", _0)]
@@ -336,7 +355,7 @@ impl SchemaReference {
         SchemaReference { subgraph }
     }
 
-    pub fn resolve<S: Store + SubgraphDeploymentStore>(
+    pub fn resolve<S: SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
     ) -> Result<Arc<Schema>, SchemaImportError> {
@@ -406,7 +425,7 @@ impl ApiSchema {
     }
 
     /// Returns `None` if the type implements no interfaces.
-    pub fn interfaces_for_type(&self, type_name: &Name) -> Option<&Vec<InterfaceType>> {
+    pub fn interfaces_for_type(&self, type_name: &str) -> Option<&Vec<InterfaceType>> {
         self.schema.interfaces_for_type(type_name)
     }
 }
@@ -437,7 +456,7 @@ impl Schema {
         }
     }
 
-    pub fn resolve_schema_references<S: Store + SubgraphDeploymentStore>(
+    pub fn resolve_schema_references<S: SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
     ) -> (
@@ -450,7 +469,7 @@ impl Schema {
         (schemas, import_errors)
     }
 
-    fn resolve_import_graph<S: Store + SubgraphDeploymentStore>(
+    fn resolve_import_graph<S: SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
         schemas: &mut HashMap<SchemaReference, Arc<Schema>>,
@@ -606,7 +625,7 @@ impl Schema {
     }
 
     /// Returns `None` if the type implements no interfaces.
-    pub fn interfaces_for_type(&self, type_name: &Name) -> Option<&Vec<InterfaceType>> {
+    pub fn interfaces_for_type(&self, type_name: &str) -> Option<&Vec<InterfaceType>> {
         self.interfaces_for_type.get(type_name)
     }
 
@@ -665,6 +684,11 @@ impl Schema {
             .unwrap_or_else(|err| errors.push(err));
         self.validate_derived_from()
             .unwrap_or_else(|err| errors.push(err));
+        self.validate_computed_fields()
+            .unwrap_or_else(|err| errors.push(err));
+        self.validate_lengths()
+            .unwrap_or_else(|err| errors.push(err));
+        self.validate_ttls().unwrap_or_else(|err| errors.push(err));
         self.validate_schema_type_has_no_fields()
             .unwrap_or_else(|err| errors.push(err));
         self.validate_directives_on_schema_type()
@@ -674,12 +698,25 @@ impl Schema {
         errors.append(&mut self.validate_fulltext_directives());
         errors.append(&mut self.validate_imported_types(schemas));
         if errors.is_empty() {
+            self.intern_names();
             Ok(())
         } else {
             Err(errors)
         }
     }
 
+    /// Intern the names of the entity types and their fields so that all
+    /// entities of this schema share them. Only call this for schemas that
+    /// are valid, like the schemas of deployed subgraphs
+    pub fn intern_names(&self) {
+        for (name, fields) in self.document.get_object_and_interface_type_fields() {
+            Word::intern(name);
+            for field in fields {
+                Word::intern(&field.name);
+            }
+        }
+    }
+
     fn validate_schema_type_has_no_fields(&self) -> Result<(), SchemaValidationError> {
         match self
             .subgraph_schema_object_type()
@@ -1246,6 +1283,154 @@ impl Schema {
         Ok(())
     }
 
+    fn validate_computed_fields(&self) -> Result<(), SchemaValidationError> {
+        fn invalid(object_type: &ObjectType, field: &Field, reason: &str) -> SchemaValidationError {
+            SchemaValidationError::InvalidComputedField(
+                object_type.name.to_owned(),
+                field.name.to_owned(),
+                reason.to_owned(),
+            )
+        }
+
+        for object_type in self.document.get_object_type_definitions() {
+            for field in &object_type.fields {
+                let computed = match ComputedField::from_field(field)
+                    .map_err(|reason| invalid(object_type, field, &reason))?
+                {
+                    Some(computed) => computed,
+                    None => continue,
+                };
+
+                if field.name == "id" {
+                    return Err(invalid(
+                        object_type,
+                        field,
+                        "the `id` field can not be computed",
+                    ));
+                }
+                if field.find_directive(String::from("derivedFrom")).is_some() {
+                    return Err(invalid(
+                        object_type,
+                        field,
+                        "a field can not be both computed and derived",
+                    ));
+                }
+                let is_list = match &field.field_type {
+                    Type::ListType(_) => true,
+                    Type::NonNullType(inner) => match inner.as_ref() {
+                        Type::ListType(_) => true,
+                        _ => false,
+                    },
+                    Type::NamedType(_) => false,
+                };
+                if is_list || !ValueType::is_scalar(field.field_type.get_base_type()) {
+                    return Err(invalid(
+                        object_type,
+                        field,
+                        "computed fields must have a scalar type",
+                    ));
+                }
+
+                for source in &computed.from {
+                    let source_field = object_type
+                        .fields
+                        .iter()
+                        .find(|field| &field.name == source)
+                        .ok_or_else(|| {
+                            let msg = format!(
+                                "field `{}` does not exist on type `{}`",
+                                source, object_type.name
+                            );
+                            invalid(object_type, field, &msg)
+                        })?;
+                    if ComputedField::is_computed(source_field)
+                        || source_field
+                            .find_directive(String::from("derivedFrom"))
+                            .is_some()
+                    {
+                        let msg = format!("field `{}` is not stored", source);
+                        return Err(invalid(object_type, field, &msg));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_lengths(&self) -> Result<(), SchemaValidationError> {
+        for object_type in self.document.get_object_type_definitions() {
+            for field in &object_type.fields {
+                let invalid = |reason: &str| {
+                    SchemaValidationError::InvalidLength(
+                        object_type.name.to_owned(),
+                        field.name.to_owned(),
+                        reason.to_owned(),
+                    )
+                };
+                if byte_length(field)
+                    .map_err(|reason| invalid(&reason))?
+                    .is_none()
+                {
+                    continue;
+                }
+                match field.field_type.get_base_type().as_str() {
+                    "Bytes" | "String" | "ID" => {}
+                    _ => {
+                        return Err(invalid(
+                            "only fields of type Bytes, String or ID can have a length",
+                        ))
+                    }
+                }
+                if field.find_directive(String::from("derivedFrom")).is_some()
+                    || ComputedField::is_computed(field)
+                {
+                    return Err(invalid("the field is not stored"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `@ttl` directives are well-formed and let entities live
+    /// for at least `MIN_TTL_BLOCKS`
+    fn validate_ttls(&self) -> Result<(), SchemaValidationError> {
+        for object_type in self.document.get_object_type_definitions() {
+            let invalid = |reason: String| {
+                SchemaValidationError::InvalidTtl(object_type.name.clone(), reason)
+            };
+            match ttl_blocks(object_type).map_err(invalid)? {
+                Some(blocks) if blocks < MIN_TTL_BLOCKS => {
+                    return Err(invalid(format!(
+                        "the @ttl `blocks` argument must be at least {}",
+                        MIN_TTL_BLOCKS
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The `@computed` fields of all object types in the schema. Malformed
+    /// directives are left for `validate` to report
+    pub fn computed_fields(&self) -> Vec<ComputedField> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .flat_map(|object_type| object_type.fields.iter())
+            .filter_map(|field| ComputedField::from_field(field).ok().flatten())
+            .collect()
+    }
+
+    /// Return `true` if any field in the schema has a `@length` directive
+    pub fn uses_lengths(&self) -> bool {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .flat_map(|object_type| object_type.fields.iter())
+            .any(|field| byte_length(field) != Ok(None))
+    }
+
     /// Validate that `object` implements `interface`.
     fn validate_interface_implementation(
         object: &ObjectType,
@@ -1408,6 +1593,144 @@ type Account implements Address @entity { id: ID!, txn: Transaction! @derivedFro
     validate("j: B @derivedFrom(field: \"id\")", "ok");
 }
 
+#[test]
+fn test_computed_field_validation() {
+    fn validate(field: &str, errmsg: &str) {
+        let raw = format!(
+            "type A @entity {{ id: ID!, x: BigInt, y: B, z: [B!]! @derivedFrom(field: \"a\")\n {} }}
+             type B @entity {{ id: ID!, a: A }}",
+            field
+        );
+
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        match schema.validate_computed_fields() {
+            Err(SchemaValidationError::InvalidComputedField(_, _, msg)) => assert_eq!(errmsg, msg),
+            Err(e) => panic!("expected InvalidComputedField but got {:?}", e),
+            Ok(_) => {
+                if errmsg != "ok" {
+                    panic!("expected validation for `{}` to fail", field)
+                }
+            }
+        }
+    }
+
+    validate("c: BigInt @computed(from: [\"x\", \"y\"])", "ok");
+    validate(
+        "c: BigDecimal! @computed(from: [\"x\"], function: \"f\")",
+        "ok",
+    );
+    validate(
+        "c: BigInt @computed",
+        "the @computed directive must have a `from` list",
+    );
+    validate(
+        "c: BigInt @computed(from: [\"x\"], function: 1)",
+        "the @computed `function` argument must be a string",
+    );
+    validate(
+        "c: [BigInt!] @computed(from: [\"x\"])",
+        "computed fields must have a scalar type",
+    );
+    validate(
+        "c: B @computed(from: [\"x\"])",
+        "computed fields must have a scalar type",
+    );
+    validate(
+        "c: BigInt @computed(from: [\"w\"])",
+        "field `w` does not exist on type `A`",
+    );
+    validate(
+        "c: BigInt @computed(from: [\"z\"])",
+        "field `z` is not stored",
+    );
+    validate(
+        "c: BigInt @computed(from: [\"x\"]), d: BigInt @computed(from: [\"c\"])",
+        "field `c` is not stored",
+    );
+}
+
+#[test]
+fn test_length_validation() {
+    fn validate(field: &str, errmsg: &str) {
+        let raw = format!(
+            "type A @entity {{ id: ID!, b: [B!]! @derivedFrom(field: \"a\")\n {} }}
+             type B @entity {{ id: ID!, a: A }}",
+            field
+        );
+
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        match schema.validate_lengths() {
+            Err(SchemaValidationError::InvalidLength(_, _, msg)) => assert_eq!(errmsg, msg),
+            Err(e) => panic!("expected InvalidLength but got {:?}", e),
+            Ok(_) => {
+                if errmsg != "ok" {
+                    panic!("expected validation for `{}` to fail", field)
+                }
+            }
+        }
+        assert_eq!(!field.is_empty(), schema.uses_lengths());
+    }
+
+    validate("", "ok");
+    validate("c: Bytes @length(bytes: 20)", "ok");
+    validate("c: [String!]! @length(bytes: 32)", "ok");
+    validate(
+        "c: BigInt @length(bytes: 20)",
+        "only fields of type Bytes, String or ID can have a length",
+    );
+    validate(
+        "c: Bytes @length(bytes: -1)",
+        "the @length `bytes` argument must be a positive number",
+    );
+    validate(
+        "c: Bytes @length",
+        "the @length directive must have a `bytes` argument",
+    );
+    validate(
+        "c: String @computed(from: [\"id\"]) @length(bytes: 20)",
+        "the field is not stored",
+    );
+}
+
+#[test]
+fn test_ttl_validation() {
+    fn validate(directive: &str, errmsg: &str) {
+        let raw = format!(
+            "type Order @entity {} {{ id: ID!, price: Int! }}",
+            directive
+        );
+
+        let document = graphql_parser::parse_schema(&raw).expect("Failed to parse raw schema");
+        let schema = Schema::new(SubgraphDeploymentId::new("id").unwrap(), document);
+        match schema.validate_ttls() {
+            Err(SchemaValidationError::InvalidTtl(_, msg)) => assert_eq!(errmsg, msg),
+            Err(e) => panic!("expected InvalidTtl but got {:?}", e),
+            Ok(_) => {
+                if errmsg != "ok" {
+                    panic!("expected validation for `{}` to fail", directive)
+                }
+            }
+        }
+    }
+
+    validate("", "ok");
+    validate(&format!("@ttl(blocks: {})", MIN_TTL_BLOCKS), "ok");
+    validate(
+        &format!("@ttl(blocks: {})", MIN_TTL_BLOCKS - 1),
+        &format!(
+            "the @ttl `blocks` argument must be at least {}",
+            MIN_TTL_BLOCKS
+        ),
+    );
+    validate(
+        "@ttl(blocks: -5)",
+        "the @ttl `blocks` argument must be a positive number",
+    );
+    validate("@ttl", "the @ttl directive must have a `blocks` argument");
+}
+
 #[test]
 fn test_reserved_type_with_fields() {
     const ROOT_SCHEMA: &str = "
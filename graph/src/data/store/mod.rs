@@ -1,15 +1,15 @@
 use crate::data::subgraph::SubgraphDeploymentId;
 use crate::prelude::{format_err, CacheWeight, EntityKey, QueryExecutionError};
+use crate::util::intern::Word;
 use failure::Error;
 use graphql_parser::query;
 use graphql_parser::schema;
 use serde::de;
 use serde::{Deserialize, Serialize};
 use stable_hash::prelude::*;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
-use std::iter::FromIterator;
 use std::ops::{Deref, DerefMut};
 use std::str::FromStr;
 use strum::AsStaticRef as _;
@@ -95,13 +95,14 @@ impl AssignmentEvent {
     }
 }
 
-/// An entity attribute name is represented as a string.
-pub type Attribute = String;
+/// An entity attribute name is represented as an interned string.
+pub type Attribute = Word;
 
 pub const ID: &str = "ID";
 pub const BYTES_SCALAR: &str = "Bytes";
 pub const BIG_INT_SCALAR: &str = "BigInt";
 pub const BIG_DECIMAL_SCALAR: &str = "BigDecimal";
+pub const TIMESTAMP_SCALAR: &str = "Timestamp";
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ValueType {
@@ -111,6 +112,7 @@ pub enum ValueType {
     BigDecimal,
     Int,
     String,
+    Timestamp,
     List,
 }
 
@@ -125,6 +127,7 @@ impl FromStr for ValueType {
             "BigDecimal" => Ok(ValueType::BigDecimal),
             "Int" => Ok(ValueType::Int),
             "String" | "ID" => Ok(ValueType::String),
+            "Timestamp" => Ok(ValueType::Timestamp),
             "List" => Ok(ValueType::List),
             s => Err(format_err!("Type not available in this context: {}", s)),
         }
@@ -142,7 +145,8 @@ impl ValueType {
                 | ValueType::BigInt
                 | ValueType::Bytes
                 | ValueType::Int
-                | ValueType::String => true,
+                | ValueType::String
+                | ValueType::Timestamp => true,
             })
             .unwrap_or(false)
     }
@@ -162,6 +166,7 @@ pub enum Value {
     Null,
     Bytes(scalar::Bytes),
     BigInt(scalar::BigInt),
+    Timestamp(scalar::Timestamp),
 }
 
 impl StableHash for Value {
@@ -186,6 +191,7 @@ impl StableHash for Value {
             List(inner) => inner.stable_hash(sequence_number, state),
             Bytes(inner) => inner.stable_hash(sequence_number, state),
             BigInt(inner) => inner.stable_hash(sequence_number, state),
+            Timestamp(inner) => inner.stable_hash(sequence_number, state),
         }
     }
 }
@@ -222,9 +228,11 @@ impl Value {
                     BYTES_SCALAR => Value::Bytes(scalar::Bytes::from_str(s)?),
                     BIG_INT_SCALAR => Value::BigInt(scalar::BigInt::from_str(s)?),
                     BIG_DECIMAL_SCALAR => Value::BigDecimal(scalar::BigDecimal::from_str(s)?),
+                    TIMESTAMP_SCALAR => Value::Timestamp(scalar::Timestamp::from_str(s)?),
                     _ => Value::String(s.clone()),
                 }
             }
+
             (query::Value::Int(i), _) => Value::Int(
                 i.to_owned()
                     .as_i64()
@@ -313,6 +321,14 @@ impl Value {
         }
     }
 
+    pub fn as_timestamp(self) -> Option<scalar::Timestamp> {
+        if let Value::Timestamp(ts) = self {
+            Some(ts)
+        } else {
+            None
+        }
+    }
+
     /// Return the name of the type of this value for display to the user
     pub fn type_name(&self) -> String {
         match self {
@@ -330,6 +346,7 @@ impl Value {
             }
             Value::Null => "Null".to_owned(),
             Value::String(_) => "String".to_owned(),
+            Value::Timestamp(_) => "Timestamp".to_owned(),
         }
     }
 }
@@ -355,6 +372,7 @@ impl fmt::Display for Value {
                 ),
                 Value::Bytes(ref bytes) => bytes.to_string(),
                 Value::BigInt(ref number) => number.to_string(),
+                Value::Timestamp(ref ts) => ts.to_string(),
             }
         )
     }
@@ -373,6 +391,7 @@ impl From<Value> for query::Value {
             }
             Value::Bytes(bytes) => query::Value::String(bytes.to_string()),
             Value::BigInt(number) => query::Value::String(number.to_string()),
+            Value::Timestamp(ts) => query::Value::String(ts.to_string()),
         }
     }
 }
@@ -425,6 +444,12 @@ impl From<scalar::BigInt> for Value {
     }
 }
 
+impl From<scalar::Timestamp> for Value {
+    fn from(value: scalar::Timestamp) -> Value {
+        Value::Timestamp(value)
+    }
+}
+
 impl From<u64> for Value {
     fn from(value: u64) -> Value {
         Value::BigInt(value.into())
@@ -511,7 +536,31 @@ impl Entity {
 
     /// Convenience method to save having to `.into()` the arguments.
     pub fn set(&mut self, name: impl Into<Attribute>, value: impl Into<Value>) -> Option<Value> {
-        self.insert(name.into(), value.into())
+        self.0.insert(name.into(), value.into())
+    }
+
+    // The following methods take precedence over the ones of the
+    // underlying map so that attributes can be accessed with any kind of
+    // string and callers do not have to convert it to an `Attribute`
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.0.get_mut(name)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn insert(&mut self, name: impl Into<Attribute>, value: Value) -> Option<Value> {
+        self.0.insert(name.into(), value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.0.remove(name)
     }
 
     /// Merges an entity update `update` into this entity.
@@ -538,6 +587,23 @@ impl Entity {
             };
         }
     }
+
+    /// The names of the fields whose values differ between this entity and
+    /// `other`, including the fields that only one of them has. Fields that
+    /// are set to `Value::Null` count as missing
+    pub fn changed_fields(&self, other: &Entity) -> BTreeSet<String> {
+        let differs = |a: &Entity, b: &Entity| {
+            a.iter()
+                .filter(|(_, value)| **value != Value::Null)
+                .filter(|(key, value)| b.get(key) != Some(*value))
+                .map(|(key, _)| key.to_string())
+                .collect::<Vec<_>>()
+        };
+        differs(self, other)
+            .into_iter()
+            .chain(differs(other, self))
+            .collect()
+    }
 }
 
 impl Deref for Entity {
@@ -556,7 +622,11 @@ impl DerefMut for Entity {
 
 impl From<Entity> for BTreeMap<String, query::Value> {
     fn from(entity: Entity) -> BTreeMap<String, query::Value> {
-        entity.0.into_iter().map(|(k, v)| (k, v.into())).collect()
+        entity
+            .0
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect()
     }
 }
 
@@ -572,11 +642,20 @@ impl From<HashMap<Attribute, Value>> for Entity {
     }
 }
 
+impl From<HashMap<String, Value>> for Entity {
+    fn from(m: HashMap<String, Value>) -> Entity {
+        Entity(m.into_iter().map(|(k, v)| (Word::from(k), v)).collect())
+    }
+}
+
 impl<'a> From<Vec<(&'a str, Value)>> for Entity {
     fn from(entries: Vec<(&'a str, Value)>) -> Entity {
-        Entity::from(HashMap::from_iter(
-            entries.into_iter().map(|(k, v)| (String::from(k), v)),
-        ))
+        Entity(
+            entries
+                .into_iter()
+                .map(|(k, v)| (Word::from(k), v))
+                .collect(),
+        )
     }
 }
 
@@ -615,6 +694,30 @@ fn value_bytes() {
     assert_eq!(query::Value::from(from_query), graphql_value);
 }
 
+#[test]
+fn entity_changed_fields() {
+    let mut old = Entity::new();
+    old.set("id", "1");
+    old.set("name", "Alice");
+    old.set("age", 30);
+    old.set("email", Value::Null);
+    old.set("nickname", "Al");
+
+    let mut new = old.clone();
+    new.set("name", "Alicia");
+    new.remove("nickname");
+    new.set("email", Value::Null);
+    new.set("city", "Berlin");
+
+    let expected: BTreeSet<_> = vec!["city", "name", "nickname"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    assert_eq!(expected, old.changed_fields(&new));
+    assert_eq!(expected, new.changed_fields(&old));
+    assert!(old.changed_fields(&old).is_empty());
+}
+
 #[test]
 fn value_bigint() {
     let big_num = "340282366920938463463374607431768211456";
@@ -627,3 +730,24 @@ fn value_bigint() {
     );
     assert_eq!(query::Value::from(from_query), graphql_value);
 }
+
+#[test]
+fn value_timestamp() {
+    let ty = query::Type::NamedType(TIMESTAMP_SCALAR.to_owned());
+    let graphql_value = query::Value::String("2020-11-03T10:00:00.000000Z".to_owned());
+    let from_query = Value::from_query_value(&graphql_value, &ty).unwrap();
+    assert_eq!(
+        from_query,
+        Value::Timestamp(scalar::Timestamp::from_micros(1604397600000000).unwrap())
+    );
+    assert_eq!(query::Value::from(from_query.clone()), graphql_value);
+
+    // Microseconds since the epoch and other offsets are accepted, too
+    for input in &["1604397600000000", "2020-11-03T11:00:00+01:00"] {
+        let graphql_value = query::Value::String(input.to_string());
+        assert_eq!(
+            from_query,
+            Value::from_query_value(&graphql_value, &ty).unwrap()
+        );
+    }
+}
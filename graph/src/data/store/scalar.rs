@@ -1,3 +1,4 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
 use diesel::deserialize::FromSql;
 use diesel::serialize::ToSql;
 use diesel_derives::{AsExpression, FromSqlRow};
@@ -241,6 +242,13 @@ impl<'a> TryFrom<&'a BigInt> for u64 {
     }
 }
 
+impl<'a> TryFrom<&'a BigInt> for i64 {
+    type Error = BigIntOutOfRangeError;
+    fn try_from(value: &'a BigInt) -> Result<i64, BigIntOutOfRangeError> {
+        bigdecimal::ToPrimitive::to_i64(&value.0).ok_or(BigIntOutOfRangeError::Overflow)
+    }
+}
+
 impl TryFrom<BigInt> for u64 {
     type Error = BigIntOutOfRangeError;
     fn try_from(value: BigInt) -> Result<u64, BigIntOutOfRangeError> {
@@ -510,9 +518,128 @@ impl<'de> Deserialize<'de> for Bytes {
     }
 }
 
+#[derive(Fail, Debug)]
+pub enum TimestampError {
+    #[fail(
+        display = "`{}` is neither an ISO-8601 timestamp nor a number of microseconds",
+        _0
+    )]
+    Invalid(String),
+    #[fail(display = "Timestamp is out of range")]
+    OutOfRange,
+}
+
+/// A point in time with microsecond precision, represented as the number
+/// of microseconds since the Unix epoch. Timestamps are always in UTC and
+/// are displayed and serialized as ISO-8601 strings like
+/// `2020-11-03T10:00:00.000000Z`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_micros(micros: i64) -> Result<Self, TimestampError> {
+        let ts = Timestamp(micros);
+        ts.checked_datetime()
+            .map(|_| ts)
+            .ok_or(TimestampError::OutOfRange)
+    }
+
+    pub fn as_micros(&self) -> i64 {
+        self.0
+    }
+
+    pub fn as_datetime(&self) -> DateTime<Utc> {
+        self.checked_datetime()
+            .expect("the constructors check that timestamps are in range")
+    }
+
+    fn checked_datetime(&self) -> Option<DateTime<Utc>> {
+        let secs = self.0.div_euclid(1_000_000);
+        let nanos = self.0.rem_euclid(1_000_000) as u32 * 1_000;
+        Utc.timestamp_opt(secs, nanos).single()
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(datetime: DateTime<Utc>) -> Self {
+        Timestamp(datetime.timestamp() * 1_000_000 + datetime.timestamp_subsec_micros() as i64)
+    }
+}
+
+impl<'a> TryFrom<&'a BigInt> for Timestamp {
+    type Error = TimestampError;
+
+    /// Interpret `value` as the number of microseconds since the epoch
+    fn try_from(value: &'a BigInt) -> Result<Timestamp, TimestampError> {
+        bigdecimal::ToPrimitive::to_i64(&value.0)
+            .ok_or(TimestampError::OutOfRange)
+            .and_then(Timestamp::from_micros)
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}",
+            self.as_datetime()
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+        )
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = TimestampError;
+
+    /// Parse an ISO-8601 timestamp or a number of microseconds since the
+    /// epoch. Timestamps without an offset are in UTC, and a date on its
+    /// own means midnight UTC of that day
+    fn from_str(s: &str) -> Result<Timestamp, Self::Err> {
+        let invalid = || TimestampError::Invalid(s.to_owned());
+
+        let digits = if s.starts_with('-') { &s[1..] } else { s };
+        if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+            let micros = i64::from_str(s).map_err(|_| TimestampError::OutOfRange)?;
+            return Timestamp::from_micros(micros);
+        }
+        if let Ok(datetime) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Timestamp::from(datetime.with_timezone(&Utc)));
+        }
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+            return Ok(Timestamp::from(DateTime::<Utc>::from_utc(datetime, Utc)));
+        }
+        let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| invalid())?;
+        Ok(Timestamp::from(DateTime::<Utc>::from_utc(
+            date.and_hms(0, 0, 0),
+            Utc,
+        )))
+    }
+}
+
+impl StableHash for Timestamp {
+    fn stable_hash<H: StableHasher>(&self, sequence_number: H::Seq, state: &mut H) {
+        BigInt::from(self.0).stable_hash(sequence_number, state)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_string().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let iso_string = <String>::deserialize(deserializer)?;
+        Timestamp::from_str(&iso_string).map_err(D::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{BigDecimal, BigInt};
+    use super::{BigDecimal, BigInt, Timestamp};
     use stable_hash::crypto::SetHasher;
     use stable_hash::prelude::*;
     use stable_hash::utils::stable_hash;
@@ -612,4 +739,38 @@ mod test {
             assert_eq!(normalized.to_string(), string);
         }
     }
+
+    #[test]
+    fn timestamp_round_trip() {
+        let ts = Timestamp::from_str("2020-11-03T10:00:00.123456Z").unwrap();
+        assert_eq!(1604397600123456, ts.as_micros());
+        assert_eq!("2020-11-03T10:00:00.123456Z", ts.to_string());
+        assert_eq!(ts, Timestamp::from_str(&ts.to_string()).unwrap());
+        assert_eq!(ts, Timestamp::from_str("1604397600123456").unwrap());
+
+        // Offsets are converted to UTC, and there is no daylight saving
+        // time in UTC: the hour in which clocks in New York went back
+        // on 2020-11-01 is two distinct hours
+        let before = Timestamp::from_str("2020-11-01T01:30:00-04:00").unwrap();
+        let after = Timestamp::from_str("2020-11-01T01:30:00-05:00").unwrap();
+        assert_eq!("2020-11-01T05:30:00.000000Z", before.to_string());
+        assert_eq!("2020-11-01T06:30:00.000000Z", after.to_string());
+        assert_eq!(3_600_000_000, after.as_micros() - before.as_micros());
+
+        // Timestamps without an offset are in UTC
+        assert_eq!(Timestamp::from_str("2020-11-01T05:30:00").unwrap(), before);
+        assert_eq!(
+            "2020-11-01T00:00:00.000000Z",
+            Timestamp::from_str("2020-11-01").unwrap().to_string()
+        );
+
+        // Times before the epoch
+        let ts = Timestamp::from_str("1969-12-31T23:59:59.999999Z").unwrap();
+        assert_eq!(-1, ts.as_micros());
+        assert_eq!(ts, Timestamp::from_str("-1").unwrap());
+
+        assert!(Timestamp::from_str("yesterday").is_err());
+        assert!(Timestamp::from_str("2020-13-01").is_err());
+        assert!(Timestamp::from_micros(std::i64::MAX).is_err());
+    }
 }
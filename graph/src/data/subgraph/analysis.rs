@@ -0,0 +1,308 @@
+//! Static analysis of the schema and manifest of a subgraph when it is
+//! deployed. Nothing found here makes a subgraph invalid; findings are
+//! reported as `SubgraphManifestValidationWarning`s so that developers hear
+//! about likely trouble before the subgraph runs in production
+use graphql_parser::schema::Type;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Mapping, SubgraphManifest, SubgraphManifestValidationWarning};
+use crate::data::graphql::ext::{DirectiveFinder, DocumentExt, TypeExt};
+
+/// Find `@derivedFrom` list fields whose entities are written by handlers
+/// that probably run very often. Such fields grow without bounds, and
+/// querying them without `first` gets more expensive with every block
+pub fn unbounded_derived_fields(
+    manifest: &SubgraphManifest,
+) -> Vec<SubgraphManifestValidationWarning> {
+    // The frequently running handlers that write each entity type
+    let mut writers: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    let mappings = manifest
+        .data_sources
+        .iter()
+        .map(|ds| (&ds.name, &ds.mapping, !ds.source.is_wildcard()))
+        // Templates are always instantiated for a specific contract
+        .chain(
+            manifest
+                .templates
+                .iter()
+                .map(|t| (&t.name, &t.mapping, true)),
+        );
+    for (name, mapping, has_address) in mappings {
+        let handlers = high_frequency_handlers(name, mapping, has_address);
+        if handlers.is_empty() {
+            continue;
+        }
+        for entity_type in &mapping.entities {
+            writers
+                .entry(entity_type.as_str())
+                .or_default()
+                .extend(handlers.iter().cloned());
+        }
+    }
+
+    let schema = &manifest.schema;
+    let mut warnings = vec![];
+    for object_type in schema.document.get_object_type_definitions() {
+        for field in &object_type.fields {
+            if field.find_directive("derivedFrom".to_owned()).is_none()
+                || !is_list(&field.field_type)
+            {
+                continue;
+            }
+
+            // A field derived from an interface lists entities of all the
+            // types that implement it
+            let derived_type = field.field_type.get_base_type();
+            let mut entity_types = vec![derived_type.as_str()];
+            if let Some(types) = schema.types_for_interface.get(derived_type) {
+                entity_types.extend(types.iter().map(|t| t.name.as_str()));
+            }
+            let handlers: BTreeSet<_> = entity_types
+                .into_iter()
+                .filter_map(|entity_type| writers.get(entity_type))
+                .flatten()
+                .cloned()
+                .collect();
+
+            if !handlers.is_empty() {
+                warnings.push(SubgraphManifestValidationWarning::UnboundedDerivedField {
+                    entity_type: object_type.name.clone(),
+                    field: field.name.clone(),
+                    derived_type: derived_type.clone(),
+                    handlers: handlers.into_iter().collect(),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// The handlers of `mapping` that nothing but the event signature restricts:
+/// event handlers of data sources without a contract address, which see the
+/// events of every contract, and block handlers without a filter, which run
+/// for every block. Handlers are named `<data source>.<handler>`
+fn high_frequency_handlers(name: &str, mapping: &Mapping, has_address: bool) -> Vec<String> {
+    let event_handlers = mapping
+        .event_handlers
+        .iter()
+        .filter(|_| !has_address)
+        .map(|handler| &handler.handler);
+    let block_handlers = mapping
+        .block_handlers
+        .iter()
+        .filter(|handler| handler.filter.is_none())
+        .map(|handler| &handler.handler);
+    event_handlers
+        .chain(block_handlers)
+        .map(|handler| format!("{}.{}", name, handler))
+        .collect()
+}
+
+fn is_list(field_type: &Type) -> bool {
+    match field_type {
+        Type::NamedType(_) => false,
+        Type::ListType(_) => true,
+        Type::NonNullType(inner) => is_list(inner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::schema::Schema;
+    use crate::data::subgraph::{
+        BlockHandlerFilter, DataSource, DataSourceTemplate, Link, MappingBlockHandler,
+        MappingEventHandler, Source, SubgraphDeploymentId, TemplateSource,
+    };
+    use std::sync::Arc;
+    use web3::types::Address;
+
+    const SCHEMA: &str = "
+        type Factory @entity {
+            id: ID!
+            pairs: [Pair!]! @derivedFrom(field: \"factory\")
+        }
+
+        type Pair @entity {
+            id: ID!
+            factory: Factory!
+            swaps: [Swap!]! @derivedFrom(field: \"pair\")
+            events: [PairEvent!]! @derivedFrom(field: \"pair\")
+            latestSwap: Swap @derivedFrom(field: \"pair\")
+            snapshots: [Snapshot!]! @derivedFrom(field: \"pair\")
+        }
+
+        interface PairEvent {
+            id: ID!
+            pair: Pair!
+        }
+
+        type Swap implements PairEvent @entity {
+            id: ID!
+            pair: Pair!
+        }
+
+        type Snapshot @entity {
+            id: ID!
+            pair: Pair!
+        }
+    ";
+
+    fn mapping(
+        entities: &[&str],
+        event_handlers: &[&str],
+        block_handlers: &[&str],
+        filtered_block_handlers: &[&str],
+    ) -> Mapping {
+        let block_handlers = block_handlers
+            .iter()
+            .map(|handler| (handler, None))
+            .chain(
+                filtered_block_handlers
+                    .iter()
+                    .map(|handler| (handler, Some(BlockHandlerFilter::Call))),
+            )
+            .map(|(handler, filter)| MappingBlockHandler {
+                handler: handler.to_string(),
+                filter,
+            })
+            .collect();
+        Mapping {
+            kind: "ethereum/events".to_owned(),
+            api_version: "0.0.4".to_owned(),
+            language: "wasm/assemblyscript".to_owned(),
+            entities: entities.iter().map(|e| e.to_string()).collect(),
+            abis: vec![],
+            block_handlers,
+            call_handlers: vec![],
+            event_handlers: event_handlers
+                .iter()
+                .map(|handler| MappingEventHandler {
+                    event: "Event()".to_owned(),
+                    topic0: None,
+                    handler: handler.to_string(),
+                })
+                .collect(),
+            runtime: Arc::new(vec![]),
+            link: Link {
+                link: "/ipfs/QmMapping".to_owned(),
+            },
+        }
+    }
+
+    fn data_source(name: &str, address: Option<Address>, mapping: Mapping) -> DataSource {
+        DataSource {
+            kind: "ethereum/contract".to_owned(),
+            network: Some("mainnet".to_owned()),
+            name: name.to_owned(),
+            source: Source {
+                address,
+                addresses: vec![],
+                abi: "Abi".to_owned(),
+                start_block: 0,
+            },
+            mapping,
+            context: None,
+            templates: vec![],
+        }
+    }
+
+    fn manifest(
+        data_sources: Vec<DataSource>,
+        templates: Vec<DataSourceTemplate>,
+    ) -> SubgraphManifest {
+        let id = SubgraphDeploymentId::new("QmAnalysis").unwrap();
+        SubgraphManifest {
+            id: id.clone(),
+            location: "/ipfs/QmAnalysis".to_owned(),
+            spec_version: "0.0.2".to_owned(),
+            description: None,
+            repository: None,
+            schema: Schema::parse(SCHEMA, id).unwrap(),
+            data_sources,
+            graft: None,
+            history_blocks: None,
+            strict_bytes: false,
+            computed: None,
+            templates,
+        }
+    }
+
+    fn flagged_fields(manifest: &SubgraphManifest) -> Vec<(String, Vec<String>)> {
+        unbounded_derived_fields(manifest)
+            .into_iter()
+            .map(|warning| match warning {
+                SubgraphManifestValidationWarning::UnboundedDerivedField {
+                    entity_type,
+                    field,
+                    handlers,
+                    ..
+                } => (format!("{}.{}", entity_type, field), handlers),
+                warning => panic!("unexpected warning: {}", warning),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flags_fields_derived_from_frequently_written_entities() {
+        let manifest = manifest(
+            vec![
+                // Only sees events from one contract
+                data_source(
+                    "Factory",
+                    Some(Address::from_low_u64_be(1)),
+                    mapping(&["Factory", "Pair"], &["handleNewPair"], &[], &[]),
+                ),
+                // Sees the swaps of every contract
+                data_source(
+                    "Swaps",
+                    None,
+                    mapping(&["Swap"], &["handleSwap"], &[], &["handleCall"]),
+                ),
+            ],
+            vec![DataSourceTemplate {
+                kind: "ethereum/contract".to_owned(),
+                network: Some("mainnet".to_owned()),
+                name: "Pair".to_owned(),
+                source: TemplateSource {
+                    abi: "Pair".to_owned(),
+                },
+                mapping: mapping(&["Snapshot"], &["handleSync"], &["handleBlock"], &[]),
+            }],
+        );
+
+        assert_eq!(
+            vec![
+                ("Pair.swaps".to_owned(), vec!["Swaps.handleSwap".to_owned()]),
+                (
+                    "Pair.events".to_owned(),
+                    vec!["Swaps.handleSwap".to_owned()]
+                ),
+                (
+                    "Pair.snapshots".to_owned(),
+                    vec!["Pair.handleBlock".to_owned()]
+                ),
+            ],
+            flagged_fields(&manifest)
+        );
+    }
+
+    #[test]
+    fn ignores_fields_derived_from_rarely_written_entities() {
+        let manifest = manifest(
+            vec![data_source(
+                "Pairs",
+                Some(Address::from_low_u64_be(1)),
+                mapping(
+                    &["Factory", "Pair", "Swap", "Snapshot"],
+                    &["handleSwap"],
+                    &[],
+                    &["handleCall"],
+                ),
+            )],
+            vec![],
+        );
+
+        assert!(flagged_fields(&manifest).is_empty());
+    }
+}
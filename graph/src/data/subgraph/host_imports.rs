@@ -0,0 +1,230 @@
+//! The functions that the node provides to mappings, and the check that a
+//! mapping only imports functions that the node provides for its
+//! apiVersion. Without the check, a mapping that was built against a newer
+//! `graph-ts` fails with a link error from the WASM runtime that only names
+//! the first missing import, and names it the way the compiler mangled it.
+//! The check runs when a subgraph is deployed and whenever a mapping is
+//! instantiated
+use semver::Version;
+
+/// A function that the node provides to mappings
+#[derive(Clone, Copy, Debug)]
+pub struct HostExport {
+    /// The name under which mappings import the function
+    pub name: &'static str,
+    /// The earliest apiVersion of mappings that can import the function
+    pub since: (u64, u64, u64),
+    /// Why mappings should not use the function anymore, if they should not
+    pub deprecated: Option<&'static str>,
+}
+
+const fn export(name: &'static str) -> HostExport {
+    HostExport {
+        name,
+        since: (0, 0, 0),
+        deprecated: None,
+    }
+}
+
+const fn export_since(name: &'static str, since: (u64, u64, u64)) -> HostExport {
+    HostExport {
+        name,
+        since,
+        deprecated: None,
+    }
+}
+
+const fn deprecated_export(name: &'static str, reason: &'static str) -> HostExport {
+    HostExport {
+        name,
+        since: (0, 0, 0),
+        deprecated: Some(reason),
+    }
+}
+
+/// All functions that the node provides to mappings. The WASM runtime links
+/// exactly these functions; a test in `graph-runtime-wasm` checks that this
+/// list and the functions it links are the same
+pub const HOST_EXPORTS: &[HostExport] = &[
+    export("abort"),
+    export("ethereum.call"),
+    export("store.get"),
+    export("store.set"),
+    export("store.remove"),
+    export_since("store.removeMany", (0, 0, 8)),
+    export_since("store.increment", (0, 0, 8)),
+    deprecated_export(
+        "ipfs.cat",
+        "improved support for IPFS will be added in the future",
+    ),
+    deprecated_export(
+        "ipfs.map",
+        "improved support for IPFS will be added in the future",
+    ),
+    export("typeConversion.bytesToString"),
+    export("typeConversion.bytesToHex"),
+    export("typeConversion.bigIntToString"),
+    export("typeConversion.bigIntToHex"),
+    export("typeConversion.stringToH160"),
+    export("typeConversion.bytesToBase58"),
+    export("json.fromBytes"),
+    export("json.try_fromBytes"),
+    export("json.toI64"),
+    export("json.toU64"),
+    export("json.toF64"),
+    export("json.toBigInt"),
+    export("crypto.keccak256"),
+    export_since("bytes.concat", (0, 0, 5)),
+    export_since("id.compose", (0, 0, 5)),
+    export_since("id.composeKeccak256", (0, 0, 5)),
+    export("bigInt.plus"),
+    export("bigInt.minus"),
+    export("bigInt.times"),
+    export("bigInt.dividedBy"),
+    export("bigInt.dividedByDecimal"),
+    export("bigInt.mod"),
+    export("bigInt.pow"),
+    export("bigDecimal.toString"),
+    export("bigDecimal.fromString"),
+    export("bigDecimal.plus"),
+    export("bigDecimal.minus"),
+    export("bigDecimal.times"),
+    export("bigDecimal.dividedBy"),
+    export("bigDecimal.equals"),
+    export("dataSource.create"),
+    export("dataSource.createWithContext"),
+    export("dataSource.address"),
+    export("dataSource.network"),
+    export("dataSource.context"),
+    export("ens.nameByHash"),
+    export("log.log"),
+    export("arweave.transactionData"),
+    export("box.profile"),
+];
+
+/// Compilers mangle import names by prefixing them with the path of the
+/// file that declares them, like `~lib/@graphprotocol/graph-ts/index/log.log`;
+/// strip that path
+pub fn readable_name(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+/// What is wrong with the function imports of a mapping
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImportCheck {
+    /// One explanation for each import that the node does not provide
+    pub unresolved: Vec<String>,
+    /// One explanation for each import of a deprecated function
+    pub deprecated: Vec<String>,
+}
+
+impl ImportCheck {
+    /// The error for the unresolved imports, or `None` if there are none
+    pub fn error(&self) -> Option<String> {
+        if self.unresolved.is_empty() {
+            None
+        } else {
+            Some(self.unresolved.join("; "))
+        }
+    }
+}
+
+/// Check the names of the functions that a mapping with `api_version`
+/// imports against the functions that the node provides
+pub fn check_imports<'a>(
+    imports: impl IntoIterator<Item = &'a str>,
+    api_version: &Version,
+) -> ImportCheck {
+    let mut check = ImportCheck::default();
+    let mut imports: Vec<_> = imports.into_iter().collect();
+    imports.sort();
+    imports.dedup();
+
+    for import in imports {
+        let name = readable_name(import);
+        match HOST_EXPORTS.iter().find(|export| export.name == import) {
+            Some(export) => {
+                let (major, minor, patch) = export.since;
+                let since = Version::new(major, minor, patch);
+                if *api_version < since {
+                    check.unresolved.push(format!(
+                        "the host export `{}` requires apiVersion {} or later, but the \
+                         mapping uses apiVersion {}",
+                        name, since, api_version
+                    ));
+                }
+                if let Some(reason) = export.deprecated {
+                    check.deprecated.push(format!(
+                        "the host export `{}` is deprecated: {}",
+                        name, reason
+                    ));
+                }
+            }
+            None if HOST_EXPORTS.iter().any(|export| export.name == name) => {
+                check.unresolved.push(format!(
+                    "the mapping imports the host export `{}` as `{}`, but it must be \
+                     imported as `{}`",
+                    name, import, name
+                ))
+            }
+            None => check.unresolved.push(format!(
+                "the host export `{}` is not provided by this node; the mapping might \
+                 have been built with a newer version of graph-ts",
+                name
+            )),
+        }
+    }
+    check
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check() {
+        let v4 = Version::new(0, 0, 4);
+        let v8 = Version::new(0, 0, 8);
+
+        assert_eq!(
+            ImportCheck::default(),
+            check_imports(vec!["store.get", "abort", "store.get"], &v4)
+        );
+
+        let check = check_imports(vec!["id.compose", "math.sqrt"], &v4);
+        assert_eq!(
+            vec![
+                "the host export `id.compose` requires apiVersion 0.0.5 or later, \
+                 but the mapping uses apiVersion 0.0.4"
+                    .to_owned(),
+                "the host export `math.sqrt` is not provided by this node; the mapping \
+                 might have been built with a newer version of graph-ts"
+                    .to_owned()
+            ],
+            check.unresolved
+        );
+        assert!(check.deprecated.is_empty());
+        assert!(check_imports(vec!["id.compose"], &v8).error().is_none());
+
+        let check = check_imports(vec!["~lib/@graphprotocol/graph-ts/index/log.log"], &v8);
+        assert_eq!(
+            Some(
+                "the mapping imports the host export `log.log` as \
+                 `~lib/@graphprotocol/graph-ts/index/log.log`, but it must be imported \
+                 as `log.log`"
+            ),
+            check.error().as_deref()
+        );
+
+        let check = check_imports(vec!["ipfs.cat", "box.profile"], &v8);
+        assert!(check.unresolved.is_empty());
+        assert_eq!(
+            vec![
+                "the host export `ipfs.cat` is deprecated: improved support for IPFS will \
+                 be added in the future"
+                    .to_owned()
+            ],
+            check.deprecated
+        );
+    }
+}
@@ -16,8 +16,11 @@ use wasmparser;
 use web3::types::{Address, H256};
 
 use crate::components::link_resolver::LinkResolver;
-use crate::components::store::{Store, StoreError, SubgraphDeploymentStore};
+use crate::components::store::{
+    BlockRangeOverlap, EntityStore, StoreError, SubgraphDeploymentStore,
+};
 use crate::components::subgraph::DataSourceTemplateInfo;
+use crate::data::graphql::computed::ComputedField;
 use crate::data::graphql::{TryFromValue, ValueMap};
 use crate::data::query::QueryExecutionError;
 use crate::data::schema::{Schema, SchemaImportError, SchemaValidationError};
@@ -26,7 +29,7 @@ use crate::data::subgraph::schema::{
     EthereumBlockHandlerEntity, EthereumCallHandlerEntity, EthereumContractAbiEntity,
     EthereumContractDataSourceTemplateEntity, EthereumContractDataSourceTemplateSourceEntity,
     EthereumContractEventHandlerEntity, EthereumContractMappingEntity,
-    EthereumContractSourceEntity, SUBGRAPHS_ID,
+    EthereumContractSourceEntity, SubgraphError, SUBGRAPHS_ID,
 };
 use crate::prelude::{
     anyhow::{self, Context},
@@ -35,7 +38,8 @@ use crate::prelude::{
 use crate::util::ethereum::string_to_h256;
 use graphql_parser::query as q;
 
-use crate::components::ethereum::NodeCapabilities;
+use crate::components::ethereum::{NodeCapabilities, REORG_THRESHOLD};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
@@ -52,6 +56,12 @@ lazy_static! {
 /// Rust representation of the GraphQL schema for a `SubgraphManifest`.
 pub mod schema;
 
+/// Deploy-time analysis of subgraphs
+pub mod analysis;
+
+/// The host functions that mappings can import
+pub mod host_imports;
+
 /// Deserialize an Address (with or without '0x' prefix).
 fn deserialize_address<'de, D>(deserializer: D) -> Result<Option<Address>, D::Error>
 where
@@ -66,6 +76,48 @@ where
         .map(Some)
 }
 
+/// Parse an address (with or without '0x' prefix). Addresses in mixed case
+/// must carry a valid EIP-55 checksum; addresses that are all lowercase or
+/// all uppercase have no checksum to check.
+fn parse_checksummed_address(s: &str) -> Result<Address, String> {
+    let hex = s.trim_start_matches("0x");
+    let address = Address::from_str(hex).map_err(|e| format!("invalid address `{}`: {}", s, e))?;
+
+    let mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if mixed_case {
+        let checksummed = checksum_address(&address);
+        if hex != checksummed {
+            return Err(format!(
+                "address `{}` has an invalid checksum, expected `0x{}`",
+                s, checksummed
+            ));
+        }
+    }
+    Ok(address)
+}
+
+/// The hex digits of `address` in the mixed case of EIP-55
+fn checksum_address(address: &Address) -> String {
+    let hex = hex::encode(address.as_bytes());
+    let hash = tiny_keccak::keccak256(hex.as_bytes());
+    hex.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = if i % 2 == 0 {
+                hash[i / 2] >> 4
+            } else {
+                hash[i / 2] & 0x0f
+            };
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
 // Note: This has a StableHash impl. Do not modify fields without a backward
 // compatible change to the StableHash impl (below)
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -271,6 +323,68 @@ pub struct CreateSubgraphResult {
     pub id: String,
 }
 
+/// Result of restarting a deployment in the registrar.
+#[derive(Serialize)]
+pub struct RestartSubgraphResult {
+    /// Whether the deployment was restarted. Only the node that the
+    /// deployment is assigned to restarts it.
+    pub restarted: bool,
+    /// The node that the deployment is assigned to.
+    pub node_id: String,
+}
+
+/// Result of checking and repairing the block ranges of a deployment in
+/// the registrar.
+#[derive(Serialize)]
+pub struct RepairBlockRangesResult {
+    /// The entities that had overlapping block ranges.
+    pub overlaps: Vec<BlockRangeOverlap>,
+    /// Whether the overlaps were repaired. This is `false` for a dry run or
+    /// if there was nothing to repair.
+    pub repaired: bool,
+}
+
+/// Result of replaying a block range of a deployment and comparing the
+/// proofs of indexing and entity changes with the ones in the store.
+#[derive(Debug, Serialize)]
+pub struct PoiVerificationResult {
+    /// The number of blocks that were replayed, including the divergent
+    /// block if there is one.
+    pub blocks: u64,
+    /// The first block for which the replay and the store disagree, if
+    /// there is one.
+    pub divergence: Option<PoiDivergence>,
+}
+
+/// How the replay of a block differs from the store.
+#[derive(Debug, Serialize)]
+pub struct PoiDivergence {
+    pub block_number: u64,
+    pub block_hash: String,
+    /// The causality regions whose proofs of indexing differ.
+    pub digests: Vec<PoiDigestDivergence>,
+    /// The entities that the replay changed in the block and that differ
+    /// from the versions in the store.
+    pub entities: Vec<EntityDivergence>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoiDigestDivergence {
+    pub causality_region: String,
+    pub stored: Option<String>,
+    pub replayed: Option<String>,
+}
+
+/// An entity as the store has it and as the replay left it; `None` if the
+/// entity does not exist.
+#[derive(Debug, Serialize)]
+pub struct EntityDivergence {
+    pub entity_type: String,
+    pub entity_id: String,
+    pub stored: Option<Entity>,
+    pub replayed: Option<Entity>,
+}
+
 #[derive(Fail, Debug)]
 pub enum SubgraphRegistrarError {
     #[fail(display = "subgraph resolve error: {}", _0)]
@@ -290,6 +404,8 @@ pub enum SubgraphRegistrarError {
     DeploymentNotFound(String),
     #[fail(display = "deployment assignment unchanged: {}", _0)]
     DeploymentAssignmentUnchanged(String),
+    #[fail(display = "deployment is not recorded as unused: {}", _0)]
+    DeploymentNotUnused(String),
     #[fail(display = "subgraph registrar internal query error: {}", _0)]
     QueryExecutionError(QueryExecutionError),
     #[fail(display = "subgraph registrar error with store: {}", _0)]
@@ -374,6 +490,34 @@ pub enum SubgraphAssignmentProviderEvent {
 pub enum SubgraphManifestValidationWarning {
     #[fail(display = "schema validation produced warnings: {:?}", _0)]
     SchemaValidationWarning(SchemaImportError),
+    #[fail(
+        display = "field `{}` of `{}` lists all `{}` entities that point to it, and there \
+                   may be a great many of them since they are written by handlers that run \
+                   very often ({:?}); paginate queries of the field with `first` and `skip` \
+                   or keep an aggregate of the entities on `{}` instead",
+        field, entity_type, derived_type, handlers, entity_type
+    )]
+    UnboundedDerivedField {
+        entity_type: String,
+        field: String,
+        derived_type: String,
+        /// The handlers that write the entities, as `<data source>.<handler>`
+        handlers: Vec<String>,
+    },
+    #[fail(
+        display = "data source `{}` lists address {:x} more than once; it is indexed \
+                   from the earliest start block given for it",
+        data_source, address
+    )]
+    DuplicateSourceAddress {
+        data_source: String,
+        address: Address,
+    },
+    #[fail(display = "data source `{}`: {}", data_source, message)]
+    DeprecatedHostExport {
+        data_source: String,
+        message: String,
+    },
 }
 
 #[derive(Fail, Debug)]
@@ -382,6 +526,11 @@ pub enum SubgraphManifestValidationError {
     NoDataSources,
     #[fail(display = "subgraph source address is required")]
     SourceAddressRequired,
+    #[fail(
+        display = "data source `{}` can have either a source `address` or `addresses`, not both",
+        _0
+    )]
+    SourceAddressesConflict(String),
     #[fail(display = "subgraph cannot index data from different Ethereum networks")]
     MultipleEthereumNetworks,
     #[fail(display = "subgraph must have at least one Ethereum network data source")]
@@ -396,6 +545,23 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[fail(display = "the graft base is invalid: {}", _0)]
     GraftBaseInvalid(String),
+    #[fail(
+        display = "historyBlocks is {} but must be at least the reorg threshold of {} blocks",
+        _0, _1
+    )]
+    HistoryBlocksTooLow(BlockNumber, u64),
+    #[fail(display = "the schema uses @length, which requires `strictBytes: true`")]
+    LengthRequiresStrictBytes,
+    #[fail(display = "the schema has @computed fields, which require a `computed` module")]
+    ComputedFieldsRequireModule,
+    #[fail(display = "the computed module is invalid: {}", _0)]
+    InvalidComputedModule(String),
+    #[fail(
+        display = "the mapping of data source `{}` imports host functions that this node does \
+                   not provide: {}",
+        _0, _1
+    )]
+    UnresolvedHostExports(String, String),
 }
 
 #[derive(Fail, Debug)]
@@ -419,13 +585,33 @@ impl From<serde_yaml::Error> for SubgraphManifestResolveError {
 /// Data source contexts are conveniently represented as entities.
 pub type DataSourceContext = Entity;
 
-/// IPLD link.
+/// IPLD link. Manifests that are read from files, like the one that the
+/// node watches with `--dev`, can also use plain strings, which are paths
+/// relative to the manifest
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
+#[serde(from = "RawLink")]
 pub struct Link {
-    #[serde(rename = "/")]
     pub link: String,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawLink {
+    Ipld {
+        #[serde(rename = "/")]
+        link: String,
+    },
+    Path(String),
+}
+
+impl From<RawLink> for Link {
+    fn from(raw: RawLink) -> Self {
+        match raw {
+            RawLink::Ipld { link } | RawLink::Path(link) => Link { link },
+        }
+    }
+}
+
 impl From<String> for Link {
     fn from(s: String) -> Self {
         Self { link: s }
@@ -451,25 +637,272 @@ impl UnresolvedSchema {
     }
 }
 
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct UnresolvedComputedModule {
+    pub file: Link,
+}
+
+impl UnresolvedComputedModule {
+    pub async fn resolve(
+        self,
+        resolver: &impl LinkResolver,
+        logger: &Logger,
+    ) -> Result<ComputedModule, failure::Error> {
+        info!(logger, "Resolve computed module"; "link" => &self.file.link);
+
+        let runtime = resolver.cat(&logger, &self.file).await?;
+        Ok(ComputedModule {
+            runtime: Arc::new(runtime),
+            link: self.file,
+        })
+    }
+}
+
+/// The WASM module whose exports compute the `@computed` fields of the
+/// schema. The module can not import anything. The function of a field
+/// takes an `i32`, `i64` or `f64` for each field in its `from` list and
+/// returns an `i32` or `i64` for `Int` and `BigInt` fields, an `i32` for
+/// `Boolean` fields, and any of the three for `BigDecimal` fields
+#[derive(Clone, Debug)]
+pub struct ComputedModule {
+    pub runtime: Arc<Vec<u8>>,
+    pub link: Link,
+}
+
+impl ComputedModule {
+    /// Check that the module can compute `fields` and explain every
+    /// problem that it has
+    pub fn check(&self, fields: &[ComputedField]) -> Vec<String> {
+        use wasmparser::{ExternalKind, Payload, Type, TypeDef};
+
+        let mut types = Vec::new();
+        let mut functions = Vec::new();
+        let mut exports = HashMap::new();
+        let mut errors = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(self.runtime.as_ref()) {
+            let result = match payload {
+                Ok(Payload::TypeSection(section)) => section
+                    .into_iter()
+                    .map(|ty| {
+                        ty.map(|ty| match ty {
+                            TypeDef::Func(ty) => types.push(Some(ty)),
+                            _ => types.push(None),
+                        })
+                    })
+                    .collect(),
+                Ok(Payload::ImportSection(section)) => section
+                    .into_iter()
+                    .map(|import| {
+                        import.map(|import| {
+                            errors.push(format!(
+                                "the module must not import anything but imports `{}.{}`",
+                                import.module,
+                                import.field.unwrap_or_default()
+                            ))
+                        })
+                    })
+                    .collect(),
+                Ok(Payload::FunctionSection(section)) => section
+                    .into_iter()
+                    .map(|ty| ty.map(|ty| functions.push(ty)))
+                    .collect(),
+                Ok(Payload::ExportSection(section)) => section
+                    .into_iter()
+                    .map(|export| {
+                        export.map(|export| {
+                            if export.kind == ExternalKind::Function {
+                                exports.insert(export.field, export.index);
+                            }
+                        })
+                    })
+                    .collect(),
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                return vec![format!("the module is not valid WebAssembly: {}", e)];
+            }
+        }
+        // Imported functions come first in the index space of functions;
+        // since there is something wrong with the module anyway, there is
+        // no point in checking its exports
+        if !errors.is_empty() {
+            return errors;
+        }
+
+        for field in fields {
+            let ty = exports
+                .get(field.function.as_str())
+                .and_then(|index| functions.get(*index as usize))
+                .and_then(|ty| types.get(*ty as usize))
+                .and_then(|ty| ty.as_ref());
+            let ty = match ty {
+                Some(ty) => ty,
+                None => {
+                    errors.push(format!(
+                        "the module does not export the function `{}` of the computed field `{}`",
+                        field.function, field.name
+                    ));
+                    continue;
+                }
+            };
+
+            let params_are_numbers = ty
+                .params
+                .iter()
+                .all(|param| matches!(param, Type::I32 | Type::I64 | Type::F64));
+            if ty.params.len() != field.from.len() || !params_are_numbers {
+                errors.push(format!(
+                    "the function `{}` must take an i32, i64 or f64 for each of the {} fields \
+                     that the computed field `{}` is computed from",
+                    field.function,
+                    field.from.len(),
+                    field.name
+                ));
+            }
+            let returns_field_type = match (field.field_type.as_str(), &*ty.returns) {
+                ("Int", [Type::I32]) | ("Int", [Type::I64]) => true,
+                ("BigInt", [Type::I32]) | ("BigInt", [Type::I64]) => true,
+                ("BigDecimal", [Type::I32]) | ("BigDecimal", [Type::I64]) => true,
+                ("BigDecimal", [Type::F64]) => true,
+                ("Boolean", [Type::I32]) => true,
+                _ => false,
+            };
+            if !returns_field_type {
+                errors.push(format!(
+                    "the function `{}` can not return a value of type `{}` for the computed \
+                     field `{}`",
+                    field.function, field.field_type, field.name
+                ));
+            }
+        }
+        errors
+    }
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct Source {
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    /// Several contracts that share the ABI and mappings of the data
+    /// source. A source has either an `address` or `addresses`
+    #[serde(default)]
+    pub addresses: Vec<SourceAddress>,
     pub abi: String,
+    /// The first block that is scanned for the data source. For a source
+    /// with `addresses`, this is the earliest start block of any of them
+    /// once the data source has been resolved
     #[serde(rename = "startBlock", default)]
     pub start_block: u64,
 }
 
+impl Source {
+    /// The contracts of this source together with the block from which on
+    /// triggers from each of them are processed
+    pub fn contracts(&self) -> Vec<(Address, u64)> {
+        self.address
+            .iter()
+            .map(|address| (*address, self.start_block))
+            .chain(self.addresses.iter().map(|source_address| {
+                (
+                    source_address.address,
+                    source_address.start_block.unwrap_or(self.start_block),
+                )
+            }))
+            .collect()
+    }
+
+    /// Whether the source matches triggers from any contract
+    pub fn is_wildcard(&self) -> bool {
+        self.address.is_none() && self.addresses.is_empty()
+    }
+
+    /// Whether a trigger from `address` in block `block_number` is for this
+    /// source. Wildcard sources match all addresses
+    pub fn matches_address(&self, address: &Address, block_number: u64) -> bool {
+        if self.is_wildcard() {
+            return self.start_block <= block_number;
+        }
+        self.contracts()
+            .iter()
+            .any(|(contract, start_block)| contract == address && *start_block <= block_number)
+    }
+
+    /// Give every address its own start block and start the source at the
+    /// earliest of them
+    fn resolve_start_blocks(&mut self) {
+        if self.addresses.is_empty() {
+            return;
+        }
+        let default_start_block = self.start_block;
+        for source_address in &mut self.addresses {
+            source_address
+                .start_block
+                .get_or_insert(default_start_block);
+        }
+        self.start_block = self
+            .addresses
+            .iter()
+            .filter_map(|source_address| source_address.start_block)
+            .min()
+            .unwrap_or(default_start_block);
+    }
+}
+
 impl From<EthereumContractSourceEntity> for Source {
     fn from(entity: EthereumContractSourceEntity) -> Self {
         Self {
             address: entity.address,
+            addresses: vec![],
             abi: entity.abi,
             start_block: entity.start_block,
         }
     }
 }
 
+/// One of the `addresses` of a source. In the manifest, this is either just
+/// the address or an object with the `address` and its own `startBlock`
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct SourceAddress {
+    pub address: Address,
+    /// Triggers from the contract before this block are not processed. If
+    /// it is not set, the `startBlock` of the source applies
+    pub start_block: Option<u64>,
+}
+
+impl<'de> de::Deserialize<'de> for SourceAddress {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Address(String),
+            WithStartBlock {
+                address: String,
+                #[serde(rename = "startBlock")]
+                start_block: Option<u64>,
+            },
+        }
+
+        let (address, start_block) = match <Raw as de::Deserialize>::deserialize(deserializer)? {
+            Raw::Address(address) => (address, None),
+            Raw::WithStartBlock {
+                address,
+                start_block,
+            } => (address, start_block),
+        };
+        let address = parse_checksummed_address(&address).map_err(D::Error::custom)?;
+        Ok(SourceAddress {
+            address,
+            start_block,
+        })
+    }
+}
+
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
 pub struct TemplateSource {
     pub abi: String,
@@ -642,6 +1075,41 @@ impl Mapping {
         return false;
     }
 
+    /// Check the functions that the WASM module of the mapping imports
+    /// against the host exports for its apiVersion. Modules that can not be
+    /// parsed and invalid apiVersions are left for the runtime to report
+    pub fn check_imports(&self) -> host_imports::ImportCheck {
+        use wasmparser::{ImportSectionEntryType, Payload};
+
+        let api_version = match semver::Version::parse(&self.api_version) {
+            Ok(api_version) => api_version,
+            Err(_) => return Default::default(),
+        };
+
+        let mut imports = Vec::new();
+        for payload in wasmparser::Parser::new(0).parse_all(self.runtime.as_ref()) {
+            match payload {
+                Ok(Payload::ImportSection(section)) => {
+                    for import in section {
+                        match import {
+                            Ok(import) => {
+                                if let (ImportSectionEntryType::Function(_), Some(field)) =
+                                    (import.ty, import.field)
+                                {
+                                    imports.push(field)
+                                }
+                            }
+                            Err(_) => return Default::default(),
+                        }
+                    }
+                }
+                Ok(_) => (),
+                Err(_) => return Default::default(),
+            }
+        }
+        host_imports::check_imports(imports, &api_version)
+    }
+
     fn has_call_handler(&self) -> bool {
         !self.call_handlers.is_empty()
     }
@@ -753,12 +1221,14 @@ impl UnresolvedDataSource {
             kind,
             network,
             name,
-            source,
+            mut source,
             mapping,
             templates,
             context,
         } = self;
 
+        source.resolve_start_blocks();
+
         info!(logger, "Resolve data source"; "name" => &name, "source" => &source.start_block);
 
         let (mapping, templates) = try_join(
@@ -818,6 +1288,7 @@ impl TryFrom<DataSourceTemplateInfo> for DataSource {
             name: template.name,
             source: Source {
                 address: Some(address),
+                addresses: vec![],
                 abi: template.source.abi,
                 start_block: 0,
             },
@@ -914,7 +1385,7 @@ pub struct Graft {
 }
 
 impl Graft {
-    fn validate<S: Store + SubgraphDeploymentStore>(
+    fn validate<S: EntityStore + SubgraphDeploymentStore>(
         &self,
         store: Arc<S>,
     ) -> Vec<SubgraphManifestValidationError> {
@@ -944,7 +1415,7 @@ impl Graft {
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct BaseSubgraphManifest<S, D, T> {
+pub struct BaseSubgraphManifest<S, D, T, C> {
     pub id: SubgraphDeploymentId,
     pub location: String,
     pub spec_version: String,
@@ -953,23 +1424,41 @@ pub struct BaseSubgraphManifest<S, D, T> {
     pub schema: S,
     pub data_sources: Vec<D>,
     pub graft: Option<Graft>,
+    /// How many blocks of history to keep; older entity versions are
+    /// pruned once the deployment is synced. All history is kept if this
+    /// is not set
+    #[serde(default)]
+    pub history_blocks: Option<BlockNumber>,
+    /// Store strings that mappings write to `Bytes` fields as bytes, store
+    /// hex strings in `@length` fields in lowercase, and fail handlers
+    /// that write values of the wrong length
+    #[serde(default)]
+    pub strict_bytes: bool,
+    /// The WASM module that computes the `@computed` fields of the schema
+    #[serde(default)]
+    pub computed: Option<C>,
     #[serde(default)]
     pub templates: Vec<T>,
 }
 
 /// Consider two subgraphs to be equal if they come from the same IPLD link.
-impl<S, D, T> PartialEq for BaseSubgraphManifest<S, D, T> {
+impl<S, D, T, C> PartialEq for BaseSubgraphManifest<S, D, T, C> {
     fn eq(&self, other: &Self) -> bool {
         self.location == other.location
     }
 }
 
 /// SubgraphManifest with IPFS links unresolved
-type UnresolvedSubgraphManifest =
-    BaseSubgraphManifest<UnresolvedSchema, UnresolvedDataSource, UnresolvedDataSourceTemplate>;
+type UnresolvedSubgraphManifest = BaseSubgraphManifest<
+    UnresolvedSchema,
+    UnresolvedDataSource,
+    UnresolvedDataSourceTemplate,
+    UnresolvedComputedModule,
+>;
 
 /// SubgraphManifest validated with IPFS links resolved
-pub type SubgraphManifest = BaseSubgraphManifest<Schema, DataSource, DataSourceTemplate>;
+pub type SubgraphManifest =
+    BaseSubgraphManifest<Schema, DataSource, DataSourceTemplate, ComputedModule>;
 
 /// Unvalidated SubgraphManifest
 pub struct UnvalidatedSubgraphManifest(SubgraphManifest);
@@ -988,18 +1477,19 @@ impl UnvalidatedSubgraphManifest {
         ))
     }
 
-    pub fn validate<S: Store + SubgraphDeploymentStore>(
-        self,
+    pub fn validate<S: EntityStore + SubgraphDeploymentStore>(
+        mut self,
         store: Arc<S>,
     ) -> Result<
         (SubgraphManifest, Vec<SubgraphManifestValidationWarning>),
         Vec<SubgraphManifestValidationError>,
     > {
         let (schemas, import_errors) = self.0.schema.resolve_schema_references(store.clone());
-        let validation_warnings = import_errors
+        let mut validation_warnings: Vec<_> = import_errors
             .into_iter()
             .map(SubgraphManifestValidationWarning::SchemaValidationWarning)
             .collect();
+        validation_warnings.extend(analysis::unbounded_derived_fields(&self.0));
 
         let mut errors: Vec<SubgraphManifestValidationError> = vec![];
 
@@ -1008,10 +1498,44 @@ impl UnvalidatedSubgraphManifest {
             errors.push(SubgraphManifestValidationError::NoDataSources);
         }
 
+        // Validate that data sources use either `address` or `addresses`, and
+        // index every one of their `addresses` only once
+        for data_source in &mut self.0.data_sources {
+            let source = &mut data_source.source;
+            if source.address.is_some() && !source.addresses.is_empty() {
+                errors.push(SubgraphManifestValidationError::SourceAddressesConflict(
+                    data_source.name.clone(),
+                ));
+            }
+
+            let mut unique: Vec<SourceAddress> = Vec::with_capacity(source.addresses.len());
+            for source_address in source.addresses.drain(..) {
+                match unique
+                    .iter_mut()
+                    .find(|other| other.address == source_address.address)
+                {
+                    Some(other) => {
+                        validation_warnings.push(
+                            SubgraphManifestValidationWarning::DuplicateSourceAddress {
+                                data_source: data_source.name.clone(),
+                                address: source_address.address,
+                            },
+                        );
+                        other.start_block = match (other.start_block, source_address.start_block) {
+                            (Some(a), Some(b)) => Some(a.min(b)),
+                            (a, b) => a.or(b),
+                        };
+                    }
+                    None => unique.push(source_address),
+                }
+            }
+            source.addresses = unique;
+        }
+
         // Validate that the manifest has a `source` address in each data source
         // which has call or block handlers
         if self.0.data_sources.iter().any(|data_source| {
-            let no_source_address = data_source.source.address.is_none();
+            let no_source_address = data_source.source.is_wildcard();
             let has_call_handlers = !data_source.mapping.call_handlers.is_empty();
             let has_block_handlers = !data_source.mapping.block_handlers.is_empty();
 
@@ -1082,6 +1606,57 @@ impl UnvalidatedSubgraphManifest {
             errors.extend(graft.validate(store));
         }
 
+        if let Some(history_blocks) = self.0.history_blocks {
+            if history_blocks < 0 || (history_blocks as u64) < *REORG_THRESHOLD {
+                errors.push(SubgraphManifestValidationError::HistoryBlocksTooLow(
+                    history_blocks,
+                    *REORG_THRESHOLD,
+                ));
+            }
+        }
+
+        if !self.0.strict_bytes && self.0.schema.uses_lengths() {
+            errors.push(SubgraphManifestValidationError::LengthRequiresStrictBytes);
+        }
+
+        let computed_fields = self.0.schema.computed_fields();
+        match &self.0.computed {
+            Some(module) => errors.extend(
+                module
+                    .check(&computed_fields)
+                    .into_iter()
+                    .map(SubgraphManifestValidationError::InvalidComputedModule),
+            ),
+            None if !computed_fields.is_empty() => {
+                errors.push(SubgraphManifestValidationError::ComputedFieldsRequireModule)
+            }
+            None => (),
+        }
+
+        // Validate that mappings only import host functions that the node
+        // provides for their apiVersion
+        let mappings = self
+            .0
+            .data_sources
+            .iter()
+            .map(|ds| (&ds.name, &ds.mapping))
+            .chain(self.0.templates.iter().map(|t| (&t.name, &t.mapping)));
+        for (name, mapping) in mappings {
+            let check = mapping.check_imports();
+            if let Some(error) = check.error() {
+                errors.push(SubgraphManifestValidationError::UnresolvedHostExports(
+                    name.clone(),
+                    error,
+                ));
+            }
+            for message in check.deprecated {
+                validation_warnings.push(SubgraphManifestValidationWarning::DeprecatedHostExport {
+                    data_source: name.clone(),
+                    message,
+                });
+            }
+        }
+
         match errors.is_empty() {
             true => Ok((self.0, validation_warnings)),
             false => Err(errors),
@@ -1206,6 +1781,9 @@ impl UnresolvedSubgraphManifest {
             schema,
             data_sources,
             graft,
+            history_blocks,
+            strict_bytes,
+            computed,
             templates,
         } = self;
 
@@ -1226,6 +1804,11 @@ impl UnresolvedSubgraphManifest {
             }
         }
 
+        let computed = match computed {
+            Some(computed) => Some(computed.resolve(resolver, logger).await?),
+            None => None,
+        };
+
         let (schema, data_sources, templates) = try_join3(
             schema.resolve(id.clone(), resolver, logger),
             data_sources
@@ -1250,6 +1833,9 @@ impl UnresolvedSubgraphManifest {
             schema,
             data_sources,
             graft,
+            history_blocks,
+            strict_bytes,
+            computed,
             templates,
         })
     }
@@ -1273,6 +1859,28 @@ pub struct DeploymentState {
     pub max_reorg_depth: u32,
     /// The number of the last block that the subgraph has processed
     pub latest_ethereum_block_number: BlockNumber,
+    /// The error that made the subgraph fail, if it has failed
+    pub fatal_error: Option<SubgraphError>,
+    /// Whether the subgraph has encountered errors that it could
+    /// continue indexing past
+    pub has_non_fatal_errors: bool,
+    /// The earliest block that can be queried; history before it has been
+    /// pruned. This is 0 for deployments that keep all their history
+    pub earliest_block_number: BlockNumber,
+    /// The note an operator left when they flagged the deployment as
+    /// unhealthy, if they did
+    pub flag: Option<DeploymentFlag>,
+}
+
+/// A note that an operator attached to a deployment to mark it as
+/// unhealthy, for example during an incident. Flagging a deployment does
+/// not change how it is indexed; it only tells clients of this node that
+/// they should prefer another node for its data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeploymentFlag {
+    pub note: String,
+    /// When the deployment was flagged, in seconds since the Unix epoch
+    pub flagged_at: u64,
 }
 
 impl DeploymentState {
@@ -1284,6 +1892,16 @@ impl DeploymentState {
             reorg_count: 0,
             max_reorg_depth: 0,
             latest_ethereum_block_number: BLOCK_NUMBER_MAX,
+            fatal_error: None,
+            has_non_fatal_errors: false,
+            earliest_block_number: 0,
+            flag: None,
         }
     }
+
+    /// Return `true` if the subgraph has encountered any errors, fatal or
+    /// not, while indexing
+    pub fn has_indexing_errors(&self) -> bool {
+        self.fatal_error.is_some() || self.has_non_fatal_errors
+    }
 }
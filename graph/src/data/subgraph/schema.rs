@@ -34,20 +34,46 @@ use crate::components::store::{
     AttributeIndexDefinition, EntityCollection, EntityFilter, EntityKey, EntityOperation,
     EntityQuery, EntityRange, MetadataOperation,
 };
+use crate::data::graphql::computed::ComputedField;
 use crate::data::graphql::{TryFromValue, ValueMap};
 use crate::data::store::{Entity, NodeId, SubgraphEntityPair, Value, ValueType};
 use crate::data::subgraph::{SubgraphManifest, SubgraphName};
 use crate::prelude::*;
+use crate::util::ethereum::{
+    canonical_event_signature, canonical_function_signature, contract_event_with_signature,
+    contract_function_with_signature, normalize_signature,
+};
 
 lazy_static! {
     /// ID of the subgraph of subgraphs.
     pub static ref SUBGRAPHS_ID: SubgraphDeploymentId =
         SubgraphDeploymentId::new("subgraphs").unwrap();
+
+    /// The most bytes of an error message that we store. Longer messages
+    /// are truncated; the hash of the full message is stored with them
+    static ref MAX_ERROR_MESSAGE_SIZE: usize = std::env::var("GRAPH_MAX_ERROR_MESSAGE_SIZE")
+        .map(|s| s
+            .parse::<usize>()
+            .expect("invalid GRAPH_MAX_ERROR_MESSAGE_SIZE"))
+        .unwrap_or(10_000);
 }
 
 pub const POI_TABLE: &str = "poi2$";
 pub const POI_OBJECT: &str = "Poi$";
 
+/// Entity types that graph-node stores next to the entities of a deployment
+/// for its own bookkeeping, like `Poi$`, have this in their name. GraphQL
+/// names can not contain it, so these types never clash with the types of
+/// a subgraph
+pub const SYSTEM_ENTITY_MARKER: char = '$';
+
+/// Whether `entity_type` is one of graph-node's own entity types. These
+/// types are never part of the API of a subgraph, and only the index node
+/// server exposes them through dedicated fields
+pub fn is_system_entity_type(entity_type: &str) -> bool {
+    entity_type.contains(SYSTEM_ENTITY_MARKER)
+}
+
 #[derive(Debug, Clone, IntoStaticStr)]
 pub enum MetadataType {
     Subgraph,
@@ -113,7 +139,7 @@ pub trait TypedEntity {
         let (subgraph_id, entity_type) = Self::subgraph_entity_pair();
         EntityKey {
             subgraph_id,
-            entity_type,
+            entity_type: entity_type.into(),
             entity_id: entity_id.to_string(),
         }
     }
@@ -167,7 +193,7 @@ impl OperationList for EntityOperationList {
         self.0.push(EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: SUBGRAPHS_ID.clone(),
-                entity_type: entity.to_string(),
+                entity_type: entity.as_str().into(),
                 entity_id: id.to_owned(),
             },
             data,
@@ -491,17 +517,36 @@ impl SubgraphDeploymentEntity {
         id: &SubgraphDeploymentId,
         error: SubgraphError,
     ) -> Vec<MetadataOperation> {
-        let error_id = hex::encode(&stable_hash::<SetHasher, _>(&error));
+        let error_id = error.id();
+
+        vec![
+            error.create_operation(error_id.clone()),
+            Self::fatal_error_operation(id, error_id),
+        ]
+    }
+
+    /// Mark the deployment as failed with the existing error `error_id`,
+    /// which happened again in `block_ptr` and has now happened `count`
+    /// times in a row
+    pub fn fail_again_operations(
+        id: &SubgraphDeploymentId,
+        error_id: String,
+        count: u32,
+        block_ptr: Option<EthereumBlockPointer>,
+    ) -> Vec<MetadataOperation> {
+        vec![
+            SubgraphError::repeat_operation(error_id.clone(), count, block_ptr),
+            Self::fatal_error_operation(id, error_id),
+        ]
+    }
 
+    fn fatal_error_operation(id: &SubgraphDeploymentId, error_id: String) -> MetadataOperation {
         let mut entity = Entity::new();
         entity.set("failed", true);
         entity.set("health", SubgraphHealth::Failed);
-        entity.set("fatalError", error_id.clone());
+        entity.set("fatalError", error_id);
 
-        vec![
-            error.create_operation(error_id),
-            update_metadata_operation(Self::TYPENAME, id.as_str(), entity),
-        ]
+        update_metadata_operation(Self::TYPENAME, id.as_str(), entity)
     }
 
     pub fn update_synced_operations(
@@ -620,6 +665,144 @@ impl<'a> From<&'a super::SubgraphManifest> for SubgraphManifestEntity {
     }
 }
 
+/// A normalized description of what a data source indexes, stored as JSON
+/// alongside the data source so that tools can verify a deployment without
+/// having to interpret its manifest. Event and call signatures are in the
+/// canonical form that their hashes are computed from, taken from the ABI
+/// where possible, and are sorted so that equivalent data sources have
+/// identical summaries.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataSourceSummary {
+    pub kind: String,
+    pub network: Option<String>,
+    pub address: Option<Address>,
+    /// The `addresses` of the source with their start blocks, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<(Address, u64)>,
+    pub start_block: u64,
+    /// The name of the ABI of the source contract. Summaries stored before
+    /// ABIs were recorded don't have it
+    #[serde(default)]
+    pub abi: Option<String>,
+    /// The ABIs that the mapping uses
+    #[serde(default)]
+    pub abis: Vec<AbiSummary>,
+    pub event_signatures: Vec<String>,
+    pub call_signatures: Vec<String>,
+}
+
+/// An ABI of a data source and the link to the file it was loaded from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AbiSummary {
+    pub name: String,
+    pub file: String,
+}
+
+impl DataSourceSummary {
+    pub fn new<'a>(
+        kind: String,
+        network: Option<String>,
+        address: Option<Address>,
+        start_block: u64,
+        event_signatures: impl IntoIterator<Item = &'a str>,
+        call_signatures: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        fn normalize<'a>(signatures: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+            let mut signatures: Vec<String> =
+                signatures.into_iter().map(normalize_signature).collect();
+            signatures.sort();
+            signatures.dedup();
+            signatures
+        }
+
+        DataSourceSummary {
+            kind,
+            network,
+            address,
+            addresses: vec![],
+            start_block,
+            abi: None,
+            abis: vec![],
+            event_signatures: normalize(event_signatures),
+            call_signatures: normalize(call_signatures),
+        }
+    }
+
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("data source summaries can be serialized")
+    }
+}
+
+impl<'a> From<&'a super::DataSource> for DataSourceSummary {
+    fn from(data_source: &'a super::DataSource) -> Self {
+        let mapping = &data_source.mapping;
+        let source = &data_source.source;
+        let mut addresses: Vec<_> = source
+            .addresses
+            .iter()
+            .map(|source_address| {
+                (
+                    source_address.address,
+                    source_address.start_block.unwrap_or(source.start_block),
+                )
+            })
+            .collect();
+        addresses.sort();
+
+        // Take the signatures from the ABI so that they are canonical no
+        // matter how the manifest spells them
+        let contract = mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == source.abi)
+            .map(|abi| &abi.contract);
+        let events: Vec<_> = mapping
+            .event_handlers
+            .iter()
+            .map(|handler| {
+                contract
+                    .and_then(|contract| contract_event_with_signature(contract, &handler.event))
+                    .map(canonical_event_signature)
+                    .unwrap_or_else(|| handler.event.clone())
+            })
+            .collect();
+        let calls: Vec<_> = mapping
+            .call_handlers
+            .iter()
+            .map(|handler| {
+                contract
+                    .and_then(|contract| {
+                        contract_function_with_signature(contract, &handler.function)
+                    })
+                    .map(canonical_function_signature)
+                    .unwrap_or_else(|| handler.function.clone())
+            })
+            .collect();
+
+        DataSourceSummary {
+            addresses,
+            abi: Some(source.abi.clone()),
+            abis: mapping
+                .abis
+                .iter()
+                .map(|abi| AbiSummary {
+                    name: abi.name.clone(),
+                    file: abi.link.link.clone(),
+                })
+                .collect(),
+            ..DataSourceSummary::new(
+                data_source.kind.clone(),
+                data_source.network.clone(),
+                source.address,
+                source.start_block,
+                events.iter().map(String::as_str),
+                calls.iter().map(String::as_str),
+            )
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EthereumContractDataSourceEntity {
     pub kind: String,
@@ -628,6 +811,7 @@ pub struct EthereumContractDataSourceEntity {
     pub source: EthereumContractSourceEntity,
     pub mapping: EthereumContractMappingEntity,
     pub templates: Vec<EthereumContractDataSourceTemplateEntity>,
+    pub summary: DataSourceSummary,
 }
 
 impl TypedEntity for EthereumContractDataSourceEntity {
@@ -664,6 +848,7 @@ impl EthereumContractDataSourceEntity {
             source: source_id,
             mapping: mapping_id,
             templates: template_ids,
+            summary: self.summary.to_json(),
         };
 
         ops.push(set_metadata_operation(Self::TYPENAME, id, entity));
@@ -673,7 +858,7 @@ impl EthereumContractDataSourceEntity {
 }
 
 impl<'a> From<&'a super::DataSource> for EthereumContractDataSourceEntity {
-    fn from(data_source: &'a super::DataSource) -> Self {
+    fn from(data_source: &'a DataSource) -> Self {
         Self {
             kind: data_source.kind.clone(),
             name: data_source.name.clone(),
@@ -685,6 +870,7 @@ impl<'a> From<&'a super::DataSource> for EthereumContractDataSourceEntity {
                 .iter()
                 .map(EthereumContractDataSourceTemplateEntity::from)
                 .collect(),
+            summary: DataSourceSummary::from(data_source),
         }
     }
 }
@@ -701,6 +887,7 @@ pub struct DynamicEthereumContractDataSourceEntity {
     mapping: EthereumContractMappingEntity,
     templates: Vec<EthereumContractDataSourceTemplateEntity>,
     context: Option<DataSourceContext>,
+    summary: DataSourceSummary,
 }
 
 impl DynamicEthereumContractDataSourceEntity {
@@ -737,6 +924,7 @@ impl WriteOperations for DynamicEthereumContractDataSourceEntity {
             mapping: _,
             templates,
             context,
+            summary,
         } = self;
 
         let template_ids: Vec<Value> = templates
@@ -763,6 +951,7 @@ impl WriteOperations for DynamicEthereumContractDataSourceEntity {
             context: context
                 .as_ref()
                 .map(|ctx| serde_json::to_string(&ctx).unwrap()),
+            summary: summary.to_json(),
         };
 
         ops.add(Self::TYPENAME, id.to_owned(), entity);
@@ -808,6 +997,7 @@ impl<'a, 'b, 'c>
                 .map(EthereumContractDataSourceTemplateEntity::from)
                 .collect(),
             context: context.clone(),
+            summary: DataSourceSummary::from(data_source),
         }
     }
 }
@@ -1378,7 +1568,7 @@ fn update_metadata_operation(
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct SubgraphError {
     pub subgraph_id: SubgraphDeploymentId,
     pub message: String,
@@ -1387,16 +1577,22 @@ pub struct SubgraphError {
 
     // `true` if we are certain the error is determinsitic. If in doubt, this is `false`.
     pub deterministic: bool,
+
+    /// How many times in a row the deployment failed with this error.
+    /// Repeated errors are stored only once
+    pub count: u32,
 }
 
 impl StableHash for SubgraphError {
     fn stable_hash<H: StableHasher>(&self, mut sequence_number: H::Seq, state: &mut H) {
+        // The count is not part of the identity of an error
         let SubgraphError {
             subgraph_id,
             message,
             block_ptr,
             handler,
             deterministic,
+            count: _,
         } = self;
         subgraph_id.stable_hash(sequence_number.next_child(), state);
         message.stable_hash(sequence_number.next_child(), state);
@@ -1412,26 +1608,73 @@ impl TypedEntity for SubgraphError {
 }
 
 impl SubgraphError {
+    /// The id under which the error is stored
+    pub fn id(&self) -> String {
+        hex::encode(&stable_hash::<SetHasher, _>(self))
+    }
+
+    /// The hash of the full error message, which is stored with the
+    /// message since we do not store messages longer than
+    /// `GRAPH_MAX_ERROR_MESSAGE_SIZE` in full
+    pub fn message_hash(&self) -> H256 {
+        H256::from(tiny_keccak::keccak256(self.message.as_bytes()))
+    }
+
     fn create_operation(self, id: String) -> MetadataOperation {
         let mut entity = Entity::from(self);
         entity.set("id", id.clone());
         set_metadata_operation(Self::TYPENAME, id, entity)
     }
+
+    fn repeat_operation(
+        id: String,
+        count: u32,
+        block_ptr: Option<EthereumBlockPointer>,
+    ) -> MetadataOperation {
+        let mut entity = Entity::new();
+        entity.set("count", count as i32);
+        entity.set("lastBlockNumber", block_ptr.map(|x| x.number));
+        entity.set("lastBlockHash", block_ptr.map(|x| x.hash));
+        update_metadata_operation(Self::TYPENAME, id, entity)
+    }
+}
+
+/// Cut `message` down to at most `max_size` bytes, marking it as truncated
+fn truncate_error_message(mut message: String, max_size: usize) -> String {
+    const MARKER: &str = "... (truncated)";
+
+    if message.len() <= max_size {
+        return message;
+    }
+    let mut end = max_size.saturating_sub(MARKER.len());
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message.truncate(end);
+    message.push_str(MARKER);
+    message
 }
 
 impl From<SubgraphError> for Entity {
     fn from(subgraph_error: SubgraphError) -> Entity {
+        let message_hash = subgraph_error.message_hash();
         let SubgraphError {
             subgraph_id,
             message,
             block_ptr,
             handler,
             deterministic,
+            count,
         } = subgraph_error;
 
         let mut entity = Entity::new();
         entity.set("subgraphId", subgraph_id.to_string());
-        entity.set("message", message);
+        entity.set(
+            "message",
+            truncate_error_message(message, *MAX_ERROR_MESSAGE_SIZE),
+        );
+        entity.set("messageHash", message_hash);
+        entity.set("count", count as i32);
         entity.set("blockNumber", block_ptr.map(|x| x.number));
         entity.set("blockHash", block_ptr.map(|x| x.hash));
         entity.set("handler", handler);
@@ -1456,6 +1699,9 @@ impl TryFromValue for SubgraphError {
             block_ptr,
             handler: value.get_optional("handler")?,
             deterministic: value.get_optional("deterministic")?.unwrap_or(false),
+            count: value
+                .get_optional::<u64>("count")?
+                .map_or(1, |count| count as u32),
         })
     }
 }
@@ -1487,10 +1733,12 @@ pub fn attribute_index_definitions(
                     .filter(|f| f.name != "id")
                     .enumerate()
                 {
-                    // Skip derived fields since they are not stored in objects
-                    // of this type. We can not put this check into the filter
-                    // above since that changes how indexes are numbered
-                    if is_derived_field(&entity_field) {
+                    // Skip derived and computed fields since they are not
+                    // stored in objects of this type. We can not put this check
+                    // into the filter above since that changes how indexes are
+                    // numbered
+                    if is_derived_field(&entity_field) || ComputedField::is_computed(&entity_field)
+                    {
                         continue;
                     }
                     indexing_ops.push(AttributeIndexDefinition {
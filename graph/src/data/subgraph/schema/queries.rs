@@ -1,6 +1,6 @@
 use super::SubgraphHealth;
 use crate::components::graphql::GraphQlRunner;
-use crate::components::store::{Store, SubgraphDeploymentStore};
+use crate::components::store::SubgraphDeploymentStore;
 use crate::data::graphql::ValueMap;
 use crate::data::query::{Query, QueryVariables};
 use crate::data::subgraph::schema::SUBGRAPHS_ID;
@@ -20,7 +20,7 @@ pub struct LazyMetadata<S, Q> {
     pub id: SubgraphDeploymentId,
 }
 
-impl<S: SubgraphDeploymentStore + Store, Q: GraphQlRunner> LazyMetadata<S, Q> {
+impl<S: SubgraphDeploymentStore, Q: GraphQlRunner> LazyMetadata<S, Q> {
     pub async fn health(&self) -> Result<SubgraphHealth, Error> {
         let value = self
             .graphql_runner
@@ -4,6 +4,9 @@ pub mod components;
 /// Common data types used throughout The Graph.
 pub mod data;
 
+/// Traits for the blockchains that subgraphs can index.
+pub mod blockchain;
+
 /// Utilities.
 pub mod util;
 
@@ -73,15 +76,18 @@ pub mod prelude {
     pub type DynTryFuture<'a, Ok = (), Err = Error> =
         Pin<Box<dyn futures03::Future<Output = Result<Ok, Err>> + Send + 'a>>;
 
+    pub use crate::blockchain::{
+        BlockPtr, BlockStream, BlockStreamBuilder, BlockStreamEvent, BlockWithTriggers, Blockchain,
+        TriggerData, TriggerFilter, TriggersAdapter,
+    };
     pub use crate::components::ethereum::{
-        BlockFinality, BlockStream, BlockStreamBuilder, BlockStreamEvent, BlockStreamMetrics,
-        ChainHeadUpdate, ChainHeadUpdateListener, ChainHeadUpdateStream, EthereumAdapter,
-        EthereumAdapterError, EthereumBlock, EthereumBlockData, EthereumBlockFilter,
-        EthereumBlockPointer, EthereumBlockTriggerType, EthereumBlockWithCalls,
-        EthereumBlockWithTriggers, EthereumCall, EthereumCallData, EthereumCallFilter,
-        EthereumContractCall, EthereumContractCallError, EthereumEventData, EthereumLogFilter,
-        EthereumNetworkIdentifier, EthereumTransactionData, EthereumTrigger, LightEthereumBlock,
-        LightEthereumBlockExt, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        BlockFinality, BlockStreamMetrics, ChainHeadUpdate, ChainHeadUpdateListener,
+        ChainHeadUpdateStream, EthereumAdapter, EthereumAdapterError, EthereumBlock,
+        EthereumBlockData, EthereumBlockFilter, EthereumBlockPointer, EthereumBlockTriggerType,
+        EthereumBlockWithCalls, EthereumCall, EthereumCallData, EthereumCallFilter,
+        EthereumCallRevert, EthereumContractCall, EthereumContractCallError, EthereumEventData,
+        EthereumLogFilter, EthereumNetworkIdentifier, EthereumTransactionData, EthereumTrigger,
+        LightEthereumBlock, LightEthereumBlockExt, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     };
     pub use crate::components::graphql::{
         GraphQlRunner, QueryLoadManager, SubscriptionResultFuture,
@@ -98,17 +104,21 @@ pub mod prelude {
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        AttributeIndexDefinition, BlockNumber, ChainStore, ChildMultiplicity, EntityCache,
-        EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityLink,
-        EntityModification, EntityOperation, EntityOrder, EntityQuery, EntityRange, EntityWindow,
-        EthereumCallCache, MetadataOperation, ParentLink, PoolWaitStats, QueryStore, Store,
+        AttributeIndexDefinition, BlockGap, BlockNumber, BlockRangeOverlap, CachedBlock,
+        CachedEthereumCall, ChainStore, ChildMultiplicity, DeclaredEntities, DeploymentStore,
+        EntityCache, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
+        EntityKey, EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery,
+        EntityRange, EntityStore, EntityValidator, EntityWindow, EthereumCallCache, GraftBase,
+        GraftLineage, HeadAnnouncement, HealthIssue, HealthIssueKind, HealthRemediation,
+        MetadataOperation, ParentLink, PoolWaitStats, Provenance, QueryStore, ScanWatermark, Store,
         StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox, SubgraphDeploymentStore,
-        TransactionAbortError, WindowAttribute, BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL,
+        SubgraphNameStore, TransactionAbortError, UndeclaredEntityWrites, WindowAttribute,
+        BLOCK_NUMBER_MAX, SUBSCRIPTION_THROTTLE_INTERVAL, UNDECLARED_ENTITY_WRITES,
     };
     pub use crate::components::subgraph::{
-        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, RuntimeHost,
-        RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance, SubgraphInstanceManager,
-        SubgraphRegistrar, SubgraphVersionSwitchingMode,
+        BlockState, DataSourceLoader, DataSourceTemplateInfo, HostMetrics, ProofOfIndexingVerifier,
+        RuntimeHost, RuntimeHostBuilder, SubgraphAssignmentProvider, SubgraphInstance,
+        SubgraphInstanceManager, SubgraphRegistrar, SubgraphVersionSwitchingMode,
     };
     pub use crate::components::{EventConsumer, EventProducer};
 
@@ -117,7 +127,7 @@ pub mod prelude {
         shape_hash::shape_hash, SerializableValue, TryFromValue, ValueMap,
     };
     pub use crate::data::query::{
-        Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
+        ErrorPolicy, Query, QueryError, QueryExecutionError, QueryResult, QueryVariables,
     };
     pub use crate::data::schema::{ApiSchema, Schema};
     pub use crate::data::store::ethereum::*;
@@ -129,11 +139,12 @@ pub mod prelude {
     pub use crate::data::subgraph::schema::{SubgraphDeploymentEntity, TypedEntity};
     pub use crate::data::subgraph::{
         BlockHandlerFilter, CreateSubgraphResult, DataSource, DataSourceContext,
-        DataSourceTemplate, DeploymentState, Link, MappingABI, MappingBlockHandler,
-        MappingCallHandler, MappingEventHandler, SubgraphAssignmentProviderError,
-        SubgraphAssignmentProviderEvent, SubgraphDeploymentId, SubgraphManifest,
-        SubgraphManifestResolveError, SubgraphManifestValidationError, SubgraphName,
-        SubgraphRegistrarError, UnvalidatedSubgraphManifest,
+        DataSourceTemplate, DeploymentFlag, DeploymentState, Link, MappingABI, MappingBlockHandler,
+        MappingCallHandler, MappingEventHandler, PoiVerificationResult, RepairBlockRangesResult,
+        RestartSubgraphResult, SubgraphAssignmentProviderError, SubgraphAssignmentProviderEvent,
+        SubgraphDeploymentId, SubgraphManifest, SubgraphManifestResolveError,
+        SubgraphManifestValidationError, SubgraphName, SubgraphRegistrarError,
+        UnvalidatedSubgraphManifest,
     };
     pub use crate::data::subscription::{
         QueryResultStream, Subscription, SubscriptionError, SubscriptionResult,
@@ -152,5 +163,6 @@ pub mod prelude {
     pub use crate::util::cache_weight::CacheWeight;
     pub use crate::util::error::CompatErr;
     pub use crate::util::futures::{retry, TimeoutError};
+    pub use crate::util::intern::Word;
     pub use crate::util::stats::MovingStats;
 }
@@ -386,4 +386,27 @@ lazy_static! {
     pub static ref LOG_SQL_TIMING: bool = log_query_timing("sql");
     pub static ref LOG_GQL_TIMING: bool = log_query_timing("gql");
     pub static ref LOG_GQL_CACHE_TIMING: bool = *LOG_GQL_TIMING && log_query_timing("cache");
+
+    /// The fraction of successful GraphQL requests for which a `Query served`
+    /// line is logged. Failed requests are always logged
+    pub static ref LOG_QUERY_SAMPLE_RATE: f64 = env::var("GRAPH_LOG_QUERY_SAMPLE_RATE")
+        .ok()
+        .map(|s| s
+            .parse::<f64>()
+            .unwrap_or_else(|_| panic!("failed to parse env var GRAPH_LOG_QUERY_SAMPLE_RATE")))
+        .unwrap_or(0.0);
+
+    /// Log the variables of GraphQL requests verbatim instead of as a hash.
+    /// Variables can contain personal data, and this should therefore only
+    /// be turned on for debugging
+    pub static ref LOG_QUERY_VARIABLES: bool = env::var("GRAPH_LOG_QUERY_VARIABLES")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+}
+
+/// Decide whether a successful GraphQL request should be logged according
+/// to `GRAPH_LOG_QUERY_SAMPLE_RATE`
+pub fn sample_query_log() -> bool {
+    let rate = *LOG_QUERY_SAMPLE_RATE;
+    rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate)
 }
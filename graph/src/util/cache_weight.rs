@@ -1,4 +1,4 @@
-use crate::prelude::{BigDecimal, BigInt, EntityKey, Value};
+use crate::prelude::{BigDecimal, BigInt, EntityKey, EntityModification, Value};
 use std::mem;
 
 /// Estimate of how much memory a value consumes.
@@ -106,7 +106,7 @@ impl CacheWeight for Value {
             Value::List(values) => values.indirect_weight(),
             Value::Bytes(bytes) => bytes.indirect_weight(),
             Value::BigInt(n) => n.indirect_weight(),
-            Value::Int(_) | Value::Bool(_) | Value::Null => 0,
+            Value::Int(_) | Value::Bool(_) | Value::Timestamp(_) | Value::Null => 0,
         }
     }
 }
@@ -138,6 +138,19 @@ impl CacheWeight for EntityKey {
     }
 }
 
+impl CacheWeight for EntityModification {
+    fn indirect_weight(&self) -> usize {
+        match self {
+            EntityModification::Insert { key, data }
+            | EntityModification::Overwrite { key, data, .. } => {
+                key.indirect_weight() + data.indirect_weight()
+            }
+            EntityModification::Remove { key } => key.indirect_weight(),
+            EntityModification::RemoveMany { keys } => keys.indirect_weight(),
+        }
+    }
+}
+
 impl CacheWeight for [u8; 32] {
     fn indirect_weight(&self) -> usize {
         0
@@ -78,6 +78,111 @@ fn event_signature(event: &Event) -> String {
     )
 }
 
+/// Returns the `Event(uint256,address)` signature of an event, which is
+/// what the first topic of its logs is the hash of.
+pub fn canonical_event_signature(event: &Event) -> String {
+    ambiguous_event_signature(event)
+}
+
+/// Returns the `function(uint256,address)` signature of a function.
+pub fn canonical_function_signature(function: &Function) -> String {
+    format!(
+        "{}({})",
+        function.name,
+        function
+            .inputs
+            .iter()
+            .map(|input| event_param_type_signature(&input.kind))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+/// Brings an event or function signature as written in a manifest into
+/// the canonical form of `canonical_event_signature` without consulting
+/// the ABI: `indexed` qualifiers, parameter names and whitespace are
+/// removed, also inside tuple types.
+pub fn normalize_signature(signature: &str) -> String {
+    let signature = signature.trim();
+    let open = match signature.find('(') {
+        Some(open) => open,
+        None => return signature.chars().filter(|c| !c.is_whitespace()).collect(),
+    };
+    let close = match signature.rfind(')') {
+        Some(close) if close > open => close,
+        _ => signature.len(),
+    };
+    format!(
+        "{}({})",
+        signature[..open].trim(),
+        normalize_params(&signature[open + 1..close])
+    )
+}
+
+/// Normalize a comma-separated list of parameters, leaving the commas
+/// inside of tuple types alone
+fn normalize_params(params: &str) -> String {
+    let mut normalized = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                normalized.push(normalize_param(&params[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    normalized.push(normalize_param(&params[start..]));
+    normalized.retain(|param| !param.is_empty());
+    normalized.join(",")
+}
+
+/// Reduce a parameter like `uint256 indexed value` or
+/// `(address owner, uint256 amount)[] indexed grants` to its type
+fn normalize_param(param: &str) -> String {
+    let mut param = param.trim();
+    if param.starts_with("indexed") && param[7..].starts_with(char::is_whitespace) {
+        param = param[7..].trim_start();
+    }
+    if !param.starts_with('(') {
+        return param
+            .split_whitespace()
+            .find(|word| *word != "indexed")
+            .unwrap_or("")
+            .to_owned();
+    }
+
+    let mut depth = 0;
+    let mut close = param.len();
+    for (i, c) in param.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = i;
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let components = normalize_params(&param[1..close]);
+    // Array brackets after the tuple are part of the type
+    let suffix = param
+        .get(close + 1..)
+        .unwrap_or("")
+        .split_whitespace()
+        .next()
+        .filter(|word| word.starts_with('['))
+        .unwrap_or("");
+    format!("({}){}", components, suffix)
+}
+
 /// Returns the contract event with the given signature, if it exists.
 pub fn contract_event_with_signature<'a>(
     contract: &'a Contract,
@@ -144,3 +249,78 @@ pub fn contract_function_with_signature<'a>(
             target_signature == actual_signature
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABI: &str = r#"[
+      {
+        "type": "event",
+        "name": "Transfer",
+        "anonymous": false,
+        "inputs": [
+          { "name": "from", "type": "address", "indexed": true },
+          { "name": "to", "type": "address", "indexed": true },
+          { "name": "value", "type": "uint256", "indexed": false }
+        ]
+      },
+      {
+        "type": "function",
+        "name": "approve",
+        "stateMutability": "nonpayable",
+        "inputs": [
+          { "name": "spender", "type": "address" },
+          { "name": "amount", "type": "uint256" }
+        ],
+        "outputs": []
+      }
+    ]"#;
+
+    #[test]
+    fn canonical_signatures() {
+        let contract = Contract::load(ABI.as_bytes()).unwrap();
+
+        let event = contract_event_with_signature(
+            &contract,
+            "Transfer(indexed address,indexed address,uint256)",
+        )
+        .unwrap();
+        assert_eq!(
+            "Transfer(address,address,uint256)",
+            canonical_event_signature(event)
+        );
+
+        let function =
+            contract_function_with_signature(&contract, "approve(address,uint256)").unwrap();
+        assert_eq!(
+            "approve(address,uint256)",
+            canonical_function_signature(function)
+        );
+    }
+
+    #[test]
+    fn normalize_signatures() {
+        assert_eq!(
+            "Transfer(address,address,uint256)",
+            normalize_signature("Transfer(indexed address, indexed address,uint256)")
+        );
+        assert_eq!(
+            "Transfer(address,address,uint256)",
+            normalize_signature(
+                " Transfer ( address indexed from,\n\taddress  indexed to, uint256 value )"
+            )
+        );
+        assert_eq!(
+            "Granted((address,uint256)[],bytes32)",
+            normalize_signature(
+                "Granted((address indexed owner, uint256 amount)[] indexed grants, bytes32 id)"
+            )
+        );
+        assert_eq!(
+            "Nested((address,(uint8,bool)),string)",
+            normalize_signature("Nested(indexed (address a, (uint8 b,bool c) d), string)")
+        );
+        assert_eq!("Empty()", normalize_signature("Empty( )"));
+    }
+}
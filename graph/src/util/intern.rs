@@ -0,0 +1,222 @@
+//! Interned strings for the names of entity types and attributes. Every
+//! entity that is loaded from the store or written by a mapping has a key
+//! for each of its attributes; interning these keys means that they are
+//! allocated once per process instead of once per entity, and that cloning
+//! them only bumps a reference count.
+//!
+//! Only the names in validated subgraph schemas are added to the interner
+//! with `Word::intern`, and words are never removed from it. Making a
+//! `Word` from any other string shares the interned text if there is one,
+//! and allocates the string on its own otherwise, so that names that come
+//! from mappings or queries can't make the interner grow without bound.
+use lazy_static::lazy_static;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use stable_hash::prelude::*;
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+
+use crate::util::cache_weight::CacheWeight;
+
+lazy_static! {
+    static ref WORDS: RwLock<HashSet<Arc<str>>> = RwLock::new(HashSet::new());
+}
+
+/// An interned string. Two words with the same text share their storage,
+/// and `Word` hashes and compares like the `str` it holds, so that maps
+/// keyed by `Word` can be accessed with a `&str`.
+#[derive(Clone, PartialOrd, Ord)]
+pub struct Word(Arc<str>);
+
+impl Word {
+    /// A word with the text `s`, which shares its storage with the
+    /// interned word for `s` if there is one
+    pub fn new(s: &str) -> Self {
+        match WORDS.read().unwrap().get(s) {
+            Some(word) => Word(word.clone()),
+            None => Word(Arc::from(s)),
+        }
+    }
+
+    /// Add `s` to the interner so that all words with that text share their
+    /// storage from now on. This must only be called for names that come
+    /// from validated subgraph schemas
+    pub fn intern(s: &str) -> Self {
+        if let Some(word) = WORDS.read().unwrap().get(s) {
+            return Word(word.clone());
+        }
+        let mut words = WORDS.write().unwrap();
+        // Another thread might have interned `s` since we looked
+        if let Some(word) = words.get(s) {
+            return Word(word.clone());
+        }
+        let word: Arc<str> = Arc::from(s);
+        words.insert(word.clone());
+        Word(word)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Word {
+    fn eq(&self, other: &Self) -> bool {
+        // Words with the same text are almost always the same word
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl Eq for Word {}
+
+impl Hash for Word {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Must hash exactly like `str` for `Borrow<str>` to work
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialEq<str> for Word {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<'a> PartialEq<&'a str> for Word {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for Word {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl PartialEq<Word> for str {
+    fn eq(&self, other: &Word) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl<'a> PartialEq<Word> for &'a str {
+    fn eq(&self, other: &Word) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl PartialEq<Word> for String {
+    fn eq(&self, other: &Word) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Deref for Word {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for Word {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Word {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Word {
+    fn from(s: &str) -> Self {
+        Word::new(s)
+    }
+}
+
+impl From<&String> for Word {
+    fn from(s: &String) -> Self {
+        Word::new(s)
+    }
+}
+
+impl From<String> for Word {
+    fn from(s: String) -> Self {
+        Word::new(&s)
+    }
+}
+
+impl From<Word> for String {
+    fn from(word: Word) -> Self {
+        word.as_str().to_owned()
+    }
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Word {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Word {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Word::from)
+    }
+}
+
+impl StableHash for Word {
+    fn stable_hash<H: StableHasher>(&self, sequence_number: H::Seq, state: &mut H) {
+        // Hash like a `String` so that proofs of indexing do not change
+        self.as_str().stable_hash(sequence_number, state)
+    }
+}
+
+impl CacheWeight for Word {
+    fn indirect_weight(&self) -> usize {
+        // The text is shared with all other uses of the word
+        0
+    }
+}
+
+#[test]
+fn words_are_shared() {
+    let a = Word::intern("interned");
+    let b = Word::from("interned".to_owned());
+    assert!(Arc::ptr_eq(&a.0, &b.0));
+    assert_eq!(a, b);
+    assert_eq!(a, "interned");
+    assert_ne!(a, Word::new("other"));
+
+    let mut map = std::collections::HashMap::new();
+    map.insert(a, 1);
+    assert_eq!(Some(&1), map.get("interned"));
+}
+
+#[test]
+fn only_interned_words_are_shared() {
+    let a = Word::new("not interned");
+    let b = Word::new("not interned");
+    assert!(!Arc::ptr_eq(&a.0, &b.0));
+    assert_eq!(a, b);
+    assert!(WORDS.read().unwrap().get("not interned").is_none());
+}
@@ -155,6 +155,18 @@ impl<K: Clone + Ord + Eq + Hash + Debug + CacheWeight, V: CacheWeight + Default>
             .is_some()
     }
 
+    /// Remove all entries for which `keep` returns `false`
+    pub fn retain(&mut self, keep: impl Fn(&K, &V) -> bool) {
+        let queue = std::mem::replace(&mut self.queue, PriorityQueue::new());
+        self.total_weight = 0;
+        for (entry, priority) in queue {
+            if keep(&entry.key, &entry.value) {
+                self.total_weight += entry.weight;
+                self.queue.push(entry, priority);
+            }
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.queue.is_empty()
     }
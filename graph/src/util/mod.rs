@@ -14,3 +14,6 @@ pub mod error;
 pub mod stats;
 
 pub mod cache_weight;
+
+/// Interned strings for attribute names.
+pub mod intern;
@@ -1,9 +1,12 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use graph::mock::MockStore;
 use graph::prelude::{
-    Entity, EntityCache, EntityKey, EntityModification, SubgraphDeploymentId, Value,
+    anyhow, BigInt, DeclaredEntities, Entity, EntityCache, EntityChange, EntityChangeOperation,
+    EntityKey, EntityModification, EntityValidator, QueryExecutionError, StoreEvent,
+    SubgraphDeploymentId, UndeclaredEntityWrites, Value,
 };
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
@@ -135,10 +138,12 @@ fn overwrite_modifications() {
             EntityModification::Overwrite {
                 key: mogwai_key,
                 data: mogwai_data,
+                changed_fields: None,
             },
             EntityModification::Overwrite {
                 key: sigurros_key,
                 data: sigurros_data,
+                changed_fields: None,
             }
         ])
     );
@@ -204,6 +209,548 @@ fn consecutive_modifications() {
                 ("name", "Mogwai".into()),
                 ("founded", 1995.into()),
             ]),
+            changed_fields: None,
         },])
     );
 }
+
+#[test]
+fn missing_entities_are_cached() {
+    let mut store = MockStore::new();
+
+    // The store is only asked once whether "mogwai" exists
+    store.expect_get_mock().times(1).returning(|_| Ok(None));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(1, cache.cached_misses());
+
+    // A later handler creates the entity
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    assert_eq!(Some(mogwai_data.clone()), cache.get(&mogwai_key).unwrap());
+    assert_eq!(1, cache.cached_misses());
+
+    // Since we know that the entity is not in the store, it is inserted
+    // without looking for it again
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        vec![EntityModification::Insert {
+            key: mogwai_key.clone(),
+            data: mogwai_data.clone(),
+        }],
+        result.modifications
+    );
+
+    // The next block sees the entity that was created
+    let mut cache = EntityCache::with_current(store.clone(), result.entity_lfu_cache);
+    assert_eq!(Some(mogwai_data), cache.get(&mogwai_key).unwrap());
+    assert_eq!(0, cache.cached_misses());
+}
+
+#[test]
+fn missing_entities_are_forgotten_after_the_block() {
+    let mut store = MockStore::new();
+
+    // The store is asked once in each block
+    store.expect_get_mock().times(2).returning(|_| Ok(None));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, _) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(1, cache.cached_misses());
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(Vec::<EntityModification>::new(), result.modifications);
+
+    // The next block looks for the entity in the store again
+    let mut cache = EntityCache::with_current(store.clone(), result.entity_lfu_cache);
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(0, cache.cached_misses());
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(1, cache.cached_misses());
+}
+
+#[test]
+fn removed_entities_stay_missing_in_the_next_block() {
+    let mut store = MockStore::new();
+
+    // The store only is asked in the first block. With batched writes, the
+    // removal is still pending when the next block is processed, and the
+    // store would return the entity again
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    let stored = mogwai_data.clone();
+    store
+        .expect_get_mock()
+        .times(1)
+        .returning(move |_| Ok(Some(stored.clone())));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    assert_eq!(Some(mogwai_data), cache.get(&mogwai_key).unwrap());
+    cache.remove(mogwai_key.clone()).unwrap();
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        vec![EntityModification::Remove {
+            key: mogwai_key.clone()
+        }],
+        result.modifications
+    );
+
+    let mut cache = EntityCache::with_current(store.clone(), result.entity_lfu_cache);
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    assert_eq!(1, cache.cached_misses());
+
+    // Setting the entity again creates it instead of overwriting the
+    // removed entity
+    let (_, new_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("founded", 1995.into())],
+    );
+    cache.set(mogwai_key.clone(), new_data.clone()).unwrap();
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        vec![EntityModification::Insert {
+            key: mogwai_key,
+            data: new_data
+        }],
+        result.modifications
+    );
+}
+
+#[test]
+fn expiring_entities_are_read_again_in_each_block() {
+    let mut store = MockStore::new();
+
+    // Bands expire, so the store is asked for "mogwai" once in each block
+    let (mogwai_key, mogwai_data) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    let stored = mogwai_data.clone();
+    store
+        .expect_get_mock()
+        .times(2)
+        .returning(move |_| Ok(Some(stored.clone())));
+
+    let store = Arc::new(store);
+    let expiring: BTreeSet<_> = vec!["Band".to_owned()].into_iter().collect();
+
+    let mut cache = EntityCache::new(store.clone());
+    cache.set_block(10, &expiring);
+    assert_eq!(Some(mogwai_data.clone()), cache.get(&mogwai_key).unwrap());
+    assert_eq!(Some(mogwai_data.clone()), cache.get(&mogwai_key).unwrap());
+    let result = cache.as_modifications(&*store).unwrap();
+
+    let mut cache = EntityCache::with_current(store.clone(), result.entity_lfu_cache);
+    cache.set_block(11, &expiring);
+    assert_eq!(Some(mogwai_data), cache.get(&mogwai_key).unwrap());
+}
+
+#[test]
+fn missing_entities_created_and_removed() {
+    let mut store = MockStore::new();
+    store.expect_get_mock().times(1).returning(|_| Ok(None));
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    );
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+    cache.set(mogwai_key.clone(), mogwai_data).unwrap();
+    cache.remove(mogwai_key.clone()).unwrap();
+    assert_eq!(None, cache.get(&mogwai_key).unwrap());
+
+    // Removing an entity that never made it into the store changes nothing
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(Vec::<EntityModification>::new(), result.modifications);
+}
+
+#[test]
+fn remove_many_batches_removals() {
+    let sigurros = || {
+        make_band(
+            "sigurros",
+            vec![("id", "sigurros".into()), ("label", "FatCat".into())],
+        )
+        .1
+    };
+
+    let mut store = MockStore::new();
+    store
+        .expect_get_mock()
+        .returning(move |_| Ok(Some(sigurros())));
+    store.expect_get_many_mock().returning(move |_, _| {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "Band".into(),
+            vec![
+                make_band("mogwai", vec![("id", "mogwai".into())]).1,
+                make_band("explosions", vec![("id", "explosions".into())]).1,
+                sigurros(),
+            ],
+        );
+        Ok(map)
+    });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    let subgraph_id = SubgraphDeploymentId::new("entity_cache").unwrap();
+
+    // An entity that is created and then removed in the same block is
+    // never written
+    let (godspeed_key, godspeed_data) = make_band("godspeed", vec![("id", "godspeed".into())]);
+    cache.set(godspeed_key, godspeed_data).unwrap();
+
+    let ids = vec!["mogwai", "explosions", "sigurros", "godspeed", "unknown"];
+    cache
+        .remove_many(
+            &subgraph_id,
+            "Band",
+            ids.into_iter().map(String::from).collect(),
+        )
+        .unwrap();
+
+    // An entity that is set again after it was removed only has the data
+    // from the later `set`
+    let (sigurros_key, sigurros_data) = make_band(
+        "sigurros",
+        vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+    );
+    cache
+        .set(sigurros_key.clone(), sigurros_data.clone())
+        .unwrap();
+
+    // The removals of existing entities become one modification that
+    // comes first
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(
+        vec![
+            EntityModification::RemoveMany {
+                keys: vec![
+                    make_band("explosions", vec![]).0,
+                    make_band("mogwai", vec![]).0
+                ],
+            },
+            EntityModification::Overwrite {
+                key: sigurros_key,
+                data: sigurros_data,
+                changed_fields: None,
+            },
+        ],
+        result.modifications
+    );
+}
+
+#[test]
+fn field_changes_in_store_events() {
+    let mut store = MockStore::new();
+    store.expect_get_many_mock().returning(|_, _| {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "Band".into(),
+            vec![
+                make_band(
+                    "mogwai",
+                    vec![
+                        ("id", "mogwai".into()),
+                        ("name", "Mogwai".into()),
+                        ("label", "Chemikal Underground".into()),
+                    ],
+                )
+                .1,
+                make_band(
+                    "sigurros",
+                    vec![("id", "sigurros".into()), ("name", "Sigur Ros".into())],
+                )
+                .1,
+            ],
+        );
+        Ok(map)
+    });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    cache.track_field_changes(true);
+
+    // Update: change the label and add the year the band was founded
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("label", "Rock Action Records".into()),
+            ("founded", 1995.into()),
+        ],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data).unwrap();
+
+    // Create
+    let (explosions_key, explosions_data) = make_band(
+        "explosions",
+        vec![
+            ("id", "explosions".into()),
+            ("name", "Explosions in the Sky".into()),
+        ],
+    );
+    cache.set(explosions_key.clone(), explosions_data).unwrap();
+
+    // Delete
+    let (sigurros_key, _) = make_band("sigurros", vec![]);
+    cache.remove(sigurros_key.clone()).unwrap();
+
+    let result = cache.as_modifications(&*store).unwrap();
+    let event: StoreEvent = result.modifications.iter().collect();
+
+    let changed_fields: BTreeSet<_> = vec!["founded".to_owned(), "label".to_owned()]
+        .into_iter()
+        .collect();
+    let expected = vec![
+        EntityChange {
+            changed_fields: Some(changed_fields),
+            ..EntityChange::from_key(mogwai_key, EntityChangeOperation::Set)
+        },
+        EntityChange::from_key(explosions_key, EntityChangeOperation::Set),
+        EntityChange::from_key(sigurros_key, EntityChangeOperation::Removed),
+    ];
+    assert_eq!(StoreEvent::new(expected), event);
+}
+
+#[test]
+fn field_changes_are_opt_in() {
+    let mut store = MockStore::new();
+    store.expect_get_many_mock().returning(|_, _| {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "Band".into(),
+            vec![
+                make_band(
+                    "mogwai",
+                    vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+                )
+                .1,
+            ],
+        );
+        Ok(map)
+    });
+
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    let (mogwai_key, mogwai_data) = make_band(
+        "mogwai",
+        vec![("id", "mogwai".into()), ("founded", 1995.into())],
+    );
+    cache.set(mogwai_key.clone(), mogwai_data).unwrap();
+
+    let result = cache.as_modifications(&*store).unwrap();
+    let event: StoreEvent = result.modifications.iter().collect();
+    assert_eq!(
+        StoreEvent::new(vec![EntityChange::from_key(
+            mogwai_key,
+            EntityChangeOperation::Set
+        )]),
+        event
+    );
+}
+
+#[test]
+fn undeclared_entity_writes() {
+    let store = Arc::new(MockStore::new());
+    let (mogwai_key, mogwai_data) = make_band("mogwai", vec![("id", "mogwai".into())]);
+    let declared = |mode| DeclaredEntities::new(mode, &["Album".to_owned()]);
+
+    // Writes are not checked when no entities are declared
+    let mut cache = EntityCache::new(store.clone());
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    cache.remove(mogwai_key.clone()).unwrap();
+    assert!(cache.clear_declared_entities().is_empty());
+
+    cache.declare_entities(declared(UndeclaredEntityWrites::Ignore));
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    cache.remove(mogwai_key.clone()).unwrap();
+    assert!(cache.clear_declared_entities().is_empty());
+
+    cache.declare_entities(declared(UndeclaredEntityWrites::Warn));
+    cache.set(mogwai_key.clone(), mogwai_data.clone()).unwrap();
+    cache.remove(mogwai_key.clone()).unwrap();
+    let mut expected = BTreeMap::new();
+    expected.insert("Band".to_owned(), 2);
+    assert_eq!(expected, cache.clear_declared_entities());
+
+    cache.declare_entities(declared(UndeclaredEntityWrites::Error));
+    match cache.set(mogwai_key.clone(), mogwai_data) {
+        Err(QueryExecutionError::UndeclaredEntityType(entity_type)) => {
+            assert_eq!("Band", entity_type)
+        }
+        _ => panic!("expected an error for an undeclared entity type"),
+    }
+    assert!(cache.remove(mogwai_key.clone()).is_err());
+
+    // Nothing is checked after the handler is done
+    cache.clear_declared_entities();
+    cache.remove(mogwai_key).unwrap();
+}
+
+fn mogwai_with_plays() -> Entity {
+    make_band(
+        "mogwai",
+        vec![
+            ("id", "mogwai".into()),
+            ("name", "Mogwai".into()),
+            ("plays", Value::Int(0)),
+            ("streams", Value::BigInt(BigInt::from(0))),
+        ],
+    )
+    .1
+}
+
+#[test]
+fn increments_match_repeated_sets() {
+    let mut store = MockStore::new();
+    store
+        .expect_get_mock()
+        .returning(|_| Ok(Some(mogwai_with_plays())));
+    let store = Arc::new(store);
+    let (mogwai_key, _) = make_band("mogwai", vec![]);
+
+    // The naive way: load the entity, change it, and store it again
+    let mut naive = EntityCache::new(store.clone());
+    for i in 1..=1000 {
+        let mut entity = naive.get(&mogwai_key).unwrap().unwrap();
+        entity.set("plays", i);
+        entity.set("streams", BigInt::from(i * 3));
+        naive.set(mogwai_key.clone(), entity).unwrap();
+    }
+
+    let mut incremented = EntityCache::new(store.clone());
+    for _ in 0..1000 {
+        incremented
+            .increment(mogwai_key.clone(), "plays", &BigInt::from(1))
+            .unwrap();
+        incremented
+            .increment(mogwai_key.clone(), "streams", &BigInt::from(3))
+            .unwrap();
+    }
+
+    let naive = naive.as_modifications(&*store).unwrap().modifications;
+    let incremented = incremented.as_modifications(&*store).unwrap().modifications;
+    assert_eq!(naive, incremented);
+    match &incremented[..] {
+        [EntityModification::Overwrite { data, .. }] => {
+            assert_eq!(Some(&Value::Int(1000)), data.get("plays"));
+            assert_eq!(
+                Some(&Value::BigInt(BigInt::from(3000))),
+                data.get("streams")
+            );
+        }
+        _ => panic!("expected a single overwrite, got {:?}", incremented),
+    }
+}
+
+#[test]
+fn invalid_increments() {
+    let mut store = MockStore::new();
+    store.expect_get_mock().returning(|key| {
+        if key.entity_id == "mogwai" {
+            Ok(Some(mogwai_with_plays()))
+        } else {
+            Ok(None)
+        }
+    });
+    let store = Arc::new(store);
+    let mut cache = EntityCache::new(store.clone());
+    let (mogwai_key, _) = make_band("mogwai", vec![]);
+    let (sigurros_key, _) = make_band("sigurros", vec![]);
+    let one = BigInt::from(1);
+
+    let assert_invalid = |res: Result<(), QueryExecutionError>| match res {
+        Err(QueryExecutionError::InvalidIncrement(..)) => (),
+        res => panic!("expected an invalid increment, got {:?}", res),
+    };
+
+    assert_invalid(cache.increment(sigurros_key, "plays", &one));
+    assert_invalid(cache.increment(mogwai_key.clone(), "name", &one));
+    assert_invalid(cache.increment(mogwai_key.clone(), "founded", &one));
+    assert_invalid(cache.increment(mogwai_key.clone(), "plays", &BigInt::from(i64::MAX)));
+    cache
+        .increment(mogwai_key.clone(), "plays", &BigInt::from(i32::MAX))
+        .unwrap();
+    assert_invalid(cache.increment(mogwai_key.clone(), "plays", &one));
+
+    cache.remove(mogwai_key.clone()).unwrap();
+    assert_invalid(cache.increment(mogwai_key, "streams", &one));
+}
+
+/// Requires a `name` and counts how often it was asked to validate
+#[derive(Default)]
+struct NameValidator(AtomicUsize);
+
+impl EntityValidator for NameValidator {
+    fn validate(&self, _key: &EntityKey, entity: &Entity) -> Result<(), anyhow::Error> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        match entity.get("name") {
+            Some(_) => Ok(()),
+            None => Err(anyhow::anyhow!("the entity has no name")),
+        }
+    }
+}
+
+#[test]
+fn deferred_validation() {
+    let mut store = MockStore::new();
+    store
+        .expect_get_many_mock()
+        .returning(|_, _| Ok(BTreeMap::new()));
+    let store = Arc::new(store);
+    let validator = Arc::new(NameValidator::default());
+
+    // The entity only becomes valid with the last `set`, and is only
+    // validated once
+    let mut cache = EntityCache::new(store.clone());
+    for data in vec![
+        vec![("id", "mogwai".into())],
+        vec![("id", "mogwai".into()), ("founded", 1995.into())],
+        vec![("id", "mogwai".into()), ("name", "Mogwai".into())],
+    ] {
+        let (mogwai_key, mogwai_data) = make_band("mogwai", data);
+        cache
+            .set_unvalidated(mogwai_key, mogwai_data, validator.clone())
+            .unwrap();
+    }
+    let result = cache.as_modifications(&*store).unwrap();
+    assert_eq!(1, result.modifications.len());
+    assert_eq!(1, validator.0.load(Ordering::SeqCst));
+
+    // An entity that is still invalid at the end of the block is an error
+    let mut cache = EntityCache::new(store.clone());
+    let (sigurros_key, sigurros_data) = make_band("sigurros", vec![("id", "sigurros".into())]);
+    cache
+        .set_unvalidated(sigurros_key.clone(), sigurros_data, validator.clone())
+        .unwrap();
+    match cache.as_modifications(&*store) {
+        Err(QueryExecutionError::InvalidEntity(msg)) => assert!(msg.contains("no name")),
+        Err(e) => panic!("expected an invalid entity, got {}", e),
+        Ok(_) => panic!("expected an invalid entity"),
+    }
+    assert_eq!(2, validator.0.load(Ordering::SeqCst));
+
+    // Entities that were removed again are not validated
+    let mut cache = EntityCache::new(store.clone());
+    let (sigurros_key, sigurros_data) = make_band("sigurros", vec![("id", "sigurros".into())]);
+    cache
+        .set_unvalidated(sigurros_key.clone(), sigurros_data, validator.clone())
+        .unwrap();
+    cache.remove(sigurros_key).unwrap();
+    cache.as_modifications(&*store).unwrap();
+    assert_eq!(2, validator.0.load(Ordering::SeqCst));
+}
@@ -1,13 +1,15 @@
 use async_trait::async_trait;
 use slog::Logger;
 use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use graph::components::link_resolver::{JsonValueStream, LinkResolver as LinkResolverTrait};
+use graph::prelude::web3::types::Address;
 use graph::prelude::{
-    Entity, Link, SubgraphDeploymentId, SubgraphManifest, SubgraphManifestValidationError,
-    UnvalidatedSubgraphManifest,
+    Entity, EthereumCallFilter, EthereumLogFilter, Link, SubgraphDeploymentId, SubgraphManifest,
+    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
 };
 
 use test_store::LOGGER;
@@ -133,7 +135,7 @@ specVersion: 0.0.1
 
     let store = test_store::STORE.clone();
 
-    test_store::STORE_RUNTIME.lock().unwrap().block_on(async {
+    test_store::TEST_STORE.lock_runtime().block_on(async {
         let unvalidated = resolve_unvalidated(YAML).await;
         let subgraph = SubgraphDeploymentId::new("Qmbase").unwrap();
 
@@ -179,6 +181,166 @@ specVersion: 0.0.1
     })
 }
 
+#[test]
+fn history_blocks_manifest() {
+    const VALID: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+historyBlocks: 1000
+specVersion: 0.0.1
+";
+    const TOO_LOW: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+historyBlocks: 10
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::TEST_STORE.lock_runtime().block_on(async {
+        let manifest = resolve_manifest(VALID).await;
+        assert_eq!(Some(1000), manifest.history_blocks);
+
+        let history_blocks_too_low = |errors: Vec<SubgraphManifestValidationError>| {
+            errors.into_iter().any(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::HistoryBlocksTooLow(_, _)
+                )
+            })
+        };
+
+        // As in `graft_invalid_manifest`, the test manifests have other
+        // problems, and we only look for the error about `historyBlocks`
+        let unvalidated = resolve_unvalidated(VALID).await;
+        let errors = unvalidated
+            .validate(store.clone())
+            .expect_err("Validation must fail");
+        assert!(!history_blocks_too_low(errors));
+
+        let unvalidated = resolve_unvalidated(TOO_LOW).await;
+        let errors = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail");
+        assert!(history_blocks_too_low(errors));
+    })
+}
+
+#[tokio::test]
+async fn strict_bytes_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let manifest = resolve_manifest(YAML).await;
+    assert!(!manifest.strict_bytes);
+
+    let manifest = resolve_manifest(&format!("{}strictBytes: true\n", YAML)).await;
+    assert!(manifest.strict_bytes);
+}
+
+#[test]
+fn computed_module_manifest() {
+    const SCHEMA: &str = "
+        type Thing @entity {
+          id: ID!
+          played: Int!
+          playedTwice: Int! @computed(from: [\"played\"])
+        }";
+    // A module that only exports `playedTwice(i64) -> i64`
+    const COMPUTED_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60, 0x01, 0x7e, 0x01,
+        0x7e, 0x03, 0x02, 0x01, 0x00, 0x07, 0x0f, 0x01, 0x0b, 0x70, 0x6c, 0x61, 0x79, 0x65, 0x64,
+        0x54, 0x77, 0x69, 0x63, 0x65, 0x00, 0x00, 0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x42,
+        0x02, 0x7e, 0x0b,
+    ];
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+    let store = test_store::STORE.clone();
+
+    test_store::TEST_STORE.lock_runtime().block_on(async {
+        let computed_errors = |yaml: &str, schema: &str| {
+            let mut resolver = TextResolver::default();
+            let link = Link::from("/ipfs/Qmmanifest".to_owned());
+            resolver.add(link.link.as_str(), yaml);
+            resolver.add("/ipfs/Qmschema", schema);
+            resolver.add(
+                "/ipfs/Qmcomputed",
+                std::str::from_utf8(COMPUTED_WASM).unwrap(),
+            );
+            let store = store.clone();
+            async move {
+                let unvalidated =
+                    UnvalidatedSubgraphManifest::resolve(link, Arc::new(resolver), &LOGGER)
+                        .await
+                        .expect("Parsing the manifest works");
+                // The manifest has no data sources, and we only look at
+                // the errors about computed fields
+                unvalidated
+                    .validate(store)
+                    .expect_err("Validation must fail")
+                    .into_iter()
+                    .filter(|e| {
+                        matches!(
+                            e,
+                            SubgraphManifestValidationError::ComputedFieldsRequireModule
+                                | SubgraphManifestValidationError::InvalidComputedModule(_)
+                        )
+                    })
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+        let with_module = format!("{}computed:\n  file:\n    /: /ipfs/Qmcomputed\n", YAML);
+
+        assert_eq!(Vec::<String>::new(), computed_errors(YAML, GQL_SCHEMA).await);
+        assert_eq!(
+            vec!["the schema has @computed fields, which require a `computed` module"],
+            computed_errors(YAML, SCHEMA).await
+        );
+        assert_eq!(
+            Vec::<String>::new(),
+            computed_errors(&with_module, SCHEMA).await
+        );
+        assert_eq!(
+            vec![
+                "the computed module is invalid: the module does not export the function \
+                 `loud` of the computed field `loudName`"
+            ],
+            computed_errors(
+                &with_module,
+                &SCHEMA.replace(
+                    "}",
+                    "name: String!, loudName: String! @computed(from: [\"name\"], function: \"loud\") }"
+                )
+            )
+            .await
+        );
+        assert_eq!(
+            vec![
+                "the computed module is invalid: the function `playedTwice` can not return a \
+                 value of type `String` for the computed field `playedTwice`"
+            ],
+            computed_errors(&with_module, &SCHEMA.replace("Int! @computed", "String! @computed")).await
+        );
+    })
+}
+
 #[tokio::test]
 async fn parse_call_handlers() {
     const YAML: &str = "
@@ -216,3 +378,189 @@ specVersion: 0.0.1
     assert_eq!("Qmmanifest", manifest.id.as_str());
     assert_eq!(true, requires_traces);
 }
+
+const ADDRESSES_YAML: &str = "
+dataSources:
+  - kind: ethereum/contract
+    name: Markets
+    network: mainnet
+    source:
+      abi: Market
+      startBlock: 200
+      addresses:
+        - '0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed'
+        - address: '0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359'
+          startBlock: 100
+        - address: '0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB'
+          startBlock: 300
+    mapping:
+      kind: ethereum/events
+      apiVersion: 0.0.4
+      language: wasm/assemblyscript
+      entities:
+        - TestEntity
+      file:
+        /: /ipfs/Qmmapping
+      abis:
+        - name: Market
+          file:
+            /: /ipfs/Qmabi
+      eventHandlers:
+        - event: Trade(uint256)
+          handler: handleTrade
+      callHandlers:
+        - function: get(uint256)
+          handler: handleGet
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.1
+";
+
+fn address(s: &str) -> Address {
+    Address::from_str(s.trim_start_matches("0x")).unwrap()
+}
+
+#[tokio::test]
+async fn parse_source_addresses() {
+    let manifest = resolve_manifest(ADDRESSES_YAML).await;
+    let source = &manifest.data_sources[0].source;
+
+    let a = address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed");
+    let b = address("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359");
+    let c = address("0xdbf03b407c01e7cd3cbea99509d93f8dddc8c6fb");
+
+    // Addresses without a start block use the one of the source, and the
+    // source starts at the earliest start block of any address
+    assert_eq!(vec![(a, 200), (b, 100), (c, 300)], source.contracts());
+    assert_eq!(100, source.start_block);
+    assert_eq!(vec![100], manifest.start_blocks());
+
+    // Triggers before the start block of their address are filtered out
+    assert!(!source.matches_address(&a, 199));
+    assert!(source.matches_address(&a, 200));
+    assert!(source.matches_address(&b, 100));
+    assert!(!source.matches_address(&c, 299));
+    assert!(source.matches_address(&c, 300));
+    assert!(!source.matches_address(&Address::zero(), 1000));
+}
+
+#[tokio::test]
+async fn filters_for_source_addresses() {
+    let manifest = resolve_manifest(ADDRESSES_YAML).await;
+
+    // One `eth_getLogs` filter covers the event on all addresses
+    let log_filters: Vec<_> = EthereumLogFilter::from_data_sources(&manifest.data_sources)
+        .eth_get_logs_filters()
+        .collect();
+    assert_eq!(1, log_filters.len());
+    let mut contracts = log_filters[0].contracts.clone();
+    contracts.sort();
+    let mut expected = vec![
+        address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"),
+        address("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"),
+        address("0xdbf03b407c01e7cd3cbea99509d93f8dddc8c6fb"),
+    ];
+    expected.sort();
+    assert_eq!(expected, contracts);
+    assert_eq!(1, log_filters[0].event_signatures.len());
+
+    // The call filter has every address with its own start block
+    let call_filter = EthereumCallFilter::from_data_sources(&manifest.data_sources);
+    let mut start_blocks = call_filter.start_blocks();
+    start_blocks.sort();
+    assert_eq!(vec![100, 200, 300], start_blocks);
+    assert_eq!(
+        Some(100),
+        call_filter
+            .contract_addresses_function_signatures
+            .get(&address("0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359"))
+            .map(|(start_block, _)| *start_block)
+    );
+}
+
+#[tokio::test]
+async fn source_address_with_invalid_checksum() {
+    // The last digit of a checksummed address in the wrong case
+    let yaml = ADDRESSES_YAML.replace(
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD",
+    );
+
+    let mut resolver = TextResolver::default();
+    let link = Link::from("/ipfs/Qmmanifest".to_owned());
+    resolver.add(link.link.as_str(), &yaml);
+
+    let err = SubgraphManifest::resolve(link, &resolver, &LOGGER)
+        .await
+        .expect_err("addresses with a wrong checksum are rejected");
+    assert!(err.to_string().contains("invalid checksum"));
+}
+
+#[test]
+fn source_address_and_addresses_conflict() {
+    let yaml = ADDRESSES_YAML.replace(
+        "      startBlock: 200\n",
+        "      startBlock: 200\n      address: '0x0000000000000000000000000000000000000001'\n",
+    );
+
+    let store = test_store::STORE.clone();
+
+    test_store::TEST_STORE.lock_runtime().block_on(async {
+        let unvalidated = resolve_unvalidated(&yaml).await;
+        let errors = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail");
+        assert!(errors.into_iter().any(|e| matches!(
+            e,
+            SubgraphManifestValidationError::SourceAddressesConflict(_)
+        )));
+    })
+}
+
+#[test]
+fn unknown_host_export() {
+    // A module that only imports `math.sqrt` from `index`, which the node
+    // does not provide
+    const UNKNOWN_IMPORT_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01,
+        0x7f, 0x02, 0x13, 0x01, 0x05, 0x69, 0x6e, 0x64, 0x65, 0x78, 0x09, 0x6d, 0x61, 0x74, 0x68,
+        0x2e, 0x73, 0x71, 0x72, 0x74, 0x00, 0x00,
+    ];
+
+    let store = test_store::STORE.clone();
+
+    test_store::TEST_STORE.lock_runtime().block_on(async {
+        let mut resolver = TextResolver::default();
+        let link = Link::from("/ipfs/Qmmanifest".to_owned());
+        resolver.add(link.link.as_str(), ADDRESSES_YAML);
+        resolver.add("/ipfs/Qmschema", GQL_SCHEMA);
+        resolver.add("/ipfs/Qmabi", ABI);
+        resolver.add(
+            "/ipfs/Qmmapping",
+            std::str::from_utf8(UNKNOWN_IMPORT_WASM).unwrap(),
+        );
+
+        let unvalidated = UnvalidatedSubgraphManifest::resolve(link, Arc::new(resolver), &LOGGER)
+            .await
+            .expect("Parsing the manifest works");
+        let msg = unvalidated
+            .validate(store)
+            .expect_err("Validation must fail")
+            .into_iter()
+            .find(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::UnresolvedHostExports(_, _)
+                )
+            })
+            .expect("There must be an UnresolvedHostExports error")
+            .to_string();
+        assert_eq!(
+            "the mapping of data source `Markets` imports host functions that this node \
+             does not provide: the host export `math.sqrt` is not provided by this node; \
+             the mapping might have been built with a newer version of graph-ts",
+            msg
+        );
+    })
+}
@@ -14,7 +14,7 @@ use std::sync::{Mutex, RwLock};
 use std::time::Instant;
 
 use graph::data::graphql::*;
-use graph::data::query::CacheStatus;
+use graph::data::query::{CacheStatus, PathSegment};
 use graph::prelude::*;
 use graph::util::lfu_cache::LfuCache;
 
@@ -365,11 +365,62 @@ where
     }
 }
 
+/// The path from the root of the response to the value that is being
+/// completed, for the errors of fields
+#[derive(Clone, Copy)]
+enum ResponsePath<'a> {
+    Root,
+    Field(&'a ResponsePath<'a>, &'a str),
+    Index(&'a ResponsePath<'a>, usize),
+}
+
+impl ResponsePath<'_> {
+    fn segments(&self) -> Vec<PathSegment> {
+        let (parent, segment) = match self {
+            ResponsePath::Root => return Vec::new(),
+            ResponsePath::Field(parent, key) => (parent, PathSegment::Field(key.to_string())),
+            ResponsePath::Index(parent, index) => (parent, PathSegment::Index(*index)),
+        };
+        let mut segments = parent.segments();
+        segments.push(segment);
+        segments
+    }
+}
+
+/// The errors for fields whose value could not be completed, each with
+/// the path to its field
+type FieldErrors = Vec<(QueryExecutionError, Vec<PathSegment>)>;
+
+/// Why a value could not be completed
+enum Failure {
+    /// The value is `null` because of a field error that has already been
+    /// recorded. If the value can not be `null`, the `null` propagates to
+    /// the parent of the value
+    Null,
+    /// An error that fails the whole query, like hitting the timeout
+    Abort(Vec<QueryExecutionError>),
+}
+
+/// Record `errors` for the field at `path` and make its value `null`
+fn field_error(
+    field_errors: &mut FieldErrors,
+    path: &ResponsePath,
+    errors: Vec<QueryExecutionError>,
+) -> Failure {
+    let path = path.segments();
+    field_errors.extend(errors.into_iter().map(|e| (e, path.clone())));
+    Failure::Null
+}
+
+/// Executes the root selection set of a query without consulting the
+/// query caches. Fields that fail only remove the part of the response
+/// that contains them, as far as their types allow, and the result has
+/// the rest of the data together with the errors
 pub fn execute_root_selection_set_uncached(
     ctx: &ExecutionContext<impl Resolver>,
     selection_set: &q::SelectionSet,
     root_type: &s::ObjectType,
-) -> Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>> {
+) -> QueryResult {
     // Split the top-level fields into introspection fields and
     // regular data fields
     let mut data_set = q::SelectionSet {
@@ -397,28 +448,58 @@ pub fn execute_root_selection_set_uncached(
         }
     }
 
+    let mut errors = FieldErrors::new();
+
     // If we are getting regular data, prefetch it from the database
     let mut values = if data_set.items.is_empty() && meta_items.is_empty() {
-        BTreeMap::default()
+        Ok(BTreeMap::default())
     } else {
-        let initial_data = ctx.resolver.prefetch(&ctx, &data_set)?;
+        let initial_data = match ctx.resolver.prefetch(&ctx, &data_set) {
+            Ok(initial_data) => initial_data,
+            Err(e) => return QueryResult::from(e),
+        };
         data_set.items.extend(meta_items);
-        execute_selection_set_to_map(&ctx, iter::once(&data_set), root_type, initial_data)?
+        execute_selection_set_to_map(
+            &ctx,
+            iter::once(&data_set),
+            root_type,
+            initial_data,
+            &ResponsePath::Root,
+            &mut errors,
+        )
     };
 
     // Resolve introspection fields, if there are any
-    if !intro_set.items.is_empty() {
+    if !intro_set.items.is_empty() && !matches!(values, Err(Failure::Abort(_))) {
         let ictx = ctx.as_introspection_context();
 
-        values.extend(execute_selection_set_to_map(
+        let intro_values = execute_selection_set_to_map(
             &ictx,
             iter::once(&intro_set),
             &*INTROSPECTION_QUERY_TYPE,
             None,
-        )?);
+            &ResponsePath::Root,
+            &mut errors,
+        );
+        values = match (values, intro_values) {
+            (Ok(mut values), Ok(intro_values)) => {
+                values.extend(intro_values);
+                Ok(values)
+            }
+            (_, Err(Failure::Abort(e))) => Err(Failure::Abort(e)),
+            _ => Err(Failure::Null),
+        };
     }
 
-    Ok(values)
+    match values {
+        Ok(values) => QueryResult::with_field_errors(Some(values), errors),
+        Err(Failure::Null) => QueryResult::with_field_errors(None, errors),
+        Err(Failure::Abort(e)) => {
+            let mut result = QueryResult::with_field_errors(None, errors);
+            result.append(QueryResult::from(e));
+            result
+        }
+    }
 }
 
 /// Executes the root selection set of a query.
@@ -487,11 +568,17 @@ pub async fn execute_root_selection_set<R: Resolver>(
         let query_text = execute_ctx.query.query_text.cheap_clone();
         let variables_text = execute_ctx.query.variables_text.cheap_clone();
         match graph::spawn_blocking_allow_panic(move || {
-            Arc::new(QueryResult::from(execute_root_selection_set_uncached(
+            let result = execute_root_selection_set_uncached(
                 &execute_ctx,
                 &execute_selection_set,
                 &execute_root_type,
-            )))
+            );
+            // Cached results are only shared between queries at the same
+            // block, and can therefore remember that block
+            Arc::new(match block_ptr {
+                Some(block_ptr) => result.with_block(block_ptr),
+                None => result,
+            })
         })
         .await
         {
@@ -575,27 +662,38 @@ fn execute_selection_set<'a>(
     selection_sets: impl Iterator<Item = &'a q::SelectionSet>,
     object_type: &s::ObjectType,
     prefetched_value: Option<q::Value>,
-) -> Result<q::Value, Vec<QueryExecutionError>> {
+    path: &ResponsePath,
+    errors: &mut FieldErrors,
+) -> Result<q::Value, Failure> {
     Ok(q::Value::Object(execute_selection_set_to_map(
         ctx,
         selection_sets,
         object_type,
         prefetched_value,
+        path,
+        errors,
     )?))
 }
 
+/// Executes the fields of a selection set. Fields that fail and can be
+/// `null` are `null` in the result, and their errors are added to
+/// `errors`. If a field that can not be `null` fails, the whole object
+/// fails, but its other fields are still executed so that all their
+/// errors get reported
 fn execute_selection_set_to_map<'a>(
     ctx: &'a ExecutionContext<impl Resolver>,
     selection_sets: impl Iterator<Item = &'a q::SelectionSet>,
     object_type: &s::ObjectType,
     prefetched_value: Option<q::Value>,
-) -> Result<BTreeMap<String, q::Value>, Vec<QueryExecutionError>> {
+    path: &ResponsePath,
+    errors: &mut FieldErrors,
+) -> Result<BTreeMap<String, q::Value>, Failure> {
     let mut prefetched_object = match prefetched_value {
         Some(q::Value::Object(object)) => Some(object),
         Some(_) => unreachable!(),
         None => None,
     };
-    let mut errors: Vec<QueryExecutionError> = Vec::new();
+    let mut failed = false;
     let mut result_map: BTreeMap<String, q::Value> = BTreeMap::new();
 
     // Group fields with the same response key, so we can execute them together
@@ -617,8 +715,7 @@ fn execute_selection_set_to_map<'a>(
     for (response_key, fields) in grouped_field_set {
         match ctx.deadline {
             Some(deadline) if deadline < Instant::now() => {
-                errors.push(QueryExecutionError::Timeout);
-                break;
+                return Err(Failure::Abort(vec![QueryExecutionError::Timeout]));
             }
             _ => (),
         }
@@ -643,20 +740,32 @@ fn execute_selection_set_to_map<'a>(
                 }
             })
             .flatten();
-        match execute_field(&ctx, object_type, field_value, &fields[0], field, fields) {
+        let field_path = ResponsePath::Field(path, response_key);
+        match execute_field(
+            &ctx,
+            object_type,
+            field_value,
+            &fields[0],
+            field,
+            fields,
+            &field_path,
+            errors,
+        ) {
             Ok(v) => {
                 result_map.insert(response_key.to_owned(), v);
             }
-            Err(mut e) => {
-                errors.append(&mut e);
+            Err(Failure::Null) if !sast::is_non_null_type(&field.field_type) => {
+                result_map.insert(response_key.to_owned(), q::Value::Null);
             }
+            Err(Failure::Null) => failed = true,
+            Err(Failure::Abort(e)) => return Err(Failure::Abort(e)),
         }
     }
 
-    if errors.is_empty() {
-        Ok(result_map)
+    if failed {
+        Err(Failure::Null)
     } else {
-        Err(errors)
+        Ok(result_map)
     }
 }
 
@@ -784,8 +893,10 @@ fn execute_field(
     field: &q::Field,
     field_definition: &s::Field,
     fields: Vec<&q::Field>,
-) -> Result<q::Value, Vec<QueryExecutionError>> {
-    coerce_argument_values(ctx, object_type, field)
+    path: &ResponsePath,
+    errors: &mut FieldErrors,
+) -> Result<q::Value, Failure> {
+    let value = coerce_argument_values(ctx, object_type, field)
         .and_then(|argument_values| {
             resolve_field_value(
                 ctx,
@@ -797,7 +908,16 @@ fn execute_field(
                 &argument_values,
             )
         })
-        .and_then(|value| complete_value(ctx, field, &field_definition.field_type, &fields, value))
+        .map_err(|e| field_error(errors, path, e))?;
+    complete_value(
+        ctx,
+        field,
+        &field_definition.field_type,
+        &fields,
+        value,
+        path,
+        errors,
+    )
 }
 
 /// Resolves the value of a field.
@@ -971,22 +1091,39 @@ fn resolve_field_value_for_list_type(
     }
 }
 
-/// Ensures that a value matches the expected return type.
+/// Ensures that a value matches the expected return type. Errors are
+/// recorded in `errors` with `path`, the path of the value in the
+/// response.
 fn complete_value(
     ctx: &ExecutionContext<impl Resolver>,
     field: &q::Field,
     field_type: &s::Type,
     fields: &Vec<&q::Field>,
     resolved_value: q::Value,
-) -> Result<q::Value, Vec<QueryExecutionError>> {
+    path: &ResponsePath,
+    errors: &mut FieldErrors,
+) -> Result<q::Value, Failure> {
     match field_type {
-        // Fail if the field type is non-null but the value is null
+        // Fail if the field type is non-null but the value is null. If
+        // completing the value failed, the failure propagates to the parent
         s::Type::NonNullType(inner_type) => {
-            return match complete_value(ctx, field, inner_type, fields, resolved_value)? {
-                q::Value::Null => Err(vec![QueryExecutionError::NonNullError(
-                    field.position,
-                    field.name.to_string(),
-                )]),
+            return match complete_value(
+                ctx,
+                field,
+                inner_type,
+                fields,
+                resolved_value,
+                path,
+                errors,
+            )? {
+                q::Value::Null => Err(field_error(
+                    errors,
+                    path,
+                    vec![QueryExecutionError::NonNullError(
+                        field.position,
+                        field.name.to_string(),
+                    )],
+                )),
 
                 v => Ok(v),
             };
@@ -1000,32 +1137,49 @@ fn complete_value(
         // Complete list values
         s::Type::ListType(inner_type) => {
             match resolved_value {
-                // Complete list values individually
+                // Complete list values individually. Elements that fail are
+                // `null` if the element type allows it; otherwise, the
+                // whole list fails
                 q::Value::List(mut values) => {
-                    let mut errors = Vec::new();
+                    let mut failed = false;
 
                     // To avoid allocating a new vector this completes the values in place.
-                    for value_place in &mut values {
+                    for (index, value_place) in values.iter_mut().enumerate() {
                         // Put in a placeholder, complete the value, put the completed value back.
                         let value = std::mem::replace(value_place, q::Value::Null);
-                        match complete_value(ctx, field, inner_type, fields, value) {
+                        let element_path = ResponsePath::Index(path, index);
+                        match complete_value(
+                            ctx,
+                            field,
+                            inner_type,
+                            fields,
+                            value,
+                            &element_path,
+                            errors,
+                        ) {
                             Ok(value) => {
                                 *value_place = value;
                             }
-                            Err(errs) => errors.extend(errs),
+                            Err(Failure::Null) if !sast::is_non_null_type(inner_type) => (),
+                            Err(Failure::Null) => failed = true,
+                            Err(Failure::Abort(e)) => return Err(Failure::Abort(e)),
                         }
                     }
-                    match errors.is_empty() {
-                        true => Ok(q::Value::List(values)),
-                        false => Err(errors),
+                    match failed {
+                        false => Ok(q::Value::List(values)),
+                        true => Err(Failure::Null),
                     }
                 }
 
                 // Return field error if the resolved value for the list is not a list
-                _ => Err(vec![QueryExecutionError::ListValueError(
-                    field.position,
-                    field.name.to_string(),
-                )]),
+                _ => Err(field_error(
+                    errors,
+                    path,
+                    vec![QueryExecutionError::ListValueError(
+                        field.position,
+                        field.name.to_string(),
+                    )],
+                )),
             }
         }
 
@@ -1036,29 +1190,37 @@ fn complete_value(
                 // Complete scalar values
                 s::TypeDefinition::Scalar(scalar_type) => {
                     resolved_value.coerce(scalar_type).map_err(|value| {
-                        vec![QueryExecutionError::ScalarCoercionError(
-                            field.position.clone(),
-                            field.name.to_owned(),
-                            value,
-                            scalar_type.name.to_owned(),
-                        )]
+                        field_error(
+                            errors,
+                            path,
+                            vec![QueryExecutionError::ScalarCoercionError(
+                                field.position.clone(),
+                                field.name.to_owned(),
+                                value,
+                                scalar_type.name.to_owned(),
+                            )],
+                        )
                     })
                 }
 
                 // Complete enum values
                 s::TypeDefinition::Enum(enum_type) => {
                     resolved_value.coerce(enum_type).map_err(|value| {
-                        vec![QueryExecutionError::EnumCoercionError(
-                            field.position.clone(),
-                            field.name.to_owned(),
-                            value,
-                            enum_type.name.to_owned(),
-                            enum_type
-                                .values
-                                .iter()
-                                .map(|value| value.name.to_owned())
-                                .collect(),
-                        )]
+                        field_error(
+                            errors,
+                            path,
+                            vec![QueryExecutionError::EnumCoercionError(
+                                field.position.clone(),
+                                field.name.to_owned(),
+                                value,
+                                enum_type.name.to_owned(),
+                                enum_type
+                                    .values
+                                    .iter()
+                                    .map(|value| value.name.to_owned())
+                                    .collect(),
+                            )],
+                        )
                     })
                 }
 
@@ -1068,29 +1230,37 @@ fn complete_value(
                     fields.iter().map(|f| &f.selection_set),
                     object_type,
                     Some(resolved_value),
+                    path,
+                    errors,
                 ),
 
                 // Resolve interface types using the resolved value and complete the value recursively
                 s::TypeDefinition::Interface(_) => {
-                    let object_type = resolve_abstract_type(ctx, named_type, &resolved_value)?;
+                    let object_type = resolve_abstract_type(ctx, named_type, &resolved_value)
+                        .map_err(|e| field_error(errors, path, e))?;
 
                     execute_selection_set(
                         ctx,
                         fields.iter().map(|f| &f.selection_set),
                         object_type,
                         Some(resolved_value),
+                        path,
+                        errors,
                     )
                 }
 
                 // Resolve union types using the resolved value and complete the value recursively
                 s::TypeDefinition::Union(_) => {
-                    let object_type = resolve_abstract_type(ctx, named_type, &resolved_value)?;
+                    let object_type = resolve_abstract_type(ctx, named_type, &resolved_value)
+                        .map_err(|e| field_error(errors, path, e))?;
 
                     execute_selection_set(
                         ctx,
                         fields.iter().map(|f| &f.selection_set),
                         object_type,
                         Some(resolved_value),
+                        path,
+                        errors,
                     )
                 }
 
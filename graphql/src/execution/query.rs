@@ -1,7 +1,6 @@
+use crossbeam::atomic::AtomicCell;
 use graphql_parser::{query as q, schema as s};
-use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -9,15 +8,16 @@ use graph::data::graphql::{
     ext::{DocumentExt, TypeExt},
     ObjectOrInterface,
 };
-use graph::data::query::{Query as GraphDataQuery, QueryVariables};
-use graph::data::schema::ApiSchema;
+use graph::data::query::{CacheStatus, ErrorPolicy, Query as GraphDataQuery, QueryVariables};
+use graph::data::schema::{ApiSchema, META_FIELD_NAME};
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
-use graph::prelude::{info, o, BlockNumber, CheapClone, Logger, QueryExecutionError};
+use graph::prelude::{info, o, warn, BlockNumber, CheapClone, Logger, QueryExecutionError};
 
 use crate::execution::{get_field, get_named_type, object_or_interface};
 use crate::introspection::introspection_schema;
 use crate::query::{ast as qast, ext::BlockConstraint, ext::FieldExt};
 use crate::schema::ast as sast;
+use crate::validation;
 
 #[derive(Copy, Clone, Debug)]
 pub enum ComplexityError {
@@ -95,6 +95,13 @@ pub struct Query {
     pub variables_text: Arc<String>,
     pub query_id: String,
     pub(crate) complexity: u64,
+    /// The error policy for the whole query
+    error_policy: ErrorPolicy,
+    /// How the last execution of this query was cached
+    pub cache_status: AtomicCell<CacheStatus>,
+    /// The validation rules that the query violates but that are not
+    /// enforced
+    pub validation_warnings: Vec<&'static str>,
 }
 
 impl Query {
@@ -108,6 +115,28 @@ impl Query {
         max_complexity: Option<u64>,
         max_depth: u8,
     ) -> Result<Arc<Self>, Vec<QueryExecutionError>> {
+        let validation = validation::validate(query.schema.document(), &query.document);
+        if !validation.errors.is_empty() {
+            return Err(validation.errors.into_iter().map(Into::into).collect());
+        }
+        if !validation.warnings.is_empty() {
+            let violations = validation
+                .warnings
+                .iter()
+                .map(|v| {
+                    format!(
+                        "{} at {}:{}: {}",
+                        v.rule, v.pos.line, v.pos.column, v.message
+                    )
+                })
+                .collect::<Vec<_>>();
+            warn!(logger, "Query violates validation rules";
+                  "subgraph_id" => query.schema.id().as_str(),
+                  "query_id" => query.query_id.as_str(),
+                  "violations" => violations.join("; "));
+        }
+        let validation_warnings: Vec<_> = validation.warnings.iter().map(|v| v.rule).collect();
+
         let mut operation = None;
         let mut fragments = HashMap::new();
         for defn in query.document.definitions.into_iter() {
@@ -140,12 +169,7 @@ impl Query {
             }
         };
 
-        let query_hash = {
-            let mut hasher = DefaultHasher::new();
-            query.query_text.hash(&mut hasher);
-            hasher.finish()
-        };
-        let query_id = format!("{:x}-{:x}", query.shape_hash, query_hash);
+        let query_id = query.query_id;
         let logger = logger.new(o!(
             "subgraph_id" => query.schema.id().clone(),
             "query_id" => query_id.clone()
@@ -165,6 +189,9 @@ impl Query {
             variables_text: query.variables_text.cheap_clone(),
             query_id,
             complexity: 0,
+            error_policy: query.error_policy,
+            cache_status: AtomicCell::new(CacheStatus::default()),
+            validation_warnings,
         };
 
         query.validate_fields()?;
@@ -223,9 +250,35 @@ impl Query {
             variables_text: self.variables_text.clone(),
             query_id: self.query_id.clone(),
             complexity: self.complexity,
+            error_policy: self.error_policy,
+            cache_status: AtomicCell::new(self.cache_status.load()),
+            validation_warnings: self.validation_warnings.clone(),
         })
     }
 
+    /// Return `true` if the query would return data from a failed
+    /// deployment that neither the query as a whole nor the toplevel field
+    /// asking for the data allows. Fields that do not return subgraph data,
+    /// i.e., `_meta` and introspection fields, are always allowed
+    pub fn denies_subgraph_errors(&self) -> Result<bool, Vec<QueryExecutionError>> {
+        if self.error_policy == ErrorPolicy::Allow {
+            return Ok(false);
+        }
+
+        for field in self.selection_set.items.iter().filter_map(|sel| match sel {
+            q::Selection::Field(f) => Some(f),
+            _ => None,
+        }) {
+            if field.name == META_FIELD_NAME || field.name.starts_with("__") {
+                continue;
+            }
+            if field.error_policy(&self.variables)? == ErrorPolicy::Deny {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
     /// Should only be called for fragments that exist in the query, and therefore have been
     /// validated to exist. Panics otherwise.
     pub fn get_fragment(&self, name: &q::Name) -> &q::FragmentDefinition {
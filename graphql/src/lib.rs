@@ -11,6 +11,9 @@ pub mod introspection;
 /// Utilities for executing GraphQL.
 mod execution;
 
+/// Validation of GraphQL documents before they are executed.
+pub mod validation;
+
 /// Utilities for executing GraphQL queries and working with query ASTs.
 pub mod query;
 
@@ -31,7 +34,11 @@ pub mod prelude {
     pub use super::execution::{ExecutionContext, Query, Resolver};
     pub use super::introspection::{introspection_schema, IntrospectionResolver};
     pub use super::query::{execute_query, ext::BlockConstraint, QueryExecutionOptions};
-    pub use super::schema::{api_schema, ast::validate_entity, APISchemaError};
+    pub use super::schema::{
+        api_schema,
+        ast::{canonical_id, canonicalize_bytes, coerce_timestamps, validate_entity},
+        APISchemaError,
+    };
     pub use super::store::{build_query, StoreResolver};
     pub use super::subscription::{execute_subscription, SubscriptionExecutionOptions};
     pub use super::values::{object_value, IntoValue, MaybeCoercible};
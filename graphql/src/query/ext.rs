@@ -6,7 +6,8 @@ use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 
 use graph::data::graphql::TryFromValue;
-use graph::data::query::QueryExecutionError;
+use graph::data::query::{ErrorPolicy, QueryExecutionError};
+use graph::data::schema::ERROR_POLICY_ARGUMENT;
 use graph::prelude::web3::types::H256;
 use graph::prelude::BlockNumber;
 
@@ -73,6 +74,13 @@ pub trait FieldExt {
         &self,
         vars: &HashMap<q::Name, q::Value>,
     ) -> Result<BlockConstraint, QueryExecutionError>;
+
+    /// The error policy set with the `subgraphError` argument, or `Deny`
+    /// if the argument was not given
+    fn error_policy(
+        &self,
+        vars: &HashMap<q::Name, q::Value>,
+    ) -> Result<ErrorPolicy, QueryExecutionError>;
 }
 
 impl FieldExt for q::Field {
@@ -128,4 +136,35 @@ impl FieldExt for q::Field {
             Ok(BlockConstraint::Latest)
         }
     }
+
+    fn error_policy(
+        &self,
+        vars: &HashMap<q::Name, q::Value>,
+    ) -> Result<ErrorPolicy, QueryExecutionError> {
+        let value = self
+            .arguments
+            .iter()
+            .find(|(name, _)| name == ERROR_POLICY_ARGUMENT)
+            .map(|(_, value)| value);
+        let value = match value {
+            Some(value) => value.lookup(vars, self.position)?,
+            None => return Ok(ErrorPolicy::Deny),
+        };
+        match value {
+            // Variables of enum type might still be strings
+            q::Value::Enum(policy) | q::Value::String(policy) => policy.parse().map_err(|_| {
+                QueryExecutionError::InvalidArgumentError(
+                    self.position.clone(),
+                    ERROR_POLICY_ARGUMENT.to_owned(),
+                    value.clone(),
+                )
+            }),
+            q::Value::Null => Ok(ErrorPolicy::Deny),
+            _ => Err(QueryExecutionError::InvalidArgumentError(
+                self.position.clone(),
+                ERROR_POLICY_ARGUMENT.to_owned(),
+                value.clone(),
+            )),
+        }
+    }
 }
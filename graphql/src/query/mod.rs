@@ -76,6 +76,7 @@ where
     .await;
     let elapsed = start.elapsed();
     let cache_status = ctx.cache_status.load();
+    query.cache_status.store(cache_status);
     options
         .load_manager
         .record_work(query.shape_hash, elapsed, cache_status);
@@ -10,12 +10,16 @@ use crate::prelude::{
 };
 use crate::query::execute_query;
 use crate::subscription::execute_prepared_subscription;
+use crate::validation::RuleMode;
+use graph::components::trace;
+use graph::data::graphql::computed::{ComputedFunctions, NoComputedFunctions};
 use graph::data::graphql::effort::LoadManager;
+use graph::data::query::CacheStatus;
 use graph::prelude::{
-    async_trait, o, CheapClone, DeploymentState, EthereumBlockPointer,
-    GraphQlRunner as GraphQlRunnerTrait, Logger, Query, QueryExecutionError, QueryResult, Store,
-    StoreError, SubgraphDeploymentId, SubgraphDeploymentStore, Subscription, SubscriptionError,
-    SubscriptionResult,
+    async_trait, info, o, CacheWeight, CheapClone, DeploymentState, DeploymentStore,
+    EthereumBlockPointer, GraphQlRunner as GraphQlRunnerTrait, Logger, Query, QueryExecutionError,
+    QueryResult, StoreError, SubgraphDeploymentId, SubgraphDeploymentStore, Subscription,
+    SubscriptionError, SubscriptionResult,
 };
 
 use lazy_static::lazy_static;
@@ -25,6 +29,8 @@ pub struct GraphQlRunner<S> {
     logger: Logger,
     store: Arc<S>,
     load_manager: Arc<LoadManager>,
+    serve_failed_subgraphs: bool,
+    computed: Arc<dyn ComputedFunctions>,
 }
 
 lazy_static! {
@@ -60,11 +66,17 @@ lazy_static! {
         .ok()
         .map(|s| s == "true")
         .unwrap_or(false);
+    // Serve data from failed subgraphs as of the block at which they
+    // failed, as if every query were sent with `subgraphError: allow`
+    static ref GRAPHQL_SERVE_FAILED_SUBGRAPHS: bool = env::var("GRAPH_GRAPHQL_SERVE_FAILED_SUBGRAPHS")
+        .ok()
+        .map(|s| s == "true")
+        .unwrap_or(false);
 }
 
 impl<S> GraphQlRunner<S>
 where
-    S: Store + SubgraphDeploymentStore,
+    S: DeploymentStore + SubgraphDeploymentStore,
 {
     /// Creates a new query runner.
     pub fn new(logger: &Logger, store: Arc<S>, load_manager: Arc<LoadManager>) -> Self {
@@ -73,9 +85,53 @@ where
             logger,
             store,
             load_manager,
+            serve_failed_subgraphs: *GRAPHQL_SERVE_FAILED_SUBGRAPHS,
+            computed: Arc::new(NoComputedFunctions),
         }
     }
 
+    /// The functions that evaluate the `@computed` fields of entities.
+    /// Queries for computed fields fail if they are not set
+    pub fn computed_functions(self, computed: Arc<dyn ComputedFunctions>) -> Self {
+        GraphQlRunner { computed, ..self }
+    }
+
+    /// Whether to serve data from failed subgraphs as of the block at
+    /// which they failed, as if every query were sent with
+    /// `subgraphError: allow`. Defaults to the value of
+    /// `GRAPH_GRAPHQL_SERVE_FAILED_SUBGRAPHS`
+    pub fn serve_failed_subgraphs(self, serve_failed_subgraphs: bool) -> Self {
+        GraphQlRunner {
+            serve_failed_subgraphs,
+            ..self
+        }
+    }
+
+    /// Count the validation rule violations of a query that was prepared
+    /// for execution, whether they caused it to be rejected or not
+    fn record_validation(
+        &self,
+        query: Result<Arc<crate::execution::Query>, Vec<QueryExecutionError>>,
+    ) -> Result<Arc<crate::execution::Query>, Vec<QueryExecutionError>> {
+        match &query {
+            Ok(query) => {
+                for rule in &query.validation_warnings {
+                    self.load_manager
+                        .record_validation_violation(rule, RuleMode::Warn.as_str());
+                }
+            }
+            Err(errors) => {
+                for error in errors {
+                    if let QueryExecutionError::ValidationError(_, rule, _) = error {
+                        self.load_manager
+                            .record_validation_violation(rule, RuleMode::Enforce.as_str());
+                    }
+                }
+            }
+        }
+        query
+    }
+
     /// Create a JSON value that contains the block information for our
     /// response
     #[allow(dead_code)]
@@ -148,6 +204,26 @@ where
         Ok(())
     }
 
+    /// Make sure that the history at the block that `resolver` queries
+    /// has not been pruned
+    fn check_earliest_block(
+        state: &DeploymentState,
+        resolver: &StoreResolver,
+    ) -> Result<(), QueryExecutionError> {
+        let number = resolver.block_number();
+        if number < state.earliest_block_number {
+            return Err(QueryExecutionError::ValueParseError(
+                "block.number".to_owned(),
+                format!(
+                    "subgraph {} only has data starting at block number {} \
+                     and data for block number {} is therefore not available",
+                    state.id, state.earliest_block_number, number
+                ),
+            ));
+        }
+        Ok(())
+    }
+
     async fn execute(
         &self,
         query: Query,
@@ -157,14 +233,30 @@ where
         max_first: Option<u32>,
         max_skip: Option<u32>,
         nested_resolver: bool,
+        cache_status: &mut CacheStatus,
     ) -> Result<Arc<QueryResult>, QueryResult> {
         let max_depth = max_depth.unwrap_or(*GRAPHQL_MAX_DEPTH);
-        let query = crate::execution::Query::new(&self.logger, query, max_complexity, max_depth)?;
+        let query = self.record_validation(crate::execution::Query::new(
+            &self.logger,
+            query,
+            max_complexity,
+            max_depth,
+        ))?;
         self.load_manager
             .decide(query.shape_hash, query.query_text.as_ref())
             .to_result()?;
 
-        let execute = |selection_set, resolver: StoreResolver| {
+        if let Some(error) = &state.fatal_error {
+            if !self.serve_failed_subgraphs && query.denies_subgraph_errors()? {
+                return Err(QueryExecutionError::DeploymentFailed(error.clone()).into());
+            }
+        }
+        let has_indexing_errors = state.has_indexing_errors();
+        let earliest_block = state.earliest_block_number;
+
+        let execute = |selection_set, mut resolver: StoreResolver| {
+            resolver.has_indexing_errors = has_indexing_errors;
+            resolver.earliest_block = earliest_block;
             execute_query(
                 query.clone(),
                 Some(selection_set),
@@ -185,8 +277,10 @@ where
         let (bc, selection_set) = by_block_constraint.next().unwrap();
 
         let store = self.store.cheap_clone();
-        let resolver =
-            StoreResolver::at_block(&self.logger, store, bc, query.schema.id().clone()).await?;
+        let resolver = StoreResolver::at_block(&self.logger, store, bc, query.schema.id().clone())
+            .await?
+            .computed_functions(self.computed.cheap_clone());
+        Self::check_earliest_block(&state, &resolver)?;
         let mut max_block = resolver.block_number();
         let mut result = execute(selection_set, resolver).await;
 
@@ -201,7 +295,9 @@ where
                     bc,
                     query.schema.id().clone(),
                 )
-                .await?;
+                .await?
+                .computed_functions(self.computed.cheap_clone());
+                Self::check_earliest_block(&state, &resolver)?;
                 max_block = max_block.max(resolver.block_number());
                 partial_res.append(execute(selection_set, resolver).await.as_ref().clone());
             }
@@ -209,6 +305,7 @@ where
         }
 
         query.log_execution(max_block);
+        *cache_status = query.cache_status.load();
         self.deployment_changed(state, max_block as u64)
             .map_err(QueryResult::from)
             .map(|()| result)
@@ -218,7 +315,7 @@ where
 #[async_trait]
 impl<S> GraphQlRunnerTrait for GraphQlRunner<S>
 where
-    S: Store + SubgraphDeploymentStore,
+    S: DeploymentStore + SubgraphDeploymentStore,
 {
     async fn run_query(
         self: Arc<Self>,
@@ -248,29 +345,65 @@ where
         max_skip: Option<u32>,
         nested_resolver: bool,
     ) -> Arc<QueryResult> {
-        self.execute(
-            query,
-            state,
-            max_complexity,
-            max_depth,
-            max_first,
-            max_skip,
-            nested_resolver,
+        let start = Instant::now();
+        let query_id = query.query_id.clone();
+        let query_hash = query.shape_hash;
+        let variables = query.variables_for_log();
+        let deployment = state.id.clone();
+        let mut cache_status = CacheStatus::default();
+        let span = trace::query_span(&deployment);
+        span.set_str("query_id", &query_id);
+
+        let result = trace::instrument(
+            self.execute(
+                query,
+                state,
+                max_complexity,
+                max_depth,
+                max_first,
+                max_skip,
+                nested_resolver,
+                &mut cache_status,
+            ),
+            &span,
         )
         .await
-        .unwrap_or_else(|e| Arc::new(e))
+        .unwrap_or_else(|e| Arc::new(e));
+
+        // Log every failed request, but only a sample of the successful ones
+        let error = result.error_class();
+        if let Some(error) = error {
+            span.set_error(&error);
+        }
+        span.end();
+
+        if error.is_some() || graph::log::sample_query_log() {
+            info!(
+                self.logger,
+                "Query served";
+                "query_id" => query_id,
+                "subgraph_id" => deployment.as_str(),
+                "query_time_ms" => start.elapsed().as_millis(),
+                "result_size" => result.weight(),
+                "error" => error.unwrap_or("none"),
+                "cached" => cache_status.to_string(),
+                "query_hash" => format!("{:x}", query_hash),
+                "variables" => variables,
+            );
+        }
+        result
     }
 
     async fn run_subscription(
         self: Arc<Self>,
         subscription: Subscription,
     ) -> Result<SubscriptionResult, SubscriptionError> {
-        let query = crate::execution::Query::new(
+        let query = self.record_validation(crate::execution::Query::new(
             &self.logger,
             subscription.query,
             *GRAPHQL_MAX_COMPLEXITY,
             *GRAPHQL_MAX_DEPTH,
-        )?;
+        ))?;
 
         if let Err(err) = self
             .load_manager
@@ -289,7 +422,8 @@ where
                     &self.logger,
                     deployment,
                     self.store.clone(),
-                ),
+                )
+                .computed_functions(self.computed.cheap_clone()),
                 timeout: GRAPHQL_QUERY_TIMEOUT.clone(),
                 max_complexity: *GRAPHQL_MAX_COMPLEXITY,
                 max_depth: *GRAPHQL_MAX_DEPTH,
@@ -2,12 +2,15 @@ use graphql_parser::schema::{Name, Value, *};
 use graphql_parser::Pos;
 use inflector::Inflector;
 use lazy_static::lazy_static;
+use std::borrow::Cow;
 
 use crate::schema::ast;
 
 use graph::data::{
+    graphql::computed::ComputedField,
     graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
-    schema::{META_FIELD_NAME, META_FIELD_TYPE},
+    schema::{ERROR_POLICY_ARGUMENT, ERROR_POLICY_TYPE, META_FIELD_NAME, META_FIELD_TYPE},
+    subgraph::schema::is_system_entity_type,
 };
 use graph::prelude::*;
 
@@ -28,6 +31,8 @@ const BLOCK_HEIGHT: &str = "Block_height";
 /// with all its fields and their input arguments, based on the existing
 /// types.
 pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
+    let input_schema = &*without_system_types(input_schema);
+
     // Refactor: Take `input_schema` by value.
     let object_types = ast::get_object_type_definitions(input_schema);
     let interface_types = ast::get_interface_type_definitions(input_schema);
@@ -47,6 +52,24 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     Ok(schema)
 }
 
+/// Remove graph-node's own entity types from `schema`; they must never
+/// become part of the API
+fn without_system_types(schema: &Document) -> Cow<Document> {
+    let is_system = |def: &Definition| match def {
+        Definition::TypeDefinition(TypeDefinition::Object(object_type)) => {
+            is_system_entity_type(&object_type.name)
+        }
+        _ => false,
+    };
+
+    if !schema.definitions.iter().any(is_system) {
+        return Cow::Borrowed(schema);
+    }
+    let mut schema = schema.clone();
+    schema.definitions.retain(|def| !is_system(def));
+    Cow::Owned(schema)
+}
+
 /// Adds built-in GraphQL scalar types (`Int`, `String` etc.) to the schema.
 fn add_builtin_scalar_types(schema: &mut Document) -> Result<(), APISchemaError> {
     for name in [
@@ -57,6 +80,7 @@ fn add_builtin_scalar_types(schema: &mut Document) -> Result<(), APISchemaError>
         "String",
         "Bytes",
         "BigInt",
+        "Timestamp",
     ]
     .iter()
     {
@@ -102,6 +126,33 @@ fn add_directives(schema: &mut Document) {
         locations: vec![DirectiveLocation::FieldDefinition],
     });
 
+    let computed = Definition::DirectiveDefinition(DirectiveDefinition {
+        position: Pos::default(),
+        description: None,
+        name: "computed".to_owned(),
+        arguments: vec![
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "from".to_owned(),
+                value_type: Type::NonNullType(Box::new(Type::ListType(Box::new(
+                    Type::NonNullType(Box::new(Type::NamedType("String".to_owned()))),
+                )))),
+                default_value: None,
+                directives: vec![],
+            },
+            InputValue {
+                position: Pos::default(),
+                description: None,
+                name: "function".to_owned(),
+                value_type: Type::NamedType("String".to_owned()),
+                default_value: None,
+                directives: vec![],
+            },
+        ],
+        locations: vec![DirectiveLocation::FieldDefinition],
+    });
+
     let subgraph_id = Definition::DirectiveDefinition(DirectiveDefinition {
         position: Pos::default(),
         description: None,
@@ -119,6 +170,7 @@ fn add_directives(schema: &mut Document) {
 
     schema.definitions.push(entity);
     schema.definitions.push(derived_from);
+    schema.definitions.push(computed);
     schema.definitions.push(subgraph_id);
 }
 
@@ -227,8 +279,11 @@ fn add_order_by_type(
                 description: None,
                 name: type_name,
                 directives: vec![],
+                // Computed fields are not stored, and we can therefore
+                // not sort by them
                 values: fields
                     .iter()
+                    .filter(|field| !ComputedField::is_computed(field))
                     .map(|field| &field.name)
                     .map(|name| EnumValue {
                         position: Pos::default(),
@@ -286,7 +341,11 @@ fn field_input_values(
     fields: &[Field],
 ) -> Result<Vec<InputValue>, APISchemaError> {
     let mut input_values = vec![];
-    for field in fields {
+    // Computed fields are not stored, and we can therefore not filter by them
+    for field in fields
+        .iter()
+        .filter(|field| !ComputedField::is_computed(field))
+    {
         input_values.extend(field_filter_input_values(
             schema,
             &field,
@@ -349,6 +408,7 @@ fn field_scalar_filter_input_values(
         "BigDecimal" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "ID" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "Int" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
+        "Timestamp" => vec!["", "not", "gt", "lt", "gte", "lte", "in", "not_in"],
         "List" => vec!["", "not", "in", "not_in", "contains", "not_contains"],
         "String" => vec![
             "",
@@ -550,6 +610,7 @@ fn query_field_for_fulltext(fulltext: &Directive) -> Option<Field> {
             default_value: None,
             directives: vec![],
         },
+        error_policy_argument(),
     ];
     Some(Field {
         position: Pos::default(),
@@ -613,11 +674,28 @@ fn block_argument() -> InputValue {
     }
 }
 
+fn error_policy_argument() -> InputValue {
+    InputValue {
+        position: Pos::default(),
+        description: Some(
+            "Set to `allow` to receive data even if the subgraph has failed. \
+             Defaults to `deny`, which returns the reason for the failure \
+             instead of data."
+                .to_owned(),
+        ),
+        name: ERROR_POLICY_ARGUMENT.to_string(),
+        value_type: Type::NamedType(ERROR_POLICY_TYPE.to_owned()),
+        default_value: Some(Value::Enum("deny".to_owned())),
+        directives: vec![],
+    }
+}
+
 /// Generates `Query` fields for the given type name (e.g. `users` and `user`).
 fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
     let input_objects = ast::get_input_object_definitions(schema);
     let mut collection_arguments = collection_arguments_for_named_type(&input_objects, type_name);
     collection_arguments.push(block_argument());
+    collection_arguments.push(error_policy_argument());
 
     vec![
         Field {
@@ -634,6 +712,7 @@ fn query_fields_for_type(schema: &Document, type_name: &Name) -> Vec<Field> {
                     directives: vec![],
                 },
                 block_argument(),
+                error_policy_argument(),
             ],
             field_type: Type::NamedType(type_name.to_owned()),
             directives: vec![],
@@ -820,6 +899,32 @@ mod tests {
             .expect("String type is missing in API schema");
     }
 
+    #[test]
+    fn api_schema_omits_system_entity_types() {
+        let mut input_schema =
+            parse_schema("type User { id: ID! } type Poi { id: ID!, digest: Bytes! }")
+                .expect("Failed to parse input schema");
+        // `Poi$` is not a valid GraphQL name, so the parser can't produce it
+        for def in &mut input_schema.definitions {
+            if let Definition::TypeDefinition(TypeDefinition::Object(object_type)) = def {
+                if object_type.name == "Poi" {
+                    object_type.name = "Poi$".to_owned();
+                }
+            }
+        }
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        assert!(ast::get_named_type(&schema, &"Poi$".to_string()).is_none());
+        assert!(ast::get_named_type(&schema, &"Poi$_filter".to_string()).is_none());
+        let query_type = ast::get_root_query_type_def(&schema).expect("Query type is missing");
+        let fields = match query_type {
+            TypeDefinition::Object(t) => t.fields.iter().map(|f| f.name.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        assert!(fields.contains(&"user"));
+        assert!(fields.iter().all(|name| !name.contains('$')));
+    }
+
     #[test]
     fn api_schema_contains_order_direction_enum() {
         let input_schema = parse_schema("type User { id: ID!, name: String! }")
@@ -866,6 +971,29 @@ mod tests {
         assert_eq!(values, [&"id".to_string(), &"name".to_string()]);
     }
 
+    #[test]
+    fn api_schema_excludes_computed_fields_from_order_by_and_filter() {
+        let input_schema = parse_schema(
+            "type Token { id: ID!, price: BigDecimal!, valueUSD: BigDecimal @computed(from: [\"price\"]) }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let order_by = match ast::get_named_type(&schema, &"Token_orderBy".to_string()) {
+            Some(TypeDefinition::Enum(t)) => t,
+            _ => panic!("Token_orderBy type is missing or not an enum"),
+        };
+        let values: Vec<&str> = order_by.values.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(values, ["id", "price"]);
+
+        let filter = match ast::get_named_type(&schema, &"Token_filter".to_string()) {
+            Some(TypeDefinition::InputObject(t)) => t,
+            _ => panic!("Token_filter type is missing or not an input object"),
+        };
+        assert!(filter.fields.iter().any(|f| f.name == "price_gt"));
+        assert!(!filter.fields.iter().any(|f| f.name.starts_with("valueUSD")));
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -986,7 +1114,11 @@ mod tests {
                 .iter()
                 .map(|input_value| input_value.name.to_owned())
                 .collect::<Vec<String>>(),
-            vec!["id".to_string(), "block".to_string()],
+            vec![
+                "id".to_string(),
+                "block".to_string(),
+                "subgraphError".to_string()
+            ],
         );
 
         let user_plural_field = match query_type {
@@ -1014,7 +1146,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
-                "block"
+                "block",
+                "subgraphError"
             ]
             .iter()
             .map(|name| name.to_string())
@@ -1077,7 +1210,11 @@ mod tests {
                 .iter()
                 .map(|input_value| input_value.name.to_owned())
                 .collect::<Vec<String>>(),
-            vec!["id".to_string(), "block".to_string()],
+            vec![
+                "id".to_string(),
+                "block".to_string(),
+                "subgraphError".to_string()
+            ],
         );
 
         let plural_field = match query_type {
@@ -1105,7 +1242,8 @@ mod tests {
                 "orderBy",
                 "orderDirection",
                 "where",
-                "block"
+                "block",
+                "subgraphError"
             ]
             .iter()
             .map(|name| name.to_string())
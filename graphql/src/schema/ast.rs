@@ -1,10 +1,13 @@
 use graphql_parser::schema::{Value, *};
 use graphql_parser::Pos;
 use lazy_static::lazy_static;
+use std::convert::TryFrom;
 use std::ops::Deref;
 use std::str::FromStr;
 
 use crate::query::ast as qast;
+use graph::data::graphql::computed::ComputedField;
+use graph::data::graphql::length::{byte_length, canonical_hex};
 use graph::data::graphql::ObjectOrInterface;
 use graph::data::store;
 use graph::prelude::*;
@@ -438,6 +441,7 @@ fn is_assignable(value: &store::Value, scalar_type: &ValueType, is_list: bool) -
         | (store::Value::Bool(_), ValueType::Boolean)
         | (store::Value::Bytes(_), ValueType::Bytes)
         | (store::Value::Int(_), ValueType::Int)
+        | (store::Value::Timestamp(_), ValueType::Timestamp)
         | (store::Value::Null, _) => true,
         (store::Value::List(values), _) if is_list => values
             .iter()
@@ -446,6 +450,167 @@ fn is_assignable(value: &store::Value, scalar_type: &ValueType, is_list: bool) -
     }
 }
 
+/// Mappings can not create timestamps directly; they set fields of type
+/// `Timestamp` to a `BigInt` with the number of microseconds since the
+/// epoch or to an ISO-8601 string. Convert such values in `entity` to
+/// proper timestamps. Values of other types are left alone so that
+/// `validate_entity` can report them
+pub fn coerce_timestamps(
+    schema: &Document,
+    key: &EntityKey,
+    entity: &mut Entity,
+) -> Result<(), anyhow::Error> {
+    fn coerce(value: &store::Value) -> Result<Option<store::Value>, store::scalar::TimestampError> {
+        use store::scalar::Timestamp;
+        use store::Value;
+
+        match value {
+            Value::BigInt(n) => Timestamp::try_from(n).map(|ts| Some(Value::Timestamp(ts))),
+            Value::String(s) => Timestamp::from_str(s).map(|ts| Some(Value::Timestamp(ts))),
+            Value::List(values) => {
+                let mut coerced = Vec::with_capacity(values.len());
+                for value in values {
+                    coerced.push(coerce(value)?.unwrap_or_else(|| value.clone()));
+                }
+                Ok(Some(Value::List(coerced)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    let object_type = match get_object_type_definitions(schema)
+        .into_iter()
+        .find(|object_type| object_type.name == key.entity_type)
+    {
+        Some(object_type) => object_type,
+        // `validate_entity` complains about unknown types
+        None => return Ok(()),
+    };
+
+    for field in &object_type.fields {
+        if scalar_value_type(schema, &field.field_type) != ValueType::Timestamp {
+            continue;
+        }
+        if let Some(value) = entity.get_mut(&field.name) {
+            match coerce(value) {
+                Ok(Some(coerced)) => *value = coerced,
+                Ok(None) => {}
+                Err(e) => anyhow::bail!(
+                    "Entity {}[{}]: the value `{}` for field `{}` is not a valid Timestamp: {}",
+                    key.entity_type,
+                    key.entity_id,
+                    value,
+                    field.name,
+                    e
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Subgraphs that set `strictBytes` may set fields of type `Bytes` to hex
+/// strings; convert such values in `entity` to bytes. Hex strings in
+/// `String` and `ID` fields with a `@length` directive are lowercased, and
+/// values of fields with a `@length` must have that many bytes
+pub fn canonicalize_bytes(
+    schema: &Document,
+    key: &EntityKey,
+    entity: &mut Entity,
+) -> Result<(), anyhow::Error> {
+    fn canonicalize(
+        value: &store::Value,
+        value_type: &ValueType,
+        length: Option<usize>,
+    ) -> Result<Option<store::Value>, String> {
+        use store::Value;
+
+        match (value, value_type) {
+            (Value::String(s), ValueType::Bytes) => {
+                let hex = canonical_hex(s, length)?;
+                store::scalar::Bytes::from_str(&hex)
+                    .map(|bytes| Some(Value::Bytes(bytes)))
+                    .map_err(|e| e.to_string())
+            }
+            (Value::Bytes(bytes), ValueType::Bytes) => match length {
+                Some(length) if bytes.as_slice().len() != length => Err(format!(
+                    "expected {} bytes but got {}",
+                    length,
+                    bytes.as_slice().len()
+                )),
+                _ => Ok(None),
+            },
+            (Value::String(s), ValueType::String) if length.is_some() => {
+                canonical_hex(s, length).map(|s| Some(Value::String(s)))
+            }
+            (Value::List(values), _) => {
+                let mut canonical = Vec::with_capacity(values.len());
+                for value in values {
+                    canonical.push(
+                        canonicalize(value, value_type, length)?.unwrap_or_else(|| value.clone()),
+                    );
+                }
+                Ok(Some(Value::List(canonical)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    let object_type = match get_object_type_definitions(schema)
+        .into_iter()
+        .find(|object_type| object_type.name == key.entity_type)
+    {
+        Some(object_type) => object_type,
+        // `validate_entity` complains about unknown types
+        None => return Ok(()),
+    };
+
+    for field in &object_type.fields {
+        // Malformed directives were rejected when the subgraph was deployed
+        let length = byte_length(field).unwrap_or(None);
+        let value_type = scalar_value_type(schema, &field.field_type);
+        if value_type != ValueType::Bytes && length.is_none() {
+            continue;
+        }
+        if let Some(value) = entity.get_mut(&field.name) {
+            match canonicalize(value, &value_type, length) {
+                Ok(Some(canonical)) => *value = canonical,
+                Ok(None) => {}
+                Err(e) => anyhow::bail!(
+                    "Entity {}[{}]: the value `{}` for field `{}` is not valid: {}",
+                    key.entity_type,
+                    key.entity_id,
+                    value,
+                    field.name,
+                    e
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Return the form of `id` under which subgraphs that set `strictBytes`
+/// store entities of type `entity_type`: ids with a `@length` directive are
+/// lowercase hex strings of that many bytes, other ids are used as they are
+pub fn canonical_id(
+    schema: &Document,
+    entity_type: &str,
+    id: &str,
+) -> Result<String, anyhow::Error> {
+    let length = get_object_type_definitions(schema)
+        .into_iter()
+        .find(|object_type| object_type.name == entity_type)
+        .and_then(|object_type| object_type.fields.iter().find(|field| field.name == "id"))
+        .and_then(|field| byte_length(field).unwrap_or(None));
+    match length {
+        Some(length) => canonical_hex(id, Some(length)).map_err(|e| {
+            anyhow::anyhow!("Entity {}[{}]: the id is not valid: {}", entity_type, id, e)
+        }),
+        None => Ok(id.to_owned()),
+    }
+}
+
 pub fn validate_entity(
     schema: &Document,
     key: &EntityKey,
@@ -463,6 +628,18 @@ pub fn validate_entity(
         })?;
 
     for field in &object_type.fields {
+        if ComputedField::is_computed(field) {
+            // Computed fields are evaluated at query time and never stored
+            if entity.get(&field.name).is_some() {
+                anyhow::bail!(
+                    "Entity {}[{}]: field `{}` is computed and can not be set",
+                    key.entity_type,
+                    key.entity_id,
+                    field.name,
+                );
+            }
+            continue;
+        }
         let is_derived = get_derived_from_directive(field).is_some();
         match (entity.get(&field.name), is_derived) {
             (Some(value), false) => {
@@ -556,6 +733,8 @@ fn entity_validation() {
           # Make sure we do not validate derived fields; it's ok
           # to store a thing with a null Cruft
           cruft: Cruft! @derivedFrom(field: \"thing\")
+          # Computed fields are not stored either
+          loud_name: String! @computed(from: [\"name\"])
       }";
         let subgraph = SubgraphDeploymentId::new("doesntmatter").unwrap();
         let schema =
@@ -563,7 +742,7 @@ fn entity_validation() {
         let id = thing.id().unwrap_or("none".to_owned());
         let key = EntityKey {
             subgraph_id: SubgraphDeploymentId::new("doesntmatter").unwrap(),
-            entity_type: "Thing".to_owned(),
+            entity_type: "Thing".into(),
             entity_id: id.to_owned(),
         };
 
@@ -638,4 +817,55 @@ fn entity_validation() {
         thing,
         "Entity Thing[t8]: field `cruft` is derived and can not be set",
     );
+
+    let mut thing = make_thing("t9");
+    thing.set("loud_name", "T9");
+    check(
+        thing,
+        "Entity Thing[t9]: field `loud_name` is computed and can not be set",
+    );
+}
+
+#[test]
+fn timestamp_coercion() {
+    use store::scalar::Timestamp;
+
+    const DOCUMENT: &str = "
+      type Event @entity {
+          id: ID!,
+          at: Timestamp!,
+          seen: [Timestamp!]
+      }";
+    let subgraph = SubgraphDeploymentId::new("doesntmatter").unwrap();
+    let schema =
+        graph::prelude::Schema::parse(DOCUMENT, subgraph).expect("Failed to parse test schema");
+    let key = EntityKey {
+        subgraph_id: SubgraphDeploymentId::new("doesntmatter").unwrap(),
+        entity_type: "Event".into(),
+        entity_id: "e1".to_owned(),
+    };
+    let expected = Timestamp::from_str("2020-11-03T10:00:00Z").unwrap();
+
+    let mut event = Entity::new();
+    event.set("id", "e1");
+    event.set("at", BigInt::from(expected.as_micros()));
+    event.set(
+        "seen",
+        store::Value::List(vec!["2020-11-03T11:00:00+01:00".into()]),
+    );
+    coerce_timestamps(&schema.document, &key, &mut event).unwrap();
+    assert_eq!(Some(&store::Value::Timestamp(expected)), event.get("at"));
+    assert_eq!(
+        Some(&store::Value::List(vec![store::Value::Timestamp(expected)])),
+        event.get("seen")
+    );
+    assert!(validate_entity(&schema.document, &key, &event).is_ok());
+
+    event.set("at", "last tuesday");
+    let err = coerce_timestamps(&schema.document, &key, &mut event).unwrap_err();
+    assert_eq!(
+        "Entity Event[e1]: the value `last tuesday` for field `at` is not a valid \
+         Timestamp: `last tuesday` is neither an ISO-8601 timestamp nor a number of microseconds",
+        err.to_string()
+    );
 }
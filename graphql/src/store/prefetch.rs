@@ -5,11 +5,13 @@ use graphql_parser::query as q;
 use graphql_parser::schema as s;
 use indexmap::IndexMap;
 use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet};
 use std::iter::once;
 use std::rc::Rc;
 use std::time::Instant;
 
+use graph::data::graphql::computed::{ComputedField, ComputedQuery};
+use graph::data::graphql::length::canonical_filter_value;
 use graph::data::graphql::*;
 use graph::prelude::{
     ApiSchema, BlockNumber, ChildMultiplicity, EntityCollection, EntityFilter, EntityLink,
@@ -453,12 +455,19 @@ fn execute_root_selection_set(
     let grouped_field_set = collect_fields(ctx, query_type, once(selection_set));
 
     // Execute the root selection set against the root query type
-    execute_selection_set(resolver, ctx, make_root_node(), grouped_field_set)
+    execute_selection_set(
+        resolver,
+        ctx,
+        &mut Computed::default(),
+        make_root_node(),
+        grouped_field_set,
+    )
 }
 
 fn execute_selection_set<'a>(
     resolver: &StoreResolver,
     ctx: &'a ExecutionContext<impl Resolver>,
+    computed: &mut Computed,
     mut parents: Vec<Node>,
     grouped_field_set: IndexMap<&'a String, CollectedResponseKey<'a>>,
 ) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
@@ -508,11 +517,26 @@ fn execute_selection_set<'a>(
             let grouped_field_set =
                 collect_fields(ctx, child_type, fields.iter().map(|f| &f.selection_set));
 
+            // For interfaces, only fetch the attributes that the selection
+            // uses from each implementation
+            let attributes = match child_type {
+                ObjectOrInterface::Interface(interface) => {
+                    selected_attributes(ctx, interface, fields.iter().map(|f| &f.selection_set))
+                }
+                ObjectOrInterface::Object(_) => BTreeMap::new(),
+            };
+
             match execute_field(
-                resolver, &ctx, type_cond, &parents, &join, &fields[0], field,
+                resolver, &ctx, computed, type_cond, &parents, &join, &fields[0], field, attributes,
             ) {
                 Ok(children) => {
-                    match execute_selection_set(resolver, ctx, children, grouped_field_set) {
+                    match execute_selection_set(
+                        resolver,
+                        ctx,
+                        computed,
+                        children,
+                        grouped_field_set,
+                    ) {
                         Ok(children) => Join::perform(parents, children, response_key),
                         Err(mut e) => errors.append(&mut e),
                     }
@@ -754,15 +778,111 @@ fn collect_fields_inner<'a>(
     }
 }
 
+/// The attributes that `selection_sets` use from each type that implements
+/// `interface`. For a type, these are the fields selected on the interface
+/// and the fields that fragments select on the type or on another
+/// interface that it implements, plus the fields that its `@computed`
+/// fields are computed from. Derived fields are left out since they are
+/// not stored with the entity; `id` and `__typename` are always available
+fn selected_attributes<'a>(
+    ctx: &'a ExecutionContext<impl Resolver>,
+    interface: &s::InterfaceType,
+    selection_sets: impl Iterator<Item = &'a q::SelectionSet> + Clone,
+) -> BTreeMap<String, BTreeSet<String>> {
+    fn collect<'a>(
+        ctx: &'a ExecutionContext<impl Resolver>,
+        object_type: &'a s::ObjectType,
+        selection_set: &'a q::SelectionSet,
+        visited_fragments: &mut HashSet<&'a q::Name>,
+        attributes: &mut BTreeSet<String>,
+    ) {
+        let applies = |condition: Option<&q::TypeCondition>| match condition {
+            None => true,
+            Some(q::TypeCondition::On(name)) => {
+                name == &object_type.name || object_type.implements_interfaces.contains(name)
+            }
+        };
+
+        for selection in &selection_set.items {
+            match selection {
+                q::Selection::Field(field) => {
+                    if let Some(field) = object_type.field(&field.name) {
+                        if sast::get_derived_from_directive(field).is_none()
+                            && !ComputedField::is_computed(field)
+                        {
+                            attributes.insert(field.name.clone());
+                        }
+                    }
+                }
+                q::Selection::FragmentSpread(spread) => {
+                    let fragment = ctx.query.get_fragment(&spread.fragment_name);
+                    if applies(Some(&fragment.type_condition))
+                        && visited_fragments.insert(&spread.fragment_name)
+                    {
+                        collect(
+                            ctx,
+                            object_type,
+                            &fragment.selection_set,
+                            visited_fragments,
+                            attributes,
+                        );
+                    }
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    if applies(fragment.type_condition.as_ref()) {
+                        collect(
+                            ctx,
+                            object_type,
+                            &fragment.selection_set,
+                            visited_fragments,
+                            attributes,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    ctx.query
+        .schema
+        .types_for_interface()
+        .get(&interface.name)
+        .into_iter()
+        .flatten()
+        .map(|object_type| {
+            let mut attributes = BTreeSet::new();
+            for selection_set in selection_sets.clone() {
+                collect(
+                    ctx,
+                    object_type,
+                    selection_set,
+                    &mut HashSet::new(),
+                    &mut attributes,
+                );
+            }
+            // Computed fields are evaluated for every entity we fetch,
+            // whether the query selects them or not
+            for field in &object_type.fields {
+                if let Ok(Some(computed)) = ComputedField::from_field(field) {
+                    attributes.extend(computed.from);
+                }
+            }
+            (object_type.name.clone(), attributes)
+        })
+        .collect()
+}
+
 /// Executes a field.
 fn execute_field(
     resolver: &StoreResolver,
     ctx: &ExecutionContext<impl Resolver>,
+    computed: &mut Computed,
     object_type: ObjectOrInterface<'_>,
     parents: &Vec<&mut Node>,
     join: &Join<'_>,
     field: &q::Field,
     field_definition: &s::Field,
+    attributes: BTreeMap<String, BTreeSet<String>>,
 ) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
     let argument_values = match object_type {
         ObjectOrInterface::Object(object_type) => {
@@ -802,15 +922,88 @@ fn execute_field(
         &join,
         argument_values,
         multiplicity,
+        attributes,
         ctx.query.schema.types_for_interface(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
         ctx.query.query_id.clone(),
     )
+    .and_then(|mut children| {
+        computed.add_fields(resolver, ctx.query.schema.document(), &mut children)?;
+        Ok(children)
+    })
     .map_err(|e| vec![e])
 }
 
+/// The `@computed` fields of the types that a query returns, and their
+/// evaluation for the query, which is only started once a node needs it
+#[derive(Default)]
+struct Computed {
+    fields: HashMap<String, Vec<ComputedField>>,
+    query: Option<Box<dyn ComputedQuery>>,
+}
+
+impl Computed {
+    /// Evaluate the computed fields of `nodes` and add their values to the
+    /// nodes; the store does not know about computed fields
+    fn add_fields(
+        &mut self,
+        resolver: &StoreResolver,
+        schema: &s::Document,
+        nodes: &mut Vec<Node>,
+    ) -> Result<(), QueryExecutionError> {
+        for node in nodes.iter_mut() {
+            let typename = node.typename().to_owned();
+            let fields = match self.fields.entry(typename.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let fields = match schema.get_object_type_definition(&typename) {
+                        Some(object_type) => object_type
+                            .fields
+                            .iter()
+                            .filter_map(|field| ComputedField::from_field(field).transpose())
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|e| {
+                                QueryExecutionError::ResolveEntitiesError(format!(
+                                    "invalid @computed directive in type {}: {}",
+                                    typename, e
+                                ))
+                            })?,
+                        None => vec![],
+                    };
+                    entry.insert(fields)
+                }
+            };
+            if fields.is_empty() {
+                continue;
+            }
+
+            if self.query.is_none() {
+                let query = resolver
+                    .computed
+                    .query(&resolver.deployment)
+                    .map_err(QueryExecutionError::ResolveEntitiesError)?;
+                self.query = Some(query);
+            }
+            let query = self.query.as_mut().unwrap();
+            for field in fields.iter() {
+                let value = field.compute(query.as_mut(), &node.entity).map_err(|e| {
+                    QueryExecutionError::ResolveEntitiesError(format!(
+                        "failed to compute field `{}` of {}[{}]: {}",
+                        field.name,
+                        typename,
+                        node.id().unwrap_or_default(),
+                        e
+                    ))
+                })?;
+                node.entity.insert(field.name.clone(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
 /// `is_single` is `true`, there is at most one child per parent.
@@ -821,6 +1014,7 @@ fn fetch(
     join: &Join<'_>,
     arguments: HashMap<&q::Name, q::Value>,
     multiplicity: ChildMultiplicity,
+    attributes: BTreeMap<String, BTreeSet<String>>,
     types_for_interface: &BTreeMap<s::Name, Vec<s::ObjectType>>,
     block: BlockNumber,
     max_first: u32,
@@ -836,6 +1030,7 @@ fn fetch(
         max_skip,
     )?;
     query.query_id = Some(query_id);
+    query.attributes = attributes;
 
     if multiplicity == ChildMultiplicity::Single {
         // Suppress 'order by' in lookups of scalar values since
@@ -845,10 +1040,12 @@ fn fetch(
 
     query.logger = Some(logger);
     if let Some(q::Value::String(id)) = arguments.get(&*ARG_ID) {
-        query.filter = Some(
-            EntityFilter::Equal(ARG_ID.to_owned(), StoreValue::from(id.to_owned()))
-                .and_maybe(query.filter),
-        );
+        let id = match sast::get_field(join.child_type, &*ARG_ID) {
+            Some(field) => canonical_filter_value(field, StoreValue::from(id.to_owned())),
+            None => StoreValue::from(id.to_owned()),
+        };
+        query.filter =
+            Some(EntityFilter::Equal(ARG_ID.as_str().into(), id).and_maybe(query.filter));
     }
 
     if !is_root_node(parents.iter().map(|p| &**p)) {
@@ -2,7 +2,9 @@ use graphql_parser::{query as q, query::Name, schema as s, schema::ObjectType};
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::mem::discriminant;
 
+use graph::data::graphql::length::canonical_filter_value;
 use graph::data::graphql::ObjectOrInterface;
+use graph::data::subgraph::schema::is_system_entity_type;
 use graph::prelude::*;
 
 use crate::schema::ast as sast;
@@ -32,6 +34,17 @@ pub fn build_query<'a>(
             .map(|o| o.name.clone())
             .collect(),
     });
+    // Even if a schema had graph-node's own entity types in it, queries
+    // must not be able to get at them
+    if let Some(entity_type) = entity_types
+        .entity_types()
+        .into_iter()
+        .find(|entity_type| is_system_entity_type(entity_type))
+    {
+        return Err(QueryExecutionError::SystemEntityType(
+            entity_type.to_owned(),
+        ));
+    }
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(arguments, max_first, max_skip)?);
     if let Some(filter) = build_filter(entity, arguments)? {
@@ -42,10 +55,10 @@ pub fn build_query<'a>(
         build_order_direction(arguments)?,
     ) {
         (Some((attr, value_type)), OrderDirection::Ascending) => {
-            EntityOrder::Ascending(attr, value_type)
+            EntityOrder::Ascending(attr.into(), value_type)
         }
         (Some((attr, value_type)), OrderDirection::Descending) => {
-            EntityOrder::Descending(attr, value_type)
+            EntityOrder::Descending(attr.into(), value_type)
         }
         (None, _) => EntityOrder::Default,
     };
@@ -120,7 +133,7 @@ fn build_fulltext_filter_from_object(
         |(key, value)| {
             if let q::Value::String(s) = value {
                 Ok(Some(EntityFilter::Equal(
-                    key.clone(),
+                    key.into(),
                     Value::String(s.clone()),
                 )))
             } else {
@@ -151,23 +164,26 @@ fn build_filter_from_object(
                 })?;
 
                 let ty = &field.field_type;
-                let store_value = Value::from_query_value(value, &ty)?;
+                let store_value =
+                    canonical_filter_value(field, Value::from_query_value(value, &ty)?);
 
                 Ok(match op {
-                    Not => EntityFilter::Not(field_name, store_value),
-                    GreaterThan => EntityFilter::GreaterThan(field_name, store_value),
-                    LessThan => EntityFilter::LessThan(field_name, store_value),
-                    GreaterOrEqual => EntityFilter::GreaterOrEqual(field_name, store_value),
-                    LessOrEqual => EntityFilter::LessOrEqual(field_name, store_value),
-                    In => EntityFilter::In(field_name, list_values(store_value, "_in")?),
-                    NotIn => EntityFilter::NotIn(field_name, list_values(store_value, "_not_in")?),
-                    Contains => EntityFilter::Contains(field_name, store_value),
-                    NotContains => EntityFilter::NotContains(field_name, store_value),
-                    StartsWith => EntityFilter::StartsWith(field_name, store_value),
-                    NotStartsWith => EntityFilter::NotStartsWith(field_name, store_value),
-                    EndsWith => EntityFilter::EndsWith(field_name, store_value),
-                    NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
-                    Equal => EntityFilter::Equal(field_name, store_value),
+                    Not => EntityFilter::Not(field_name.into(), store_value),
+                    GreaterThan => EntityFilter::GreaterThan(field_name.into(), store_value),
+                    LessThan => EntityFilter::LessThan(field_name.into(), store_value),
+                    GreaterOrEqual => EntityFilter::GreaterOrEqual(field_name.into(), store_value),
+                    LessOrEqual => EntityFilter::LessOrEqual(field_name.into(), store_value),
+                    In => EntityFilter::In(field_name.into(), list_values(store_value, "_in")?),
+                    NotIn => {
+                        EntityFilter::NotIn(field_name.into(), list_values(store_value, "_not_in")?)
+                    }
+                    Contains => EntityFilter::Contains(field_name.into(), store_value),
+                    NotContains => EntityFilter::NotContains(field_name.into(), store_value),
+                    StartsWith => EntityFilter::StartsWith(field_name.into(), store_value),
+                    NotStartsWith => EntityFilter::NotStartsWith(field_name.into(), store_value),
+                    EndsWith => EntityFilter::EndsWith(field_name.into(), store_value),
+                    NotEndsWith => EntityFilter::NotEndsWith(field_name.into(), store_value),
+                    Equal => EntityFilter::Equal(field_name.into(), store_value),
                 })
             })
             .collect::<Result<Vec<EntityFilter>, QueryExecutionError>>()?
@@ -446,6 +462,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_query_rejects_system_entity_types() {
+        match build_query(
+            &object("Poi$"),
+            BLOCK_NUMBER_MAX,
+            &default_arguments(),
+            &BTreeMap::new(),
+            std::u32::MAX,
+            std::u32::MAX,
+        ) {
+            Err(QueryExecutionError::SystemEntityType(entity_type)) => {
+                assert_eq!("Poi$", entity_type)
+            }
+            other => panic!("expected a SystemEntityType error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn build_query_yields_no_order_if_order_arguments_are_missing() {
         assert_eq!(
@@ -479,7 +512,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".into(), ValueType::String)
         );
 
         let mut args = default_arguments();
@@ -495,7 +528,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("email".to_string(), ValueType::String)
+            EntityOrder::Ascending("email".into(), ValueType::String)
         );
     }
 
@@ -553,7 +586,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".into(), ValueType::String)
         );
 
         let mut args = default_arguments();
@@ -570,7 +603,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Descending("name".to_string(), ValueType::String)
+            EntityOrder::Descending("name".into(), ValueType::String)
         );
 
         let mut args = default_arguments();
@@ -590,7 +623,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".into(), ValueType::String)
         );
 
         // No orderBy -> EntityOrder::Default
@@ -632,7 +665,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".into(), ValueType::String)
         );
 
         let mut args = default_arguments();
@@ -649,7 +682,7 @@ mod tests {
             )
             .unwrap()
             .order,
-            EntityOrder::Ascending("name".to_string(), ValueType::String)
+            EntityOrder::Ascending("name".into(), ValueType::String)
         );
     }
 
@@ -719,9 +752,49 @@ mod tests {
             .unwrap()
             .filter,
             Some(EntityFilter::And(vec![EntityFilter::EndsWith(
-                "name".to_string(),
+                "name".into(),
                 Value::String("ello".to_string()),
             )]))
         )
     }
+
+    #[test]
+    fn build_query_lowercases_filters_on_fields_with_length() {
+        let whre = "where".to_string();
+        let mut args = default_arguments();
+        args.insert(
+            &whre,
+            q::Value::Object(BTreeMap::from_iter(vec![(
+                "owner_in".to_string(),
+                q::Value::List(vec![q::Value::String("0xABcd".to_string())]),
+            )])),
+        );
+        let owner = Field {
+            directives: vec![Directive {
+                name: "length".to_string(),
+                position: Pos::default(),
+                arguments: vec![("bytes".to_string(), SchemaValue::Int(2.into()))],
+            }],
+            ..field("owner", Type::NamedType("String".to_owned()))
+        };
+        assert_eq!(
+            build_query(
+                &ObjectType {
+                    fields: vec![owner],
+                    ..default_object()
+                },
+                BLOCK_NUMBER_MAX,
+                &args,
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::And(vec![EntityFilter::In(
+                "owner".into(),
+                vec![Value::String("0xabcd".to_string())],
+            )]))
+        )
+    }
 }
@@ -4,6 +4,7 @@ use std::result;
 use std::sync::Arc;
 
 use graph::components::store::*;
+use graph::data::graphql::computed::{ComputedFunctions, NoComputedFunctions};
 use graph::data::graphql::ObjectOrInterface;
 use graph::prelude::*;
 
@@ -19,7 +20,15 @@ pub struct StoreResolver {
     logger: Logger,
     pub(crate) store: Arc<dyn QueryStore>,
     pub(crate) block_ptr: Option<EthereumBlockPointer>,
-    deployment: SubgraphDeploymentId,
+    pub(crate) deployment: SubgraphDeploymentId,
+    /// Reported through `_meta`; whether the deployment has encountered
+    /// any indexing errors
+    pub(crate) has_indexing_errors: bool,
+    /// Reported through `_meta`; the earliest block for which the
+    /// deployment still has the history of its entities
+    pub(crate) earliest_block: BlockNumber,
+    /// Evaluates the `@computed` fields of entities
+    pub(crate) computed: Arc<dyn ComputedFunctions>,
 }
 
 impl CheapClone for StoreResolver {}
@@ -32,13 +41,16 @@ impl StoreResolver {
     pub fn for_subscription(
         logger: &Logger,
         deployment: SubgraphDeploymentId,
-        store: Arc<impl Store>,
+        store: Arc<impl EntityStore>,
     ) -> Self {
         StoreResolver {
             logger: logger.new(o!("component" => "StoreResolver")),
             store: store.query_store(true),
             block_ptr: None,
             deployment,
+            has_indexing_errors: false,
+            earliest_block: 0,
+            computed: Arc::new(NoComputedFunctions),
         }
     }
 
@@ -49,7 +61,7 @@ impl StoreResolver {
     /// created
     pub async fn at_block(
         logger: &Logger,
-        store: Arc<impl Store + SubgraphDeploymentStore>,
+        store: Arc<impl EntityStore + SubgraphDeploymentStore>,
         bc: BlockConstraint,
         deployment: SubgraphDeploymentId,
     ) -> Result<Self, QueryExecutionError> {
@@ -66,10 +78,19 @@ impl StoreResolver {
             store: store.query_store(false),
             block_ptr: Some(block_ptr),
             deployment,
+            has_indexing_errors: false,
+            earliest_block: 0,
+            computed: Arc::new(NoComputedFunctions),
         };
         Ok(resolver)
     }
 
+    /// Evaluate the `@computed` fields of entities with `computed`. Without
+    /// it, queries for computed fields fail
+    pub fn computed_functions(self, computed: Arc<dyn ComputedFunctions>) -> Self {
+        StoreResolver { computed, ..self }
+    }
+
     pub fn block_number(&self) -> BlockNumber {
         self.block_ptr
             .map(|ptr| ptr.number as BlockNumber)
@@ -77,7 +98,7 @@ impl StoreResolver {
     }
 
     fn locate_block(
-        store: &(impl Store + SubgraphDeploymentStore),
+        store: &(impl EntityStore + SubgraphDeploymentStore),
         bc: BlockConstraint,
         subgraph: SubgraphDeploymentId,
     ) -> Result<EthereumBlockPointer, QueryExecutionError> {
@@ -194,6 +215,14 @@ impl StoreResolver {
                 "deployment".to_string(),
                 q::Value::String(self.deployment.to_string()),
             );
+            map.insert(
+                "hasIndexingErrors".to_string(),
+                q::Value::Boolean(self.has_indexing_errors),
+            );
+            map.insert(
+                "earliestBlockNumber".to_string(),
+                q::Value::Int(self.earliest_block.into()),
+            );
             return Ok((None, Some(q::Value::Object(map))));
         }
         return Ok((prefetched_object, None));
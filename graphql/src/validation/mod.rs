@@ -0,0 +1,499 @@
+//! Validation of GraphQL documents against the rules in section 5 of the
+//! GraphQL spec. Validation runs before a query is executed and reports
+//! all violations at once, instead of letting execution fail on the first
+//! one, or not at all.
+//!
+//! Each rule can be enforced, which rejects documents that violate it,
+//! merely warned about in the logs, or turned off. Rules that reject
+//! documents that would otherwise execute fine default to `warn` so that
+//! existing clients keep working; the mode of each rule can be changed
+//! with `GRAPH_GRAPHQL_VALIDATION`, for example
+//! `GRAPH_GRAPHQL_VALIDATION=no_unused_fragments=enforce,unique_argument_names=off`.
+//!
+//! Rules that execution already checks, like whether the selected fields
+//! and the types in type conditions exist, are not repeated here
+use graphql_parser::{query as q, schema as s, Pos};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+use graph::prelude::{anyhow, QueryExecutionError};
+
+mod rules;
+
+use rules::Context;
+
+lazy_static! {
+    static ref RULE_MODES: HashMap<&'static str, RuleMode> =
+        rule_modes(env::var("GRAPH_GRAPHQL_VALIDATION").ok().as_deref()).unwrap_or_else(|e| {
+            panic!("failed to parse env var GRAPH_GRAPHQL_VALIDATION: {}", e)
+        });
+}
+
+/// What to do when a document violates a rule
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RuleMode {
+    /// Reject the document
+    Enforce,
+    /// Execute the document, but log the violation
+    Warn,
+    /// Do not check the rule
+    Off,
+}
+
+impl RuleMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleMode::Enforce => "enforce",
+            RuleMode::Warn => "warn",
+            RuleMode::Off => "off",
+        }
+    }
+}
+
+impl fmt::Display for RuleMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for RuleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enforce" => Ok(RuleMode::Enforce),
+            "warn" => Ok(RuleMode::Warn),
+            "off" => Ok(RuleMode::Off),
+            _ => Err(anyhow::anyhow!(
+                "invalid rule mode `{}`, it must be one of `enforce`, `warn` or `off`",
+                s
+            )),
+        }
+    }
+}
+
+/// A validation rule
+pub struct Rule {
+    /// The name under which the rule is configured and reported
+    pub name: &'static str,
+    /// The mode of the rule unless `GRAPH_GRAPHQL_VALIDATION` sets it
+    pub default: RuleMode,
+    /// Add the position and message of every violation of the rule
+    check: fn(&Context, &mut Vec<(Pos, String)>),
+}
+
+/// All validation rules. The rules that default to `enforce` only reject
+/// documents that execution would also reject, or mishandle badly, for
+/// example by recursing forever on a fragment that spreads itself
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "lone_anonymous_operation",
+        default: RuleMode::Enforce,
+        check: rules::lone_anonymous_operation,
+    },
+    Rule {
+        name: "unique_operation_names",
+        default: RuleMode::Enforce,
+        check: rules::unique_operation_names,
+    },
+    Rule {
+        name: "known_fragment_names",
+        default: RuleMode::Enforce,
+        check: rules::known_fragment_names,
+    },
+    Rule {
+        name: "no_fragment_cycles",
+        default: RuleMode::Enforce,
+        check: rules::no_fragment_cycles,
+    },
+    Rule {
+        name: "unique_fragment_names",
+        default: RuleMode::Warn,
+        check: rules::unique_fragment_names,
+    },
+    Rule {
+        name: "no_unused_fragments",
+        default: RuleMode::Warn,
+        check: rules::no_unused_fragments,
+    },
+    Rule {
+        name: "unique_variable_names",
+        default: RuleMode::Warn,
+        check: rules::unique_variable_names,
+    },
+    Rule {
+        name: "no_undefined_variables",
+        default: RuleMode::Warn,
+        check: rules::no_undefined_variables,
+    },
+    Rule {
+        name: "no_unused_variables",
+        default: RuleMode::Warn,
+        check: rules::no_unused_variables,
+    },
+    Rule {
+        name: "unique_argument_names",
+        default: RuleMode::Warn,
+        check: rules::unique_argument_names,
+    },
+    Rule {
+        name: "overlapping_fields_can_be_merged",
+        default: RuleMode::Warn,
+        check: rules::overlapping_fields_can_be_merged,
+    },
+];
+
+/// Parse a list like `rule1=enforce,rule2=off` into the mode for every
+/// rule; rules that are not in the list get their default mode
+pub fn rule_modes(config: Option<&str>) -> Result<HashMap<&'static str, RuleMode>, anyhow::Error> {
+    let mut modes: HashMap<_, _> = RULES.iter().map(|rule| (rule.name, rule.default)).collect();
+
+    for entry in config.unwrap_or("").split(',') {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let mut parts = entry.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let mode = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("`{}` must have the form `rule=mode`", entry))?
+            .trim()
+            .parse()?;
+        let rule = RULES
+            .iter()
+            .find(|rule| rule.name == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown validation rule `{}`", name))?;
+        modes.insert(rule.name, mode);
+    }
+    Ok(modes)
+}
+
+/// A place where a document violates a rule
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: &'static str,
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl From<Violation> for QueryExecutionError {
+    fn from(violation: Violation) -> Self {
+        QueryExecutionError::ValidationError(violation.pos, violation.rule, violation.message)
+    }
+}
+
+/// The violations of a document, split by the mode of the rule they
+/// violate
+#[derive(Clone, Debug, Default)]
+pub struct Validation {
+    /// Violations of enforced rules
+    pub errors: Vec<Violation>,
+    /// Violations of rules that only cause warnings
+    pub warnings: Vec<Violation>,
+}
+
+/// Validate `document` against the rules with the modes set in the
+/// environment
+pub fn validate(schema: &s::Document, document: &q::Document) -> Validation {
+    validate_with(&*RULE_MODES, schema, document)
+}
+
+/// Validate `document` against the rules with the given `modes`
+pub fn validate_with(
+    modes: &HashMap<&'static str, RuleMode>,
+    schema: &s::Document,
+    document: &q::Document,
+) -> Validation {
+    let ctx = Context::new(schema, document);
+    let mut validation = Validation::default();
+
+    for rule in RULES {
+        let mode = modes.get(rule.name).copied().unwrap_or(rule.default);
+        if mode == RuleMode::Off {
+            continue;
+        }
+        let mut found = Vec::new();
+        (rule.check)(&ctx, &mut found);
+        let violations = found.into_iter().map(|(pos, message)| Violation {
+            rule: rule.name,
+            pos,
+            message,
+        });
+        match mode {
+            RuleMode::Enforce => validation.errors.extend(violations),
+            RuleMode::Warn => validation.warnings.extend(violations),
+            RuleMode::Off => unreachable!("rules that are off are not checked"),
+        }
+    }
+    validation
+}
+
+#[cfg(test)]
+mod tests {
+    use graphql_parser::{parse_query, parse_schema};
+
+    use super::*;
+
+    const SCHEMA: &str = "
+type Query {
+    user(id: ID): User
+    users(first: Int): [User]
+    nodes: [Node]
+}
+
+interface Node {
+    id: ID!
+}
+
+type User implements Node {
+    id: ID!
+    name: String
+    friends(first: Int): [User]
+}
+
+type Pet implements Node {
+    id: ID!
+    name: String
+    owner: User
+}
+";
+
+    fn violations(query: &str) -> Vec<(&'static str, String)> {
+        let schema = parse_schema(SCHEMA).expect("test schema is valid");
+        let query = parse_query(query).expect("test query parses");
+        let enforce_all: HashMap<_, _> = RULES
+            .iter()
+            .map(|rule| (rule.name, RuleMode::Enforce))
+            .collect();
+        let validation = validate_with(&enforce_all, &schema, &query);
+        assert!(validation.warnings.is_empty());
+        validation
+            .errors
+            .into_iter()
+            .map(|v| (v.rule, v.message))
+            .collect()
+    }
+
+    #[test]
+    fn valid_documents() {
+        const VALID: &[&str] = &[
+            "{ users { id name } }",
+            "query Q($id: ID) { user(id: $id) { id } }",
+            "query A { users { id } } query B { users { id } }",
+            "{ users { ...F } } fragment F on User { id friends { ...G } } fragment G on User { id }",
+            "query Q($skip: Boolean!) { users { id @skip(if: $skip) } }",
+            "query Q($n: Int) { users { ...F } } fragment F on User { friends(first: $n) { id } }",
+            "{ users { id id } users { name } }",
+            "{ users(first: 1) { id } users(first: 1) { name } }",
+            "{ nodes { ... on User { name: id } ... on Pet { name } } }",
+            "{ nodes { ... on User { owner: friends { id } } ... on Pet { owner { name } } } }",
+        ];
+
+        for query in VALID {
+            assert_eq!(Vec::<(&str, String)>::new(), violations(query), "{}", query);
+        }
+    }
+
+    #[test]
+    fn invalid_documents() {
+        const INVALID: &[(&str, &[(&str, &str)])] = &[
+            (
+                "{ users { id } } query Q { users { id } }",
+                &[(
+                    "lone_anonymous_operation",
+                    "an anonymous operation must be the only operation in the document",
+                )],
+            ),
+            (
+                "query Q { users { id } } query Q { user(id: \"1\") { id } }",
+                &[(
+                    "unique_operation_names",
+                    "there can be only one operation named `Q`",
+                )],
+            ),
+            (
+                "{ users { ...Missing } }",
+                &[("known_fragment_names", "fragment `Missing` is not defined")],
+            ),
+            (
+                "{ users { ...A } } fragment A on User { id friends { ...A } }",
+                &[("no_fragment_cycles", "fragment `A` can not spread itself")],
+            ),
+            (
+                "{ users { ...A } } \
+                 fragment A on User { friends { ...B } } \
+                 fragment B on User { friends { ...A } }",
+                &[(
+                    "no_fragment_cycles",
+                    "fragment `A` can not spread itself via `B`",
+                )],
+            ),
+            (
+                "{ users { ...A } } fragment A on User { id } fragment A on User { name }",
+                &[(
+                    "unique_fragment_names",
+                    "there can be only one fragment named `A`",
+                )],
+            ),
+            (
+                "{ users { id } } fragment A on User { id }",
+                &[("no_unused_fragments", "fragment `A` is never used")],
+            ),
+            (
+                "query Q($id: ID, $id: ID) { user(id: $id) { id } }",
+                &[(
+                    "unique_variable_names",
+                    "there can be only one variable named `$id`",
+                )],
+            ),
+            (
+                "query Q { user(id: $id) { id } }",
+                &[(
+                    "no_undefined_variables",
+                    "variable `$id` is not defined by operation `Q`",
+                )],
+            ),
+            (
+                "{ users { ...F } } fragment F on User { friends(first: $n) { id } }",
+                &[(
+                    "no_undefined_variables",
+                    "variable `$n` is not defined by the anonymous operation",
+                )],
+            ),
+            (
+                "query Q($id: ID, $n: Int) { user(id: $id) { id } }",
+                &[(
+                    "no_unused_variables",
+                    "variable `$n` is never used in operation `Q`",
+                )],
+            ),
+            (
+                "{ users(first: 1, first: 2) { id } }",
+                &[(
+                    "unique_argument_names",
+                    "there can be only one argument named `first`",
+                )],
+            ),
+            (
+                "{ users { name: id name } }",
+                &[(
+                    "overlapping_fields_can_be_merged",
+                    "`name` selects both `id` and `name`; use different aliases on the fields",
+                )],
+            ),
+            (
+                "{ users(first: 1) { id } users(first: 2) { id } }",
+                &[(
+                    "overlapping_fields_can_be_merged",
+                    "`users` selects the field `users` with different arguments; use \
+                     different aliases on the fields",
+                )],
+            ),
+            (
+                "{ users { friends { id } } users { friends { id: name } } }",
+                &[(
+                    "overlapping_fields_can_be_merged",
+                    "`id` selects both `id` and `name`; use different aliases on the fields",
+                )],
+            ),
+            (
+                "{ users { ...A ...B } } \
+                 fragment A on User { x: id } \
+                 fragment B on User { x: name }",
+                &[(
+                    "overlapping_fields_can_be_merged",
+                    "`x` selects both `id` and `name`; use different aliases on the fields",
+                )],
+            ),
+            (
+                "{ nodes { ... on Node { x: id } ... on User { x: name } } }",
+                &[(
+                    "overlapping_fields_can_be_merged",
+                    "`x` selects both `id` and `name`; use different aliases on the fields",
+                )],
+            ),
+            (
+                "{ users { ...Missing } } query Q($a: Int) { users(first: $b) { id } }",
+                &[
+                    (
+                        "lone_anonymous_operation",
+                        "an anonymous operation must be the only operation in the document",
+                    ),
+                    ("known_fragment_names", "fragment `Missing` is not defined"),
+                    (
+                        "no_undefined_variables",
+                        "variable `$b` is not defined by operation `Q`",
+                    ),
+                    (
+                        "no_unused_variables",
+                        "variable `$a` is never used in operation `Q`",
+                    ),
+                ],
+            ),
+        ];
+
+        for (query, expected) in INVALID {
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(rule, message)| (*rule, message.to_string()))
+                .collect();
+            assert_eq!(expected, violations(query), "{}", query);
+        }
+    }
+
+    #[test]
+    fn violation_position() {
+        let schema = parse_schema(SCHEMA).unwrap();
+        let query = parse_query("{\n  users {\n    ...Missing\n  }\n}").unwrap();
+        let validation = validate_with(&rule_modes(None).unwrap(), &schema, &query);
+        assert_eq!(
+            vec![Violation {
+                rule: "known_fragment_names",
+                pos: Pos { line: 3, column: 5 },
+                message: "fragment `Missing` is not defined".to_owned()
+            }],
+            validation.errors
+        );
+    }
+
+    #[test]
+    fn modes() {
+        let schema = parse_schema(SCHEMA).unwrap();
+        let query = parse_query("{ users { ...Missing } } fragment F on User { id }").unwrap();
+
+        let modes = rule_modes(None).unwrap();
+        let validation = validate_with(&modes, &schema, &query);
+        assert_eq!(
+            vec!["known_fragment_names"],
+            validation.errors.iter().map(|v| v.rule).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["no_unused_fragments"],
+            validation
+                .warnings
+                .iter()
+                .map(|v| v.rule)
+                .collect::<Vec<_>>()
+        );
+
+        let modes = rule_modes(Some(
+            "no_unused_fragments=enforce, known_fragment_names=off",
+        ))
+        .unwrap();
+        assert_eq!(Some(&RuleMode::Warn), modes.get("no_unused_variables"));
+        let validation = validate_with(&modes, &schema, &query);
+        assert_eq!(
+            vec!["no_unused_fragments"],
+            validation.errors.iter().map(|v| v.rule).collect::<Vec<_>>()
+        );
+        assert!(validation.warnings.is_empty());
+
+        assert!(rule_modes(Some("no_such_rule=warn")).is_err());
+        assert!(rule_modes(Some("no_unused_fragments")).is_err());
+        assert!(rule_modes(Some("no_unused_fragments=sometimes")).is_err());
+    }
+}
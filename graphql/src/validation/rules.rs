@@ -0,0 +1,565 @@
+//! The checks for the individual validation rules. Each check adds the
+//! position and a message for every violation it finds
+use graphql_parser::{query as q, schema as s, Pos};
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+
+use crate::schema::ast as sast;
+
+/// The parts of a document that the rules need
+pub struct Context<'a> {
+    schema: &'a s::Document,
+    operations: Vec<&'a q::OperationDefinition>,
+    /// All fragment definitions, in the order of the document
+    fragment_list: Vec<&'a q::FragmentDefinition>,
+    /// The first definition of each fragment
+    fragments: HashMap<&'a str, &'a q::FragmentDefinition>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(schema: &'a s::Document, document: &'a q::Document) -> Self {
+        let mut operations = Vec::new();
+        let mut fragment_list = Vec::new();
+        let mut fragments = HashMap::new();
+        for defn in &document.definitions {
+            match defn {
+                q::Definition::Operation(op) => operations.push(op),
+                q::Definition::Fragment(frag) => {
+                    fragment_list.push(frag);
+                    fragments.entry(frag.name.as_str()).or_insert(frag);
+                }
+            }
+        }
+        Context {
+            schema,
+            operations,
+            fragment_list,
+            fragments,
+        }
+    }
+
+    /// The names of the fragments that `selection_set` spreads, directly or
+    /// through other fragments; fragments that are not defined are left out
+    fn reachable_fragments(&self, selection_set: &'a q::SelectionSet) -> Vec<&'a str> {
+        let mut reachable = Vec::new();
+        let mut todo = spreads(selection_set);
+        while let Some(spread) = todo.pop() {
+            let name = spread.fragment_name.as_str();
+            if reachable.contains(&name) {
+                continue;
+            }
+            if let Some(frag) = self.fragments.get(name) {
+                reachable.push(name);
+                todo.extend(spreads(&frag.selection_set));
+            }
+        }
+        reachable
+    }
+
+    /// Whether `a` and `b` are different object types. Fields in fragments
+    /// on such types are never selected for the same object
+    fn exclusive(&self, a: Option<&str>, b: Option<&str>) -> bool {
+        let is_object = |name: &str| {
+            matches!(
+                sast::get_named_type(self.schema, &name.to_owned()),
+                Some(s::TypeDefinition::Object(_))
+            )
+        };
+        match (a, b) {
+            (Some(a), Some(b)) => a != b && is_object(a) && is_object(b),
+            _ => false,
+        }
+    }
+}
+
+/// The position, name, variable definitions, directives and selection set
+/// of an operation
+fn operation_parts(
+    op: &q::OperationDefinition,
+) -> (
+    Pos,
+    Option<&q::Name>,
+    &[q::VariableDefinition],
+    &[q::Directive],
+    &q::SelectionSet,
+) {
+    match op {
+        q::OperationDefinition::SelectionSet(set) => (set.span.0, None, &[], &[], set),
+        q::OperationDefinition::Query(op) => (
+            op.position,
+            op.name.as_ref(),
+            &op.variable_definitions,
+            &op.directives,
+            &op.selection_set,
+        ),
+        q::OperationDefinition::Mutation(op) => (
+            op.position,
+            op.name.as_ref(),
+            &op.variable_definitions,
+            &op.directives,
+            &op.selection_set,
+        ),
+        q::OperationDefinition::Subscription(op) => (
+            op.position,
+            op.name.as_ref(),
+            &op.variable_definitions,
+            &op.directives,
+            &op.selection_set,
+        ),
+    }
+}
+
+fn describe_operation(name: Option<&q::Name>) -> String {
+    match name {
+        Some(name) => format!("operation `{}`", name),
+        None => "the anonymous operation".to_owned(),
+    }
+}
+
+/// Call `f` for every selection in `selection_set` and in the selection
+/// sets nested in it. Fragment spreads are not followed
+fn walk<'a>(selection_set: &'a q::SelectionSet, f: &mut impl FnMut(&'a q::Selection)) {
+    for selection in &selection_set.items {
+        f(selection);
+        match selection {
+            q::Selection::Field(field) => walk(&field.selection_set, f),
+            q::Selection::InlineFragment(frag) => walk(&frag.selection_set, f),
+            q::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+/// The fragment spreads in `selection_set`
+fn spreads(selection_set: &q::SelectionSet) -> Vec<&q::FragmentSpread> {
+    let mut spreads = Vec::new();
+    walk(selection_set, &mut |selection| {
+        if let q::Selection::FragmentSpread(spread) = selection {
+            spreads.push(spread)
+        }
+    });
+    spreads
+}
+
+/// Add the names of the variables that `value` uses to `vars`
+fn value_variables<'a>(value: &'a q::Value, pos: Pos, vars: &mut Vec<(&'a str, Pos)>) {
+    match value {
+        q::Value::Variable(name) => vars.push((name.as_str(), pos)),
+        q::Value::List(values) => values
+            .iter()
+            .for_each(|value| value_variables(value, pos, vars)),
+        q::Value::Object(values) => values
+            .values()
+            .for_each(|value| value_variables(value, pos, vars)),
+        _ => {}
+    }
+}
+
+fn directive_variables<'a>(directives: &'a [q::Directive], vars: &mut Vec<(&'a str, Pos)>) {
+    for directive in directives {
+        for (_, value) in &directive.arguments {
+            value_variables(value, directive.position, vars);
+        }
+    }
+}
+
+/// The variables that `selection_set` uses, with the position of the
+/// field or directive that uses them
+fn selection_variables<'a>(selection_set: &'a q::SelectionSet, vars: &mut Vec<(&'a str, Pos)>) {
+    walk(selection_set, &mut |selection| match selection {
+        q::Selection::Field(field) => {
+            for (_, value) in &field.arguments {
+                value_variables(value, field.position, vars);
+            }
+            directive_variables(&field.directives, vars);
+        }
+        q::Selection::FragmentSpread(spread) => directive_variables(&spread.directives, vars),
+        q::Selection::InlineFragment(frag) => directive_variables(&frag.directives, vars),
+    })
+}
+
+/// The variables that `op` uses, directly or in the fragments it spreads
+fn operation_variables<'a>(
+    ctx: &Context<'a>,
+    op: &'a q::OperationDefinition,
+) -> Vec<(&'a str, Pos)> {
+    let (_, _, _, directives, selection_set) = operation_parts(op);
+    let mut vars = Vec::new();
+    directive_variables(directives, &mut vars);
+    selection_variables(selection_set, &mut vars);
+    for name in ctx.reachable_fragments(selection_set) {
+        let frag = ctx.fragments[name];
+        directive_variables(&frag.directives, &mut vars);
+        selection_variables(&frag.selection_set, &mut vars);
+    }
+    vars
+}
+
+pub fn lone_anonymous_operation(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    if ctx.operations.len() < 2 {
+        return;
+    }
+    for op in &ctx.operations {
+        let (pos, name, _, _, _) = operation_parts(op);
+        if name.is_none() {
+            violations.push((
+                pos,
+                "an anonymous operation must be the only operation in the document".to_owned(),
+            ));
+        }
+    }
+}
+
+pub fn unique_operation_names(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    let mut seen = HashSet::new();
+    for op in &ctx.operations {
+        if let (pos, Some(name), _, _, _) = operation_parts(op) {
+            if !seen.insert(name) {
+                violations.push((
+                    pos,
+                    format!("there can be only one operation named `{}`", name),
+                ));
+            }
+        }
+    }
+}
+
+pub fn unique_fragment_names(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    let mut seen = HashSet::new();
+    for frag in &ctx.fragment_list {
+        if !seen.insert(&frag.name) {
+            violations.push((
+                frag.position,
+                format!("there can be only one fragment named `{}`", frag.name),
+            ));
+        }
+    }
+}
+
+pub fn known_fragment_names(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    let selection_sets = ctx
+        .operations
+        .iter()
+        .map(|op| operation_parts(op).4)
+        .chain(ctx.fragment_list.iter().map(|frag| &frag.selection_set));
+    for selection_set in selection_sets {
+        for spread in spreads(selection_set) {
+            if !ctx.fragments.contains_key(spread.fragment_name.as_str()) {
+                violations.push((
+                    spread.position,
+                    format!("fragment `{}` is not defined", spread.fragment_name),
+                ));
+            }
+        }
+    }
+}
+
+pub fn no_unused_fragments(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    let used: HashSet<_> = ctx
+        .operations
+        .iter()
+        .flat_map(|op| ctx.reachable_fragments(operation_parts(op).4))
+        .collect();
+    for frag in &ctx.fragment_list {
+        if !used.contains(frag.name.as_str()) {
+            violations.push((
+                frag.position,
+                format!("fragment `{}` is never used", frag.name),
+            ));
+        }
+    }
+}
+
+pub fn no_fragment_cycles(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    fn detect<'a>(
+        ctx: &Context<'a>,
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        violations: &mut Vec<(Pos, String)>,
+    ) {
+        if done.contains(name) {
+            return;
+        }
+        let frag = match ctx.fragments.get(name) {
+            Some(frag) => frag,
+            None => return,
+        };
+        path.push(name);
+        for spread in spreads(&frag.selection_set) {
+            let target = spread.fragment_name.as_str();
+            match path.iter().position(|step| *step == target) {
+                Some(start) => {
+                    let via = path[start + 1..]
+                        .iter()
+                        .map(|step| format!("`{}`", step))
+                        .collect::<Vec<_>>();
+                    let message = if via.is_empty() {
+                        format!("fragment `{}` can not spread itself", target)
+                    } else {
+                        format!(
+                            "fragment `{}` can not spread itself via {}",
+                            target,
+                            via.join(", ")
+                        )
+                    };
+                    violations.push((spread.position, message));
+                }
+                None => detect(ctx, target, path, done, violations),
+            }
+        }
+        path.pop();
+        done.insert(name);
+    }
+
+    let mut done = HashSet::new();
+    for frag in &ctx.fragment_list {
+        detect(ctx, &frag.name, &mut Vec::new(), &mut done, violations);
+    }
+}
+
+pub fn unique_variable_names(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    for op in &ctx.operations {
+        let (_, _, var_defs, _, _) = operation_parts(op);
+        let mut seen = HashSet::new();
+        for var_def in var_defs {
+            if !seen.insert(&var_def.name) {
+                violations.push((
+                    var_def.position,
+                    format!("there can be only one variable named `${}`", var_def.name),
+                ));
+            }
+        }
+    }
+}
+
+pub fn no_undefined_variables(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    for op in &ctx.operations {
+        let (_, name, var_defs, _, _) = operation_parts(op);
+        let mut reported = HashSet::new();
+        for (var, pos) in operation_variables(ctx, op) {
+            if !var_defs.iter().any(|var_def| var_def.name == var) && reported.insert(var) {
+                violations.push((
+                    pos,
+                    format!(
+                        "variable `${}` is not defined by {}",
+                        var,
+                        describe_operation(name)
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+pub fn no_unused_variables(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    for op in &ctx.operations {
+        let (_, name, var_defs, _, _) = operation_parts(op);
+        let used: HashSet<_> = operation_variables(ctx, op)
+            .into_iter()
+            .map(|(var, _)| var)
+            .collect();
+        for var_def in var_defs {
+            if !used.contains(var_def.name.as_str()) {
+                violations.push((
+                    var_def.position,
+                    format!(
+                        "variable `${}` is never used in {}",
+                        var_def.name,
+                        describe_operation(name)
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+pub fn unique_argument_names(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    fn check(pos: Pos, arguments: &[(q::Name, q::Value)], violations: &mut Vec<(Pos, String)>) {
+        let mut seen = HashSet::new();
+        for (name, _) in arguments {
+            if !seen.insert(name) {
+                violations.push((
+                    pos,
+                    format!("there can be only one argument named `{}`", name),
+                ));
+            }
+        }
+    }
+    fn check_directives(directives: &[q::Directive], violations: &mut Vec<(Pos, String)>) {
+        for directive in directives {
+            check(directive.position, &directive.arguments, violations);
+        }
+    }
+
+    let selection_sets = ctx
+        .operations
+        .iter()
+        .map(|op| {
+            let (_, _, _, directives, selection_set) = operation_parts(op);
+            (directives, selection_set)
+        })
+        .chain(
+            ctx.fragment_list
+                .iter()
+                .map(|frag| (frag.directives.as_slice(), &frag.selection_set)),
+        );
+    for (directives, selection_set) in selection_sets {
+        check_directives(directives, violations);
+        walk(selection_set, &mut |selection| match selection {
+            q::Selection::Field(field) => {
+                check(field.position, &field.arguments, violations);
+                check_directives(&field.directives, violations);
+            }
+            q::Selection::FragmentSpread(spread) => {
+                check_directives(&spread.directives, violations)
+            }
+            q::Selection::InlineFragment(frag) => check_directives(&frag.directives, violations),
+        });
+    }
+}
+
+/// A field together with the type condition of the fragment it appears
+/// in, or `None` if it is selected directly from its parent
+struct ScopedField<'a> {
+    type_condition: Option<&'a str>,
+    field: &'a q::Field,
+}
+
+/// Collect the fields that `selection_set` selects for the same object,
+/// including the ones from fragments. Each fragment is only followed once
+/// so that fragment cycles do not make us recurse forever
+fn collect_fields<'a>(
+    ctx: &Context<'a>,
+    type_condition: Option<&'a str>,
+    selection_set: &'a q::SelectionSet,
+    visited: &mut HashSet<&'a str>,
+    fields: &mut Vec<ScopedField<'a>>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            q::Selection::Field(field) => fields.push(ScopedField {
+                type_condition,
+                field,
+            }),
+            q::Selection::InlineFragment(frag) => {
+                let type_condition = match &frag.type_condition {
+                    Some(q::TypeCondition::On(name)) => Some(name.as_str()),
+                    None => type_condition,
+                };
+                collect_fields(ctx, type_condition, &frag.selection_set, visited, fields)
+            }
+            q::Selection::FragmentSpread(spread) => {
+                let name = spread.fragment_name.as_str();
+                if let Some(frag) = ctx.fragments.get(name) {
+                    if visited.insert(name) {
+                        let q::TypeCondition::On(type_condition) = &frag.type_condition;
+                        collect_fields(
+                            ctx,
+                            Some(type_condition.as_str()),
+                            &frag.selection_set,
+                            visited,
+                            fields,
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn sorted_arguments(field: &q::Field) -> Vec<&(q::Name, q::Value)> {
+    let mut arguments: Vec<_> = field.arguments.iter().collect();
+    arguments.sort_by(|a, b| a.0.cmp(&b.0));
+    arguments
+}
+
+/// Check that all fields with the same response key in `selection_sets`,
+/// which are all selected for the same object, select the same field with
+/// the same arguments, unless they can never apply to the same object
+fn check_merge<'a>(
+    ctx: &Context<'a>,
+    selection_sets: Vec<&'a q::SelectionSet>,
+    reported: &mut HashSet<(usize, usize)>,
+    violations: &mut Vec<(Pos, String)>,
+) {
+    let mut fields = Vec::new();
+    let mut visited = HashSet::new();
+    for selection_set in selection_sets {
+        collect_fields(ctx, None, selection_set, &mut visited, &mut fields);
+    }
+
+    let mut by_key: IndexMap<&str, Vec<ScopedField>> = IndexMap::new();
+    for field in fields {
+        let f = field.field;
+        let key = f.alias.as_ref().unwrap_or(&f.name);
+        by_key
+            .entry(key.as_str())
+            .or_insert_with(Vec::new)
+            .push(field);
+    }
+
+    for (key, fields) in by_key {
+        let any_exclusive = fields.iter().enumerate().any(|(i, other)| {
+            fields[..i]
+                .iter()
+                .any(|field| ctx.exclusive(field.type_condition, other.type_condition))
+        });
+        for (i, other) in fields.iter().enumerate().skip(1) {
+            for field in &fields[..i] {
+                if ctx.exclusive(field.type_condition, other.type_condition) {
+                    continue;
+                }
+                let message = if field.field.name != other.field.name {
+                    format!(
+                        "`{}` selects both `{}` and `{}`; use different aliases on the fields",
+                        key, field.field.name, other.field.name
+                    )
+                } else if sorted_arguments(field.field) != sorted_arguments(other.field) {
+                    format!(
+                        "`{}` selects the field `{}` with different arguments; use different \
+                         aliases on the fields",
+                        key, other.field.name
+                    )
+                } else {
+                    continue;
+                };
+                let pos = other.field.position;
+                if reported.insert((pos.line, pos.column)) {
+                    violations.push((pos, message));
+                }
+                break;
+            }
+        }
+
+        // The selections of fields that are merged are merged, too
+        let nested = fields
+            .iter()
+            .map(|field| &field.field.selection_set)
+            .filter(|selection_set| !selection_set.items.is_empty());
+        if any_exclusive {
+            for selection_set in nested {
+                check_merge(ctx, vec![selection_set], reported, violations);
+            }
+        } else {
+            let nested: Vec<_> = nested.collect();
+            if !nested.is_empty() {
+                check_merge(ctx, nested, reported, violations);
+            }
+        }
+    }
+}
+
+pub fn overlapping_fields_can_be_merged(ctx: &Context, violations: &mut Vec<(Pos, String)>) {
+    // Merging the fields of a fragment that spreads itself inside one of
+    // its fields would never end; `no_fragment_cycles` reports these
+    let mut cycles = Vec::new();
+    no_fragment_cycles(ctx, &mut cycles);
+    if !cycles.is_empty() {
+        return;
+    }
+
+    let mut reported = HashSet::new();
+    for op in &ctx.operations {
+        check_merge(ctx, vec![operation_parts(op).4], &mut reported, violations);
+    }
+}
@@ -52,6 +52,12 @@ impl MaybeCoercible<ScalarType> for Value {
             ("BigInt", Value::Int(n)) => {
                 Ok(Value::String(n.as_i64().ok_or(Value::Int(n))?.to_string()))
             }
+            // Timestamps are ISO-8601 strings or microseconds since the
+            // epoch; `Timestamp::from_str` accepts both
+            ("Timestamp", v @ Value::String(_)) => Ok(v),
+            ("Timestamp", Value::Int(n)) => {
+                Ok(Value::String(n.as_i64().ok_or(Value::Int(n))?.to_string()))
+            }
             (_, v) => Err(v),
         }
     }
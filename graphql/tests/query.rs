@@ -3,16 +3,19 @@ extern crate pretty_assertions;
 
 use graphql_parser::{query as q, Pos};
 use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use graph::data::graphql::computed::{ComputedField, ComputedFunctions, ComputedQuery};
 use graph::data::query::CacheStatus;
+use graph::data::subgraph::schema::SubgraphError;
 use graph::prelude::{
     async_trait, futures03::stream::StreamExt, futures03::FutureExt, futures03::TryFutureExt, o,
-    slog, tokio, ApiSchema, DeploymentState, Entity, EntityKey, EntityOperation,
-    EthereumBlockPointer, FutureExtension, GraphQlRunner as _, Logger, Query, QueryError,
-    QueryExecutionError, QueryLoadManager, QueryResult, QueryVariables, Schema, Store,
+    slog, tokio, ApiSchema, DeploymentState, DeploymentStore, Entity, EntityKey, EntityOperation,
+    EntityStore as _, EthereumBlockPointer, FutureExtension, GraphQlRunner as _, Logger, Query,
+    QueryError, QueryExecutionError, QueryLoadManager, QueryResult, QueryVariables, Schema, Store,
     SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphDeploymentStore, SubgraphManifest,
     Subscription, SubscriptionError, Value, BLOCK_NUMBER_MAX,
 };
@@ -45,6 +48,7 @@ fn test_schema(id: SubgraphDeploymentId) -> Schema {
                 name: String!
                 mainBand: Band
                 bands: [Band!]!
+                favoriteSongs: [Song]
                 writtenSongs: [Song]! @derivedFrom(field: \"writtenBy\")
             }
 
@@ -66,6 +70,7 @@ fn test_schema(id: SubgraphDeploymentId) -> Schema {
                 id: ID!
                 song: Song @derivedFrom(field: \"id\")
                 played: Int!
+                playedTwice: Int! @computed(from: [\"played\"])
             }
             ",
         id,
@@ -93,6 +98,9 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         schema: schema.clone(),
         data_sources: vec![],
         graft: None,
+        history_blocks: None,
+        strict_bytes: false,
+        computed: None,
         templates: vec![],
     };
 
@@ -113,6 +121,10 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
                 "bands",
                 Value::List(vec![Value::from("b1"), Value::from("b2")]),
             ),
+            (
+                "favoriteSongs",
+                Value::List(vec![Value::from("s1"), Value::from("s4")]),
+            ),
         ]),
         Entity::from(vec![
             ("__typename", Value::from("Musician")),
@@ -203,7 +215,7 @@ fn insert_test_entities(store: &impl Store, id: SubgraphDeploymentId) {
         let insert_ops = entities.into_iter().map(|data| EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: id.clone(),
-                entity_type: data["__typename"].clone().as_string().unwrap(),
+                entity_type: data["__typename"].clone().as_string().unwrap().into(),
                 entity_id: data["id"].clone().as_string().unwrap(),
             },
             data,
@@ -231,17 +243,20 @@ async fn execute_query_document_with_variables(
     query: q::Document,
     variables: Option<QueryVariables>,
 ) -> QueryResult {
-    let runner = Arc::new(GraphQlRunner::new(
-        &*LOGGER,
-        STORE.clone(),
-        LOAD_MANAGER.clone(),
-    ));
+    let runner = Arc::new(
+        GraphQlRunner::new(&*LOGGER, STORE.clone(), LOAD_MANAGER.clone())
+            .computed_functions(Arc::new(TestComputedFunctions)),
+    );
     let query = Query::new(Arc::new(api_test_schema(id)), query, variables, None);
     let state = DeploymentState {
         id: query.schema.id().clone(),
         reorg_count: 0,
         max_reorg_depth: 0,
         latest_ethereum_block_number: BLOCK_NUMBER_MAX,
+        fatal_error: None,
+        has_non_fatal_errors: false,
+        earliest_block_number: 0,
+        flag: None,
     };
 
     runner
@@ -256,11 +271,10 @@ async fn execute_query_document_with_state(
     query: q::Document,
     state: DeploymentState,
 ) -> QueryResult {
-    let runner = Arc::new(GraphQlRunner::new(
-        &*LOGGER,
-        STORE.clone(),
-        LOAD_MANAGER.clone(),
-    ));
+    let runner = Arc::new(
+        GraphQlRunner::new(&*LOGGER, STORE.clone(), LOAD_MANAGER.clone())
+            .computed_functions(Arc::new(TestComputedFunctions)),
+    );
     let query = Query::new(Arc::new(api_test_schema(id)), query, None, None);
 
     graph::prelude::futures03::executor::block_on(
@@ -270,6 +284,30 @@ async fn execute_query_document_with_state(
     .clone()
 }
 
+/// The functions of the computed fields in the test schema, in place of
+/// the WASM module that a deployment would have
+struct TestComputedFunctions;
+
+impl ComputedFunctions for TestComputedFunctions {
+    fn query(&self, _: &SubgraphDeploymentId) -> Result<Box<dyn ComputedQuery>, String> {
+        Ok(Box::new(TestComputedFunctions))
+    }
+}
+
+impl ComputedQuery for TestComputedFunctions {
+    fn compute(&mut self, field: &ComputedField, args: &[q::Value]) -> Result<q::Value, String> {
+        match (field.function.as_str(), args) {
+            ("playedTwice", [q::Value::Int(played)]) => played
+                .as_i64()
+                .and_then(|played| i32::try_from(played).ok())
+                .and_then(|played| played.checked_mul(2))
+                .map(|played| q::Value::Int(q::Number::from(played)))
+                .ok_or_else(|| format!("`played` is too big to double: {:?}", played)),
+            (function, args) => Err(format!("can not compute `{}` from {:?}", function, args)),
+        }
+    }
+}
+
 struct MockQueryLoadManager(Arc<tokio::sync::Semaphore>);
 
 #[async_trait]
@@ -1155,6 +1193,94 @@ fn ambiguous_derived_from_result() {
     })
 }
 
+#[test]
+fn dangling_references_null_nearest_nullable_field() {
+    use graph::prelude::serde_json::{self, json};
+
+    // At block 0, song `s4` refers to musician `m3`, who only appears in
+    // block 1, and its non-null `writtenBy` can therefore not be resolved
+    async fn check(id: &SubgraphDeploymentId, query: &str, expected: serde_json::Value) {
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document(id, query).await;
+        let mut result = serde_json::to_value(&result).unwrap();
+        // Only compare the paths, not the messages and locations
+        for error in result["errors"].as_array_mut().unwrap() {
+            *error = error["path"].clone();
+        }
+        assert_eq!(expected, result);
+    }
+
+    run_test_sequentially(setup, |_, id| async move {
+        // The song can be null, which leaves the other fields alone
+        check(
+            &id,
+            "query {
+               song(id: \"s4\", block: { number: 0 }) { title writtenBy { name } }
+               musicians(block: { number: 0 }, orderBy: id) { name }
+             }",
+            json!({
+                "data": {
+                    "song": null,
+                    "musicians": [{ "name": "John" }, { "name": "Lisa" }],
+                },
+                "errors": [["song", "writtenBy"]],
+            }),
+        )
+        .await;
+
+        // The elements of `originalSongs` and `bands` can not be null, and
+        // neither can these lists, so the whole musician is null
+        check(
+            &id,
+            "query {
+               musician(id: \"m1\", block: { number: 0 }) {
+                 name
+                 bands(orderBy: id) { name originalSongs(orderBy: id) { writtenBy { name } } }
+               }
+             }",
+            json!({
+                "data": { "musician": null },
+                "errors": [["musician", "bands", 1, "originalSongs", 2, "writtenBy"]],
+            }),
+        )
+        .await;
+
+        // The elements of `favoriteSongs` can be null
+        check(
+            &id,
+            "query {
+               musician(id: \"m1\", block: { number: 0 }) {
+                 name
+                 favoriteSongs(orderBy: id) { title writtenBy { name } }
+               }
+             }",
+            json!({
+                "data": {
+                    "musician": {
+                        "name": "John",
+                        "favoriteSongs": [
+                            { "title": "Cheesy Tune", "writtenBy": { "name": "John" } },
+                            null,
+                        ],
+                    },
+                },
+                "errors": [["musician", "favoriteSongs", 1, "writtenBy"]],
+            }),
+        )
+        .await;
+
+        // The root field `songs` can not be null, so there is no data
+        check(
+            &id,
+            "query {
+               songs(block: { number: 0 }, orderBy: id) { writtenBy { name } }
+             }",
+            json!({ "errors": [["songs", 3, "writtenBy"]] }),
+        )
+        .await;
+    })
+}
+
 #[test]
 fn can_filter_by_relationship_fields() {
     run_test_sequentially(setup, |_, id| async move {
@@ -1247,6 +1373,61 @@ fn cannot_filter_by_derved_relationship_fields() {
     })
 }
 
+#[test]
+fn can_query_computed_fields() {
+    run_test_sequentially(setup, |_, id| async move {
+        let result = execute_query_document(
+            &id,
+            graphql_parser::parse_query(
+                "
+        query {
+            songStats(first: 100, orderBy: id) {
+                id
+                playedTwice
+            }
+        }
+        ",
+            )
+            .expect("invalid test query"),
+        )
+        .await;
+
+        assert_eq!(
+            extract_data!(result),
+            Some(object_value(vec![(
+                "songStats",
+                q::Value::List(vec![
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s1"))),
+                        ("playedTwice", q::Value::Int(q::Number::from(20))),
+                    ]),
+                    object_value(vec![
+                        ("id", q::Value::String(String::from("s2"))),
+                        ("playedTwice", q::Value::Int(q::Number::from(30))),
+                    ]),
+                ])
+            )]))
+        );
+
+        // Computed fields are not stored and can not be used for sorting
+        // or filtering
+        for query in &[
+            "query { songStats(orderBy: playedTwice) { id } }",
+            "query { songStats(where: { playedTwice: 20 }) { id } }",
+        ] {
+            let result = execute_query_document(
+                &id,
+                graphql_parser::parse_query(query).expect("invalid test query"),
+            )
+            .await;
+            match &result.to_result().unwrap_err()[0] {
+                QueryError::ExecutionError(QueryExecutionError::InvalidArgumentError(..)) => (),
+                e => panic!(format!("expected InvalidArgumentError, got {}", e)),
+            }
+        }
+    })
+}
+
 #[test]
 fn subscription_gets_result_even_without_events() {
     run_test_sequentially(setup, |_, id| async move {
@@ -1664,3 +1845,133 @@ fn can_query_meta() {
         assert!(result.has_errors());
     })
 }
+
+#[test]
+fn query_failed_deployment() {
+    run_test_sequentially(setup, |_, id| async move {
+        if !STORE.uses_relational_schema(&id).unwrap() {
+            // We don't care about JSONB storage
+            return;
+        }
+        let error = SubgraphError {
+            subgraph_id: id.clone(),
+            message: "cow jumped over the moon".to_owned(),
+            block_ptr: Some(BLOCK_ONE.clone()),
+            handler: None,
+            deterministic: true,
+            count: 1,
+        };
+        let mut state = STORE
+            .deployment_state_from_id(id.clone())
+            .expect("failed to get state");
+        state.fatal_error = Some(error.clone());
+
+        // By default, the reason for the failure is returned instead of data
+        let query = "query { musician(id: \"m1\") { id } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document_with_state(&id, query, state.clone()).await;
+        let errors = result.to_result().unwrap_err();
+        match &errors[0] {
+            QueryError::ExecutionError(QueryExecutionError::DeploymentFailed(e)) => {
+                assert_eq!(&error, e)
+            }
+            e => panic!("unexpected error for failed deployment: {:?}", e),
+        }
+        assert!(errors[0].to_string().contains("cow jumped over the moon"));
+
+        // Data as of the failure can still be requested explicitly
+        let query = "query { musician(id: \"m1\", subgraphError: allow) { id } \
+                             _meta { hasIndexingErrors } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document_with_state(&id, query, state.clone()).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(
+                musician: object!(id: "m1"),
+                _meta: object!(hasIndexingErrors: true)
+            ))
+        );
+
+        // `_meta` reports the failure without having to allow errors
+        let query = "query { _meta { hasIndexingErrors } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document_with_state(&id, query, state).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(_meta: object!(hasIndexingErrors: true)))
+        );
+    })
+}
+
+#[test]
+fn query_deployment_with_non_fatal_errors() {
+    run_test_sequentially(setup, |_, id| async move {
+        if !STORE.uses_relational_schema(&id).unwrap() {
+            // We don't care about JSONB storage
+            return;
+        }
+        let mut state = STORE
+            .deployment_state_from_id(id.clone())
+            .expect("failed to get state");
+
+        let query = "query { musician(id: \"m1\") { id } _meta { hasIndexingErrors } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+
+        // A healthy deployment has no indexing errors
+        let result = execute_query_document_with_state(&id, query.clone(), state.clone()).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(
+                musician: object!(id: "m1"),
+                _meta: object!(hasIndexingErrors: false)
+            ))
+        );
+
+        // Non-fatal errors do not keep queries from returning data
+        state.has_non_fatal_errors = true;
+        let result = execute_query_document_with_state(&id, query, state).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(
+                musician: object!(id: "m1"),
+                _meta: object!(hasIndexingErrors: true)
+            ))
+        );
+    })
+}
+
+#[test]
+fn query_violating_validation_rules() {
+    run_test_sequentially(setup, |_, id| async move {
+        // All violations of enforced rules are reported, and the query is
+        // not executed
+        let query = "query Q { musicians { ...Missing } } query Q { bands { id } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        let rules = result
+            .to_result()
+            .unwrap_err()
+            .into_iter()
+            .map(|e| match e {
+                QueryError::ExecutionError(QueryExecutionError::ValidationError(_, rule, _)) => {
+                    rule
+                }
+                e => panic!("unexpected error for invalid query: {:?}", e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["unique_operation_names", "known_fragment_names"],
+            rules
+        );
+
+        // Violations of rules that are not enforced do not keep the query
+        // from running
+        let query = "query Q($unused: Int) { musician(id: \"m1\") { id } }";
+        let query = graphql_parser::parse_query(query).expect("invalid test query");
+        let result = execute_query_document(&id, query).await;
+        assert_eq!(
+            extract_data!(result),
+            Some(object!(musician: object!(id: "m1")))
+        );
+    })
+}
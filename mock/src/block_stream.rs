@@ -1,64 +1,119 @@
-use futures::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 use graph::prelude::*;
 
-pub struct MockBlockStream {
-    chain_head_update_sink: Sender<ChainHeadUpdate>,
-    _chain_head_update_stream: Receiver<ChainHeadUpdate>,
+use crate::chain::{MockBlock, MockChain, MockTriggerFilter};
+
+/// A block stream over the blocks of a `MockChain` up to a head that tests
+/// can move. Like a real block stream, it starts after the block pointer
+/// that the store has for the subgraph
+pub struct MockBlockStream<S> {
+    store: Arc<S>,
+    deployment_id: SubgraphDeploymentId,
+    filter: MockTriggerFilter,
+    head: Arc<AtomicU64>,
+    starts: Arc<Mutex<Vec<u64>>>,
+
+    /// The next block to yield, `None` before the stream was polled
+    next: Option<u64>,
+    ended: bool,
 }
 
-impl MockBlockStream {
-    fn new() -> Self {
-        let (chain_head_update_sink, chain_head_update_stream) = channel(100);
+impl<S: EntityStore> Stream for MockBlockStream<S> {
+    type Item = BlockStreamEvent<MockChain>;
+    type Error = Error;
+
+    fn poll(&mut self) -> Result<Async<Option<Self::Item>>, Error> {
+        let next = match self.next {
+            Some(next) => next,
+            None => {
+                let start = self
+                    .store
+                    .block_ptr(self.deployment_id.clone())?
+                    .map_or(0, |ptr| ptr.number + 1);
+                self.starts.lock().unwrap().push(start);
+                start
+            }
+        };
 
-        Self {
-            chain_head_update_sink,
-            _chain_head_update_stream: chain_head_update_stream,
+        if next <= self.head.load(Ordering::SeqCst) {
+            self.next = Some(next + 1);
+            let block = self.filter.triggers_in_block(MockBlock::new(next));
+            return Ok(Async::Ready(Some(BlockStreamEvent::Block(block))));
+        }
+
+        self.next = Some(next);
+        if self.ended {
+            Ok(Async::NotReady)
+        } else {
+            self.ended = true;
+            Ok(Async::Ready(Some(BlockStreamEvent::EndOfBlocks)))
         }
     }
 }
 
-impl Stream for MockBlockStream {
-    type Item = BlockStreamEvent;
-    type Error = Error;
+impl<S: EntityStore> BlockStream<MockChain> for MockBlockStream<S> {}
 
-    fn poll(&mut self) -> Result<Async<Option<BlockStreamEvent>>, Error> {
-        Ok(Async::Ready(None))
-    }
+pub struct MockBlockStreamBuilder<S> {
+    store: Arc<S>,
+    head: Arc<AtomicU64>,
+    starts: Arc<Mutex<Vec<u64>>>,
 }
 
-impl EventConsumer<ChainHeadUpdate> for MockBlockStream {
-    fn event_sink(&self) -> Box<dyn Sink<SinkItem = ChainHeadUpdate, SinkError = ()> + Send> {
-        Box::new(self.chain_head_update_sink.clone().sink_map_err(|_| ()))
+impl<S> Clone for MockBlockStreamBuilder<S> {
+    fn clone(&self) -> Self {
+        MockBlockStreamBuilder {
+            store: self.store.clone(),
+            head: self.head.clone(),
+            starts: self.starts.clone(),
+        }
     }
 }
 
-impl BlockStream for MockBlockStream {}
+impl<S> MockBlockStreamBuilder<S> {
+    /// A builder for streams whose chain head is block `head`
+    pub fn new(store: Arc<S>, head: u64) -> Self {
+        MockBlockStreamBuilder {
+            store,
+            head: Arc::new(AtomicU64::new(head)),
+            starts: Arc::new(Mutex::new(vec![])),
+        }
+    }
 
-#[derive(Clone)]
-pub struct MockBlockStreamBuilder;
+    /// Move the chain head of all streams to block `head`
+    pub fn set_head(&self, head: u64) {
+        self.head.store(head, Ordering::SeqCst);
+    }
 
-impl MockBlockStreamBuilder {
-    pub fn new() -> Self {
-        Self {}
+    /// The first block of each stream that was polled, in the order in
+    /// which the streams were polled first
+    pub fn starts(&self) -> Vec<u64> {
+        self.starts.lock().unwrap().clone()
     }
 }
 
-impl BlockStreamBuilder for MockBlockStreamBuilder {
-    type Stream = MockBlockStream;
+impl<S: EntityStore> BlockStreamBuilder for MockBlockStreamBuilder<S> {
+    type Chain = MockChain;
+    type Stream = MockBlockStream<S>;
 
     fn build(
         &self,
         _logger: Logger,
-        _deployment_id: SubgraphDeploymentId,
+        deployment_id: SubgraphDeploymentId,
         _network_name: String,
         _start_blocks: Vec<u64>,
-        _: EthereumLogFilter,
-        _: EthereumCallFilter,
-        _: EthereumBlockFilter,
-        _: bool,
+        filter: MockTriggerFilter,
         _: Arc<BlockStreamMetrics>,
     ) -> Self::Stream {
-        MockBlockStream::new()
+        MockBlockStream {
+            store: self.store.clone(),
+            deployment_id,
+            filter,
+            head: self.head.clone(),
+            starts: self.starts.clone(),
+            next: None,
+            ended: false,
+        }
     }
 }
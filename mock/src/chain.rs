@@ -0,0 +1,188 @@
+//! A toy chain for tests of the code that is generic over `Blockchain`.
+//! Block `n` of the chain contains the triggers `0..n`
+
+use graph::blockchain::{
+    Block, BlockPtr, BlockWithTriggers, Blockchain, TriggerData, TriggerFilter, TriggersAdapter,
+};
+use graph::data::store::scalar::Bytes;
+use graph::prelude::web3::types::H256;
+use graph::prelude::*;
+
+#[derive(Clone, Debug)]
+pub struct MockChain;
+
+impl Blockchain for MockChain {
+    const KIND: &'static str = "mock/contract";
+
+    type Pointer = BlockPtr;
+    type Block = MockBlock;
+    type MappingBlock = MockBlock;
+    type DataSource = MockDataSource;
+    type TriggerData = MockTrigger;
+    type TriggerFilter = MockTriggerFilter;
+
+    fn data_source(data_source: &DataSource) -> Result<MockDataSource, Error> {
+        Ok(MockDataSource {
+            name: data_source.name.clone(),
+        })
+    }
+
+    fn triggers_adapter(
+        &self,
+        _: &SubgraphManifest,
+        _: &BlockStreamMetrics,
+    ) -> Result<Arc<dyn TriggersAdapter<Self>>, Error> {
+        Ok(Arc::new(MockTriggersAdapter))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MockBlock {
+    number: u64,
+}
+
+impl MockBlock {
+    pub fn new(number: u64) -> Self {
+        MockBlock { number }
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// The pointer to block `number`. Block hashes are 32 bytes long so
+    /// that the store can record them as the pointer of a subgraph
+    pub fn ptr_to(number: u64) -> BlockPtr {
+        BlockPtr {
+            hash: Bytes::from(H256::from_low_u64_be(number).as_bytes()),
+            number,
+        }
+    }
+}
+
+impl Block<MockChain> for MockBlock {
+    fn ptr(&self) -> BlockPtr {
+        Self::ptr_to(self.number)
+    }
+
+    fn parent_ptr(&self) -> Option<BlockPtr> {
+        match self.number {
+            0 => None,
+            n => Some(Self::ptr_to(n - 1)),
+        }
+    }
+
+    fn is_final(&self) -> bool {
+        true
+    }
+
+    fn mapping_block(&self) -> MockBlock {
+        self.clone()
+    }
+}
+
+/// The data sources of the chain only have a name
+#[derive(Clone, Debug)]
+pub struct MockDataSource {
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MockTrigger(pub u64);
+
+impl TriggerData for MockTrigger {
+    fn kind(&self) -> &'static str {
+        "mock"
+    }
+
+    fn location(&self) -> Option<String> {
+        Some(format!("trigger {}", self.0))
+    }
+}
+
+/// Matches the triggers that are divisible by `divisor`
+#[derive(Clone, Debug)]
+pub struct MockTriggerFilter {
+    pub divisor: u64,
+}
+
+impl MockTriggerFilter {
+    pub fn triggers_in_block(&self, block: MockBlock) -> BlockWithTriggers<MockChain> {
+        let triggers = (0..block.number)
+            .rev()
+            .filter(|trigger| trigger % self.divisor == 0)
+            .map(MockTrigger)
+            .collect();
+        BlockWithTriggers::new(block, triggers)
+    }
+}
+
+impl TriggerFilter<MockChain> for MockTriggerFilter {
+    fn from_manifest(_: &SubgraphManifest) -> Result<Self, Error> {
+        Ok(MockTriggerFilter { divisor: 1 })
+    }
+
+    fn from_data_sources<'a>(_: impl Iterator<Item = &'a MockDataSource> + Clone) -> Self {
+        MockTriggerFilter { divisor: 1 }
+    }
+
+    fn extend<'a>(&mut self, _: impl Iterator<Item = &'a MockDataSource> + Clone) {}
+}
+
+pub struct MockTriggersAdapter;
+
+#[async_trait]
+impl TriggersAdapter<MockChain> for MockTriggersAdapter {
+    async fn triggers_in_block(
+        &self,
+        _: &Logger,
+        block: MockBlock,
+        filter: MockTriggerFilter,
+    ) -> Result<BlockWithTriggers<MockChain>, Error> {
+        Ok(filter.triggers_in_block(block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_with_triggers_sorts_triggers() {
+        let block = MockBlock::new(7);
+        let triggers = vec![MockTrigger(3), MockTrigger(1), MockTrigger(2)];
+
+        let block = BlockWithTriggers::<MockChain>::new(block, triggers);
+        assert_eq!(
+            vec![MockTrigger(1), MockTrigger(2), MockTrigger(3)],
+            block.triggers
+        );
+        assert_eq!(7, block.ptr().number);
+        assert_eq!(Some(6), block.block.parent_ptr().map(|ptr| ptr.number));
+        assert_eq!(None, MockBlock::new(0).parent_ptr());
+    }
+
+    #[test]
+    fn trigger_filter_selects_triggers() {
+        let block = MockBlock::new(12);
+        let filter = MockTriggerFilter { divisor: 4 };
+
+        let block = filter.triggers_in_block(block);
+        assert_eq!(
+            vec![MockTrigger(0), MockTrigger(4), MockTrigger(8)],
+            block.triggers
+        );
+        assert_eq!(
+            vec!["mock"; 3],
+            block.triggers.iter().map(|t| t.kind()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn block_pointers_can_be_stored() {
+        let ptr = MockBlock::new(3).ptr();
+        let stored = EthereumBlockPointer::try_from(ptr).unwrap();
+        assert_eq!(3, stored.number);
+        assert_eq!(H256::from_low_u64_be(3), stored.hash);
+    }
+}
@@ -6,10 +6,16 @@ extern crate graphql_parser;
 extern crate rand;
 
 mod block_stream;
+mod chain;
 
 mod metrics_registry;
 mod store;
 
 pub use self::block_stream::{MockBlockStream, MockBlockStreamBuilder};
+pub use self::chain::{
+    MockBlock, MockChain, MockDataSource, MockTrigger, MockTriggerFilter, MockTriggersAdapter,
+};
 pub use self::metrics_registry::MockMetricsRegistry;
-pub use self::store::{mock_store_with_users_subgraph, MockStore};
+pub use self::store::{
+    mock_store_with_users_subgraph, mock_store_with_users_subgraph_on_network, MockStore,
+};
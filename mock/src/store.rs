@@ -21,18 +21,28 @@ mock! {
         fn uses_relational_schema(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error>;
 
         fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error>;
+
+        fn strict_bytes(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error>;
+
+        fn computed_module(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<Vec<u8>>, Error>;
+
+        fn health_issues(&self) -> Result<Vec<HealthIssue>, Error>;
+
+        fn flagged_deployments(&self) -> Result<BTreeMap<SubgraphDeploymentId, DeploymentFlag>, Error>;
+
+        fn graft_lineage(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<GraftLineage>, Error>;
     }
 
     trait ChainStore: Send + Sync + 'static {
         fn genesis_block_ptr(&self) -> Result<EthereumBlockPointer, Error>;
 
-        fn upsert_blocks<B, E>(&self, blocks: B) -> Box<dyn Future<Item = (), Error = E> + Send + 'static>
+        fn upsert_blocks<B, E>(&self, provider: String, blocks: B) -> Box<dyn Future<Item = (), Error = E> + Send + 'static>
         where
             B: Stream<Item = EthereumBlock, Error = E> + Send + 'static,
             E: From<Error> + Send + 'static,
             Self: Sized;
 
-        fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error>;
+        fn upsert_light_blocks(&self, provider: &str, blocks: Vec<LightEthereumBlock>) -> Result<(), Error>;
 
         fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, Error>;
 
@@ -52,11 +62,30 @@ mock! {
 
         fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error>;
 
+        fn block_hashes_and_parents_by_block_number(
+            &self,
+            number: u64,
+        ) -> Result<Vec<(H256, H256)>, Error>;
+
+        fn canonical_block_hash_by_block_number(&self, number: u64) -> Result<Option<H256>, Error>;
+
         fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error>;
+
+        fn find_gaps(&self, from: u64, to: u64) -> Result<Vec<BlockGap>, Error>;
+
+        fn cached_block(&self, hash: H256) -> Result<Option<CachedBlock>, Error>;
+
+        fn save_head_announcements(
+            &self,
+            provider: &str,
+            announcements: &[HeadAnnouncement],
+        ) -> Result<(), Error>;
+
+        fn head_announcements(&self) -> Result<Vec<(String, Vec<HeadAnnouncement>)>, Error>;
     }
 }
 
-impl Store for MockStore {
+impl EntityStore for MockStore {
     fn block_ptr(
         &self,
         _subgraph_id: SubgraphDeploymentId,
@@ -114,31 +143,53 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn apply_metadata_operations(
+    fn revert_block_operations(
         &self,
-        _operations: Vec<MetadataOperation>,
+        _subgraph_id: SubgraphDeploymentId,
+        _block_ptr_from: EthereumBlockPointer,
+        _block_ptr_to: EthereumBlockPointer,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
 
-    fn build_entity_attribute_indexes(
+    fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+        unimplemented!()
+    }
+
+    fn scan_watermark(
         &self,
-        _subgraph: &SubgraphDeploymentId,
-        _indexes: Vec<AttributeIndexDefinition>,
-    ) -> Result<(), SubgraphAssignmentProviderError> {
+        _subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<ScanWatermark>, StoreError> {
         unimplemented!()
     }
 
-    fn revert_block_operations(
+    fn set_scan_watermark(
         &self,
-        _subgraph_id: SubgraphDeploymentId,
-        _block_ptr_from: EthereumBlockPointer,
-        _block_ptr_to: EthereumBlockPointer,
+        _subgraph_id: &SubgraphDeploymentId,
+        _watermark: ScanWatermark,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
 
-    fn subscribe(&self, _entities: Vec<SubgraphEntityPair>) -> StoreEventStreamBox {
+    fn block_number(
+        &self,
+        _subgraph_id: &SubgraphDeploymentId,
+        _block_hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        unimplemented!()
+    }
+
+    fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+        unimplemented!()
+    }
+}
+
+impl DeploymentStore for MockStore {
+    fn build_entity_attribute_indexes(
+        &self,
+        _subgraph: &SubgraphDeploymentId,
+        _indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
         unimplemented!()
     }
 
@@ -155,6 +206,10 @@ impl Store for MockStore {
             reorg_count: 0,
             max_reorg_depth: 0,
             latest_ethereum_block_number: 0,
+            fatal_error: None,
+            has_non_fatal_errors: false,
+            earliest_block_number: 0,
+            flag: None,
         })
     }
 
@@ -175,6 +230,14 @@ impl Store for MockStore {
         unimplemented!()
     }
 
+    fn fail_subgraph(
+        &self,
+        _id: &SubgraphDeploymentId,
+        _error: SubgraphError,
+    ) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         _logger: &Logger,
@@ -184,20 +247,87 @@ impl Store for MockStore {
         unimplemented!()
     }
 
-    fn block_number(
+    fn restore_unused_deployment(&self, _id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn memory_limit(&self, _id: &SubgraphDeploymentId) -> Result<Option<u64>, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_memory_limit(
         &self,
-        _subgraph_id: &SubgraphDeploymentId,
-        _block_hash: H256,
-    ) -> Result<Option<BlockNumber>, StoreError> {
+        _id: &SubgraphDeploymentId,
+        _limit: Option<u64>,
+    ) -> Result<bool, StoreError> {
         unimplemented!()
     }
 
-    fn query_store(self: Arc<Self>, _: bool) -> Arc<dyn QueryStore + Send + Sync> {
+    fn set_deployment_flag(
+        &self,
+        _id: &SubgraphDeploymentId,
+        _note: Option<String>,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_history_blocks(
+        &self,
+        _id: &SubgraphDeploymentId,
+        _history_blocks: Option<BlockNumber>,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_strict_bytes(
+        &self,
+        _id: &SubgraphDeploymentId,
+        _strict_bytes: bool,
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn set_computed_module(
+        &self,
+        _id: &SubgraphDeploymentId,
+        _module: &[u8],
+    ) -> Result<bool, StoreError> {
+        unimplemented!()
+    }
+
+    fn check_block_ranges(
+        &self,
+        _id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        unimplemented!()
+    }
+
+    fn repair_block_ranges(
+        &self,
+        _id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        unimplemented!()
+    }
+}
+
+impl SubgraphNameStore for MockStore {
+    fn apply_metadata_operations(
+        &self,
+        _operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
         unimplemented!()
     }
 }
 
 pub fn mock_store_with_users_subgraph() -> (Arc<MockStore>, SubgraphDeploymentId) {
+    mock_store_with_users_subgraph_on_network(None)
+}
+
+/// Like `mock_store_with_users_subgraph`, but the "users" subgraph indexes
+/// `network`
+pub fn mock_store_with_users_subgraph_on_network(
+    network: Option<&str>,
+) -> (Arc<MockStore>, SubgraphDeploymentId) {
     let mut store = MockStore::new();
 
     let subgraph_id = SubgraphDeploymentId::new("users").unwrap();
@@ -239,7 +369,10 @@ pub fn mock_store_with_users_subgraph() -> (Arc<MockStore>, SubgraphDeploymentId
             Ok(Arc::new(ApiSchema::from_api_schema(schema).unwrap()))
         });
 
-    store.expect_network_name().returning(|_| Ok(None));
+    let network = network.map(str::to_owned);
+    store
+        .expect_network_name()
+        .returning(move |_| Ok(network.clone()));
 
     (Arc::new(store), subgraph_id)
 }
@@ -0,0 +1,125 @@
+//! Run the node with `--dev`: deploy the subgraph from a manifest on disk
+//! and deploy it again, from scratch, whenever one of its files changes
+use std::time::Duration;
+
+use graph::prelude::*;
+use graph_core::dev::{is_dev_deployment, DevManifest, DEV_SUBGRAPH_NAME};
+use graph_store_postgres::Store as DieselStore;
+
+/// How often the files of the subgraph are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the files have to stay the same after a change before the
+/// subgraph is deployed again. Build tools usually write several files in
+/// quick succession, and we only want to deploy once for all of them
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+/// Dev mode removes deployments and reuses the subgraph name `dev`; refuse
+/// to run it against a store that also holds other deployments unless
+/// `force` is set
+pub fn check_store(logger: &Logger, store: &DieselStore, force: bool) -> Result<(), Error> {
+    let others: Vec<_> = store
+        .deployment_ids()?
+        .into_iter()
+        .filter(|id| !is_dev_deployment(id))
+        .collect();
+
+    match others.first() {
+        None => Ok(()),
+        Some(id) if force => {
+            warn!(logger, "Running in dev mode against a store with other deployments";
+                  "deployments" => others.len(), "example" => id.as_str());
+            Ok(())
+        }
+        Some(id) => Err(format_err!(
+            "the store contains {} deployments that were not made with `--dev`, \
+             for example `{}`; use a separate database for `--dev` or pass `--force`",
+            others.len(),
+            id
+        )),
+    }
+}
+
+/// Deploy the subgraph of `manifest` and deploy it again whenever its files
+/// change. Runs forever
+pub async fn run<R: SubgraphRegistrar>(
+    logger: Logger,
+    manifest: DevManifest,
+    registrar: Arc<R>,
+    store: Arc<DieselStore>,
+    node_id: NodeId,
+) {
+    let logger = logger.new(o!("component" => "DevMode"));
+    info!(logger, "Watching subgraph for changes";
+          "manifest" => manifest.path().display().to_string());
+
+    let mut stamps = manifest.stamps();
+    deploy(&logger, &manifest, &*registrar, &store, &node_id).await;
+
+    loop {
+        tokio::time::delay_for(POLL_INTERVAL).await;
+        let mut current = manifest.stamps();
+        if current == stamps {
+            continue;
+        }
+
+        // Wait for the files to settle before deploying
+        loop {
+            tokio::time::delay_for(DEBOUNCE).await;
+            let settled = manifest.stamps();
+            if settled == current {
+                break;
+            }
+            current = settled;
+        }
+        stamps = current;
+
+        deploy(&logger, &manifest, &*registrar, &store, &node_id).await;
+    }
+}
+
+async fn deploy<R: SubgraphRegistrar>(
+    logger: &Logger,
+    manifest: &DevManifest,
+    registrar: &R,
+    store: &DieselStore,
+    node_id: &NodeId,
+) {
+    match redeploy(manifest, registrar, store, node_id).await {
+        Ok(id) => info!(logger, "Reloaded subgraph"; "deployment" => id.as_str()),
+        Err(e) => error!(logger, "Failed to deploy subgraph, waiting for the next change";
+                         "error" => e.to_string()),
+    }
+}
+
+/// Remove the previous dev deployments with all their data, and deploy the
+/// current files under the name `dev`. Since the new deployment starts
+/// with an empty store, it indexes from the start blocks in the manifest
+async fn redeploy<R: SubgraphRegistrar>(
+    manifest: &DevManifest,
+    registrar: &R,
+    store: &DieselStore,
+    node_id: &NodeId,
+) -> Result<SubgraphDeploymentId, Error> {
+    let id = manifest.deployment_id()?;
+    let name = SubgraphName::new(DEV_SUBGRAPH_NAME).expect("the dev subgraph name is valid");
+
+    // Removing the name unassigns the previous deployment, which stops it
+    match registrar.remove_subgraph(name.clone()).await {
+        Ok(()) | Err(SubgraphRegistrarError::NameNotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    for old in store
+        .deployment_ids()?
+        .into_iter()
+        .filter(is_dev_deployment)
+    {
+        store.remove_deployment(&old)?;
+    }
+
+    registrar.create_subgraph(name.clone()).await?;
+    registrar
+        .create_subgraph_version(name, id.clone(), node_id.clone())
+        .await?;
+    Ok(id)
+}
@@ -10,50 +10,95 @@ use std::str::FromStr;
 use std::sync::RwLock;
 use std::time::Duration;
 use structopt::StructOpt;
+use tokio::signal::unix::SignalKind;
 use tokio::sync::mpsc;
 
-use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::components::ethereum::head_publisher::{
+    publish_chain_heads, publish_deployment_blocks, HeadPublisher, HeadPublisherConfig,
+};
+use graph::components::ethereum::health::{
+    persist_head_announcements, poll_provider_heads, restore_head_announcements,
+    ProviderHealthRegistry,
+};
+use graph::components::ethereum::reload::{
+    plan_reload, provider_sets, ProviderConfig, ProviderSet,
+};
+use graph::components::ethereum::replay;
+use graph::components::ethereum::shared_logs::{share_logs, SharedLogsMetrics};
+use graph::components::ethereum::stream_status::{
+    log_unchanged_block_stream_statuses, BlockStreamStatusRegistry,
+};
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities, REORG_THRESHOLD};
 use graph::components::forward;
+use graph::components::server::auth::{AuthMetrics, AuthToken, ServerAuth, ServerSecurity};
+use graph::components::server::rate_limit::{RateLimiter, RateLimits, TrustedProxies};
+use graph::components::server::tls::TlsConfig;
+use graph::components::subgraph::handler_stats::HandlerStatsRegistry;
+use graph::components::trace;
 use graph::data::graphql::effort::LoadManager;
 use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
 use graph::util::security::SafeDisplay;
 use graph_chain_arweave::adapter::ArweaveAdapter;
-use graph_chain_ethereum::{network_indexer, BlockIngestor, BlockStreamBuilder, Transport};
+use graph_chain_ethereum::{
+    network_indexer, BlockIngestor, BlockStreamBuilder, ChainReorgMetrics, EthereumChain, Transport,
+};
 use graph_core::{
-    three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
-    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
-    SubgraphRegistrar as IpfsSubgraphRegistrar,
+    dev::DevManifest, three_box::ThreeBoxAdapter, LinkResolver, MetricsRegistry,
+    ProofOfIndexingVerifier, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
+    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
 use graph_graphql::prelude::GraphQlRunner;
-use graph_runtime_wasm::RuntimeHostBuilder as WASMRuntimeHostBuilder;
+use graph_runtime_wasm::{RuntimeHostBuilder as WASMRuntimeHostBuilder, WasmComputedFunctions};
 use graph_server_http::GraphQLServer as GraphQLQueryServer;
-use graph_server_index_node::IndexNodeServer;
+use graph_server_index_node::{Chains, IndexNodeServer};
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::NetworkStore as DieselNetworkStore;
+use graph_store_postgres::{ConsistencyPolicy, NetworkStore as DieselNetworkStore};
 use graphql_parser::query as q;
 
+mod dev;
 mod opt;
 mod store_builder;
 
 use store_builder::StoreBuilder;
 
 lazy_static! {
-    // Default to an Ethereum reorg threshold to 50 blocks
-    static ref REORG_THRESHOLD: u64 = env::var("ETHEREUM_REORG_THRESHOLD")
-        .ok()
-        .map(|s| u64::from_str(&s)
-            .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_REORG_THRESHOLD")))
-        .unwrap_or(50);
-
     // Default to an ancestor count of 50 blocks
     static ref ANCESTOR_COUNT: u64 = env::var("ETHEREUM_ANCESTOR_COUNT")
         .ok()
         .map(|s| u64::from_str(&s)
              .unwrap_or_else(|_| panic!("failed to parse env var ETHEREUM_ANCESTOR_COUNT")))
         .unwrap_or(50);
+
+    // Removing unused deployments is opt-in
+    static ref REMOVE_UNUSED_DEPLOYMENTS: bool = env::var("GRAPH_REMOVE_UNUSED_DEPLOYMENTS")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+
+    // Default to considering deployments unused after a week without use
+    static ref UNUSED_DEPLOYMENT_GRACE_PERIOD: Duration = unused_deployment_hours(
+        "GRAPH_UNUSED_DEPLOYMENT_GRACE_PERIOD");
+
+    // Default to removing unused deployments after another week
+    static ref UNUSED_DEPLOYMENT_REMOVAL_DELAY: Duration = unused_deployment_hours(
+        "GRAPH_UNUSED_DEPLOYMENT_REMOVAL_DELAY");
+
+    // Default to not starting deployments with inconsistent metadata
+    static ref CONSISTENCY_POLICY: ConsistencyPolicy = env::var("GRAPH_STORE_CONSISTENCY_POLICY")
+        .ok()
+        .map(|s| ConsistencyPolicy::from_str(&s)
+             .unwrap_or_else(|e| panic!("failed to parse env var GRAPH_STORE_CONSISTENCY_POLICY: {}", e)))
+        .unwrap_or(ConsistencyPolicy::Skip);
+}
+
+fn unused_deployment_hours(var: &str) -> Duration {
+    let hours = env::var(var)
+        .ok()
+        .map(|s| u64::from_str(&s).unwrap_or_else(|_| panic!("failed to parse env var {}", var)))
+        .unwrap_or(7 * 24);
+    Duration::from_secs(hours * 3600)
 }
 
 git_testament!(TESTAMENT);
@@ -142,6 +187,11 @@ async fn main() {
 
     info!(logger, "Starting up");
 
+    // Fail early if a deployment should be replayed from a recording that
+    // can not be loaded
+    replay::init(&logger)
+        .unwrap_or_else(|e| panic!("failed to set up recording Ethereum requests: {}", e));
+
     // Optionally, identify the Elasticsearch logging configuration
     let elastic_config = opt
         .elasticsearch_url
@@ -155,11 +205,21 @@ async fn main() {
     // Create a component and subgraph logger factory
     let logger_factory = LoggerFactory::new(logger.clone(), elastic_config);
 
+    // Optionally, export traces; they are exported for as long as the
+    // guard lives
+    let _trace_guard = trace::init(&logger, opt.otlp_endpoint.clone(), opt.trace_sample_rate);
+
     // Try to create IPFS clients for each URL specified in `--ipfs`
     let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &opt.ipfs);
 
-    // Convert the client into a link resolver
-    let link_resolver = Arc::new(LinkResolver::from(ipfs_clients));
+    // Convert the client into a link resolver; with `--dev`, it also reads
+    // the files of the dev subgraph from disk
+    let dev_manifest = opt.dev.as_ref().map(|path| DevManifest::new(path.into()));
+    let link_resolver = match &dev_manifest {
+        Some(manifest) => LinkResolver::from(ipfs_clients).with_dev_manifest(manifest.clone()),
+        None => LinkResolver::from(ipfs_clients),
+    };
+    let link_resolver = Arc::new(link_resolver);
 
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
@@ -170,15 +230,86 @@ async fn main() {
     let mut metrics_server =
         PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
 
+    // Set up authentication and TLS for the admin and index node servers
+    let admin_security = {
+        let tokens = opt
+            .admin_auth_tokens
+            .iter()
+            .map(|token| token.parse::<AuthToken>())
+            .collect::<Result<Vec<_>, _>>()
+            .expect("invalid admin auth token");
+        let tls = match (&opt.admin_tls_cert, &opt.admin_tls_key) {
+            (Some(cert), Some(key)) => Some(TlsConfig::new(cert, key)),
+            _ => None,
+        };
+        let auth_metrics = Arc::new(AuthMetrics::new(metrics_registry.clone()));
+        ServerSecurity::new(ServerAuth::new(tokens), tls, auth_metrics)
+    };
+    let index_node_security = if opt.index_node_auth {
+        admin_security.clone()
+    } else {
+        ServerSecurity::new(ServerAuth::disabled(), None, admin_security.metrics.clone())
+    };
+
+    // Set up rate limiting for the GraphQL servers
+    let rate_limiter = {
+        let limits = RateLimits {
+            default: opt
+                .query_rate_limit
+                .as_ref()
+                .map(|limit| limit.parse().expect("invalid --query-rate-limit")),
+            deployments: opt
+                .deployment_query_rate_limits
+                .iter()
+                .map(|limit| RateLimits::parse_deployment_limit(limit))
+                .collect::<Result<_, _>>()
+                .expect("invalid --deployment-query-rate-limits"),
+        };
+        let proxies = TrustedProxies::new(
+            opt.trusted_proxies
+                .iter()
+                .map(|net| net.parse())
+                .collect::<Result<_, _>>()
+                .expect("invalid --trusted-proxies"),
+            opt.client_ip_headers.clone(),
+        );
+        if limits.is_empty() {
+            None
+        } else {
+            info!(logger, "Rate limiting queries"; "limits" => format!("{:?}", limits));
+            Some(Arc::new(RateLimiter::new(
+                limits,
+                proxies,
+                metrics_registry.clone(),
+            )))
+        }
+    };
+
     // Ethereum clients
-    let eth_networks = create_ethereum_networks(
-        &logger,
-        metrics_registry.clone(),
-        &opt.ethereum_rpc,
-        &opt.ethereum_ipc,
-        &opt.ethereum_ws,
-    )
-    .await;
+    let eth_networks = match &opt.ethereum_providers_file {
+        Some(path) => {
+            // Providers from a file can be changed without a restart
+            let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(metrics_registry.clone()));
+            let (sets, eth_networks) = create_provider_sets(&logger, path, &eth_rpc_metrics).await;
+            graph::spawn(reload_providers_on_hangup(
+                logger.clone(),
+                path.clone(),
+                sets,
+                eth_rpc_metrics,
+            ));
+            eth_networks
+        }
+        None => {
+            create_ethereum_networks(
+                &logger,
+                metrics_registry.clone(),
+                &opt.ethereum_rpc,
+                &opt.ethereum_ipc,
+                &opt.ethereum_ws,
+            )
+            .await
+        }
+    };
 
     // Set up Store
     info!(
@@ -209,6 +340,59 @@ async fn main() {
     ));
     let store_builder2 = store_builder.clone();
 
+    match store_builder
+        .store()
+        .check_consistency(&logger, *CONSISTENCY_POLICY)
+    {
+        Ok(issues) if !issues.is_empty() && *CONSISTENCY_POLICY == ConsistencyPolicy::Refuse => {
+            panic!(
+                "Found {} inconsistencies in subgraph metadata, refusing to start; \
+                 set GRAPH_STORE_CONSISTENCY_POLICY to `skip` or `repair` to start anyway",
+                issues.len()
+            );
+        }
+        Ok(_) => {}
+        Err(e) => panic!("Failed to check subgraph metadata for consistency: {}", e),
+    }
+
+    if dev_manifest.is_some() {
+        if let Err(e) = dev::check_store(&logger, &store_builder.store(), opt.force) {
+            panic!("Refusing to start in dev mode: {}", e);
+        }
+    }
+
+    if *REMOVE_UNUSED_DEPLOYMENTS {
+        let store = store_builder.store();
+        let logger = logger.new(o!("component" => "UnusedDeployments"));
+        info!(logger, "Removing unused deployments";
+              "grace_period_hours" => UNUSED_DEPLOYMENT_GRACE_PERIOD.as_secs() / 3600,
+              "removal_delay_hours" => UNUSED_DEPLOYMENT_REMOVAL_DELAY.as_secs() / 3600);
+        std::thread::spawn(move || loop {
+            if let Err(e) = store.remove_unused_deployments(
+                &logger,
+                *UNUSED_DEPLOYMENT_GRACE_PERIOD,
+                *UNUSED_DEPLOYMENT_REMOVAL_DELAY,
+            ) {
+                error!(logger, "Failed to remove unused deployments"; "error" => e.to_string());
+            }
+            std::thread::sleep(Duration::from_secs(3600));
+        });
+    }
+
+    {
+        // Remove the entity history that deployments with `historyBlocks`
+        // in their manifest do not need anymore
+        let store = store_builder.store();
+        let logger = logger.new(o!("component" => "PruneDeployments"));
+        let node_id = node_id.clone();
+        std::thread::spawn(move || loop {
+            if let Err(e) = store.prune_deployments(&logger, &node_id) {
+                error!(logger, "Failed to prune deployments"; "error" => e.to_string());
+            }
+            std::thread::sleep(Duration::from_secs(3600));
+        });
+    }
+
     graph::spawn(
         futures::stream::FuturesOrdered::from_iter(stores_eth_networks.flatten().into_iter().map(
             |(network_name, capabilities, eth_adapter)| {
@@ -250,29 +434,96 @@ async fn main() {
                 metrics_registry.clone(),
                 store_conn_pool_size as usize,
             ));
-            let graphql_runner = Arc::new(GraphQlRunner::new(
-                &logger,
-                store_builder.store(),
-                load_manager,
-            ));
+            let graphql_runner = Arc::new(
+                GraphQlRunner::new(&logger, store_builder.store(), load_manager)
+                    .computed_functions(Arc::new(WasmComputedFunctions::new(
+                        store_builder.store(),
+                    ))),
+            );
+            let chain_stores: HashMap<_, _> = network_stores
+                .iter()
+                .map(|(name, store)| (name.clone(), store.clone() as Arc<dyn ChainStore>))
+                .collect();
+
+            // Deployments on the same network share the logs they request
+            let eth_networks = share_logs(
+                &eth_networks,
+                &chain_stores,
+                Arc::new(SharedLogsMetrics::new(metrics_registry.clone())),
+            );
             let mut graphql_server = GraphQLQueryServer::new(
                 &logger_factory,
                 graphql_metrics_registry,
                 graphql_runner.clone(),
                 store_builder.store(),
+                chain_stores.clone(),
                 node_id.clone(),
             );
-            let subscription_server = GraphQLSubscriptionServer::new(
+            let mut subscription_server = GraphQLSubscriptionServer::new(
                 &logger,
                 graphql_runner.clone(),
                 store_builder.store(),
             );
+            if let Some(rate_limiter) = &rate_limiter {
+                graphql_server = graphql_server.with_rate_limiter(rate_limiter.clone());
+                subscription_server = subscription_server.with_rate_limiter(rate_limiter.clone());
+            }
+
+            // Keep track of the heads and errors of all providers for the
+            // `chains` field of the index node server
+            let provider_health = Arc::new(ProviderHealthRegistry::with_metrics(
+                metrics_registry.clone(),
+            ));
+            restore_head_announcements(&logger, &provider_health, &eth_networks, &chain_stores);
+            graph::spawn(poll_provider_heads(
+                logger.clone(),
+                provider_health.clone(),
+                eth_networks.clone(),
+            ));
+            graph::spawn(persist_head_announcements(
+                logger.clone(),
+                provider_health.clone(),
+                chain_stores.clone(),
+            ));
+            // What the block streams of the deployments on this node are
+            // doing, for the index node server and for the logs
+            let block_stream_statuses = Arc::new(BlockStreamStatusRegistry::new());
+            graph::spawn(log_unchanged_block_stream_statuses(
+                block_stream_statuses.clone(),
+            ));
+
+            // Tell external consumers about new chain heads and blocks
+            // that deployments processed, if that is turned on
+            let head_publisher_config = HeadPublisherConfig::from_env()
+                .unwrap_or_else(|e| panic!("invalid GRAPH_HEAD_PUBLISHER_URL: {}", e));
+            if let Some(config) = head_publisher_config {
+                let publisher = Arc::new(HeadPublisher::new(&logger, config));
+                for (network, store) in &chain_stores {
+                    graph::spawn(publish_chain_heads(
+                        publisher.clone(),
+                        network.clone(),
+                        store.clone(),
+                    ));
+                }
+                graph::spawn(publish_deployment_blocks(
+                    publisher.clone(),
+                    store_builder.store(),
+                ));
+                graph::spawn(publisher.run());
+            }
+
+            // How the handlers of the deployments on this node perform
+            let handler_stats = Arc::new(HandlerStatsRegistry::new());
 
             let mut index_node_server = IndexNodeServer::new(
                 &logger_factory,
                 graphql_runner.clone(),
                 store_builder.store(),
+                Chains::new(chain_stores.clone(), provider_health.clone()),
+                block_stream_statuses.clone(),
+                handler_stats.clone(),
                 node_id.clone(),
+                index_node_security,
             );
 
             // Spawn Ethereum network indexers for all networks that are to be indexed
@@ -323,6 +574,8 @@ async fn main() {
                     &eth_networks,
                     &network_stores,
                     &logger_factory,
+                    metrics_registry.clone(),
+                    provider_health,
                 );
             }
 
@@ -333,7 +586,23 @@ async fn main() {
                 node_id.clone(),
                 *REORG_THRESHOLD,
                 metrics_registry.clone(),
+                block_stream_statuses,
             );
+            // Replays for verifying proofs of indexing answer `ethereum.call`s
+            // from the call cache instead of sending them to Ethereum nodes
+            let poi_verifier = Arc::new(ProofOfIndexingVerifier::new(
+                &logger_factory,
+                link_resolver.clone(),
+                graphql_runner.clone(),
+                network_stores.clone(),
+                WASMRuntimeHostBuilder::new(
+                    replay::call_cache_networks(&eth_networks),
+                    link_resolver.clone(),
+                    network_stores.clone(),
+                    arweave_adapter.cheap_clone(),
+                    three_box_adapter.cheap_clone(),
+                ),
+            ));
             let runtime_host_builder = WASMRuntimeHostBuilder::new(
                 eth_networks.clone(),
                 link_resolver.clone(),
@@ -342,14 +611,16 @@ async fn main() {
                 three_box_adapter,
             );
 
+            let ethereum_chain = Arc::new(EthereumChain::new(eth_networks.clone(), chain_stores));
             let subgraph_instance_manager = SubgraphInstanceManager::new(
                 &logger_factory,
                 network_stores.clone(),
-                eth_networks.clone(),
+                ethereum_chain,
                 runtime_host_builder,
                 block_stream_builder,
                 metrics_registry.clone(),
                 graphql_runner.cheap_clone(),
+                handler_stats,
             );
 
             // Create IPFS-based subgraph provider
@@ -383,6 +654,7 @@ async fn main() {
                 store_builder.store(),
                 network_stores,
                 eth_networks.clone(),
+                poi_verifier,
                 node_id.clone(),
                 version_switching_mode,
             ));
@@ -400,12 +672,25 @@ async fn main() {
                 ws_port,
                 subgraph_registrar.clone(),
                 node_id.clone(),
+                admin_security,
                 logger.clone(),
             )
             .expect("failed to start JSON-RPC admin server");
 
             // Let the server run forever.
-            std::mem::forget(json_rpc_server);
+            graph::spawn(json_rpc_server);
+
+            // Deploy the subgraph from `--dev`, and deploy it again whenever
+            // its files change
+            if let Some(manifest) = dev_manifest {
+                graph::spawn(dev::run(
+                    logger.clone(),
+                    manifest,
+                    subgraph_registrar.clone(),
+                    store_builder.store(),
+                    node_id.clone(),
+                ));
+            }
 
             // Add the CLI subgraph with a REST request to the admin server.
             if let Some(subgraph) = subgraph {
@@ -507,92 +792,267 @@ async fn parse_ethereum_networks(
     let eth_rpc_metrics = Arc::new(ProviderEthRpcMetrics::new(registry));
     let mut parsed_networks = EthereumNetworks::new();
     for network_arg in networks {
-        if network_arg.starts_with("wss://")
-            || network_arg.starts_with("http://")
-            || network_arg.starts_with("https://")
-        {
-            return Err(anyhow::anyhow!(
-                "Is your Ethereum node string missing a network name? \
-                     Try 'mainnet:' + the Ethereum node URL."
-            ));
+        let ProviderConfig {
+            network: name,
+            capabilities,
+            url,
+        } = parse_provider(&network_arg)?;
+
+        info!(
+            logger,
+            "Creating transport";
+            "network" => &name,
+            "url" => &url,
+            "capabilities" => capabilities
+        );
+
+        parsed_networks.insert(
+            name.clone(),
+            capabilities,
+            create_ethereum_adapter(&name, &url, connection_type.clone(), &eth_rpc_metrics).await,
+        );
+    }
+    Ok(parsed_networks)
+}
+
+/// Parses an Ethereum connection string of the form
+/// `NETWORK_NAME:NETWORK_CAPABILITIES:URL` or `NETWORK_NAME:URL`; the
+/// capabilities default to `archive,traces`
+fn parse_provider(network_arg: &str) -> Result<ProviderConfig, anyhow::Error> {
+    if network_arg.starts_with("wss://")
+        || network_arg.starts_with("ws://")
+        || network_arg.starts_with("ipc://")
+        || network_arg.starts_with("http://")
+        || network_arg.starts_with("https://")
+    {
+        return Err(anyhow::anyhow!(
+            "Is your Ethereum node string missing a network name? \
+                 Try 'mainnet:' + the Ethereum node URL."
+        ));
+    }
+
+    let split_at = network_arg.find(':').ok_or_else(|| {
+        return anyhow::anyhow!(
+            "A network name must be provided alongside the \
+                 Ethereum node location. Try e.g. 'mainnet:URL'."
+        );
+    })?;
+
+    let (name, rest_with_delim) = network_arg.split_at(split_at);
+    let rest = &rest_with_delim[1..];
+    if name.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Ethereum network name cannot be an empty string"
+        ));
+    }
+
+    let url_split_at = rest.find(":").ok_or_else(|| {
+        return anyhow::anyhow!(
+            "A network name must be provided alongside the \
+                 Ethereum node location. Try e.g. 'mainnet:URL'."
+        );
+    })?;
+
+    let (capabilities_str, url_str) = rest.split_at(url_split_at);
+    let (url, capabilities) =
+        if vec!["http", "https", "ws", "wss", "ipc"].contains(&capabilities_str) {
+            (
+                rest,
+                NodeCapabilities {
+                    archive: true,
+                    traces: true,
+                },
+            )
         } else {
-            // Parse string (format is "NETWORK_NAME:NETWORK_CAPABILITIES:URL" OR
-            // "NETWORK_NAME::URL" which will default to NETWORK_CAPABILITIES="archive,traces")
-            let split_at = network_arg.find(':').ok_or_else(|| {
-                return anyhow::anyhow!(
-                    "A network name must be provided alongside the \
-                         Ethereum node location. Try e.g. 'mainnet:URL'."
-                );
-            })?;
+            (&url_str[1..], capabilities_str.parse()?)
+        };
 
-            let (name, rest_with_delim) = network_arg.split_at(split_at);
-            let rest = &rest_with_delim[1..];
-            if name.is_empty() {
-                return Err(anyhow::anyhow!(
-                    "Ethereum network name cannot be an empty string"
-                ));
-            }
+    if rest.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Ethereum node URL cannot be an empty string"
+        ));
+    }
 
-            let url_split_at = rest.find(":").ok_or_else(|| {
-                return anyhow::anyhow!(
-                    "A network name must be provided alongside the \
-                         Ethereum node location. Try e.g. 'mainnet:URL'."
-                );
-            })?;
+    Ok(ProviderConfig {
+        network: name.to_owned(),
+        capabilities,
+        url: url.to_owned(),
+    })
+}
 
-            let (capabilities_str, url_str) = rest.split_at(url_split_at);
-            let (url, capabilities) =
-                if vec!["http", "https", "ws", "wss"].contains(&capabilities_str) {
-                    (
-                        rest,
-                        NodeCapabilities {
-                            archive: true,
-                            traces: true,
-                        },
-                    )
-                } else {
-                    (&url_str[1..], capabilities_str.parse()?)
-                };
+async fn create_ethereum_adapter(
+    name: &str,
+    url: &str,
+    connection_type: ConnectionType,
+    eth_rpc_metrics: &Arc<ProviderEthRpcMetrics>,
+) -> Arc<dyn EthereumAdapter> {
+    // Providers passed with `--ethereum-rpc` can also be reached over
+    // WebSockets or IPC, depending on the scheme of their URL. Only those
+    // tell us about new blocks without polling
+    let transport = match connection_type {
+        ConnectionType::RPC if url.starts_with("ws://") || url.starts_with("wss://") => {
+            Transport::new_ws(url)
+        }
+        ConnectionType::RPC if url.starts_with("ipc://") => {
+            Transport::new_ipc(&url["ipc://".len()..])
+        }
+        ConnectionType::RPC => Transport::new_rpc(url),
+        ConnectionType::IPC => Transport::new_ipc(url),
+        ConnectionType::WS => Transport::new_ws(url),
+    };
+
+    Arc::new(
+        graph_chain_ethereum::EthereumAdapter::new(name, url, transport, eth_rpc_metrics.clone())
+            .await,
+    ) as Arc<dyn EthereumAdapter>
+}
 
-            if rest.is_empty() {
-                return Err(anyhow::anyhow!(
-                    "Ethereum node URL cannot be an empty string"
-                ));
-            }
+/// Read the providers in the file at `path`, one per line in the same
+/// format as `--ethereum-rpc`. Empty lines and lines starting with `#` are
+/// ignored. The error messages never contain the lines of the file since
+/// those often contain API keys
+fn read_providers_file(path: &str) -> Result<Vec<ProviderConfig>, anyhow::Error> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| anyhow::anyhow!("failed to open providers file {}: {}", path, e))?;
+    let mut providers = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let provider = parse_provider(line).map_err(|e| {
+            anyhow::anyhow!(
+                "line {} of {} is not an Ethereum provider; only providers \
+                 can be in this file, everything else, like the store, is \
+                 configured on the command line: {}",
+                number + 1,
+                path,
+                e
+            )
+        })?;
+        providers.push(provider);
+    }
+    Ok(providers)
+}
 
-            info!(
-                logger,
-                "Creating transport";
-                "network" => &name,
-                "url" => &url,
-                "capabilities" => capabilities
-            );
+/// Create the adapters for the providers in the file at `path`, grouped
+/// into sets that `reload_providers` can change later
+async fn create_provider_sets(
+    logger: &Logger,
+    path: &str,
+    eth_rpc_metrics: &Arc<ProviderEthRpcMetrics>,
+) -> (Vec<Arc<ProviderSet>>, EthereumNetworks) {
+    let configs = read_providers_file(path).expect("Failed to read Ethereum providers");
+    let mut providers = Vec::new();
+    for config in configs {
+        let adapter = create_ethereum_adapter(
+            &config.network,
+            &config.url,
+            ConnectionType::RPC,
+            eth_rpc_metrics,
+        )
+        .await;
+        info!(
+            logger,
+            "Creating transport";
+            "network" => &config.network,
+            "provider" => adapter.url_hostname(),
+            "capabilities" => config.capabilities
+        );
+        providers.push((config, adapter));
+    }
+    provider_sets(providers)
+}
 
-            let (transport_event_loop, transport) = match connection_type {
-                ConnectionType::RPC => Transport::new_rpc(url),
-                ConnectionType::IPC => Transport::new_ipc(url),
-                ConnectionType::WS => Transport::new_ws(url),
-            };
+/// Read the providers file at `path` again every time the node gets a
+/// `SIGHUP` and change the `sets` to match it
+async fn reload_providers_on_hangup(
+    logger: Logger,
+    path: String,
+    sets: Vec<Arc<ProviderSet>>,
+    eth_rpc_metrics: Arc<ProviderEthRpcMetrics>,
+) {
+    let logger = logger.new(o!("component" => "ProviderReload"));
+    let mut hangups = match tokio::signal::unix::signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!(logger, "Failed to listen for SIGHUP, Ethereum providers can not be reloaded";
+                   "error" => e.to_string());
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!(logger, "Reloading Ethereum providers"; "file" => &path);
+        if let Err(e) = reload_providers(&logger, &path, &sets, &eth_rpc_metrics).await {
+            error!(logger, "Rejected reload of Ethereum providers, keeping the current ones";
+                   "error" => e.to_string());
+        }
+    }
+}
 
-            // If we drop the event loop the transport will stop working.
-            // For now it's fine to just leak it.
-            std::mem::forget(transport_event_loop);
-
-            parsed_networks.insert(
-                name.to_string(),
-                capabilities,
-                Arc::new(
-                    graph_chain_ethereum::EthereumAdapter::new(
-                        url,
-                        transport,
-                        eth_rpc_metrics.clone(),
+/// Change the `sets` to match the providers file at `path`. The file is
+/// checked completely before any set is changed so that a reload either
+/// takes effect as a whole or not at all
+async fn reload_providers(
+    logger: &Logger,
+    path: &str,
+    sets: &[Arc<ProviderSet>],
+    eth_rpc_metrics: &Arc<ProviderEthRpcMetrics>,
+) -> Result<(), anyhow::Error> {
+    let configs = read_providers_file(path)?;
+    let changes = plan_reload(sets, &configs).map_err(|e| anyhow::anyhow!("{}", e))?;
+    if changes.is_empty() {
+        info!(logger, "Ethereum providers are unchanged");
+        return Ok(());
+    }
+
+    let mut updates = Vec::new();
+    for change in changes {
+        let expected = change
+            .set
+            .net_identifiers(logger)
+            .compat()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut added = Vec::new();
+        for config in change.added {
+            let adapter = create_ethereum_adapter(
+                &config.network,
+                &config.url,
+                ConnectionType::RPC,
+                eth_rpc_metrics,
+            )
+            .await;
+            let ident = adapter
+                .net_identifiers(logger)
+                .compat()
+                .await
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "failed to connect to new provider {}: {}",
+                        adapter.url_hostname(),
+                        e
                     )
-                    .await,
-                ) as Arc<dyn EthereumAdapter>,
-            );
+                })?;
+            if ident.net_version != expected.net_version
+                || ident.genesis_block_hash != expected.genesis_block_hash
+            {
+                return Err(anyhow::anyhow!(
+                    "new provider {} is not on network `{}`",
+                    adapter.url_hostname(),
+                    config.network
+                ));
+            }
+            added.push((config.url, adapter));
         }
+        updates.push((change.set, change.urls, added));
     }
-    Ok(parsed_networks)
+    for (set, urls, added) in updates {
+        set.update(logger, &urls, added)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+    }
+    Ok(())
 }
 
 fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<IpfsClient> {
@@ -701,6 +1161,8 @@ fn start_block_ingestor(
     eth_networks: &EthereumNetworks,
     network_stores: &HashMap<String, Arc<DieselNetworkStore>>,
     logger_factory: &LoggerFactory,
+    metrics_registry: Arc<MetricsRegistry>,
+    provider_health: Arc<ProviderHealthRegistry>,
 ) {
     // BlockIngestor must be configured to keep at least REORG_THRESHOLD ancestors,
     // otherwise BlockStream will not work properly.
@@ -710,6 +1172,8 @@ fn start_block_ingestor(
 
     info!(logger, "Starting block ingestors");
 
+    let reorg_metrics = Arc::new(ChainReorgMetrics::new(metrics_registry));
+
     // Create Ethereum block ingestors and spawn a thread to run each
     eth_networks
         .networks
@@ -731,6 +1195,8 @@ fn start_block_ingestor(
                 network_name.to_string(),
                 logger_factory,
                 block_polling_interval,
+                reorg_metrics.clone(),
+                provider_health.clone(),
             )
             .expect("failed to create Ethereum block ingestor");
 
@@ -22,6 +22,21 @@ pub struct Opt {
         help = "name and IPFS hash of the subgraph manifest"
     )]
     pub subgraph: Option<String>,
+    #[structopt(
+        long,
+        value_name = "MANIFEST",
+        conflicts_with = "subgraph",
+        help = "Deploy the subgraph from the manifest at this path under the name `dev`, and \
+                remove and deploy it again whenever the manifest or a file it refers to changes"
+    )]
+    pub dev: Option<String>,
+    #[structopt(
+        long,
+        requires = "dev",
+        help = "Start with --dev even if the store contains deployments that were not made \
+                with --dev"
+    )]
+    pub force: bool,
     #[structopt(
         long,
         value_name = "URL",
@@ -53,29 +68,39 @@ pub struct Opt {
     #[structopt(
         long,
         min_values=0,
-        required_unless_one = &["ethereum-ws", "ethereum-ipc"],
-        conflicts_with_all = &["ethereum-ws", "ethereum-ipc"],
+        required_unless_one = &["ethereum-ws", "ethereum-ipc", "ethereum-providers-file"],
+        conflicts_with_all = &["ethereum-ws", "ethereum-ipc", "ethereum-providers-file"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_RPC",
         help= "Ethereum network name (e.g. 'mainnet') and Ethereum RPC URL, separated by a ':'",
     )]
     pub ethereum_rpc: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ipc"],
-        conflicts_with_all = &["ethereum-rpc", "ethereum-ipc"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ipc", "ethereum-providers-file"],
+        conflicts_with_all = &["ethereum-rpc", "ethereum-ipc", "ethereum-providers-file"],
         value_name="NETWORK_NAME:URL",
         env="ETHEREUM_WS",
         help= "Ethereum network name (e.g. 'mainnet') and Ethereum WebSocket URL, separated by a ':'",
     )]
     pub ethereum_ws: Vec<String>,
     #[structopt(long, min_values=0,
-        required_unless_one = &["ethereum-rpc", "ethereum-ws"],
-        conflicts_with_all = &["ethereum-rpc", "ethereum-ws"],
+        required_unless_one = &["ethereum-rpc", "ethereum-ws", "ethereum-providers-file"],
+        conflicts_with_all = &["ethereum-rpc", "ethereum-ws", "ethereum-providers-file"],
         value_name="NETWORK_NAME:FILE",
         env="ETHEREUM_IPC",
         help= "Ethereum network name (e.g. 'mainnet') and Ethereum IPC pipe, separated by a ':'",
     )]
     pub ethereum_ipc: Vec<String>,
+    #[structopt(
+        long,
+        value_name = "FILE",
+        env = "ETHEREUM_PROVIDERS_FILE",
+        required_unless_one = &["ethereum-rpc", "ethereum-ws", "ethereum-ipc"],
+        conflicts_with_all = &["ethereum-rpc", "ethereum-ws", "ethereum-ipc"],
+        help = "File with one Ethereum provider per line, in the same format as --ethereum-rpc. \
+                The providers are read again when the node gets a SIGHUP"
+    )]
+    pub ethereum_providers_file: Option<String>,
     #[structopt(
         long,
         value_name = "HOST:PORT",
@@ -111,6 +136,78 @@ pub struct Opt {
         help = "Port for the JSON-RPC admin server"
     )]
     pub admin_port: u16,
+    #[structopt(
+        long,
+        value_name = "FILE",
+        env = "GRAPH_ADMIN_TLS_CERT",
+        requires = "admin-tls-key",
+        help = "PEM encoded certificate chain for serving the JSON-RPC admin server over TLS"
+    )]
+    pub admin_tls_cert: Option<String>,
+    #[structopt(
+        long,
+        value_name = "FILE",
+        env = "GRAPH_ADMIN_TLS_KEY",
+        requires = "admin-tls-cert",
+        help = "PEM encoded private key for serving the JSON-RPC admin server over TLS"
+    )]
+    pub admin_tls_key: Option<String>,
+    #[structopt(
+        long,
+        value_name = "[LABEL:]TOKEN,",
+        use_delimiter = true,
+        env = "GRAPH_ADMIN_AUTH_TOKENS",
+        hide_env_values = true,
+        help = "Comma-separated list of bearer tokens that grant access to the JSON-RPC \
+                admin server. The optional label is used in logs to identify which token \
+                was used. If no tokens are given, the admin server does not require \
+                authentication"
+    )]
+    pub admin_auth_tokens: Vec<String>,
+    #[structopt(
+        long,
+        env = "GRAPH_INDEX_NODE_AUTH",
+        help = "Also require the admin auth tokens, and use the admin TLS certificate, \
+                for the index node server"
+    )]
+    pub index_node_auth: bool,
+    #[structopt(
+        long,
+        value_name = "RATE[/BURST]",
+        env = "GRAPH_QUERY_RATE_LIMIT",
+        help = "How many queries per second each client may send to a deployment over \
+                the GraphQL HTTP and WebSocket servers, and optionally how many it may \
+                send at once. Clients that go over the limit get a 429 response"
+    )]
+    pub query_rate_limit: Option<String>,
+    #[structopt(
+        long,
+        value_name = "DEPLOYMENT=RATE[/BURST],",
+        use_delimiter = true,
+        env = "GRAPH_DEPLOYMENT_QUERY_RATE_LIMITS",
+        help = "Comma-separated list of query rate limits for specific deployments that \
+                take the place of --query-rate-limit"
+    )]
+    pub deployment_query_rate_limits: Vec<String>,
+    #[structopt(
+        long,
+        value_name = "IP[/PREFIX],",
+        use_delimiter = true,
+        env = "GRAPH_TRUSTED_PROXIES",
+        help = "Comma-separated list of addresses and networks of proxies whose \
+                --client-ip-headers are believed when rate limiting queries"
+    )]
+    pub trusted_proxies: Vec<String>,
+    #[structopt(
+        long,
+        value_name = "HEADER,",
+        use_delimiter = true,
+        default_value = "X-Forwarded-For",
+        env = "GRAPH_CLIENT_IP_HEADERS",
+        help = "Comma-separated list of headers in which trusted proxies pass on the \
+                address of the client; only the first one that a request has is used"
+    )]
+    pub client_ip_headers: Vec<String>,
     #[structopt(
         long,
         default_value = "8040",
@@ -151,6 +248,22 @@ pub struct Opt {
         help = "Password to use for Elasticsearch logging"
     )]
     pub elasticsearch_password: Option<String>,
+    #[structopt(
+        long,
+        value_name = "URL",
+        env = "GRAPH_OTLP_ENDPOINT",
+        help = "OpenTelemetry collector to export traces to with OTLP; \
+                requires a build with the `otel` feature"
+    )]
+    pub otlp_endpoint: Option<String>,
+    #[structopt(
+        long,
+        value_name = "RATE",
+        default_value = "0.01",
+        env = "GRAPH_TRACE_SAMPLE_RATE",
+        help = "The fraction of blocks and queries to export traces for"
+    )]
+    pub trace_sample_rate: f64,
     #[structopt(
         long,
         value_name = "MILLISECONDS",
@@ -365,7 +365,8 @@ impl StoreValueKind {
             Value::List(_) => StoreValueKind::Array,
             Value::Null => StoreValueKind::Null,
             Value::Bytes(_) => StoreValueKind::Bytes,
-            Value::BigInt(_) => StoreValueKind::BigInt,
+            // Mappings see timestamps as microseconds since the epoch
+            Value::BigInt(_) | Value::Timestamp(_) => StoreValueKind::BigInt,
         }
     }
 }
@@ -418,6 +419,28 @@ pub(crate) struct AscEthereumBlock {
     pub size: AscPtr<AscBigInt>,
 }
 
+/// The block that block handlers receive from apiVersion 0.0.7 on. It adds
+/// the number of transactions in the block
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumBlock_0_0_7 {
+    pub hash: AscPtr<AscH256>,
+    pub parent_hash: AscPtr<AscH256>,
+    pub uncles_hash: AscPtr<AscH256>,
+    pub author: AscPtr<AscH160>,
+    pub state_root: AscPtr<AscH256>,
+    pub transactions_root: AscPtr<AscH256>,
+    pub receipts_root: AscPtr<AscH256>,
+    pub number: AscPtr<AscBigInt>,
+    pub gas_used: AscPtr<AscBigInt>,
+    pub gas_limit: AscPtr<AscBigInt>,
+    pub timestamp: AscPtr<AscBigInt>,
+    pub difficulty: AscPtr<AscBigInt>,
+    pub total_difficulty: AscPtr<AscBigInt>,
+    pub size: AscPtr<AscBigInt>,
+    pub transaction_count: AscPtr<AscBigInt>,
+}
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumTransaction {
@@ -516,6 +539,17 @@ pub(crate) struct AscUnresolvedContractCall_0_0_4 {
     pub function_args: AscPtr<Array<AscPtr<AscEnum<EthereumValueKind>>>>,
 }
 
+/// The result of `ethereum.call` from apiVersion 0.0.6 on. For calls that
+/// reverted, `value` is null and `revert_reason` says why; `revert_data` is
+/// null if the Ethereum node did not say what the contract reverted with
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEthereumCallResult {
+    pub value: AscPtr<Array<AscPtr<AscEnum<EthereumValueKind>>>>,
+    pub revert_reason: AscPtr<AscString>,
+    pub revert_data: AscPtr<Uint8Array>,
+}
+
 #[repr(u32)]
 #[derive(AscType, Copy, Clone)]
 pub(crate) enum JsonValueKind {
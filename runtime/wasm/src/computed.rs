@@ -0,0 +1,399 @@
+use graphql_parser::query as q;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use wasmtime::{Val, ValType};
+
+use futures03::channel::oneshot;
+use graph::data::graphql::computed::{ComputedField, ComputedFunctions, ComputedQuery};
+use graph::prelude::*;
+
+use crate::mapping::ValidModule;
+
+lazy_static! {
+    /// How long the computed functions of a query may run; the functions
+    /// are interrupted once this much time has passed since the query
+    /// started computing fields. Defaults to one second
+    static ref TIMEOUT: Duration = std::env::var("GRAPH_COMPUTED_FIELD_TIMEOUT")
+        .ok()
+        .map(|s| u64::from_str(&s).expect("Invalid value for GRAPH_COMPUTED_FIELD_TIMEOUT"))
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(1));
+}
+
+/// Computed functions only work with numbers and do not need much memory
+const MAX_MEMORY: u64 = 16 * 1024 * 1024;
+
+/// The compiled `computed` module of a deployment
+pub struct ComputedModule {
+    valid_module: ValidModule,
+}
+
+impl ComputedModule {
+    pub fn new(raw_module: &[u8]) -> Result<Self, anyhow::Error> {
+        Ok(ComputedModule {
+            valid_module: ValidModule::new(raw_module, MAX_MEMORY)?,
+        })
+    }
+
+    /// Start evaluating computed fields for one query. Computing fields
+    /// fails once `timeout` has passed
+    pub fn query(&self, timeout: Duration) -> Result<WasmComputedQuery, String> {
+        let module = self.valid_module.module.clone();
+        let store = wasmtime::Store::new(module.engine());
+
+        // The watchdog stops waiting when the query drops `cancel`
+        let interrupt_handle = store.interrupt_handle().map_err(|e| e.to_string())?;
+        let (cancel, cancelled) = oneshot::channel::<()>();
+        graph::spawn_allow_panic(async move {
+            if tokio::time::timeout(timeout, cancelled).await.is_err() {
+                interrupt_handle.interrupt();
+            }
+        });
+
+        Ok(WasmComputedQuery {
+            module,
+            store,
+            functions: HashMap::new(),
+            timeout,
+            deadline: Instant::now() + timeout,
+            _cancel: cancel,
+        })
+    }
+}
+
+/// The evaluation of computed fields for one query. Each function that the
+/// query uses is instantiated once and then called for all the entities
+/// of the query, since instantiating the module for every value would
+/// make queries for many entities slow. Reusing an instance is safe because
+/// the module can not import anything; a function that keeps state in its
+/// globals or memory can only affect later calls in the same query
+pub struct WasmComputedQuery {
+    module: wasmtime::Module,
+    store: wasmtime::Store,
+    functions: HashMap<String, wasmtime::Func>,
+    timeout: Duration,
+    /// The watchdog interrupts calls that are still running at the
+    /// deadline; later calls are not even started
+    deadline: Instant,
+    /// Dropping this stops the watchdog of the query
+    _cancel: oneshot::Sender<()>,
+}
+
+impl WasmComputedQuery {
+    fn function(&mut self, name: &str) -> Result<wasmtime::Func, String> {
+        if let Some(func) = self.functions.get(name) {
+            return Ok(func.clone());
+        }
+
+        let instance =
+            wasmtime::Instance::new(&self.store, &self.module, &[]).map_err(|e| e.to_string())?;
+        let func = instance.get_func(name).ok_or_else(|| {
+            format!(
+                "the computed module does not export the function `{}`",
+                name
+            )
+        })?;
+        self.functions.insert(name.to_owned(), func.clone());
+        Ok(func)
+    }
+}
+
+impl ComputedQuery for WasmComputedQuery {
+    /// Call the function of `field` with `args`. The field is `null` if any
+    /// of the fields it is computed from is `null`
+    fn compute(&mut self, field: &ComputedField, args: &[q::Value]) -> Result<q::Value, String> {
+        if args.iter().any(|arg| arg == &q::Value::Null) {
+            return Ok(q::Value::Null);
+        }
+
+        if Instant::now() >= self.deadline {
+            return Err(format!(
+                "the query hit the timeout of {} seconds for computed fields",
+                self.timeout.as_secs()
+            ));
+        }
+
+        let func = self.function(&field.function)?;
+        let ty = func.ty();
+        if ty.params().len() != args.len() {
+            return Err(format!(
+                "the function `{}` takes {} arguments but the field is computed from {} fields",
+                field.function,
+                ty.params().len(),
+                args.len()
+            ));
+        }
+        let params = ty
+            .params()
+            .iter()
+            .zip(args.iter().zip(&field.from))
+            .map(|(ty, (arg, name))| {
+                to_wasm(ty, arg).map_err(|e| {
+                    format!(
+                        "can not pass `{}` to the function `{}`: {}",
+                        name, field.function, e
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let results = func.call(&params).map_err(|e| {
+            format!(
+                "the function `{}` failed or the query hit the timeout of {} seconds \
+                 for computed fields: {}",
+                field.function,
+                self.timeout.as_secs(),
+                e
+            )
+        })?;
+
+        match &*results {
+            [result] => from_wasm(&field.field_type, result),
+            _ => Err(format!(
+                "the function `{}` must return exactly one value",
+                field.function
+            )),
+        }
+    }
+}
+
+/// Convert the value of a field to the type of a parameter of a computed
+/// function. `BigInt` and `BigDecimal` values must fit into that type
+fn to_wasm(ty: &ValType, value: &q::Value) -> Result<Val, String> {
+    let out_of_range = |value: &dyn std::fmt::Display| format!("{} is out of range", value);
+    match (ty, value) {
+        (ValType::I32, q::Value::Boolean(b)) => Ok(Val::I32(*b as i32)),
+        (ValType::I64, q::Value::Boolean(b)) => Ok(Val::I64(*b as i64)),
+        (ValType::I32, q::Value::Int(n)) => n
+            .as_i64()
+            .and_then(|n| i32::try_from(n).ok())
+            .map(Val::I32)
+            .ok_or_else(|| out_of_range(&format!("{:?}", n))),
+        (ValType::I64, q::Value::Int(n)) => n
+            .as_i64()
+            .map(Val::I64)
+            .ok_or_else(|| out_of_range(&format!("{:?}", n))),
+        (ValType::F64, q::Value::Int(n)) => n
+            .as_i64()
+            .map(|n| Val::F64((n as f64).to_bits()))
+            .ok_or_else(|| out_of_range(&format!("{:?}", n))),
+        // `BigInt` and `BigDecimal` values are strings in queries
+        (ValType::I32, q::Value::String(s)) => {
+            i32::from_str(s).map(Val::I32).map_err(|_| out_of_range(s))
+        }
+        (ValType::I64, q::Value::String(s)) => {
+            i64::from_str(s).map(Val::I64).map_err(|_| out_of_range(s))
+        }
+        (ValType::F64, q::Value::String(s)) => f64::from_str(s)
+            .map(|f| Val::F64(f.to_bits()))
+            .map_err(|_| out_of_range(s)),
+        (ty, value) => Err(format!("can not convert {} to {:?}", value, ty)),
+    }
+}
+
+/// Convert the result of a computed function to a value of the scalar type
+/// `field_type`
+fn from_wasm(field_type: &str, result: &Val) -> Result<q::Value, String> {
+    let n = match result {
+        Val::I32(n) => Some(*n as i64),
+        Val::I64(n) => Some(*n),
+        _ => None,
+    };
+    match (field_type, n, result) {
+        ("Int", Some(n), _) => i32::try_from(n)
+            .map(|n| q::Value::Int(q::Number::from(n)))
+            .map_err(|_| format!("{} is too big for an Int", n)),
+        ("BigInt", Some(n), _) => Ok(q::Value::String(BigInt::from(n).to_string())),
+        ("BigDecimal", Some(n), _) => Ok(q::Value::String(BigDecimal::from(n).to_string())),
+        ("BigDecimal", None, Val::F64(bits)) => {
+            let f = f64::from_bits(*bits);
+            if f.is_finite() {
+                Ok(q::Value::String(BigDecimal::from(f).to_string()))
+            } else {
+                Err(format!("{} is not a BigDecimal", f))
+            }
+        }
+        ("Boolean", _, Val::I32(b)) => Ok(q::Value::Boolean(*b != 0)),
+        _ => Err(format!(
+            "can not convert {:?} to a value of type {}",
+            result, field_type
+        )),
+    }
+}
+
+/// Evaluates computed fields with the `computed` modules of deployments,
+/// which it loads from the store and compiles the first time that a query
+/// needs them
+pub struct WasmComputedFunctions<S> {
+    store: Arc<S>,
+    modules: Mutex<HashMap<SubgraphDeploymentId, Option<Arc<ComputedModule>>>>,
+}
+
+impl<S: SubgraphDeploymentStore> WasmComputedFunctions<S> {
+    pub fn new(store: Arc<S>) -> Self {
+        WasmComputedFunctions {
+            store,
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn module(
+        &self,
+        deployment: &SubgraphDeploymentId,
+    ) -> Result<Option<Arc<ComputedModule>>, String> {
+        if let Some(module) = self.modules.lock().unwrap().get(deployment) {
+            return Ok(module.clone());
+        }
+
+        // Deployments can not change their manifest, and it is therefore
+        // safe to keep their modules for as long as the node runs
+        let module = match self
+            .store
+            .computed_module(deployment)
+            .map_err(|e| e.to_string())?
+        {
+            Some(raw_module) => Some(Arc::new(
+                ComputedModule::new(&raw_module).map_err(|e| e.to_string())?,
+            )),
+            None => None,
+        };
+        self.modules
+            .lock()
+            .unwrap()
+            .insert(deployment.clone(), module.clone());
+        Ok(module)
+    }
+}
+
+impl<S: SubgraphDeploymentStore> ComputedFunctions for WasmComputedFunctions<S> {
+    fn query(&self, deployment: &SubgraphDeploymentId) -> Result<Box<dyn ComputedQuery>, String> {
+        match self.module(deployment)? {
+            Some(module) => Ok(Box::new(module.query(*TIMEOUT)?)),
+            None => Err(format!(
+                "the deployment {} does not have a computed module",
+                deployment
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAT: &str = r#"
+        (module
+          (func (export "playedTwice") (param i64) (result i64)
+            local.get 0
+            i64.const 2
+            i64.mul)
+          (func (export "valueUSD") (param f64 f64) (result f64)
+            local.get 0
+            local.get 1
+            f64.mul)
+          (func (export "isEven") (param i32) (result i32)
+            local.get 0
+            i32.const 1
+            i32.and
+            i32.eqz)
+          (func (export "forever") (param i32) (result i32)
+            (loop (br 0))
+            local.get 0)
+          (global $calls (mut i32) (i32.const 0))
+          (func (export "calls") (param i32) (result i32)
+            global.get $calls
+            i32.const 1
+            i32.add
+            global.set $calls
+            global.get $calls))
+    "#;
+
+    fn field(name: &str, field_type: &str, from: &[&str]) -> ComputedField {
+        ComputedField {
+            name: name.to_owned(),
+            field_type: field_type.to_owned(),
+            function: name.to_owned(),
+            from: from.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn int(n: i32) -> q::Value {
+        q::Value::Int(q::Number::from(n))
+    }
+
+    fn string(s: &str) -> q::Value {
+        q::Value::String(s.to_owned())
+    }
+
+    #[tokio::test]
+    async fn compute() {
+        let module = ComputedModule::new(&wat::parse_str(WAT).unwrap()).unwrap();
+        let mut query = module.query(Duration::from_secs(1)).unwrap();
+        let mut compute = |field: &ComputedField, args: &[q::Value]| query.compute(field, args);
+
+        let played_twice = field("playedTwice", "Int", &["played"]);
+        assert_eq!(Ok(int(20)), compute(&played_twice, &[int(10)]));
+        assert_eq!(
+            Ok(q::Value::Null),
+            compute(&played_twice, &[q::Value::Null])
+        );
+        // The result does not fit into an `Int`
+        assert!(compute(&played_twice, &[int(std::i32::MAX)]).is_err());
+
+        let played_twice = field("playedTwice", "BigInt", &["played"]);
+        assert_eq!(
+            Ok(string("4294967294")),
+            compute(&played_twice, &[string("2147483647")])
+        );
+        assert!(compute(&played_twice, &[string("not a number")]).is_err());
+
+        let value_usd = field("valueUSD", "BigDecimal", &["amount", "price"]);
+        assert_eq!(
+            Ok(string("3")),
+            compute(&value_usd, &[string("1.5"), int(2)])
+        );
+        assert!(compute(&value_usd, &[string("1.5")]).is_err());
+
+        let is_even = field("isEven", "Boolean", &["played"]);
+        assert_eq!(Ok(q::Value::Boolean(true)), compute(&is_even, &[int(4)]));
+        assert_eq!(
+            Ok(q::Value::Boolean(false)),
+            compute(&is_even, &[q::Value::Boolean(true)])
+        );
+
+        let missing = field("missing", "Int", &["played"]);
+        assert!(compute(&missing, &[int(1)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn instantiates_functions_once_per_query() {
+        let module = ComputedModule::new(&wat::parse_str(WAT).unwrap()).unwrap();
+        let calls = field("calls", "Int", &["played"]);
+
+        let mut query = module.query(Duration::from_secs(1)).unwrap();
+        assert_eq!(Ok(int(1)), query.compute(&calls, &[int(0)]));
+        assert_eq!(Ok(int(2)), query.compute(&calls, &[int(0)]));
+        assert_eq!(1, query.functions.len());
+
+        // The next query starts with a new instance
+        let mut query = module.query(Duration::from_secs(1)).unwrap();
+        assert_eq!(Ok(int(1)), query.compute(&calls, &[int(0)]));
+    }
+
+    // The watchdog needs to run while the call blocks the test's thread
+    #[tokio::test(threaded_scheduler)]
+    async fn compute_times_out() {
+        let module = ComputedModule::new(&wat::parse_str(WAT).unwrap()).unwrap();
+        let forever = field("forever", "Int", &["played"]);
+        let played_twice = field("playedTwice", "Int", &["played"]);
+
+        let mut query = module.query(Duration::from_millis(10)).unwrap();
+        let err = query.compute(&forever, &[int(1)]).unwrap_err();
+        assert!(err.contains("timeout"), "{}", err);
+        // The deadline is for the whole query
+        assert!(query.compute(&played_twice, &[int(1)]).is_err());
+    }
+}
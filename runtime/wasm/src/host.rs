@@ -15,15 +15,16 @@ use tiny_keccak::keccak256;
 
 use graph::components::arweave::ArweaveAdapter;
 use graph::components::ethereum::*;
-use graph::components::store::Store;
+use graph::components::store::EntityStore;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
+use graph::components::trace::Span;
 use graph::data::subgraph::{Mapping, Source};
 use graph::prelude::{
     RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
 };
 use graph::util;
-use web3::types::{Log, Transaction};
+use web3::types::{Address, Log, Transaction};
 
 use crate::host_exports::HostExports;
 use crate::mapping::{MappingContext, MappingRequest, MappingTrigger};
@@ -35,6 +36,13 @@ lazy_static! {
         .map(Duration::from_secs);
     static ref ALLOW_NON_DETERMINISTIC_IPFS: bool =
         std::env::var("GRAPH_ALLOW_NON_DETERMINISTIC_IPFS").is_ok();
+
+    /// The most WASM memory, in bytes, that a handler may use unless the limit was changed for
+    /// a deployment. Defaults to 1GiB
+    static ref MAX_MEMORY: u64 = std::env::var("GRAPH_MAPPING_MAX_MEMORY")
+        .ok()
+        .map(|s| u64::from_str(&s).expect("Invalid value for GRAPH_MAPPING_MAX_MEMORY"))
+        .unwrap_or(1024 * 1024 * 1024);
 }
 
 struct RuntimeHostConfig {
@@ -57,7 +65,7 @@ pub struct RuntimeHostBuilder<S> {
 
 impl<S> Clone for RuntimeHostBuilder<S>
 where
-    S: Store,
+    S: EntityStore,
 {
     fn clone(&self) -> Self {
         RuntimeHostBuilder {
@@ -72,7 +80,7 @@ where
 
 impl<S> RuntimeHostBuilder<S>
 where
-    S: Store + SubgraphDeploymentStore + EthereumCallCache,
+    S: EntityStore + SubgraphDeploymentStore + EthereumCallCache,
 {
     pub fn new(
         ethereum_networks: EthereumNetworks,
@@ -93,7 +101,7 @@ where
 
 impl<S> RuntimeHostBuilderTrait for RuntimeHostBuilder<S>
 where
-    S: Send + Sync + 'static + Store + SubgraphDeploymentStore + EthereumCallCache,
+    S: Send + Sync + 'static + EntityStore + SubgraphDeploymentStore + EthereumCallCache,
 {
     type Host = RuntimeHost;
     type Req = MappingRequest;
@@ -103,6 +111,7 @@ where
         logger: Logger,
         subgraph_id: SubgraphDeploymentId,
         metrics: Arc<HostMetrics>,
+        max_memory: Option<u64>,
     ) -> Result<Sender<Self::Req>, anyhow::Error> {
         crate::mapping::spawn_module(
             raw_module,
@@ -112,6 +121,7 @@ where
             tokio::runtime::Handle::current(),
             *TIMEOUT,
             *ALLOW_NON_DETERMINISTIC_IPFS,
+            max_memory.unwrap_or(*MAX_MEMORY),
         )
     }
 
@@ -136,6 +146,8 @@ where
         let ethereum_adapter = self
             .ethereum_networks
             .adapter_with_capabilities(network_name.clone(), &required_capabilities)?;
+        let ethereum_adapter =
+            replay::adapter_for_deployment(&subgraph_id, ethereum_adapter.clone())?;
 
         // Detect whether the subgraph uses templates in data sources, which are
         // deprecated, or the top-level templates field.
@@ -192,9 +204,9 @@ impl RuntimeHost {
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
     ) -> Result<Self, Error> {
         let api_version = Version::parse(&config.mapping.api_version)?;
-        if !VersionReq::parse("<= 0.0.4").unwrap().matches(&api_version) {
+        if !VersionReq::parse("<= 0.0.8").unwrap().matches(&api_version) {
             return Err(format_err!(
-                "This Graph Node only supports mapping API versions <= 0.0.4, but subgraph `{}` uses `{}`",
+                "This Graph Node only supports mapping API versions <= 0.0.8, but subgraph `{}` uses `{}`",
                 config.subgraph_id,
                 api_version
             ));
@@ -215,6 +227,7 @@ impl RuntimeHost {
             .clone();
 
         let data_source_name = config.data_source_name;
+        let strict_bytes = store.strict_bytes(&config.subgraph_id)?;
 
         // Create new instance of externally hosted functions invoker. The `Arc` is simply to avoid
         // implementing `Clone` for `HostExports`.
@@ -225,11 +238,13 @@ impl RuntimeHost {
             config.contract.address.clone(),
             config.data_source_network,
             config.data_source_context,
+            DeclaredEntities::new(*UNDECLARED_ENTITY_WRITES, &config.mapping.entities),
             config.templates,
             config.mapping.abis,
             ethereum_adapter,
             link_resolver,
             store,
+            strict_bytes,
             call_cache,
             arweave_adapter,
             three_box_adapter,
@@ -250,11 +265,11 @@ impl RuntimeHost {
 
     fn matches_call_address(&self, call: &EthereumCall) -> bool {
         // The runtime host matches the contract address of the `EthereumCall`
-        // if the data source contains the same contract address or
-        // if the data source doesn't have a contract address at all
+        // if the data source contains the same contract address, from the
+        // start block of that address on, or if the data source doesn't have
+        // a contract address at all
         self.data_source_contract
-            .address
-            .map_or(true, |addr| addr == call.to)
+            .matches_address(&call.to, call.block_number)
     }
 
     fn matches_call_function(&self, call: &EthereumCall) -> bool {
@@ -268,11 +283,11 @@ impl RuntimeHost {
 
     fn matches_log_address(&self, log: &Log) -> bool {
         // The runtime host matches the contract address of the `Log`
-        // if the data source contains the same contract address or
-        // if the data source doesn't have a contract address at all
+        // if the data source contains the same contract address, from the
+        // start block of that address on, or if the data source doesn't have
+        // a contract address at all
         self.data_source_contract
-            .address
-            .map_or(true, |addr| addr == log.address)
+            .matches_address(&log.address, log.block_number.unwrap().as_u64())
     }
 
     fn matches_log_signature(&self, log: &Log) -> bool {
@@ -286,15 +301,22 @@ impl RuntimeHost {
             .any(|handler| *topic0 == handler.topic0())
     }
 
-    fn matches_block_trigger(&self, block_trigger_type: &EthereumBlockTriggerType) -> bool {
+    fn matches_block_trigger(
+        &self,
+        block_trigger_type: &EthereumBlockTriggerType,
+        block_number: u64,
+    ) -> bool {
         let source_address_matches = match block_trigger_type {
             EthereumBlockTriggerType::WithCallTo(address) => {
-                self.data_source_contract
-                    .address
-                    // Do not match if this datasource has no address
-                    .map_or(false, |addr| addr == *address)
+                // Do not match if this datasource has no address
+                !self.data_source_contract.is_wildcard()
+                    && self
+                        .data_source_contract
+                        .matches_address(address, block_number)
+            }
+            EthereumBlockTriggerType::Every => {
+                self.data_source_contract.start_block <= block_number
             }
-            EthereumBlockTriggerType::Every => true,
         };
         source_address_matches && self.handler_for_block(block_trigger_type).is_ok()
     }
@@ -381,7 +403,9 @@ impl RuntimeHost {
     }
 
     /// Sends a MappingRequest to the thread which owns the host,
-    /// and awaits the result.
+    /// and awaits the result. The `address` of the contract that caused the
+    /// trigger is what `dataSource.address()` returns for data sources with
+    /// several `addresses`
     async fn send_mapping_request<T: slog::SendSyncRefUnwindSafeKV>(
         &self,
         logger: &Logger,
@@ -389,10 +413,12 @@ impl RuntimeHost {
         state: BlockState,
         handler: &str,
         trigger: MappingTrigger,
+        address: Option<Address>,
         block: &Arc<LightEthereumBlock>,
         proof_of_indexing: SharedProofOfIndexing,
     ) -> Result<BlockState, MappingError> {
         let trigger_type = trigger.as_static();
+        let trigger_address = address.filter(|_| !self.data_source_contract.addresses.is_empty());
         debug!(
             logger, "Start processing Ethereum trigger";
             &extra,
@@ -401,6 +427,12 @@ impl RuntimeHost {
             "data_source" => &self.data_source_name,
         );
 
+        // The span of the block is current while its triggers are processed
+        let span = Span::current_child("handler");
+        span.set_str("handler", handler);
+        span.set_str("data_source", &self.data_source_name);
+        span.set_str("trigger_type", trigger_type);
+
         let (result_sender, result_receiver) = channel();
         let start_time = Instant::now();
         let metrics = self.metrics.clone();
@@ -413,7 +445,9 @@ impl RuntimeHost {
                     state,
                     host_exports: self.host_exports.cheap_clone(),
                     block: block.cheap_clone(),
+                    trigger_address,
                     proof_of_indexing,
+                    trace: span.context(),
                 },
                 trigger,
                 result_sender,
@@ -428,6 +462,12 @@ impl RuntimeHost {
 
         let elapsed = start_time.elapsed();
         metrics.observe_handler_execution_time(elapsed.as_secs_f64(), handler);
+        match &result {
+            Ok(_) => (),
+            Err(MappingError::PossibleReorg(e))
+            | Err(MappingError::Deterministic(e))
+            | Err(MappingError::Unknown(e)) => span.set_error(e),
+        }
 
         info!(
             logger, "Done processing Ethereum trigger";
@@ -454,15 +494,11 @@ impl RuntimeHost {
 #[async_trait]
 impl RuntimeHostTrait for RuntimeHost {
     fn matches_log(&self, log: &Log) -> bool {
-        self.matches_log_address(log)
-            && self.matches_log_signature(log)
-            && self.data_source_contract.start_block <= log.block_number.unwrap().as_u64()
+        self.matches_log_address(log) && self.matches_log_signature(log)
     }
 
     fn matches_call(&self, call: &EthereumCall) -> bool {
-        self.matches_call_address(call)
-            && self.matches_call_function(call)
-            && self.data_source_contract.start_block <= call.block_number
+        self.matches_call_address(call) && self.matches_call_function(call)
     }
 
     fn matches_block(
@@ -470,8 +506,7 @@ impl RuntimeHostTrait for RuntimeHost {
         block_trigger_type: &EthereumBlockTriggerType,
         block_number: u64,
     ) -> bool {
-        self.matches_block_trigger(block_trigger_type)
-            && self.data_source_contract.start_block <= block_number
+        self.matches_block_trigger(block_trigger_type, block_number)
     }
 
     async fn process_call(
@@ -576,6 +611,7 @@ impl RuntimeHostTrait for RuntimeHost {
                 outputs,
                 handler: call_handler.clone(),
             },
+            Some(call.to),
             block,
             proof_of_indexing,
         )
@@ -602,6 +638,10 @@ impl RuntimeHostTrait for RuntimeHost {
             MappingTrigger::Block {
                 handler: block_handler.clone(),
             },
+            match trigger_type {
+                EthereumBlockTriggerType::WithCallTo(address) => Some(*address),
+                EthereumBlockTriggerType::Every => None,
+            },
             block,
             proof_of_indexing,
         )
@@ -716,6 +756,7 @@ impl RuntimeHostTrait for RuntimeHost {
                 params,
                 handler: event_handler.clone(),
             },
+            Some(log.address),
             block,
             proof_of_indexing,
         )
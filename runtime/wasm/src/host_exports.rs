@@ -4,7 +4,7 @@ use ethabi::{Address, Token};
 use graph::components::arweave::ArweaveAdapter;
 use graph::components::ethereum::*;
 use graph::components::store::EntityKey;
-use graph::components::subgraph::{ProofOfIndexingEvent, SharedProofOfIndexing};
+use graph::components::subgraph::{MappingError, ProofOfIndexingEvent, SharedProofOfIndexing};
 use graph::components::three_box::ThreeBoxAdapter;
 use graph::data::store;
 use graph::prelude::serde_json;
@@ -16,7 +16,9 @@ use std::str::FromStr;
 use std::time::{Duration, Instant};
 use web3::types::H160;
 
-use graph_graphql::prelude::validate_entity;
+use graph_graphql::prelude::{
+    canonical_id, canonicalize_bytes, coerce_timestamps, validate_entity,
+};
 
 use crate::module::{WasmInstance, WasmInstanceContext};
 
@@ -50,10 +52,12 @@ impl From<failure::Error> for HostExportError {
 pub(crate) struct HostExports {
     subgraph_id: SubgraphDeploymentId,
     pub(crate) api_version: Version,
-    data_source_name: String,
+    pub(crate) data_source_name: String,
     data_source_address: Option<Address>,
     data_source_network: String,
     data_source_context: Option<DataSourceContext>,
+    /// The entity types that the handlers of the data source may write to
+    pub(crate) declared_entities: DeclaredEntities,
     /// Some data sources have indeterminism or different notions of time. These
     /// need to be each be stored separately to separate causality between them,
     /// and merge the results later. Right now, this is just the ethereum
@@ -65,6 +69,8 @@ pub(crate) struct HostExports {
     pub(crate) link_resolver: Arc<dyn LinkResolver>,
     call_cache: Arc<dyn EthereumCallCache>,
     store: Arc<dyn crate::RuntimeStore>,
+    /// Whether the manifest of the subgraph sets `strictBytes`
+    strict_bytes: bool,
     arweave_adapter: Arc<dyn ArweaveAdapter>,
     three_box_adapter: Arc<dyn ThreeBoxAdapter>,
 }
@@ -84,11 +90,13 @@ impl HostExports {
         data_source_address: Option<Address>,
         data_source_network: String,
         data_source_context: Option<DataSourceContext>,
+        declared_entities: DeclaredEntities,
         templates: Arc<Vec<DataSourceTemplate>>,
         abis: Vec<MappingABI>,
         ethereum_adapter: Arc<dyn EthereumAdapter>,
         link_resolver: Arc<dyn LinkResolver>,
         store: Arc<dyn crate::RuntimeStore>,
+        strict_bytes: bool,
         call_cache: Arc<dyn EthereumCallCache>,
         arweave_adapter: Arc<dyn ArweaveAdapter>,
         three_box_adapter: Arc<dyn ThreeBoxAdapter>,
@@ -102,6 +110,7 @@ impl HostExports {
             data_source_address,
             data_source_network,
             data_source_context,
+            declared_entities,
             causality_region,
             templates,
             abis,
@@ -109,6 +118,7 @@ impl HostExports {
             link_resolver,
             call_cache,
             store,
+            strict_bytes,
             arweave_adapter,
             three_box_adapter,
         }
@@ -151,9 +161,7 @@ impl HostExports {
         entity_type: String,
         entity_id: String,
         mut data: HashMap<String, Value>,
-    ) -> Result<(), anyhow::Error> {
-        use graph::prelude::failure::ResultExt;
-
+    ) -> Result<(), HostExportError> {
         if let Some(proof_of_indexing) = proof_of_indexing {
             let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
             proof_of_indexing.write(
@@ -170,43 +178,80 @@ impl HostExports {
         // Automatically add an "id" value
         match data.insert("id".to_string(), Value::String(entity_id.clone())) {
             Some(ref v) if v != &Value::String(entity_id.clone()) => {
-                anyhow::bail!(
+                return Err(HostExportError::Unknown(anyhow::anyhow!(
                     "Value of {} attribute 'id' conflicts with ID passed to `store.set()`: \
                      {} != {}",
                     entity_type,
                     v,
                     entity_id,
-                );
+                )));
             }
             _ => (),
         }
 
+        let entity_id = self.entity_id(&entity_type, entity_id)?;
         let key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
-            entity_type,
+            entity_type: entity_type.into(),
             entity_id,
         };
-        let entity = Entity::from(data);
-        let schema = self.store.input_schema(&self.subgraph_id).compat()?;
+        let mut entity = Entity::from(data);
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        coerce_timestamps(&schema.document, &key, &mut entity)
+            .map_err(HostExportError::Deterministic)?;
+        if self.strict_bytes {
+            canonicalize_bytes(&schema.document, &key, &mut entity)
+                .map_err(HostExportError::Deterministic)?;
+        }
+
+        // Validate the changes against the subgraph schema. If the set of
+        // fields we have is already valid, avoid hitting the DB. Otherwise,
+        // the entity is validated with its final state once the block is
+        // done, which saves loading and checking it on every `store.set`
         let is_valid = validate_entity(&schema.document, &key, &entity).is_ok();
-        state.entity_cache.set(key.clone(), entity).compat()?;
-
-        // Validate the changes against the subgraph schema.
-        // If the set of fields we have is already valid, avoid hitting the DB.
-        if !is_valid
-            && self
-                .store
-                .uses_relational_schema(&self.subgraph_id)
-                .compat()?
-        {
-            let entity = state
+        if is_valid || !self.store.uses_relational_schema(&self.subgraph_id)? {
+            state.entity_cache.set(key, entity)
+        } else {
+            state
                 .entity_cache
-                .get(&key)
-                .compat()?
-                .expect("we just stored this entity");
-            validate_entity(&schema.document, &key, &entity)?;
+                .set_unvalidated(key, entity, Arc::new(SchemaValidator(schema)))
         }
-        Ok(())
+        .map_err(entity_cache_error)
+    }
+
+    pub(crate) fn store_increment(
+        &self,
+        logger: &Logger,
+        state: &mut BlockState,
+        proof_of_indexing: &SharedProofOfIndexing,
+        entity_type: String,
+        entity_id: String,
+        field: String,
+        amount: BigInt,
+    ) -> Result<(), HostExportError> {
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
+            proof_of_indexing.write(
+                logger,
+                &self.causality_region,
+                &ProofOfIndexingEvent::IncrementField {
+                    entity_type: &entity_type,
+                    id: &entity_id,
+                    field: &field,
+                    amount: &amount,
+                },
+            );
+        }
+        let entity_id = self.entity_id(&entity_type, entity_id)?;
+        let key = EntityKey {
+            subgraph_id: self.subgraph_id.clone(),
+            entity_type: entity_type.into(),
+            entity_id,
+        };
+        state
+            .entity_cache
+            .increment(key, &field, &amount)
+            .map_err(entity_cache_error)
     }
 
     pub(crate) fn store_remove(
@@ -216,7 +261,7 @@ impl HostExports {
         proof_of_indexing: &SharedProofOfIndexing,
         entity_type: String,
         entity_id: String,
-    ) {
+    ) -> Result<(), HostExportError> {
         if let Some(proof_of_indexing) = proof_of_indexing {
             let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
             proof_of_indexing.write(
@@ -228,12 +273,46 @@ impl HostExports {
                 },
             );
         }
+        let entity_id = self.entity_id(&entity_type, entity_id)?;
         let key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
-            entity_type,
+            entity_type: entity_type.into(),
             entity_id,
         };
-        state.entity_cache.remove(key);
+        state.entity_cache.remove(key).map_err(entity_cache_error)
+    }
+
+    /// Like calling `store_remove` for each of `entity_ids`, including the
+    /// proof of indexing, but with only one host call
+    pub(crate) fn store_remove_many(
+        &self,
+        logger: &Logger,
+        state: &mut BlockState,
+        proof_of_indexing: &SharedProofOfIndexing,
+        entity_type: String,
+        entity_ids: Vec<String>,
+    ) -> Result<(), HostExportError> {
+        if let Some(proof_of_indexing) = proof_of_indexing {
+            let mut proof_of_indexing = proof_of_indexing.deref().borrow_mut();
+            for entity_id in &entity_ids {
+                proof_of_indexing.write(
+                    logger,
+                    &self.causality_region,
+                    &ProofOfIndexingEvent::RemoveEntity {
+                        entity_type: &entity_type,
+                        id: entity_id,
+                    },
+                );
+            }
+        }
+        let entity_ids = entity_ids
+            .into_iter()
+            .map(|entity_id| self.entity_id(&entity_type, entity_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        state
+            .entity_cache
+            .remove_many(&self.subgraph_id, &entity_type, entity_ids)
+            .map_err(entity_cache_error)
     }
 
     pub(crate) fn store_get(
@@ -241,23 +320,66 @@ impl HostExports {
         state: &mut BlockState,
         entity_type: String,
         entity_id: String,
-    ) -> Result<Option<Entity>, anyhow::Error> {
+    ) -> Result<Option<Entity>, HostExportError> {
+        let entity_id = self.entity_id(&entity_type, entity_id)?;
         let store_key = EntityKey {
             subgraph_id: self.subgraph_id.clone(),
-            entity_type: entity_type.clone(),
+            entity_type: entity_type.into(),
             entity_id: entity_id.clone(),
         };
 
-        Ok(state.entity_cache.get(&store_key)?)
+        state
+            .entity_cache
+            .get(&store_key)
+            .map_err(|e| HostExportError::Unknown(e.into()))
     }
 
-    /// Returns `Ok(None)` if the call was reverted.
+    /// The id under which entities of type `entity_type` are stored. Ids
+    /// with a `@length` are lowercased in subgraphs that set `strictBytes`
+    fn entity_id(&self, entity_type: &str, entity_id: String) -> Result<String, HostExportError> {
+        if !self.strict_bytes {
+            return Ok(entity_id);
+        }
+        let schema = self.store.input_schema(&self.subgraph_id)?;
+        canonical_id(&schema.document, entity_type, &entity_id)
+            .map_err(HostExportError::Deterministic)
+    }
+
+    /// The number of transactions in `block`. It comes from the Ethereum
+    /// node for blocks that were fetched without their transactions; since
+    /// the node may not know the block anymore, failing to get it is treated
+    /// as a possible reorg
+    pub(crate) fn block_transaction_count(
+        &self,
+        logger: &Logger,
+        block: &LightEthereumBlock,
+    ) -> Result<u64, MappingError> {
+        if !block.transactions_omitted() {
+            return Ok(block.transactions.len() as u64);
+        }
+
+        let hash = block.hash.unwrap();
+        let eth_adapter = self.ethereum_adapter.clone();
+        let logger = logger.clone();
+        block_on(future::lazy(move || {
+            eth_adapter.block_transaction_count(&logger, hash)
+        }))
+        .map_err(|e| {
+            MappingError::PossibleReorg(anyhow::anyhow!(
+                "Failed to get the transaction count of block {:?}: {}",
+                hash,
+                e
+            ))
+        })
+    }
+
+    /// Returns `Ok(Err(revert))` if the call was reverted.
     pub(crate) fn ethereum_call(
         &self,
         logger: &Logger,
         block: &LightEthereumBlock,
         unresolved_call: UnresolvedContractCall,
-    ) -> Result<Option<Vec<Token>>, EthereumCallError> {
+    ) -> Result<Result<Vec<Token>, EthereumCallRevert>, EthereumCallError> {
         let start_time = Instant::now();
 
         // Obtain the path to the contract ABI
@@ -326,10 +448,10 @@ impl HostExports {
         let result = match block_on(future::lazy(move || {
             eth_adapter.contract_call(&logger1, call, call_cache)
         })) {
-            Ok(tokens) => Ok(Some(tokens)),
-            Err(EthereumContractCallError::Revert(reason)) => {
-                info!(logger, "Contract call reverted"; "reason" => reason);
-                Ok(None)
+            Ok(tokens) => Ok(Ok(tokens)),
+            Err(EthereumContractCallError::Revert(revert)) => {
+                info!(logger, "Contract call reverted"; "reason" => &revert.reason);
+                Ok(Err(revert))
             }
 
             // Any error reported by the Ethereum node could be due to the block no longer being on
@@ -481,6 +603,24 @@ impl HostExports {
         tiny_keccak::keccak256(&input)
     }
 
+    pub(crate) fn bytes_concat(&self, mut a: Vec<u8>, b: Vec<u8>) -> Vec<u8> {
+        a.extend_from_slice(&b);
+        a
+    }
+
+    pub(crate) fn id_compose(&self, parts: Vec<store::Value>) -> Result<String, HostExportError> {
+        compose_id(&parts)
+    }
+
+    /// The Keccak-256 hash of the UTF-8 bytes of the id that `id_compose`
+    /// builds from `parts`.
+    pub(crate) fn id_compose_keccak_256(
+        &self,
+        parts: Vec<store::Value>,
+    ) -> Result<[u8; 32], HostExportError> {
+        Ok(tiny_keccak::keccak256(compose_id(&parts)?.as_bytes()))
+    }
+
     pub(crate) fn big_int_plus(&self, x: BigInt, y: BigInt) -> BigInt {
         x + y
     }
@@ -651,6 +791,67 @@ impl HostExports {
     }
 }
 
+/// Checks entities that a handler only set partially against the schema of
+/// the subgraph once they have their final state
+struct SchemaValidator(Arc<Schema>);
+
+impl EntityValidator for SchemaValidator {
+    fn validate(&self, key: &EntityKey, entity: &Entity) -> Result<(), anyhow::Error> {
+        validate_entity(&self.0.document, key, entity)
+    }
+}
+
+/// Writing to an entity type that the data source does not declare is a
+/// deterministic error; anything else that goes wrong in the entity cache
+/// is not
+fn entity_cache_error(e: QueryExecutionError) -> HostExportError {
+    match e {
+        QueryExecutionError::UndeclaredEntityType(_)
+        | QueryExecutionError::InvalidIncrement(..) => {
+            HostExportError::Deterministic(anyhow::anyhow!("{}", e))
+        }
+        e => HostExportError::Unknown(e.into()),
+    }
+}
+
+/// The separator that `id.compose` puts between the parts of an id. Ids end
+/// up in the entities a subgraph produces, so changing this would make
+/// subgraphs produce different data.
+pub(crate) const ID_SEPARATOR: &str = "-";
+
+/// Join the canonical string forms of `parts` with `ID_SEPARATOR`. Strings
+/// are used as they are, bytes as lowercase hex with a `0x` prefix, like
+/// `Bytes.toHexString()` does, and `Int` and `BigInt` values in decimal.
+pub(crate) fn compose_id(parts: &[store::Value]) -> Result<String, HostExportError> {
+    use std::fmt::Write;
+    use store::Value;
+
+    let mut id = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            id.push_str(ID_SEPARATOR);
+        }
+        match part {
+            Value::String(s) => id.push_str(s),
+            Value::Bytes(bytes) => write!(id, "{}", bytes).unwrap(),
+            Value::Int(n) => write!(id, "{}", n).unwrap(),
+            Value::BigInt(n) => write!(id, "{}", n).unwrap(),
+            Value::BigDecimal(_)
+            | Value::Bool(_)
+            | Value::Timestamp(_)
+            | Value::List(_)
+            | Value::Null => {
+                return Err(HostExportError::Deterministic(anyhow::anyhow!(
+                    "id parts must be strings, bytes, ints or big ints, but part {} is `{}`",
+                    i,
+                    part
+                )))
+            }
+        }
+    }
+    Ok(id)
+}
+
 pub(crate) fn json_from_bytes(bytes: &Vec<u8>) -> Result<serde_json::Value, HostExportError> {
     serde_json::from_reader(bytes.as_slice()).map_err(|e| HostExportError::Deterministic(e.into()))
 }
@@ -682,6 +883,24 @@ pub(crate) fn bytes_to_string(logger: &Logger, bytes: Vec<u8>) -> String {
     s.trim_end_matches('\u{0000}').to_string()
 }
 
+#[test]
+fn test_compose_id() {
+    use store::Value;
+
+    let parts = vec![
+        Value::String("pair".to_owned()),
+        Value::Bytes(store::scalar::Bytes::from(&[0xab, 0x01][..])),
+        Value::Int(-3),
+        Value::BigInt(store::scalar::BigInt::from(12345678901234567890u64)),
+    ];
+    assert_eq!(
+        "pair-0xab01--3-12345678901234567890",
+        compose_id(&parts).unwrap()
+    );
+    assert_eq!("", compose_id(&[]).unwrap());
+    assert!(compose_id(&[Value::Bool(true)]).is_err());
+}
+
 #[test]
 fn test_string_to_h160_with_0x() {
     assert_eq!(
@@ -14,8 +14,12 @@ mod module;
 /// Runtime-agnostic implementation of exports to WASM.
 mod host_exports;
 
+/// Evaluates `@computed` fields with the WASM modules of deployments.
+mod computed;
+pub use computed::WasmComputedFunctions;
+
 use graph::prelude::web3::types::Address;
-use graph::prelude::{Store, SubgraphDeploymentStore};
+use graph::prelude::{EntityStore, SubgraphDeploymentStore};
 
 #[derive(Clone, Debug)]
 pub(crate) struct UnresolvedContractCall {
@@ -26,5 +30,5 @@ pub(crate) struct UnresolvedContractCall {
     pub function_args: Vec<ethabi::Token>,
 }
 
-trait RuntimeStore: Store + SubgraphDeploymentStore {}
-impl<S: Store + SubgraphDeploymentStore> RuntimeStore for S {}
+trait RuntimeStore: EntityStore + SubgraphDeploymentStore {}
+impl<S: EntityStore + SubgraphDeploymentStore> RuntimeStore for S {}
@@ -1,16 +1,18 @@
+use crate::module::memory_limit::{limit_memory, max_pages};
 use crate::module::WasmInstance;
 use ethabi::LogParam;
 use futures::sync::mpsc;
 use futures03::channel::oneshot::Sender;
 use graph::components::ethereum::*;
 use graph::components::subgraph::{MappingError, SharedProofOfIndexing};
+use graph::components::trace::TraceContext;
 use graph::prelude::*;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::thread;
 use std::time::Instant;
 use strum_macros::AsStaticStr;
-use web3::types::{Log, Transaction};
+use web3::types::{Address, Log, Transaction};
 
 /// Spawn a wasm module in its own thread.
 pub fn spawn_module(
@@ -21,8 +23,9 @@ pub fn spawn_module(
     runtime: tokio::runtime::Handle,
     timeout: Option<Duration>,
     allow_non_deterministic_ipfs: bool,
+    max_memory: u64,
 ) -> Result<mpsc::Sender<MappingRequest>, anyhow::Error> {
-    let valid_module = Arc::new(ValidModule::new(&raw_module)?);
+    let valid_module = Arc::new(ValidModule::new(&raw_module, max_memory)?);
 
     // Create channel for event handling requests
     let (mapping_request_sender, mapping_request_receiver) = mpsc::channel(100);
@@ -48,6 +51,10 @@ pub fn spawn_module(
                         result_sender,
                     } = request;
 
+                    // Spans for host exports are children of the handler's span
+                    let trace = ctx.trace.clone();
+                    let _trace_guard = trace.attach();
+
                     // Start the WASM module runtime.
                     let section = host_metrics.stopwatch.start_section("module_init");
                     let module = WasmInstance::from_valid_module_with_ctx(
@@ -146,8 +153,13 @@ pub(crate) struct MappingContext {
     pub(crate) logger: Logger,
     pub(crate) host_exports: Arc<crate::host_exports::HostExports>,
     pub(crate) block: Arc<LightEthereumBlock>,
+    /// The contract that caused the trigger, if the data source has
+    /// several addresses
+    pub(crate) trigger_address: Option<Address>,
     pub(crate) state: BlockState,
     pub(crate) proof_of_indexing: SharedProofOfIndexing,
+    /// The span of the handler
+    pub(crate) trace: TraceContext,
 }
 
 impl MappingContext {
@@ -156,8 +168,10 @@ impl MappingContext {
             logger: self.logger.clone(),
             host_exports: self.host_exports.clone(),
             block: self.block.clone(),
+            trigger_address: self.trigger_address,
             state: BlockState::new(self.state.entity_cache.store.clone(), Default::default()),
             proof_of_indexing: self.proof_of_indexing.cheap_clone(),
+            trace: self.trace.clone(),
         }
     }
 }
@@ -175,11 +189,18 @@ pub(crate) struct ValidModule {
     // AS now has an `@external("module", "name")` decorator which would make things cleaner, but
     // the ship has sailed.
     pub(super) import_name_to_modules: BTreeMap<String, Vec<String>>,
+
+    // The most memory, in bytes, that an instance of the module may use
+    pub(super) max_memory: u64,
 }
 
 impl ValidModule {
-    /// Pre-process and validate the module.
-    pub fn new(raw_module: &[u8]) -> Result<Self, anyhow::Error> {
+    /// Pre-process and validate the module. Instances of the module can use at most `max_memory`
+    /// bytes of memory, rounded down to whole WASM pages.
+    pub fn new(raw_module: &[u8], max_memory: u64) -> Result<Self, anyhow::Error> {
+        let raw_module = limit_memory(raw_module, max_pages(max_memory))
+            .context("Failed to limit the memory of the WASM module")?;
+
         // We currently use Cranelift as a compilation engine. Cranelift is an optimizing compiler,
         // but that should not cause determinism issues since it adheres to the Wasm spec. Still we
         // turn off optional optimizations to be conservative.
@@ -189,7 +210,7 @@ impl ValidModule {
         config.cranelift_nan_canonicalization(true); // For NaN determinism.
         config.cranelift_opt_level(wasmtime::OptLevel::None);
         let engine = &wasmtime::Engine::new(&config);
-        let module = wasmtime::Module::from_binary(&engine, raw_module)?;
+        let module = wasmtime::Module::from_binary(&engine, &raw_module)?;
 
         let mut import_name_to_modules: BTreeMap<String, Vec<String>> = BTreeMap::new();
         for (name, module) in module
@@ -205,6 +226,7 @@ impl ValidModule {
         Ok(ValidModule {
             module,
             import_name_to_modules,
+            max_memory,
         })
     }
 }
@@ -0,0 +1,136 @@
+//! Limit how much memory a WASM module can use by lowering the maximum of
+//! the memories it defines. Once a memory reaches its maximum,
+//! `memory.grow` fails, which AssemblyScript's allocators turn into an
+//! `unreachable` trap; that happens at the same point on every node, and is
+//! therefore deterministic.
+
+use anyhow::{anyhow, Error};
+
+/// The size of a page of WASM memory
+pub(crate) const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// The most pages that a 32-bit WASM memory can have
+const MAX_WASM_PAGES: u64 = 65536;
+
+const MEMORY_SECTION_ID: u8 = 5;
+
+/// The number of pages that fit into `max_memory` bytes
+pub(crate) fn max_pages(max_memory: u64) -> u32 {
+    (max_memory / WASM_PAGE_SIZE).min(MAX_WASM_PAGES) as u32
+}
+
+/// Rewrite the memory section of `raw_module` so that no memory it defines
+/// can grow beyond `max_pages`. Memories that already have a lower maximum
+/// are left alone. Fails if the module is malformed, or if a memory starts
+/// out bigger than `max_pages`.
+pub(crate) fn limit_memory(raw_module: &[u8], max_pages: u32) -> Result<Vec<u8>, Error> {
+    const HEADER_LEN: usize = 8;
+
+    if raw_module.len() < HEADER_LEN || &raw_module[0..4] != b"\0asm" {
+        return Err(anyhow!("not a WASM module"));
+    }
+
+    let mut module = raw_module[0..HEADER_LEN].to_vec();
+    let mut reader = Reader::new(&raw_module[HEADER_LEN..]);
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let len = reader.u32()? as usize;
+        let payload = reader.bytes(len)?;
+
+        let payload = if id == MEMORY_SECTION_ID {
+            limit_memory_section(payload, max_pages)?
+        } else {
+            payload.to_vec()
+        };
+
+        module.push(id);
+        write_u32(&mut module, payload.len() as u32);
+        module.extend_from_slice(&payload);
+    }
+    Ok(module)
+}
+
+fn limit_memory_section(section: &[u8], max_pages: u32) -> Result<Vec<u8>, Error> {
+    const HAS_MAXIMUM: u8 = 0x01;
+
+    let mut reader = Reader::new(section);
+    let mut out = Vec::with_capacity(section.len() + 5);
+    let count = reader.u32()?;
+    write_u32(&mut out, count);
+    for _ in 0..count {
+        let flags = reader.byte()?;
+        let initial = reader.u32()?;
+        let maximum = if flags & HAS_MAXIMUM != 0 {
+            reader.u32()?.min(max_pages)
+        } else {
+            max_pages
+        };
+        if initial > maximum {
+            return Err(anyhow!(
+                "the module needs {} bytes of memory initially, but may only use {} bytes",
+                initial as u64 * WASM_PAGE_SIZE,
+                maximum as u64 * WASM_PAGE_SIZE
+            ));
+        }
+        out.push(flags | HAS_MAXIMUM);
+        write_u32(&mut out, initial);
+        write_u32(&mut out, maximum);
+    }
+    if !reader.is_empty() {
+        return Err(anyhow!("malformed memory section"));
+    }
+    Ok(out)
+}
+
+/// Append `value` in LEB128 encoding
+fn write_u32(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(anyhow!("unexpected end of WASM module"));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    /// Read a LEB128 encoded `u32`
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("malformed integer in WASM module"))
+    }
+}
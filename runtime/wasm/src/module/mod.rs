@@ -11,10 +11,12 @@ use wasmtime::{Memory, Trap};
 use crate::host_exports;
 use crate::mapping::MappingContext;
 use anyhow::Error;
-use ethabi::LogParam;
+use ethabi::{LogParam, Token};
 use graph::components::ethereum::*;
 use graph::components::subgraph::MappingError;
+use graph::components::trace;
 use graph::data::store;
+use graph::data::subgraph::host_imports;
 use graph::prelude::*;
 use host_exports::HostExportError;
 use web3::types::{Log, Transaction, U256};
@@ -27,6 +29,7 @@ use crate::mapping::ValidModule;
 use crate::UnresolvedContractCall;
 
 mod into_wasm_ret;
+pub(crate) mod memory_limit;
 mod stopwatch;
 
 use into_wasm_ret::IntoWasmRet;
@@ -37,6 +40,16 @@ mod test;
 
 const TRAP_TIMEOUT: &str = "trap: interrupt";
 
+lazy_static! {
+    /// From this apiVersion on, `ethereum.call` returns an
+    /// `AscEthereumCallResult` that says why a call reverted instead of null
+    static ref CALL_RESULT_API_VERSION: Version = Version::new(0, 0, 6);
+
+    /// From this apiVersion on, block handlers receive an
+    /// `AscEthereumBlock_0_0_7` that includes the transaction count
+    static ref BLOCK_TRANSACTION_COUNT_API_VERSION: Version = Version::new(0, 0, 7);
+}
+
 macro_rules! try_host_export {
     ($this:ident, $e:expr) => {
         match $e {
@@ -97,8 +110,11 @@ impl WasmInstance {
             .get_func(handler_name)
             .with_context(|| format!("function {} not found", handler_name))?
             .get2()?;
+        self.instance_ctx_mut().declare_entities();
         func(value.wasm_ptr(), user_data.wasm_ptr())
             .with_context(|| format!("Failed to handle callback '{}'", handler_name))?;
+        self.instance_ctx_mut()
+            .report_undeclared_writes(handler_name);
 
         Ok(self.take_ctx().ctx.state)
     }
@@ -177,10 +193,22 @@ impl WasmInstance {
         mut self,
         handler_name: &str,
     ) -> Result<BlockState, MappingError> {
-        let block = EthereumBlockData::from(self.instance_ctx().ctx.block.as_ref());
+        let mut block = EthereumBlockData::from(self.instance_ctx().ctx.block.as_ref());
 
         // Prepare an EthereumBlock for the WASM runtime
-        let arg = self.asc_new(&block);
+        let arg = if self.instance_ctx().ctx.host_exports.api_version
+            >= *BLOCK_TRANSACTION_COUNT_API_VERSION
+        {
+            block.transaction_count = {
+                let instance = self.instance_ctx();
+                let ctx = &instance.ctx;
+                ctx.host_exports
+                    .block_transaction_count(&ctx.logger, &ctx.block)?
+            };
+            self.asc_new::<AscEthereumBlock_0_0_7, _>(&block).erase()
+        } else {
+            self.asc_new::<AscEthereumBlock, _>(&block).erase()
+        };
 
         self.invoke_handler(handler_name, arg)?;
 
@@ -195,7 +223,6 @@ impl WasmInstance {
         std::cell::Ref::map(self.instance_ctx.borrow(), |i| i.as_ref().unwrap())
     }
 
-    #[cfg(test)]
     pub(crate) fn instance_ctx_mut(&self) -> std::cell::RefMut<'_, WasmInstanceContext> {
         std::cell::RefMut::map(self.instance_ctx.borrow_mut(), |i| i.as_mut().unwrap())
     }
@@ -211,7 +238,21 @@ impl WasmInstance {
             .get_func(handler)
             .with_context(|| format!("function {} not found", handler))?;
 
-        func.get1()?(arg.wasm_ptr()).map_err(|trap: Trap| {
+        self.instance_ctx_mut().declare_entities();
+        let start = Instant::now();
+        let result = func.get1()?(arg.wasm_ptr());
+        self.instance_ctx()
+            .observe_handler_invocation(handler, start.elapsed());
+        self.instance_ctx_mut().report_undeclared_writes(handler);
+
+        // Memory never shrinks, and each handler runs in a fresh instance, so the current size
+        // is the most memory that the handler used
+        let memory = self.instance_ctx().memory_size();
+        self.instance_ctx()
+            .host_metrics
+            .observe_handler_memory(memory, handler);
+
+        result.map_err(|trap: Trap| {
             if self.instance_ctx().possible_reorg {
                 MappingError::PossibleReorg(trap.into())
             } else if trap.to_string().contains(TRAP_TIMEOUT) {
@@ -220,6 +261,12 @@ impl WasmInstance {
                     handler,
                     self.instance_ctx().timeout.unwrap().as_secs()
                 )))
+            } else if self.instance_ctx().memory_exhausted(&trap) {
+                MappingError::Deterministic(Error::from(trap).context(format!(
+                    "memory limit exceeded: handler '{}' tried to use more than {} bytes of memory",
+                    handler,
+                    self.instance_ctx().valid_module.max_memory
+                )))
             } else {
                 use wasmtime::TrapCode::*;
                 let trap_code = trap.trap_code();
@@ -279,6 +326,11 @@ pub(crate) struct WasmInstanceContext {
     deterministic_host_trap: bool,
 
     pub(crate) allow_non_determinstic_ipfs: bool,
+
+    // The number of `ethereum.call`s and entity operations made by the handler, for the
+    // handler stats. Each handler runs in a fresh instance, so these start at zero.
+    eth_calls: u64,
+    entity_ops: u64,
 }
 
 impl WasmInstance {
@@ -321,6 +373,30 @@ impl WasmInstance {
             });
         }
 
+        let api_version = ctx
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .host_exports
+            .api_version
+            .clone();
+
+        // Refuse to instantiate modules that import functions we do not
+        // provide with an error that lists all of them. Since we only link
+        // the functions that the module imports, linking all of them below
+        // is fine once this check passes
+        let check = host_imports::check_imports(
+            valid_module
+                .module
+                .imports()
+                .filter(|import| matches!(import.ty(), wasmtime::ExternType::Func(_)))
+                .map(|import| import.name()),
+            &api_version,
+        );
+        if let Some(error) = check.error() {
+            return Err(anyhow::anyhow!("{}", error));
+        }
+
         macro_rules! link {
             ($wasm_name:expr, $rust_name:ident, $($param:ident),*) => {
                 link!($wasm_name, $rust_name, "host_export_other", $($param),*)
@@ -420,7 +496,11 @@ impl WasmInstance {
                         instance.asc_get::<_, AscUnresolvedContractCall>(call_ptr.into())
                     };
 
-                    let ret = instance.ethereum_call(arg)?.wasm_ptr();
+                    let ret = if instance.ctx.host_exports.api_version >= *CALL_RESULT_API_VERSION {
+                        instance.ethereum_call_result(arg)?.wasm_ptr()
+                    } else {
+                        instance.ethereum_call(arg)?.wasm_ptr()
+                    };
                     instance.host_metrics.observe_host_fn_execution_time(
                         start.elapsed().as_secs_f64(),
                         "ethereum_call",
@@ -454,6 +534,22 @@ impl WasmInstance {
         );
 
         link!("store.remove", store_remove, entity_ptr, id_ptr);
+        link!(
+            "store.removeMany",
+            store_remove_many,
+            "host_export_store_remove_many",
+            entity_ptr,
+            ids_ptr
+        );
+        link!(
+            "store.increment",
+            store_increment,
+            "host_export_store_increment",
+            entity_ptr,
+            id_ptr,
+            field_ptr,
+            amount_ptr
+        );
 
         link!("typeConversion.bytesToString", bytes_to_string, ptr);
         link!("typeConversion.bytesToHex", bytes_to_hex, ptr);
@@ -471,6 +567,10 @@ impl WasmInstance {
 
         link!("crypto.keccak256", crypto_keccak_256, ptr);
 
+        link!("bytes.concat", bytes_concat, a_ptr, b_ptr);
+        link!("id.compose", id_compose, ptr);
+        link!("id.composeKeccak256", id_compose_keccak_256, ptr);
+
         link!("bigInt.plus", big_int_plus, x_ptr, y_ptr);
         link!("bigInt.minus", big_int_minus, x_ptr, y_ptr);
         link!("bigInt.times", big_int_times, x_ptr, y_ptr);
@@ -529,6 +629,25 @@ impl WasmInstance {
     }
 }
 
+impl WasmInstanceContext {
+    /// The current size of the memory in bytes
+    fn memory_size(&self) -> u64 {
+        self.memory.size() as u64 * memory_limit::WASM_PAGE_SIZE
+    }
+
+    /// Whether `trap` happened because the module could not get more memory. When `memory.grow`
+    /// fails, AssemblyScript's allocators execute `unreachable`. They try to double the memory
+    /// before settling for what they need, so when growing fails, either more than half of the
+    /// memory we allow is in use already, or a single allocation needed more than half of it.
+    /// We only recognize the first case since we can not tell the second one apart from other
+    /// sources of `unreachable`.
+    fn memory_exhausted(&self, trap: &Trap) -> bool {
+        let max_pages = memory_limit::max_pages(self.valid_module.max_memory);
+        trap.trap_code() == Some(wasmtime::TrapCode::UnreachableCodeReached)
+            && self.memory.size() as u64 * 2 > max_pages as u64
+    }
+}
+
 impl AscHeap for WasmInstanceContext {
     fn raw_new(&mut self, bytes: &[u8]) -> u32 {
         // We request large chunks from the AssemblyScript allocator to use as arenas that we
@@ -610,6 +729,8 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             allow_non_determinstic_ipfs,
+            eth_calls: 0,
+            entity_ops: 0,
         })
     }
 
@@ -648,10 +769,46 @@ impl WasmInstanceContext {
             possible_reorg: false,
             deterministic_host_trap: false,
             allow_non_determinstic_ipfs,
+            eth_calls: 0,
+            entity_ops: 0,
         })
     }
 }
 
+impl WasmInstanceContext {
+    /// Record a finished invocation of `handler` that took `time`
+    pub(crate) fn observe_handler_invocation(&self, handler: &str, time: Duration) {
+        self.host_metrics.observe_handler_invocation(
+            handler,
+            time,
+            self.eth_calls,
+            self.entity_ops,
+        );
+    }
+
+    /// Make the entity cache check that the handler that is about to run
+    /// only writes to the entity types that its data source declares
+    pub(crate) fn declare_entities(&mut self) {
+        let declared = self.ctx.host_exports.declared_entities.clone();
+        self.ctx.state.entity_cache.declare_entities(declared);
+    }
+
+    /// Stop checking the entity types that `handler` writes to, and warn
+    /// about the ones that its data source does not declare
+    pub(crate) fn report_undeclared_writes(&mut self, handler: &str) {
+        for (entity_type, writes) in self.ctx.state.entity_cache.clear_declared_entities() {
+            warn!(self.ctx.logger,
+                  "Handler wrote to an entity type that is not listed in the `entities` of its data source";
+                  "handler" => handler,
+                  "entity_type" => &entity_type,
+                  "writes" => writes,
+                  "data_source" => &self.ctx.host_exports.data_source_name);
+            self.host_metrics
+                .observe_undeclared_entity_writes(handler, &entity_type, writes);
+        }
+    }
+}
+
 // Implementation of externals.
 impl WasmInstanceContext {
     /// function abort(message?: string | null, fileName?: string | null, lineNumber?: u32, columnNumber?: u32): void
@@ -701,31 +858,94 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
         data_ptr: AscPtr<AscEntity>,
     ) -> Result<(), Trap> {
+        self.entity_ops += 1;
         let entity = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
         let data = self.try_asc_get(data_ptr)?;
-        self.ctx.host_exports.store_set(
-            &self.ctx.logger,
-            &mut self.ctx.state,
-            &self.ctx.proof_of_indexing,
-            entity,
-            id,
-            data,
-        )?;
+        try_host_export!(
+            self,
+            self.ctx.host_exports.store_set(
+                &self.ctx.logger,
+                &mut self.ctx.state,
+                &self.ctx.proof_of_indexing,
+                entity,
+                id,
+                data,
+            )
+        );
         Ok(())
     }
 
     /// function store.remove(entity: string, id: string): void
-    fn store_remove(&mut self, entity_ptr: AscPtr<AscString>, id_ptr: AscPtr<AscString>) {
+    fn store_remove(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), Trap> {
+        self.entity_ops += 1;
         let entity = self.asc_get(entity_ptr);
         let id = self.asc_get(id_ptr);
-        self.ctx.host_exports.store_remove(
-            &self.ctx.logger,
-            &mut self.ctx.state,
-            &self.ctx.proof_of_indexing,
-            entity,
-            id,
+        try_host_export!(
+            self,
+            self.ctx.host_exports.store_remove(
+                &self.ctx.logger,
+                &mut self.ctx.state,
+                &self.ctx.proof_of_indexing,
+                entity,
+                id,
+            )
         );
+        Ok(())
+    }
+
+    /// function store.removeMany(entity: string, ids: Array<string>): void
+    fn store_remove_many(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        ids_ptr: AscPtr<Array<AscPtr<AscString>>>,
+    ) -> Result<(), Trap> {
+        let entity = self.asc_get(entity_ptr);
+        let ids: Vec<String> = self.asc_get(ids_ptr);
+        self.entity_ops += ids.len() as u64;
+        try_host_export!(
+            self,
+            self.ctx.host_exports.store_remove_many(
+                &self.ctx.logger,
+                &mut self.ctx.state,
+                &self.ctx.proof_of_indexing,
+                entity,
+                ids,
+            )
+        );
+        Ok(())
+    }
+
+    /// function store.increment(entity: string, id: string, field: string, amount: BigInt): void
+    fn store_increment(
+        &mut self,
+        entity_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_ptr: AscPtr<AscString>,
+        amount_ptr: AscPtr<AscBigInt>,
+    ) -> Result<(), Trap> {
+        self.entity_ops += 1;
+        let entity = self.asc_get(entity_ptr);
+        let id = self.asc_get(id_ptr);
+        let field = self.asc_get(field_ptr);
+        let amount = self.asc_get(amount_ptr);
+        try_host_export!(
+            self,
+            self.ctx.host_exports.store_increment(
+                &self.ctx.logger,
+                &mut self.ctx.state,
+                &self.ctx.proof_of_indexing,
+                entity,
+                id,
+                field,
+                amount,
+            )
+        );
+        Ok(())
     }
 
     /// function store.get(entity: string, id: string): Entity | null
@@ -735,12 +955,15 @@ impl WasmInstanceContext {
         id_ptr: AscPtr<AscString>,
     ) -> Result<AscPtr<AscEntity>, Trap> {
         let start = Instant::now();
+        self.entity_ops += 1;
         let entity_ptr = self.asc_get(entity_ptr);
         let id_ptr = self.asc_get(id_ptr);
-        let entity_option =
+        let entity_option = try_host_export!(
+            self,
             self.ctx
                 .host_exports
-                .store_get(&mut self.ctx.state, entity_ptr, id_ptr)?;
+                .store_get(&mut self.ctx.state, entity_ptr, id_ptr)
+        );
 
         let ret = Ok(match entity_option {
             Some(entity) => {
@@ -759,26 +982,52 @@ impl WasmInstanceContext {
         ret
     }
 
-    /// function ethereum.call(call: SmartContractCall): Array<Token> | null
-    fn ethereum_call(
+    fn ethereum_call_outcome(
         &mut self,
         call: UnresolvedContractCall,
-    ) -> Result<AscEnumArray<EthereumValueKind>, Trap> {
+    ) -> Result<Result<Vec<Token>, EthereumCallRevert>, Trap> {
+        self.eth_calls += 1;
+        let span = trace::Span::current_child("ethereum_call");
+        span.set_str("contract", &call.contract_name);
+        span.set_str("function", &call.function_name);
         let result = self
             .ctx
             .host_exports
             .ethereum_call(&self.ctx.logger, &self.ctx.block, call);
         match result {
-            Ok(Some(tokens)) => Ok(self.asc_new(tokens.as_slice())),
-            Ok(None) => Ok(AscPtr::null()),
-            Err(EthereumCallError::Unknown(e)) => Err(e.into()),
+            Ok(outcome) => Ok(outcome),
+            Err(EthereumCallError::Unknown(e)) => {
+                span.set_error(&e);
+                Err(e.into())
+            }
             Err(EthereumCallError::PossibleReorg(e)) => {
+                span.set_error(&e);
                 self.possible_reorg = true;
                 Err(e.into())
             }
         }
     }
 
+    /// function ethereum.call(call: SmartContractCall): Array<Token> | null
+    fn ethereum_call(
+        &mut self,
+        call: UnresolvedContractCall,
+    ) -> Result<AscEnumArray<EthereumValueKind>, Trap> {
+        match self.ethereum_call_outcome(call)? {
+            Ok(tokens) => Ok(self.asc_new(tokens.as_slice())),
+            Err(_) => Ok(AscPtr::null()),
+        }
+    }
+
+    /// function ethereum.call(call: SmartContractCall): CallResult
+    fn ethereum_call_result(
+        &mut self,
+        call: UnresolvedContractCall,
+    ) -> Result<AscPtr<AscEthereumCallResult>, Trap> {
+        let outcome = self.ethereum_call_outcome(call)?;
+        Ok(self.asc_new(&outcome))
+    }
+
     /// function typeConversion.bytesToString(bytes: Bytes): string
     fn bytes_to_string(&mut self, bytes_ptr: AscPtr<Uint8Array>) -> AscPtr<AscString> {
         let string = host_exports::bytes_to_string(&self.ctx.logger, self.asc_get(bytes_ptr));
@@ -993,6 +1242,39 @@ impl WasmInstanceContext {
         Ok(hash_ptr)
     }
 
+    /// function bytes.concat(a: Bytes, b: Bytes): Bytes
+    fn bytes_concat(
+        &mut self,
+        a_ptr: AscPtr<Uint8Array>,
+        b_ptr: AscPtr<Uint8Array>,
+    ) -> AscPtr<Uint8Array> {
+        let bytes = self
+            .ctx
+            .host_exports
+            .bytes_concat(self.asc_get(a_ptr), self.asc_get(b_ptr));
+        self.asc_new(bytes.as_slice())
+    }
+
+    /// function id.compose(parts: Array<Value>): string
+    fn id_compose(
+        &mut self,
+        parts_ptr: AscEnumArray<StoreValueKind>,
+    ) -> Result<AscPtr<AscString>, Trap> {
+        let parts = self.try_asc_get(parts_ptr)?;
+        let id = try_host_export!(self, self.ctx.host_exports.id_compose(parts));
+        Ok(self.asc_new(id.as_str()))
+    }
+
+    /// function id.composeKeccak256(parts: Array<Value>): Bytes
+    fn id_compose_keccak_256(
+        &mut self,
+        parts_ptr: AscEnumArray<StoreValueKind>,
+    ) -> Result<AscPtr<Uint8Array>, Trap> {
+        let parts = self.try_asc_get(parts_ptr)?;
+        let hash = try_host_export!(self, self.ctx.host_exports.id_compose_keccak_256(parts));
+        Ok(self.asc_new(hash.as_ref()))
+    }
+
     /// function bigInt.plus(x: BigInt, y: BigInt): BigInt
     fn big_int_plus(
         &mut self,
@@ -1236,7 +1518,11 @@ impl WasmInstanceContext {
 
     /// function dataSource.address(): Bytes
     fn data_source_address(&mut self) -> AscPtr<Uint8Array> {
-        self.asc_new(&self.ctx.host_exports.data_source_address())
+        let address = self
+            .ctx
+            .trigger_address
+            .unwrap_or_else(|| self.ctx.host_exports.data_source_address());
+        self.asc_new(&address)
     }
 
     /// function dataSource.network(): String
@@ -1,11 +1,12 @@
 use ethabi::Token;
 use hex;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::Cursor;
 use std::str::FromStr;
 
 use crate::host_exports::HostExports;
 use graph::components::store::*;
+use graph::components::subgraph::handler_stats::DeploymentHandlerStats;
 use graph::data::store::scalar;
 use graph::data::subgraph::*;
 use graph::mock::MockEthereumAdapter;
@@ -15,7 +16,7 @@ use graph_core::three_box::ThreeBoxAdapter;
 use graph_mock::MockMetricsRegistry;
 use test_store::STORE;
 
-use web3::types::{Address, H160};
+use web3::types::{Address, H160, H256};
 
 use super::*;
 
@@ -39,6 +40,57 @@ fn test_valid_module_and_store_with_timeout(
     WasmInstance,
     Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
 ) {
+    test_valid_module_and_store_with_limits(subgraph_id, data_source, timeout, 1 << 30)
+}
+
+fn test_valid_module_and_store_with_limits(
+    subgraph_id: &str,
+    data_source: DataSource,
+    timeout: Option<Duration>,
+    max_memory: u64,
+) -> (
+    WasmInstance,
+    Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+) {
+    try_test_valid_module_and_store(subgraph_id, data_source, timeout, max_memory).unwrap()
+}
+
+fn try_test_valid_module_and_store(
+    subgraph_id: &str,
+    data_source: DataSource,
+    timeout: Option<Duration>,
+    max_memory: u64,
+) -> Result<
+    (
+        WasmInstance,
+        Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    ),
+    anyhow::Error,
+> {
+    try_test_valid_module_and_store_with_handler_stats(
+        subgraph_id,
+        data_source,
+        timeout,
+        max_memory,
+        Arc::new(DeploymentHandlerStats::new()),
+        *UNDECLARED_ENTITY_WRITES,
+    )
+}
+
+fn try_test_valid_module_and_store_with_handler_stats(
+    subgraph_id: &str,
+    data_source: DataSource,
+    timeout: Option<Duration>,
+    max_memory: u64,
+    handler_stats: Arc<DeploymentHandlerStats>,
+    undeclared_entity_writes: UndeclaredEntityWrites,
+) -> Result<
+    (
+        WasmInstance,
+        Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    ),
+    anyhow::Error,
+> {
     let store = STORE.clone();
     let metrics_registry = Arc::new(MockMetricsRegistry::new());
     test_store::create_test_subgraph(
@@ -64,33 +116,58 @@ fn test_valid_module_and_store_with_timeout(
         metrics_registry,
         deployment_id.as_str(),
         stopwatch_metrics,
+        handler_stats,
     ));
 
     let module = WasmInstance::from_valid_module_with_ctx(
-        Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref()).unwrap()),
-        mock_context(deployment_id, data_source, store.clone()),
+        Arc::new(ValidModule::new(data_source.mapping.runtime.as_ref(), max_memory).unwrap()),
+        mock_context(
+            deployment_id,
+            data_source,
+            store.clone(),
+            undeclared_entity_writes,
+        ),
         host_metrics,
         timeout,
         true,
-    )
-    .unwrap();
+    )?;
 
-    (module, store)
+    Ok((module, store))
 }
 
 fn test_module(subgraph_id: &str, data_source: DataSource) -> WasmInstance {
     test_valid_module_and_store(subgraph_id, data_source).0
 }
 
+fn test_module_with_undeclared_entity_writes(
+    subgraph_id: &str,
+    data_source: DataSource,
+    undeclared_entity_writes: UndeclaredEntityWrites,
+) -> WasmInstance {
+    try_test_valid_module_and_store_with_handler_stats(
+        subgraph_id,
+        data_source,
+        None,
+        1 << 30,
+        Arc::new(DeploymentHandlerStats::new()),
+        undeclared_entity_writes,
+    )
+    .unwrap()
+    .0
+}
+
 fn mock_data_source(path: &str) -> DataSource {
-    let runtime = std::fs::read(path).unwrap();
+    mock_data_source_from_bytes(std::fs::read(path).unwrap())
+}
 
+fn mock_data_source_from_bytes(runtime: Vec<u8>) -> DataSource {
     DataSource {
         kind: String::from("ethereum/contract"),
         name: String::from("example data source"),
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            addresses: vec![],
             abi: String::from("123123"),
             start_block: 0,
         },
@@ -138,11 +215,16 @@ fn mock_host_exports(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    undeclared_entity_writes: UndeclaredEntityWrites,
+    strict_bytes: bool,
 ) -> HostExports {
     let mock_ethereum_adapter = Arc::new(MockEthereumAdapter::default());
     let arweave_adapter = Arc::new(ArweaveAdapter::new("https://arweave.net".to_string()));
     let three_box_adapter = Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_string()));
 
+    let declared_entities =
+        DeclaredEntities::new(undeclared_entity_writes, &data_source.mapping.entities);
+
     HostExports::new(
         subgraph_id,
         Version::parse(&data_source.mapping.api_version).unwrap(),
@@ -150,6 +232,7 @@ fn mock_host_exports(
         data_source.source.address,
         data_source.network.unwrap(),
         data_source.context,
+        declared_entities,
         Arc::new(data_source.templates),
         data_source.mapping.abis,
         mock_ethereum_adapter,
@@ -157,6 +240,7 @@ fn mock_host_exports(
             ipfs_api::IpfsClient::default(),
         )),
         store.clone(),
+        strict_bytes,
         store,
         arweave_adapter,
         three_box_adapter,
@@ -167,13 +251,22 @@ fn mock_context(
     subgraph_id: SubgraphDeploymentId,
     data_source: DataSource,
     store: Arc<impl Store + SubgraphDeploymentStore + EthereumCallCache>,
+    undeclared_entity_writes: UndeclaredEntityWrites,
 ) -> MappingContext {
     MappingContext {
         logger: test_store::LOGGER.clone(),
         block: Default::default(),
-        host_exports: Arc::new(mock_host_exports(subgraph_id, data_source, store.clone())),
+        trigger_address: None,
+        host_exports: Arc::new(mock_host_exports(
+            subgraph_id,
+            data_source,
+            store.clone(),
+            undeclared_entity_writes,
+            false,
+        )),
         state: BlockState::new(store, Default::default()),
         proof_of_indexing: None,
+        trace: Default::default(),
     }
 }
 
@@ -303,7 +396,7 @@ fn make_thing(subgraph_id: &str, id: &str, value: &str) -> (String, EntityModifi
     data.set("extra", USER_DATA);
     let key = EntityKey {
         subgraph_id: SubgraphDeploymentId::new(subgraph_id).unwrap(),
-        entity_type: "Thing".to_string(),
+        entity_type: "Thing".into(),
         entity_id: id.to_string(),
     };
     (
@@ -633,6 +726,68 @@ async fn ens_name_by_hash() {
         .is_null());
 }
 
+#[tokio::test]
+async fn handler_stats_per_handler() {
+    // Handlers that run for the same block each get a fresh instance, but
+    // share the handler stats of the deployment
+    let handler_stats = Arc::new(DeploymentHandlerStats::new());
+    let handler_module = || {
+        try_test_valid_module_and_store_with_handler_stats(
+            "handlerStats",
+            mock_data_source("wasm_test/store.wasm"),
+            None,
+            1 << 30,
+            handler_stats.clone(),
+            *UNDECLARED_ENTITY_WRITES,
+        )
+        .unwrap()
+        .0
+    };
+
+    // One store.get
+    let mut module = handler_module();
+    let id = module.asc_new("alex");
+    let start = Instant::now();
+    module.invoke_export::<_, AscEntity>("getUser", id);
+    module
+        .instance_ctx()
+        .observe_handler_invocation("getUser", start.elapsed());
+
+    // A store.get and a store.set, twice
+    for name in &["Alex", "Alexander"] {
+        let mut module = handler_module();
+        let id_ptr = module.asc_new("alex");
+        let name_ptr = module.asc_new(*name);
+        let start = Instant::now();
+        module
+            .invoke_export2_void("loadAndSetUserName", id_ptr, name_ptr)
+            .unwrap();
+        module
+            .instance_ctx()
+            .observe_handler_invocation("loadAndSetUserName", start.elapsed());
+    }
+
+    let stats: Vec<_> = handler_stats
+        .stats()
+        .into_iter()
+        .map(|stats| {
+            (
+                stats.handler,
+                stats.invocations,
+                stats.eth_calls,
+                stats.entity_ops,
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            ("getUser".to_owned(), 1, 0, 1),
+            ("loadAndSetUserName".to_owned(), 2, 0, 4),
+        ],
+        stats
+    );
+}
+
 #[tokio::test]
 async fn entity_store() {
     let (mut module, store) =
@@ -720,6 +875,208 @@ async fn entity_store() {
     }
 }
 
+/// Run `loadAndSetUserName` as a handler of a data source that only
+/// declares `Thing`, and return the instance and the result of the handler
+fn set_undeclared_user(
+    subgraph_id: &str,
+    mode: UndeclaredEntityWrites,
+) -> (WasmInstance, Result<(), wasmtime::Trap>) {
+    let mut data_source = mock_data_source("wasm_test/store.wasm");
+    data_source.mapping.entities = vec!["Thing".to_owned()];
+    let mut module = test_module_with_undeclared_entity_writes(subgraph_id, data_source, mode);
+
+    let id_ptr = module.asc_new("alex");
+    let name_ptr = module.asc_new("Alex");
+    module.instance_ctx_mut().declare_entities();
+    let result = module.invoke_export2_void("loadAndSetUserName", id_ptr, name_ptr);
+    (module, result)
+}
+
+#[tokio::test]
+async fn undeclared_entity_writes_ignore() {
+    let (mut module, result) =
+        set_undeclared_user("undeclaredWritesIgnore", UndeclaredEntityWrites::Ignore);
+    result.unwrap();
+
+    // The write goes through
+    let mut state = module.take_ctx().ctx.state;
+    assert!(state.entity_cache.clear_declared_entities().is_empty());
+    let mods = state
+        .entity_cache
+        .as_modifications(STORE.as_ref())
+        .unwrap()
+        .modifications;
+    assert_eq!(1, mods.len());
+}
+
+#[tokio::test]
+async fn undeclared_entity_writes_warn() {
+    let (mut module, result) =
+        set_undeclared_user("undeclaredWritesWarn", UndeclaredEntityWrites::Warn);
+    result.unwrap();
+
+    // The write goes through
+    let mut state = module.take_ctx().ctx.state;
+    assert_eq!(
+        vec![("User".to_owned(), 1)],
+        state
+            .entity_cache
+            .clear_declared_entities()
+            .into_iter()
+            .collect::<Vec<_>>()
+    );
+    let mods = state
+        .entity_cache
+        .as_modifications(STORE.as_ref())
+        .unwrap()
+        .modifications;
+    assert_eq!(1, mods.len());
+}
+
+#[tokio::test]
+async fn undeclared_entity_writes_error() {
+    let (module, result) =
+        set_undeclared_user("undeclaredWritesError", UndeclaredEntityWrites::Error);
+    let err = result.unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("entity type `User` is not listed in the `entities` of the data source"));
+    assert!(module.instance_ctx().deterministic_host_trap);
+}
+
+#[tokio::test]
+async fn strict_bytes() {
+    const ID: &str = "strictBytes";
+
+    test_store::create_test_subgraph(
+        ID,
+        "type Account @entity {
+            id: ID! @length(bytes: 2),
+            owner: Bytes @length(bytes: 2),
+            delegates: [Bytes!]
+        }",
+    );
+    let store = STORE.clone();
+    let host_exports = mock_host_exports(
+        SubgraphDeploymentId::new(ID).unwrap(),
+        mock_data_source("wasm_test/abi_store_value.wasm"),
+        store.clone(),
+        UndeclaredEntityWrites::Ignore,
+        true,
+    );
+    let logger = test_store::LOGGER.clone();
+    let mut state = BlockState::new(store, Default::default());
+    let set = |state: &mut BlockState, id: &str, owner: &str| {
+        let mut data = HashMap::new();
+        data.insert("owner".to_owned(), Value::String(owner.to_owned()));
+        data.insert(
+            "delegates".to_owned(),
+            Value::List(vec![Value::String("0xABCDEF".to_owned())]),
+        );
+        host_exports.store_set(
+            &logger,
+            state,
+            &None,
+            "Account".to_owned(),
+            id.to_owned(),
+            data,
+        )
+    };
+
+    set(&mut state, "0xABcd", "0xEF01").unwrap();
+    let account = host_exports
+        .store_get(&mut state, "Account".to_owned(), "0xabCD".to_owned())
+        .unwrap()
+        .expect("the account can be looked up with any case");
+    assert_eq!(Some(&Value::from("0xabcd")), account.get("id"));
+    assert_eq!(
+        Some(&Value::Bytes(scalar::Bytes::from_str("0xef01").unwrap())),
+        account.get("owner")
+    );
+    assert_eq!(
+        Some(&Value::List(vec![Value::Bytes(
+            scalar::Bytes::from_str("0xabcdef").unwrap()
+        )])),
+        account.get("delegates")
+    );
+
+    for (id, owner) in &[
+        ("0xabcd", "0xef0123"),
+        ("0xabcdef", "0xef01"),
+        ("abcd", "0xef01"),
+    ] {
+        match set(&mut state, id, owner) {
+            Err(HostExportError::Deterministic(_)) => {}
+            Err(e) => panic!("expected a deterministic error but got {}", e),
+            Ok(()) => panic!("writing {} with owner {} must fail", id, owner),
+        }
+    }
+}
+
+#[tokio::test]
+async fn store_remove_many() {
+    const ID: &str = "storeRemoveMany";
+
+    test_store::create_test_subgraph(ID, "type Bid @entity { id: ID!, amount: Int! }");
+    let store = STORE.clone();
+    let host_exports = mock_host_exports(
+        SubgraphDeploymentId::new(ID).unwrap(),
+        mock_data_source("wasm_test/abi_store_value.wasm"),
+        store.clone(),
+        UndeclaredEntityWrites::Ignore,
+        false,
+    );
+    let logger = test_store::LOGGER.clone();
+    let mut state = BlockState::new(store.clone(), Default::default());
+    let set = |state: &mut BlockState, id: &str| {
+        let mut data = HashMap::new();
+        data.insert("amount".to_owned(), Value::Int(1));
+        host_exports
+            .store_set(&logger, state, &None, "Bid".to_owned(), id.to_owned(), data)
+            .unwrap()
+    };
+    let exists = |state: &mut BlockState, id: &str| {
+        host_exports
+            .store_get(state, "Bid".to_owned(), id.to_owned())
+            .unwrap()
+            .is_some()
+    };
+
+    set(&mut state, "1");
+    set(&mut state, "2");
+    set(&mut state, "3");
+    host_exports
+        .store_remove_many(
+            &logger,
+            &mut state,
+            &None,
+            "Bid".to_owned(),
+            vec!["1".to_owned(), "2".to_owned(), "4".to_owned()],
+        )
+        .unwrap();
+    // Creating a removed entity again after the removal wins
+    set(&mut state, "2");
+
+    assert!(!exists(&mut state, "1"));
+    assert!(exists(&mut state, "2"));
+    assert!(exists(&mut state, "3"));
+    assert!(!exists(&mut state, "4"));
+
+    let mut ids: Vec<_> = state
+        .entity_cache
+        .as_modifications(store.as_ref())
+        .unwrap()
+        .modifications
+        .into_iter()
+        .map(|modification| match modification {
+            EntityModification::Insert { key, .. } => key.entity_id,
+            _ => panic!("expected only inserts"),
+        })
+        .collect();
+    ids.sort();
+    assert_eq!(vec!["2", "3"], ids);
+}
+
 #[tokio::test]
 async fn detect_contract_calls() {
     let data_source_without_calls = mock_data_source("wasm_test/abi_store_value.wasm");
@@ -738,3 +1095,277 @@ async fn detect_contract_calls() {
         true
     );
 }
+
+/// A module with a handler `grow` that grows the memory by the number of
+/// pages passed to it and traps if that fails, like AssemblyScript's
+/// allocators do. In text format:
+///
+/// (module
+///   (memory (export "memory") 1)
+///   (func (export "memory.allocate") (param i32) (result i32)
+///     i32.const 0)
+///   (func (export "grow") (param i32)
+///     local.get 0
+///     memory.grow
+///     i32.const 0
+///     i32.lt_s
+///     if
+///       unreachable
+///     end))
+const GROW_MEMORY_WASM: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0a, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f,
+    0x60, 0x01, 0x7f, 0x00, 0x03, 0x03, 0x02, 0x00, 0x01, 0x05, 0x03, 0x01, 0x00, 0x01, 0x07, 0x23,
+    0x03, 0x06, 0x6d, 0x65, 0x6d, 0x6f, 0x72, 0x79, 0x02, 0x00, 0x0f, 0x6d, 0x65, 0x6d, 0x6f, 0x72,
+    0x79, 0x2e, 0x61, 0x6c, 0x6c, 0x6f, 0x63, 0x61, 0x74, 0x65, 0x00, 0x00, 0x04, 0x67, 0x72, 0x6f,
+    0x77, 0x00, 0x01, 0x0a, 0x14, 0x02, 0x04, 0x00, 0x41, 0x00, 0x0b, 0x0d, 0x00, 0x20, 0x00, 0x40,
+    0x00, 0x41, 0x00, 0x48, 0x04, 0x40, 0x00, 0x0b, 0x0b,
+];
+
+#[test]
+fn limit_memory_section() {
+    use super::memory_limit::limit_memory;
+
+    // The memory section of `GROW_MEMORY_WASM` is `05 03 01 00 01`, one
+    // memory with an initial size of 1 page and no maximum
+    let limited = limit_memory(GROW_MEMORY_WASM, 16).unwrap();
+    assert_eq!(GROW_MEMORY_WASM.len() + 1, limited.len());
+    assert_eq!(&GROW_MEMORY_WASM[0..25], &limited[0..25]);
+    assert_eq!(&[0x05, 0x04, 0x01, 0x01, 0x01, 0x10], &limited[25..31]);
+    assert_eq!(&GROW_MEMORY_WASM[30..], &limited[31..]);
+
+    // A lower maximum is kept
+    assert_eq!(&limited, &limit_memory(&limited, 100).unwrap());
+
+    // The initial memory already exceeds the limit
+    assert!(limit_memory(GROW_MEMORY_WASM, 0).is_err());
+}
+
+#[tokio::test]
+async fn memory_limit() {
+    // Allow 16 pages
+    let max_memory = 16 * 64 * 1024;
+    let (mut module, _) = test_valid_module_and_store_with_limits(
+        "memoryLimit",
+        mock_data_source_from_bytes(GROW_MEMORY_WASM.to_vec()),
+        None,
+        max_memory,
+    );
+
+    // Growing to 11 pages is fine
+    module
+        .invoke_handler("grow", AscPtr::<()>::from(10))
+        .unwrap();
+
+    // Growing to 21 pages is not
+    match module.invoke_handler("grow", AscPtr::<()>::from(10)) {
+        Err(MappingError::Deterministic(e)) => assert_eq!(
+            "memory limit exceeded: handler 'grow' tried to use more than 1048576 bytes of memory",
+            e.to_string()
+        ),
+        Err(e) => panic!("expected a deterministic error, got {:?}", e),
+        Ok(()) => panic!("growing memory beyond the limit should fail"),
+    }
+}
+
+#[tokio::test]
+async fn id_host_exports() {
+    let mut module = test_module("idHostExports", mock_data_source("wasm_test/crypto.wasm"));
+
+    let a: AscPtr<Uint8Array> = module.asc_new(&[0xabu8, 0x01][..]);
+    let b: AscPtr<Uint8Array> = module.asc_new(&[0xcdu8][..]);
+    let concat = module.instance_ctx_mut().bytes_concat(a, b);
+    let concat: Vec<u8> = module.asc_get(concat);
+    assert_eq!(vec![0xab, 0x01, 0xcd], concat);
+
+    let parts = vec![
+        store::Value::String("pair".to_owned()),
+        store::Value::Bytes(scalar::Bytes::from(&[0xab, 0x01][..])),
+        store::Value::Int(-3),
+        store::Value::BigInt(scalar::BigInt::from(7)),
+    ];
+    let parts: AscEnumArray<StoreValueKind> = module.asc_new(parts.as_slice());
+    let id = module.instance_ctx_mut().id_compose(parts).unwrap();
+    let id: String = module.asc_get(id);
+    assert_eq!("pair-0xab01--3-7", id);
+
+    let hash = module
+        .instance_ctx_mut()
+        .id_compose_keccak_256(parts)
+        .unwrap();
+    let hash: Vec<u8> = module.asc_get(hash);
+    assert_eq!(tiny_keccak::keccak256(id.as_bytes()).to_vec(), hash);
+
+    // Other values can not be part of an id
+    let parts: AscEnumArray<StoreValueKind> = module.asc_new(
+        &[
+            store::Value::String("pair".to_owned()),
+            store::Value::Bool(true),
+        ][..],
+    );
+    assert!(module.instance_ctx_mut().id_compose(parts).is_err());
+    assert!(module.instance_ctx().deterministic_host_trap);
+}
+
+/// The number of bytes that `f` allocates in WASM memory
+fn allocated_bytes(module: &mut WasmInstance, f: impl FnOnce(&mut WasmInstance)) -> i32 {
+    // Use up the current arena and start a new one so that everything `f`
+    // allocates comes from the same arena
+    let free = module.instance_ctx().arena_free_size;
+    module.raw_new(&vec![0; free as usize]);
+    module.raw_new(&[0]);
+
+    let before = module.instance_ctx().arena_free_size;
+    f(module);
+    let after = module.instance_ctx().arena_free_size;
+    assert!(after <= before, "allocations must fit into one arena");
+    before - after
+}
+
+#[tokio::test]
+async fn id_compose_allocations() {
+    let mut module = test_module(
+        "idComposeAllocations",
+        mock_data_source("wasm_test/crypto.wasm"),
+    );
+
+    // The transaction hash and log index of an event, which are already in
+    // WASM memory when a handler runs
+    let hash: AscPtr<Uint8Array> = module.asc_new(H256::from_low_u64_be(0x1234).as_bytes());
+    let log_index: AscPtr<AscBigInt> = module.asc_new(&BigInt::from(12));
+
+    // What `event.transaction.hash.toHex() + "-" + event.logIndex.toString()`
+    // allocates: the two conversions, and a new string for each `+`
+    let mut concatenated = None;
+    let concatenated_bytes = allocated_bytes(&mut module, |module| {
+        let hex = module.instance_ctx_mut().bytes_to_hex(hash);
+        let hex: String = module.asc_get(hex);
+        let index = module.instance_ctx_mut().big_int_to_string(log_index);
+        let index: String = module.asc_get(index);
+        let _: AscPtr<AscString> = module.asc_new(format!("{}-", hex).as_str());
+        let id: AscPtr<AscString> = module.asc_new(format!("{}-{}", hex, index).as_str());
+        concatenated = Some(module.asc_get::<String, _>(id));
+    });
+
+    // What `id.compose([Value.fromBytes(hash), Value.fromBigInt(logIndex)])`
+    // allocates: the two values, the array and the id
+    let mut composed = None;
+    let composed_bytes = allocated_bytes(&mut module, |module| {
+        let parts: Vec<AscPtr<AscEnum<StoreValueKind>>> = vec![
+            (StoreValueKind::Bytes, EnumPayload::from(hash)),
+            (StoreValueKind::BigInt, EnumPayload::from(log_index)),
+        ]
+        .into_iter()
+        .map(|(kind, payload)| {
+            let value = AscEnum {
+                kind,
+                _padding: 0,
+                payload,
+            };
+            AscPtr::from(module.raw_new(&value.to_asc_bytes()))
+        })
+        .collect();
+        let parts = Array::new(&parts, module);
+        let parts: AscEnumArray<StoreValueKind> =
+            AscPtr::from(module.raw_new(&parts.to_asc_bytes()));
+        let id = module.instance_ctx_mut().id_compose(parts).unwrap();
+        composed = Some(module.asc_get::<String, _>(id));
+    });
+
+    assert_eq!(concatenated, composed);
+    assert_eq!(
+        Some(format!("{:#x}-12", H256::from_low_u64_be(0x1234))),
+        composed
+    );
+    assert!(
+        composed_bytes < concatenated_bytes,
+        "id.compose allocated {} bytes, concatenating strings {} bytes",
+        composed_bytes,
+        concatenated_bytes
+    );
+}
+
+#[tokio::test]
+async fn id_exports_require_api_version() {
+    // A module that only imports `id.compose` from `index`
+    const ID_COMPOSE_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01,
+        0x7f, 0x02, 0x14, 0x01, 0x05, 0x69, 0x6e, 0x64, 0x65, 0x78, 0x0a, 0x69, 0x64, 0x2e, 0x63,
+        0x6f, 0x6d, 0x70, 0x6f, 0x73, 0x65, 0x00, 0x00,
+    ];
+
+    let mut data_source = mock_data_source_from_bytes(ID_COMPOSE_WASM.to_vec());
+    data_source.mapping.api_version = "0.0.4".to_owned();
+    match try_test_valid_module_and_store("idExportsApiVersion", data_source, None, 1 << 30) {
+        Err(e) => assert_eq!(
+            "the host export `id.compose` requires apiVersion 0.0.5 or later, \
+             but the mapping uses apiVersion 0.0.4",
+            e.to_string()
+        ),
+        Ok(_) => panic!("id.compose must not be available with apiVersion 0.0.4"),
+    }
+
+    let data_source = mock_data_source_from_bytes(ID_COMPOSE_WASM.to_vec());
+    assert!(
+        try_test_valid_module_and_store("idExportsApiVersion", data_source, None, 1 << 30).is_ok()
+    );
+}
+
+#[tokio::test]
+async fn unknown_host_export() {
+    // A module that imports `id.compose` and `math.sqrt` from `index`;
+    // the node does not provide `math.sqrt`
+    const UNKNOWN_IMPORT_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, 0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01,
+        0x7f, 0x02, 0x26, 0x02, 0x05, 0x69, 0x6e, 0x64, 0x65, 0x78, 0x0a, 0x69, 0x64, 0x2e, 0x63,
+        0x6f, 0x6d, 0x70, 0x6f, 0x73, 0x65, 0x00, 0x00, 0x05, 0x69, 0x6e, 0x64, 0x65, 0x78, 0x09,
+        0x6d, 0x61, 0x74, 0x68, 0x2e, 0x73, 0x71, 0x72, 0x74, 0x00, 0x00,
+    ];
+
+    let mut data_source = mock_data_source_from_bytes(UNKNOWN_IMPORT_WASM.to_vec());
+    data_source.mapping.api_version = "0.0.4".to_owned();
+    assert_eq!(
+        vec![
+            "the host export `id.compose` requires apiVersion 0.0.5 or later, \
+             but the mapping uses apiVersion 0.0.4"
+                .to_owned(),
+            "the host export `math.sqrt` is not provided by this node; the mapping \
+             might have been built with a newer version of graph-ts"
+                .to_owned(),
+        ],
+        data_source.mapping.check_imports().unresolved
+    );
+
+    match try_test_valid_module_and_store("unknownHostExport", data_source, None, 1 << 30) {
+        Err(e) => assert_eq!(
+            "the host export `id.compose` requires apiVersion 0.0.5 or later, \
+             but the mapping uses apiVersion 0.0.4; the host export `math.sqrt` is not \
+             provided by this node; the mapping might have been built with a newer \
+             version of graph-ts",
+            e.to_string()
+        ),
+        Ok(_) => panic!("a module that imports `math.sqrt` must not be instantiated"),
+    }
+}
+
+#[test]
+fn host_export_table_matches_linker() {
+    // The names of all functions that `WasmInstance::from_valid_module_with_ctx`
+    // links, taken from its `link!` calls
+    let linked: BTreeSet<&str> = include_str!("mod.rs")
+        .split("link!(")
+        .skip(1)
+        .filter_map(|call| {
+            let call = call.trim_start();
+            if call.starts_with('"') {
+                call[1..].split('"').next()
+            } else {
+                None
+            }
+        })
+        .collect();
+    let exports: BTreeSet<&str> = host_imports::HOST_EXPORTS
+        .iter()
+        .map(|export| export.name)
+        .collect();
+    assert_eq!(exports, linked);
+}
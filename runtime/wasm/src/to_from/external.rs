@@ -2,13 +2,14 @@ use ethabi;
 use std::collections::HashMap;
 
 use graph::components::ethereum::{
-    EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    EthereumBlockData, EthereumCallData, EthereumCallRevert, EthereumEventData,
+    EthereumTransactionData,
 };
 use graph::data::store;
 use graph::prelude::anyhow::{ensure, Error};
 use graph::prelude::serde_json;
 use graph::prelude::web3::types as web3;
-use graph::prelude::{BigDecimal, BigInt};
+use graph::prelude::{BigDecimal, BigInt, Word};
 
 use crate::asc_abi::class::*;
 use crate::asc_abi::{AscHeap, AscPtr, AscType, FromAscObj, ToAscObj, TryFromAscObj};
@@ -236,6 +237,11 @@ impl ToAscObj<AscEnum<StoreValueKind>> for store::Value {
                 let bytes_obj: AscPtr<Uint8Array> = heap.asc_new(&*big_int.to_signed_bytes_le());
                 bytes_obj.into()
             }
+            Value::Timestamp(ts) => {
+                let micros = store::scalar::BigInt::from(ts.as_micros());
+                let bytes_obj: AscPtr<Uint8Array> = heap.asc_new(&*micros.to_signed_bytes_le());
+                bytes_obj.into()
+            }
         };
 
         AscEnum {
@@ -271,6 +277,12 @@ impl ToAscObj<AscEntity> for HashMap<String, store::Value> {
     }
 }
 
+impl ToAscObj<AscString> for Word {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscString {
+        self.as_str().to_asc_obj(heap)
+    }
+}
+
 impl ToAscObj<AscEntity> for store::Entity {
     fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEntity {
         AscTypedMap {
@@ -324,6 +336,29 @@ impl ToAscObj<AscEthereumBlock> for EthereumBlockData {
     }
 }
 
+impl ToAscObj<AscEthereumBlock_0_0_7> for EthereumBlockData {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumBlock_0_0_7 {
+        let block: AscEthereumBlock = self.to_asc_obj(heap);
+        AscEthereumBlock_0_0_7 {
+            hash: block.hash,
+            parent_hash: block.parent_hash,
+            uncles_hash: block.uncles_hash,
+            author: block.author,
+            state_root: block.state_root,
+            transactions_root: block.transactions_root,
+            receipts_root: block.receipts_root,
+            number: block.number,
+            gas_used: block.gas_used,
+            gas_limit: block.gas_limit,
+            timestamp: block.timestamp,
+            difficulty: block.difficulty,
+            total_difficulty: block.total_difficulty,
+            size: block.size,
+            transaction_count: heap.asc_new(&BigInt::from(self.transaction_count)),
+        }
+    }
+}
+
 impl ToAscObj<AscEthereumTransaction> for EthereumTransactionData {
     fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumTransaction {
         AscEthereumTransaction {
@@ -406,6 +441,27 @@ impl ToAscObj<AscEthereumCall_0_0_3> for EthereumCallData {
     }
 }
 
+impl ToAscObj<AscEthereumCallResult> for Result<Vec<ethabi::Token>, EthereumCallRevert> {
+    fn to_asc_obj<H: AscHeap>(&self, heap: &mut H) -> AscEthereumCallResult {
+        match self {
+            Ok(tokens) => AscEthereumCallResult {
+                value: heap.asc_new(tokens.as_slice()),
+                revert_reason: AscPtr::null(),
+                revert_data: AscPtr::null(),
+            },
+            Err(revert) => AscEthereumCallResult {
+                value: AscPtr::null(),
+                revert_reason: heap.asc_new(&revert.reason),
+                revert_data: revert
+                    .data
+                    .as_ref()
+                    .map(|data| heap.asc_new(data.0.as_slice()))
+                    .unwrap_or_else(|| AscPtr::null()),
+            },
+        }
+    }
+}
+
 impl FromAscObj<AscUnresolvedContractCall> for UnresolvedContractCall {
     fn from_asc_obj<H: AscHeap>(asc_call: AscUnresolvedContractCall, heap: &H) -> Self {
         UnresolvedContractCall {
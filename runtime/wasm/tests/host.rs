@@ -0,0 +1,569 @@
+// Tests that run mappings through a `RuntimeHost`, the way that subgraphs run
+// them. The mappings are written in the WebAssembly text format so that each
+// test can call the host exports it is about directly.
+
+use std::collections::HashMap;
+
+use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+use graph::components::subgraph::handler_stats::DeploymentHandlerStats;
+use graph::components::subgraph::MappingError;
+use graph::data::subgraph::*;
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::web3::types::{Address, Transaction, H256, U64};
+use graph::prelude::{
+    RuntimeHost as RuntimeHostTrait, RuntimeHostBuilder as RuntimeHostBuilderTrait, *,
+};
+use graph::util::lfu_cache::LfuCache;
+use graph_chain_arweave::adapter::ArweaveAdapter;
+use graph_core::three_box::ThreeBoxAdapter;
+use graph_mock::MockMetricsRegistry;
+use graph_runtime_wasm::RuntimeHostBuilder;
+use test_store::{LOGGER, STORE};
+
+/// The part of every mapping that the runtime relies on: the memory and the
+/// allocator that the host uses to pass values to the mapping. Data segments
+/// of the mappings must end before address 4096
+const RUNTIME: &str = r#"
+    (memory (export "memory") 4)
+    (global $next (mut i32) (i32.const 4096))
+
+    ;; Hand out memory after the data segments, aligned at 8 bytes, and
+    ;; never free it
+    (func (export "memory.allocate") (param $size i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $next))
+      (global.set $next
+        (i32.and
+          (i32.add (i32.add (local.get $ptr) (local.get $size)) (i32.const 7))
+          (i32.const -8)))
+      (local.get $ptr))
+
+    ;; Trap unless `$cond` holds
+    (func $assert (param $cond i32)
+      (if (i32.eqz (local.get $cond)) (then unreachable)))
+"#;
+
+/// A data source on `mainnet` for the contract `Contract` with the ABI `abi`.
+/// Its mapping has apiVersion `api_version` and consists of the `imports`
+/// from `index`, the `RUNTIME` and `body`, which must export the block
+/// handler `handler`
+fn data_source(
+    api_version: &str,
+    abi: &str,
+    imports: &str,
+    body: &str,
+    handler: &str,
+) -> DataSource {
+    let wat = format!("(module {} {} {})", imports, RUNTIME, body);
+    let runtime = wat::parse_str(&wat).expect("the mapping is valid WebAssembly");
+
+    DataSource {
+        kind: String::from("ethereum/contract"),
+        name: String::from("Contract"),
+        network: Some(String::from("mainnet")),
+        source: Source {
+            address: Some(Address::from_low_u64_be(1)),
+            addresses: vec![],
+            abi: String::from("Contract"),
+            start_block: 0,
+        },
+        mapping: Mapping {
+            kind: String::from("ethereum/events"),
+            api_version: String::from(api_version),
+            language: String::from("wasm/assemblyscript"),
+            entities: vec![],
+            abis: vec![MappingABI {
+                name: String::from("Contract"),
+                contract: ethabi::Contract::load(abi.as_bytes()).unwrap(),
+                link: Link::from("abi".to_owned()),
+            }],
+            event_handlers: vec![],
+            call_handlers: vec![],
+            block_handlers: vec![MappingBlockHandler {
+                handler: String::from(handler),
+                filter: None,
+            }],
+            link: Link::from("mapping".to_owned()),
+            runtime: Arc::new(runtime),
+        },
+        templates: vec![],
+        context: None,
+    }
+}
+
+/// Build the host for `data_source` of the new deployment `subgraph_id` the
+/// way the subgraph instance builds it, with a network whose only Ethereum
+/// adapter is `adapter`
+fn runtime_host<S>(
+    store: Arc<S>,
+    subgraph_id: &str,
+    data_source: DataSource,
+    adapter: MockEthereumAdapter,
+) -> impl RuntimeHostTrait
+where
+    S: Send + Sync + 'static + EntityStore + SubgraphDeploymentStore + EthereumCallCache,
+{
+    test_store::create_test_subgraph(subgraph_id, "type Thing @entity { id: ID! }");
+    let subgraph_id = SubgraphDeploymentId::new(subgraph_id).unwrap();
+
+    let mut ethereum_networks = EthereumNetworks::new();
+    ethereum_networks.insert(
+        "mainnet".to_owned(),
+        NodeCapabilities {
+            archive: false,
+            traces: false,
+        },
+        Arc::new(adapter),
+    );
+    let stores: HashMap<String, _> = vec![("mainnet".to_owned(), store)].into_iter().collect();
+    let builder = RuntimeHostBuilder::new(
+        ethereum_networks,
+        Arc::new(graph_core::LinkResolver::from(
+            ipfs_api::IpfsClient::default(),
+        )),
+        stores,
+        Arc::new(ArweaveAdapter::new("https://arweave.net".to_string())),
+        Arc::new(ThreeBoxAdapter::new("https://ipfs.3box.io/".to_string())),
+    );
+
+    let metrics_registry = Arc::new(MockMetricsRegistry::new());
+    let stopwatch_metrics = StopwatchMetrics::new(
+        LOGGER.clone(),
+        subgraph_id.clone(),
+        metrics_registry.clone(),
+    );
+    let metrics = Arc::new(HostMetrics::new(
+        metrics_registry,
+        subgraph_id.as_str(),
+        stopwatch_metrics,
+        Arc::new(DeploymentHandlerStats::new()),
+    ));
+
+    let mapping_request_sender = RuntimeHostBuilder::<S>::spawn_mapping(
+        data_source.mapping.runtime.as_ref().clone(),
+        LOGGER.clone(),
+        subgraph_id.clone(),
+        metrics.clone(),
+        None,
+    )
+    .unwrap();
+    builder
+        .build(
+            "mainnet".to_owned(),
+            subgraph_id,
+            data_source,
+            Arc::new(vec![]),
+            mapping_request_sender,
+            metrics,
+        )
+        .unwrap()
+}
+
+/// Run the block handler of `host` for `block`
+async fn process_block(
+    host: &impl RuntimeHostTrait,
+    block: LightEthereumBlock,
+) -> Result<(), MappingError> {
+    let state = BlockState::new(STORE.clone(), LfuCache::new());
+    host.process_block(
+        &LOGGER,
+        &Arc::new(block),
+        &EthereumBlockTriggerType::Every,
+        state,
+        None,
+    )
+    .await
+    .map(|_| ())
+}
+
+fn block(number: u64) -> LightEthereumBlock {
+    LightEthereumBlock {
+        hash: Some(H256::from_low_u64_be(number)),
+        number: Some(U64::from(number)),
+        ..Default::default()
+    }
+}
+
+const ID_IMPORTS: &str = r#"
+    (import "index" "bytes.concat" (func $bytes_concat (param i32 i32) (result i32)))
+    (import "index" "id.compose" (func $id_compose (param i32) (result i32)))
+    (import "index" "id.composeKeccak256" (func $id_compose_keccak256 (param i32) (result i32)))
+"#;
+
+const ID_MAPPING: &str = r#"
+    ;; The string "pair"
+    (data (i32.const 16) "\04\00\00\00p\00a\00i\00r\00")
+    ;; The values "pair" and -3
+    (data (i32.const 32) "\00\00\00\00\00\00\00\00\10\00\00\00\00\00\00\00")
+    (data (i32.const 48) "\01\00\00\00\00\00\00\00\fd\ff\ff\ff\ff\ff\ff\ff")
+    ;; The array of the two values
+    (data (i32.const 64) "\08\00\00\00\00\00\00\00\20\00\00\00\30\00\00\00")
+    (data (i32.const 80) "\40\00\00\00\02\00\00\00")
+    ;; The bytes 0xab01 and 0xcd
+    (data (i32.const 96) "\02\00\00\00\00\00\00\00\ab\01")
+    (data (i32.const 112) "\60\00\00\00\00\00\00\00\02\00\00\00")
+    (data (i32.const 128) "\01\00\00\00\00\00\00\00\cd")
+    (data (i32.const 144) "\80\00\00\00\00\00\00\00\01\00\00\00")
+
+    (func (export "handleBlock") (param $block i32)
+      (local $ptr i32)
+
+      ;; bytes.concat(0xab01, 0xcd) is 0xab01cd
+      (local.set $ptr (call $bytes_concat (i32.const 112) (i32.const 144)))
+      (call $assert (i32.eq (i32.load offset=8 (local.get $ptr)) (i32.const 3)))
+      (call $assert (i32.eq (i32.load8_u offset=10 (i32.load (local.get $ptr))) (i32.const 0xcd)))
+
+      ;; id.compose(["pair", -3]) is "pair--3"
+      (local.set $ptr (call $id_compose (i32.const 80)))
+      (call $assert (i32.eq (i32.load (local.get $ptr)) (i32.const 7)))
+      (call $assert (i32.eq (i32.load16_u offset=14 (local.get $ptr)) (i32.const 0x2d)))
+      (call $assert (i32.eq (i32.load16_u offset=16 (local.get $ptr)) (i32.const 0x33)))
+
+      ;; id.composeKeccak256 returns the 32 bytes of the hash of the id
+      (local.set $ptr (call $id_compose_keccak256 (i32.const 80)))
+      (call $assert (i32.eq (i32.load offset=8 (local.get $ptr)) (i32.const 32))))
+"#;
+
+#[tokio::test]
+async fn id_exports_run_with_api_version_0_0_5() {
+    let data_source = data_source("0.0.5", "[]", ID_IMPORTS, ID_MAPPING, "handleBlock");
+    let host = runtime_host(
+        STORE.clone(),
+        "hostIdExports",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    if let Err(e) = process_block(&host, block(1)).await {
+        panic!("the mapping failed to compose ids: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn id_exports_do_not_run_with_api_version_0_0_4() {
+    let data_source = data_source("0.0.4", "[]", ID_IMPORTS, ID_MAPPING, "handleBlock");
+    let host = runtime_host(
+        STORE.clone(),
+        "hostIdExportsApiVersion",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    assert!(
+        process_block(&host, block(1)).await.is_err(),
+        "mappings with apiVersion 0.0.4 can not import the id exports"
+    );
+}
+
+const BALANCE_ABI: &str = r#"[{
+    "type": "function",
+    "name": "balance",
+    "stateMutability": "view",
+    "inputs": [],
+    "outputs": [{ "name": "balance", "type": "uint256" }]
+}]"#;
+
+const CALL_IMPORTS: &str = r#"
+    (import "index" "ethereum.call" (func $ethereum_call (param i32) (result i32)))
+"#;
+
+const CALL_MAPPING: &str = r#"
+    ;; The strings "Contract", "balance" and "balance():(uint256)"
+    (data (i32.const 16) "\08\00\00\00C\00o\00n\00t\00r\00a\00c\00t\00")
+    (data (i32.const 40) "\07\00\00\00b\00a\00l\00a\00n\00c\00e\00")
+    (data (i32.const 64) "\13\00\00\00b\00a\00l\00a\00n\00c\00e\00(\00)\00:\00(\00u\00i\00n\00t\002\005\006\00)\00")
+    ;; The address of the contract
+    (data (i32.const 112) "\14\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\00\01")
+    (data (i32.const 144) "\70\00\00\00\00\00\00\00\14\00\00\00")
+    ;; The empty array of arguments
+    (data (i32.const 160) "\00\00\00\00\00\00\00\00")
+    (data (i32.const 168) "\a0\00\00\00\00\00\00\00")
+    ;; The call of `balance()`
+    (data (i32.const 176) "\10\00\00\00\90\00\00\00\28\00\00\00\40\00\00\00\a8\00\00\00")
+
+    (func (export "handleCallResult") (param $block i32)
+      (local $result i32)
+      (local.set $result (call $ethereum_call (i32.const 176)))
+
+      ;; The call has a `value` and no `revertReason`
+      (call $assert (i32.ne (i32.load (local.get $result)) (i32.const 0)))
+      (call $assert (i32.eqz (i32.load offset=4 (local.get $result))))
+
+      ;; The `value` is the `uint256` that `balance()` returns
+      (local.set $result (i32.load (local.get $result)))
+      (call $assert (i32.eq (i32.load offset=4 (local.get $result)) (i32.const 1)))
+      (call $assert (i32.eq (i32.load (i32.load offset=8 (i32.load (local.get $result)))) (i32.const 4))))
+
+    (func (export "handleRevertedCallResult") (param $block i32)
+      (local $result i32)
+      (local.set $result (call $ethereum_call (i32.const 176)))
+
+      ;; The call has no `value` and the `revertReason` "no balance"
+      (call $assert (i32.eqz (i32.load (local.get $result))))
+      (call $assert (i32.eq (i32.load (i32.load offset=4 (local.get $result))) (i32.const 10))))
+
+    (func (export "handleTokens") (param $block i32)
+      ;; The call returns the `uint256` that `balance()` returns
+      (call $assert (i32.eq (i32.load offset=4 (call $ethereum_call (i32.const 176))) (i32.const 1))))
+"#;
+
+/// An adapter whose calls of `balance()` return 7, or revert with `revert`
+fn balance_adapter(revert: Option<&'static str>) -> MockEthereumAdapter {
+    let mut adapter = MockEthereumAdapter::new();
+    adapter.expect_contract_call().returning(move |_, call, _| {
+        assert_eq!("balance", call.function.name);
+        match revert {
+            None => Box::new(future::ok(vec![ethabi::Token::Uint(ethabi::Uint::from(7))])),
+            Some(reason) => Box::new(future::err(EthereumContractCallError::Revert(
+                EthereumCallRevert::with_reason(reason),
+            ))),
+        }
+    });
+    adapter
+}
+
+#[tokio::test]
+async fn ethereum_call_returns_call_result_with_api_version_0_0_6() {
+    let data_source = data_source(
+        "0.0.6",
+        BALANCE_ABI,
+        CALL_IMPORTS,
+        CALL_MAPPING,
+        "handleCallResult",
+    );
+    let host = runtime_host(
+        STORE.clone(),
+        "hostCallResult",
+        data_source,
+        balance_adapter(None),
+    );
+
+    if let Err(e) = process_block(&host, block(1)).await {
+        panic!("the mapping did not get the call result: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn ethereum_call_returns_revert_reason_with_api_version_0_0_6() {
+    let data_source = data_source(
+        "0.0.6",
+        BALANCE_ABI,
+        CALL_IMPORTS,
+        CALL_MAPPING,
+        "handleRevertedCallResult",
+    );
+    let host = runtime_host(
+        STORE.clone(),
+        "hostRevertedCallResult",
+        data_source,
+        balance_adapter(Some("no balance")),
+    );
+
+    if let Err(e) = process_block(&host, block(1)).await {
+        panic!("the mapping did not get the revert reason: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn ethereum_call_returns_tokens_with_api_version_0_0_5() {
+    let data_source = data_source(
+        "0.0.5",
+        BALANCE_ABI,
+        CALL_IMPORTS,
+        CALL_MAPPING,
+        "handleTokens",
+    );
+    let host = runtime_host(
+        STORE.clone(),
+        "hostCallTokens",
+        data_source,
+        balance_adapter(None),
+    );
+
+    if let Err(e) = process_block(&host, block(1)).await {
+        panic!("the mapping did not get the tokens: {:?}", e);
+    }
+}
+
+/// A mapping whose block handler checks that `block.transactionCount` is
+/// `count`, which must be less than 128 so that it is a single byte
+fn transaction_count_mapping(count: u8) -> String {
+    format!(
+        r#"
+    (func (export "handleBlock") (param $block i32)
+      (local $count i32)
+      (local.set $count (i32.load offset=56 (local.get $block)))
+      (call $assert (i32.eq (i32.load offset=8 (local.get $count)) (i32.const 1)))
+      (call $assert (i32.eq (i32.load8_u offset=8 (i32.load (local.get $count))) (i32.const {}))))
+"#,
+        count
+    )
+}
+
+/// A block that was fetched without its transactions, as happens with
+/// header-only ingestion
+fn header_only_block(number: u64) -> LightEthereumBlock {
+    LightEthereumBlock {
+        transactions_root: H256::from_low_u64_be(0x7700),
+        ..block(number)
+    }
+}
+
+#[tokio::test]
+async fn block_handlers_count_fetched_transactions() {
+    let data_source = data_source(
+        "0.0.7",
+        "[]",
+        "",
+        &transaction_count_mapping(3),
+        "handleBlock",
+    );
+    // The adapter fails the mapping if the host asks it for the count
+    let host = runtime_host(
+        STORE.clone(),
+        "hostFullBlockTransactionCount",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    let block = LightEthereumBlock {
+        transactions: vec![Transaction::default(); 3],
+        ..block(1)
+    };
+    if let Err(e) = process_block(&host, block).await {
+        panic!("the mapping did not get the transaction count: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn block_handlers_get_transaction_count_of_header_only_blocks() {
+    let data_source = data_source(
+        "0.0.7",
+        "[]",
+        "",
+        &transaction_count_mapping(5),
+        "handleBlock",
+    );
+    let mut adapter = MockEthereumAdapter::new();
+    adapter
+        .expect_block_transaction_count()
+        .withf(|_, hash| *hash == H256::from_low_u64_be(1))
+        .times(1)
+        .returning(|_, _| Box::new(future::ok(5)));
+    let host = runtime_host(
+        STORE.clone(),
+        "hostLightBlockTransactionCount",
+        data_source,
+        adapter,
+    );
+
+    if let Err(e) = process_block(&host, header_only_block(1)).await {
+        panic!("the mapping did not get the transaction count: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn missing_transaction_count_is_possible_reorg() {
+    let data_source = data_source(
+        "0.0.7",
+        "[]",
+        "",
+        &transaction_count_mapping(5),
+        "handleBlock",
+    );
+    let mut adapter = MockEthereumAdapter::new();
+    adapter
+        .expect_block_transaction_count()
+        .returning(|_, hash| {
+            Box::new(future::err(format_err!(
+                "Ethereum node did not find block {:?}",
+                hash
+            )))
+        });
+    let host = runtime_host(
+        STORE.clone(),
+        "hostMissingBlockTransactionCount",
+        data_source,
+        adapter,
+    );
+
+    match process_block(&host, header_only_block(1)).await {
+        Err(MappingError::PossibleReorg(_)) => (),
+        other => panic!("expected a possible reorg, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn block_handlers_before_api_version_0_0_7_do_not_get_transaction_count() {
+    let data_source = data_source(
+        "0.0.6",
+        "[]",
+        "",
+        r#"(func (export "handleBlock") (param $block i32))"#,
+        "handleBlock",
+    );
+    // The adapter fails the mapping if the host asks it for the count
+    let host = runtime_host(
+        STORE.clone(),
+        "hostOldBlockTransactionCount",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    if let Err(e) = process_block(&host, header_only_block(1)).await {
+        panic!("the mapping failed: {:?}", e);
+    }
+}
+
+const INCREMENT_IMPORTS: &str = r#"
+    (import "index" "store.increment" (func $store_increment (param i32 i32 i32 i32)))
+"#;
+
+const INCREMENT_MAPPING: &str = r#"(func (export "handleBlock") (param $block i32))"#;
+
+#[tokio::test]
+async fn increment_is_available_with_api_version_0_0_8() {
+    let data_source = data_source(
+        "0.0.8",
+        "[]",
+        INCREMENT_IMPORTS,
+        INCREMENT_MAPPING,
+        "handleBlock",
+    );
+    let host = runtime_host(
+        STORE.clone(),
+        "hostIncrement",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    if let Err(e) = process_block(&host, block(1)).await {
+        panic!("the mapping failed to import store.increment: {:?}", e);
+    }
+}
+
+#[tokio::test]
+async fn increment_is_not_available_with_api_version_0_0_7() {
+    let data_source = data_source(
+        "0.0.7",
+        "[]",
+        INCREMENT_IMPORTS,
+        INCREMENT_MAPPING,
+        "handleBlock",
+    );
+    let host = runtime_host(
+        STORE.clone(),
+        "hostIncrementApiVersion",
+        data_source,
+        MockEthereumAdapter::new(),
+    );
+
+    match process_block(&host, block(1)).await {
+        Err(e) => assert!(
+            format!("{:?}", e).contains("requires apiVersion 0.0.8"),
+            "{:?}",
+            e
+        ),
+        Ok(()) => panic!("mappings with apiVersion 0.0.7 can not import store.increment"),
+    }
+}
@@ -0,0 +1,212 @@
+//! Advisory `Cache-Control` and `ETag` headers for GraphQL responses so that
+//! CDNs in front of query nodes know how long they may serve a response.
+//! Responses for blocks that can not be reverted anymore never change;
+//! responses that track the head of a deployment can change as soon as the
+//! next block is mined.
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::prelude::tiny_keccak::Keccak;
+use graph::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// Set to `false` to not send `Cache-Control` and `ETag` headers
+    static ref CACHE_CONTROL: bool = std::env::var("GRAPH_HTTP_CACHE_CONTROL")
+        .map(|s| s != "false")
+        .unwrap_or(true);
+
+    /// The `max-age` in seconds for responses for blocks that are final
+    static ref HISTORICAL_MAX_AGE: u64 = std::env::var("GRAPH_HTTP_CACHE_HISTORICAL_MAX_AGE")
+        .map(|s| s
+            .parse::<u64>()
+            .expect("invalid GRAPH_HTTP_CACHE_HISTORICAL_MAX_AGE"))
+        .unwrap_or(86400);
+
+    /// The largest `max-age` in seconds for responses for blocks close to
+    /// the chain head, regardless of how long blocks take on the chain
+    static ref MAX_HEAD_AGE: u64 = std::env::var("GRAPH_HTTP_CACHE_MAX_HEAD_AGE")
+        .map(|s| s
+            .parse::<u64>()
+            .expect("invalid GRAPH_HTTP_CACHE_MAX_HEAD_AGE"))
+        .unwrap_or(60);
+}
+
+/// The number of blocks over which we average the block time of a chain
+const BLOCK_TIME_SAMPLE: u64 = 100;
+
+/// How long we use what we found out about the head of a chain before
+/// we look at the chain store again
+const CHAIN_HEAD_TTL: Duration = Duration::from_secs(1);
+
+/// What we know about the head of a chain
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ChainHead {
+    pub number: u64,
+    /// The timestamp of the head block, in seconds since the epoch
+    pub timestamp: u64,
+    /// The average number of seconds between blocks over the last
+    /// `BLOCK_TIME_SAMPLE` blocks, if the chain store has these blocks
+    pub block_time: Option<f64>,
+}
+
+/// The heads of the chains that deployments index, as far as they matter
+/// for how long responses can be cached
+pub struct ChainHeads {
+    chain_stores: HashMap<String, Arc<dyn ChainStore>>,
+    heads: Mutex<HashMap<String, (Instant, ChainHead)>>,
+}
+
+impl ChainHeads {
+    pub fn new(chain_stores: HashMap<String, Arc<dyn ChainStore>>) -> Self {
+        ChainHeads {
+            chain_stores,
+            heads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The head of `network`, or `None` if we do not know it. This can
+    /// access the database and must not be called from async code
+    pub(crate) fn head(&self, network: &str) -> Option<ChainHead> {
+        if let Some((checked, head)) = self.heads.lock().unwrap().get(network) {
+            if checked.elapsed() < CHAIN_HEAD_TTL {
+                return Some(*head);
+            }
+        }
+
+        let head = Self::load(self.chain_stores.get(network)?.as_ref())
+            .ok()
+            .flatten()?;
+        self.heads
+            .lock()
+            .unwrap()
+            .insert(network.to_owned(), (Instant::now(), head));
+        Some(head)
+    }
+
+    fn load(store: &dyn ChainStore) -> Result<Option<ChainHead>, Error> {
+        let ptr = match store.chain_head_ptr()? {
+            Some(ptr) => ptr,
+            None => return Ok(None),
+        };
+        let start = ptr.number.saturating_sub(BLOCK_TIME_SAMPLE);
+        let mut hashes = vec![ptr.hash];
+        if start < ptr.number {
+            hashes.extend(
+                store
+                    .block_hashes_by_block_number(start)?
+                    .into_iter()
+                    .take(1),
+            );
+        }
+        let blocks = store.blocks(hashes)?;
+        let timestamp = |number: u64| {
+            blocks
+                .iter()
+                .find(|block| block.number.map(|n| n.as_u64()) == Some(number))
+                .map(|block| block.timestamp.as_u64())
+        };
+
+        let head_timestamp = match timestamp(ptr.number) {
+            Some(timestamp) => timestamp,
+            None => return Ok(None),
+        };
+        let block_time = timestamp(start)
+            .filter(|_| start < ptr.number)
+            .map(|start_timestamp| {
+                head_timestamp.saturating_sub(start_timestamp) as f64 / (ptr.number - start) as f64
+            });
+        Ok(Some(ChainHead {
+            number: ptr.number,
+            timestamp: head_timestamp,
+            block_time,
+        }))
+    }
+}
+
+/// How long clients may cache a response
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum CacheControl {
+    NoStore,
+    MaxAge(u64),
+}
+
+impl CacheControl {
+    /// How long `result` can be cached. The blocks of the result are final
+    /// if they are more than `REORG_THRESHOLD` blocks behind `head`, or
+    /// behind `latest_block` of the deployment if we don't know the chain
+    /// head. Responses for other blocks are valid until the next block is
+    /// expected, counting from `now` in seconds since the epoch. Return
+    /// `None` if the node should not send `Cache-Control` headers
+    pub(crate) fn for_result(
+        result: &QueryResult,
+        head: Option<&ChainHead>,
+        latest_block: BlockNumber,
+        now: u64,
+    ) -> Option<Self> {
+        if !*CACHE_CONTROL {
+            return None;
+        }
+        if result.has_errors() {
+            return Some(CacheControl::NoStore);
+        }
+
+        let head_number = head
+            .map(|head| head.number)
+            .unwrap_or_else(|| latest_block.max(0) as u64);
+        let is_final = !result.blocks().is_empty()
+            && result
+                .blocks()
+                .iter()
+                .all(|block| block.number.saturating_add(*REORG_THRESHOLD) <= head_number);
+        if is_final {
+            return Some(CacheControl::MaxAge(*HISTORICAL_MAX_AGE));
+        }
+
+        let remaining = head
+            .and_then(|head| {
+                head.block_time
+                    .map(|block_time| block_time - now.saturating_sub(head.timestamp) as f64)
+            })
+            .map(|remaining| (remaining.max(0.0) as u64).min(*MAX_HEAD_AGE))
+            .unwrap_or(0);
+        Some(CacheControl::MaxAge(remaining))
+    }
+
+    pub(crate) fn header_value(&self) -> String {
+        match self {
+            CacheControl::NoStore => "no-store".to_owned(),
+            CacheControl::MaxAge(secs) => format!("public, max-age={}", secs),
+        }
+    }
+}
+
+/// The `ETag` of the response to `request` for `deployment` with data from
+/// `blocks`. A query against the same blocks always produces the same
+/// response, which is what makes it possible to derive the tag from the
+/// request instead of the response
+pub(crate) fn etag(
+    deployment: &SubgraphDeploymentId,
+    blocks: &[EthereumBlockPointer],
+    request: &[u8],
+) -> String {
+    let mut hash = [0u8; 32];
+    let mut sponge = Keccak::new_keccak256();
+    sponge.update(deployment.as_str().as_bytes());
+    for block in blocks {
+        sponge.update(block.hash.as_bytes());
+    }
+    sponge.update(request);
+    sponge.finalize(&mut hash);
+
+    let hex: String = hash[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Whether the value of an `If-None-Match` header matches `etag`
+pub(crate) fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match.split(',').map(str::trim).any(|tag| {
+        let tag = tag.trim_start_matches("W/");
+        tag == "*" || tag == etag
+    })
+}
@@ -0,0 +1,159 @@
+//! Compression of HTTP responses for clients that advertise support for it
+//! with an `Accept-Encoding` header. Bodies are compressed while they are
+//! sent, so that we never hold the compressed form of a response in memory.
+use async_compression::stream::{BrotliEncoder, GzipEncoder};
+use graph::prelude::*;
+use http::header::{self, HeaderValue};
+use hyper::body::HttpBody;
+use hyper::{Body, Response};
+use std::io;
+
+lazy_static! {
+    /// Responses with fewer bytes than this are sent uncompressed
+    static ref COMPRESSION_THRESHOLD: u64 = std::env::var("GRAPH_GRAPHQL_COMPRESSION_THRESHOLD")
+        .map(|s| s
+            .parse::<u64>()
+            .expect("invalid GRAPH_GRAPHQL_COMPRESSION_THRESHOLD"))
+        .unwrap_or(1024);
+}
+
+/// The encodings we can compress responses with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// The encoding that the client prefers according to the value of its
+/// `Accept-Encoding` header, or `None` if it does not accept any that we
+/// support. When the client likes gzip and brotli equally, we use gzip
+/// since it is much cheaper to produce
+pub(crate) fn preferred_encoding(accept_encoding: &str) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let encoding = match params.next().unwrap().trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" | "*" => Encoding::Gzip,
+            "br" => Encoding::Brotli,
+            _ => continue,
+        };
+        let quality = params
+            .map(str::trim)
+            .find(|param| param.starts_with("q="))
+            .map(|param| param[2..].parse::<f32>().unwrap_or(0.0))
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        match best {
+            Some((best_encoding, best_quality))
+                if best_quality > quality
+                    || (best_quality == quality && best_encoding == Encoding::Gzip) => {}
+            _ => best = Some((encoding, quality)),
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress the body of `response` with the encoding that the client
+/// prefers according to `accept_encoding`. Responses that are smaller than
+/// `GRAPH_GRAPHQL_COMPRESSION_THRESHOLD` bytes or that are already encoded
+/// are returned unchanged
+pub(crate) fn compress_response(
+    accept_encoding: Option<&HeaderValue>,
+    response: Response<Body>,
+) -> Response<Body> {
+    if response.headers().contains_key(header::CONTENT_ENCODING) {
+        return response;
+    }
+    // Bodies without a known size are streamed and usually large
+    match response.body().size_hint().exact() {
+        Some(size) if size < *COMPRESSION_THRESHOLD => return response,
+        _ => {}
+    }
+
+    let (mut parts, body) = response.into_parts();
+    // Whether we compress a response this large depends on the client, and
+    // caches need to know that
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    let encoding = match accept_encoding
+        .and_then(|value| value.to_str().ok())
+        .and_then(preferred_encoding)
+    {
+        Some(encoding) => encoding,
+        None => return Response::from_parts(parts, body),
+    };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_ENCODING,
+        HeaderValue::from_static(encoding.as_str()),
+    );
+    let body = TryStreamExt::map_err(body, |e| io::Error::new(io::ErrorKind::Other, e));
+    let body = match encoding {
+        Encoding::Gzip => Body::wrap_stream(GzipEncoder::new(body)),
+        Encoding::Brotli => Body::wrap_stream(BrotliEncoder::new(body)),
+    };
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_accept_encoding() {
+        use Encoding::*;
+
+        assert_eq!(Some(Gzip), preferred_encoding("gzip"));
+        assert_eq!(Some(Brotli), preferred_encoding("br"));
+        assert_eq!(Some(Gzip), preferred_encoding("gzip, deflate, br"));
+        assert_eq!(Some(Gzip), preferred_encoding("br, gzip"));
+        assert_eq!(Some(Brotli), preferred_encoding("gzip;q=0.5, br"));
+        assert_eq!(Some(Gzip), preferred_encoding("br;q=0.2, GZIP;q=0.8"));
+        assert_eq!(Some(Gzip), preferred_encoding("deflate, *"));
+        assert_eq!(Some(Brotli), preferred_encoding("gzip;q=0, br"));
+        assert_eq!(None, preferred_encoding("gzip;q=0"));
+        assert_eq!(None, preferred_encoding("identity"));
+        assert_eq!(None, preferred_encoding(""));
+    }
+
+    #[test]
+    fn leaves_small_responses_alone() {
+        let accept = HeaderValue::from_static("gzip");
+        let response = compress_response(Some(&accept), Response::new(Body::from("small")));
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!response.headers().contains_key(header::VARY));
+    }
+
+    #[test]
+    fn compresses_large_responses() {
+        let large = "x".repeat(*COMPRESSION_THRESHOLD as usize);
+        let accept = HeaderValue::from_static("br;q=1, gzip;q=0.5");
+        let response = compress_response(Some(&accept), Response::new(Body::from(large.clone())));
+        assert_eq!(
+            Some(&HeaderValue::from_static("br")),
+            response.headers().get(header::CONTENT_ENCODING)
+        );
+        assert_eq!(
+            Some(&HeaderValue::from_static("accept-encoding")),
+            response.headers().get(header::VARY)
+        );
+
+        // Clients that do not accept compression get the plain response
+        let response = compress_response(None, Response::new(Body::from(large)));
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(response.headers().contains_key(header::VARY));
+    }
+}
@@ -1,3 +1,4 @@
+extern crate async_compression;
 extern crate futures;
 extern crate graph;
 extern crate graph_graphql;
@@ -8,10 +9,13 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod cache_control;
+mod compression;
 mod request;
 mod server;
 mod service;
 
+pub use self::cache_control::ChainHeads;
 pub use self::request::GraphQLRequest;
 pub use self::server::GraphQLServer;
 pub use self::service::{GraphQLService, GraphQLServiceResponse};
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
 use hyper;
+use hyper::server::conn::AddrStream;
 use hyper::service::make_service_fn;
 use hyper::Server;
 
+use crate::cache_control::ChainHeads;
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::server::rate_limit::RateLimiter;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
 
 /// Errors that may occur when starting the server.
@@ -26,16 +30,20 @@ pub struct GraphQLServer<Q, S> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    chain_heads: Arc<ChainHeads>,
     node_id: NodeId,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl<Q, S> GraphQLServer<Q, S> {
-    /// Creates a new GraphQL server.
+    /// Creates a new GraphQL server. The `chain_stores` are used to find
+    /// out how long responses for each network stay valid
     pub fn new(
         logger_factory: &LoggerFactory,
         metrics_registry: Arc<impl MetricsRegistry>,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        chain_stores: HashMap<String, Arc<dyn ChainStore>>,
         node_id: NodeId,
     ) -> Self {
         let logger = logger_factory.component_logger(
@@ -52,15 +60,23 @@ impl<Q, S> GraphQLServer<Q, S> {
             metrics,
             graphql_runner,
             store,
+            chain_heads: Arc::new(ChainHeads::new(chain_stores)),
             node_id,
+            rate_limiter: None,
         }
     }
+
+    /// Limit how many queries each client may send
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
 }
 
 impl<Q, S> GraphQLServerTrait for GraphQLServer<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + DeploymentStore,
 {
     type ServeError = GraphQLServeError;
 
@@ -84,16 +100,22 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let store = self.store.clone();
+        let chain_heads = self.chain_heads.clone();
         let node_id = self.node_id.clone();
-        let new_service = make_service_fn(move |_| {
-            futures03::future::ok::<_, Error>(GraphQLService::new(
-                logger_for_service.clone(),
-                metrics.clone(),
-                graphql_runner.clone(),
-                store.clone(),
-                ws_port,
-                node_id.clone(),
-            ))
+        let rate_limiter = self.rate_limiter.clone();
+        let new_service = make_service_fn(move |conn: &AddrStream| {
+            futures03::future::ok::<_, Error>(
+                GraphQLService::new(
+                    logger_for_service.clone(),
+                    metrics.clone(),
+                    graphql_runner.clone(),
+                    store.clone(),
+                    chain_heads.clone(),
+                    ws_port,
+                    node_id.clone(),
+                )
+                .with_rate_limiter(rate_limiter.clone(), conn.remote_addr()),
+            )
         });
 
         // Create a task to run the server and handle HTTP requests
@@ -1,19 +1,36 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::task::Context;
 use std::task::Poll;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use graph::components::server::query::GraphQLServerError;
+use graph::components::server::rate_limit::{self, RateLimiter};
+use graph::data::query::{new_query_id, QUERY_ID_HEADER};
 use graph::data::subgraph::schema::{SubgraphEntity, SUBGRAPHS_ID};
 use graph::prelude::*;
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
+use crate::cache_control::{self, CacheControl, ChainHeads};
+use crate::compression::compress_response;
 use crate::request::GraphQLRequest;
 
+/// Clients can set this header to `allow` to receive data from failed
+/// subgraphs for all fields of a query
+const ERROR_POLICY_HEADER: &str = "Graph-Subgraph-Error";
+
+lazy_static! {
+    /// Set to `true` to send a warning in the extensions of responses for
+    /// deployments that an operator flagged as unhealthy
+    static ref FLAGGED_WARNING: bool = std::env::var("GRAPH_GRAPHQL_FLAGGED_WARNING")
+        .map(|s| s == "true")
+        .unwrap_or(false);
+}
+
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
     failed_query_execution_time: Box<HistogramVec>,
@@ -76,8 +93,11 @@ pub struct GraphQLService<Q, S> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    chain_heads: Arc<ChainHeads>,
     ws_port: u16,
     node_id: NodeId,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    remote_addr: Option<SocketAddr>,
 }
 
 impl<Q, S> Clone for GraphQLService<Q, S> {
@@ -87,8 +107,11 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
             metrics: self.metrics.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            chain_heads: self.chain_heads.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            remote_addr: self.remote_addr,
         }
     }
 }
@@ -96,7 +119,7 @@ impl<Q, S> Clone for GraphQLService<Q, S> {
 impl<Q, S> GraphQLService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + DeploymentStore,
 {
     /// Creates a new GraphQL service.
     pub fn new(
@@ -104,6 +127,7 @@ where
         metrics: Arc<GraphQLServiceMetrics>,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        chain_heads: Arc<ChainHeads>,
         ws_port: u16,
         node_id: NodeId,
     ) -> Self {
@@ -112,11 +136,53 @@ where
             metrics,
             graphql_runner,
             store,
+            chain_heads,
             ws_port,
             node_id,
+            rate_limiter: None,
+            remote_addr: None,
         }
     }
 
+    /// Limit how many queries the client at `remote_addr` may send with
+    /// `rate_limiter`, if there is one
+    pub fn with_rate_limiter(
+        mut self,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        remote_addr: SocketAddr,
+    ) -> Self {
+        self.rate_limiter = rate_limiter;
+        self.remote_addr = Some(remote_addr);
+        self
+    }
+
+    /// Take a token from the rate limit bucket of the client for
+    /// `deployment`, and return a `429 Too Many Requests` response if there
+    /// was none
+    fn check_rate_limit(
+        &self,
+        deployment: &SubgraphDeploymentId,
+        request: &Request<Body>,
+    ) -> Option<Response<Body>> {
+        let (rate_limiter, remote_addr) = match (&self.rate_limiter, self.remote_addr) {
+            (Some(rate_limiter), Some(remote_addr)) => (rate_limiter, remote_addr),
+            _ => return None,
+        };
+        let client = rate_limiter.client_ip(remote_addr.ip(), request.headers());
+        let retry_in = rate_limiter.check(client, deployment).err()?;
+        debug!(self.logger, "Rate limited query";
+               "subgraph_id" => deployment.as_str(),
+               "client" => client.to_string());
+        Some(
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Access-Control-Allow-Origin", "*")
+                .header(header::RETRY_AFTER, rate_limit::retry_after(retry_in))
+                .body(Body::from("Rate limit exceeded"))
+                .unwrap(),
+        )
+    }
+
     fn graphiql_html(&self) -> String {
         include_str!("../assets/index.html")
             .replace("__WS_PORT__", format!("{}", self.ws_port).as_str())
@@ -198,7 +264,7 @@ where
                 .unwrap() // Propagate panics.
                 .map_err(|e| GraphQLServerError::from(e))?;
 
-        self.handle_graphql_query(state, request.into_body()).await
+        self.handle_graphql_query(state, request).await
     }
 
     fn handle_graphql_query_by_id(
@@ -215,20 +281,49 @@ where
             });
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(state) => self
-                .handle_graphql_query(state, request.into_body())
-                .boxed(),
+            Ok(state) => self.handle_graphql_query(state, request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         state: DeploymentState,
-        request_body: Body,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
+        if let Some(response) = self.check_rate_limit(&state.id, &request) {
+            return Ok(response);
+        }
+
+        let error_policy = match request.headers().get(ERROR_POLICY_HEADER) {
+            None => ErrorPolicy::default(),
+            Some(value) => value
+                .to_str()
+                .map_err(|e| e.to_string())
+                .and_then(|value| value.parse())
+                .map_err(|e| {
+                    GraphQLServerError::ClientError(format!(
+                        "Invalid `{}` header: {}",
+                        ERROR_POLICY_HEADER, e
+                    ))
+                })?,
+        };
+        let if_none_match = request
+            .headers()
+            .get(header::IF_NONE_MATCH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let request_body = request.into_body();
         let service = self.clone();
         let service_metrics = self.metrics.clone();
         let sd_id = state.id.clone();
+        let latest_block = state.latest_ethereum_block_number;
+        let warning = match &state.flag {
+            Some(flag) if *FLAGGED_WARNING => Some(format!(
+                "deployment {} is flagged as unhealthy: {}",
+                sd_id, flag.note
+            )),
+            _ => None,
+        };
 
         let schema = match self.store.api_schema(&state.id) {
             Ok(schema) => schema,
@@ -248,18 +343,124 @@ where
         let body = hyper::body::to_bytes(request_body)
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = GraphQLRequest::new(body, schema, network).compat().await;
+        // The response depends on the error policy as much as on the body
+        let cache_key = [body.as_ref(), format!("{:?}", error_policy).as_bytes()].concat();
+        let query = GraphQLRequest::new(body, schema, network.clone())
+            .compat()
+            .await;
 
+        let query_id = new_query_id();
         let result = match query {
-            Ok(query) => service.graphql_runner.run_query(query, state, false).await,
-            Err(GraphQLServerError::QueryError(e)) => Arc::new(QueryResult::from(e)),
+            Ok(mut query) => {
+                query.error_policy = error_policy;
+                query.query_id = query_id.clone();
+                service.graphql_runner.run_query(query, state, false).await
+            }
+            Err(GraphQLServerError::QueryError(e)) => {
+                // The runner logs the queries it runs; requests that did
+                // not even parse need to be logged here
+                let result = QueryResult::from(e);
+                info!(
+                    self.logger,
+                    "Query served";
+                    "query_id" => &query_id,
+                    "subgraph_id" => sd_id.as_str(),
+                    "query_time_ms" => start.elapsed().as_millis(),
+                    "error" => result.error_class().unwrap_or("none"),
+                );
+                Arc::new(result)
+            }
             Err(e) => return Err(e),
         };
 
         service_metrics
             .observe_query_execution_time(start.elapsed().as_secs_f64(), sd_id.to_string());
 
-        Ok(result.as_http_response())
+        let mut response = result.as_http_response_with_warning(&query_id, warning.as_deref());
+        if warning.is_some() {
+            // The flag can be removed at any time, and responses must not
+            // outlive it in a cache
+            response.headers_mut().insert(
+                header::CACHE_CONTROL,
+                header::HeaderValue::from_str(&CacheControl::NoStore.header_value()).unwrap(),
+            );
+            return Ok(response);
+        }
+        Ok(self
+            .add_cache_headers(
+                response,
+                &result,
+                &sd_id,
+                network,
+                latest_block,
+                &cache_key,
+                if_none_match,
+            )
+            .await)
+    }
+
+    /// Tell clients and CDNs how long they may cache the `response` for
+    /// `result` with `Cache-Control` and `ETag` headers. If the client
+    /// already has the response according to its `If-None-Match` header,
+    /// send it a `304 Not Modified` instead
+    async fn add_cache_headers(
+        &self,
+        mut response: Response<Body>,
+        result: &QueryResult,
+        deployment: &SubgraphDeploymentId,
+        network: Option<String>,
+        latest_block: BlockNumber,
+        request: &[u8],
+        if_none_match: Option<String>,
+    ) -> Response<Body> {
+        let head = match (result.has_errors(), network) {
+            (false, Some(network)) => {
+                let chain_heads = self.chain_heads.clone();
+                tokio::task::spawn_blocking(move || chain_heads.head(&network))
+                    .await
+                    .unwrap() // Propagate panics.
+            }
+            _ => None,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.as_secs())
+            .unwrap_or(0);
+        let cache_control = match CacheControl::for_result(result, head.as_ref(), latest_block, now)
+        {
+            Some(cache_control) => cache_control,
+            None => return response,
+        };
+
+        let etag = match cache_control {
+            CacheControl::NoStore => None,
+            CacheControl::MaxAge(_) => {
+                Some(cache_control::etag(deployment, result.blocks(), request))
+            }
+        };
+        if let (Some(etag), Some(if_none_match)) = (&etag, &if_none_match) {
+            if cache_control::etag_matches(if_none_match, etag) {
+                let query_id = response.headers().get(QUERY_ID_HEADER).cloned();
+                response = Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::empty())
+                    .unwrap();
+                if let Some(query_id) = query_id {
+                    response.headers_mut().insert(QUERY_ID_HEADER, query_id);
+                }
+            }
+        }
+
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_str(&cache_control.header_value()).unwrap(),
+        );
+        if let Some(etag) = etag {
+            headers.insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+        }
+        response
     }
 
     // Handles OPTIONS requests
@@ -268,7 +469,10 @@ where
             Ok(Response::builder()
                 .status(200)
                 .header("Access-Control-Allow-Origin", "*")
-                .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+                .header(
+                    "Access-Control-Allow-Headers",
+                    "Content-Type, User-Agent, Graph-Subgraph-Error",
+                )
                 .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
                 .body(Body::from(""))
                 .unwrap())
@@ -376,7 +580,7 @@ where
 impl<Q, S> Service<Request<Body>> for GraphQLService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + DeploymentStore,
 {
     type Response = Response<Body>;
     type Error = GraphQLServerError;
@@ -389,13 +593,14 @@ where
     fn call(&mut self, req: Request<Body>) -> Self::Future {
         let logger = self.logger.clone();
         let service = self.clone();
+        let accept_encoding = req.headers().get(header::ACCEPT_ENCODING).cloned();
 
         // Returning Err here will prevent the client from receiving any response.
         // Instead, we generate a Response with an error code and return Ok
         Box::pin(async move {
             let result = service.handle_call(req).await;
             match result {
-                Ok(response) => Ok(response),
+                Ok(response) => Ok(compress_response(accept_encoding.as_ref(), response)),
                 Err(err @ GraphQLServerError::ClientError(_)) => Ok(Response::builder()
                     .status(400)
                     .header("Content-Type", "text/plain")
@@ -426,16 +631,23 @@ where
 
 #[cfg(test)]
 mod tests {
+    use http::header;
     use http::status::StatusCode;
     use hyper::service::Service;
-    use hyper::{Body, Method, Request};
-    use std::collections::BTreeMap;
+    use hyper::{Body, Method, Request, Response};
+    use std::collections::{BTreeMap, HashMap};
+    use std::time::{SystemTime, UNIX_EPOCH};
 
     use graph::data::graphql::effort::LoadManager;
+    use graph::prelude::web3::types::{Block, H256, U256, U64};
     use graph::prelude::*;
-    use graph_mock::{mock_store_with_users_subgraph, MockMetricsRegistry};
+    use graph_mock::{
+        mock_store_with_users_subgraph, mock_store_with_users_subgraph_on_network,
+        MockMetricsRegistry, MockStore,
+    };
     use graphql_parser::query as q;
 
+    use crate::cache_control::ChainHeads;
     use crate::test_utils;
 
     use super::GraphQLService;
@@ -461,17 +673,35 @@ mod tests {
 
         async fn run_query(
             self: Arc<Self>,
-            _query: Query,
+            query: Query,
             _state: DeploymentState,
             _: bool,
         ) -> Arc<QueryResult> {
-            Arc::new(QueryResult::new(vec![Arc::new(BTreeMap::from_iter(
+            let variable = |name: &str| {
+                query
+                    .variables
+                    .as_ref()
+                    .and_then(|variables| variables.get(&String::from(name)).cloned())
+            };
+            // Queries with a `fail` variable fail, and queries with a
+            // `block` variable read data at that block
+            if variable("fail").is_some() {
+                return Arc::new(QueryExecutionError::Timeout.into());
+            }
+            let result = QueryResult::new(vec![Arc::new(BTreeMap::from_iter(
                 vec![(
                     String::from("name"),
                     q::Value::String(String::from("Jordi")),
                 )]
                 .into_iter(),
-            ))]))
+            ))]);
+            match variable("block") {
+                Some(q::Value::Int(number)) => {
+                    let number = number.as_i64().unwrap() as u64;
+                    Arc::new(result.with_block(block_ptr(number)))
+                }
+                _ => Arc::new(result),
+            }
         }
 
         async fn run_subscription(
@@ -493,10 +723,18 @@ mod tests {
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
         let (store, subgraph_id) = mock_store_with_users_subgraph();
         let graphql_runner = Arc::new(TestGraphQlRunner);
+        let chain_heads = Arc::new(ChainHeads::new(HashMap::new()));
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            chain_heads,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -526,10 +764,18 @@ mod tests {
         let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
         let (store, subgraph_id) = mock_store_with_users_subgraph();
         let graphql_runner = Arc::new(TestGraphQlRunner);
+        let chain_heads = Arc::new(ChainHeads::new(HashMap::new()));
 
         let node_id = NodeId::new("test").unwrap();
-        let mut service =
-            GraphQLService::new(logger, metrics, graphql_runner, store, 8001, node_id);
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            chain_heads,
+            8001,
+            node_id,
+        );
 
         let request = Request::builder()
             .method(Method::POST)
@@ -555,4 +801,161 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    fn block_ptr(number: u64) -> EthereumBlockPointer {
+        EthereumBlockPointer {
+            hash: H256::from_low_u64_be(number),
+            number,
+        }
+    }
+
+    /// The head of mainnet is block 1000, which was mined 5 seconds ago,
+    /// and blocks are 15 seconds apart
+    fn chain_heads() -> Arc<ChainHeads> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let block = move |number: u64| Block {
+            hash: Some(H256::from_low_u64_be(number)),
+            number: Some(U64::from(number)),
+            timestamp: U256::from(now - 5 - (1000 - number) * 15),
+            ..Default::default()
+        };
+
+        let mut chain_store = MockStore::new();
+        chain_store
+            .expect_chain_head_ptr()
+            .returning(|| Ok(Some(block_ptr(1000))));
+        chain_store
+            .expect_block_hashes_by_block_number()
+            .returning(|number| Ok(vec![H256::from_low_u64_be(number)]));
+        chain_store.expect_blocks().returning(move |hashes| {
+            Ok(hashes
+                .into_iter()
+                .map(|hash| block(hash.to_low_u64_be()))
+                .collect())
+        });
+
+        let mut chain_stores = HashMap::new();
+        chain_stores.insert(
+            "mainnet".to_owned(),
+            Arc::new(chain_store) as Arc<dyn ChainStore>,
+        );
+        Arc::new(ChainHeads::new(chain_stores))
+    }
+
+    /// Send `body` to the "users" subgraph on mainnet
+    async fn query(body: &str, if_none_match: Option<&str>) -> Response<Body> {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let (store, subgraph_id) = mock_store_with_users_subgraph_on_network(Some("mainnet"));
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(
+            logger,
+            metrics,
+            graphql_runner,
+            store,
+            chain_heads(),
+            8001,
+            node_id,
+        );
+
+        let mut request = Request::builder().method(Method::POST).uri(format!(
+            "http://localhost:8000/subgraphs/id/{}",
+            subgraph_id
+        ));
+        if let Some(etag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        let request = request.body(Body::from(body.to_owned())).unwrap();
+
+        tokio::spawn(service.call(request))
+            .await
+            .unwrap()
+            .expect("Should return a response")
+    }
+
+    fn header(response: &Response<Body>, name: header::HeaderName) -> Option<String> {
+        response
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn historical_queries_are_cached_for_long() {
+        let response = query(r#"{"query": "{ name }", "variables": {"block": 10}}"#, None).await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            Some("public, max-age=86400".to_owned()),
+            header(&response, header::CACHE_CONTROL)
+        );
+        assert!(header(&response, header::ETAG).is_some());
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn head_queries_are_cached_until_the_next_block() {
+        let response = query(
+            r#"{"query": "{ name }", "variables": {"block": 999}}"#,
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+
+        // The next block is expected in 10 seconds; allow for the time it
+        // took to run the query
+        let cache_control = header(&response, header::CACHE_CONTROL).unwrap();
+        assert!(
+            cache_control == "public, max-age=10" || cache_control == "public, max-age=9",
+            "unexpected Cache-Control: {}",
+            cache_control
+        );
+        assert!(header(&response, header::ETAG).is_some());
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn errors_are_not_stored() {
+        let response = query(
+            r#"{"query": "{ name }", "variables": {"fail": true}}"#,
+            None,
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            Some("no-store".to_owned()),
+            header(&response, header::CACHE_CONTROL)
+        );
+        assert_eq!(None, header(&response, header::ETAG));
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn conditional_requests_get_not_modified() {
+        const BODY: &str = r#"{"query": "{ name }", "variables": {"block": 10}}"#;
+
+        let response = query(BODY, None).await;
+        let etag = header(&response, header::ETAG).unwrap();
+
+        let response = query(BODY, Some(&etag)).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+        assert_eq!(Some(etag.clone()), header(&response, header::ETAG));
+        assert_eq!(
+            Some("public, max-age=86400".to_owned()),
+            header(&response, header::CACHE_CONTROL)
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+
+        // A query at another block has a different tag
+        let response = query(
+            r#"{"query": "{ name }", "variables": {"block": 11}}"#,
+            Some(&etag),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, response.status());
+        assert_ne!(Some(etag), header(&response, header::ETAG));
+    }
 }
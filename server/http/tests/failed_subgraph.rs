@@ -0,0 +1,146 @@
+//! Queries over HTTP against a deployment that has failed, served by the
+//! real `GraphQlRunner` from the shared test store
+use graph::data::subgraph::schema::SubgraphError;
+use graph::prelude::*;
+use graph_graphql::prelude::GraphQlRunner;
+use graph_mock::MockMetricsRegistry;
+use graph_server_http::GraphQLServer as HyperGraphQLServer;
+use hyper::{Body, Client, Request};
+use std::collections::HashMap;
+use test_store::{
+    create_test_subgraph, insert_entities, remove_subgraphs, run_test_sequentially, GENESIS_PTR,
+    LOAD_MANAGER, LOGGER, STORE,
+};
+use tokio::time::delay_for;
+
+const SCHEMA: &str = "type User @entity { id: ID!, name: String }";
+
+fn setup() -> SubgraphDeploymentId {
+    let id = SubgraphDeploymentId::new("failedSubgraphHttp").unwrap();
+    remove_subgraphs();
+    create_test_subgraph(id.as_str(), SCHEMA);
+    insert_entities(
+        id.clone(),
+        vec![(
+            "User",
+            Entity::from(vec![
+                ("id", Value::from("u1")),
+                ("name", Value::from("Alice")),
+            ]),
+        )],
+    )
+    .unwrap();
+    STORE
+        .fail_subgraph(
+            &id,
+            SubgraphError {
+                subgraph_id: id.clone(),
+                message: "cow jumped over the moon".to_owned(),
+                block_ptr: Some(GENESIS_PTR.clone()),
+                handler: None,
+                deterministic: true,
+                count: 1,
+            },
+        )
+        .unwrap();
+    id
+}
+
+/// Start a server on `port` whose runner serves failed subgraphs if
+/// `serve_failed_subgraphs` is set, and send it `{ users { name } }`,
+/// with `policy` in the `Graph-Subgraph-Error` header if it is given
+async fn query(
+    port: u16,
+    id: &SubgraphDeploymentId,
+    serve_failed_subgraphs: bool,
+    policy: Option<&str>,
+) -> (u16, String) {
+    let logger_factory = LoggerFactory::new(LOGGER.clone(), None);
+    let runner = Arc::new(
+        GraphQlRunner::new(&*LOGGER, STORE.clone(), LOAD_MANAGER.clone())
+            .serve_failed_subgraphs(serve_failed_subgraphs),
+    );
+    let mut server = HyperGraphQLServer::new(
+        &logger_factory,
+        Arc::new(MockMetricsRegistry::new()),
+        runner,
+        STORE.clone(),
+        HashMap::new(),
+        NodeId::new("test").unwrap(),
+    );
+    let http_server = server
+        .serve(port, port + 1)
+        .expect("Failed to start GraphQL server");
+    tokio::spawn(http_server.fuse().compat());
+    // Give some time for the server to start.
+    delay_for(Duration::from_secs(2)).await;
+
+    let mut request = Request::post(format!("http://localhost:{}/subgraphs/id/{}", port, id));
+    if let Some(policy) = policy {
+        request = request.header("Graph-Subgraph-Error", policy);
+    }
+    let request = request
+        .body(Body::from("{\"query\": \"{ users { name } }\"}"))
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    let status = response.status().as_u16();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+fn json(body: &str) -> serde_json::Value {
+    serde_json::from_str(body).expect("response is not valid JSON")
+}
+
+fn users(body: &serde_json::Value) -> &serde_json::Value {
+    &body["data"]["users"]
+}
+
+fn error_message(body: &serde_json::Value) -> &str {
+    body["errors"][0]["message"]
+        .as_str()
+        .expect("response has an error message")
+}
+
+#[test]
+fn failed_subgraph_returns_the_failure() {
+    run_test_sequentially(setup, |_, id| async move {
+        let (status, body) = query(8101, &id, false, None).await;
+        assert_eq!(200, status);
+        let body = json(&body);
+        assert!(body.get("data").map_or(true, |data| data.is_null()));
+        let message = error_message(&body);
+        assert!(message.contains("failed"), "unexpected error `{}`", message);
+        assert!(message.contains("cow jumped over the moon"));
+
+        let (status, body) = query(8103, &id, false, Some("deny")).await;
+        assert_eq!(200, status);
+        assert!(error_message(&json(&body)).contains("cow jumped over the moon"));
+
+        // The header allows errors for the whole query
+        let (status, body) = query(8105, &id, false, Some("allow")).await;
+        assert_eq!(200, status);
+        let body = json(&body);
+        assert_eq!(&serde_json::json!([{ "name": "Alice" }]), users(&body));
+        assert!(body.get("errors").is_none());
+
+        let (status, body) = query(8107, &id, false, Some("whatever")).await;
+        assert_eq!(400, status);
+        assert!(body.contains("Invalid `Graph-Subgraph-Error` header"));
+    })
+}
+
+#[test]
+fn failed_subgraph_is_served_when_configured() {
+    run_test_sequentially(setup, |_, id| async move {
+        // With `GRAPH_GRAPHQL_SERVE_FAILED_SUBGRAPHS`, the data as of the
+        // failure is returned no matter what the header says
+        for (port, policy) in vec![(8111, None), (8113, Some("deny")), (8115, Some("allow"))] {
+            let (status, body) = query(port, &id, true, policy).await;
+            assert_eq!(200, status);
+            let body = json(&body);
+            assert_eq!(&serde_json::json!([{ "name": "Alice" }]), users(&body));
+            assert!(body.get("errors").is_none());
+        }
+    })
+}
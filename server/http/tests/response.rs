@@ -1,3 +1,4 @@
+use graph::data::query::QUERY_ID_HEADER;
 use graph::prelude::*;
 use graph_graphql::object;
 use graph_server_http::test_utils;
@@ -19,6 +20,32 @@ fn generates_valid_json_for_an_empty_result() {
     assert!(data.is_empty());
 }
 
+#[test]
+fn sends_query_id_in_header_and_extensions() {
+    let query_result = QueryResult::try_from(object! { id: "12345" })
+        .unwrap()
+        .as_http_response_with_query_id::<String>("some-query-id");
+    assert_eq!(
+        "some-query-id",
+        query_result.headers().get(QUERY_ID_HEADER).unwrap()
+    );
+    assert_eq!(
+        r#"{"data":{"id":"12345"},"extensions":{"queryId":"some-query-id"}}"#,
+        query_result.body()
+    );
+}
+
+#[test]
+fn sends_warning_in_extensions() {
+    let query_result = QueryResult::try_from(object! { id: "12345" })
+        .unwrap()
+        .as_http_response_with_warning::<String>("some-query-id", Some("flagged"));
+    assert_eq!(
+        r#"{"data":{"id":"12345"},"extensions":{"queryId":"some-query-id","warning":"flagged"}}"#,
+        query_result.body()
+    );
+}
+
 #[test]
 fn canonical_serialization() {
     macro_rules! assert_resp {
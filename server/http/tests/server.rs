@@ -1,7 +1,11 @@
+use async_compression::stream::{BrotliDecoder, GzipDecoder};
 use graphql_parser::query as q;
 use http::StatusCode;
-use hyper::{Body, Client, Request};
-use std::collections::BTreeMap;
+use hyper::body::Bytes;
+use hyper::{Body, Client, Request, Response};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::pin::Pin;
 use std::time::Duration;
 
 use graph::data::graphql::effort::LoadManager;
@@ -36,6 +40,21 @@ impl GraphQlRunner for TestGraphQlRunner {
         _state: DeploymentState,
         _: bool,
     ) -> Arc<QueryResult> {
+        // Queries with a `repeat` variable get a large response
+        if let Some(q::Value::String(repeat)) = query
+            .variables
+            .as_ref()
+            .and_then(|variables| variables.get(&String::from("repeat")))
+        {
+            return Arc::new(QueryResult::new(vec![Arc::new(BTreeMap::from_iter(
+                vec![(
+                    String::from("name"),
+                    q::Value::String(repeat.repeat(100_000)),
+                )]
+                .into_iter(),
+            ))]));
+        }
+
         Arc::new(QueryResult::new(vec![Arc::new(
             if query.variables.is_some()
                 && query
@@ -79,6 +98,33 @@ impl GraphQlRunner for TestGraphQlRunner {
     }
 }
 
+/// The JSON body of `response`, decompressed according to its
+/// `Content-Encoding`
+async fn json_body(response: Response<Body>) -> serde_json::Value {
+    let encoding = response
+        .headers()
+        .get("Content-Encoding")
+        .map(|encoding| encoding.to_str().unwrap().to_owned());
+    let body = response
+        .into_body()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+    let body: Pin<Box<dyn futures03::Stream<Item = io::Result<Bytes>> + Send>> =
+        match encoding.as_deref() {
+            None => Box::pin(body),
+            Some("gzip") => Box::pin(GzipDecoder::new(body)),
+            Some("br") => Box::pin(BrotliDecoder::new(body)),
+            Some(encoding) => panic!("unexpected Content-Encoding `{}`", encoding),
+        };
+    let body = body
+        .try_fold(Vec::new(), |mut body, chunk| {
+            body.extend_from_slice(&chunk);
+            futures03::future::ready(Ok(body))
+        })
+        .await
+        .expect("Failed to decompress response");
+    serde_json::from_slice(&body).expect("GraphQL response is not valid JSON")
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -95,7 +141,7 @@ mod test {
                 let (store, id) = mock_store_with_users_subgraph();
                 let query_runner = Arc::new(TestGraphQlRunner);
                 let node_id = NodeId::new("test").unwrap();
-                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, store, node_id);
+                let mut server = HyperGraphQLServer::new(&logger_factory, metrics_registry, query_runner, store, HashMap::new(), node_id);
                 let http_server = server
                     .serve(8001, 8002)
                     .expect("Failed to start GraphQL server");
@@ -142,6 +188,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                HashMap::new(),
                 node_id,
             );
             let http_server = server
@@ -229,6 +276,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                HashMap::new(),
                 node_id,
             );
             let http_server = server
@@ -281,6 +329,7 @@ mod test {
                 metrics_registry,
                 query_runner,
                 store,
+                HashMap::new(),
                 node_id,
             );
             let http_server = server
@@ -330,4 +379,94 @@ mod test {
                 .unwrap()
         });
     }
+
+    #[test]
+    fn compresses_large_responses() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let logger = Logger::root(slog::Discard, o!());
+            let logger_factory = LoggerFactory::new(logger, None);
+            let metrics_registry = Arc::new(MockMetricsRegistry::new());
+            let (store, id) = mock_store_with_users_subgraph();
+            let query_runner = Arc::new(TestGraphQlRunner);
+            let node_id = NodeId::new("test").unwrap();
+            let mut server = HyperGraphQLServer::new(
+                &logger_factory,
+                metrics_registry,
+                query_runner,
+                store,
+                HashMap::new(),
+                node_id,
+            );
+            let http_server = server
+                .serve(8007, 8008)
+                .expect("Failed to start GraphQL server");
+
+            tokio::spawn(http_server.fuse().compat());
+            // Give some time for the server to start.
+            delay_for(Duration::from_secs(2)).await;
+
+            let client = Client::new();
+            let query = |accept_encoding: Option<&str>, repeat: Option<&str>| {
+                let variables = repeat
+                    .map(|repeat| format!("{{ \"repeat\": \"{}\" }}", repeat))
+                    .unwrap_or("null".to_owned());
+                let mut request =
+                    Request::post(format!("http://localhost:8007/subgraphs/id/{}", id));
+                if let Some(accept_encoding) = accept_encoding {
+                    request = request.header("Accept-Encoding", accept_encoding);
+                }
+                client.request(
+                    request
+                        .body(Body::from(format!(
+                            "{{\"query\": \"{{ name }}\", \"variables\": {}}}",
+                            variables
+                        )))
+                        .unwrap(),
+                )
+            };
+            let header = |response: &Response<Body>, name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .map(|value| value.to_str().unwrap().to_owned())
+            };
+
+            for (accept_encoding, expected_encoding) in vec![
+                (Some("gzip, deflate, br"), Some("gzip")),
+                (Some("gzip;q=0.5, br"), Some("br")),
+                (Some("identity"), None),
+                (None, None),
+            ] {
+                let response = query(accept_encoding, Some("abc")).await.unwrap();
+                assert_eq!(StatusCode::OK, response.status());
+                assert_eq!(
+                    expected_encoding.map(str::to_owned),
+                    header(&response, "Content-Encoding"),
+                    "response for `Accept-Encoding: {:?}`",
+                    accept_encoding
+                );
+                assert_eq!(
+                    Some("accept-encoding".to_owned()),
+                    header(&response, "Vary")
+                );
+                if expected_encoding.is_some() {
+                    assert_eq!(None, header(&response, "Content-Length"));
+                }
+
+                let json = json_body(response).await;
+                assert_eq!(
+                    Some("abc".repeat(100_000).as_str()),
+                    json["data"]["name"].as_str()
+                );
+            }
+
+            // Small responses are never compressed
+            let response = query(Some("gzip"), None).await.unwrap();
+            assert_eq!(None, header(&response, "Content-Encoding"));
+            assert_eq!(None, header(&response, "Vary"));
+            let json = json_body(response).await;
+            assert_eq!(Some("Jordi"), json["data"]["name"].as_str());
+        });
+    }
 }
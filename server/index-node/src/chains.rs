@@ -0,0 +1,342 @@
+use graphql_parser::query as q;
+use std::collections::{BTreeSet, HashMap};
+
+use graph::components::ethereum::health::ProviderHealthRegistry;
+use graph::components::ethereum::provider_id;
+use graph::prelude::*;
+use graph_graphql::prelude::object;
+
+use crate::resolver::EthereumBlock;
+
+/// The networks that a node is configured for, which the `chains` field
+/// reports on.
+#[derive(Clone, Default)]
+pub struct Chains {
+    /// The chain store for each network.
+    pub stores: HashMap<String, Arc<dyn ChainStore>>,
+    /// The health of the providers for each network.
+    pub provider_health: Arc<ProviderHealthRegistry>,
+}
+
+impl Chains {
+    pub fn new(
+        stores: HashMap<String, Arc<dyn ChainStore>>,
+        provider_health: Arc<ProviderHealthRegistry>,
+    ) -> Self {
+        Chains {
+            stores,
+            provider_health,
+        }
+    }
+
+    /// The value of the `chains` field: for each network, the chain head
+    /// in the store, the most recent block any provider reported, and the
+    /// head of each provider with the heads it announced recently.
+    pub(crate) fn to_value(&self) -> Result<q::Value, QueryExecutionError> {
+        let networks: BTreeSet<_> = self
+            .stores
+            .keys()
+            .cloned()
+            .chain(self.provider_health.networks())
+            .collect();
+
+        let mut chains = Vec::new();
+        for network in networks {
+            let chain_head_block = match self.stores.get(&network) {
+                Some(store) => store
+                    .chain_head_ptr()
+                    .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))?,
+                None => None,
+            };
+
+            let providers = self.provider_health.providers(&network);
+            let latest_block = providers
+                .iter()
+                .filter_map(|health| health.latest_block.clone())
+                .max_by_key(|block| block.number);
+            let providers: Vec<_> = providers
+                .into_iter()
+                .map(|health| {
+                    let blocks_behind = match (&latest_block, &health.latest_block) {
+                        (Some(latest), Some(block)) => {
+                            Some(latest.number.saturating_sub(block.number))
+                        }
+                        _ => None,
+                    };
+                    let announcements: Vec<_> = health
+                        .announcements
+                        .into_iter()
+                        .rev()
+                        .map(|announcement| {
+                            object! {
+                                __typename: "HeadAnnouncement",
+                                block: EthereumBlock(announcement.block),
+                                receivedAt: announcement.received_at,
+                            }
+                        })
+                        .collect();
+                    object! {
+                        __typename: "ProviderInfo",
+                        providerId: provider_id(&health.provider),
+                        provider: health.provider,
+                        latestBlock: health.latest_block.map(EthereumBlock),
+                        blocksBehind: blocks_behind,
+                        errors: health.errors,
+                        announcements: announcements,
+                    }
+                })
+                .collect();
+
+            chains.push(object! {
+                __typename: "ChainInfo",
+                network: network,
+                chainHeadBlock: chain_head_block.map(EthereumBlock),
+                latestBlock: latest_block.map(EthereumBlock),
+                providers: providers,
+            });
+        }
+        Ok(q::Value::List(chains))
+    }
+
+    /// The value of the `blockData` field: the block with `hash` in the
+    /// block cache of `network`, and where it came from
+    pub(crate) fn block_data(
+        &self,
+        network: &str,
+        hash: H256,
+    ) -> Result<q::Value, QueryExecutionError> {
+        let block = match self.stores.get(network) {
+            Some(store) => store
+                .cached_block(hash)
+                .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))?,
+            None => None,
+        };
+        Ok(block
+            .map(|block| {
+                object! {
+                    __typename: "CachedBlock",
+                    network: network,
+                    block: EthereumBlock(block.ptr),
+                    parentHash: block.parent_hash.map(|hash| format!("{:#x}", hash)),
+                    provider: block.provenance.provider,
+                    fetchedAt: block.provenance.fetched_at,
+                }
+            })
+            .unwrap_or(q::Value::Null))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::ethereum::health::poll_provider_heads_once;
+    use graph::components::ethereum::{EthereumNetworks, NodeCapabilities};
+    use graph::components::store::MockChainStore;
+    use graph::mock::MockEthereumAdapter;
+    use graph::prelude::web3::types::{Block, H256, U64};
+    use std::time::Duration;
+
+    fn block_ptr(number: u64) -> EthereumBlockPointer {
+        (H256::from_low_u64_be(number), number).into()
+    }
+
+    fn adapter(hostname: &str, head: Option<u64>) -> Arc<dyn EthereumAdapter> {
+        let mut adapter = MockEthereumAdapter::new();
+        adapter
+            .expect_url_hostname()
+            .return_const(hostname.to_owned());
+        adapter
+            .expect_latest_block_header()
+            .returning(move |_| match head {
+                Some(number) => Box::new(future::ok(Block {
+                    hash: Some(H256::from_low_u64_be(number)),
+                    number: Some(U64::from(number)),
+                    ..Default::default()
+                })),
+                None => Box::new(future::err(EthereumAdapterError::Unknown(format_err!(
+                    "provider is down"
+                )))),
+            });
+        Arc::new(adapter)
+    }
+
+    fn block_value(number: u64) -> q::Value {
+        EthereumBlock(block_ptr(number)).into()
+    }
+
+    fn field<'a>(value: &'a q::Value, name: &str) -> &'a q::Value {
+        match value {
+            q::Value::Object(map) => map.get(name).expect("field exists"),
+            _ => panic!("not an object: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn chains_with_divergent_provider_heads() {
+        let capabilities = NodeCapabilities {
+            archive: false,
+            traces: false,
+        };
+        let mut networks = EthereumNetworks::new();
+        networks.insert(
+            "mainnet".to_owned(),
+            capabilities,
+            adapter("ahead.example.com", Some(105)),
+        );
+        networks.insert(
+            "mainnet".to_owned(),
+            capabilities,
+            adapter("behind.example.com", Some(100)),
+        );
+        networks.insert(
+            "mainnet".to_owned(),
+            capabilities,
+            adapter("down.example.com", None),
+        );
+        networks.insert(
+            "ropsten".to_owned(),
+            capabilities,
+            adapter("ropsten.example.com", Some(7)),
+        );
+
+        let registry = Arc::new(ProviderHealthRegistry::new());
+        let logger = Logger::root(slog::Discard, o!());
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        for _ in 0..2 {
+            runtime.block_on(poll_provider_heads_once(
+                &logger,
+                &registry,
+                &networks,
+                Duration::from_secs(5),
+            ));
+        }
+
+        let mut store = MockChainStore::new();
+        store
+            .expect_chain_head_ptr()
+            .returning(|| Ok(Some(block_ptr(103))));
+        let mut stores: HashMap<String, Arc<dyn ChainStore>> = HashMap::new();
+        stores.insert("mainnet".to_owned(), Arc::new(store));
+
+        let chains = match Chains::new(stores, registry).to_value().unwrap() {
+            q::Value::List(chains) => chains,
+            value => panic!("chains must be a list: {:?}", value),
+        };
+        assert_eq!(2, chains.len());
+
+        let mainnet = &chains[0];
+        assert_eq!(
+            &q::Value::String("mainnet".to_owned()),
+            field(mainnet, "network")
+        );
+        assert_eq!(&block_value(103), field(mainnet, "chainHeadBlock"));
+        assert_eq!(&block_value(105), field(mainnet, "latestBlock"));
+        let providers = match field(mainnet, "providers") {
+            q::Value::List(providers) => providers,
+            value => panic!("providers must be a list: {:?}", value),
+        };
+        let expected = vec![
+            ("ahead.example.com", Some(105), Some("0"), "0"),
+            ("behind.example.com", Some(100), Some("5"), "0"),
+            ("down.example.com", None, None, "2"),
+        ];
+        assert_eq!(expected.len(), providers.len());
+        for (provider, (hostname, head, behind, errors)) in providers.iter().zip(expected) {
+            assert_eq!(
+                &q::Value::String(hostname.to_owned()),
+                field(provider, "provider")
+            );
+            assert_eq!(
+                &q::Value::String(provider_id(hostname)),
+                field(provider, "providerId")
+            );
+            assert_eq!(
+                &head.map(block_value).unwrap_or(q::Value::Null),
+                field(provider, "latestBlock")
+            );
+            assert_eq!(
+                &behind
+                    .map(|behind| q::Value::String(behind.to_owned()))
+                    .unwrap_or(q::Value::Null),
+                field(provider, "blocksBehind")
+            );
+            assert_eq!(
+                &q::Value::String(errors.to_owned()),
+                field(provider, "errors")
+            );
+            // Polling twice announced each head once
+            let announcements = match field(provider, "announcements") {
+                q::Value::List(announcements) => announcements,
+                value => panic!("announcements must be a list: {:?}", value),
+            };
+            assert_eq!(head.iter().count(), announcements.len());
+            if let Some(head) = head {
+                assert_eq!(&block_value(head), field(&announcements[0], "block"));
+            }
+        }
+
+        // There is no chain store for ropsten, only a provider
+        let ropsten = &chains[1];
+        assert_eq!(
+            &q::Value::String("ropsten".to_owned()),
+            field(ropsten, "network")
+        );
+        assert_eq!(&q::Value::Null, field(ropsten, "chainHeadBlock"));
+        assert_eq!(&block_value(7), field(ropsten, "latestBlock"));
+    }
+
+    #[test]
+    fn block_data_reports_provenance() {
+        let mut store = MockChainStore::new();
+        store.expect_cached_block().returning(|hash| {
+            Ok(match hash.to_low_u64_be() {
+                3 => Some(CachedBlock {
+                    ptr: block_ptr(3),
+                    parent_hash: Some(block_ptr(2).hash),
+                    provenance: Provenance::new(Some("0123456789abcdef".to_owned()), Some(1600)),
+                }),
+                4 => Some(CachedBlock {
+                    ptr: block_ptr(4),
+                    parent_hash: Some(block_ptr(3).hash),
+                    provenance: Provenance::new(None, None),
+                }),
+                _ => None,
+            })
+        });
+        let mut stores: HashMap<String, Arc<dyn ChainStore>> = HashMap::new();
+        stores.insert("mainnet".to_owned(), Arc::new(store));
+        let chains = Chains::new(stores, Arc::new(ProviderHealthRegistry::new()));
+
+        let block = chains.block_data("mainnet", block_ptr(3).hash).unwrap();
+        assert_eq!(&block_value(3), field(&block, "block"));
+        assert_eq!(
+            &q::Value::String(format!("{:#x}", block_ptr(2).hash)),
+            field(&block, "parentHash")
+        );
+        assert_eq!(
+            &q::Value::String("0123456789abcdef".to_owned()),
+            field(&block, "provider")
+        );
+        assert_eq!(
+            &q::Value::String("1600".to_owned()),
+            field(&block, "fetchedAt")
+        );
+
+        // Blocks cached before providers were recorded
+        let block = chains.block_data("mainnet", block_ptr(4).hash).unwrap();
+        assert_eq!(
+            &q::Value::String("unknown".to_owned()),
+            field(&block, "provider")
+        );
+        assert_eq!(&q::Value::Null, field(&block, "fetchedAt"));
+
+        assert_eq!(
+            q::Value::Null,
+            chains.block_data("mainnet", block_ptr(5).hash).unwrap()
+        );
+        assert_eq!(
+            q::Value::Null,
+            chains.block_data("ropsten", block_ptr(3).hash).unwrap()
+        );
+    }
+}
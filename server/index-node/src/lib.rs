@@ -1,9 +1,11 @@
+mod chains;
 mod request;
 mod resolver;
 mod schema;
 mod server;
 mod service;
 
+pub use self::chains::Chains;
 pub use self::request::IndexNodeRequest;
 pub use self::server::IndexNodeServer;
 pub use self::service::{IndexNodeService, IndexNodeServiceResponse};
@@ -1,13 +1,22 @@
 use graphql_parser::{query as q, schema as s};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use graph::components::ethereum::stream_status::{
+    BlockStreamStatus, BlockStreamStatusRegistry, TimedBlockStreamStatus,
+};
+use graph::components::subgraph::handler_stats::{HandlerStats, HandlerStatsRegistry};
 use graph::data::graphql::{ObjectOrInterface, TryFromValue, ValueList, ValueMap};
-use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth, SUBGRAPHS_ID};
+use graph::data::subgraph::schema::{
+    AbiSummary, DataSourceSummary, SubgraphError, SubgraphHealth, SUBGRAPHS_ID,
+};
 use graph::prelude::*;
 use graph_graphql::prelude::{object, ExecutionContext, IntoValue, Resolver};
 use std::convert::TryInto;
 use web3::types::{Address, H256};
 
+use crate::chains::Chains;
+
 static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     fragment deploymentStatus on SubgraphDeploymentDetail {
         id
@@ -20,6 +29,7 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
             blockHash
             handler
             deterministic
+            count
         }
         nonFatalErrors(first: 1000, orderBy: blockNumber) {
             subgraphId
@@ -28,6 +38,7 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
             blockHash
             handler
             deterministic
+            count
         }
         ethereumHeadBlockNumber
         ethereumHeadBlockHash
@@ -43,11 +54,37 @@ static DEPLOYMENT_STATUS_FRAGMENT: &str = r#"
     }
   "#;
 
+/// The fields of data sources in the subgraph of subgraphs that a
+/// `DataSourceSummary` is built from. The same fields exist on both
+/// `EthereumContractDataSource` and `DynamicEthereumContractDataSource`.
+static DATA_SOURCE_SUMMARY_FIELDS: &str = r#"
+    kind
+    name
+    network
+    summary
+    source { address abi startBlock }
+    mapping {
+        abis(first: 1000) { name file }
+        eventHandlers(first: 1000) { event }
+        callHandlers(first: 1000) { function }
+    }
+  "#;
+
+/// The largest number of dynamic data sources that can be requested with
+/// one `subgraphDataSources` query.
+const MAX_DYNAMIC_DATA_SOURCES: i64 = 1000;
+
+/// The most entries `cachedEthereumCalls` returns
+const MAX_CACHED_CALLS: usize = 1000;
+
 /// Resolver for the index node GraphQL API.
 pub struct IndexNodeResolver<R, S> {
     logger: Logger,
     graphql_runner: Arc<R>,
     store: Arc<S>,
+    chains: Arc<Chains>,
+    block_streams: Arc<BlockStreamStatusRegistry>,
+    handler_stats: Arc<HandlerStatsRegistry>,
 }
 
 /// The ID of a subgraph deployment assignment.
@@ -70,7 +107,7 @@ impl TryFromValue for DeploymentAssignment {
 
 /// Light wrapper around `EthereumBlockPointer` that is compatible with GraphQL values.
 #[derive(Debug)]
-struct EthereumBlock(EthereumBlockPointer);
+pub(crate) struct EthereumBlock(pub(crate) EthereumBlockPointer);
 
 impl From<EthereumBlock> for q::Value {
     fn from(block: EthereumBlock) -> Self {
@@ -155,6 +192,14 @@ struct IndexingStatus {
 
     /// ID of the Graph Node that the subgraph is indexed by.
     node: String,
+
+    /// What the block stream of the subgraph is doing, if it runs on this
+    /// node.
+    block_stream: Option<TimedBlockStreamStatus>,
+
+    /// The note an operator left when they flagged the subgraph as
+    /// unhealthy, if they did.
+    flag: Option<DeploymentFlag>,
 }
 
 impl IndexingStatusWithoutNode {
@@ -168,6 +213,8 @@ impl IndexingStatusWithoutNode {
             non_fatal_errors: self.non_fatal_errors,
             chains: self.chains,
             node,
+            block_stream: None,
+            flag: None,
         }
     }
 
@@ -227,6 +274,8 @@ impl From<IndexingStatus> for q::Value {
             node,
             non_fatal_errors,
             synced,
+            block_stream,
+            flag,
         } = status;
 
         fn subgraph_error_to_value(subgraph_error: SubgraphError) -> q::Value {
@@ -236,6 +285,7 @@ impl From<IndexingStatus> for q::Value {
                 block_ptr,
                 handler,
                 deterministic,
+                count,
             } = subgraph_error;
 
             object! {
@@ -249,6 +299,7 @@ impl From<IndexingStatus> for q::Value {
                     hash: block_ptr.map(|x| q::Value::from(Value::Bytes(x.hash.as_ref().into()))),
                 },
                 deterministic: deterministic,
+                count: count as i32,
             }
         }
 
@@ -267,10 +318,208 @@ impl From<IndexingStatus> for q::Value {
             nonFatalErrors: non_fatal_errors,
             chains: chains.into_iter().map(q::Value::from).collect::<Vec<_>>(),
             node: node,
+            blockStream: block_stream.map(block_stream_status_value),
+            flagged: flag.map(|flag| object! {
+                __typename: "DeploymentFlag",
+                note: flag.note,
+                flaggedAt: flag.flagged_at,
+            }),
+        }
+    }
+}
+
+fn block_stream_status_value(status: TimedBlockStreamStatus) -> q::Value {
+    fn unix_secs(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+
+    let TimedBlockStreamStatus { status, since } = status;
+    let state = q::Value::Enum(status.state().to_owned());
+    let (mut from_block, mut to_block, mut block, mut error, mut retry_at) =
+        (None, None, None, None, None);
+    match status {
+        BlockStreamStatus::Starting | BlockStreamStatus::WaitingForChainHead => {}
+        BlockStreamStatus::ScanningBlocks { from, to } => {
+            from_block = Some(from);
+            to_block = Some(to);
+        }
+        BlockStreamStatus::FetchingBlock { number }
+        | BlockStreamStatus::ProcessingBlock { number }
+        | BlockStreamStatus::RevertingBlock { number } => block = Some(number),
+        BlockStreamStatus::BackingOff { error: e, until } => {
+            error = Some(e);
+            retry_at = Some(unix_secs(until));
+        }
+    }
+
+    object! {
+        __typename: "BlockStreamStatus",
+        state: state,
+        since: unix_secs(since),
+        fromBlock: from_block,
+        toBlock: to_block,
+        block: block,
+        error: error,
+        retryAt: retry_at,
+    }
+}
+
+fn handler_stats_value(stats: HandlerStats) -> q::Value {
+    fn millis(time: std::time::Duration) -> f64 {
+        time.as_secs_f64() * 1000.0
+    }
+
+    object! {
+        __typename: "HandlerStats",
+        handler: stats.handler,
+        invocations: stats.invocations,
+        totalTimeMs: millis(stats.total_time),
+        p95TimeMs: millis(stats.p95_time),
+        ethCalls: stats.eth_calls,
+        entityOps: stats.entity_ops,
+    }
+}
+
+/// A data source of a deployment as exposed by `subgraphDataSources`.
+#[derive(Debug)]
+struct DataSourceInfo {
+    name: String,
+    summary: DataSourceSummary,
+    /// The block in which the data source was created, for dynamic data
+    /// sources only.
+    creation_block: Option<EthereumBlock>,
+}
+
+impl TryFromValue for DataSourceInfo {
+    fn try_from_value(value: &q::Value) -> Result<Self, Error> {
+        let summary = match value.get_optional::<String>("summary")? {
+            Some(summary) => serde_json::from_str(&summary)?,
+            // Deployments created before summaries were stored don't have
+            // one; build it from the data source the same way it is built
+            // during deployment
+            None => {
+                let source = value.get_required::<q::Value>("source")?;
+                let mapping = value.get_required::<q::Value>("mapping")?;
+                let events = mapping
+                    .get_required::<q::Value>("eventHandlers")?
+                    .get_values::<q::Value>()?
+                    .iter()
+                    .map(|handler| handler.get_required::<String>("event"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let calls = mapping
+                    .get_required::<q::Value>("callHandlers")?
+                    .get_values::<q::Value>()?
+                    .iter()
+                    .map(|handler| handler.get_required::<String>("function"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let abis = mapping
+                    .get_required::<q::Value>("abis")?
+                    .get_values::<q::Value>()?
+                    .iter()
+                    .map(|abi| {
+                        Ok(AbiSummary {
+                            name: abi.get_required("name")?,
+                            file: abi.get_required("file")?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                DataSourceSummary {
+                    abi: Some(source.get_required("abi")?),
+                    abis,
+                    ..DataSourceSummary::new(
+                        value.get_required("kind")?,
+                        value.get_optional("network")?,
+                        source.get_optional("address")?,
+                        source
+                            .get_optional::<BigInt>("startBlock")?
+                            .map(|n| n.to_u64())
+                            .unwrap_or(0),
+                        events.iter().map(String::as_str),
+                        calls.iter().map(String::as_str),
+                    )
+                }
+            }
+        };
+
+        Ok(Self {
+            name: value.get_required("name")?,
+            summary,
+            creation_block: IndexingStatusWithoutNode::block_from_value(value, "ethereumBlock")?,
+        })
+    }
+}
+
+impl From<DataSourceInfo> for q::Value {
+    fn from(info: DataSourceInfo) -> Self {
+        let DataSourceInfo {
+            name,
+            summary,
+            creation_block,
+        } = info;
+
+        object! {
+            __typename: "DataSourceSummary",
+            kind: summary.kind,
+            name: name,
+            network: summary.network,
+            address: summary
+                .address
+                .map(|address| q::Value::from(Value::Bytes(address.as_ref().into()))),
+            startBlock: summary.start_block,
+            abi: summary.abi,
+            abis: summary
+                .abis
+                .into_iter()
+                .map(|abi| object! {
+                    __typename: "DataSourceAbi",
+                    name: abi.name,
+                    file: abi.file,
+                })
+                .collect::<Vec<_>>(),
+            eventSignatures: summary.event_signatures,
+            callSignatures: summary.call_signatures,
+            creationBlock: creation_block,
         }
     }
 }
 
+/// Turn the result of the `dataSources` query against the subgraph of
+/// subgraphs into the response of `subgraphDataSources`
+fn data_sources_from_result(
+    subgraph_id: String,
+    data: q::Value,
+) -> Result<q::Value, QueryExecutionError> {
+    let deployment = match data
+        .get_optional::<q::Value>("subgraphDeployment")
+        .expect("invalid subgraph deployment")
+    {
+        Some(deployment) => deployment,
+        None => return Ok(q::Value::Null),
+    };
+
+    let data_sources = deployment
+        .get_required::<q::Value>("manifest")
+        .and_then(|manifest| manifest.get_required::<q::Value>("dataSources"))
+        .and_then(|data_sources| data_sources.get_values::<DataSourceInfo>())
+        .map_err(|e| QueryExecutionError::Panic(e.to_string()))?;
+    let dynamic_data_sources = deployment
+        .get_required::<q::Value>("dynamicDataSources")
+        .and_then(|data_sources| data_sources.get_values::<DataSourceInfo>())
+        .map_err(|e| QueryExecutionError::Panic(e.to_string()))?;
+
+    Ok(object! {
+        __typename: "SubgraphDataSources",
+        subgraph: subgraph_id,
+        dataSources: data_sources.into_iter().map(q::Value::from).collect::<Vec<_>>(),
+        dynamicDataSources: dynamic_data_sources
+            .into_iter()
+            .map(q::Value::from)
+            .collect::<Vec<_>>(),
+    })
+}
+
 struct IndexingStatuses(Vec<IndexingStatus>);
 
 impl From<q::Value> for IndexingStatuses {
@@ -301,6 +550,28 @@ impl From<q::Value> for IndexingStatuses {
     }
 }
 
+impl IndexingStatuses {
+    /// Add the status of the block streams that run on this node
+    fn with_block_streams(mut self, block_streams: &BlockStreamStatusRegistry) -> Self {
+        for status in self.0.iter_mut() {
+            status.block_stream = SubgraphDeploymentId::new(status.subgraph.clone())
+                .ok()
+                .and_then(|id| block_streams.status(&id));
+        }
+        self
+    }
+
+    /// Add the flags that operators put on deployments
+    fn with_flags(mut self, flags: &BTreeMap<SubgraphDeploymentId, DeploymentFlag>) -> Self {
+        for status in self.0.iter_mut() {
+            status.flag = SubgraphDeploymentId::new(status.subgraph.clone())
+                .ok()
+                .and_then(|id| flags.get(&id).cloned());
+        }
+        self
+    }
+}
+
 impl From<IndexingStatuses> for q::Value {
     fn from(statuses: IndexingStatuses) -> Self {
         q::Value::List(statuses.0.into_iter().map(q::Value::from).collect())
@@ -310,17 +581,159 @@ impl From<IndexingStatuses> for q::Value {
 impl<R, S> IndexNodeResolver<R, S>
 where
     R: GraphQlRunner,
-    S: Store + SubgraphDeploymentStore,
+    S: EntityStore + SubgraphDeploymentStore + EthereumCallCache,
 {
-    pub fn new(logger: &Logger, graphql_runner: Arc<R>, store: Arc<S>) -> Self {
+    pub fn new(
+        logger: &Logger,
+        graphql_runner: Arc<R>,
+        store: Arc<S>,
+        chains: Arc<Chains>,
+        block_streams: Arc<BlockStreamStatusRegistry>,
+        handler_stats: Arc<HandlerStatsRegistry>,
+    ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
         Self {
             logger,
             graphql_runner,
             store,
+            chains,
+            block_streams,
+            handler_stats,
         }
     }
 
+    fn resolve_handler_stats(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let subgraph = arguments.get_required::<String>("subgraph").unwrap();
+
+        Ok(SubgraphDeploymentId::new(subgraph)
+            .ok()
+            .and_then(|id| self.handler_stats.stats(&id))
+            .map(|stats| q::Value::List(stats.into_iter().map(handler_stats_value).collect()))
+            .unwrap_or(q::Value::Null))
+    }
+
+    fn resolve_block_data(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the arguments are non-nullable and have been validated.
+        let network = arguments.get_required::<String>("network").unwrap();
+        let block_hash = arguments
+            .get_required::<H256>("blockHash")
+            .expect("Valid blockHash required");
+
+        self.chains.block_data(&network, block_hash)
+    }
+
+    fn resolve_cached_ethereum_calls(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let block_number = arguments.get_required::<u64>("blockNumber").unwrap();
+        let contract_address = arguments
+            .get_optional::<Address>("contractAddress")
+            .expect("Invalid contractAddress");
+
+        let calls = self
+            .store
+            .cached_calls(block_number, contract_address, MAX_CACHED_CALLS)
+            .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))?;
+        Ok(q::Value::List(
+            calls
+                .into_iter()
+                .map(|call| {
+                    object! {
+                        __typename: "CachedEthereumCall",
+                        contractAddress: q::Value::from(Value::Bytes(
+                            call.contract_address.as_ref().into(),
+                        )),
+                        blockNumber: call.block_number,
+                        returnValue: q::Value::from(Value::Bytes(call.return_value.as_slice().into())),
+                        revertReason: call.revert_reason,
+                        provider: call.provenance.provider,
+                        fetchedAt: call.provenance.fetched_at,
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn flagged_deployments(
+        &self,
+    ) -> Result<BTreeMap<SubgraphDeploymentId, DeploymentFlag>, QueryExecutionError> {
+        self.store
+            .flagged_deployments()
+            .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))
+    }
+
+    fn resolve_health_issues(&self) -> Result<q::Value, QueryExecutionError> {
+        let issues = self
+            .store
+            .health_issues()
+            .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))?;
+        Ok(q::Value::List(
+            issues
+                .into_iter()
+                .map(|issue| {
+                    object! {
+                        __typename: "HealthIssue",
+                        kind: q::Value::Enum(issue.kind.as_str().to_owned()),
+                        deployment: issue.deployment,
+                        subgraphName: issue.subgraph_name,
+                        message: issue.message,
+                        remediation: q::Value::Enum(issue.remediation.as_str().to_owned()),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    fn resolve_graft_lineage(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let subgraph_id = arguments.get_required::<String>("subgraphId").unwrap();
+        let id = match SubgraphDeploymentId::new(subgraph_id.clone()) {
+            Ok(id) => id,
+            Err(_) => return Ok(q::Value::Null),
+        };
+
+        let lineage = match self
+            .store
+            .graft_lineage(&id)
+            .map_err(|e| QueryExecutionError::from(StoreError::Unknown(e)))?
+        {
+            Some(lineage) => lineage,
+            None => return Ok(q::Value::Null),
+        };
+        Ok(object! {
+            __typename: "GraftLineage",
+            subgraph: subgraph_id,
+            bases: lineage
+                .bases
+                .into_iter()
+                .map(|base| {
+                    object! {
+                        __typename: "GraftBase",
+                        subgraph: base.deployment.to_string(),
+                        block: EthereumBlock(base.block),
+                    }
+                })
+                .collect::<Vec<_>>(),
+            dependents: lineage
+                .dependents
+                .into_iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>(),
+        })
+    }
+
     fn resolve_indexing_statuses(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -408,7 +821,10 @@ where
             Ok(Some(data)) => data,
         };
 
-        Ok(IndexingStatuses::from(data).into())
+        Ok(IndexingStatuses::from(data)
+            .with_block_streams(&self.block_streams)
+            .with_flags(&self.flagged_deployments()?)
+            .into())
     }
 
     fn resolve_indexing_statuses_for_subgraph_name(
@@ -534,7 +950,10 @@ where
                     .expect("missing deployment assignments"),
         };
 
-        Ok(IndexingStatuses::from(transformed_data).into())
+        Ok(IndexingStatuses::from(transformed_data)
+            .with_block_streams(&self.block_streams)
+            .with_flags(&self.flagged_deployments()?)
+            .into())
     }
 
     fn resolve_proof_of_indexing(
@@ -575,6 +994,117 @@ where
         Ok(poi)
     }
 
+    fn resolve_subgraph_data_sources(
+        &self,
+        arguments: &HashMap<&q::Name, q::Value>,
+    ) -> Result<q::Value, QueryExecutionError> {
+        // We can safely unwrap because the argument is non-nullable and has been validated.
+        let subgraph_id = arguments.get_required::<String>("subgraphId").unwrap();
+
+        let first = match arguments.get(&String::from("first")) {
+            Some(q::Value::Int(n)) => {
+                let n = n.as_i64().expect("first is Int");
+                if n > 0 && n <= MAX_DYNAMIC_DATA_SOURCES {
+                    n
+                } else {
+                    return Err(QueryExecutionError::RangeArgumentsError(
+                        "first",
+                        MAX_DYNAMIC_DATA_SOURCES as u32,
+                        n,
+                    ));
+                }
+            }
+            Some(q::Value::Null) | None => 100,
+            _ => unreachable!("first is an Int with a default value"),
+        };
+        let skip = match arguments.get(&String::from("skip")) {
+            Some(q::Value::Int(n)) => {
+                let n = n.as_i64().expect("skip is Int");
+                if n >= 0 && n <= std::i32::MAX as i64 {
+                    n
+                } else {
+                    return Err(QueryExecutionError::RangeArgumentsError(
+                        "skip",
+                        std::i32::MAX as u32,
+                        n,
+                    ));
+                }
+            }
+            Some(q::Value::Null) | None => 0,
+            _ => unreachable!("skip is an Int with a default value"),
+        };
+
+        let query = Query::new(
+            self.store
+                .api_schema(&SUBGRAPHS_ID)
+                .map_err(|e| QueryExecutionError::StoreError(e.into()))?,
+            q::parse_query(&format!(
+                r#"
+                query dataSources($id: ID!, $first: Int!, $skip: Int!) {{
+                  subgraphDeployment(id: $id) {{
+                    id
+                    manifest {{
+                      dataSources(first: 1000) {{
+                        {fields}
+                      }}
+                    }}
+                    dynamicDataSources(
+                      orderBy: ethereumBlockNumber,
+                      first: $first,
+                      skip: $skip
+                    ) {{
+                      {fields}
+                      ethereumBlockHash
+                      ethereumBlockNumber
+                    }}
+                  }}
+                }}
+                "#,
+                fields = DATA_SOURCE_SUMMARY_FIELDS
+            ))
+            .unwrap(),
+            Some(QueryVariables::new(HashMap::from_iter(
+                vec![
+                    ("id".into(), q::Value::String(subgraph_id.clone())),
+                    ("first".into(), q::Value::Int((first as i32).into())),
+                    ("skip".into(), q::Value::Int((skip as i32).into())),
+                ]
+                .into_iter(),
+            ))),
+            None,
+        );
+
+        // Execute the query. We are in a blocking context so we may just block.
+        let result = graph::block_on(self.graphql_runner.cheap_clone().run_query_with_complexity(
+            query,
+            DeploymentState::meta(),
+            None,
+            None,
+            Some(std::u32::MAX),
+            Some(std::u32::MAX),
+            true,
+        ));
+
+        // Metadata queries are not cached.
+        let result = Arc::try_unwrap(result).unwrap();
+
+        let data = match result.to_result() {
+            Err(errors) => {
+                error!(
+                    self.logger,
+                    "Failed to query subgraph data sources";
+                    "subgraph" => &subgraph_id,
+                    "errors" => format!("{:?}", errors)
+                );
+                return Ok(q::Value::Null);
+            }
+            Ok(None) => return Ok(q::Value::Null),
+            Ok(Some(data)) => data,
+        };
+
+        data_sources_from_result(subgraph_id, data)
+    }
+
     fn resolve_indexing_status_for_version(
         &self,
         arguments: &HashMap<&q::Name, q::Value>,
@@ -703,6 +1233,8 @@ where
         );
 
         Ok(IndexingStatuses::from(transformed_data)
+            .with_block_streams(&self.block_streams)
+            .with_flags(&self.flagged_deployments()?)
             .0
             .into_iter()
             .next()
@@ -714,13 +1246,16 @@ where
 impl<R, S> Clone for IndexNodeResolver<R, S>
 where
     R: GraphQlRunner,
-    S: Store + SubgraphDeploymentStore,
+    S: EntityStore + SubgraphDeploymentStore + EthereumCallCache,
 {
     fn clone(&self) -> Self {
         Self {
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            chains: self.chains.clone(),
+            block_streams: self.block_streams.clone(),
+            handler_stats: self.handler_stats.clone(),
         }
     }
 }
@@ -728,7 +1263,7 @@ where
 impl<R, S> Resolver for IndexNodeResolver<R, S>
 where
     R: GraphQlRunner,
-    S: Store + SubgraphDeploymentStore,
+    S: EntityStore + SubgraphDeploymentStore + EthereumCallCache,
 {
     const CACHEABLE: bool = false;
 
@@ -783,6 +1318,20 @@ where
                 self.resolve_indexing_statuses_for_subgraph_name(arguments)
             }
 
+            // The top-level `chains` field
+            (None, "ChainInfo", "chains") => self.chains.to_value(),
+
+            // The top-level `handlerStats` field
+            (None, "HandlerStats", "handlerStats") => self.resolve_handler_stats(arguments),
+
+            // The top-level `cachedEthereumCalls` field
+            (None, "CachedEthereumCall", "cachedEthereumCalls") => {
+                self.resolve_cached_ethereum_calls(arguments)
+            }
+
+            // The top-level `healthIssues` field
+            (None, "HealthIssue", "healthIssues") => self.resolve_health_issues(),
+
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
@@ -807,8 +1356,171 @@ where
                 self.resolve_indexing_status_for_version(arguments, false)
             }
 
+            // The top-level `subgraphDataSources` field
+            (None, "subgraphDataSources") => self.resolve_subgraph_data_sources(arguments),
+
+            // The top-level `blockData` field
+            (None, "blockData") => self.resolve_block_data(arguments),
+
+            // The top-level `graftLineage` field
+            (None, "graftLineage") => self.resolve_graft_lineage(arguments),
+
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(q::Value::Null)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field<'a>(value: &'a q::Value, name: &str) -> &'a q::Value {
+        match value {
+            q::Value::Object(map) => map.get(name).expect("field exists"),
+            _ => panic!("not an object: {:?}", value),
+        }
+    }
+
+    fn string(s: &str) -> q::Value {
+        q::Value::String(s.to_owned())
+    }
+
+    fn strings(value: &q::Value) -> Vec<&str> {
+        match value {
+            q::Value::List(values) => values
+                .iter()
+                .map(|value| match value {
+                    q::Value::String(s) => s.as_str(),
+                    _ => panic!("not a string: {:?}", value),
+                })
+                .collect(),
+            _ => panic!("not a list: {:?}", value),
+        }
+    }
+
+    fn list(value: &q::Value) -> &Vec<q::Value> {
+        match value {
+            q::Value::List(values) => values,
+            _ => panic!("not a list: {:?}", value),
+        }
+    }
+
+    /// A data source as the subgraph of subgraphs returns it for a
+    /// deployment that was created before summaries were stored
+    fn legacy_data_source() -> q::Value {
+        object! {
+            kind: "ethereum/contract",
+            name: "Token",
+            network: "mainnet",
+            summary: q::Value::Null,
+            source: object! {
+                address: "0x22843e74c59580b3eaf6c233fa67d8b7c561a835",
+                abi: "ERC20",
+                startBlock: "100",
+            },
+            mapping: object! {
+                abis: vec![
+                    object! { name: "ERC20", file: "/ipfs/QmErc20" },
+                    object! { name: "Factory", file: "/ipfs/QmFactory" },
+                ],
+                eventHandlers: vec![
+                    object! { event: "Transfer(address indexed from, address indexed to, uint256)" },
+                    object! { event: "Approval((address owner, uint256 limit) indexed grant)" },
+                ],
+                callHandlers: vec![object! { function: "approve(address, uint256)" }],
+            },
+        }
+    }
+
+    fn dynamic_data_source() -> q::Value {
+        let summary = DataSourceSummary {
+            abi: Some("Pair".to_owned()),
+            abis: vec![AbiSummary {
+                name: "Pair".to_owned(),
+                file: "/ipfs/QmPair".to_owned(),
+            }],
+            ..DataSourceSummary::new(
+                "ethereum/contract".to_owned(),
+                Some("mainnet".to_owned()),
+                Some(Address::from_low_u64_be(7)),
+                200,
+                vec!["Sync(uint112,uint112)"],
+                vec![],
+            )
+        };
+        object! {
+            kind: "ethereum/contract",
+            name: "Pair",
+            network: "mainnet",
+            summary: serde_json::to_string(&summary).unwrap(),
+            ethereumBlockHash: format!("{:x}", H256::from_low_u64_be(200)),
+            ethereumBlockNumber: "200",
+        }
+    }
+
+    #[test]
+    fn data_sources() {
+        let data = object! {
+            subgraphDeployment: object! {
+                id: "QmDeployment",
+                manifest: object! { dataSources: vec![legacy_data_source()] },
+                dynamicDataSources: vec![dynamic_data_source()],
+            },
+        };
+
+        let result = data_sources_from_result("QmDeployment".to_owned(), data).unwrap();
+        assert_eq!(&string("QmDeployment"), field(&result, "subgraph"));
+
+        let data_sources = list(field(&result, "dataSources"));
+        assert_eq!(1, data_sources.len());
+        let token = &data_sources[0];
+        assert_eq!(&string("Token"), field(token, "name"));
+        assert_eq!(&string("100"), field(token, "startBlock"));
+        assert_eq!(&string("ERC20"), field(token, "abi"));
+        assert_eq!(
+            vec!["ERC20", "Factory"],
+            list(field(token, "abis"))
+                .iter()
+                .map(|abi| match field(abi, "name") {
+                    q::Value::String(name) => name.as_str(),
+                    _ => panic!("ABI names are strings"),
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![
+                "Approval((address,uint256))",
+                "Transfer(address,address,uint256)"
+            ],
+            strings(field(token, "eventSignatures"))
+        );
+        assert_eq!(
+            vec!["approve(address,uint256)"],
+            strings(field(token, "callSignatures"))
+        );
+        assert_eq!(&q::Value::Null, field(token, "creationBlock"));
+
+        let dynamic = list(field(&result, "dynamicDataSources"));
+        assert_eq!(1, dynamic.len());
+        let pair = &dynamic[0];
+        assert_eq!(&string("Pair"), field(pair, "abi"));
+        assert_eq!(
+            vec!["Sync(uint112,uint112)"],
+            strings(field(pair, "eventSignatures"))
+        );
+        assert_eq!(
+            &string("200"),
+            field(field(pair, "creationBlock"), "number")
+        );
+    }
+
+    #[test]
+    fn data_sources_of_unknown_deployment() {
+        let data = object! { subgraphDeployment: q::Value::Null };
+        assert_eq!(
+            q::Value::Null,
+            data_sources_from_result("QmUnknown".to_owned(), data).unwrap()
+        );
+    }
+}
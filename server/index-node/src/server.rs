@@ -3,8 +3,13 @@ use hyper::service::make_service_fn;
 use hyper::Server;
 use std::net::{Ipv4Addr, SocketAddrV4};
 
+use graph::components::ethereum::stream_status::BlockStreamStatusRegistry;
+use graph::components::server::auth::ServerSecurity;
+use graph::components::server::tls;
+use graph::components::subgraph::handler_stats::HandlerStatsRegistry;
 use graph::prelude::{IndexNodeServer as IndexNodeServerTrait, *};
 
+use crate::chains::Chains;
 use crate::service::IndexNodeService;
 
 /// Errors that may occur when starting the server.
@@ -12,6 +17,10 @@ use crate::service::IndexNodeService;
 pub enum IndexNodeServeError {
     #[fail(display = "Bind error: {}", _0)]
     BindError(hyper::Error),
+    #[fail(display = "Failed to listen on port: {}", _0)]
+    ListenError(std::io::Error),
+    #[fail(display = "Invalid TLS configuration: {}", _0)]
+    TlsError(String),
 }
 
 impl From<hyper::Error> for IndexNodeServeError {
@@ -20,12 +29,22 @@ impl From<hyper::Error> for IndexNodeServeError {
     }
 }
 
+impl From<std::io::Error> for IndexNodeServeError {
+    fn from(err: std::io::Error) -> Self {
+        IndexNodeServeError::ListenError(err)
+    }
+}
+
 /// A GraphQL server based on Hyper.
 pub struct IndexNodeServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    chains: Arc<Chains>,
+    block_streams: Arc<BlockStreamStatusRegistry>,
+    handler_stats: Arc<HandlerStatsRegistry>,
     node_id: NodeId,
+    security: ServerSecurity,
 }
 
 impl<Q, S> IndexNodeServer<Q, S> {
@@ -34,7 +53,11 @@ impl<Q, S> IndexNodeServer<Q, S> {
         logger_factory: &LoggerFactory,
         graphql_runner: Arc<Q>,
         store: Arc<S>,
+        chains: Chains,
+        block_streams: Arc<BlockStreamStatusRegistry>,
+        handler_stats: Arc<HandlerStatsRegistry>,
         node_id: NodeId,
+        security: ServerSecurity,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
@@ -49,7 +72,11 @@ impl<Q, S> IndexNodeServer<Q, S> {
             logger,
             graphql_runner,
             store,
+            chains: Arc::new(chains),
+            block_streams,
+            handler_stats,
             node_id,
+            security,
         }
     }
 }
@@ -57,7 +84,7 @@ impl<Q, S> IndexNodeServer<Q, S> {
 impl<Q, S> IndexNodeServerTrait for IndexNodeServer<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + EntityStore + EthereumCallCache,
 {
     type ServeError = IndexNodeServeError;
 
@@ -69,28 +96,48 @@ where
 
         info!(
             logger,
-            "Starting index node server at: http://localhost:{}", port
+            "Starting index node server at: {}://localhost:{}",
+            self.security.scheme(),
+            port;
+            "auth" => self.security.auth.is_enabled(),
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
+        let acceptor = match &self.security.tls {
+            Some(tls) => Some(
+                tls.acceptor()
+                    .map_err(|e| IndexNodeServeError::TlsError(e.to_string()))?,
+            ),
+            None => None,
+        };
+        let incoming = tls::incoming(self.logger.clone(), addr.into(), acceptor)?;
 
         // On every incoming request, launch a new GraphQL service that writes
         // incoming queries to the query sink.
         let logger_for_service = self.logger.clone();
         let graphql_runner = self.graphql_runner.clone();
         let store = self.store.clone();
+        let chains = self.chains.clone();
+        let block_streams = self.block_streams.clone();
+        let handler_stats = self.handler_stats.clone();
         let node_id = self.node_id.clone();
-        let new_service = make_service_fn(move |_| {
+        let security = self.security.clone();
+        let new_service = make_service_fn(move |conn: &tls::ServerStream| {
             futures03::future::ok::<_, Error>(IndexNodeService::new(
                 logger_for_service.clone(),
                 graphql_runner.clone(),
                 store.clone(),
+                chains.clone(),
+                block_streams.clone(),
+                handler_stats.clone(),
                 node_id.clone(),
+                security.clone(),
+                conn.peer_addr(),
             ))
         });
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
+        let task = Server::builder(hyper::server::accept::from_stream(incoming))
             .serve(new_service)
             .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
 
@@ -1,13 +1,18 @@
 use http::header;
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use std::net::SocketAddr;
 use std::task::Context;
 use std::task::Poll;
 
+use graph::components::ethereum::stream_status::BlockStreamStatusRegistry;
+use graph::components::server::auth::ServerSecurity;
 use graph::components::server::query::GraphQLServerError;
+use graph::components::subgraph::handler_stats::HandlerStatsRegistry;
 use graph::prelude::*;
 use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
 
+use crate::chains::Chains;
 use crate::request::IndexNodeRequest;
 use crate::resolver::IndexNodeResolver;
 use crate::schema::SCHEMA;
@@ -16,12 +21,16 @@ use crate::schema::SCHEMA;
 pub type IndexNodeServiceResponse = DynTryFuture<'static, Response<Body>, GraphQLServerError>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct IndexNodeService<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    chains: Arc<Chains>,
+    block_streams: Arc<BlockStreamStatusRegistry>,
+    handler_stats: Arc<HandlerStatsRegistry>,
     node_id: NodeId,
+    security: ServerSecurity,
+    source: SocketAddr,
 }
 
 impl<Q, S> Clone for IndexNodeService<Q, S> {
@@ -30,7 +39,12 @@ impl<Q, S> Clone for IndexNodeService<Q, S> {
             logger: self.logger.clone(),
             graphql_runner: self.graphql_runner.clone(),
             store: self.store.clone(),
+            chains: self.chains.clone(),
+            block_streams: self.block_streams.clone(),
+            handler_stats: self.handler_stats.clone(),
             node_id: self.node_id.clone(),
+            security: self.security.clone(),
+            source: self.source,
         }
     }
 }
@@ -40,15 +54,30 @@ impl<Q, S> CheapClone for IndexNodeService<Q, S> {}
 impl<Q, S> IndexNodeService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + EntityStore + EthereumCallCache,
 {
     /// Creates a new GraphQL service.
-    pub fn new(logger: Logger, graphql_runner: Arc<Q>, store: Arc<S>, node_id: NodeId) -> Self {
+    pub fn new(
+        logger: Logger,
+        graphql_runner: Arc<Q>,
+        store: Arc<S>,
+        chains: Arc<Chains>,
+        block_streams: Arc<BlockStreamStatusRegistry>,
+        handler_stats: Arc<HandlerStatsRegistry>,
+        node_id: NodeId,
+        security: ServerSecurity,
+        source: SocketAddr,
+    ) -> Self {
         IndexNodeService {
             logger,
             graphql_runner,
             store,
+            chains,
+            block_streams,
+            handler_stats,
             node_id,
+            security,
+            source,
         }
     }
 
@@ -103,7 +132,14 @@ where
         let logger = self.logger.cheap_clone();
         let result = {
             let options = QueryExecutionOptions {
-                resolver: IndexNodeResolver::new(&logger, graphql_runner, store),
+                resolver: IndexNodeResolver::new(
+                    &logger,
+                    graphql_runner,
+                    store,
+                    self.chains.clone(),
+                    self.block_streams.clone(),
+                    self.handler_stats.clone(),
+                ),
                 deadline: None,
                 max_first: std::u32::MAX,
                 max_skip: std::u32::MAX,
@@ -125,7 +161,10 @@ where
         Response::builder()
             .status(200)
             .header("Access-Control-Allow-Origin", "*")
-            .header("Access-Control-Allow-Headers", "Content-Type, User-Agent")
+            .header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, User-Agent, Authorization",
+            )
             .header("Access-Control-Allow-Methods", "GET, OPTIONS, POST")
             .body(Body::from(""))
             .unwrap()
@@ -146,6 +185,15 @@ where
             })
     }
 
+    /// Handles requests without acceptable credentials.
+    fn handle_unauthorized() -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(header::WWW_AUTHENTICATE, "Bearer")
+            .body(Body::from("Unauthorized"))
+            .unwrap()
+    }
+
     /// Handles 404s.
     fn handle_not_found() -> Response<Body> {
         Response::builder()
@@ -157,6 +205,29 @@ where
     async fn handle_call(self, req: Request<Body>) -> Result<Response<Body>, GraphQLServerError> {
         let method = req.method().clone();
 
+        // CORS preflight requests never carry credentials
+        if method != Method::OPTIONS {
+            let auth_header = req
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok());
+            match self
+                .security
+                .authorize("index-node", auth_header, Some(self.source))
+            {
+                Ok(Some(label)) => debug!(self.logger, "Authorized index node request";
+                                          "token" => label,
+                                          "source" => self.source.to_string()),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(self.logger, "Rejected index node request";
+                          "error" => e.to_string(),
+                          "source" => self.source.to_string());
+                    return Ok(Self::handle_unauthorized());
+                }
+            }
+        }
+
         let path = req.uri().path().to_owned();
         let path_segments = {
             let mut segments = path.split('/');
@@ -193,7 +264,7 @@ where
 impl<Q, S> Service<Request<Body>> for IndexNodeService<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + EntityStore + EthereumCallCache,
 {
     type Response = Response<Body>;
     type Error = GraphQLServerError;
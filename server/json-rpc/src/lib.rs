@@ -1,34 +1,61 @@
 extern crate graph;
-extern crate jsonrpc_http_server;
+extern crate jsonrpc_core;
 extern crate lazy_static;
 extern crate serde;
 
+use graph::components::server::auth::ServerSecurity;
+use graph::components::server::tls;
 use graph::prelude::futures03::channel::{mpsc, oneshot};
 use graph::prelude::futures03::SinkExt;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
-use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
-    RestApi, Server, ServerBuilder,
-};
+use hyper::header::{self, HeaderValue};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use jsonrpc_core::{Compatibility, IoHandler, Params, Value};
 use lazy_static::lazy_static;
 
 use std::collections::BTreeMap;
 use std::env;
 use std::io;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
 lazy_static! {
     static ref EXTERNAL_HTTP_BASE_URL: Option<String> = env::var_os("EXTERNAL_HTTP_BASE_URL")
         .map(|s| s.into_string().expect("invalid external HTTP base URL"));
     static ref EXTERNAL_WS_BASE_URL: Option<String> = env::var_os("EXTERNAL_WS_BASE_URL")
         .map(|s| s.into_string().expect("invalid external WS base URL"));
+
+    /// The hosts that requests may name in their `Host` header. Requests
+    /// for any host are accepted if this is not set
+    static ref ALLOWED_HOSTS: Option<Vec<String>> = env::var_os("GRAPH_ADMIN_ALLOWED_HOSTS")
+        .map(|s| split_list(&s.into_string().expect("invalid admin allowed hosts")));
+
+    /// The origins that browsers may send requests from. Requests from any
+    /// origin are accepted if this is not set
+    static ref CORS_ORIGINS: Option<Vec<String>> = env::var_os("GRAPH_ADMIN_CORS_ORIGINS")
+        .map(|s| split_list(&s.into_string().expect("invalid admin CORS origins")));
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|entry| entry.trim().to_owned())
+        .filter(|entry| !entry.is_empty())
+        .collect()
 }
 
 const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_RESTORE_ERROR: i64 = 4;
+const JSON_RPC_CHAIN_REPAIR_ERROR: i64 = 5;
+const JSON_RPC_MEMORY_LIMIT_ERROR: i64 = 6;
+const JSON_RPC_RESTART_ERROR: i64 = 7;
+const JSON_RPC_REPAIR_BLOCK_RANGES_ERROR: i64 = 8;
+const JSON_RPC_POI_VERIFY_ERROR: i64 = 9;
+const JSON_RPC_FLAG_ERROR: i64 = 10;
+const JSON_RPC_UNFLAG_ERROR: i64 = 11;
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -53,6 +80,55 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct UnusedRestoreParams {
+    ipfs_hash: SubgraphDeploymentId,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphMemoryLimitParams {
+    ipfs_hash: SubgraphDeploymentId,
+    max_memory: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRestartParams {
+    ipfs_hash: SubgraphDeploymentId,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphRepairBlockRangesParams {
+    ipfs_hash: SubgraphDeploymentId,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct PoiVerifyParams {
+    deployment: SubgraphDeploymentId,
+    from: u64,
+    to: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphFlagParams {
+    deployment: SubgraphDeploymentId,
+    note: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphUnflagParams {
+    deployment: SubgraphDeploymentId,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainRepairParams {
+    network: String,
+    from: u64,
+    to: u64,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
     http_port: u16,
@@ -151,13 +227,200 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `unused_restore` endpoint.
+    async fn restore_handler(
+        &self,
+        params: UnusedRestoreParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received unused_restore request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .restore_unused_deployment(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "unused_restore",
+                e,
+                JSON_RPC_RESTORE_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_memory_limit` endpoint.
+    async fn memory_limit_handler(
+        &self,
+        params: SubgraphMemoryLimitParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_memory_limit request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .set_memory_limit(params.ipfs_hash.clone(), params.max_memory)
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_memory_limit",
+                e,
+                JSON_RPC_MEMORY_LIMIT_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_restart` endpoint.
+    async fn restart_handler(
+        &self,
+        params: SubgraphRestartParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_restart request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .restart_subgraph(params.ipfs_hash.clone(), params.reason.clone())
+            .await
+        {
+            Ok(result) => {
+                Ok(serde_json::to_value(result).expect("invalid subgraph restart result"))
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_restart",
+                e,
+                JSON_RPC_RESTART_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_repair_block_ranges` endpoint.
+    async fn repair_block_ranges_handler(
+        &self,
+        params: SubgraphRepairBlockRangesParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_repair_block_ranges request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .repair_block_ranges(params.ipfs_hash.clone(), params.dry_run)
+            .await
+        {
+            Ok(result) => {
+                Ok(serde_json::to_value(result).expect("invalid block range repair result"))
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_repair_block_ranges",
+                e,
+                JSON_RPC_REPAIR_BLOCK_RANGES_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `chain_repair` endpoint.
+    async fn chain_repair_handler(
+        &self,
+        params: ChainRepairParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received chain_repair request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .repair_chain(params.network.clone(), params.from, params.to)
+            .await
+        {
+            Ok(repair) => Ok(serde_json::to_value(repair).expect("invalid chain repair result")),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "chain_repair",
+                e,
+                JSON_RPC_CHAIN_REPAIR_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `poi_verify` endpoint.
+    async fn poi_verify_handler(
+        &self,
+        params: PoiVerifyParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received poi_verify request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .verify_proof_of_indexing(params.deployment.clone(), params.from, params.to)
+            .await
+        {
+            Ok(result) => {
+                Ok(serde_json::to_value(result).expect("invalid proof of indexing verification"))
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "poi_verify",
+                e,
+                JSON_RPC_POI_VERIFY_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_flag` endpoint.
+    async fn flag_handler(&self, params: SubgraphFlagParams) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_flag request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .flag_subgraph(params.deployment.clone(), params.note.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_flag",
+                e,
+                JSON_RPC_FLAG_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_unflag` endpoint.
+    async fn unflag_handler(
+        &self,
+        params: SubgraphUnflagParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_unflag request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .unflag_subgraph(params.deployment.clone())
+            .await
+        {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_unflag",
+                e,
+                JSON_RPC_UNFLAG_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
 where
     R: SubgraphRegistrar,
 {
-    type Server = Server;
+    type Server = Pin<Box<dyn std::future::Future<Output = ()> + Send>>;
 
     fn serve(
         port: u16,
@@ -165,13 +428,17 @@ where
         ws_port: u16,
         registrar: Arc<R>,
         node_id: NodeId,
+        security: ServerSecurity,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
         let logger = logger.new(o!("component" => "JsonRpcServer"));
 
         info!(
             logger,
-            "Starting JSON-RPC admin server at: http://localhost:{}", port
+            "Starting JSON-RPC admin server at: {}://localhost:{}",
+            security.scheme(),
+            port;
+            "auth" => security.auth.is_enabled(),
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -183,7 +450,7 @@ where
             http_port,
             ws_port,
             node_id,
-            logger,
+            logger: logger.clone(),
         });
 
         let (task_sender, task_receiver) =
@@ -276,14 +543,378 @@ where
             .compat()
         });
 
-        ServerBuilder::new(handler)
-            // Enable REST API:
-            // POST /<method>/<param1>/<param2>
-            .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("unused_restore", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.restore_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_memory_limit", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.memory_limit_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_restart", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.restart_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_repair_block_ranges", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.repair_block_ranges_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("chain_repair", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.chain_repair_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("poi_verify", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.poi_verify_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_flag", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.flag_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let me = arc_self.clone();
+        let sender = task_sender.clone();
+        handler.add_method("subgraph_unflag", move |params: Params| {
+            let me = me.clone();
+            Box::pin(tokio02_spawn(
+                sender.clone(),
+                async move {
+                    let params = params.parse()?;
+                    me.unflag_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        let acceptor = match &security.tls {
+            Some(tls) => Some(
+                tls.acceptor()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?,
+            ),
+            None => None,
+        };
+        let incoming = tls::incoming(logger.clone(), addr.into(), acceptor)?;
+
+        let handler = Arc::new(handler);
+        let server_logger = logger.clone();
+        let new_service = make_service_fn(move |conn: &tls::ServerStream| {
+            let source = conn.peer_addr();
+            let handler = handler.clone();
+            let security = security.clone();
+            let logger = logger.clone();
+            futures03::future::ok::<_, hyper::Error>(service_fn(move |req| {
+                handle_request(
+                    handler.clone(),
+                    security.clone(),
+                    logger.clone(),
+                    source,
+                    req,
+                )
+            }))
+        });
+
+        // Create a task to run the server and handle HTTP requests
+        let task = Server::builder(hyper::server::accept::from_stream(incoming))
+            .serve(new_service)
+            .map(move |res| {
+                if let Err(e) = res {
+                    error!(server_logger, "Server error"; "error" => format!("{}", e));
+                }
+            });
+        Ok(Box::pin(task))
     }
 }
 
+/// Serve a single HTTP request after checking its `Host` and `Origin`
+/// headers, and answer CORS preflight requests
+async fn handle_request(
+    handler: Arc<IoHandler>,
+    security: ServerSecurity,
+    logger: Logger,
+    source: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    if !host_allowed(ALLOWED_HOSTS.as_deref(), host) {
+        return Ok(forbidden("Provided Host header is not whitelisted.\n"));
+    }
+
+    let origin = req
+        .headers()
+        .get(header::ORIGIN)
+        .and_then(|value| value.to_str().ok());
+    let allow_origin = match cors_allow_origin(CORS_ORIGINS.as_deref(), origin) {
+        Ok(allow_origin) => allow_origin,
+        Err(()) => {
+            return Ok(forbidden(
+                "Origin of the request is not whitelisted. CORS headers would not be sent \
+                 and any side-effects were cancelled as well.\n",
+            ))
+        }
+    };
+
+    // Browsers send preflight requests without credentials
+    let mut response = if req.method() == Method::OPTIONS {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, "OPTIONS, POST")
+            .header(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                "Origin, Content-Type, Accept, Authorization",
+            )
+            .body(Body::empty())
+            .unwrap()
+    } else {
+        serve_request(handler, security, logger, source, req).await?
+    };
+    if let Some(allow_origin) = allow_origin {
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+    }
+    Ok(response)
+}
+
+/// Whether a request for `host` may be served. An allowed host without a
+/// port matches the host with any port
+fn host_allowed(allowed: Option<&[String]>, host: Option<&str>) -> bool {
+    let allowed = match allowed {
+        Some(allowed) => allowed,
+        None => return true,
+    };
+    let host = match host {
+        Some(host) => host.to_ascii_lowercase(),
+        None => return false,
+    };
+    let name = match host.rfind(':') {
+        // Don't mistake the colons of an IPv6 address for a port
+        Some(pos) if !host[pos..].contains(']') => &host[..pos],
+        _ => host.as_str(),
+    };
+    allowed.iter().any(|entry| {
+        let entry = entry.to_ascii_lowercase();
+        entry == host || entry == name
+    })
+}
+
+/// The `Access-Control-Allow-Origin` header for a request from `origin`,
+/// `None` if the request does not need one, and an error if the origin is
+/// not allowed
+fn cors_allow_origin(
+    allowed: Option<&[String]>,
+    origin: Option<&str>,
+) -> Result<Option<HeaderValue>, ()> {
+    let origin = match origin {
+        Some(origin) => origin,
+        None => return Ok(None),
+    };
+    let allowed = match allowed {
+        Some(allowed) => allowed.iter().any(|entry| entry == "*" || entry == origin),
+        None => true,
+    };
+    if allowed {
+        HeaderValue::from_str(origin).map(Some).map_err(|_| ())
+    } else {
+        Err(())
+    }
+}
+
+fn forbidden(message: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+/// Serve a single HTTP request. Besides plain JSON-RPC requests posted to
+/// `/`, the REST form `POST /<method>/<param1>/<param2>` is accepted as
+/// long as the request has a JSON content type.
+async fn serve_request(
+    handler: Arc<IoHandler>,
+    security: ServerSecurity,
+    logger: Logger,
+    source: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let auth_header = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    match security.authorize("json-rpc", auth_header, Some(source)) {
+        Ok(Some(label)) => debug!(logger, "Authorized admin request";
+                                  "token" => label,
+                                  "source" => source.to_string()),
+        Ok(None) => {}
+        Err(e) => {
+            warn!(logger, "Rejected admin request";
+                  "error" => e.to_string(),
+                  "source" => source.to_string());
+            return Ok(unauthorized());
+        }
+    }
+
+    if req.method() != Method::POST {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from(
+                "Used HTTP Method is not allowed. POST is required",
+            ))
+            .unwrap());
+    }
+
+    let path = req.uri().path().to_owned();
+    let is_json = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or(false);
+    let body = hyper::body::to_bytes(req.into_body()).await?;
+
+    let request = if path == "/" {
+        String::from_utf8_lossy(&body).into_owned()
+    } else if is_json {
+        rest_request(&path)
+    } else {
+        return Ok(Response::builder()
+            .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .body(Body::from(
+                "Supplied content type is not allowed. Content-Type: application/json is required",
+            ))
+            .unwrap());
+    };
+
+    let response = handler.handle_request(&request).compat().await;
+    Ok(match response {
+        Ok(Some(response)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json; charset=utf-8"),
+            )
+            .body(Body::from(response))
+            .unwrap(),
+        // Notifications do not get a response
+        Ok(None) => Response::builder()
+            .status(StatusCode::OK)
+            .body(Body::empty())
+            .unwrap(),
+        Err(()) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::from("internal error"))
+            .unwrap(),
+    })
+}
+
+fn unauthorized() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(header::WWW_AUTHENTICATE, "Bearer")
+        .body(Body::from("Unauthorized"))
+        .unwrap()
+}
+
+/// Turn a REST-style path `/<method>/<param1>/<param2>` into a JSON-RPC
+/// request. Parameters that parse as JSON are passed as such, all others
+/// are passed as strings.
+fn rest_request(path: &str) -> String {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let method = segments.next().unwrap_or("");
+    let params: Vec<Value> = segments
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| {
+            serde_json::from_str(segment).unwrap_or_else(|_| Value::String(segment.to_owned()))
+        })
+        .collect();
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    })
+    .to_string()
+}
+
 fn json_rpc_error(
     logger: &Logger,
     operation: &str,
@@ -343,3 +974,265 @@ fn subgraph_routes(name: &SubgraphName, http_port: u16, ws_port: u16) -> Value {
     );
     jsonrpc_core::to_value(map).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::components::ethereum::repair::ChainRepair;
+    use graph::components::server::auth::{AuthMetrics, ServerAuth};
+    use graph::prelude::async_trait;
+    use graph_mock::MockMetricsRegistry;
+
+    /// A registrar whose deployment `QmRunning` is assigned to the node
+    /// `test`, and `QmElsewhere` to the node `other`
+    struct FakeRegistrar;
+
+    #[async_trait]
+    impl SubgraphRegistrar for FakeRegistrar {
+        async fn create_subgraph(
+            &self,
+            _: SubgraphName,
+        ) -> Result<CreateSubgraphResult, SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn create_subgraph_version(
+            &self,
+            _: SubgraphName,
+            _: SubgraphDeploymentId,
+            _: NodeId,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn remove_subgraph(&self, _: SubgraphName) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn reassign_subgraph(
+            &self,
+            _: SubgraphDeploymentId,
+            _: NodeId,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn restore_unused_deployment(
+            &self,
+            _: SubgraphDeploymentId,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn set_memory_limit(
+            &self,
+            _: SubgraphDeploymentId,
+            _: Option<u64>,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn flag_subgraph(
+            &self,
+            _: SubgraphDeploymentId,
+            _: String,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn unflag_subgraph(
+            &self,
+            _: SubgraphDeploymentId,
+        ) -> Result<(), SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn restart_subgraph(
+            &self,
+            hash: SubgraphDeploymentId,
+            _: Option<String>,
+        ) -> Result<RestartSubgraphResult, SubgraphRegistrarError> {
+            match hash.as_str() {
+                "QmRunning" => Ok(RestartSubgraphResult {
+                    restarted: true,
+                    node_id: "test".to_owned(),
+                }),
+                "QmElsewhere" => Ok(RestartSubgraphResult {
+                    restarted: false,
+                    node_id: "other".to_owned(),
+                }),
+                _ => Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string())),
+            }
+        }
+
+        async fn repair_block_ranges(
+            &self,
+            _: SubgraphDeploymentId,
+            _: bool,
+        ) -> Result<RepairBlockRangesResult, SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn repair_chain(
+            &self,
+            _: String,
+            _: u64,
+            _: u64,
+        ) -> Result<ChainRepair, SubgraphRegistrarError> {
+            unimplemented!()
+        }
+
+        async fn verify_proof_of_indexing(
+            &self,
+            _: SubgraphDeploymentId,
+            _: u64,
+            _: u64,
+        ) -> Result<PoiVerificationResult, SubgraphRegistrarError> {
+            unimplemented!()
+        }
+    }
+
+    fn restart(hash: &str) -> Result<Value, jsonrpc_core::Error> {
+        let server = JsonRpcServer {
+            registrar: Arc::new(FakeRegistrar),
+            http_port: 8000,
+            ws_port: 8001,
+            node_id: NodeId::new("test").unwrap(),
+            logger: Logger::root(slog::Discard, o!()),
+        };
+        let params = SubgraphRestartParams {
+            ipfs_hash: SubgraphDeploymentId::new(hash).unwrap(),
+            reason: Some("testing".to_owned()),
+        };
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(server.restart_handler(params))
+    }
+
+    fn security() -> ServerSecurity {
+        ServerSecurity::new(
+            ServerAuth::new(vec!["deployer:s3cr3t".parse().unwrap()]),
+            None,
+            Arc::new(AuthMetrics::new(Arc::new(MockMetricsRegistry::new()))),
+        )
+    }
+
+    fn handler() -> Arc<IoHandler> {
+        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        handler.add_method("ping", |_: Params| {
+            futures03::future::ok::<_, jsonrpc_core::Error>(Value::from("pong"))
+                .boxed()
+                .compat()
+        });
+        Arc::new(handler)
+    }
+
+    fn request(auth: Option<&str>) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .header(header::CONTENT_TYPE, "application/json");
+        if let Some(auth) = auth {
+            builder = builder.header(header::AUTHORIZATION, auth);
+        }
+        builder
+            .body(Body::from(
+                r#"{"jsonrpc":"2.0","id":1,"method":"ping","params":[]}"#,
+            ))
+            .unwrap()
+    }
+
+    fn send(req: Request<Body>) -> Response<Body> {
+        let logger = Logger::root(slog::Discard, o!());
+        let source = "127.0.0.1:4000".parse().unwrap();
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(handle_request(handler(), security(), logger, source, req))
+            .unwrap()
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_tokens() {
+        for auth in vec![None, Some("Bearer s3cr3"), Some("s3cr3t")] {
+            let response = send(request(auth));
+            assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+            assert_eq!(
+                Some("Bearer"),
+                response
+                    .headers()
+                    .get(header::WWW_AUTHENTICATE)
+                    .and_then(|value| value.to_str().ok())
+            );
+        }
+
+        let response = send(request(Some("Bearer s3cr3t")));
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[test]
+    fn answers_preflight_requests_without_credentials() {
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(header::ORIGIN, "https://example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(req);
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            Some(&HeaderValue::from_static("https://example.com")),
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[test]
+    fn restart_reports_the_node_of_the_deployment() {
+        assert_eq!(
+            serde_json::json!({ "restarted": true, "node_id": "test" }),
+            restart("QmRunning").unwrap()
+        );
+        assert_eq!(
+            serde_json::json!({ "restarted": false, "node_id": "other" }),
+            restart("QmElsewhere").unwrap()
+        );
+
+        let error = restart("QmMissing").unwrap_err();
+        assert_eq!(
+            jsonrpc_core::ErrorCode::ServerError(JSON_RPC_RESTART_ERROR),
+            error.code
+        );
+        assert!(error.message.contains("QmMissing"));
+    }
+
+    #[test]
+    fn check_hosts() {
+        let allowed = vec!["localhost".to_owned(), "admin.example.com:8020".to_owned()];
+        let allowed = Some(allowed.as_slice());
+        assert!(host_allowed(None, None));
+        assert!(host_allowed(None, Some("evil.com")));
+        assert!(host_allowed(allowed, Some("localhost")));
+        assert!(host_allowed(allowed, Some("LOCALHOST:8020")));
+        assert!(host_allowed(allowed, Some("admin.example.com:8020")));
+        assert!(!host_allowed(allowed, Some("admin.example.com:80")));
+        assert!(!host_allowed(allowed, Some("evil.com")));
+        assert!(!host_allowed(allowed, None));
+    }
+
+    #[test]
+    fn check_origins() {
+        let allowed = vec!["https://example.com".to_owned()];
+        let allowed = Some(allowed.as_slice());
+        assert_eq!(Ok(None), cors_allow_origin(allowed, None));
+        assert_eq!(
+            Ok(Some(HeaderValue::from_static("https://example.com"))),
+            cors_allow_origin(allowed, Some("https://example.com"))
+        );
+        assert_eq!(
+            Err(()),
+            cors_allow_origin(allowed, Some("https://evil.com"))
+        );
+        assert_eq!(
+            Ok(Some(HeaderValue::from_static("https://evil.com"))),
+            cors_allow_origin(None, Some("https://evil.com"))
+        );
+    }
+}
@@ -6,12 +6,14 @@ use http::StatusCode;
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
 use std::str::FromStr;
 use tokio::prelude::{AsyncRead, AsyncWrite};
 use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
+use graph::components::server::rate_limit::{retry_after, RateLimiter};
 use graph::prelude::*;
 
 lazy_static! {
@@ -172,6 +174,7 @@ pub struct GraphQlConnection<Q, S> {
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     schema: Arc<ApiSchema>,
+    rate_limit: Option<(Arc<RateLimiter>, IpAddr)>,
 }
 
 impl<Q, S> GraphQlConnection<Q, S>
@@ -192,9 +195,20 @@ where
             graphql_runner,
             stream,
             schema,
+            rate_limit: None,
         }
     }
 
+    /// Limit how many operations `client` may start with `rate_limiter`
+    pub(crate) fn with_rate_limiter(
+        mut self,
+        rate_limiter: Arc<RateLimiter>,
+        client: IpAddr,
+    ) -> Self {
+        self.rate_limit = Some((rate_limiter, client));
+        self
+    }
+
     async fn handle_incoming_messages(
         mut ws_stream: SplitStream<WebSocketStream<S>>,
         mut msg_sink: mpsc::UnboundedSender<WsMessage>,
@@ -202,6 +216,7 @@ where
         connection_id: String,
         schema: Arc<ApiSchema>,
         graphql_runner: Arc<Q>,
+        rate_limit: Option<(Arc<RateLimiter>, IpAddr)>,
     ) -> Result<(), WsError> {
         let mut operations = Operations::new(msg_sink.clone());
 
@@ -260,6 +275,21 @@ where
                         }
                     }
 
+                    // Every operation counts against the rate limit of the
+                    // client, not just opening the connection
+                    if let Some((rate_limiter, client)) = &rate_limit {
+                        if let Err(retry_in) = rate_limiter.check(*client, schema.id()) {
+                            return send_error_string(
+                                &msg_sink,
+                                id.clone(),
+                                format!(
+                                    "Rate limit exceeded, retry in {} seconds",
+                                    retry_after(retry_in)
+                                ),
+                            );
+                        }
+                    }
+
                     // Parse the GraphQL query document; respond with a GQL_ERROR if
                     // the query is invalid
                     let query = match parse_query(&payload.query) {
@@ -308,6 +338,19 @@ where
                            "connection" => &connection_id,
                            "id" => &id);
 
+                    // Operations that fail are always logged below, those
+                    // that start successfully only if they are sampled
+                    let query_id = subscription.query.query_id.clone();
+                    if graph::log::sample_query_log() {
+                        info!(logger, "Subscription started";
+                              "connection" => &connection_id,
+                              "id" => &id,
+                              "query_id" => &query_id,
+                              "subgraph_id" => schema.id().as_str(),
+                              "query_hash" => format!("{:x}", subscription.query.shape_hash),
+                              "variables" => subscription.query.variables_for_log());
+                    }
+
                     // Execute the GraphQL subscription
                     let error_sink = msg_sink.clone();
                     let result_sink = msg_sink.clone();
@@ -320,6 +363,15 @@ where
                         .run_subscription(subscription)
                         .compat()
                         .map_err(move |e| {
+                            let SubscriptionError::GraphQLError(errors) = &e;
+                            info!(err_logger, "Subscription failed";
+                                              "connection" => &err_connection_id,
+                                              "id" => &err_id,
+                                              "query_id" => &query_id,
+                                              "error" => errors
+                                                  .first()
+                                                  .map(|e| e.class())
+                                                  .unwrap_or("none"));
                             debug!(err_logger, "Subscription error";
                                                "connection" => &err_connection_id,
                                                "id" => &err_id,
@@ -404,6 +456,7 @@ where
             self.id.clone(),
             self.schema.clone(),
             self.graphql_runner.clone(),
+            self.rate_limit.clone(),
         );
 
         // Send outgoing messages asynchronously
@@ -1,6 +1,7 @@
+use graph::components::server::rate_limit::{self, RateLimiter};
 use graph::data::subgraph::schema::SUBGRAPHS_ID;
 use graph::prelude::{SubscriptionServer as SubscriptionServerTrait, *};
-use http::{HeaderValue, Response, StatusCode};
+use http::{header, HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Mutex;
 use tokio::net::TcpListener;
@@ -14,26 +15,34 @@ pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + DeploymentStore,
 {
     pub fn new(logger: &Logger, graphql_runner: Arc<Q>, store: Arc<S>) -> Self {
         SubscriptionServer {
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            rate_limiter: None,
         }
     }
 
+    /// Limit how many connections and operations each client may open
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
     fn subgraph_id_from_url_path(
         store: Arc<S>,
         path: &str,
     ) -> Result<Option<SubgraphDeploymentId>, Error> {
-        fn id_from_name<S: SubgraphDeploymentStore + Store>(
+        fn id_from_name<S: SubgraphDeploymentStore + DeploymentStore>(
             store: Arc<S>,
             name: String,
         ) -> Option<SubgraphDeploymentId> {
@@ -76,7 +85,7 @@ where
 impl<Q, S> SubscriptionServerTrait for SubscriptionServer<Q, S>
 where
     Q: GraphQlRunner,
-    S: SubgraphDeploymentStore + Store,
+    S: SubgraphDeploymentStore + DeploymentStore,
 {
     async fn serve(self, port: u16) {
         info!(
@@ -103,10 +112,23 @@ where
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
             let store2 = self.store.clone();
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(e) => {
+                    trace!(self.logger, "Connection error: {}", e);
+                    continue;
+                }
+            };
+            let rate_limiter = self.rate_limiter.clone();
 
             // Subgraph that the request is resolved to (if any)
             let subgraph_id = Arc::new(Mutex::new(None));
             let accept_subgraph_id = subgraph_id.clone();
+            // The client that the request came from, according to the
+            // trusted proxies
+            let client = Arc::new(Mutex::new(peer.ip()));
+            let accept_client = client.clone();
+            let accept_rate_limiter = rate_limiter.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
                 // Try to obtain the subgraph ID or name from the URL path.
@@ -138,6 +160,22 @@ where
                     Ok(true) => (),
                 }
 
+                if let Some(rate_limiter) = &accept_rate_limiter {
+                    let client = rate_limiter.client_ip(peer.ip(), request.headers());
+                    *accept_client.lock().unwrap() = client;
+                    if let Err(retry_in) = rate_limiter.check(client, &subgraph_id) {
+                        debug!(logger, "Rate limited WS connection";
+                               "subgraph_id" => subgraph_id.to_string(),
+                               "client" => client.to_string(),
+                        );
+                        return Err(Response::builder()
+                            .status(StatusCode::TOO_MANY_REQUESTS)
+                            .header(header::RETRY_AFTER, rate_limit::retry_after(retry_in))
+                            .body(None)
+                            .unwrap());
+                    }
+                }
+
                 *accept_subgraph_id.lock().unwrap() = Some(subgraph_id);
                 response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("graphql-ws"));
                 Ok(response)
@@ -161,12 +199,16 @@ where
                         };
 
                         // Spawn a GraphQL over WebSocket connection
-                        let service = GraphQlConnection::new(
+                        let mut service = GraphQlConnection::new(
                             &logger2,
                             schema,
                             ws_stream,
                             graphql_runner.clone(),
                         );
+                        if let Some(rate_limiter) = rate_limiter {
+                            let client = *client.lock().unwrap();
+                            service = service.with_rate_limiter(rate_limiter, client);
+                        }
 
                         graph::spawn_allow_panic(service.into_future().compat());
                     }
@@ -201,6 +201,7 @@ fn print_diesel_tables(layout: &Layout) {
             ColumnType::BigDecimal | ColumnType::BigInt => "Numeric",
             ColumnType::Bytes | ColumnType::BytesId => "Binary",
             ColumnType::Int => "Integer",
+            ColumnType::Timestamp => "Timestamptz",
             ColumnType::String | ColumnType::Enum(_) | ColumnType::TSVector(_) => "Text",
         }
         .to_owned();
@@ -220,6 +221,7 @@ fn print_diesel_tables(layout: &Layout) {
             ColumnType::BigDecimal | ColumnType::BigInt => "BigDecimal",
             ColumnType::Bytes | ColumnType::BytesId => "Vec<u8>",
             ColumnType::Int => "i32",
+            ColumnType::Timestamp => "DateTime<Utc>",
             ColumnType::String | ColumnType::Enum(_) | ColumnType::TSVector(_) => "String",
         }
         .to_owned();
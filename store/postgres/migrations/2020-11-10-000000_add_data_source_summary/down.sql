@@ -0,0 +1,2 @@
+ALTER TABLE subgraphs.ethereum_contract_data_source DROP COLUMN summary;
+ALTER TABLE subgraphs.dynamic_ethereum_contract_data_source DROP COLUMN summary;
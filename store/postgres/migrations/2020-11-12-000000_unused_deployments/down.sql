@@ -0,0 +1,4 @@
+drop table unused_deployments;
+
+alter table deployment_schemas
+  drop column last_used;
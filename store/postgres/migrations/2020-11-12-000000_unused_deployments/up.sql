@@ -0,0 +1,11 @@
+-- When a deployment was last queried or deployed; used to decide which
+-- deployments are unused and can be removed
+alter table deployment_schemas
+  add column last_used timestamptz not null default now();
+
+-- Deployments that were found to be unused, and when that was noticed.
+-- They get removed once they have been unused for long enough
+create table unused_deployments(
+  deployment  text primary key,
+  unused_at   timestamptz not null default now()
+);
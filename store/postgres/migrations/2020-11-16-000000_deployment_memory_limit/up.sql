@@ -0,0 +1,4 @@
+-- The maximum size of the WASM memory, in bytes, that a handler of the
+-- deployment may use; the node's default applies if this is null
+alter table deployment_schemas
+  add column max_memory int8;
@@ -0,0 +1,10 @@
+alter table
+    subgraphs.subgraph_error
+drop
+    column message_hash,
+drop
+    column count,
+drop
+    column last_block_number,
+drop
+    column last_block_hash;
@@ -0,0 +1,12 @@
+-- Repeated errors are stored once, with a count and the last block in
+-- which they happened
+alter table
+    subgraphs.subgraph_error
+add
+    column message_hash bytea,
+add
+    column count int4 not null default 1,
+add
+    column last_block_number numeric,
+add
+    column last_block_hash bytea;
@@ -0,0 +1,4 @@
+alter table
+    public.eth_call_cache
+drop
+    column revert_reason;
@@ -0,0 +1,6 @@
+-- For calls that reverted, return_value holds the data the contract
+-- reverted with, and revert_reason why it reverted
+alter table
+    public.eth_call_cache
+add
+    column revert_reason text;
@@ -0,0 +1,6 @@
+-- The number of blocks of history a deployment keeps, or null if it keeps
+-- all of it, and the earliest block that can still be queried because
+-- entity versions before it have been pruned
+alter table deployment_schemas
+  add column history_blocks int4,
+  add column earliest_block int4 not null default 0;
@@ -0,0 +1,12 @@
+alter table
+    public.ethereum_blocks
+drop
+    column provider,
+drop
+    column fetched_at;
+alter table
+    public.eth_call_cache
+drop
+    column provider,
+drop
+    column fetched_at;
@@ -0,0 +1,15 @@
+-- The provider that served a cached block or call, as a hash of its
+-- hostname, and when it was fetched. Rows from before this migration
+-- have neither and are reported as coming from an unknown provider
+alter table
+    public.ethereum_blocks
+add
+    column provider text,
+add
+    column fetched_at timestamptz;
+alter table
+    public.eth_call_cache
+add
+    column provider text,
+add
+    column fetched_at timestamptz;
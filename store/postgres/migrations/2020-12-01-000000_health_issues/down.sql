@@ -0,0 +1 @@
+drop table health_issues;
@@ -0,0 +1,11 @@
+-- Inconsistencies in subgraph metadata that were found the last time the
+-- metadata was checked, and what was done about them
+create table health_issues(
+  id             serial primary key,
+  kind           text not null,
+  deployment     text,
+  subgraph_name  text,
+  message        text not null,
+  remediation    text not null,
+  detected_at    timestamptz not null default now()
+);
@@ -0,0 +1,3 @@
+alter table deployment_schemas
+  drop column flag_note,
+  drop column flagged_at;
@@ -0,0 +1,6 @@
+-- A note that an operator left on a deployment to mark it as unhealthy,
+-- and when they did that; both are null for deployments that are not
+-- flagged
+alter table deployment_schemas
+  add column flag_note text,
+  add column flagged_at timestamptz;
@@ -0,0 +1,4 @@
+-- Whether the deployment's manifest sets `strictBytes`, which changes how
+-- mappings write bytes and hex strings
+alter table deployment_schemas
+  add column strict_bytes boolean not null default false;
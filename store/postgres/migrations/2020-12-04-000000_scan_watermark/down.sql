@@ -0,0 +1,3 @@
+alter table deployment_schemas
+  drop column scanned_block,
+  drop column scanned_up_to;
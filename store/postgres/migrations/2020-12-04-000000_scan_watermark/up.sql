@@ -0,0 +1,6 @@
+-- The blocks after `scanned_block` up to `scanned_up_to` were scanned for
+-- triggers by the block stream and do not contain any; both are null if
+-- the block stream has not recorded that yet
+alter table deployment_schemas
+  add column scanned_block int,
+  add column scanned_up_to int;
@@ -0,0 +1 @@
+drop table ethereum_head_announcements;
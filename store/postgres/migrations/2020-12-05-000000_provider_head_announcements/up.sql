@@ -0,0 +1,13 @@
+-- The last few chain heads that each provider announced, so that they
+-- survive a restart of the node
+create table ethereum_head_announcements(
+  id           serial primary key,
+  network_name text not null,
+  provider     text not null,
+  block_number int8 not null,
+  block_hash   text not null,
+  received_at  timestamptz not null
+);
+
+create index ethereum_head_announcements_provider
+    on ethereum_head_announcements(network_name, provider);
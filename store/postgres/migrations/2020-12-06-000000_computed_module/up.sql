@@ -0,0 +1,4 @@
+-- The WASM module that computes the `@computed` fields of the deployment's
+-- schema at query time
+alter table deployment_schemas
+  add column computed_module bytea;
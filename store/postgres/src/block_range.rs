@@ -81,7 +81,11 @@ impl ToSql<Range<Integer>, Pg> for BlockRange {
 }
 
 /// Generate the clause that checks whether `block` is in the block range
-/// of an entity
+/// of an entity. For tables with a `ttl`, the clause also checks that the
+/// entity has not expired at `block`, i.e., that the version was changed at
+/// most `ttl` blocks before `block`. Reads with `BLOCK_NUMBER_MAX` see
+/// expired entities until they are removed; mappings therefore read at the
+/// block that they process
 #[derive(Constructor)]
 pub struct BlockRangeContainsClause<'a> {
     table: &'a Table,
@@ -97,6 +101,17 @@ impl<'a> QueryFragment<Pg> for BlockRangeContainsClause<'a> {
         out.push_identifier(BLOCK_RANGE_COLUMN)?;
         out.push_sql(" @> ");
         out.push_bind_param::<Integer, _>(&self.block)?;
+        if self.block < BLOCK_NUMBER_MAX {
+            if let Some(ttl) = &self.table.ttl {
+                out.push_sql(" and lower(");
+                out.push_sql(self.table_prefix);
+                out.push_identifier(BLOCK_RANGE_COLUMN)?;
+                out.push_sql(") >= ");
+                out.push_bind_param::<Integer, _>(&self.block)?;
+                out.push_sql(" - ");
+                out.push_bind_param::<Integer, _>(ttl)?;
+            }
+        }
         if self.table.is_account_like && self.block < BLOCK_NUMBER_MAX {
             // When block is BLOCK_NUMBER_MAX, these checks would be wrong; we
             // don't worry about adding the equivalent in that case since
@@ -9,9 +9,10 @@ use std::iter::FromIterator;
 use std::sync::Arc;
 
 use graph::prelude::{
-    serde_json, web3::types::H256, BlockNumber, ChainHeadUpdateListener as _,
-    ChainHeadUpdateStream, Error, EthereumBlock, EthereumBlockPointer, EthereumNetworkIdentifier,
-    Future, LightEthereumBlock, Stream,
+    serde_json, web3::types::H256, BlockGap, BlockNumber, CachedBlock,
+    ChainHeadUpdateListener as _, ChainHeadUpdateStream, Error, EthereumBlock,
+    EthereumBlockPointer, EthereumNetworkIdentifier, Future, HeadAnnouncement, LightEthereumBlock,
+    Provenance, Stream,
 };
 
 //use web3::types::H256;
@@ -118,6 +119,47 @@ impl ChainStore {
 
         Ok(())
     }
+
+    /// Find the hash of the block with the given `number` on the chain that
+    /// ends in the current chain head by following parent hashes back from
+    /// the head. Returns `None` if there is no chain head yet, if `number`
+    /// is past the chain head, or if the walk runs into a gap in the block
+    /// cache.
+    fn canonical_block_hash(
+        &self,
+        conn: &PgConnection,
+        number: u64,
+    ) -> Result<Option<String>, Error> {
+        use diesel::sql_types::{BigInt, Text};
+
+        #[derive(QueryableByName)]
+        struct CanonicalHash {
+            #[sql_type = "Text"]
+            hash: String,
+        };
+
+        let query = "
+            with recursive chain(hash, parent_hash, number) as (
+                select b.hash, b.parent_hash, b.number
+                  from ethereum_blocks b, ethereum_networks n
+                 where n.name = $1
+                   and b.network_name = $1
+                   and b.hash = n.head_block_hash
+                union all
+                select b.hash, b.parent_hash, b.number
+                  from ethereum_blocks b, chain c
+                 where b.hash = c.parent_hash
+                   and b.network_name = $1
+                   and c.number > $2)
+            select hash from chain where number = $2";
+        Ok(diesel::sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(number as i64)
+            .get_results::<CanonicalHash>(conn)?
+            .into_iter()
+            .next()
+            .map(|CanonicalHash { hash }| hash))
+    }
 }
 
 impl ChainStoreTrait for ChainStore {
@@ -127,6 +169,7 @@ impl ChainStoreTrait for ChainStore {
 
     fn upsert_blocks<B, E>(
         &self,
+        provider_id: String,
         blocks: B,
     ) -> Box<dyn Future<Item = (), Error = E> + Send + 'static>
     where
@@ -134,6 +177,8 @@ impl ChainStoreTrait for ChainStore {
         E: From<Error> + Send + 'static,
     {
         use crate::db_schema::ethereum_blocks::dsl::*;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Nullable, Timestamptz};
 
         let conn = self.conn.clone();
         let net_name = self.network.clone();
@@ -145,6 +190,8 @@ impl ChainStoreTrait for ChainStore {
                 parent_hash.eq(format!("{:x}", block.block.parent_hash)),
                 network_name.eq(&net_name),
                 data.eq(json_blob),
+                provider.eq(&provider_id),
+                fetched_at.eq(sql::<Nullable<Timestamptz>>("now()")),
             );
 
             // Insert blocks.
@@ -163,8 +210,14 @@ impl ChainStoreTrait for ChainStore {
         }))
     }
 
-    fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), Error> {
+    fn upsert_light_blocks(
+        &self,
+        provider_id: &str,
+        blocks: Vec<LightEthereumBlock>,
+    ) -> Result<(), Error> {
         use crate::db_schema::ethereum_blocks::dsl::*;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Nullable, Timestamptz};
 
         let conn = self.conn.clone();
         let net_name = self.network.clone();
@@ -183,6 +236,8 @@ impl ChainStoreTrait for ChainStore {
                 parent_hash.eq(p_hash),
                 network_name.eq(&net_name),
                 data.eq(json_blob),
+                provider.eq(provider_id),
+                fetched_at.eq(sql::<Nullable<Timestamptz>>("now()")),
             );
 
             // Insert blocks. On conflict do nothing, we don't want to erase transaction receipts.
@@ -340,18 +395,64 @@ impl ChainStoreTrait for ChainStore {
     }
 
     fn block_hashes_by_block_number(&self, number: u64) -> Result<Vec<H256>, Error> {
+        self.block_hashes_and_parents_by_block_number(number)
+            .map(|blocks| blocks.into_iter().map(|(hash, _)| hash).collect())
+    }
+
+    fn block_hashes_and_parents_by_block_number(
+        &self,
+        number: u64,
+    ) -> Result<Vec<(H256, H256)>, Error> {
         use crate::db_schema::ethereum_blocks::dsl;
 
         let conn = self.get_conn()?;
-        dsl::ethereum_blocks
-            .select(dsl::hash)
+        let mut blocks = dsl::ethereum_blocks
+            .select((dsl::hash, dsl::parent_hash))
             .filter(dsl::network_name.eq(&self.network))
             .filter(dsl::number.eq(number as i64))
-            .get_results::<String>(&conn)?
+            .order_by(dsl::hash)
+            .get_results::<(String, String)>(&conn)?;
+
+        // Only go looking for the canonical block if there is more than one
+        // candidate; the sort is stable and keeps the remaining blocks in
+        // lexicographic order
+        if blocks.len() > 1 {
+            if let Some(canonical) = self.canonical_block_hash(&conn, number)? {
+                blocks.sort_by_key(|(hash, _)| hash != &canonical);
+            }
+        }
+
+        blocks
             .into_iter()
-            .map(|h| h.parse())
-            .collect::<Result<Vec<H256>, _>>()
-            .map_err(Error::from)
+            .map(|(hash, parent_hash)| -> Result<(H256, H256), Error> {
+                Ok((hash.parse()?, parent_hash.parse()?))
+            })
+            .collect()
+    }
+
+    fn canonical_block_hash_by_block_number(&self, number: u64) -> Result<Option<H256>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        let conn = self.get_conn()?;
+        let mut hashes = dsl::ethereum_blocks
+            .select(dsl::hash)
+            .filter(dsl::network_name.eq(&self.network))
+            .filter(dsl::number.eq(number as i64))
+            .get_results::<String>(&conn)?;
+
+        let hash = match hashes.len() {
+            0 => return Ok(None),
+            1 => hashes.pop().unwrap(),
+            _ => self.canonical_block_hash(&conn, number)?.ok_or_else(|| {
+                failure::format_err!(
+                    "there are {} blocks with number {} in the block cache, and none of them \
+                     is known to be on the chain that ends in the chain head",
+                    hashes.len(),
+                    number
+                )
+            })?,
+        };
+        Ok(Some(hash.parse()?))
     }
 
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, Error> {
@@ -365,4 +466,165 @@ impl ChainStoreTrait for ChainStore {
             .execute(&conn)
             .map_err(Error::from)
     }
+
+    fn find_gaps(&self, from: u64, to: u64) -> Result<Vec<BlockGap>, Error> {
+        use diesel::sql_types::{BigInt, Nullable, Text};
+
+        #[derive(QueryableByName)]
+        struct Gap {
+            #[sql_type = "BigInt"]
+            number: i64,
+            #[sql_type = "Nullable<Text>"]
+            hash: Option<String>,
+            #[sql_type = "Nullable<Text>"]
+            parent_hash: Option<String>,
+        };
+
+        // Heights without any block, and blocks whose parent is not among
+        // the blocks one below them. We do not report a broken link for a
+        // block if its parent height is missing altogether since repairing
+        // the missing height takes care of that
+        let query = "
+            select h.number, null as hash, null as parent_hash
+              from generate_series($2, $3) as h(number)
+             where not exists (select 1
+                                 from ethereum_blocks b
+                                where b.network_name = $1
+                                  and b.number = h.number)
+            union all
+            select b.number, b.hash, b.parent_hash
+              from ethereum_blocks b
+             where b.network_name = $1
+               and b.number > $2
+               and b.number <= $3
+               and exists (select 1
+                             from ethereum_blocks p
+                            where p.network_name = $1
+                              and p.number = b.number - 1)
+               and not exists (select 1
+                                 from ethereum_blocks p
+                                where p.network_name = $1
+                                  and p.number = b.number - 1
+                                  and p.hash = b.parent_hash)
+             order by number, hash";
+        diesel::sql_query(query)
+            .bind::<Text, _>(&self.network)
+            .bind::<BigInt, _>(from as i64)
+            .bind::<BigInt, _>(to as i64)
+            .get_results::<Gap>(&*self.get_conn()?)?
+            .into_iter()
+            .map(|gap| {
+                let number = gap.number as u64;
+                match (gap.hash, gap.parent_hash) {
+                    (Some(hash), Some(parent_hash)) => Ok(BlockGap::BrokenLink {
+                        number,
+                        hash: hash.parse()?,
+                        parent_hash: parent_hash.parse()?,
+                    }),
+                    _ => Ok(BlockGap::Missing(number)),
+                }
+            })
+            .collect()
+    }
+
+    fn cached_block(&self, block_hash: H256) -> Result<Option<CachedBlock>, Error> {
+        use crate::db_schema::ethereum_blocks::dsl::*;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Nullable};
+
+        ethereum_blocks
+            .select((
+                number,
+                parent_hash,
+                provider,
+                sql::<Nullable<BigInt>>("extract(epoch from fetched_at)::int8"),
+            ))
+            .filter(network_name.eq(&self.network))
+            .filter(hash.eq(format!("{:x}", block_hash)))
+            .first::<(i64, Option<String>, Option<String>, Option<i64>)>(&*self.get_conn()?)
+            .optional()?
+            .map(|(block_number, parent, provider_id, fetched)| {
+                Ok(CachedBlock {
+                    ptr: EthereumBlockPointer::from((block_hash, block_number)),
+                    parent_hash: parent.map(|parent| parent.parse()).transpose()?,
+                    provenance: Provenance::new(provider_id, fetched.map(|secs| secs as u64)),
+                })
+            })
+            .transpose()
+    }
+
+    fn save_head_announcements(
+        &self,
+        provider: &str,
+        announcements: &[HeadAnnouncement],
+    ) -> Result<(), Error> {
+        use diesel::sql_types::{BigInt, Text};
+
+        let conn = self.get_conn()?;
+        conn.transaction(|| {
+            diesel::sql_query(
+                "delete from ethereum_head_announcements
+                  where network_name = $1 and provider = $2",
+            )
+            .bind::<Text, _>(&self.network)
+            .bind::<Text, _>(provider)
+            .execute(&*conn)?;
+            for announcement in announcements {
+                diesel::sql_query(
+                    "insert into ethereum_head_announcements
+                       (network_name, provider, block_number, block_hash, received_at)
+                     values ($1, $2, $3, $4, to_timestamp($5))",
+                )
+                .bind::<Text, _>(&self.network)
+                .bind::<Text, _>(provider)
+                .bind::<BigInt, _>(announcement.block.number as i64)
+                .bind::<Text, _>(format!("{:x}", announcement.block.hash))
+                .bind::<BigInt, _>(announcement.received_at as i64)
+                .execute(&*conn)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn head_announcements(&self) -> Result<Vec<(String, Vec<HeadAnnouncement>)>, Error> {
+        use diesel::sql_types::{BigInt, Text};
+
+        #[derive(QueryableByName)]
+        struct Announcement {
+            #[sql_type = "Text"]
+            provider: String,
+            #[sql_type = "BigInt"]
+            block_number: i64,
+            #[sql_type = "Text"]
+            block_hash: String,
+            #[sql_type = "BigInt"]
+            received_at: i64,
+        };
+
+        let rows = diesel::sql_query(
+            "select provider, block_number, block_hash,
+                    extract(epoch from received_at)::int8 as received_at
+               from ethereum_head_announcements
+              where network_name = $1
+              order by provider, id",
+        )
+        .bind::<Text, _>(&self.network)
+        .get_results::<Announcement>(&*self.get_conn()?)?;
+
+        let mut announcements: Vec<(String, Vec<HeadAnnouncement>)> = Vec::new();
+        for row in rows {
+            let announcement = HeadAnnouncement {
+                block: EthereumBlockPointer::from((
+                    row.block_hash.parse::<H256>()?,
+                    row.block_number,
+                )),
+                received_at: row.received_at as u64,
+            };
+            match announcements.last_mut() {
+                Some((provider, list)) if *provider == row.provider => list.push(announcement),
+                _ => announcements.push((row.provider, vec![announcement])),
+            }
+        }
+        Ok(announcements)
+    }
 }
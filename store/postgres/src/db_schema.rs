@@ -15,6 +15,8 @@ table! {
         parent_hash -> Nullable<Varchar>,
         network_name -> Varchar, // REFERENCES ethereum_networks (name),
         data -> Jsonb,
+        provider -> Nullable<Text>,
+        fetched_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -40,6 +42,9 @@ table! {
         return_value -> Bytea,
         contract_address -> Bytea,
         block_number -> Integer,
+        revert_reason -> Nullable<Text>,
+        provider -> Nullable<Text>,
+        fetched_at -> Nullable<Timestamptz>,
     }
 }
 
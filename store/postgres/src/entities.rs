@@ -34,7 +34,7 @@ use inflector::cases::snakecase::to_snake_case;
 use lazy_static::lazy_static;
 use maybe_owned::MaybeOwned;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryInto;
 use std::hash::{Hash, Hasher};
 use std::ops::Deref as _;
@@ -42,11 +42,11 @@ use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use graph::data::schema::Schema as SubgraphSchema;
-use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
+use graph::data::subgraph::schema::{is_system_entity_type, POI_OBJECT, POI_TABLE, SUBGRAPHS_ID};
 use graph::prelude::{
-    debug, format_err, info, serde_json, warn, AttributeIndexDefinition, BlockNumber, Entity,
-    EntityChange, EntityChangeOperation, EntityCollection, EntityFilter, EntityKey,
-    EntityModification, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger,
+    debug, format_err, info, serde_json, warn, AttributeIndexDefinition, BlockNumber,
+    BlockRangeOverlap, Entity, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
+    EntityKey, EntityModification, EntityOrder, EntityRange, Error, EthereumBlockPointer, Logger,
     QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, ValueType, BLOCK_NUMBER_MAX,
 };
 
@@ -414,8 +414,8 @@ impl Connection<'_> {
 
     pub(crate) fn find(
         &self,
-        entity: &String,
-        id: &String,
+        entity: &str,
+        id: &str,
         block: BlockNumber,
     ) -> Result<Option<Entity>, StoreError> {
         match &*self.storage {
@@ -452,6 +452,14 @@ impl Connection<'_> {
         }
     }
 
+    /// Whether `entity_type` is one of graph-node's own entity types
+    pub(crate) fn is_system_entity_type(&self, entity_type: &str) -> bool {
+        match &*self.storage {
+            Storage::Json(_) => is_system_entity_type(entity_type),
+            Storage::Relational(layout) => layout.is_system_entity_type(entity_type),
+        }
+    }
+
     pub(crate) fn query<T: crate::relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -459,6 +467,7 @@ impl Connection<'_> {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        attributes: BTreeMap<String, BTreeSet<String>>,
         block: BlockNumber,
         query_id: Option<String>,
     ) -> Result<Vec<T>, QueryExecutionError> {
@@ -475,14 +484,19 @@ impl Connection<'_> {
                     .into());
                 }
                 let order = match order {
-                    EntityOrder::Ascending(attr, value_type) => Some((attr, value_type, "asc")),
-                    EntityOrder::Descending(attr, value_type) => Some((attr, value_type, "desc")),
+                    EntityOrder::Ascending(attr, value_type) => {
+                        Some((attr.into(), value_type, "asc"))
+                    }
+                    EntityOrder::Descending(attr, value_type) => {
+                        Some((attr.into(), value_type, "desc"))
+                    }
                     EntityOrder::Default | EntityOrder::Unordered => None,
                 };
+                // JSON storage always fetches all attributes
                 json.query(&self.conn, collection, filter, order, range)
             }
             Storage::Relational(layout) => layout.query(
-                logger, &self.conn, collection, filter, order, range, block, query_id,
+                logger, &self.conn, collection, filter, order, range, attributes, block, query_id,
             ),
         }
     }
@@ -556,8 +570,8 @@ impl Connection<'_> {
 
     pub(crate) fn find_metadata(
         &self,
-        entity: &String,
-        id: &String,
+        entity: &str,
+        id: &str,
     ) -> Result<Option<Entity>, StoreError> {
         self.metadata_layout()
             .find(&self.conn, entity, id, BLOCK_NUMBER_MAX)
@@ -577,6 +591,34 @@ impl Connection<'_> {
         }
     }
 
+    /// Delete the entities with `keys`, which must all have the same
+    /// subgraph and entity type, and return how many were deleted.
+    /// Relational storage deletes all of them with one query
+    pub(crate) fn delete_many(
+        &self,
+        keys: &[EntityKey],
+        history_event: Option<&HistoryEvent>,
+    ) -> Result<usize, StoreError> {
+        let first = match keys.first() {
+            Some(first) => first,
+            None => return Ok(0),
+        };
+        match (self.storage_for(first), history_event) {
+            (Storage::Relational(layout), Some(history_event)) => {
+                let ids: Vec<_> = keys.iter().map(|key| key.entity_id.as_str()).collect();
+                layout.delete_many(
+                    &self.conn,
+                    &first.entity_type,
+                    &ids,
+                    block_number(&history_event),
+                )
+            }
+            _ => keys
+                .iter()
+                .try_fold(0, |count, key| Ok(count + self.delete(key, history_event)?)),
+        }
+    }
+
     pub(crate) fn build_attribute_index(
         &self,
         index: &AttributeIndexDefinition,
@@ -614,6 +656,43 @@ impl Connection<'_> {
         Ok((event.extend(meta_event), count))
     }
 
+    /// Remove the entity versions that are not needed anymore to answer
+    /// queries at `earliest_block` or later. Subgraphs that use JSONB
+    /// storage do not support pruning, and nothing is removed for them
+    pub(crate) fn prune(&self, earliest_block: BlockNumber) -> Result<usize, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Ok(0),
+            Storage::Relational(layout) => layout.prune(&self.conn, earliest_block),
+        }
+    }
+
+    /// Remove the entities of `@ttl` types that expired at block `block`.
+    /// Subgraphs that use JSONB storage do not support `@ttl`, and nothing
+    /// is removed for them
+    pub(crate) fn expire(&self, block: BlockNumber) -> Result<usize, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Ok(0),
+            Storage::Relational(layout) => layout.expire(&self.conn, block),
+        }
+    }
+
+    /// Find the entities that have versions with overlapping block ranges
+    pub(crate) fn check_block_ranges(&self) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Ok(vec![]),
+            Storage::Relational(layout) => layout.check_block_ranges(&self.conn),
+        }
+    }
+
+    /// Resolve overlapping block ranges and return the number of versions
+    /// that were changed or removed
+    pub(crate) fn repair_block_ranges(&self) -> Result<usize, StoreError> {
+        match &*self.storage {
+            Storage::Json(_) => Ok(0),
+            Storage::Relational(layout) => layout.repair_block_ranges(&self.conn),
+        }
+    }
+
     pub(crate) fn update_entity_count(&self, count: i32) -> Result<(), StoreError> {
         if count == 0 {
             return Ok(());
@@ -1031,7 +1110,7 @@ impl JsonStorage {
                        values($1, $2, $3, $4)",
             self.schema
         ))
-        .bind::<Text, _>(&key.entity_type)
+        .bind::<Text, _>(key.entity_type.as_str())
         .bind::<Text, _>(&key.entity_id)
         .bind::<Jsonb, _>(&data)
         .bind::<Text, _>(&event_source)
@@ -1055,7 +1134,7 @@ impl JsonStorage {
             self.schema
         );
         let query = diesel::sql_query(query)
-            .bind::<Text, _>(&key.entity_type)
+            .bind::<Text, _>(key.entity_type.as_str())
             .bind::<Text, _>(&key.entity_id)
             .bind::<Jsonb, _>(data)
             .bind::<Text, _>(event_source);
@@ -1084,7 +1163,7 @@ impl JsonStorage {
             self.schema
         );
         let query = diesel::sql_query(query)
-            .bind::<Text, _>(&key.entity_type)
+            .bind::<Text, _>(key.entity_type.as_str())
             .bind::<Text, _>(&key.entity_id)
             .bind::<Jsonb, _>(data)
             .bind::<Text, _>(&event_source);
@@ -1115,7 +1194,7 @@ impl JsonStorage {
             self.schema
         );
         let query = diesel::sql_query(query)
-            .bind::<Text, _>(&key.entity_type)
+            .bind::<Text, _>(key.entity_type.as_str())
             .bind::<Text, _>(&key.entity_id);
         Ok(query.execute(conn)?)
     }
@@ -1184,7 +1263,7 @@ impl JsonStorage {
         ))
         .bind::<Integer, _>(event_id)
         .bind::<Text, _>(&*history_event.subgraph)
-        .bind::<Text, _>(&key.entity_type)
+        .bind::<Text, _>(key.entity_type.as_str())
         .bind::<Text, _>(&key.entity_id)
         .bind::<Integer, i32>(operation.into())
         .execute(conn)?;
@@ -1234,7 +1313,8 @@ impl JsonStorage {
             | ValueType::Bytes
             | ValueType::BigDecimal
             | ValueType::Int
-            | ValueType::String => (String::from("btree"), String::from(""), "->>"),
+            | ValueType::String
+            | ValueType::Timestamp => (String::from("btree"), String::from(""), "->>"),
             ValueType::List => (String::from("gin"), String::from("jsonb_path_ops"), "->"),
         };
         // Cast between the type we store in JSONB for the field and the type
@@ -1320,7 +1400,7 @@ impl JsonStorage {
             // Perform the actual reversion
             let key = EntityKey {
                 subgraph_id: self.subgraph.clone(),
-                entity_type: history.entity.clone(),
+                entity_type: history.entity.as_str().into(),
                 entity_id: history.entity_id.clone(),
             };
             match history.op {
@@ -1366,6 +1446,7 @@ impl JsonStorage {
                         )))
                     }
                 },
+                changed_fields: None,
             };
             changes.push(change);
         }
@@ -1483,6 +1564,7 @@ pub fn delete_all_entities_for_test_use_only(
 ) -> Result<(), StoreError> {
     // Delete public entities and related data
     diesel::delete(public::event_meta_data::table).execute(conn)?;
+    conn.batch_execute("delete from unused_deployments")?;
     // Delete all subgraph schemas
     for subgraph in public::deployment_schemas::table
         .select(public::deployment_schemas::subgraph)
@@ -1592,8 +1674,7 @@ pub fn create_split_schema(conn: &PgConnection, schema_name: &str) -> Result<(),
 /// Drop the schema for `subgraph`. This deletes all data for the subgraph,
 /// and can not be reversed. It does not remove any of the metadata in
 /// `subgraphs.entities` associated with the subgraph
-#[cfg(debug_assertions)]
-fn drop_schema(
+pub(crate) fn drop_schema(
     conn: &diesel::pg::PgConnection,
     subgraph: &SubgraphDeploymentId,
 ) -> Result<usize, StoreError> {
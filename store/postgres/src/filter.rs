@@ -217,9 +217,11 @@ where
 
         Contains(..) | NotContains(..) => {
             let (attribute, contains, op, value) = match filter {
-                EntityFilter::Contains(attribute, value) => (attribute, true, " LIKE ", value),
+                EntityFilter::Contains(attribute, value) => {
+                    (attribute.to_string(), true, " LIKE ", value)
+                }
                 EntityFilter::NotContains(attribute, value) => {
-                    (attribute, false, " NOT LIKE ", value)
+                    (attribute.to_string(), false, " NOT LIKE ", value)
                 }
                 _ => unreachable!(),
             };
@@ -250,7 +252,8 @@ where
                 | Value::BigDecimal(_)
                 | Value::Int(_)
                 | Value::Bool(_)
-                | Value::BigInt(_) => {
+                | Value::BigInt(_)
+                | Value::Timestamp(_) => {
                     return Err(UnsupportedFilter {
                         filter: if contains { "contains" } else { "not_contains" }.to_owned(),
                         value,
@@ -261,8 +264,8 @@ where
 
         Equal(..) | Not(..) => {
             let (attribute, op, is_negated, value) = match filter {
-                Equal(attribute, value) => (attribute, " = ", false, value),
-                Not(attribute, value) => (attribute, " != ", true, value),
+                Equal(attribute, value) => (attribute.to_string(), " = ", false, value),
+                Not(attribute, value) => (attribute.to_string(), " != ", true, value),
                 _ => unreachable!(),
             };
 
@@ -312,15 +315,17 @@ where
                     )
                 }),
                 Value::String(s) => Ok(s.into_filter(attribute, op)),
+                // Timestamps are stored as ISO-8601 strings in UTC
+                Value::Timestamp(ts) => Ok(ts.to_string().into_filter(attribute, op)),
             }
         }
 
         GreaterThan(..) | LessThan(..) | GreaterOrEqual(..) | LessOrEqual(..) => {
             let (attribute, op, value) = match filter {
-                GreaterThan(attribute, value) => (attribute, " > ", value),
-                LessThan(attribute, value) => (attribute, " < ", value),
-                GreaterOrEqual(attribute, value) => (attribute, " >= ", value),
-                LessOrEqual(attribute, value) => (attribute, " <= ", value),
+                GreaterThan(attribute, value) => (attribute.to_string(), " > ", value),
+                LessThan(attribute, value) => (attribute.to_string(), " < ", value),
+                GreaterOrEqual(attribute, value) => (attribute.to_string(), " >= ", value),
+                LessOrEqual(attribute, value) => (attribute.to_string(), " <= ", value),
                 _ => unreachable!(),
             };
 
@@ -329,6 +334,9 @@ where
                 Value::BigDecimal(n) => Ok(n.into_filter(attribute, op)),
                 Value::Int(n) => Ok(n.into_filter(attribute, op)),
                 Value::String(s) => Ok(s.into_filter(attribute, op)),
+                // ISO-8601 strings in UTC compare like the timestamps they
+                // represent
+                Value::Timestamp(ts) => Ok(ts.to_string().into_filter(attribute, op)),
                 Value::Bool(_) | Value::Bytes(_) | Value::List(_) | Value::Null => {
                     return Err(UnsupportedFilter {
                         filter: op.to_owned(),
@@ -342,6 +350,7 @@ where
             if values.is_empty() {
                 return Ok(false_expr);
             }
+            let attribute = attribute.to_string();
             let op = " = ANY ";
 
             match values[0] {
@@ -357,7 +366,7 @@ where
                 }
                 Value::Int(_) => Ok(SqlValue::new_array(values)
                     .into_array_filter::<Integer>(attribute, op, "::int")),
-                Value::String(_) => {
+                Value::String(_) | Value::Timestamp(_) => {
                     Ok(SqlValue::new_array(values).into_array_filter::<Text>(attribute, op, ""))
                 }
                 Value::List(_) | Value::Null => {
@@ -382,8 +391,8 @@ where
 
         StartsWith(..) | NotStartsWith(..) => {
             let (attribute, op, value) = match filter {
-                StartsWith(attribute, value) => (attribute, " LIKE ", value),
-                NotStartsWith(attribute, value) => (attribute, " NOT LIKE ", value),
+                StartsWith(attribute, value) => (attribute.to_string(), " LIKE ", value),
+                NotStartsWith(attribute, value) => (attribute.to_string(), " NOT LIKE ", value),
                 _ => unreachable!(),
             };
 
@@ -394,6 +403,7 @@ where
                 | Value::Bytes(_)
                 | Value::BigDecimal(_)
                 | Value::Int(_)
+                | Value::Timestamp(_)
                 | Value::List(_)
                 | Value::Null => {
                     return Err(UnsupportedFilter {
@@ -411,8 +421,8 @@ where
 
         EndsWith(..) | NotEndsWith(..) => {
             let (attribute, op, value) = match filter {
-                EndsWith(attribute, value) => (attribute, " LIKE ", value),
-                NotEndsWith(attribute, value) => (attribute, " NOT LIKE ", value),
+                EndsWith(attribute, value) => (attribute.to_string(), " LIKE ", value),
+                NotEndsWith(attribute, value) => (attribute.to_string(), " NOT LIKE ", value),
                 _ => unreachable!(),
             };
 
@@ -423,6 +433,7 @@ where
                 | Value::Bytes(_)
                 | Value::BigDecimal(_)
                 | Value::Int(_)
+                | Value::Timestamp(_)
                 | Value::List(_)
                 | Value::Null => {
                     return Err(UnsupportedFilter {
@@ -47,7 +47,7 @@ impl<'a> FilterQuery<'a> {
                 ValueType::Boolean => "::boolean",
                 ValueType::Bytes => "",
                 ValueType::Int => "::bigint",
-                ValueType::String => "",
+                ValueType::String | ValueType::Timestamp => "",
                 ValueType::List => {
                     return Err(QueryExecutionError::OrderByNotSupportedForType(
                         "List".to_string(),
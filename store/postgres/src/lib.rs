@@ -20,6 +20,10 @@ extern crate postgres;
 extern crate serde;
 extern crate uuid;
 
+// Must come first so that its macros are visible in the other modules
+#[macro_use]
+pub mod transact_metrics;
+
 mod block_range;
 mod catalog;
 mod chain_head_listener;
@@ -48,6 +52,11 @@ pub mod db_schema_for_tests {
     pub use crate::db_schema::ethereum_networks;
 }
 
+#[cfg(debug_assertions)]
+pub mod notification_listener_for_tests {
+    pub use crate::notification_listener::*;
+}
+
 #[cfg(debug_assertions)]
 pub mod layout_for_tests {
     pub use crate::block_range::*;
@@ -58,5 +67,5 @@ pub mod layout_for_tests {
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
 pub use self::network_store::NetworkStore;
-pub use self::store::{Store, StoreConfig};
+pub use self::store::{ConsistencyPolicy, Store, StoreConfig};
 pub use self::store_events::SubscriptionManager;
@@ -1,17 +1,22 @@
 //! Utilities for dealing with subgraph metadata
 use diesel::dsl::{sql, update};
-use diesel::pg::PgConnection;
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::{
     ExpressionMethods, JoinOnDsl, NullableExpressionMethods, OptionalExtension, QueryDsl,
     RunQueryDsl,
 };
+use diesel::sql_types::{BigInt, Binary, Bool, Double, Integer, Nullable, Text};
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::convert::TryFrom;
+use std::time::Duration;
 
-use graph::data::subgraph::schema::{SubgraphManifestEntity, SUBGRAPHS_ID};
+use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity, SUBGRAPHS_ID};
 use graph::prelude::{
     bigdecimal::ToPrimitive, format_err, web3::types::H256, BigDecimal, BlockNumber,
-    DeploymentState, EntityChange, EntityChangeOperation, EthereumBlockPointer, Schema, StoreError,
-    StoreEvent, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphName, TypedEntity,
+    DeploymentFlag, DeploymentState, EntityChange, EntityChangeOperation, EthereumBlockPointer,
+    GraftBase, GraftLineage, HealthIssue, HealthIssueKind, NodeId, ScanWatermark, Schema,
+    StoreError, StoreEvent, SubgraphDeploymentEntity, SubgraphDeploymentId, SubgraphName,
+    TypedEntity,
 };
 
 // Diesel tables for some of the metadata
@@ -113,7 +118,30 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(subgraph, subgraph_version, subgraph_deployment);
+table! {
+    subgraphs.subgraph_error (vid) {
+        vid -> BigInt,
+        id -> Text,
+        subgraph_id -> Text,
+        message -> Text,
+        block_number -> Nullable<Numeric>,
+        block_hash -> Nullable<Binary>,
+        handler -> Nullable<Text>,
+        deterministic -> Bool,
+        message_hash -> Nullable<Binary>,
+        count -> Integer,
+        last_block_number -> Nullable<Numeric>,
+        last_block_hash -> Nullable<Binary>,
+        block_range -> Range<Integer>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+    subgraph,
+    subgraph_version,
+    subgraph_deployment,
+    subgraph_error
+);
 
 /// Look up the graft point for the given subgraph in the database and
 /// return it
@@ -127,28 +155,119 @@ pub fn deployment_graft(
         // There is no SubgraphDeployment for the metadata subgraph
         Ok(None)
     } else {
-        match sd::table
+        graft_from_row(
+            sd::table
+                .select((sd::graft_base, sd::graft_block_hash, sd::graft_block_number))
+                .filter(sd::id.eq(id.as_str()))
+                .first::<GraftRow>(conn)?,
+        )
+    }
+}
+
+type GraftRow = (Option<String>, Option<Vec<u8>>, Option<BigDecimal>);
+
+fn graft_from_row(
+    row: GraftRow,
+) -> Result<Option<(SubgraphDeploymentId, EthereumBlockPointer)>, StoreError> {
+    match row {
+        (None, None, None) => Ok(None),
+        (Some(subgraph), Some(hash), Some(block)) => {
+            let hash = H256::from_slice(hash.as_slice());
+            let block = block.to_u64().expect("block numbers fit into a u64");
+            let subgraph = SubgraphDeploymentId::new(subgraph.clone()).map_err(|_| {
+                StoreError::Unknown(format_err!(
+                    "the base subgraph for a graft must be a valid subgraph id but is `{}`",
+                    subgraph
+                ))
+            })?;
+            Ok(Some((subgraph, EthereumBlockPointer::from((hash, block)))))
+        }
+        _ => unreachable!(
+            "graftBlockHash and graftBlockNumber are either both set or neither is set"
+        ),
+    }
+}
+
+/// The deployments that are grafted directly onto `id`
+pub fn graft_dependents(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    use subgraph_deployment as sd;
+
+    sd::table
+        .select(sd::id)
+        .filter(sd::graft_base.eq(id.as_str()))
+        .order(sd::id)
+        .load::<String>(conn)?
+        .into_iter()
+        .map(|id| {
+            SubgraphDeploymentId::new(id).map_err(|id| {
+                StoreError::ConstraintViolation(format!("illegal deployment id `{}`", id))
+            })
+        })
+        .collect()
+}
+
+/// The deployments that `id` is grafted onto, following grafts until we
+/// reach a deployment that is not grafted or that does not exist anymore,
+/// and the deployments that are grafted onto `id`, directly or through
+/// other deployments. Return `None` if `id` does not exist. Grafts can not
+/// form a cycle, but in case the metadata says they do, we return an error
+/// rather than following the cycle forever
+pub fn graft_lineage(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<GraftLineage>, StoreError> {
+    use subgraph_deployment as sd;
+
+    let graft = |id: &SubgraphDeploymentId| -> Result<Option<GraftRow>, StoreError> {
+        Ok(sd::table
             .select((sd::graft_base, sd::graft_block_hash, sd::graft_block_number))
             .filter(sd::id.eq(id.as_str()))
-            .first::<(Option<String>, Option<Vec<u8>>, Option<BigDecimal>)>(conn)?
-        {
-            (None, None, None) => Ok(None),
-            (Some(subgraph), Some(hash), Some(block)) => {
-                let hash = H256::from_slice(hash.as_slice());
-                let block = block.to_u64().expect("block numbers fit into a u64");
-                let subgraph = SubgraphDeploymentId::new(subgraph.clone()).map_err(|_| {
-                    StoreError::Unknown(format_err!(
-                        "the base subgraph for a graft must be a valid subgraph id but is `{}`",
-                        subgraph
-                    ))
-                })?;
-                Ok(Some((subgraph, EthereumBlockPointer::from((hash, block)))))
+            .first::<GraftRow>(conn)
+            .optional()?)
+    };
+
+    let mut next = match graft(id)? {
+        Some(row) => graft_from_row(row)?,
+        None => return Ok(None),
+    };
+    let mut seen = HashSet::new();
+    seen.insert(id.clone());
+    let mut bases = Vec::new();
+    while let Some((base, block)) = next {
+        if !seen.insert(base.clone()) {
+            return Err(StoreError::ConstraintViolation(format!(
+                "the grafts of deployment `{}` form a cycle through `{}`",
+                id, base
+            )));
+        }
+        next = match graft(&base)? {
+            Some(row) => graft_from_row(row)?,
+            None => None,
+        };
+        bases.push(GraftBase {
+            deployment: base,
+            block,
+        });
+    }
+
+    // Since every deployment is grafted onto at most one other deployment,
+    // and `id` is not part of a cycle, the dependents form a tree
+    let mut dependents = Vec::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(id.clone());
+    while let Some(base) = queue.pop_front() {
+        for dependent in graft_dependents(conn, &base)? {
+            if seen.insert(dependent.clone()) {
+                dependents.push(dependent.clone());
+                queue.push_back(dependent);
             }
-            _ => unreachable!(
-                "graftBlockHash and graftBlockNumber are either both set or neither is set"
-            ),
         }
     }
+
+    Ok(Some(GraftLineage { bases, dependents }))
 }
 
 pub fn subgraph_schema(
@@ -169,7 +288,10 @@ pub fn subgraph_schema(
             .first(conn)?;
         Schema::parse(s.as_str(), id)
     };
-    res.map_err(|e| StoreError::Unknown(e))
+    // The schemas in the database were validated when they were deployed
+    let schema = res.map_err(|e| StoreError::Unknown(e))?;
+    schema.intern_names();
+    Ok(schema)
 }
 
 pub fn subgraph_network(
@@ -205,6 +327,7 @@ fn block_ptr_store_event(id: &SubgraphDeploymentId) -> StoreEvent {
         entity_id: id.to_string(),
         subgraph_id: SUBGRAPHS_ID.to_owned(),
         operation: EntityChangeOperation::Set,
+        changed_fields: None,
     };
     StoreEvent::new(vec![change])
 }
@@ -294,6 +417,136 @@ fn latest_as_block_number(
     }
 }
 
+/// Return the error that made the deployment `id` fail, if it has failed,
+/// and whether it has encountered any non-fatal errors
+fn deployment_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(Option<SubgraphError>, bool), StoreError> {
+    use subgraph_deployment as d;
+    use subgraph_error as e;
+
+    let (non_fatal_errors, message, block_number, block_hash, handler, deterministic, count) =
+        d::table
+            .left_outer_join(e::table.on(d::fatal_error.eq(e::id.nullable())))
+            .filter(d::id.eq(id.as_str()))
+            .select((
+                d::non_fatal_errors,
+                e::message.nullable(),
+                e::block_number.nullable(),
+                e::block_hash.nullable(),
+                e::handler.nullable(),
+                e::deterministic.nullable(),
+                e::count.nullable(),
+            ))
+            .first::<(
+                Vec<String>,
+                Option<String>,
+                Option<BigDecimal>,
+                Option<Vec<u8>>,
+                Option<String>,
+                Option<bool>,
+                Option<i32>,
+            )>(conn)?;
+
+    let fatal_error = message.map(|message| {
+        let block_ptr = match (block_number, block_hash) {
+            (Some(number), Some(hash)) => Some(EthereumBlockPointer::from((
+                H256::from_slice(hash.as_slice()),
+                number.to_u64().expect("block numbers fit into a u64"),
+            ))),
+            _ => None,
+        };
+        SubgraphError {
+            subgraph_id: id.clone(),
+            message,
+            block_ptr,
+            handler,
+            deterministic: deterministic.unwrap_or(false),
+            count: count.unwrap_or(1) as u32,
+        }
+    });
+    Ok((fatal_error, !non_fatal_errors.is_empty()))
+}
+
+#[derive(QueryableByName)]
+struct LastError {
+    #[sql_type = "Text"]
+    id: String,
+    #[sql_type = "Nullable<Binary>"]
+    message_hash: Option<Vec<u8>>,
+    #[sql_type = "Nullable<Text>"]
+    handler: Option<String>,
+    #[sql_type = "Bool"]
+    deterministic: bool,
+    #[sql_type = "Integer"]
+    count: i32,
+}
+
+/// If `error` is the same as the last error of the deployment `id`, return
+/// the id of that error and how many times in a row it has happened. Errors
+/// are the same if they have the same message, handler and determinism; the
+/// block in which they happened does not matter
+pub fn repeated_error(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    error: &SubgraphError,
+) -> Result<Option<(String, u32)>, StoreError> {
+    let last = diesel::sql_query(
+        "select id, message_hash, handler, deterministic, count
+           from subgraphs.subgraph_error
+          where subgraph_id = $1
+          order by coalesce(last_block_number, block_number) desc nulls last, vid desc
+          limit 1",
+    )
+    .bind::<Text, _>(id.as_str())
+    .get_result::<LastError>(conn)
+    .optional()?;
+
+    let message_hash = error.message_hash();
+    Ok(last
+        .filter(|last| {
+            last.message_hash.as_deref() == Some(message_hash.as_bytes())
+                && last.handler == error.handler
+                && last.deterministic == error.deterministic
+        })
+        .map(|last| (last.id, last.count as u32)))
+}
+
+/// Remove the errors of the deployment `id` that happened after `ptr`.
+/// Errors that happened repeatedly until after `ptr` lose one occurrence,
+/// and are assumed to have happened last in `ptr`, which is exact for
+/// errors that happen in every block
+pub fn revert_errors(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    ptr: EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    let number = ptr.number as i64;
+
+    diesel::sql_query(
+        "delete from subgraphs.subgraph_error
+          where subgraph_id = $1 and block_number > $2",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<BigInt, _>(number)
+    .execute(conn)?;
+
+    // When only one occurrence remains, it happened in `block_number`
+    diesel::sql_query(
+        "update subgraphs.subgraph_error
+            set count = count - 1,
+                last_block_number = case when count > 2 then $2 end,
+                last_block_hash = case when count > 2 then $3 end
+          where subgraph_id = $1 and last_block_number > $2",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<BigInt, _>(number)
+    .bind::<Binary, _>(ptr.hash.as_bytes())
+    .execute(conn)?;
+    Ok(())
+}
+
 pub fn deployment_state_from_name(
     conn: &PgConnection,
     name: SubgraphName,
@@ -361,11 +614,18 @@ pub fn deployment_state_from_name(
                     convert_to_u32(max_reorg_depth, "max_reorg_depth", name.as_str())?;
                 let latest_ethereum_block_number =
                     latest_as_block_number(latest_ethereum_block_number, name.as_str())?;
+                let (fatal_error, has_non_fatal_errors) = deployment_errors(conn, &id)?;
+                let earliest_block_number = earliest_block(conn, &id)?;
+                let flag = deployment_flag(conn, &id)?;
                 Ok(DeploymentState {
                     id,
                     reorg_count,
                     max_reorg_depth,
                     latest_ethereum_block_number,
+                    fatal_error,
+                    has_non_fatal_errors,
+                    earliest_block_number,
+                    flag,
                 })
             }
         }
@@ -399,13 +659,777 @@ pub fn deployment_state_from_id(
                 convert_to_u32(Some(max_reorg_depth), "max_reorg_depth", id.as_str())?;
             let latest_ethereum_block_number =
                 latest_as_block_number(latest_ethereum_block_number, id.as_str())?;
+            let (fatal_error, has_non_fatal_errors) = deployment_errors(conn, &id)?;
+            let earliest_block_number = earliest_block(conn, &id)?;
+            let flag = deployment_flag(conn, &id)?;
 
             Ok(DeploymentState {
                 id,
                 reorg_count,
                 max_reorg_depth,
                 latest_ethereum_block_number,
+                fatal_error,
+                has_non_fatal_errors,
+                earliest_block_number,
+                flag,
             })
         }
     }
 }
+
+/// Deployments that must never be removed as unused: those that a subgraph
+/// name refers to as its current or pending version, those that are
+/// assigned to a node, and those that another deployment is grafted onto.
+/// Removing unused deployments therefore removes a chain of grafts from
+/// the last deployment in the chain to the first. Meant to be used as the
+/// beginning of a query that can then refer to `protected`
+const PROTECTED_DEPLOYMENTS: &str = "
+    with protected as (
+      select v.deployment as id
+        from subgraphs.subgraph s, subgraphs.subgraph_version v
+       where v.id = s.current_version or v.id = s.pending_version
+      union
+      select a.id
+        from subgraphs.subgraph_deployment_assignment a
+      union
+      select d.graft_base as id
+        from subgraphs.subgraph_deployment d
+       where d.graft_base is not null)";
+
+/// The metadata tables that hold the manifest and dynamic data sources of
+/// a deployment. The ids of their entries start with the id of the
+/// deployment or of one of its dynamic data sources
+/// See also: ed42d219c6704a4aab57ce1ea66698e7
+const DATA_SOURCE_TABLES: &[&str] = &[
+    "subgraph_manifest",
+    "ethereum_block_handler_entity",
+    "ethereum_block_handler_filter_entity",
+    "ethereum_call_handler_entity",
+    "ethereum_contract_abi",
+    "ethereum_contract_data_source",
+    "ethereum_contract_data_source_template",
+    "ethereum_contract_data_source_template_source",
+    "ethereum_contract_event_handler",
+    "ethereum_contract_mapping",
+    "ethereum_contract_source",
+];
+
+#[derive(QueryableByName)]
+struct UnusedDeployment {
+    #[sql_type = "Text"]
+    deployment: String,
+}
+
+fn unused_deployment_ids(
+    deployments: Vec<UnusedDeployment>,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    deployments
+        .into_iter()
+        .map(|d| {
+            SubgraphDeploymentId::new(d.deployment.clone()).map_err(|_| {
+                StoreError::Unknown(format_err!("illegal deployment id {}", d.deployment))
+            })
+        })
+        .collect()
+}
+
+/// The ids of all deployments except for the subgraph of subgraphs
+pub fn deployment_ids(conn: &PgConnection) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    unused_deployment_ids(
+        diesel::sql_query(
+            "select subgraph as deployment from deployment_schemas where subgraph != $1",
+        )
+        .bind::<Text, _>(SUBGRAPHS_ID.as_str())
+        .load::<UnusedDeployment>(conn)?,
+    )
+}
+
+/// Note that the deployment `id` was used just now
+pub fn deployment_used(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<(), StoreError> {
+    diesel::sql_query("update deployment_schemas set last_used = now() where subgraph = $1")
+        .bind::<Text, _>(id.as_str())
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget about deployments that were recorded as unused but that have
+/// been used since, or that are protected now, and return their ids
+pub fn unrecord_used_deployments(
+    conn: &PgConnection,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    let query = format!(
+        "{}
+         delete from unused_deployments u
+          using deployment_schemas ds
+          where ds.subgraph = u.deployment
+            and (ds.last_used > u.unused_at
+                 or u.deployment in (select id from protected))
+         returning u.deployment",
+        PROTECTED_DEPLOYMENTS
+    );
+    unused_deployment_ids(diesel::sql_query(query).load::<UnusedDeployment>(conn)?)
+}
+
+/// Record all deployments that are not protected and that have not been
+/// used for `grace_period` as unused, and return the ids of the ones that
+/// were not recorded before
+pub fn record_unused_deployments(
+    conn: &PgConnection,
+    grace_period: Duration,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    let query = format!(
+        "{}
+         insert into unused_deployments(deployment)
+         select ds.subgraph
+           from deployment_schemas ds
+          where ds.subgraph != $1
+            and ds.last_used < now() - make_interval(secs => $2)
+            and ds.subgraph not in (select id from protected)
+         on conflict(deployment) do nothing
+         returning deployment",
+        PROTECTED_DEPLOYMENTS
+    );
+    unused_deployment_ids(
+        diesel::sql_query(query)
+            .bind::<Text, _>(SUBGRAPHS_ID.as_str())
+            .bind::<Double, _>(grace_period.as_secs_f64())
+            .load::<UnusedDeployment>(conn)?,
+    )
+}
+
+/// The deployments that have been recorded as unused for longer than
+/// `delay` and that are still unused and not protected
+pub fn removable_deployments(
+    conn: &PgConnection,
+    delay: Duration,
+) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+    let query = format!(
+        "{}
+         select u.deployment
+           from unused_deployments u, deployment_schemas ds
+          where ds.subgraph = u.deployment
+            and ds.last_used <= u.unused_at
+            and u.unused_at < now() - make_interval(secs => $1)
+            and u.deployment not in (select id from protected)
+          order by u.unused_at",
+        PROTECTED_DEPLOYMENTS
+    );
+    unused_deployment_ids(
+        diesel::sql_query(query)
+            .bind::<Double, _>(delay.as_secs_f64())
+            .load::<UnusedDeployment>(conn)?,
+    )
+}
+
+/// Take `id` off the list of unused deployments, and mark it as used so
+/// that it does not get recorded again right away. Return `false` if `id`
+/// was not recorded as unused
+pub fn restore_unused_deployment(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<bool, StoreError> {
+    let count = diesel::sql_query("delete from unused_deployments where deployment = $1")
+        .bind::<Text, _>(id.as_str())
+        .execute(conn)?;
+    deployment_used(conn, id)?;
+    Ok(count > 0)
+}
+
+/// Delete all metadata for the deployment `id`, including its entry in the
+/// list of unused deployments. This can not be undone
+pub fn drop_deployment_metadata(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    for table in DATA_SOURCE_TABLES {
+        let query = format!(
+            "delete from subgraphs.{} e
+              where left(e.id, length($1) + 1) = $1 || '-'
+                 or left(e.id, 40) in
+                    (select dds.id
+                       from subgraphs.dynamic_ethereum_contract_data_source dds
+                      where dds.deployment = $1)",
+            table
+        );
+        diesel::sql_query(query)
+            .bind::<Text, _>(id.as_str())
+            .execute(conn)?;
+    }
+
+    const STATEMENTS: &[&str] = &[
+        "delete from subgraphs.dynamic_ethereum_contract_data_source where deployment = $1",
+        "delete from subgraphs.subgraph_error where subgraph_id = $1",
+        "delete from subgraphs.subgraph_version where deployment = $1",
+        "delete from subgraphs.subgraph_deployment_assignment where id = $1",
+        "delete from subgraphs.subgraph_deployment where id = $1",
+        "delete from unused_deployments where deployment = $1",
+    ];
+    for statement in STATEMENTS {
+        diesel::sql_query(*statement)
+            .bind::<Text, _>(id.as_str())
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct MemoryLimit {
+    #[sql_type = "Nullable<BigInt>"]
+    max_memory: Option<i64>,
+}
+
+/// The memory limit in bytes for the handlers of `id` if it was changed
+/// from the default
+pub fn memory_limit(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<u64>, StoreError> {
+    Ok(
+        diesel::sql_query("select max_memory from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .get_result::<MemoryLimit>(conn)
+            .optional()?
+            .and_then(|limit| limit.max_memory)
+            .map(|limit| limit as u64),
+    )
+}
+
+/// Set the memory limit in bytes for the handlers of `id`, or reset it to
+/// the default if `limit` is `None`. Return `false` if `id` does not exist
+pub fn set_memory_limit(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    limit: Option<u64>,
+) -> Result<bool, StoreError> {
+    let limit = limit
+        .map(|limit| {
+            i64::try_from(limit)
+                .map_err(|_| StoreError::Unknown(format_err!("memory limit {} is too big", limit)))
+        })
+        .transpose()?;
+    let count =
+        diesel::sql_query("update deployment_schemas set max_memory = $2 where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .bind::<Nullable<BigInt>, _>(limit)
+            .execute(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(QueryableByName)]
+struct FlagRow {
+    #[sql_type = "Text"]
+    subgraph: String,
+    #[sql_type = "Text"]
+    flag_note: String,
+    #[sql_type = "BigInt"]
+    flagged_at: i64,
+}
+
+impl FlagRow {
+    fn into_flag(self) -> DeploymentFlag {
+        DeploymentFlag {
+            note: self.flag_note,
+            flagged_at: self.flagged_at.max(0) as u64,
+        }
+    }
+}
+
+const FLAG_QUERY: &str = "
+    select subgraph, flag_note, extract(epoch from flagged_at)::int8 as flagged_at
+      from deployment_schemas
+     where flag_note is not null";
+
+/// The flag an operator put on `id`, if any
+fn deployment_flag(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<DeploymentFlag>, StoreError> {
+    Ok(
+        diesel::sql_query(format!("{} and subgraph = $1", FLAG_QUERY))
+            .bind::<Text, _>(id.as_str())
+            .get_result::<FlagRow>(conn)
+            .optional()?
+            .map(FlagRow::into_flag),
+    )
+}
+
+/// All deployments that an operator flagged as unhealthy
+pub fn flagged_deployments(
+    conn: &PgConnection,
+) -> Result<BTreeMap<SubgraphDeploymentId, DeploymentFlag>, StoreError> {
+    diesel::sql_query(FLAG_QUERY)
+        .load::<FlagRow>(conn)?
+        .into_iter()
+        .map(|row| {
+            let id = SubgraphDeploymentId::new(row.subgraph.clone()).map_err(|id| {
+                StoreError::ConstraintViolation(format!("illegal deployment id `{}`", id))
+            })?;
+            Ok((id, row.into_flag()))
+        })
+        .collect()
+}
+
+/// Flag `id` with `note`, or remove its flag if `note` is `None`. Return
+/// `false` if `id` does not exist
+pub fn set_deployment_flag(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    note: Option<String>,
+) -> Result<bool, StoreError> {
+    let count = diesel::sql_query(
+        "update deployment_schemas
+            set flag_note = $2,
+                flagged_at = case when $2 is null then null else now() end
+          where subgraph = $1",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<Nullable<Text>, _>(note)
+    .execute(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(QueryableByName)]
+struct EarliestBlock {
+    #[sql_type = "Integer"]
+    earliest_block: i32,
+}
+
+/// The earliest block that can be queried for `id`. Entity versions that
+/// were no longer current at that block have been pruned
+pub fn earliest_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<BlockNumber, StoreError> {
+    Ok(
+        diesel::sql_query("select earliest_block from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .get_result::<EarliestBlock>(conn)
+            .optional()?
+            .map(|earliest| earliest.earliest_block)
+            .unwrap_or(0),
+    )
+}
+
+/// Set how many blocks of history `id` keeps, or keep all of it if
+/// `history_blocks` is `None`. Return `false` if `id` does not exist
+pub fn set_history_blocks(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    history_blocks: Option<BlockNumber>,
+) -> Result<bool, StoreError> {
+    let count =
+        diesel::sql_query("update deployment_schemas set history_blocks = $2 where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .bind::<Nullable<Integer>, _>(history_blocks)
+            .execute(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(QueryableByName)]
+struct ScanWatermarkRow {
+    #[sql_type = "Integer"]
+    scanned_block: BlockNumber,
+    #[sql_type = "Integer"]
+    scanned_up_to: BlockNumber,
+}
+
+/// The blocks that the block stream of `id` recorded as scanned
+pub fn scan_watermark(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<ScanWatermark>, StoreError> {
+    Ok(diesel::sql_query(
+        "select scanned_block, scanned_up_to
+           from deployment_schemas
+          where subgraph = $1
+            and scanned_block is not null
+            and scanned_up_to is not null",
+    )
+    .bind::<Text, _>(id.as_str())
+    .get_result::<ScanWatermarkRow>(conn)
+    .optional()?
+    .map(|row| ScanWatermark {
+        block: row.scanned_block,
+        scanned_up_to: row.scanned_up_to,
+    }))
+}
+
+pub fn set_scan_watermark(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    watermark: ScanWatermark,
+) -> Result<(), StoreError> {
+    diesel::sql_query(
+        "update deployment_schemas
+            set scanned_block = $2, scanned_up_to = $3
+          where subgraph = $1",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<Integer, _>(watermark.block)
+    .bind::<Integer, _>(watermark.scanned_up_to)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Forget which blocks were scanned, since they were scanned for fewer
+/// triggers than the block stream needs to look for now
+pub fn clear_scan_watermark(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<(), StoreError> {
+    diesel::sql_query(
+        "update deployment_schemas
+            set scanned_block = null, scanned_up_to = null
+          where subgraph = $1",
+    )
+    .bind::<Text, _>(id.as_str())
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Forget that blocks after `ptr` were scanned, since the blocks with
+/// those numbers might be different ones after a reorg
+pub fn revert_scan_watermark(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    ptr: EthereumBlockPointer,
+) -> Result<(), StoreError> {
+    diesel::sql_query(
+        "update deployment_schemas
+            set scanned_up_to = least(scanned_up_to, $2)
+          where subgraph = $1",
+    )
+    .bind::<Text, _>(id.as_str())
+    .bind::<Integer, _>(ptr.number as BlockNumber)
+    .execute(conn)?;
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct StrictBytes {
+    #[sql_type = "Bool"]
+    strict_bytes: bool,
+}
+
+/// Return whether `id` uses strict bytes handling; deployments that do not
+/// exist do not
+pub fn strict_bytes(conn: &PgConnection, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+    Ok(
+        diesel::sql_query("select strict_bytes from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .get_result::<StrictBytes>(conn)
+            .optional()?
+            .map(|row| row.strict_bytes)
+            .unwrap_or(false),
+    )
+}
+
+/// Turn strict bytes handling for `id` on or off. Return `false` if `id`
+/// does not exist
+pub fn set_strict_bytes(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    strict_bytes: bool,
+) -> Result<bool, StoreError> {
+    let count =
+        diesel::sql_query("update deployment_schemas set strict_bytes = $2 where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .bind::<Bool, _>(strict_bytes)
+            .execute(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(QueryableByName)]
+struct ComputedModule {
+    #[sql_type = "Nullable<Binary>"]
+    computed_module: Option<Vec<u8>>,
+}
+
+/// Return the WASM module that computes the `@computed` fields of `id`;
+/// deployments that do not exist do not have one
+pub fn computed_module(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+) -> Result<Option<Vec<u8>>, StoreError> {
+    Ok(
+        diesel::sql_query("select computed_module from deployment_schemas where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .get_result::<ComputedModule>(conn)
+            .optional()?
+            .and_then(|row| row.computed_module),
+    )
+}
+
+/// Set the WASM module that computes the `@computed` fields of `id`.
+/// Return `false` if `id` does not exist
+pub fn set_computed_module(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    module: &[u8],
+) -> Result<bool, StoreError> {
+    let count =
+        diesel::sql_query("update deployment_schemas set computed_module = $2 where subgraph = $1")
+            .bind::<Text, _>(id.as_str())
+            .bind::<Binary, _>(module)
+            .execute(conn)?;
+    Ok(count > 0)
+}
+
+#[derive(QueryableByName)]
+struct PrunableDeployment {
+    #[sql_type = "Text"]
+    deployment: String,
+    #[sql_type = "Integer"]
+    earliest_block: i32,
+}
+
+/// The deployments assigned to `node` that keep a limited amount of
+/// history and that have more history than that, together with the block
+/// before which their history should be pruned. Only deployments that are
+/// synced and use relational storage are pruned
+pub fn prunable_deployments(
+    conn: &PgConnection,
+    node: &NodeId,
+) -> Result<Vec<(SubgraphDeploymentId, BlockNumber)>, StoreError> {
+    let query = "
+        select ds.subgraph as deployment,
+               (d.latest_ethereum_block_number - ds.history_blocks)::int4 as earliest_block
+          from deployment_schemas ds,
+               subgraphs.subgraph_deployment d,
+               subgraphs.subgraph_deployment_assignment a
+         where ds.subgraph = d.id
+           and ds.subgraph = a.id
+           and a.node_id = $1
+           and ds.version = 'relational'
+           and ds.history_blocks is not null
+           and d.synced
+           and d.latest_ethereum_block_number - ds.history_blocks > ds.earliest_block";
+    diesel::sql_query(query)
+        .bind::<Text, _>(node.as_str())
+        .load::<PrunableDeployment>(conn)?
+        .into_iter()
+        .map(|d| {
+            SubgraphDeploymentId::new(d.deployment.clone())
+                .map(|id| (id, d.earliest_block))
+                .map_err(|_| {
+                    StoreError::Unknown(format_err!("illegal deployment id {}", d.deployment))
+                })
+        })
+        .collect()
+}
+
+#[derive(QueryableByName)]
+struct DeploymentHead {
+    #[sql_type = "Text"]
+    deployment: String,
+    #[sql_type = "Integer"]
+    head: i32,
+}
+
+/// The deployments assigned to `node` that use relational storage and
+/// have processed a block, together with the number of their latest block.
+/// Entities of `@ttl` types in these deployments can expire
+pub fn expirable_deployments(
+    conn: &PgConnection,
+    node: &NodeId,
+) -> Result<Vec<(SubgraphDeploymentId, BlockNumber)>, StoreError> {
+    let query = "
+        select ds.subgraph as deployment,
+               d.latest_ethereum_block_number::int4 as head
+          from deployment_schemas ds,
+               subgraphs.subgraph_deployment d,
+               subgraphs.subgraph_deployment_assignment a
+         where ds.subgraph = d.id
+           and ds.subgraph = a.id
+           and a.node_id = $1
+           and ds.version = 'relational'
+           and d.latest_ethereum_block_number is not null";
+    diesel::sql_query(query)
+        .bind::<Text, _>(node.as_str())
+        .load::<DeploymentHead>(conn)?
+        .into_iter()
+        .map(|d| {
+            SubgraphDeploymentId::new(d.deployment.clone())
+                .map(|id| (id, d.head))
+                .map_err(|_| {
+                    StoreError::Unknown(format_err!("illegal deployment id {}", d.deployment))
+                })
+        })
+        .collect()
+}
+
+/// Record that `id` can only be queried from block `earliest` on. The
+/// earliest block in the deployment's status is also changed so that it
+/// shows that history was pruned; its hash is `None` if we do not know it
+pub fn set_earliest_block(
+    conn: &PgConnection,
+    id: &SubgraphDeploymentId,
+    earliest: BlockNumber,
+    hash: Option<H256>,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    diesel::sql_query("update deployment_schemas set earliest_block = $2 where subgraph = $1")
+        .bind::<Text, _>(id.as_str())
+        .bind::<Integer, _>(earliest)
+        .execute(conn)?;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", earliest);
+    update(d::table.filter(d::id.eq(id.as_str())))
+        .set((
+            d::earliest_ethereum_block_number.eq(sql(&number)),
+            d::earliest_ethereum_block_hash.eq(hash.as_ref().map(|hash| hash.as_bytes())),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct HealthIssueRow {
+    #[sql_type = "Text"]
+    kind: String,
+    #[sql_type = "Nullable<Text>"]
+    deployment: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    subgraph_name: Option<String>,
+    #[sql_type = "Text"]
+    message: String,
+    #[sql_type = "Text"]
+    remediation: String,
+}
+
+impl HealthIssueRow {
+    fn into_issue(self) -> Result<HealthIssue, StoreError> {
+        Ok(HealthIssue {
+            kind: self.kind.parse().map_err(StoreError::Unknown)?,
+            deployment: self.deployment,
+            subgraph_name: self.subgraph_name,
+            message: self.message,
+            remediation: self.remediation.parse().map_err(StoreError::Unknown)?,
+        })
+    }
+}
+
+/// For each kind of health issue, the query that finds all issues of that
+/// kind. Each query returns the affected deployment and subgraph name, and
+/// a message that describes the issue. The query for schemas without a
+/// deployment takes the id of the subgraph of subgraphs as `$1`, since
+/// that is the one schema that never has a deployment
+const HEALTH_CHECKS: &[(HealthIssueKind, &str)] = &[
+    (
+        HealthIssueKind::AssignmentWithoutDeployment,
+        "select a.id as deployment, null::text as subgraph_name,
+                'deployment ' || a.id || ' is assigned to node ' || a.node_id
+                  || ' but does not exist' as message
+           from subgraphs.subgraph_deployment_assignment a
+          where not exists (select 1 from subgraphs.subgraph_deployment d
+                             where d.id = a.id)",
+    ),
+    (
+        HealthIssueKind::DeploymentWithoutSchema,
+        "select d.id as deployment, null::text as subgraph_name,
+                'deployment ' || d.id || ' has no database schema for its data' as message
+           from subgraphs.subgraph_deployment d
+          where not exists (select 1 from deployment_schemas ds
+                             where ds.subgraph = d.id)",
+    ),
+    (
+        HealthIssueKind::SchemaWithoutDeployment,
+        "select ds.subgraph::text as deployment, null::text as subgraph_name,
+                'database schema ' || ds.name || ' holds data for deployment '
+                  || ds.subgraph || ' which does not exist' as message
+           from deployment_schemas ds
+          where ds.subgraph != $1
+            and not exists (select 1 from subgraphs.subgraph_deployment d
+                             where d.id = ds.subgraph)",
+    ),
+    (
+        HealthIssueKind::VersionWithoutDeployment,
+        "select v.deployment, s.name as subgraph_name,
+                'version ' || v.id || ' of subgraph ' || coalesce(s.name, v.subgraph)
+                  || ' uses deployment ' || v.deployment || ' which does not exist' as message
+           from subgraphs.subgraph_version v
+                left join subgraphs.subgraph s on s.id = v.subgraph
+          where not exists (select 1 from subgraphs.subgraph_deployment d
+                             where d.id = v.deployment)",
+    ),
+    (
+        HealthIssueKind::NameWithoutVersion,
+        "select null::text as deployment, s.name as subgraph_name,
+                'the ' || p.pointer || ' version ' || p.version || ' of subgraph '
+                  || s.name || ' does not exist' as message
+           from subgraphs.subgraph s,
+                lateral (values ('current', s.current_version),
+                                ('pending', s.pending_version)) as p(pointer, version)
+          where p.version is not null
+            and not exists (select 1 from subgraphs.subgraph_version v
+                             where v.id = p.version)",
+    ),
+];
+
+/// Cross-check assignments, deployment schemas, deployment metadata and
+/// subgraph names and return all inconsistencies between them. None of the
+/// issues have been remediated yet
+pub fn find_health_issues(conn: &PgConnection) -> Result<Vec<HealthIssue>, StoreError> {
+    let mut issues = Vec::new();
+    for (kind, query) in HEALTH_CHECKS {
+        let mut query = diesel::sql_query(format!(
+            "select '{}' as kind, c.deployment, c.subgraph_name, c.message,
+                    'none' as remediation
+               from ({}) c
+              order by c.deployment, c.subgraph_name",
+            kind.as_str(),
+            query
+        ))
+        .into_boxed::<Pg>();
+        if *kind == HealthIssueKind::SchemaWithoutDeployment {
+            query = query.bind::<Text, _>(SUBGRAPHS_ID.as_str());
+        }
+        for row in query.load::<HealthIssueRow>(conn)? {
+            issues.push(row.into_issue()?);
+        }
+    }
+    Ok(issues)
+}
+
+/// Replace the health issues that were recorded previously with `issues`
+pub fn record_health_issues(conn: &PgConnection, issues: &[HealthIssue]) -> Result<(), StoreError> {
+    diesel::sql_query("delete from health_issues").execute(conn)?;
+    for issue in issues {
+        diesel::sql_query(
+            "insert into health_issues(kind, deployment, subgraph_name, message, remediation)
+             values ($1, $2, $3, $4, $5)",
+        )
+        .bind::<Text, _>(issue.kind.as_str())
+        .bind::<Nullable<Text>, _>(issue.deployment.as_deref())
+        .bind::<Nullable<Text>, _>(issue.subgraph_name.as_deref())
+        .bind::<Text, _>(&issue.message)
+        .bind::<Text, _>(issue.remediation.as_str())
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// The health issues that were found when the metadata was last checked
+pub fn health_issues(conn: &PgConnection) -> Result<Vec<HealthIssue>, StoreError> {
+    diesel::sql_query(
+        "select kind, deployment, subgraph_name, message, remediation
+           from health_issues
+          order by id",
+    )
+    .load::<HealthIssueRow>(conn)?
+    .into_iter()
+    .map(HealthIssueRow::into_issue)
+    .collect()
+}
+
+/// Remove the assignment of the deployment `id` to a node, and return
+/// whether there was one
+pub fn remove_assignment(conn: &PgConnection, id: &str) -> Result<bool, StoreError> {
+    let count =
+        diesel::sql_query("delete from subgraphs.subgraph_deployment_assignment where id = $1")
+            .bind::<Text, _>(id)
+            .execute(conn)?;
+    Ok(count > 0)
+}
@@ -1,11 +1,13 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use graph::prelude::{
     ethabi,
     web3::types::{Address, H256},
-    BlockNumber, ChainHeadUpdateStream, ChainStore as ChainStoreTrait, CheapClone, Error,
-    EthereumBlock, EthereumBlockPointer, EthereumCallCache, Future, LightEthereumBlock,
-    Store as StoreTrait, Stream, SubgraphDeploymentStore,
+    BlockGap, BlockNumber, CachedBlock, CachedEthereumCall, ChainHeadUpdateStream,
+    ChainStore as ChainStoreTrait, CheapClone, DeploymentStore, EntityStore, Error, EthereumBlock,
+    EthereumBlockPointer, EthereumCallCache, EthereumCallRevert, Future, HeadAnnouncement,
+    LightEthereumBlock, Stream, SubgraphDeploymentStore, SubgraphNameStore,
 };
 
 use crate::chain_store::ChainStore;
@@ -32,7 +34,7 @@ impl NetworkStore {
     }
 }
 
-impl StoreTrait for NetworkStore {
+impl EntityStore for NetworkStore {
     fn block_ptr(
         &self,
         subgraph_id: graph::prelude::SubgraphDeploymentId,
@@ -75,6 +77,27 @@ impl StoreTrait for NetworkStore {
         self.store.get_many(subgraph_id, ids_for_type)
     }
 
+    fn get_for_block(
+        &self,
+        key: graph::prelude::EntityKey,
+        block: BlockNumber,
+    ) -> Result<Option<graph::prelude::Entity>, graph::prelude::QueryExecutionError> {
+        self.store.get_for_block(key, block)
+    }
+
+    fn get_many_for_block(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+        ids_for_type: std::collections::BTreeMap<&str, Vec<&str>>,
+        block: BlockNumber,
+    ) -> Result<
+        std::collections::BTreeMap<String, Vec<graph::prelude::Entity>>,
+        graph::prelude::StoreError,
+    > {
+        self.store
+            .get_many_for_block(subgraph_id, ids_for_type, block)
+    }
+
     fn find(
         &self,
         query: graph::prelude::EntityQuery,
@@ -107,19 +130,17 @@ impl StoreTrait for NetworkStore {
             .transact_block_operations(subgraph_id, block_ptr_to, mods, stopwatch)
     }
 
-    fn apply_metadata_operations(
+    fn transact_block_operations_batch(
         &self,
-        operations: Vec<graph::prelude::MetadataOperation>,
-    ) -> Result<(), graph::prelude::StoreError> {
-        self.store.apply_metadata_operations(operations)
-    }
-
-    fn build_entity_attribute_indexes(
-        &self,
-        subgraph: &graph::prelude::SubgraphDeploymentId,
-        indexes: Vec<graph::prelude::AttributeIndexDefinition>,
-    ) -> Result<(), graph::prelude::SubgraphAssignmentProviderError> {
-        self.store.build_entity_attribute_indexes(subgraph, indexes)
+        subgraph_id: graph::prelude::SubgraphDeploymentId,
+        blocks: Vec<(
+            EthereumBlockPointer,
+            Vec<graph::prelude::EntityModification>,
+        )>,
+        stopwatch: graph::prelude::StopwatchMetrics,
+    ) -> Result<Option<EthereumBlockPointer>, graph::prelude::StoreError> {
+        self.store
+            .transact_block_operations_batch(subgraph_id, blocks, stopwatch)
     }
 
     fn revert_block_operations(
@@ -139,6 +160,46 @@ impl StoreTrait for NetworkStore {
         self.store.subscribe(entities)
     }
 
+    fn scan_watermark(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Option<graph::prelude::ScanWatermark>, graph::prelude::StoreError> {
+        self.store.scan_watermark(subgraph_id)
+    }
+
+    fn set_scan_watermark(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+        watermark: graph::prelude::ScanWatermark,
+    ) -> Result<(), graph::prelude::StoreError> {
+        self.store.set_scan_watermark(subgraph_id, watermark)
+    }
+
+    fn block_number(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+        block_hash: H256,
+    ) -> Result<Option<BlockNumber>, graph::prelude::StoreError> {
+        self.store.block_number(subgraph_id, block_hash)
+    }
+
+    fn query_store(
+        self: Arc<Self>,
+        for_subscription: bool,
+    ) -> Arc<dyn graph::prelude::QueryStore + Send + Sync> {
+        self.store.cheap_clone().query_store(for_subscription)
+    }
+}
+
+impl DeploymentStore for NetworkStore {
+    fn build_entity_attribute_indexes(
+        &self,
+        subgraph: &graph::prelude::SubgraphDeploymentId,
+        indexes: Vec<graph::prelude::AttributeIndexDefinition>,
+    ) -> Result<(), graph::prelude::SubgraphAssignmentProviderError> {
+        self.store.build_entity_attribute_indexes(subgraph, indexes)
+    }
+
     fn deployment_state_from_name(
         &self,
         name: graph::prelude::SubgraphName,
@@ -171,6 +232,14 @@ impl StoreTrait for NetworkStore {
             .start_subgraph_deployment(logger, subgraph_id, ops)
     }
 
+    fn fail_subgraph(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        error: graph::data::subgraph::schema::SubgraphError,
+    ) -> Result<(), graph::prelude::StoreError> {
+        self.store.fail_subgraph(id, error)
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         logger: &graph::prelude::Logger,
@@ -181,19 +250,81 @@ impl StoreTrait for NetworkStore {
             .migrate_subgraph_deployment(logger, subgraph_id, block_ptr)
     }
 
-    fn block_number(
+    fn restore_unused_deployment(
         &self,
-        subgraph_id: &graph::prelude::SubgraphDeploymentId,
-        block_hash: H256,
-    ) -> Result<Option<BlockNumber>, graph::prelude::StoreError> {
-        self.store.block_number(subgraph_id, block_hash)
+        id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.restore_unused_deployment(id)
     }
 
-    fn query_store(
-        self: Arc<Self>,
-        for_subscription: bool,
-    ) -> Arc<dyn graph::prelude::QueryStore + Send + Sync> {
-        self.store.cheap_clone().query_store(for_subscription)
+    fn memory_limit(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Option<u64>, graph::prelude::StoreError> {
+        self.store.memory_limit(id)
+    }
+
+    fn set_memory_limit(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        limit: Option<u64>,
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.set_memory_limit(id, limit)
+    }
+
+    fn set_deployment_flag(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        note: Option<String>,
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.set_deployment_flag(id, note)
+    }
+
+    fn set_history_blocks(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.set_history_blocks(id, history_blocks)
+    }
+
+    fn set_strict_bytes(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        strict_bytes: bool,
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.set_strict_bytes(id, strict_bytes)
+    }
+
+    fn set_computed_module(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+        module: &[u8],
+    ) -> Result<bool, graph::prelude::StoreError> {
+        self.store.set_computed_module(id, module)
+    }
+
+    fn check_block_ranges(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Vec<graph::prelude::BlockRangeOverlap>, graph::prelude::StoreError> {
+        self.store.check_block_ranges(id)
+    }
+
+    fn repair_block_ranges(
+        &self,
+        id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Vec<graph::prelude::BlockRangeOverlap>, graph::prelude::StoreError> {
+        self.store.repair_block_ranges(id)
+    }
+}
+
+impl SubgraphNameStore for NetworkStore {
+    fn apply_metadata_operations(
+        &self,
+        operations: Vec<graph::prelude::MetadataOperation>,
+    ) -> Result<(), graph::prelude::StoreError> {
+        self.store.apply_metadata_operations(operations)
     }
 }
 
@@ -225,6 +356,40 @@ impl SubgraphDeploymentStore for NetworkStore {
     ) -> Result<Option<String>, failure::Error> {
         self.store.network_name(subgraph_id)
     }
+
+    fn strict_bytes(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<bool, failure::Error> {
+        self.store.strict_bytes(subgraph_id)
+    }
+
+    fn computed_module(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Option<Vec<u8>>, failure::Error> {
+        self.store.computed_module(subgraph_id)
+    }
+
+    fn health_issues(&self) -> Result<Vec<graph::prelude::HealthIssue>, failure::Error> {
+        self.store.health_issues()
+    }
+
+    fn flagged_deployments(
+        &self,
+    ) -> Result<
+        BTreeMap<graph::prelude::SubgraphDeploymentId, graph::prelude::DeploymentFlag>,
+        failure::Error,
+    > {
+        self.store.flagged_deployments()
+    }
+
+    fn graft_lineage(
+        &self,
+        subgraph_id: &graph::prelude::SubgraphDeploymentId,
+    ) -> Result<Option<graph::prelude::GraftLineage>, failure::Error> {
+        self.store.graft_lineage(subgraph_id)
+    }
 }
 
 impl EthereumCallCache for NetworkStore {
@@ -233,19 +398,47 @@ impl EthereumCallCache for NetworkStore {
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-    ) -> Result<Option<Vec<u8>>, failure::Error> {
+    ) -> Result<Option<Result<Vec<u8>, EthereumCallRevert>>, failure::Error> {
         self.store.get_call(contract_address, encoded_call, block)
     }
 
     fn set_call(
         &self,
+        provider: &str,
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
         return_value: &[u8],
+    ) -> Result<(), failure::Error> {
+        self.store.set_call(
+            provider,
+            contract_address,
+            encoded_call,
+            block,
+            return_value,
+        )
+    }
+
+    fn set_call_revert(
+        &self,
+        provider: &str,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        revert: &EthereumCallRevert,
     ) -> Result<(), failure::Error> {
         self.store
-            .set_call(contract_address, encoded_call, block, return_value)
+            .set_call_revert(provider, contract_address, encoded_call, block, revert)
+    }
+
+    fn cached_calls(
+        &self,
+        block_number: u64,
+        contract_address: Option<ethabi::Address>,
+        limit: usize,
+    ) -> Result<Vec<CachedEthereumCall>, failure::Error> {
+        self.store
+            .cached_calls(block_number, contract_address, limit)
     }
 }
 
@@ -256,6 +449,7 @@ impl ChainStoreTrait for NetworkStore {
 
     fn upsert_blocks<B, E>(
         &self,
+        provider: String,
         blocks: B,
     ) -> Box<dyn Future<Item = (), Error = E> + Send + 'static>
     where
@@ -263,11 +457,15 @@ impl ChainStoreTrait for NetworkStore {
         E: From<Error> + Send + 'static,
         Self: Sized,
     {
-        self.chain_store.upsert_blocks(blocks)
+        self.chain_store.upsert_blocks(provider, blocks)
     }
 
-    fn upsert_light_blocks(&self, blocks: Vec<LightEthereumBlock>) -> Result<(), failure::Error> {
-        self.chain_store.upsert_light_blocks(blocks)
+    fn upsert_light_blocks(
+        &self,
+        provider: &str,
+        blocks: Vec<LightEthereumBlock>,
+    ) -> Result<(), failure::Error> {
+        self.chain_store.upsert_light_blocks(provider, blocks)
     }
 
     fn attempt_chain_head_update(&self, ancestor_count: u64) -> Result<Vec<H256>, failure::Error> {
@@ -305,7 +503,44 @@ impl ChainStoreTrait for NetworkStore {
         self.chain_store.block_hashes_by_block_number(number)
     }
 
+    fn block_hashes_and_parents_by_block_number(
+        &self,
+        number: u64,
+    ) -> Result<Vec<(H256, H256)>, failure::Error> {
+        self.chain_store
+            .block_hashes_and_parents_by_block_number(number)
+    }
+
+    fn canonical_block_hash_by_block_number(
+        &self,
+        number: u64,
+    ) -> Result<Option<H256>, failure::Error> {
+        self.chain_store
+            .canonical_block_hash_by_block_number(number)
+    }
+
     fn confirm_block_hash(&self, number: u64, hash: &H256) -> Result<usize, failure::Error> {
         self.chain_store.confirm_block_hash(number, hash)
     }
+
+    fn find_gaps(&self, from: u64, to: u64) -> Result<Vec<BlockGap>, failure::Error> {
+        self.chain_store.find_gaps(from, to)
+    }
+
+    fn cached_block(&self, hash: H256) -> Result<Option<CachedBlock>, failure::Error> {
+        self.chain_store.cached_block(hash)
+    }
+
+    fn save_head_announcements(
+        &self,
+        provider: &str,
+        announcements: &[HeadAnnouncement],
+    ) -> Result<(), failure::Error> {
+        self.chain_store
+            .save_head_announcements(provider, announcements)
+    }
+
+    fn head_announcements(&self) -> Result<Vec<(String, Vec<HeadAnnouncement>)>, failure::Error> {
+        self.chain_store.head_announcements()
+    }
 }
@@ -5,14 +5,14 @@ use fallible_iterator::FallibleIterator;
 use lazy_static::lazy_static;
 use postgres::notification::Notification;
 use postgres::{Connection, TlsMode};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-use futures::sync::mpsc::{channel, Receiver};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
 use graph::prelude::serde_json;
 use graph::prelude::*;
 
@@ -26,8 +26,24 @@ lazy_static! {
                 )))
             )
             .unwrap_or(Duration::from_secs(300));
+
+    /// The dispatcher for each database, keyed by its Postgres URL. All
+    /// listeners for the same database share one dispatcher, and therefore
+    /// one connection
+    static ref DISPATCHERS: Mutex<HashMap<String, Arc<Dispatcher>>> = Mutex::new(HashMap::new());
 }
 
+/// The `application_name` of the connection a dispatcher listens on, so
+/// that it is easy to find in `pg_stat_activity`
+pub const LISTENER_APPLICATION_NAME: &str = "graph-node-notification-listener";
+
+/// How long the dispatcher waits for notifications before it checks whether
+/// it needs to `LISTEN` to new channels
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(100);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
 /// This newtype exists to make it hard to misuse the `NotificationListener` API in a way that
 /// could impact security.
 pub struct SafeChannelName(String);
@@ -47,155 +63,278 @@ impl SafeChannelName {
     }
 }
 
-pub struct NotificationListener {
-    output: Option<Receiver<JsonNotification>>,
-    worker_handle: Option<thread::JoinHandle<()>>,
-    terminate_worker: Arc<AtomicBool>,
-    worker_barrier: Arc<Barrier>,
-    started: bool,
+/// The listeners that are registered with a dispatcher
+#[derive(Default)]
+struct Registrations {
+    /// The senders of all listeners for each channel, by listener id
+    senders: HashMap<String, HashMap<usize, UnboundedSender<JsonNotification>>>,
+    next_id: usize,
+    /// Incremented whenever the set of channels changes
+    version: usize,
+    /// The last `version` for which the dispatcher is listening on all
+    /// channels
+    listening: usize,
 }
 
-impl NotificationListener {
-    /// Connect to the specified database and listen for Postgres notifications on the specified
-    /// channel.
-    ///
-    /// Must call `.start()` to begin receiving notifications.
-    pub fn new(logger: &Logger, postgres_url: String, channel_name: SafeChannelName) -> Self {
-        // Listen to Postgres notifications in a worker thread
-        let (receiver, worker_handle, terminate_worker, worker_barrier) =
-            Self::listen(logger, postgres_url, channel_name);
-
-        NotificationListener {
-            output: Some(receiver),
-            worker_handle: Some(worker_handle),
-            terminate_worker,
-            worker_barrier,
-            started: false,
-        }
-    }
+/// Listens on a single connection for notifications on all channels that
+/// listeners have registered for, and sends each notification to the
+/// listeners for its channel. Each listener has its own unbounded queue so
+/// that a slow listener does not hold up notifications for anybody else.
+///
+/// If the connection is lost, the dispatcher reconnects and listens on all
+/// registered channels again; notifications that were sent while it was
+/// not connected are lost.
+struct Dispatcher {
+    registrations: Mutex<Registrations>,
+    /// Signalled whenever `registrations.listening` changes
+    listening: Condvar,
+}
 
-    /// Start accepting notifications.
-    /// Must be called for any notifications to be received.
-    pub fn start(&mut self) {
-        if !self.started {
-            self.worker_barrier.wait();
-            self.started = true;
+impl Dispatcher {
+    /// Return the dispatcher for `postgres_url`, starting it if there is
+    /// none yet
+    fn for_url(logger: &Logger, postgres_url: String) -> Arc<Dispatcher> {
+        let mut dispatchers = DISPATCHERS.lock().unwrap();
+        if let Some(dispatcher) = dispatchers.get(&postgres_url) {
+            return dispatcher.clone();
         }
-    }
-
-    fn listen(
-        logger: &Logger,
-        postgres_url: String,
-        channel_name: SafeChannelName,
-    ) -> (
-        Receiver<JsonNotification>,
-        thread::JoinHandle<()>,
-        Arc<AtomicBool>,
-        Arc<Barrier>,
-    ) {
-        let logger = logger.new(o!(
-            "component" => "NotificationListener",
-            "channel" => channel_name.0.clone()
-        ));
 
+        let logger = logger.new(o!("component" => "NotificationListener"));
         debug!(
             logger,
             "Cleaning up large notifications after about {}s",
             LARGE_NOTIFICATION_CLEANUP_INTERVAL.as_secs()
         );
 
-        // Create two ends of a boolean variable for signalling when the worker
-        // thread should be terminated
-        let terminate = Arc::new(AtomicBool::new(false));
-        let terminate_worker = terminate.clone();
-        let barrier = Arc::new(Barrier::new(2));
-        let worker_barrier = barrier.clone();
-
-        // Create a channel for notifications
-        let (sender, receiver) = channel(100);
+        let dispatcher = Arc::new(Dispatcher {
+            registrations: Mutex::new(Registrations::default()),
+            listening: Condvar::new(),
+        });
+        dispatchers.insert(postgres_url.clone(), dispatcher.clone());
 
-        let worker_handle = thread::spawn(move || {
+        let worker = dispatcher.clone();
+        thread::spawn(move || {
             // We exit the process on panic so unwind safety is irrelevant.
             std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
-                // Connect to Postgres
-                let conn = Connection::connect(postgres_url, TlsMode::None)
-                    .expect("failed to connect notification listener to Postgres");
-
-                // Subscribe to the notification channel in Postgres
-                conn.execute(&format!("LISTEN {}", channel_name.0), &[])
-                    .expect("failed to listen to Postgres notifications");
-
-                // Wait until the listener has been started
-                barrier.wait();
-
-                // Read notifications until the thread is to be terminated
-                while !terminate.load(Ordering::SeqCst) {
-                    // Obtain a notifications iterator from Postgres
-                    let notifications = conn.notifications();
-
-                    // Read notifications until there hasn't been one for 500ms
-                    for notification in notifications
-                        .timeout_iter(Duration::from_millis(500))
-                        .iterator()
-                        .filter_map(|item| match item {
-                            Ok(msg) => Some(msg),
-                            Err(e) => {
-                                let msg = format!("{}", e);
-                                crit!(logger, "Error receiving message"; "error" => &msg);
-                                eprintln!(
-                                    "Connection to Postgres lost while listening for events. \
-                                 Aborting to avoid inconsistent state. ({})",
-                                    msg
-                                );
-                                std::process::abort();
-                            }
-                        })
-                        .filter(|notification| notification.channel == channel_name.0)
-                    {
-                        // Terminate the thread if desired
-                        if terminate.load(Ordering::SeqCst) {
-                            break;
-                        }
-
-                        match JsonNotification::parse(&notification, &conn) {
-                            Ok(json_notification) => {
-                                // We'll assume here that if sending fails, this means that the
-                                // listener has already been dropped, the receiving
-                                // end is gone and we should terminate the listener loop
-                                if sender.clone().send(json_notification).wait().is_err() {
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                crit!(
-                                    logger,
-                                    "Failed to parse database notification";
-                                    "notification" => format!("{:?}", notification),
-                                    "error" => format!("{}", e),
-                                );
-                                continue;
-                            }
-                        }
-                    }
-                }
+                worker.run(&logger, &postgres_url)
             }))
             .unwrap_or_else(|_| std::process::exit(1))
         });
+        dispatcher
+    }
+
+    /// Start sending notifications on `channel` to `sender`. Returns once
+    /// the dispatcher listens on `channel`, so that no notifications sent
+    /// after this returns are missed
+    fn register(&self, channel: &str, sender: UnboundedSender<JsonNotification>) -> usize {
+        let mut regs = self.registrations.lock().unwrap();
+        let id = regs.next_id;
+        regs.next_id += 1;
+
+        let senders = regs.senders.entry(channel.to_owned()).or_default();
+        senders.insert(id, sender);
+        if senders.len() == 1 {
+            regs.version += 1;
+        }
+
+        // Even if the channel already had listeners, the dispatcher might
+        // not have gotten around to listening on it yet
+        let version = regs.version;
+        while regs.listening < version {
+            regs = self.listening.wait(regs).unwrap();
+        }
+        id
+    }
+
+    /// Stop sending notifications to the listener `id`, and stop listening
+    /// on `channel` if that was its last listener
+    fn unregister(&self, channel: &str, id: usize) {
+        let mut regs = self.registrations.lock().unwrap();
+        let last = match regs.senders.get_mut(channel) {
+            Some(senders) => {
+                senders.remove(&id);
+                senders.is_empty()
+            }
+            None => false,
+        };
+        if last {
+            regs.senders.remove(channel);
+            regs.version += 1;
+        }
+    }
+
+    /// Listen and dispatch notifications forever, reconnecting whenever the
+    /// connection is lost
+    fn run(&self, logger: &Logger, postgres_url: &str) {
+        let mut delay = RECONNECT_DELAY_MIN;
+        loop {
+            let conn = match Connection::connect(postgres_url, TlsMode::None).and_then(|conn| {
+                conn.batch_execute(&format!(
+                    "SET application_name = '{}'",
+                    LISTENER_APPLICATION_NAME
+                ))
+                .map(|()| conn)
+            }) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!(logger, "Failed to connect notification listener to Postgres";
+                           "error" => e.to_string(),
+                           "retry_delay_ms" => delay.as_millis() as u64);
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+                    continue;
+                }
+            };
+            delay = RECONNECT_DELAY_MIN;
+
+            let e = self.dispatch(logger, &conn);
+            error!(logger, "Connection to Postgres lost while listening for notifications, \
+                            reconnecting. Notifications sent until then are lost";
+                   "error" => e.to_string());
+        }
+    }
+
+    /// Listen on all registered channels and dispatch notifications until
+    /// there is an error with the connection
+    fn dispatch(&self, logger: &Logger, conn: &Connection) -> postgres::Error {
+        let mut channels = HashSet::new();
+        let mut version = None;
+        loop {
+            if let Err(e) = self.listen(conn, &mut channels, &mut version) {
+                return e;
+            }
+
+            // Read notifications until there hasn't been one for a while
+            let notifications = conn.notifications();
+            for item in notifications.timeout_iter(POLL_INTERVAL).iterator() {
+                let notification = match item {
+                    Ok(notification) => notification,
+                    Err(e) => return e,
+                };
+                self.send(logger, conn, &notification);
+
+                if version != Some(self.registrations.lock().unwrap().version) {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Make the set of channels we `LISTEN` on on `conn`, which is
+    /// currently `channels` as of `version`, match the registered channels
+    fn listen(
+        &self,
+        conn: &Connection,
+        channels: &mut HashSet<String>,
+        version: &mut Option<usize>,
+    ) -> Result<(), postgres::Error> {
+        let (current, wanted) = {
+            let regs = self.registrations.lock().unwrap();
+            if *version == Some(regs.version) {
+                return Ok(());
+            }
+            let wanted: HashSet<_> = regs.senders.keys().cloned().collect();
+            (regs.version, wanted)
+        };
+
+        for channel in wanted.difference(channels) {
+            conn.execute(&format!("LISTEN {}", channel), &[])?;
+        }
+        for channel in channels.difference(&wanted) {
+            conn.execute(&format!("UNLISTEN {}", channel), &[])?;
+        }
+        *channels = wanted;
+        *version = Some(current);
 
-        (receiver, worker_handle, terminate_worker, worker_barrier)
+        let mut regs = self.registrations.lock().unwrap();
+        regs.listening = regs.listening.max(current);
+        self.listening.notify_all();
+        Ok(())
+    }
+
+    /// Send `notification` to all listeners for its channel
+    fn send(&self, logger: &Logger, conn: &Connection, notification: &Notification) {
+        if !self
+            .registrations
+            .lock()
+            .unwrap()
+            .senders
+            .contains_key(&notification.channel)
+        {
+            return;
+        }
+
+        // Parse outside of the lock since large notifications need a query
+        let json_notification = match JsonNotification::parse(notification, conn) {
+            Ok(json_notification) => json_notification,
+            Err(e) => {
+                crit!(
+                    logger,
+                    "Failed to parse database notification";
+                    "notification" => format!("{:?}", notification),
+                    "error" => format!("{}", e),
+                );
+                return;
+            }
+        };
+
+        let regs = self.registrations.lock().unwrap();
+        for sender in regs
+            .senders
+            .get(&notification.channel)
+            .into_iter()
+            .flat_map(|senders| senders.values())
+        {
+            // Sending only fails if the receiving end has been dropped;
+            // the listener will unregister once it is dropped, too
+            sender.unbounded_send(json_notification.clone()).ok();
+        }
+    }
+}
+
+/// Receives the notifications on one channel. All listeners for the same
+/// database share one connection, on which the channels of all listeners
+/// that have been started are `LISTEN`ed to.
+pub struct NotificationListener {
+    dispatcher: Arc<Dispatcher>,
+    channel: String,
+    sender: Option<UnboundedSender<JsonNotification>>,
+    id: Option<usize>,
+    output: Option<UnboundedReceiver<JsonNotification>>,
+}
+
+impl NotificationListener {
+    /// Prepare to listen for Postgres notifications on the specified
+    /// channel of the specified database.
+    ///
+    /// Must call `.start()` to begin receiving notifications.
+    pub fn new(logger: &Logger, postgres_url: String, channel_name: SafeChannelName) -> Self {
+        let (sender, receiver) = unbounded();
+
+        NotificationListener {
+            dispatcher: Dispatcher::for_url(logger, postgres_url),
+            channel: channel_name.0,
+            sender: Some(sender),
+            id: None,
+            output: Some(receiver),
+        }
+    }
+
+    /// Start accepting notifications. Once this returns, all
+    /// notifications sent on the channel will be received.
+    /// Must be called for any notifications to be received.
+    pub fn start(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            self.id = Some(self.dispatcher.register(&self.channel, sender));
+        }
     }
 }
 
 impl Drop for NotificationListener {
     fn drop(&mut self) {
-        // When dropping the listener, also make sure we signal termination
-        // to the worker and wait for it to shut down
-        self.terminate_worker.store(true, Ordering::SeqCst);
-        self.worker_handle
-            .take()
-            .unwrap()
-            .join()
-            .expect("failed to terminate NotificationListener thread");
+        if let Some(id) = self.id {
+            self.dispatcher.unregister(&self.channel, id);
+        }
     }
 }
 
@@ -215,7 +354,7 @@ impl EventProducer<JsonNotification> for NotificationListener {
 // via NOTIFY in place of the actual payload. Consumers of large
 // notifications are then responsible to fetch the actual payload from
 // the `large_notifications` table.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct JsonNotification {
     pub process_id: i32,
     pub channel: String,
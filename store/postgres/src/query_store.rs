@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use crate::store::ReplicaId;
 use graph::components::store::QueryStore as QueryStoreTrait;
-use graph::prelude::{Store as _, *};
+use graph::prelude::*;
 
 pub(crate) struct QueryStore {
     replica_id: ReplicaId,
@@ -33,6 +33,18 @@ impl QueryStoreTrait for QueryStore {
             .store
             .get_entity_conn(&query.subgraph_id, self.replica_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        // Queries through this store come from the GraphQL API, which must
+        // not give access to graph-node's own entities
+        if let Some(entity_type) = query
+            .collection
+            .entity_types()
+            .into_iter()
+            .find(|entity_type| conn.is_system_entity_type(entity_type))
+        {
+            return Err(QueryExecutionError::SystemEntityType(
+                entity_type.to_owned(),
+            ));
+        }
         self.store.execute_query(&conn, query)
     }
 
@@ -23,21 +23,25 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use crate::relational_queries::{
-    self as rq, ClampRangeQuery, ConflictingEntityQuery, DeleteByPrefixQuery,
-    DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, FilterCollection, FilterQuery,
-    FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery, UpdateQuery,
+    self as rq, ClampOverlappingRangesQuery, ClampRangeQuery, ConflictingEntityQuery,
+    DeleteByPrefixQuery, DeleteDynamicDataSourcesQuery, DeleteQuery, EntityData, ExpireQuery,
+    FilterCollection, FilterQuery, FindManyQuery, FindQuery, InsertQuery,
+    OverlappingBlockRangesQuery, PruneQuery, RemoveShadowedVersionsQuery, RevertClampQuery,
+    RevertRemoveQuery, UpdateQuery,
 };
+use graph::data::graphql::computed::ComputedField;
 use graph::data::graphql::ext::{DocumentExt, ObjectTypeExt};
+use graph::data::graphql::ttl::ttl_blocks;
 use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{
-    DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
+    is_system_entity_type, DynamicEthereumContractDataSourceEntity, POI_OBJECT, POI_TABLE,
 };
 use graph::prelude::{
-    format_err, info, BlockNumber, Entity, EntityChange, EntityChangeOperation, EntityCollection,
-    EntityFilter, EntityKey, EntityOrder, EntityRange, EthereumBlockPointer, Logger,
-    QueryExecutionError, StoreError, StoreEvent, SubgraphDeploymentId, Value, ValueType,
-    BLOCK_NUMBER_MAX,
+    format_err, info, warn, BlockNumber, BlockRangeOverlap, Entity, EntityChange,
+    EntityChangeOperation, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityRange,
+    EthereumBlockPointer, Logger, QueryExecutionError, StoreError, StoreEvent,
+    SubgraphDeploymentId, Value, ValueType, Word, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::{BLOCK_RANGE_COLUMN, BLOCK_UNVERSIONED};
@@ -67,12 +71,11 @@ lazy_static! {
 /// really use the SQL version 'big_thing'
 ///
 /// We use `SqlName` for example for table and column names, and feed these
-/// directly to Postgres. Postgres truncates names to 63 characters; if
-/// users have GraphQL type names that do not differ in the first
-/// 63 characters after snakecasing, schema creation will fail because, to
-/// Postgres, we would create the same table twice. We consider this case
-/// to be pathological and so unlikely in practice that we do not try to work
-/// around it in the application.
+/// directly to Postgres. Postgres truncates names to `MAX_IDENTIFIER_LEN`
+/// bytes; if users have GraphQL type names that do not differ in the first
+/// 63 characters after snakecasing, we would create the same table twice.
+/// `Layout::check_identifiers` rejects such schemas before we create any
+/// tables for them.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Hash)]
 pub struct SqlName(String);
 
@@ -182,6 +185,17 @@ impl TryFrom<&s::Type> for IdType {
     }
 }
 
+/// The longest identifier, in bytes, that Postgres uses as it is. Longer
+/// identifiers are silently truncated to this length
+pub(crate) const MAX_IDENTIFIER_LEN: usize = 63;
+
+/// The name under which Postgres knows the identifier `name`
+fn truncated_identifier(name: &str) -> &str {
+    // Identifiers are ASCII, which `SqlName::check_valid_identifier` makes
+    // sure of, so that we can not cut a character in half
+    &name[..name.len().min(MAX_IDENTIFIER_LEN)]
+}
+
 type IdTypeMap = HashMap<String, IdType>;
 
 type EnumMap = BTreeMap<String, Arc<BTreeSet<String>>>;
@@ -330,7 +344,7 @@ impl Layout {
             columns: vec![
                 Column {
                     name: SqlName::from("digest"),
-                    field: "digest".to_owned(),
+                    field: Word::from("digest"),
                     field_type: q::Type::NonNullType(Box::new(q::Type::NamedType(
                         BYTES_SCALAR.to_owned(),
                     ))),
@@ -340,7 +354,7 @@ impl Layout {
                 },
                 Column {
                     name: SqlName::from(PRIMARY_KEY_COLUMN),
-                    field: PRIMARY_KEY_COLUMN.to_owned(),
+                    field: Word::from(PRIMARY_KEY_COLUMN),
                     field_type: q::Type::NonNullType(Box::new(q::Type::NamedType(
                         "String".to_owned(),
                     ))),
@@ -354,6 +368,8 @@ impl Layout {
             /// predictable
             position: position as u32,
             is_account_like: false,
+            is_system: true,
+            ttl: None,
         }
     }
 
@@ -364,6 +380,7 @@ impl Layout {
     ) -> Result<Layout, StoreError> {
         let catalog = Catalog::new(conn, schema_name)?;
         let layout = Self::new(schema, catalog, true)?;
+        layout.check_identifiers()?;
         let sql = layout
             .as_ddl()
             .map_err(|_| StoreError::Unknown(format_err!("failed to generate DDL for layout")))?;
@@ -434,6 +451,16 @@ impl Layout {
         metadata.revert_metadata(conn, &self.subgraph, block_to_revert)?;
         info!(logger, "Rewound subgraph to block {}", block.number;
               "time_ms" => start.elapsed().as_millis());
+
+        // 4. Check that the copy does not contain overlapping versions. We
+        // only report them here since fixing them rewrites entity history;
+        // operators can repair them with `subgraph_repair_block_ranges`
+        let overlaps = self.check_block_ranges(conn)?;
+        if !overlaps.is_empty() {
+            warn!(logger, "Grafted subgraph has entities with overlapping block ranges";
+                  "entities" => overlaps.len(),
+                  "first" => format!("{}[{}]", overlaps[0].entity_type, overlaps[0].entity_id));
+        }
         Ok(())
     }
 
@@ -490,6 +517,99 @@ impl Layout {
         Ok(out)
     }
 
+    /// Check that Postgres can use every identifier that the DDL for this
+    /// layout contains as it is: the names of tables, columns and enum
+    /// types must not be longer than `MAX_IDENTIFIER_LEN` bytes, and no two
+    /// of the names that share a namespace in Postgres may be the same,
+    /// neither after snakecasing GraphQL names nor after Postgres truncates
+    /// the names of indexes. Index names are often longer than the limit,
+    /// which is fine as long as they stay unique.
+    ///
+    /// We only check this when we create the database schema for a new
+    /// deployment, since the layout of existing deployments can not change
+    pub(crate) fn check_identifiers(&self) -> Result<(), StoreError> {
+        fn check_length(name: &SqlName, what: impl Fn() -> String) -> Result<(), StoreError> {
+            if name.as_str().len() > MAX_IDENTIFIER_LEN {
+                return Err(StoreError::InvalidIdentifier(format!(
+                    "the database name `{}` for {} is {} bytes long, \
+                     but Postgres only allows {} bytes",
+                    name,
+                    what(),
+                    name.as_str().len(),
+                    MAX_IDENTIFIER_LEN
+                )));
+            }
+            Ok(())
+        }
+
+        /// Record that `what` uses `name` in `names`, failing if something
+        /// else already uses it
+        fn claim(
+            names: &mut HashMap<String, String>,
+            name: &str,
+            what: String,
+        ) -> Result<(), StoreError> {
+            let name = truncated_identifier(name);
+            match names.get(name) {
+                Some(other) => Err(StoreError::InvalidIdentifier(format!(
+                    "{} and {} both use the database name `{}`",
+                    other, what, name
+                ))),
+                None => {
+                    names.insert(name.to_owned(), what);
+                    Ok(())
+                }
+            }
+        }
+
+        // Tables, indexes and types share one namespace in Postgres since
+        // every table also defines a type with its name
+        let mut relations = HashMap::new();
+
+        for name in self.enums.keys() {
+            let sql_name = SqlName::from(name.as_str());
+            check_length(&sql_name, || format!("the enum `{}`", name))?;
+            claim(
+                &mut relations,
+                sql_name.as_str(),
+                format!("the enum `{}`", name),
+            )?;
+        }
+
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            let object = &table.object;
+            check_length(&table.name, || format!("the type `{}`", object))?;
+            claim(
+                &mut relations,
+                table.name.as_str(),
+                format!("the type `{}`", object),
+            )?;
+            for index in table.index_names() {
+                claim(
+                    &mut relations,
+                    &index,
+                    format!("an index on the type `{}`", object),
+                )?;
+            }
+
+            let mut columns = HashMap::new();
+            for internal in &[VID_COLUMN, BLOCK_RANGE_COLUMN] {
+                columns.insert(
+                    internal.to_string(),
+                    format!("the internal column `{}`", internal),
+                );
+            }
+            for column in &table.columns {
+                let what = || format!("the field `{}.{}`", object, column.field);
+                check_length(&column.name, what)?;
+                claim(&mut columns, column.name.as_str(), what())?;
+            }
+        }
+        Ok(())
+    }
+
     /// Find the table with the provided `name`. The name must exactly match
     /// the name of an existing table. No conversions of the name are done
     pub fn table(&self, name: &SqlName) -> Option<&Table> {
@@ -499,6 +619,14 @@ impl Layout {
             .map(|rc| rc.as_ref())
     }
 
+    /// Whether `entity` is one of graph-node's own entity types
+    pub fn is_system_entity_type(&self, entity: &str) -> bool {
+        self.tables
+            .get(entity)
+            .map(|table| table.is_system)
+            .unwrap_or_else(|| is_system_entity_type(entity))
+    }
+
     pub fn table_for_entity(&self, entity: &str) -> Result<&Arc<Table>, StoreError> {
         self.tables
             .get(entity)
@@ -554,6 +682,11 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
+        if table.ttl.is_some() {
+            // Mappings do not see an entity that expired, and set it as if it
+            // was new; its last version ends where the new one starts
+            ClampRangeQuery::new(table, &[key.entity_id.as_str()], block).execute(conn)?;
+        }
         let query = InsertQuery::new(table, key, entity, block)?;
         query.execute(conn)?;
         Ok(())
@@ -592,6 +725,7 @@ impl Layout {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        attributes: BTreeMap<String, BTreeSet<String>>,
         block: BlockNumber,
         query_id: Option<String>,
     ) -> Result<Vec<T>, QueryExecutionError> {
@@ -631,6 +765,7 @@ impl Layout {
             filter.as_ref(),
             order,
             range,
+            &attributes,
             block,
             query_id,
         )?;
@@ -663,7 +798,7 @@ impl Layout {
         block: BlockNumber,
     ) -> Result<(), StoreError> {
         let table = self.table_for_entity(&key.entity_type)?;
-        ClampRangeQuery::new(table, key, block).execute(conn)?;
+        ClampRangeQuery::new(table, &[key.entity_id.as_str()], block).execute(conn)?;
         let query = InsertQuery::new(table, key, entity, block)?;
         query.execute(conn)?;
         Ok(())
@@ -704,8 +839,20 @@ impl Layout {
         key: &EntityKey,
         block: BlockNumber,
     ) -> Result<usize, StoreError> {
-        let table = self.table_for_entity(&key.entity_type)?;
-        Ok(ClampRangeQuery::new(table, key, block).execute(conn)?)
+        self.delete_many(conn, &key.entity_type, &[key.entity_id.as_str()], block)
+    }
+
+    /// Delete the entities of type `entity_type` with the given `entity_ids`
+    /// with one query, and return how many entities were deleted
+    pub fn delete_many(
+        &self,
+        conn: &PgConnection,
+        entity_type: &str,
+        entity_ids: &[&str],
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let table = self.table_for_entity(entity_type)?;
+        Ok(ClampRangeQuery::new(table, entity_ids, block).execute(conn)?)
     }
 
     pub fn delete_unversioned(
@@ -758,6 +905,7 @@ impl Layout {
                     entity_type: table.object.clone(),
                     entity_id: id,
                     operation: EntityChangeOperation::Removed,
+                    changed_fields: None,
                 });
             changes.extend(deleted);
             // EntityChange for versions that we just updated or inserted
@@ -766,12 +914,86 @@ impl Layout {
                 entity_type: table.object.clone(),
                 entity_id: id,
                 operation: EntityChangeOperation::Set,
+                changed_fields: None,
             });
             changes.extend(set);
         }
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Remove all entity versions that were no longer current at
+    /// `earliest_block`, since queries can not go back further than that
+    /// block anymore. The Proof of Indexing is kept in its entirety so that
+    /// it can still be compared with other indexers for older blocks.
+    /// Return the number of versions that were removed
+    pub fn prune(
+        &self,
+        conn: &PgConnection,
+        earliest_block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            if table.object == POI_OBJECT {
+                continue;
+            }
+            count += PruneQuery::new(table, earliest_block).execute(conn)?;
+        }
+        Ok(count)
+    }
+
+    /// Remove all versions of the entities of `@ttl` types that have not
+    /// been changed for more than their `ttl` blocks as of block `block`.
+    /// Neither queries nor mappings see these entities at `block` or any
+    /// later block. `block` must therefore be the oldest block that the
+    /// deployment can still process again after a revert.
+    /// Return the number of versions that were removed
+    pub fn expire(&self, conn: &PgConnection, block: BlockNumber) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            if let Some(ttl) = table.ttl {
+                count += ExpireQuery::new(table, block.saturating_sub(ttl)).execute(conn)?;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Find all entities that have versions whose block ranges overlap with
+    /// those of other versions. Reverts that were interrupted in older
+    /// versions of `graph-node` could leave such versions behind
+    pub fn check_block_ranges(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        let mut overlaps = Vec::new();
+        for table in self.tables.values() {
+            let data: Vec<rq::OverlappingEntityData> =
+                OverlappingBlockRangesQuery::new(table).load(conn)?;
+            overlaps.extend(data.into_iter().map(|data| BlockRangeOverlap {
+                entity_type: table.object.clone(),
+                entity_id: data.id,
+                versions: data.versions,
+            }));
+        }
+        overlaps
+            .sort_by(|a, b| (&a.entity_type, &a.entity_id).cmp(&(&b.entity_type, &b.entity_id)));
+        Ok(overlaps)
+    }
+
+    /// Change block ranges so that exactly one version of each entity is
+    /// visible at any block. Of several versions that start at the same
+    /// block, only the one that was written last is kept; after that, the
+    /// block range of each version that still overlaps with later versions
+    /// is clamped to end where the next of them starts. Return the number
+    /// of versions that were removed or changed
+    pub fn repair_block_ranges(&self, conn: &PgConnection) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self.tables.values() {
+            count += RemoveShadowedVersionsQuery::new(table).execute(conn)?;
+            count += ClampOverlappingRangesQuery::new(table).execute(conn)?;
+        }
+        Ok(count)
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`. This function can only be called on the `Layout`
     /// for the metadata subgraph.
@@ -807,6 +1029,7 @@ impl Layout {
                 entity_type: DDS.to_owned(),
                 entity_id: id.to_owned(),
                 operation: EntityChangeOperation::Removed,
+                changed_fields: None,
             })
             .collect();
 
@@ -837,6 +1060,7 @@ impl Layout {
                         entity_type: table.object.clone(),
                         entity_id: data.id,
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     });
                 changes.extend(deleted);
             }
@@ -878,6 +1102,7 @@ pub enum ColumnType {
     Bytes,
     Int,
     String,
+    Timestamp,
     TSVector(FulltextConfig),
     Enum(EnumType),
     /// A `bytea` in SQL, represented as a ValueType::String; this is
@@ -938,6 +1163,7 @@ impl ColumnType {
             ValueType::Bytes => Ok(ColumnType::Bytes),
             ValueType::Int => Ok(ColumnType::Int),
             ValueType::String => Ok(ColumnType::String),
+            ValueType::Timestamp => Ok(ColumnType::Timestamp),
             ValueType::List => Err(StoreError::Unknown(format_err!(
                 "can not convert ValueType::List to ColumnType"
             ))),
@@ -952,6 +1178,7 @@ impl ColumnType {
             ColumnType::Bytes => "bytea",
             ColumnType::Int => "integer",
             ColumnType::String => "text",
+            ColumnType::Timestamp => "timestamptz",
             ColumnType::TSVector(_) => "tsvector",
             ColumnType::Enum(enum_type) => enum_type.name.as_str(),
             ColumnType::BytesId => "bytea",
@@ -975,7 +1202,7 @@ impl ColumnType {
 #[derive(Clone, Debug)]
 pub struct Column {
     pub name: SqlName,
-    pub field: String,
+    pub field: Word,
     pub field_type: q::Type,
     pub column_type: ColumnType,
     pub fulltext_fields: Option<HashSet<String>>,
@@ -1010,7 +1237,7 @@ impl Column {
         };
         Ok(Column {
             name: sql_name,
-            field: field.name.clone(),
+            field: Word::from(&field.name),
             column_type,
             field_type: field.field_type.clone(),
             fulltext_fields: None,
@@ -1024,7 +1251,7 @@ impl Column {
 
         Ok(Column {
             name: sql_name,
-            field: def.name.to_string(),
+            field: Word::from(def.name.as_str()),
             field_type: q::Type::NamedType(String::from("fulltext".to_string())),
             column_type: ColumnType::TSVector(def.config.clone()),
             fulltext_fields: Some(def.included_fields.clone()),
@@ -1158,6 +1385,15 @@ pub struct Table {
     /// entities are updated frequently on average
     pub is_account_like: bool,
 
+    /// This table holds entities that graph-node keeps for its own
+    /// bookkeeping, like the Proof of Indexing. They are not part of the
+    /// API of the subgraph
+    pub is_system: bool,
+
+    /// Entities in this table expire when they have not been changed for
+    /// more than this many blocks; set with the `@ttl` directive
+    pub ttl: Option<BlockNumber>,
+
     /// The position of this table in all the tables for this layout; this
     /// is really only needed for the tests to make the names of indexes
     /// predictable
@@ -1179,17 +1415,22 @@ impl Table {
         let columns = defn
             .fields
             .iter()
-            .filter(|field| !derived_column(field))
+            .filter(|field| !derived_column(field) && !ComputedField::is_computed(field))
             .map(|field| Column::new(&table_name, field, catalog, enums, id_types))
             .chain(fulltexts.iter().map(|def| Column::new_fulltext(def)))
             .collect::<Result<Vec<Column>, StoreError>>()?;
         let is_account_like =
             ACCOUNT_TABLES.contains(&format!("{}.{}", catalog.schema, table_name));
+        let ttl = ttl_blocks(defn).map_err(|reason| {
+            StoreError::Unknown(format_err!("invalid @ttl on `{}`: {}", defn.name, reason))
+        })?;
         let table = Table {
             object: defn.name.clone(),
             name: table_name.clone(),
             qualified_name: SqlName::qualified_name(&catalog.schema, &table_name),
             is_account_like,
+            is_system: is_system_entity_type(&defn.name),
+            ttl,
             columns,
             position,
         };
@@ -1292,9 +1533,10 @@ impl Table {
         //
         // We also index `vid` as that correlates with the order in which
         // entities are stored.
-        write!(out,"create index brin_{table_name}\n    \
+        write!(out,"create index {index_name}\n    \
                     on {schema_name}.{table_name}\n \
                        using brin(lower(block_range), coalesce(upper(block_range), {block_max}), vid);\n",
+            index_name = self.brin_index_name(),
             table_name = self.name,
             schema_name = layout.catalog.schema,
             block_max = BLOCK_NUMBER_MAX)?;
@@ -1303,24 +1545,17 @@ impl Table {
         // it faster to find entity versions that have been modified
         write!(
             out,
-            "create index {table_name}_block_range_closed\n    \
+            "create index {index_name}\n    \
                      on {schema_name}.{table_name}(coalesce(upper(block_range), {block_max}))\n \
                      where coalesce(upper(block_range), {block_max}) < {block_max};\n",
+            index_name = self.block_range_closed_index_name(),
             table_name = self.name,
             schema_name = layout.catalog.schema,
             block_max = BLOCK_NUMBER_MAX
         )?;
 
-        // Create indexes. Skip columns whose type is an array of enum,
-        // since there is no good way to index them with Postgres 9.6.
-        // Once we move to Postgres 11, we can enable that
-        // (tracked in graph-node issue #1330)
-        for (i, column) in self
-            .columns
-            .iter()
-            .filter(|col| !(col.is_list() && col.is_enum()))
-            .enumerate()
-        {
+        // Create indexes for the attributes
+        for (i, column) in self.indexed_columns() {
             let (method, index_expr) = if column.is_reference() && !column.is_list() {
                 // For foreign keys, index the key together with the block range
                 // since we almost always also have a block_range clause in
@@ -1348,11 +1583,9 @@ impl Table {
             };
             write!(
                 out,
-                "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
-                table_index = self.position,
+                "create index {index_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+                index_name = self.attr_index_name(i, column),
                 table_name = self.name,
-                column_index = i,
-                column_name = column.name,
                 schema_name = layout.catalog.schema,
                 method = method,
                 index_expr = index_expr,
@@ -1360,6 +1593,42 @@ impl Table {
         }
         writeln!(out)
     }
+
+    fn brin_index_name(&self) -> String {
+        format!("brin_{}", self.name)
+    }
+
+    fn block_range_closed_index_name(&self) -> String {
+        format!("{}_block_range_closed", self.name)
+    }
+
+    /// The columns that get an index, together with their position among
+    /// them. Skip columns whose type is an array of enum, since there is no
+    /// good way to index them with Postgres 9.6. Once we move to Postgres
+    /// 11, we can enable that (tracked in graph-node issue #1330)
+    fn indexed_columns(&self) -> impl Iterator<Item = (usize, &Column)> {
+        self.columns
+            .iter()
+            .filter(|col| !(col.is_list() && col.is_enum()))
+            .enumerate()
+    }
+
+    fn attr_index_name(&self, column_index: usize, column: &Column) -> String {
+        format!(
+            "attr_{}_{}_{}_{}",
+            self.position, column_index, self.name, column.name
+        )
+    }
+
+    /// The names of all indexes that `as_ddl` creates for this table
+    fn index_names(&self) -> Vec<String> {
+        let mut names = vec![self.brin_index_name(), self.block_range_closed_index_name()];
+        names.extend(
+            self.indexed_columns()
+                .map(|(i, column)| self.attr_index_name(i, column)),
+        );
+        names
+    }
 }
 
 /// Return the enclosed named type for a field type, i.e., the type after
@@ -1522,6 +1791,103 @@ mod tests {
         );
     }
 
+    #[test]
+    fn check_identifiers() {
+        fn check(gql: &str) -> Result<(), String> {
+            test_layout(gql)
+                .check_identifiers()
+                .map_err(|e| e.to_string())
+        }
+
+        fn assert_rejected(gql: &str, expected: &str) {
+            match check(gql) {
+                Ok(()) => panic!("schema should be rejected: {}", gql),
+                Err(e) => assert!(
+                    e.contains(expected),
+                    "error `{}` should contain `{}`",
+                    e,
+                    expected
+                ),
+            }
+        }
+
+        // A lowercase name with `len` bytes, starting with `first`
+        fn name(first: char, len: usize) -> String {
+            format!("{}{}", first, "a".repeat(len - 1))
+        }
+
+        // Names of exactly `MAX_IDENTIFIER_LEN` bytes are fine, one more
+        // byte is too much
+        let long_type = name('T', MAX_IDENTIFIER_LEN);
+        let long_field = name('f', MAX_IDENTIFIER_LEN);
+        let long_enum = name('E', MAX_IDENTIFIER_LEN);
+        assert_eq!(
+            Ok(()),
+            check(&format!(
+                "type {} @entity {{ id: ID!, {}: Int }} enum {} {{ A }}",
+                long_type, long_field, long_enum
+            ))
+        );
+
+        let too_long = name('T', MAX_IDENTIFIER_LEN + 1);
+        assert_rejected(
+            &format!("type {} @entity {{ id: ID! }}", too_long),
+            &format!(
+                "the database name `{}` for the type `{}` is 64 bytes long",
+                too_long.to_lowercase(),
+                too_long
+            ),
+        );
+        let too_long = name('f', MAX_IDENTIFIER_LEN + 1);
+        assert_rejected(
+            &format!("type Thing @entity {{ id: ID!, {}: Int }}", too_long),
+            &format!("for the field `Thing.{}` is 64 bytes long", too_long),
+        );
+        let too_long = name('E', MAX_IDENTIFIER_LEN + 1);
+        assert_rejected(
+            &format!("type Thing @entity {{ id: ID! }} enum {} {{ A }}", too_long),
+            &format!("for the enum `{}` is 64 bytes long", too_long),
+        );
+
+        // Names that are the same after snakecasing
+        assert_rejected(
+            "type Thing @entity { id: ID!, fooBar: Int, foo_bar: Int }",
+            "the field `Thing.fooBar` and the field `Thing.foo_bar` \
+             both use the database name `foo_bar`",
+        );
+        assert_rejected(
+            "type FooBar @entity { id: ID! } type Foo_Bar @entity { id: ID! }",
+            "the type `FooBar` and the type `Foo_Bar` both use the database name `foo_bar`",
+        );
+        assert_rejected(
+            "type Thing @entity { id: ID!, blockRange: Int }",
+            "the internal column `block_range` and the field `Thing.blockRange` \
+             both use the database name `block_range`",
+        );
+        assert_rejected(
+            "type Thing @entity { id: ID! } type ThingBlockRangeClosed @entity { id: ID! }",
+            "an index on the type `Thing` and the type `ThingBlockRangeClosed` \
+             both use the database name `thing_block_range_closed`",
+        );
+
+        // `brin_{table}` is 64 bytes long for both tables, and Postgres
+        // would truncate both index names to the same 63 bytes
+        let prefix = name('T', MAX_IDENTIFIER_LEN - 5);
+        assert_rejected(
+            &format!(
+                "type {}b @entity {{ id: ID! }} type {}c @entity {{ id: ID! }}",
+                prefix, prefix
+            ),
+            &format!(
+                "an index on the type `{}b` and an index on the type `{}c` \
+                 both use the database name `brin_{}`",
+                prefix,
+                prefix,
+                prefix.to_lowercase()
+            ),
+        );
+    }
+
     const THING_GQL: &str = "
         type Thing @entity {
             id: ID!
@@ -9,10 +9,10 @@ use diesel::pg::{Pg, PgConnection};
 use diesel::query_builder::{AstPass, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, Binary, Bool, Integer, Jsonb, Range, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Range, Text};
 use diesel::Connection;
 use lazy_static::lazy_static;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::env;
 use std::iter::FromIterator;
@@ -22,7 +22,7 @@ use graph::data::{schema::FulltextAlgorithm, store::scalar};
 use graph::prelude::{
     format_err, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
     EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value,
+    QueryExecutionError, StoreError, Value, Word,
 };
 
 use crate::block_range::{
@@ -30,7 +30,9 @@ use crate::block_range::{
 };
 use crate::entities::STRING_PREFIX_SIZE;
 use crate::filter::UnsupportedFilter;
-use crate::relational::{Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN};
+use crate::relational::{
+    Column, ColumnType, IdType, Layout, SqlName, Table, PRIMARY_KEY_COLUMN, VID_COLUMN,
+};
 use crate::sql_value::SqlValue;
 
 lazy_static! {
@@ -47,6 +49,12 @@ lazy_static! {
             })
             .unwrap_or(150)
     };
+
+    /// The attributes that `EntityData::deserialize_with_layout` adds to
+    /// every entity, interned once so that loading entities does not
+    /// intern them again
+    static ref TYPENAME: Word = Word::from("__typename");
+    static ref PARENT_ID: Word = Word::from("g$parent_id");
 }
 
 fn str_as_bytes(id: &str) -> QueryResult<scalar::Bytes> {
@@ -117,6 +125,16 @@ trait ForeignKeyClauses {
         self.bind_id(id, out)
     }
 
+    /// Generate a clause `{name()} = any($ids)` using the right types to
+    /// bind `$ids` into `out`
+    fn eq_any(&self, ids: &[&str], out: &mut AstPass<Pg>) -> QueryResult<()> {
+        out.push_sql(self.name());
+        out.push_sql(" = any(");
+        self.bind_ids(ids, out)?;
+        out.push_sql(")");
+        Ok(())
+    }
+
     /// Generate a clause
     ///    `exists (select 1 from unnest($ids) as p(g$id) where id = p.g$id)`
     /// using the right types to bind `$ids` into `out`
@@ -192,22 +210,22 @@ impl ForeignKeyClauses for Column {
 pub trait FromEntityData: Default + From<Entity> {
     type Value: FromColumnValue;
 
-    fn insert_entity_data(&mut self, key: String, v: Self::Value);
+    fn insert_entity_data(&mut self, key: &Word, v: Self::Value);
 }
 
 impl FromEntityData for Entity {
     type Value = graph::prelude::Value;
 
-    fn insert_entity_data(&mut self, key: String, v: Self::Value) {
-        self.insert(key, v);
+    fn insert_entity_data(&mut self, key: &Word, v: Self::Value) {
+        self.insert(key.clone(), v);
     }
 }
 
 impl FromEntityData for BTreeMap<String, graphql_parser::query::Value> {
     type Value = graphql_parser::query::Value;
 
-    fn insert_entity_data(&mut self, key: String, v: Self::Value) {
-        self.insert(key, v);
+    fn insert_entity_data(&mut self, key: &Word, v: Self::Value) {
+        self.insert(key.to_string(), v);
     }
 }
 
@@ -229,6 +247,8 @@ pub trait FromColumnValue: Sized {
     // The string returned by the DB, without the leading '\x'
     fn from_bytes(i: &str) -> Result<Self, StoreError>;
 
+    fn from_timestamp(ts: scalar::Timestamp) -> Self;
+
     fn from_vec(v: Vec<Self>) -> Self;
 
     fn from_column_value(
@@ -275,6 +295,11 @@ pub trait FromColumnValue: Sized {
             }
             (j::String(s), ColumnType::Bytes) => Self::from_bytes(s.trim_start_matches("\\x")),
             (j::String(s), ColumnType::BytesId) => Ok(Self::from_string(bytes_as_str(&s))),
+            (j::String(s), ColumnType::Timestamp) => scalar::Timestamp::from_str(&s)
+                .map(Self::from_timestamp)
+                .map_err(|e| {
+                    StoreError::Unknown(format_err!("failed to convert {} to Timestamp: {}", s, e))
+                }),
             (j::String(s), column_type) => Err(StoreError::Unknown(format_err!(
                 "can not convert string {} to {:?}",
                 s,
@@ -326,6 +351,10 @@ impl FromColumnValue for graphql_parser::query::Value {
         Ok(graphql_parser::query::Value::String(format!("0x{}", b)))
     }
 
+    fn from_timestamp(ts: scalar::Timestamp) -> Self {
+        graphql_parser::query::Value::String(ts.to_string())
+    }
+
     fn from_vec(v: Vec<Self>) -> Self {
         graphql_parser::query::Value::List(v)
     }
@@ -372,6 +401,10 @@ impl FromColumnValue for graph::prelude::Value {
             })
     }
 
+    fn from_timestamp(ts: scalar::Timestamp) -> Self {
+        graph::prelude::Value::Timestamp(ts)
+    }
+
     fn from_vec(v: Vec<Self>) -> Self {
         graph::prelude::Value::List(v)
     }
@@ -406,18 +439,18 @@ impl EntityData {
         match self.data {
             j::Object(map) => {
                 let mut out = T::default();
-                out.insert_entity_data("__typename".to_owned(), T::Value::from_string(self.entity));
+                out.insert_entity_data(&TYPENAME, T::Value::from_string(self.entity));
                 for (key, json) in map {
                     // Simply ignore keys that do not have an underlying table
                     // column; those will be things like the block_range that
                     // is used internally for versioning
                     if key == "g$parent_id" {
                         let value = T::Value::from_column_value(&ColumnType::String, json)?;
-                        out.insert_entity_data("g$parent_id".to_owned(), value);
+                        out.insert_entity_data(&PARENT_ID, value);
                     } else if let Some(column) = table.column(&SqlName::verbatim(key)) {
                         let value = T::Value::from_column_value(&column.column_type, json)?;
                         if !value.is_null() {
-                            out.insert_entity_data(column.field.clone(), value);
+                            out.insert_entity_data(&column.field, value);
                         }
                     }
                 }
@@ -459,6 +492,13 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                         .map_err(|e| DieselError::SerializationError(Box::new(e)))?;
                     out.push_bind_param::<Binary, _>(&bytes.as_slice())
                 }
+                ColumnType::Timestamp => {
+                    let ts = scalar::Timestamp::from_str(&s)
+                        .map_err(|e| DieselError::SerializationError(e.to_string().into()))?;
+                    out.push_bind_param::<Text, _>(&ts.to_string())?;
+                    out.push_sql("::timestamptz");
+                    Ok(())
+                }
                 _ => unreachable!(
                     "only string, enum and tsvector columns have values of type string"
                 ),
@@ -479,6 +519,12 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                         out.push_sql("::numeric[]");
                         Ok(())
                     }
+                    ColumnType::Timestamp => {
+                        let text_values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+                        out.push_bind_param::<Array<Text>, _>(&text_values)?;
+                        out.push_sql("::timestamptz[]");
+                        Ok(())
+                    }
                     ColumnType::Boolean => out.push_bind_param::<Array<Bool>, _>(&sql_values),
                     ColumnType::Bytes => out.push_bind_param::<Array<Binary>, _>(&sql_values),
                     ColumnType::Int => out.push_bind_param::<Array<Integer>, _>(&sql_values),
@@ -525,6 +571,11 @@ impl<'a> QueryFragment<Pg> for QueryValue<'a> {
                 out.push_sql("::numeric");
                 Ok(())
             }
+            Value::Timestamp(ts) => {
+                out.push_bind_param::<Text, _>(&ts.to_string())?;
+                out.push_sql("::timestamptz");
+                Ok(())
+            }
         }
     }
 }
@@ -815,7 +866,8 @@ impl<'a> QueryFilter<'a> {
             | Value::BigDecimal(_)
             | Value::Int(_)
             | Value::Bool(_)
-            | Value::BigInt(_) => {
+            | Value::BigInt(_)
+            | Value::Timestamp(_) => {
                 let filter = match negated {
                     false => "contains",
                     true => "not_contains",
@@ -855,6 +907,7 @@ impl<'a> QueryFilter<'a> {
                 | Value::Bytes(_)
                 | Value::BigDecimal(_)
                 | Value::Int(_)
+                | Value::Timestamp(_)
                 | Value::List(_) => {
                     out.push_sql(op.as_str());
                     QueryValue(value, &column.column_type).walk_ast(out)?;
@@ -887,9 +940,11 @@ impl<'a> QueryFilter<'a> {
             out.push_identifier(column.name.as_str())?;
             out.push_sql(op.as_str());
             match value {
-                Value::BigInt(_) | Value::BigDecimal(_) | Value::Int(_) | Value::String(_) => {
-                    QueryValue(value, &column.column_type).walk_ast(out)?
-                }
+                Value::BigInt(_)
+                | Value::BigDecimal(_)
+                | Value::Int(_)
+                | Value::String(_)
+                | Value::Timestamp(_) => QueryValue(value, &column.column_type).walk_ast(out)?,
                 Value::Bool(_) | Value::Bytes(_) | Value::List(_) | Value::Null => {
                     return Err(UnsupportedFilter {
                         filter: op.as_str().to_owned(),
@@ -1016,6 +1071,7 @@ impl<'a> QueryFilter<'a> {
             | Value::Bytes(_)
             | Value::BigDecimal(_)
             | Value::Int(_)
+            | Value::Timestamp(_)
             | Value::List(_)
             | Value::Null => {
                 return Err(UnsupportedFilter {
@@ -1189,7 +1245,7 @@ impl<'a> InsertQuery<'a> {
                         .cloned()
                         .collect::<Vec<Value>>();
                     if !fulltext_field_values.is_empty() {
-                        entity.insert(column.field.to_string(), Value::List(fulltext_field_values));
+                        entity.insert(column.field.clone(), Value::List(fulltext_field_values));
                     }
                 }
                 None => (),
@@ -1931,6 +1987,17 @@ impl<'a> FilterCollection<'a> {
         }
     }
 
+    /// The tables from which the collection takes entities
+    fn tables(&self) -> Vec<&'a Table> {
+        match self {
+            FilterCollection::All(entities) => entities.iter().map(|pair| pair.0).collect(),
+            FilterCollection::SingleWindow(window) => vec![window.table],
+            FilterCollection::MultiWindow(windows, _) => {
+                windows.iter().map(|window| window.table).collect()
+            }
+        }
+    }
+
     fn first_table(&self) -> Option<&Table> {
         match self {
             FilterCollection::All(entities) => entities.first().map(|pair| pair.0),
@@ -1972,7 +2039,7 @@ impl<'a> SortKey<'a> {
 
         fn with_key<'a>(
             table: &'a Table,
-            attribute: String,
+            attribute: Attribute,
             filter: Option<&'a EntityFilter>,
             direction: &'static str,
         ) -> Result<SortKey<'a>, QueryExecutionError> {
@@ -2010,6 +2077,23 @@ impl<'a> SortKey<'a> {
         }
     }
 
+    /// The column we sort by if it can contain `NULL`, which is the case
+    /// for attributes that are not required. Fulltext and list columns
+    /// are sorted by expressions that we can not split on `NULL`
+    fn nullable_column(&self) -> Option<&'a Column> {
+        match self {
+            SortKey::None | SortKey::Id => None,
+            SortKey::Key {
+                column,
+                value: None,
+                direction: _,
+            } if column.is_nullable() && !column.is_list() && !column.is_primary_key() => {
+                Some(*column)
+            }
+            SortKey::Key { .. } => None,
+        }
+    }
+
     /// Generate selecting the sort key if it is needed
     fn select(&self, out: &mut AstPass<Pg>) -> QueryResult<()> {
         match self {
@@ -2142,6 +2226,9 @@ pub struct FilterQuery<'a> {
     collection: &'a FilterCollection<'a>,
     sort_key: SortKey<'a>,
     range: FilterRange,
+    /// The columns to put into the data of entities, by entity type; we
+    /// put all columns into the data of types that are not in here
+    columns: HashMap<&'a str, Vec<&'a Column>>,
     block: BlockNumber,
     query_id: Option<String>,
 }
@@ -2152,6 +2239,7 @@ impl<'a> FilterQuery<'a> {
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        attributes: &BTreeMap<String, BTreeSet<String>>,
         block: BlockNumber,
         query_id: Option<String>,
     ) -> Result<Self, QueryExecutionError> {
@@ -2164,15 +2252,64 @@ impl<'a> FilterQuery<'a> {
             .expect("an entity query always contains at least one entity type/table");
         let sort_key = SortKey::new(order, first_table, filter)?;
 
+        let mut columns = HashMap::new();
+        for table in collection.tables() {
+            if let Some(attributes) = attributes.get(table.object.as_str()) {
+                let mut selected = vec![table.primary_key()];
+                for attribute in attributes {
+                    let column = table.column_for_field(attribute)?;
+                    if !column.is_primary_key() {
+                        selected.push(column);
+                    }
+                }
+                columns.insert(table.object.as_str(), selected);
+            }
+        }
+
         Ok(FilterQuery {
             collection,
             sort_key,
             range: FilterRange(range),
+            columns,
             block,
             query_id,
         })
     }
 
+    /// Generate the JSONB data for the row `c` of the table for `object`.
+    /// That is `to_jsonb(c.*)` unless we only need some of the columns, in
+    /// which case we generate `jsonb_build_object('col', c.col, ..)`. Since
+    /// `jsonb_build_object` takes at most 100 arguments, we concatenate
+    /// several of them for large selections
+    fn select_data(&self, object: &str, out: &mut AstPass<Pg>) -> QueryResult<()> {
+        const MAX_COLUMNS: usize = 50;
+
+        let columns = match self.columns.get(object) {
+            Some(columns) => columns,
+            None => {
+                out.push_sql("to_jsonb(c.*)");
+                return Ok(());
+            }
+        };
+        for (i, chunk) in columns.chunks(MAX_COLUMNS).enumerate() {
+            if i > 0 {
+                out.push_sql(" || ");
+            }
+            out.push_sql("jsonb_build_object(");
+            for (j, column) in chunk.iter().enumerate() {
+                if j > 0 {
+                    out.push_sql(", ");
+                }
+                out.push_sql("'");
+                out.push_sql(column.name.as_str());
+                out.push_sql("', c.");
+                out.push_identifier(column.name.as_str())?;
+            }
+            out.push_sql(")");
+        }
+        Ok(())
+    }
+
     /// Generate
     ///     from schema.table c
     ///    where block_range @> $block
@@ -2198,10 +2335,24 @@ impl<'a> FilterQuery<'a> {
         Ok(())
     }
 
-    fn select_entity_and_data(table: &Table, out: &mut AstPass<Pg>) {
+    /// Generate `select '..' as entity, {data} as data`. When `c` has a
+    /// `g$parent_id` column, `with_parent_id` must be `true` so that the
+    /// data contains it
+    fn select_entity_and_data(
+        &self,
+        table: &Table,
+        with_parent_id: bool,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
         out.push_sql("select '");
         out.push_sql(&table.object);
-        out.push_sql("' as entity, to_jsonb(c.*) as data");
+        out.push_sql("' as entity, ");
+        self.select_data(&table.object, out)?;
+        if with_parent_id && self.columns.contains_key(table.object.as_str()) {
+            out.push_sql(" || jsonb_build_object('g$parent_id', c.g$parent_id)");
+        }
+        out.push_sql(" as data");
+        Ok(())
     }
 
     /// Only one table/filter pair, and no window
@@ -2216,13 +2367,20 @@ impl<'a> FilterQuery<'a> {
     ///         where block_range @> $block
     ///           and filter
     ///         order by .. limit .. skip ..) c
+    ///
+    /// When we sort by an attribute that can be `NULL`, the query is split
+    /// on `NULL`, see `query_no_window_one_entity_nulls`
     fn query_no_window_one_entity(
         &self,
         table: &Table,
         filter: &Option<QueryFilter>,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
-        Self::select_entity_and_data(table, &mut out);
+        if let (Some(column), Some(first)) = (self.sort_key.nullable_column(), self.range.0.first) {
+            return self.query_no_window_one_entity_nulls(table, filter, column, first, out);
+        }
+
+        self.select_entity_and_data(table, false, &mut out)?;
         out.push_sql(" from (select * ");
         self.filtered_rows(table, filter, out.reborrow())?;
         out.push_sql("\n ");
@@ -2232,6 +2390,70 @@ impl<'a> FilterQuery<'a> {
         Ok(())
     }
 
+    /// Only one table/filter pair, no window, and sorting by a `column`
+    /// that can be `NULL`
+    ///
+    /// A single `order by {column}, id` has to sort all rows matching the
+    /// filter, including all the rows where `column` is `NULL`, for every
+    /// page. We therefore get the rows where `column` is not `NULL`, which
+    /// can use the index on `column` in either direction, separately from
+    /// the rows where it is `NULL`, which are ordered by `id` alone. We sort
+    /// `NULL` last in both directions, so that the rows from the first
+    /// branch always come before the rows from the second one. Each branch
+    /// only needs to produce as many rows as the page could possibly use
+    ///
+    ///   select '..' as entity, to_jsonb(e.*) as data
+    ///     from
+    ///       ((select *
+    ///           from table c
+    ///          where block_range @> $block
+    ///            and filter
+    ///            and c.{column} is not null
+    ///          order by {column} {direction} nulls last, id
+    ///          limit {first + skip})
+    ///        union all
+    ///        (select *
+    ///           from table c
+    ///          where block_range @> $block
+    ///            and filter
+    ///            and c.{column} is null
+    ///          order by id
+    ///          limit {first + skip})
+    ///        order by .. limit .. skip ..) c
+    fn query_no_window_one_entity_nulls(
+        &self,
+        table: &Table,
+        filter: &Option<QueryFilter>,
+        column: &Column,
+        first: u32,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let branch_limit = (first as u64 + self.range.0.skip as u64).to_string();
+
+        self.select_entity_and_data(table, false, &mut out)?;
+        out.push_sql(" from ((select * ");
+        self.filtered_rows(table, filter, out.reborrow())?;
+        out.push_sql("   and c.");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(" is not null\n ");
+        self.sort_key.order_by(&mut out)?;
+        out.push_sql("\n limit ");
+        out.push_sql(&branch_limit);
+        out.push_sql(")\nunion all\n(select * ");
+        self.filtered_rows(table, filter, out.reborrow())?;
+        out.push_sql("   and c.");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(" is null\n order by ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("\n limit ");
+        out.push_sql(&branch_limit);
+        out.push_sql(")\n ");
+        self.sort_key.order_by(&mut out)?;
+        self.range.walk_ast(out.reborrow())?;
+        out.push_sql(") c");
+        Ok(())
+    }
+
     /// Only one table/filter pair, and a window
     ///
     /// Generate a query
@@ -2244,7 +2466,7 @@ impl<'a> FilterQuery<'a> {
         window: &FilterWindow,
         mut out: AstPass<Pg>,
     ) -> QueryResult<()> {
-        Self::select_entity_and_data(&window.table, &mut out);
+        self.select_entity_and_data(&window.table, true, &mut out)?;
         out.push_sql(" from (\n");
         out.push_sql("select c.*, p.id::text as g$parent_id");
         window.children(
@@ -2268,7 +2490,8 @@ impl<'a> FilterQuery<'a> {
         // need to do the query in two steps: first we build a CTE with the
         // id's of entities matching the filter and order/limit. As a second
         // step, we get matching rows from the underlying tables and convert
-        // them to JSONB.
+        // them to JSONB. When we do not need all the columns of a table, we
+        // only convert the ones we need rather than the entire row.
         //
         // Overall, we generate a query
         //
@@ -2314,7 +2537,9 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            out.push_sql("select m.entity, to_jsonb(c.*) as data, c.id");
+            out.push_sql("select m.entity, ");
+            self.select_data(&table.object, &mut out)?;
+            out.push_sql(" as data, c.id");
             self.sort_key.select(&mut out)?;
             out.push_sql("\n  from ");
             out.push_sql(table.qualified_name.as_str());
@@ -2394,10 +2619,9 @@ impl<'a> FilterQuery<'a> {
             if i > 0 {
                 out.push_sql("\nunion all\n");
             }
-            out.push_sql(
-                "select m.*, \
-                 to_jsonb(c.*) || jsonb_build_object('g$parent_id', m.g$parent_id) as data",
-            );
+            out.push_sql("select m.*, ");
+            self.select_data(object, &mut out)?;
+            out.push_sql(" || jsonb_build_object('g$parent_id', m.g$parent_id) as data");
             out.push_sql("\n  from ");
             out.push_sql(table_name.as_str());
             out.push_sql(" c, matches m\n where c.vid = m.vid and m.entity = '");
@@ -2463,11 +2687,12 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
 /// Reduce the upper bound of the current entry's block range to `block` as
-/// long as that does not result in an empty block range
+/// long as that does not result in an empty block range, for each of the
+/// entities with the given `entity_ids`
 #[derive(Debug, Clone, Constructor)]
 pub struct ClampRangeQuery<'a> {
     table: &'a Table,
-    key: &'a EntityKey,
+    entity_ids: &'a [&'a str],
     block: BlockNumber,
 }
 
@@ -2475,7 +2700,7 @@ impl<'a> QueryFragment<Pg> for ClampRangeQuery<'a> {
     fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
         // update table
         //    set block_range = int4range(lower(block_range), $block)
-        //  where id = $id
+        //  where id = $id (or id = any($ids))
         //    and block_range @> INTMAX
         out.unsafe_to_cache_prepared();
         out.push_sql("update ");
@@ -2487,7 +2712,10 @@ impl<'a> QueryFragment<Pg> for ClampRangeQuery<'a> {
         out.push_sql("), ");
         out.push_bind_param::<Integer, _>(&self.block)?;
         out.push_sql(")\n where ");
-        self.table.primary_key().eq(&self.key.entity_id, &mut out)?;
+        match self.entity_ids {
+            [entity_id] => self.table.primary_key().eq(entity_id, &mut out)?,
+            entity_ids => self.table.primary_key().eq_any(entity_ids, &mut out)?,
+        }
         out.push_sql(" and (");
         out.push_sql(BLOCK_RANGE_CURRENT);
         out.push_sql(")");
@@ -2650,6 +2878,285 @@ fn block_number_max_is_i32_max() {
     assert_eq!(2147483647, graph::prelude::BLOCK_NUMBER_MAX);
 }
 
+/// A query that removes all versions that stopped being current before
+/// `earliest_block`, i.e., whose block range lies entirely before it
+#[derive(Debug, Clone, Constructor)]
+pub struct PruneQuery<'a> {
+    table: &'a Table,
+    earliest_block: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for PruneQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where coalesce(upper(block_range), 2147483647) <= $earliest_block
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where coalesce(upper(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), 2147483647) <= ");
+        out.push_bind_param::<Integer, _>(&self.earliest_block)?;
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneQuery<'a> {}
+
+/// A query that removes all versions of the entities in `table` that were
+/// last changed before `expired_before`. An entity is changed when one of
+/// its versions starts or ends
+#[derive(Debug, Clone, Constructor)]
+pub struct ExpireQuery<'a> {
+    table: &'a Table,
+    expired_before: BlockNumber,
+}
+
+impl<'a> QueryFragment<Pg> for ExpireQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where id in (select id from table
+        //                  group by id
+        //                 having max(coalesce(upper(block_range),
+        //                                     lower(block_range))) < $expired_before)
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(
+            "
+ where ",
+        );
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" in (select ");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(
+            "
+ group by ",
+        );
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(
+            "
+ having max(coalesce(upper(",
+        );
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), lower(");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("))) < ");
+        out.push_bind_param::<Integer, _>(&self.expired_before)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for ExpireQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExpireQuery<'a> {}
+
+/// The ids of entities that have versions whose block ranges overlap with
+/// the block range of another version of the same entity, together with
+/// the number of such versions
+#[derive(QueryableByName)]
+pub struct OverlappingEntityData {
+    #[sql_type = "Text"]
+    pub id: String,
+    #[sql_type = "BigInt"]
+    pub versions: i64,
+}
+
+/// Push the condition that the versions `a` and `b` of an entity overlap
+fn push_overlap_condition(out: &mut AstPass<Pg>) -> QueryResult<()> {
+    out.push_sql("a.");
+    out.push_identifier(PRIMARY_KEY_COLUMN)?;
+    out.push_sql(" = b.");
+    out.push_identifier(PRIMARY_KEY_COLUMN)?;
+    out.push_sql(" and a.");
+    out.push_identifier(VID_COLUMN)?;
+    out.push_sql(" <> b.");
+    out.push_identifier(VID_COLUMN)?;
+    out.push_sql(" and a.");
+    out.push_identifier(BLOCK_RANGE_COLUMN)?;
+    out.push_sql(" && b.");
+    out.push_identifier(BLOCK_RANGE_COLUMN)
+}
+
+/// A query that finds the entities that have several versions that are
+/// visible at the same block. That should never happen, but interrupted
+/// reverts in older versions of `graph-node` could leave such versions
+/// behind
+#[derive(Debug, Clone, Constructor)]
+pub struct OverlappingBlockRangesQuery<'a> {
+    table: &'a Table,
+}
+
+impl<'a> QueryFragment<Pg> for OverlappingBlockRangesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   select a.id::text as id, count(*) as versions
+        //     from table a
+        //    where exists (select 1 from table b
+        //                   where a.id = b.id and a.vid <> b.vid
+        //                     and a.block_range && b.block_range)
+        //    group by a.id
+        //    order by a.id
+        out.push_sql("select a.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("::text as id, count(*) as versions\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" a\n where exists (select 1 from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" b where ");
+        push_overlap_condition(&mut out)?;
+        out.push_sql(")\n group by a.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql("\n order by a.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)
+    }
+}
+
+impl<'a> QueryId for OverlappingBlockRangesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, OverlappingEntityData> for OverlappingBlockRangesQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<OverlappingEntityData>> {
+        conn.query_by_name(&self)
+            .map(|mut data: Vec<OverlappingEntityData>| {
+                if let IdType::Bytes = self.table.primary_key().column_type.id_type() {
+                    for entry in data.iter_mut() {
+                        entry.id = bytes_as_str(&entry.id);
+                    }
+                }
+                data
+            })
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for OverlappingBlockRangesQuery<'a> {}
+
+/// A query that removes versions whose block range starts at the same
+/// block as the block range of a later version of the same entity. The
+/// version that was written last wins
+#[derive(Debug, Clone, Constructor)]
+pub struct RemoveShadowedVersionsQuery<'a> {
+    table: &'a Table,
+}
+
+impl<'a> QueryFragment<Pg> for RemoveShadowedVersionsQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table a
+        //    using table b
+        //    where a.id = b.id and a.vid <> b.vid
+        //      and a.block_range && b.block_range
+        //      and lower(a.block_range) = lower(b.block_range)
+        //      and a.vid < b.vid
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" a\n using ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" b\n where ");
+        push_overlap_condition(&mut out)?;
+        out.push_sql("\n   and lower(a.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") = lower(b.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")\n   and a.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" < b.");
+        out.push_identifier(VID_COLUMN)
+    }
+}
+
+impl<'a> QueryId for RemoveShadowedVersionsQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for RemoveShadowedVersionsQuery<'a> {}
+
+/// A query that clamps the block range of each version that overlaps with
+/// versions of the same entity that start later so that it ends where the
+/// earliest of these later versions starts. The version with the latest
+/// lower bound therefore wins
+#[derive(Debug, Clone, Constructor)]
+pub struct ClampOverlappingRangesQuery<'a> {
+    table: &'a Table,
+}
+
+impl<'a> QueryFragment<Pg> for ClampOverlappingRangesQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   update table c
+        //      set block_range = int4range(lower(c.block_range), o.next)
+        //     from (select a.vid, min(lower(b.block_range)) as next
+        //             from table a, table b
+        //            where a.id = b.id and a.vid <> b.vid
+        //              and a.block_range && b.block_range
+        //              and lower(a.block_range) < lower(b.block_range)
+        //            group by a.vid) o
+        //    where c.vid = o.vid
+        out.push_sql("update ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c\n   set ");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(" = int4range(lower(c.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql("), o.next)\n  from (select a.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" as vid, min(lower(b.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")) as next\n          from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" a, ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" b\n         where ");
+        push_overlap_condition(&mut out)?;
+        out.push_sql("\n           and lower(a.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(") < lower(b.");
+        out.push_identifier(BLOCK_RANGE_COLUMN)?;
+        out.push_sql(")\n         group by a.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(") o\n where c.");
+        out.push_identifier(VID_COLUMN)?;
+        out.push_sql(" = o.vid");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for ClampOverlappingRangesQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ClampOverlappingRangesQuery<'a> {}
+
 /// A query that removes all dynamic data sources for a given subgraph
 /// whose block range lies entirely beyond `block`. The query only deletes
 /// the data sources but not any related objects
@@ -51,6 +51,7 @@ impl ToSql<Text, Pg> for SqlValue {
         match self.0 {
             Value::String(ref s) => <String as ToSql<Text, Pg>>::to_sql(&s, out),
             Value::Bytes(ref h) => <String as ToSql<Text, Pg>>::to_sql(&h.to_string(), out),
+            Value::Timestamp(ref ts) => <String as ToSql<Text, Pg>>::to_sql(&ts.to_string(), out),
             _ => panic!("Failed to convert attribute value to String or Bytes in SQL"),
         }
     }
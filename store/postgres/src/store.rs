@@ -8,27 +8,35 @@ use graph::prelude::{CancelGuard, CancelHandle, CancelToken, CancelableError};
 use lazy_static::lazy_static;
 use lru_time_cache::LruCache;
 use rand::{seq::SliceRandom, thread_rng};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::iter::FromIterator;
 use std::ops::Deref;
+use std::str::FromStr;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::Semaphore;
 
-use graph::components::store::{EntityCollection, QueryStore, Store as StoreTrait};
+use graph::components::ethereum::REORG_THRESHOLD;
+use graph::components::store::{
+    DeploymentStore, EntityCollection, EntityStore, QueryStore, SubgraphNameStore,
+};
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::data::subgraph::schema::{
-    SubgraphDeploymentEntity, TypedEntity as _, POI_OBJECT, SUBGRAPHS_ID,
+    MetadataType, SubgraphDeploymentEntity, SubgraphError, TypedEntity as _, POI_OBJECT,
+    SUBGRAPHS_ID,
 };
 use graph::prelude::{
     debug, ethabi, format_err, futures03, info, o, tiny_keccak, tokio, trace, warn, web3,
-    ApiSchema, AttributeIndexDefinition, BigInt, BlockNumber, CheapClone, DeploymentState,
-    DynTryFuture, Entity, EntityKey, EntityModification, EntityOrder, EntityQuery, EntityRange,
-    Error, EthereumBlockPointer, EthereumCallCache, Logger, MetadataOperation, MetricsRegistry,
-    QueryExecutionError, Schema, StopwatchMetrics, StoreError, StoreEvent, StoreEventStreamBox,
-    SubgraphAssignmentProviderError, SubgraphDeploymentId, SubgraphDeploymentStore,
-    SubgraphEntityPair, SubgraphName, TransactionAbortError, Value, BLOCK_NUMBER_MAX,
+    ApiSchema, AttributeIndexDefinition, BigInt, BlockNumber, BlockRangeOverlap,
+    CachedEthereumCall, CheapClone, DeploymentFlag, DeploymentState, DynTryFuture, Entity,
+    EntityKey, EntityModification, EntityOrder, EntityQuery, EntityRange, Error,
+    EthereumBlockPointer, EthereumCallCache, EthereumCallRevert, GraftLineage, HealthIssue,
+    HealthIssueKind, HealthRemediation, Logger, MetadataOperation, MetricsRegistry, NodeId,
+    Provenance, QueryExecutionError, ScanWatermark, Schema, StopwatchMetrics, StoreError,
+    StoreEvent, StoreEventStreamBox, SubgraphAssignmentProviderError, SubgraphDeploymentId,
+    SubgraphDeploymentStore, SubgraphEntityPair, SubgraphName, TransactionAbortError, Value,
+    BLOCK_NUMBER_MAX,
 };
 
 use graph_graphql::prelude::api_schema;
@@ -39,6 +47,7 @@ use crate::history_event::HistoryEvent;
 use crate::metadata;
 use crate::relational_queries::FromEntityData;
 use crate::store_events::SubscriptionManager;
+use crate::transact_metrics::{Phase, TransactMetrics};
 
 lazy_static! {
     static ref CONNECTION_LIMITER: Semaphore = {
@@ -51,6 +60,10 @@ lazy_static! {
     };
 }
 
+/// How often we update when a deployment was last used; updating that for
+/// every query would be too expensive
+const LAST_USED_UPDATE_INTERVAL: Duration = Duration::from_secs(600);
+
 embed_migrations!("./migrations");
 
 /// Run all schema migrations.
@@ -126,6 +139,35 @@ pub struct StoreConfig {
     pub network_name: String,
 }
 
+/// What to do about inconsistent subgraph metadata that is found when the
+/// metadata is checked at startup
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConsistencyPolicy {
+    /// Only report the issues; the node should not start if there are any
+    Refuse,
+    /// Do not start the deployments that are affected
+    Skip,
+    /// Repair what can be repaired without losing data, and do not start
+    /// the deployments that are affected by the remaining issues
+    Repair,
+}
+
+impl FromStr for ConsistencyPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(ConsistencyPolicy::Refuse),
+            "skip" => Ok(ConsistencyPolicy::Skip),
+            "repair" => Ok(ConsistencyPolicy::Repair),
+            _ => Err(format_err!(
+                "unknown consistency policy `{}`, expected one of `refuse`, `skip`, or `repair`",
+                s
+            )),
+        }
+    }
+}
+
 /// When connected to read replicas, this allows choosing which DB server to use for an operation.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ReplicaId {
@@ -150,6 +192,8 @@ struct SubgraphInfo {
     /// The block number at which this subgraph was grafted onto
     /// another one. We do not allow reverting past this block
     graft_block: Option<BlockNumber>,
+    /// Whether the manifest of the subgraph sets `strictBytes`
+    strict_bytes: bool,
 }
 
 pub struct StoreInner {
@@ -169,6 +213,15 @@ pub struct StoreInner {
     /// the entities module
     pub(crate) storage_cache: e::StorageCache,
 
+    /// When we last recorded in the database that a deployment was used
+    last_used: Mutex<HashMap<SubgraphDeploymentId, Instant>>,
+
+    /// Deployments that must not be started because the last check of
+    /// the metadata found issues with them
+    skipped_deployments: Mutex<HashSet<SubgraphDeploymentId>>,
+
+    transact_metrics: TransactMetrics,
+
     registry: Arc<dyn MetricsRegistry>,
 }
 
@@ -231,6 +284,9 @@ impl Store {
             conn_round_robin_counter: AtomicUsize::new(0),
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             storage_cache: e::make_storage_cache(),
+            last_used: Mutex::new(HashMap::new()),
+            skipped_deployments: Mutex::new(HashSet::new()),
+            transact_metrics: TransactMetrics::new(registry.cheap_clone()),
             registry,
         };
         let store = Store(Arc::new(store));
@@ -239,23 +295,291 @@ impl Store {
         store
     }
 
+    /// The metrics for the phases of `transact_block_operations`
+    pub fn transact_metrics(&self) -> &TransactMetrics {
+        &self.transact_metrics
+    }
+
+    /// Remember that the deployment `id` was used just now
+    fn deployment_used(
+        &self,
+        conn: &PgConnection,
+        id: &SubgraphDeploymentId,
+    ) -> Result<(), StoreError> {
+        let now = Instant::now();
+        {
+            let mut last_used = self.last_used.lock().unwrap();
+            if let Some(used) = last_used.get(id) {
+                if now.duration_since(*used) < LAST_USED_UPDATE_INTERVAL {
+                    return Ok(());
+                }
+            }
+            last_used.insert(id.clone(), now);
+        }
+        metadata::deployment_used(conn, id)
+    }
+
+    /// Look for deployments that are not in use. Deployments that have not
+    /// been used for `grace_period` are recorded as unused, and those that
+    /// have been recorded as unused for `removal_delay` are removed. Until
+    /// a deployment is removed, using it again or calling
+    /// `restore_unused_deployment` takes it off the list of unused
+    /// deployments again.
+    pub fn remove_unused_deployments(
+        &self,
+        logger: &Logger,
+        grace_period: Duration,
+        removal_delay: Duration,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+
+        for id in metadata::unrecord_used_deployments(&conn)? {
+            info!(logger, "Deployment is in use again and will not be removed";
+                          "deployment" => id.as_str());
+        }
+        for id in metadata::record_unused_deployments(&conn, grace_period)? {
+            info!(logger, "Deployment is unused and will be removed";
+                          "deployment" => id.as_str(),
+                          "removal_delay_secs" => removal_delay.as_secs());
+        }
+        for id in metadata::removable_deployments(&conn, removal_delay)? {
+            if self.drop_deployment(&conn, &id, removal_delay)? {
+                info!(logger, "Removed unused deployment"; "deployment" => id.as_str());
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the data and metadata of the deployment `id` if it is still
+    /// removable and no other deployment is grafted onto it, and return
+    /// whether it was removed
+    fn drop_deployment(
+        &self,
+        conn: &PgConnection,
+        id: &SubgraphDeploymentId,
+        removal_delay: Duration,
+    ) -> Result<bool, StoreError> {
+        let dropped = conn.transaction(|| -> Result<bool, StoreError> {
+            // Check again in case something started using the deployment
+            // since we last looked
+            if !metadata::removable_deployments(conn, removal_delay)?.contains(id)
+                || !metadata::graft_dependents(conn, id)?.is_empty()
+            {
+                return Ok(false);
+            }
+            e::drop_schema(conn, id)?;
+            metadata::drop_deployment_metadata(conn, id)?;
+            Ok(true)
+        })?;
+        if dropped {
+            self.forget_deployment(id);
+        }
+        Ok(dropped)
+    }
+
+    /// Remove the data and metadata of the deployment `id` right away,
+    /// whether it is still used or not. Return `false` if the deployment
+    /// does not exist, and an error if other deployments are grafted onto
+    /// it since they could not be indexed without it
+    pub fn remove_deployment(&self, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        let removed = conn.transaction(|| -> Result<bool, StoreError> {
+            let dependents = metadata::graft_dependents(&conn, id)?;
+            if !dependents.is_empty() {
+                return Err(StoreError::ConstraintViolation(format!(
+                    "deployment `{}` can not be removed because {} other deployments \
+                     are grafted onto it, for example `{}`",
+                    id,
+                    dependents.len(),
+                    dependents[0]
+                )));
+            }
+            let removed = e::drop_schema(&conn, id)? > 0;
+            metadata::drop_deployment_metadata(&conn, id)?;
+            Ok(removed)
+        })?;
+        self.forget_deployment(id);
+        Ok(removed)
+    }
+
+    /// The ids of all deployments in the store
+    pub fn deployment_ids(&self) -> Result<Vec<SubgraphDeploymentId>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::deployment_ids(&conn)
+    }
+
+    /// Drop everything we cache about the deployment `id`
+    fn forget_deployment(&self, id: &SubgraphDeploymentId) {
+        self.storage_cache.lock().unwrap().remove(id);
+        self.subgraph_cache.lock().unwrap().remove(id);
+        self.last_used.lock().unwrap().remove(id);
+    }
+
+    /// Cross-check assignments, deployment schemas, deployment metadata
+    /// and subgraph names, log every inconsistency, and deal with it as
+    /// `policy` says. The issues replace the ones that were recorded by
+    /// the previous check and are returned with what was done about them
+    pub fn check_consistency(
+        &self,
+        logger: &Logger,
+        policy: ConsistencyPolicy,
+    ) -> Result<Vec<HealthIssue>, StoreError> {
+        let conn = self.get_conn()?;
+
+        self.skipped_deployments.lock().unwrap().clear();
+        let issues = conn.transaction(|| -> Result<_, StoreError> {
+            let mut issues = metadata::find_health_issues(&conn)?;
+            for issue in issues.iter_mut() {
+                issue.remediation = self.remediate(&conn, issue, policy)?;
+            }
+            metadata::record_health_issues(&conn, &issues)?;
+            Ok(issues)
+        })?;
+
+        for issue in &issues {
+            warn!(logger, "Inconsistent subgraph metadata";
+                          "kind" => issue.kind.as_str(),
+                          "deployment" => issue.deployment.as_deref().unwrap_or(""),
+                          "subgraph_name" => issue.subgraph_name.as_deref().unwrap_or(""),
+                          "remediation" => issue.remediation.as_str(),
+                          "message" => &issue.message);
+        }
+        Ok(issues)
+    }
+
+    /// Deal with `issue` according to `policy`. The only repair we make is
+    /// removing the assignment of a deployment that can not be indexed,
+    /// since that never loses any data
+    fn remediate(
+        &self,
+        conn: &PgConnection,
+        issue: &HealthIssue,
+        policy: ConsistencyPolicy,
+    ) -> Result<HealthRemediation, StoreError> {
+        let deployment = match &issue.deployment {
+            Some(deployment) => deployment,
+            None => return Ok(HealthRemediation::None),
+        };
+        if policy == ConsistencyPolicy::Repair {
+            match issue.kind {
+                HealthIssueKind::AssignmentWithoutDeployment
+                | HealthIssueKind::DeploymentWithoutSchema => {
+                    if metadata::remove_assignment(conn, deployment)? {
+                        return Ok(HealthRemediation::Repaired);
+                    }
+                }
+                HealthIssueKind::SchemaWithoutDeployment
+                | HealthIssueKind::VersionWithoutDeployment
+                | HealthIssueKind::NameWithoutVersion => {}
+            }
+        }
+        if policy == ConsistencyPolicy::Refuse {
+            return Ok(HealthRemediation::None);
+        }
+        match SubgraphDeploymentId::new(deployment.clone()) {
+            Ok(id) => {
+                self.skipped_deployments.lock().unwrap().insert(id);
+                Ok(HealthRemediation::Skipped)
+            }
+            Err(_) => Ok(HealthRemediation::None),
+        }
+    }
+
+    /// Remove the entity history that deployments assigned to `node` do
+    /// not need to keep anymore because they only keep a limited number of
+    /// blocks of history. Deployments are only pruned once they are synced.
+    /// Entities of `@ttl` types that expired are removed from all
+    /// deployments assigned to `node`
+    pub fn prune_deployments(&self, logger: &Logger, node: &NodeId) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+
+        for (id, earliest_block) in metadata::prunable_deployments(&conn, node)? {
+            let start = Instant::now();
+            let removed = self.prune_deployment(&id, earliest_block)?;
+            info!(logger, "Pruned deployment history";
+                          "deployment" => id.as_str(),
+                          "earliest_block" => earliest_block,
+                          "removed_versions" => removed,
+                          "time_ms" => start.elapsed().as_millis());
+        }
+
+        for (id, head) in metadata::expirable_deployments(&conn, node)? {
+            // A revert can make the deployment process the blocks up to the
+            // reorg threshold behind its head again, and mappings must see
+            // the same entities then as they did the first time
+            let block = head.saturating_sub(*REORG_THRESHOLD as BlockNumber);
+            let start = Instant::now();
+            let econn = self.get_entity_conn(&id, ReplicaId::Main)?;
+            let removed = econn.transaction(|| econn.expire(block))?;
+            if removed > 0 {
+                info!(logger, "Removed expired entities";
+                              "deployment" => id.as_str(),
+                              "block" => block,
+                              "removed_versions" => removed,
+                              "time_ms" => start.elapsed().as_millis());
+            }
+        }
+        Ok(())
+    }
+
+    /// Make `earliest_block` the earliest block for which `id` can be
+    /// queried, and then remove the entity versions that are not needed
+    /// for that anymore. We record the earliest block first so that
+    /// queries never see a partially pruned block.
+    fn prune_deployment(
+        &self,
+        id: &SubgraphDeploymentId,
+        earliest_block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let econn = self.get_entity_conn(id, ReplicaId::Main)?;
+        let hash = self.block_hash(&econn.conn, id, earliest_block)?;
+
+        econn
+            .transaction(|| metadata::set_earliest_block(&econn.conn, id, earliest_block, hash))?;
+        econn.transaction(|| econn.prune(earliest_block))
+    }
+
+    /// The hash of block `number` on the network of the deployment `id`.
+    /// Return `None` if we do not know it for sure because the block is not
+    /// in the block cache or there is more than one block with that number
+    fn block_hash(
+        &self,
+        conn: &PgConnection,
+        id: &SubgraphDeploymentId,
+        number: BlockNumber,
+    ) -> Result<Option<H256>, StoreError> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        let network_name = match self.network_name(id)? {
+            Some(network_name) => network_name,
+            None => return Ok(None),
+        };
+        let hashes: Vec<String> = dsl::ethereum_blocks
+            .select(dsl::hash)
+            .filter(dsl::network_name.eq(network_name))
+            .filter(dsl::number.eq(number as i64))
+            .limit(2)
+            .load(conn)?;
+        match hashes.as_slice() {
+            [hash] => Ok(hash.parse::<H256>().ok()),
+            _ => Ok(None),
+        }
+    }
+
     /// Gets an entity from Postgres.
     fn get_entity(
         &self,
         conn: &e::Connection,
         op_subgraph: &SubgraphDeploymentId,
-        op_entity: &String,
-        op_id: &String,
+        op_entity: &str,
+        op_id: &str,
+        block: BlockNumber,
     ) -> Result<Option<Entity>, QueryExecutionError> {
-        // We should really have callers pass in a block number; but until
-        // that is fully plumbed in, we just use the biggest possible block
-        // number so that we will always return the latest version,
-        // i.e., the one with an infinite upper bound
-        conn.find(op_entity, op_id, BLOCK_NUMBER_MAX).map_err(|e| {
+        conn.find(op_entity, op_id, block).map_err(|e| {
             QueryExecutionError::ResolveEntityError(
                 op_subgraph.clone(),
-                op_entity.clone(),
-                op_id.clone(),
+                op_entity.to_owned(),
+                op_id.to_owned(),
                 format!("Invalid entity {}", e),
             )
         })
@@ -274,6 +598,7 @@ impl Store {
             query.filter,
             query.order,
             query.range,
+            query.attributes,
             query.block,
             query.query_id,
         )
@@ -312,7 +637,7 @@ impl Store {
                 conn.conflicting_entity(&key.entity_id, types_with_shared_interface)?
             {
                 return Err(StoreError::ConflictingId(
-                    key.entity_type.clone(),
+                    key.entity_type.to_string(),
                     key.entity_id.clone(),
                     conflicting_entity,
                 ));
@@ -335,7 +660,13 @@ impl Store {
 
                 // Load the entity if exists
                 let entity = self
-                    .get_entity(conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+                    .get_entity(
+                        conn,
+                        &key.subgraph_id,
+                        &key.entity_type,
+                        &key.entity_id,
+                        BLOCK_NUMBER_MAX,
+                    )
                     .map_err(Error::from)?;
 
                 // Identify whether this is an insert or an update operation and
@@ -475,8 +806,16 @@ impl Store {
             use EntityModification::*;
 
             let do_count = !modification.entity_key().subgraph_id.is_meta();
+            transact_span!(
+                "entity_modification",
+                entity_type = modification.entity_key().entity_type.as_str()
+            );
+            let table_duration = self
+                .transact_metrics
+                .table_histogram(modification.entity_key().entity_type.as_str());
+            let start = Instant::now();
             let n = match modification {
-                Overwrite { key, data } => {
+                Overwrite { key, data, .. } => {
                     let section = stopwatch.start_section("check_interface_entity_uniqueness");
                     self.check_interface_entity_uniqueness(conn, &key)?;
                     section.end();
@@ -506,7 +845,21 @@ impl Store {
                         )
                         .into()
                     }),
+                RemoveMany { keys } => conn
+                    .delete_many(&keys, history_event)
+                    .map(|n| -(n as i32))
+                    .map_err(|e| {
+                        format_err!(
+                            "Failed to remove {} entities ({}, {}): {}",
+                            keys.len(),
+                            keys[0].subgraph_id,
+                            keys[0].entity_type,
+                            e
+                        )
+                        .into()
+                    }),
             }?;
+            table_duration.observe(start.elapsed().as_secs_f64());
             if do_count {
                 count += n;
             }
@@ -774,6 +1127,7 @@ impl Store {
 
         let graft_block =
             metadata::deployment_graft(&conn, &subgraph_id)?.map(|(_, ptr)| ptr.number as i32);
+        let strict_bytes = metadata::strict_bytes(&conn, &subgraph_id)?;
 
         // Generate an API schema for the subgraph and make sure all types in the
         // API schema have a @subgraphId directive as well
@@ -789,6 +1143,7 @@ impl Store {
             ),
             network,
             graft_block,
+            strict_bytes,
         };
 
         // Insert the schema into the cache.
@@ -837,7 +1192,7 @@ impl Store {
     }
 }
 
-impl StoreTrait for Store {
+impl EntityStore for Store {
     fn block_ptr(
         &self,
         subgraph_id: SubgraphDeploymentId,
@@ -928,6 +1283,7 @@ impl StoreTrait for Store {
                                     first: None,
                                     skip: 0,
                                 },
+                                BTreeMap::new(),
                                 block_number.try_into().unwrap(),
                                 None,
                             )
@@ -979,7 +1335,17 @@ impl StoreTrait for Store {
         let conn = self
             .get_entity_conn(&key.subgraph_id, ReplicaId::Main)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
-        self.get_entity(&conn, &key.subgraph_id, &key.entity_type, &key.entity_id)
+        // We should really have callers pass in a block number; but until
+        // that is fully plumbed in, we just use the biggest possible block
+        // number so that we will always return the latest version,
+        // i.e., the one with an infinite upper bound
+        self.get_entity(
+            &conn,
+            &key.subgraph_id,
+            &key.entity_type,
+            &key.entity_id,
+            BLOCK_NUMBER_MAX,
+        )
     }
 
     fn get_many(
@@ -996,6 +1362,41 @@ impl StoreTrait for Store {
         conn.find_many(ids_for_type, BLOCK_NUMBER_MAX)
     }
 
+    // The store does not have any versions that start after the block that
+    // a mapping processes, so reading at that block returns the latest
+    // version of an entity, except when it expired
+    fn get_for_block(
+        &self,
+        key: EntityKey,
+        block: BlockNumber,
+    ) -> Result<Option<Entity>, QueryExecutionError> {
+        let conn = self
+            .get_entity_conn(&key.subgraph_id, ReplicaId::Main)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.get_entity(
+            &conn,
+            &key.subgraph_id,
+            &key.entity_type,
+            &key.entity_id,
+            block,
+        )
+    }
+
+    fn get_many_for_block(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        ids_for_type: BTreeMap<&str, Vec<&str>>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Vec<Entity>>, StoreError> {
+        if ids_for_type.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let conn = self
+            .get_entity_conn(subgraph_id, ReplicaId::Main)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        conn.find_many(ids_for_type, block)
+    }
+
     fn find(&self, query: EntityQuery) -> Result<Vec<Entity>, QueryExecutionError> {
         let conn = self
             .get_entity_conn(&query.subgraph_id, ReplicaId::Main)
@@ -1043,10 +1444,21 @@ impl StoreTrait for Store {
         mods: Vec<EntityModification>,
         stopwatch: StopwatchMetrics,
     ) -> Result<bool, StoreError> {
+        self.transact_block_operations_batch(subgraph_id, vec![(block_ptr_to, mods)], stopwatch)
+            .map(|migrate_at| migrate_at.is_some())
+    }
+
+    fn transact_block_operations_batch(
+        &self,
+        subgraph_id: SubgraphDeploymentId,
+        blocks: Vec<(EthereumBlockPointer, Vec<EntityModification>)>,
+        stopwatch: StopwatchMetrics,
+    ) -> Result<Option<EthereumBlockPointer>, StoreError> {
         // All operations should apply only to entities in this subgraph or
         // the subgraph of subgraphs
-        if mods
+        if blocks
             .iter()
+            .flat_map(|(_, mods)| mods.iter())
             .map(|modification| modification.entity_key())
             .any(|key| key.subgraph_id != subgraph_id && key.subgraph_id != *SUBGRAPHS_ID)
         {
@@ -1056,71 +1468,106 @@ impl StoreTrait for Store {
             );
         }
 
+        let block_ptr_to = match blocks.last() {
+            Some((block_ptr, _)) => *block_ptr,
+            None => return Ok(None),
+        };
+
+        transact_span!(
+            "transact_block_operations",
+            deployment = subgraph_id.as_str(),
+            blocks = blocks.len()
+        );
+        let metrics = &self.transact_metrics;
+        let bucket = TransactMetrics::deployment_bucket(&subgraph_id);
+
         let econn = self.get_entity_conn(&subgraph_id, ReplicaId::Main)?;
 
-        let (event, metadata_event, should_migrate) =
+        let (event, metadata_event, migrate_at, commit_start) =
             econn.transaction(|| -> Result<_, StoreError> {
-                let block_ptr_from = Self::block_ptr_with_conn(&subgraph_id, &econn)?;
-                if let Some(ref block_ptr_from) = block_ptr_from {
-                    if block_ptr_from.number >= block_ptr_to.number {
-                        return Err(StoreError::DuplicateBlockProcessing(
-                            subgraph_id,
-                            block_ptr_to.number,
-                        ));
+                let start = Instant::now();
+                // Every block must come after the one before it, and the
+                // first one after the current subgraph block pointer
+                let mut block_ptr_from = Self::block_ptr_with_conn(&subgraph_id, &econn)?;
+                for (block_ptr, _) in &blocks {
+                    if let Some(ref block_ptr_from) = block_ptr_from {
+                        if block_ptr_from.number >= block_ptr.number {
+                            return Err(StoreError::DuplicateBlockProcessing(
+                                subgraph_id.clone(),
+                                block_ptr.number,
+                            ));
+                        }
                     }
+                    block_ptr_from = Some(*block_ptr);
                 }
 
-                // Ensure the history event exists in the database
-                let history_event = econn.create_history_event(block_ptr_to, &mods)?;
-
-                let should_migrate = econn.should_migrate(&subgraph_id, &block_ptr_to)?;
-
                 // Emit a store event for the changes we are about to make. We
                 // wait with sending it until we have done all our other work
                 // so that we do not hold a lock on the notification queue
                 // for longer than we have to
-                let event: StoreEvent = mods.iter().collect();
-
-                // Make the changes
-                let section = stopwatch.start_section("apply_entity_modifications");
-                self.apply_entity_modifications(&econn, mods, Some(&history_event), stopwatch)?;
-                section.end();
+                let event: StoreEvent = blocks.iter().flat_map(|(_, mods)| mods.iter()).collect();
+
+                // Data sources that these blocks create add to the triggers
+                // the block stream looks for, and the blocks it scanned
+                // before might have triggers for them
+                let creates_data_sources = blocks
+                    .iter()
+                    .flat_map(|(_, mods)| mods.iter())
+                    .map(|modification| modification.entity_key())
+                    .any(|key| {
+                        key.subgraph_id == *SUBGRAPHS_ID
+                            && key.entity_type
+                                == MetadataType::DynamicEthereumContractDataSource.as_str()
+                    });
+                metrics.observe(Phase::Prepare, &bucket, start);
+
+                let mut migrate_at = None;
+                for (block_ptr, mods) in blocks {
+                    transact_span!("block", number = block_ptr.number);
+
+                    // Ensure the history event exists in the database. Each
+                    // block gets its own so that it can be reverted on its own
+                    let start = Instant::now();
+                    let history_event = econn.create_history_event(block_ptr, &mods)?;
+
+                    if econn.should_migrate(&subgraph_id, &block_ptr)? {
+                        migrate_at = Some(block_ptr);
+                    }
+                    metrics.observe(Phase::HistoryEvent, &bucket, start);
+
+                    // Make the changes
+                    let start = Instant::now();
+                    let section = stopwatch.start_section("apply_entity_modifications");
+                    self.apply_entity_modifications(
+                        &econn,
+                        mods,
+                        Some(&history_event),
+                        stopwatch.clone(),
+                    )?;
+                    section.end();
+                    metrics.observe(Phase::EntityModifications, &bucket, start);
+                }
 
+                let start = Instant::now();
+                if creates_data_sources {
+                    metadata::clear_scan_watermark(&econn.conn, &subgraph_id)?;
+                }
                 let metadata_event =
                     metadata::forward_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
-                Ok((event, metadata_event, should_migrate))
+                metrics.observe(Phase::Metadata, &bucket, start);
+                Ok((event, metadata_event, migrate_at, Instant::now()))
             })?;
+        metrics.observe(Phase::Commit, &bucket, commit_start);
 
         // Send the events separately, because NOTIFY uses a global DB lock.
+        let start = Instant::now();
         econn.transaction(|| {
             econn.send_store_event(&metadata_event)?;
             econn.send_store_event(&event)
         })?;
+        metrics.observe(Phase::SendEvents, &bucket, start);
 
-        Ok(should_migrate)
-    }
-
-    /// Apply a series of entity operations. Return `true` if the subgraph
-    /// mentioned in `history_event` should have its schema migrated
-    fn apply_metadata_operations(
-        &self,
-        operations: Vec<MetadataOperation>,
-    ) -> Result<(), StoreError> {
-        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
-        let event =
-            econn.transaction(|| self.apply_metadata_operations_with_conn(&econn, operations))?;
-
-        // Send the event separately, because NOTIFY uses a global DB lock.
-        econn.transaction(|| econn.send_store_event(&event))
-    }
-
-    fn build_entity_attribute_indexes(
-        &self,
-        subgraph: &SubgraphDeploymentId,
-        indexes: Vec<AttributeIndexDefinition>,
-    ) -> Result<(), SubgraphAssignmentProviderError> {
-        let econn = self.get_entity_conn(subgraph, ReplicaId::Main)?;
-        econn.transaction(|| self.build_entity_attribute_indexes_with_conn(&econn, indexes))
+        Ok(migrate_at)
     }
 
     fn revert_block_operations(
@@ -1157,6 +1604,8 @@ impl StoreTrait for Store {
             );
             let metadata_event =
                 metadata::revert_block_ptr(&econn.conn, &subgraph_id, block_ptr_to)?;
+            metadata::revert_errors(&econn.conn, &subgraph_id, block_ptr_to)?;
+            metadata::revert_scan_watermark(&econn.conn, &subgraph_id, block_ptr_to)?;
 
             let (event, count) = econn.revert_block(&block_ptr_from)?;
             econn.update_entity_count(count)?;
@@ -1174,12 +1623,105 @@ impl StoreTrait for Store {
         self.subscriptions.subscribe(entities)
     }
 
+    fn scan_watermark(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<ScanWatermark>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::scan_watermark(&conn, subgraph_id)
+    }
+
+    fn set_scan_watermark(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        watermark: ScanWatermark,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_scan_watermark(&conn, subgraph_id, watermark)
+    }
+
+    fn block_number(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+        hash: H256,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        use crate::db_schema::ethereum_blocks::dsl;
+
+        // We should also really check that the block with the given hash is
+        // on the chain starting at the subgraph's current head. That check is
+        // very expensive though with the data structures we have currently
+        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
+        // memory so that we can check against them, and then mark in the
+        // database the blocks on the main chain that we consider final
+        let block: Option<(i64, String)> = dsl::ethereum_blocks
+            .select((dsl::number, dsl::network_name))
+            .filter(dsl::hash.eq(format!("{:x}", hash)))
+            .first(&*self.get_conn()?)
+            .optional()?;
+        let subgraph_network = self.network_name(subgraph_id)?;
+        block
+            .map(|(number, network_name)| {
+                if subgraph_network.is_none() || Some(&network_name) == subgraph_network.as_ref() {
+                    BlockNumber::try_from(number)
+                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
+                } else {
+                    Err(StoreError::QueryExecutionError(format!(
+                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
+                        subgraph_id,
+                        subgraph_network.unwrap_or("(none)".to_owned()),
+                        hash,
+                        network_name
+                    )))
+                }
+            })
+            .transpose()
+    }
+
+    fn query_store(
+        self: Arc<Self>,
+        for_subscription: bool,
+    ) -> Arc<(dyn QueryStore + Send + Sync + 'static)> {
+        use std::sync::atomic::Ordering;
+
+        let replica_id = match for_subscription {
+            // Pick a weighted ReplicaId. `replica_order` contains a list of
+            // replicas with repetitions according to their weight
+            false => {
+                let weights_count = self.replica_order.len();
+                let index =
+                    self.conn_round_robin_counter.fetch_add(1, Ordering::SeqCst) % weights_count;
+                *self.replica_order.get(index).unwrap()
+            }
+            // Subscriptions always go to the main replica.
+            true => ReplicaId::Main,
+        };
+
+        Arc::new(crate::query_store::QueryStore::new(
+            self,
+            for_subscription,
+            replica_id,
+        ))
+    }
+}
+
+impl DeploymentStore for Store {
+    fn build_entity_attribute_indexes(
+        &self,
+        subgraph: &SubgraphDeploymentId,
+        indexes: Vec<AttributeIndexDefinition>,
+    ) -> Result<(), SubgraphAssignmentProviderError> {
+        let econn = self.get_entity_conn(subgraph, ReplicaId::Main)?;
+        econn.transaction(|| self.build_entity_attribute_indexes_with_conn(&econn, indexes))
+    }
+
     fn deployment_state_from_name(
         &self,
         name: SubgraphName,
     ) -> Result<DeploymentState, StoreError> {
         let conn = self.get_conn()?;
-        metadata::deployment_state_from_name(&conn, name)
+        let state = metadata::deployment_state_from_name(&conn, name)?;
+        self.deployment_used(&conn, &state.id)?;
+        Ok(state)
     }
 
     fn deployment_state_from_id(
@@ -1190,7 +1732,9 @@ impl StoreTrait for Store {
             Ok(DeploymentState::meta())
         } else {
             let conn = self.get_conn()?;
-            metadata::deployment_state_from_id(&conn, id)
+            let state = metadata::deployment_state_from_id(&conn, id)?;
+            self.deployment_used(&conn, &state.id)?;
+            Ok(state)
         }
     }
 
@@ -1213,6 +1757,19 @@ impl StoreTrait for Store {
         subgraph_id: &SubgraphDeploymentId,
         ops: Vec<MetadataOperation>,
     ) -> Result<(), StoreError> {
+        if self
+            .skipped_deployments
+            .lock()
+            .unwrap()
+            .contains(subgraph_id)
+        {
+            return Err(StoreError::Unknown(format_err!(
+                "deployment {} is not started because its metadata is inconsistent; \
+                 see `healthIssues` in the index node API",
+                subgraph_id
+            )));
+        }
+
         let econn = self.get_entity_conn(subgraph_id, ReplicaId::Main)?;
 
         if !econn.uses_relational_schema() {
@@ -1227,10 +1784,34 @@ impl StoreTrait for Store {
         econn.transaction(|| {
             let event = self.apply_metadata_operations_with_conn(&econn, ops)?;
             econn.start_subgraph(logger)?;
+            metadata::deployment_used(&econn.conn, subgraph_id)?;
             econn.send_store_event(&event)
         })
     }
 
+    fn fail_subgraph(
+        &self,
+        id: &SubgraphDeploymentId,
+        error: SubgraphError,
+    ) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        let event = econn.transaction(|| {
+            let ops = match metadata::repeated_error(&econn.conn, id, &error)? {
+                Some((error_id, count)) => SubgraphDeploymentEntity::fail_again_operations(
+                    id,
+                    error_id,
+                    count + 1,
+                    error.block_ptr,
+                ),
+                None => SubgraphDeploymentEntity::fail_operations(id, error),
+            };
+            self.apply_metadata_operations_with_conn(&econn, ops)
+        })?;
+
+        // Send the event separately, because NOTIFY uses a global DB lock.
+        econn.transaction(|| econn.send_store_event(&event))
+    }
+
     fn migrate_subgraph_deployment(
         &self,
         logger: &Logger,
@@ -1258,67 +1839,99 @@ impl StoreTrait for Store {
         }
     }
 
-    fn block_number(
+    fn restore_unused_deployment(&self, id: &SubgraphDeploymentId) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::restore_unused_deployment(&conn, id)
+    }
+
+    fn memory_limit(&self, id: &SubgraphDeploymentId) -> Result<Option<u64>, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::memory_limit(&conn, id)
+    }
+
+    fn set_memory_limit(
         &self,
-        subgraph_id: &SubgraphDeploymentId,
-        hash: H256,
-    ) -> Result<Option<BlockNumber>, StoreError> {
-        use crate::db_schema::ethereum_blocks::dsl;
+        id: &SubgraphDeploymentId,
+        limit: Option<u64>,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_memory_limit(&conn, id, limit)
+    }
 
-        // We should also really check that the block with the given hash is
-        // on the chain starting at the subgraph's current head. That check is
-        // very expensive though with the data structures we have currently
-        // available. Ideally, we'd have the last REORG_THRESHOLD blocks in
-        // memory so that we can check against them, and then mark in the
-        // database the blocks on the main chain that we consider final
-        let block: Option<(i64, String)> = dsl::ethereum_blocks
-            .select((dsl::number, dsl::network_name))
-            .filter(dsl::hash.eq(format!("{:x}", hash)))
-            .first(&*self.get_conn()?)
-            .optional()?;
-        let subgraph_network = self.network_name(subgraph_id)?;
-        block
-            .map(|(number, network_name)| {
-                if subgraph_network.is_none() || Some(&network_name) == subgraph_network.as_ref() {
-                    BlockNumber::try_from(number)
-                        .map_err(|e| StoreError::QueryExecutionError(e.to_string()))
-                } else {
-                    Err(StoreError::QueryExecutionError(format!(
-                        "subgraph {} belongs to network {} but block {:x} belongs to network {}",
-                        subgraph_id,
-                        subgraph_network.unwrap_or("(none)".to_owned()),
-                        hash,
-                        network_name
-                    )))
-                }
-            })
-            .transpose()
+    fn set_deployment_flag(
+        &self,
+        id: &SubgraphDeploymentId,
+        note: Option<String>,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_deployment_flag(&conn, id, note)
     }
 
-    fn query_store(
-        self: Arc<Self>,
-        for_subscription: bool,
-    ) -> Arc<(dyn QueryStore + Send + Sync + 'static)> {
-        use std::sync::atomic::Ordering;
+    fn set_history_blocks(
+        &self,
+        id: &SubgraphDeploymentId,
+        history_blocks: Option<BlockNumber>,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_history_blocks(&conn, id, history_blocks)
+    }
 
-        let replica_id = match for_subscription {
-            // Pick a weighted ReplicaId. `replica_order` contains a list of
-            // replicas with repetitions according to their weight
-            false => {
-                let weights_count = self.replica_order.len();
-                let index =
-                    self.conn_round_robin_counter.fetch_add(1, Ordering::SeqCst) % weights_count;
-                *self.replica_order.get(index).unwrap()
+    fn set_strict_bytes(
+        &self,
+        id: &SubgraphDeploymentId,
+        strict_bytes: bool,
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        let updated = metadata::set_strict_bytes(&conn, id, strict_bytes)?;
+        self.subgraph_cache.lock().unwrap().remove(id);
+        Ok(updated)
+    }
+
+    fn set_computed_module(
+        &self,
+        id: &SubgraphDeploymentId,
+        module: &[u8],
+    ) -> Result<bool, StoreError> {
+        let conn = self.get_conn()?;
+        metadata::set_computed_module(&conn, id, module)
+    }
+
+    fn check_block_ranges(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        let econn = self.get_entity_conn(id, ReplicaId::Main)?;
+        econn.check_block_ranges()
+    }
+
+    fn repair_block_ranges(
+        &self,
+        id: &SubgraphDeploymentId,
+    ) -> Result<Vec<BlockRangeOverlap>, StoreError> {
+        let econn = self.get_entity_conn(id, ReplicaId::Main)?;
+        econn.transaction(|| {
+            let overlaps = econn.check_block_ranges()?;
+            if !overlaps.is_empty() {
+                econn.repair_block_ranges()?;
             }
-            // Subscriptions always go to the main replica.
-            true => ReplicaId::Main,
-        };
+            Ok(overlaps)
+        })
+    }
+}
 
-        Arc::new(crate::query_store::QueryStore::new(
-            self,
-            for_subscription,
-            replica_id,
-        ))
+impl SubgraphNameStore for Store {
+    /// Apply a series of entity operations. Return `true` if the subgraph
+    /// mentioned in `history_event` should have its schema migrated
+    fn apply_metadata_operations(
+        &self,
+        operations: Vec<MetadataOperation>,
+    ) -> Result<(), StoreError> {
+        let econn = self.get_entity_conn(&*SUBGRAPHS_ID, ReplicaId::Main)?;
+        let event =
+            econn.transaction(|| self.apply_metadata_operations_with_conn(&econn, operations))?;
+
+        // Send the event separately, because NOTIFY uses a global DB lock.
+        econn.transaction(|| econn.send_store_event(&event))
     }
 }
 
@@ -1339,6 +1952,106 @@ impl SubgraphDeploymentStore for Store {
     fn network_name(&self, subgraph_id: &SubgraphDeploymentId) -> Result<Option<String>, Error> {
         Ok(self.subgraph_info(subgraph_id)?.network)
     }
+
+    fn strict_bytes(&self, subgraph_id: &SubgraphDeploymentId) -> Result<bool, Error> {
+        Ok(self.subgraph_info(subgraph_id)?.strict_bytes)
+    }
+
+    fn computed_module(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let conn = self.get_conn()?;
+        Ok(metadata::computed_module(&conn, subgraph_id)?)
+    }
+
+    fn health_issues(&self) -> Result<Vec<HealthIssue>, Error> {
+        let conn = self.get_conn()?;
+        Ok(metadata::health_issues(&conn)?)
+    }
+
+    fn flagged_deployments(&self) -> Result<BTreeMap<SubgraphDeploymentId, DeploymentFlag>, Error> {
+        let conn = self.get_conn()?;
+        Ok(metadata::flagged_deployments(&conn)?)
+    }
+
+    fn graft_lineage(
+        &self,
+        subgraph_id: &SubgraphDeploymentId,
+    ) -> Result<Option<GraftLineage>, Error> {
+        let conn = self.get_conn()?;
+        Ok(metadata::graft_lineage(&conn, subgraph_id)?)
+    }
+}
+
+impl Store {
+    /// Cache a call. Calls with no `provider` are calls that were cached
+    /// before we recorded where they came from
+    fn insert_call(
+        &self,
+        provider: Option<&str>,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        return_value: &[u8],
+        revert_reason: Option<&str>,
+    ) -> Result<(), Error> {
+        use crate::db_schema::{eth_call_cache, eth_call_meta};
+        use diesel::dsl::sql;
+        use diesel::sql_types::{Nullable, Timestamptz};
+
+        let id = contract_call_id(&contract_address, encoded_call, &block);
+        let fetched_at = match provider {
+            Some(_) => "now()",
+            None => "null",
+        };
+        let conn = &*self.get_conn()?;
+        conn.transaction(|| {
+            insert_into(eth_call_cache::table)
+                .values((
+                    eth_call_cache::id.eq(id.as_ref()),
+                    eth_call_cache::contract_address.eq(contract_address.as_ref()),
+                    eth_call_cache::block_number.eq(block.number as i32),
+                    eth_call_cache::return_value.eq(return_value),
+                    eth_call_cache::revert_reason.eq(revert_reason),
+                    eth_call_cache::provider.eq(provider),
+                    eth_call_cache::fetched_at.eq(sql::<Nullable<Timestamptz>>(fetched_at)),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            let accessed_at = eth_call_meta::accessed_at.eq(sql("CURRENT_DATE"));
+            insert_into(eth_call_meta::table)
+                .values((
+                    eth_call_meta::contract_address.eq(contract_address.as_ref()),
+                    accessed_at.clone(),
+                ))
+                .on_conflict(eth_call_meta::contract_address)
+                .do_update()
+                .set(accessed_at)
+                .execute(conn)
+                .map(|_| ())
+                .map_err(Error::from)
+        })
+    }
+}
+
+/// Turn a row of the call cache back into the outcome of the call. For
+/// calls that reverted, `return_value` is the data the contract reverted
+/// with, which is empty if the Ethereum node did not pass it along
+fn call_outcome(
+    return_value: Vec<u8>,
+    revert_reason: Option<String>,
+) -> Result<Vec<u8>, EthereumCallRevert> {
+    match revert_reason {
+        None => Ok(return_value),
+        Some(reason) => Err(EthereumCallRevert {
+            reason,
+            data: Some(return_value)
+                .filter(|data| !data.is_empty())
+                .map(web3::types::Bytes),
+        }),
+    }
 }
 
 impl EthereumCallCache for Store {
@@ -1347,18 +2060,19 @@ impl EthereumCallCache for Store {
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
-    ) -> Result<Option<Vec<u8>>, Error> {
+    ) -> Result<Option<Result<Vec<u8>, EthereumCallRevert>>, Error> {
         use crate::db_schema::{eth_call_cache, eth_call_meta};
         use diesel::dsl::sql;
 
         let id = contract_call_id(&contract_address, encoded_call, &block);
         let conn = &*self.get_conn()?;
         if let Some(call_output) = conn.transaction::<_, Error, _>(|| {
-            if let Some((return_value, update_accessed_at)) = eth_call_cache::table
+            if let Some((return_value, revert_reason, update_accessed_at)) = eth_call_cache::table
                 .find(id.as_ref())
                 .inner_join(eth_call_meta::table)
                 .select((
                     eth_call_cache::return_value,
+                    eth_call_cache::revert_reason,
                     sql("CURRENT_DATE > eth_call_meta.accessed_at"),
                 ))
                 .get_result(conn)
@@ -1369,14 +2083,15 @@ impl EthereumCallCache for Store {
                         .set(eth_call_meta::accessed_at.eq(sql("CURRENT_DATE")))
                         .execute(conn)?;
                 }
-                Ok(Some(return_value))
+                Ok(Some(call_outcome(return_value, revert_reason)))
             } else {
                 Ok(None)
             }
         })? {
             Ok(Some(call_output))
         } else {
-            // No entry with the new id format, try the old one.
+            // No entry with the new id format, try the old one. Calls that
+            // reverted were never cached with the old id format.
             let old_id = old_contract_call_id(&contract_address, &encoded_call, &block);
             if let Some(return_value) = eth_call_cache::table
                 .find(old_id.as_ref())
@@ -1390,10 +2105,17 @@ impl EthereumCallCache for Store {
                 trace!(self.logger, "Updating eth call cache entry");
 
                 // Migrate to the new format by re-inserting the call and deleting the old entry.
-                self.set_call(contract_address, encoded_call, block, &return_value)?;
+                self.insert_call(
+                    None,
+                    contract_address,
+                    encoded_call,
+                    block,
+                    &return_value,
+                    None,
+                )?;
                 diesel::delete(eth_call_cache::table.filter(dsl::id.eq(old_id.as_ref())))
                     .execute(conn)?;
-                Ok(Some(return_value))
+                Ok(Some(Ok(return_value)))
             } else {
                 Ok(None)
             }
@@ -1402,40 +2124,85 @@ impl EthereumCallCache for Store {
 
     fn set_call(
         &self,
+        provider: &str,
         contract_address: ethabi::Address,
         encoded_call: &[u8],
         block: EthereumBlockPointer,
         return_value: &[u8],
     ) -> Result<(), Error> {
-        use crate::db_schema::{eth_call_cache, eth_call_meta};
-        use diesel::dsl::sql;
+        self.insert_call(
+            Some(provider),
+            contract_address,
+            encoded_call,
+            block,
+            return_value,
+            None,
+        )
+    }
 
-        let id = contract_call_id(&contract_address, encoded_call, &block);
-        let conn = &*self.get_conn()?;
-        conn.transaction(|| {
-            insert_into(eth_call_cache::table)
-                .values((
-                    eth_call_cache::id.eq(id.as_ref()),
-                    eth_call_cache::contract_address.eq(contract_address.as_ref()),
-                    eth_call_cache::block_number.eq(block.number as i32),
-                    eth_call_cache::return_value.eq(return_value),
-                ))
-                .on_conflict_do_nothing()
-                .execute(conn)?;
+    fn set_call_revert(
+        &self,
+        provider: &str,
+        contract_address: ethabi::Address,
+        encoded_call: &[u8],
+        block: EthereumBlockPointer,
+        revert: &EthereumCallRevert,
+    ) -> Result<(), Error> {
+        let data = revert.data.as_ref().map(|data| data.0.as_slice());
+        self.insert_call(
+            Some(provider),
+            contract_address,
+            encoded_call,
+            block,
+            data.unwrap_or_default(),
+            Some(&revert.reason),
+        )
+    }
 
-            let accessed_at = eth_call_meta::accessed_at.eq(sql("CURRENT_DATE"));
-            insert_into(eth_call_meta::table)
-                .values((
-                    eth_call_meta::contract_address.eq(contract_address.as_ref()),
-                    accessed_at.clone(),
-                ))
-                .on_conflict(eth_call_meta::contract_address)
-                .do_update()
-                .set(accessed_at)
-                .execute(conn)
-                .map(|_| ())
-                .map_err(Error::from)
-        })
+    fn cached_calls(
+        &self,
+        block_number: u64,
+        contract_address: Option<ethabi::Address>,
+        limit: usize,
+    ) -> Result<Vec<CachedEthereumCall>, Error> {
+        use crate::db_schema::eth_call_cache::dsl;
+        use diesel::dsl::sql;
+        use diesel::sql_types::{BigInt, Nullable};
+
+        let mut query = dsl::eth_call_cache
+            .select((
+                dsl::contract_address,
+                dsl::return_value,
+                dsl::revert_reason,
+                dsl::provider,
+                sql::<Nullable<BigInt>>("extract(epoch from fetched_at)::int8"),
+            ))
+            .filter(dsl::block_number.eq(block_number as i32))
+            .order_by((dsl::contract_address, dsl::id))
+            .limit(limit as i64)
+            .into_boxed();
+        if let Some(address) = contract_address {
+            query = query.filter(dsl::contract_address.eq(address.as_ref().to_vec()));
+        }
+        Ok(query
+            .load::<(
+                Vec<u8>,
+                Vec<u8>,
+                Option<String>,
+                Option<String>,
+                Option<i64>,
+            )>(&*self.get_conn()?)?
+            .into_iter()
+            .map(
+                |(address, return_value, revert_reason, provider, fetched_at)| CachedEthereumCall {
+                    contract_address: ethabi::Address::from_slice(&address),
+                    block_number,
+                    return_value,
+                    revert_reason,
+                    provenance: Provenance::new(provider, fetched_at.map(|secs| secs as u64)),
+                },
+            )
+            .collect())
     }
 }
 
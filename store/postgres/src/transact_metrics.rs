@@ -0,0 +1,228 @@
+//! Metrics for the phases of writing the changes of a block with
+//! `transact_block_operations`
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use graph::prelude::{Histogram, HistogramVec, MetricsRegistry, SubgraphDeploymentId};
+
+/// The number of buckets that deployments are hashed into for the
+/// `deployment_bucket` label. This keeps the number of time series bounded
+/// regardless of how many deployments a node indexes
+const DEPLOYMENT_BUCKETS: u64 = 16;
+
+/// The number of buckets that entity types are hashed into for the
+/// `table_bucket` label, for the same reason
+const TABLE_BUCKETS: u64 = 16;
+
+fn bucket(name: &str, buckets: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish() % buckets
+}
+
+/// Enter a `tracing` span that lasts until the end of the enclosing
+/// scope. Without the `tracing` feature, this expands to nothing so that
+/// there is no overhead at all
+#[cfg(feature = "tracing")]
+macro_rules! transact_span {
+    ($($args:tt)*) => {
+        let span = tracing::debug_span!($($args)*);
+        let _enter = span.enter();
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! transact_span {
+    ($($args:tt)*) => {};
+}
+
+/// The phases of `transact_block_operations` that we measure
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Checking block pointers and building the store event
+    Prepare,
+    /// Creating the history event for each block
+    HistoryEvent,
+    /// Running the inserts, updates and deletes for entities
+    EntityModifications,
+    /// Updating deployment metadata like the block pointer
+    Metadata,
+    /// Committing the transaction
+    Commit,
+    /// Sending store events
+    SendEvents,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 6] = [
+        Phase::Prepare,
+        Phase::HistoryEvent,
+        Phase::EntityModifications,
+        Phase::Metadata,
+        Phase::Commit,
+        Phase::SendEvents,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Prepare => "prepare",
+            Phase::HistoryEvent => "history_event",
+            Phase::EntityModifications => "entity_modifications",
+            Phase::Metadata => "metadata",
+            Phase::Commit => "commit",
+            Phase::SendEvents => "send_events",
+        }
+    }
+}
+
+/// Histograms of how long each phase of writing a block takes, and how
+/// long the statements for each table take
+#[derive(Clone)]
+pub struct TransactMetrics {
+    phase_duration: Box<HistogramVec>,
+    /// The histograms of `deployment_transact_table_duration` by table
+    /// bucket, looked up once so that observing them is cheap
+    table_durations: Vec<Histogram>,
+}
+
+impl TransactMetrics {
+    pub fn new(registry: Arc<dyn MetricsRegistry>) -> Self {
+        let phase_duration = registry
+            .new_histogram_vec(
+                "deployment_transact_phase_duration",
+                "Measures how long each phase of writing the changes of a block takes",
+                vec![String::from("phase"), String::from("deployment_bucket")],
+                vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0],
+            )
+            .expect("failed to create `deployment_transact_phase_duration` histogram");
+        let table_duration = registry
+            .new_histogram_vec(
+                "deployment_transact_table_duration",
+                "Measures how long each statement that writes the changes of a block to \
+                 a table takes, with tables hashed into a fixed number of buckets",
+                vec![String::from("table_bucket")],
+                vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0],
+            )
+            .expect("failed to create `deployment_transact_table_duration` histogram");
+        let table_durations = (0..TABLE_BUCKETS)
+            .map(|bucket| table_duration.with_label_values(&[&bucket.to_string()]))
+            .collect();
+        TransactMetrics {
+            phase_duration,
+            table_durations,
+        }
+    }
+
+    /// The label for `deployment`; deployments are spread over a fixed
+    /// number of buckets so that we can still tell whether slow writes
+    /// come from a few deployments or from all of them
+    pub fn deployment_bucket(deployment: &SubgraphDeploymentId) -> String {
+        bucket(deployment.as_str(), DEPLOYMENT_BUCKETS).to_string()
+    }
+
+    /// The histogram for `phase` for deployments in `bucket`
+    pub fn histogram(&self, phase: Phase, bucket: &str) -> Histogram {
+        self.phase_duration
+            .with_label_values(&[phase.as_str(), bucket])
+    }
+
+    /// Record that `phase` ran from `start` until now
+    pub fn observe(&self, phase: Phase, bucket: &str, start: Instant) {
+        self.histogram(phase, bucket)
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    /// The histogram for the bucket of the table of `entity_type`
+    pub fn table_histogram(&self, entity_type: &str) -> &Histogram {
+        &self.table_durations[bucket(entity_type, TABLE_BUCKETS) as usize]
+    }
+
+    /// Record that a statement for the table of `entity_type` took
+    /// `duration`
+    pub fn observe_table(&self, entity_type: &str, duration: Duration) {
+        self.table_histogram(entity_type)
+            .observe(duration.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::{Collector, Counter, Gauge, Opts, PrometheusError};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A registry that only remembers the names of the metrics that were
+    /// registered with it
+    #[derive(Default)]
+    struct NameRegistry {
+        names: Mutex<Vec<String>>,
+    }
+
+    impl MetricsRegistry for NameRegistry {
+        fn register(&self, name: &str, _c: Box<dyn Collector>) {
+            self.names.lock().unwrap().push(name.to_owned());
+        }
+
+        fn global_counter(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Counter, PrometheusError> {
+            Counter::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn global_gauge(
+            &self,
+            name: &str,
+            help: &str,
+            const_labels: HashMap<String, String>,
+        ) -> Result<Gauge, PrometheusError> {
+            Gauge::with_opts(Opts::new(name, help).const_labels(const_labels))
+        }
+
+        fn unregister(&self, _: Box<dyn Collector>) {}
+    }
+
+    #[test]
+    fn registers_and_observes() {
+        let registry = Arc::new(NameRegistry::default());
+        let metrics = TransactMetrics::new(registry.clone());
+        assert_eq!(
+            vec![
+                "deployment_transact_phase_duration".to_owned(),
+                "deployment_transact_table_duration".to_owned()
+            ],
+            *registry.names.lock().unwrap()
+        );
+
+        let id = SubgraphDeploymentId::new("QmTransactMetrics").unwrap();
+        let bucket = TransactMetrics::deployment_bucket(&id);
+        assert_eq!(bucket, TransactMetrics::deployment_bucket(&id));
+        assert!(bucket.parse::<u64>().unwrap() < DEPLOYMENT_BUCKETS);
+
+        let start = Instant::now();
+        for phase in Phase::ALL.iter() {
+            metrics.observe(*phase, &bucket, start);
+        }
+        for phase in Phase::ALL.iter() {
+            let histogram = metrics.histogram(*phase, &bucket);
+            assert_eq!(1, histogram.get_sample_count());
+            assert!(histogram.get_sample_sum() >= 0.0);
+            assert!(histogram.get_sample_sum() < 60.0);
+        }
+
+        metrics.observe_table("User", Duration::from_millis(3));
+        let histogram = metrics.table_histogram("User");
+        assert_eq!(1, histogram.get_sample_count());
+        assert!((histogram.get_sample_sum() - 0.003).abs() < 1e-9);
+        let observed = (0..100)
+            .map(|i| metrics.table_histogram(&format!("Thing{}", i)))
+            .filter(|histogram| histogram.get_sample_count() > 0)
+            .count();
+        assert!(observed < 100);
+    }
+}
@@ -5,20 +5,31 @@ use futures::future::IntoFuture;
 use std::fmt::Debug;
 use std::sync::Arc;
 
-use graph::components::store::{ChainStore, Store as _};
-use graph::prelude::{Future01CompatExt, SubgraphDeploymentId};
+use graph::components::ethereum::repair::{repair_chain, ChainRepair};
+use graph::components::store::{ChainStore, EntityStore as _};
+use graph::mock::MockEthereumAdapter;
+use graph::prelude::{
+    future, futures03::FutureExt as _, o, slog, web3::types::H256, BlockGap, Error,
+    Future01CompatExt, HeadAnnouncement, LightEthereumBlock, Logger, Provenance,
+    SubgraphDeploymentId, TryFutureExt as _,
+};
 use graph_store_postgres::NetworkStore as DieselStore;
+use lazy_static::lazy_static;
 
-use test_store::block_store::{
+use graph_test_support::block_store::{
     Chain, FakeBlock, BLOCK_FIVE, BLOCK_FOUR, BLOCK_ONE, BLOCK_ONE_NO_PARENT, BLOCK_ONE_SIBLING,
     BLOCK_THREE, BLOCK_THREE_NO_PARENT, BLOCK_TWO, BLOCK_TWO_NO_PARENT, GENESIS_BLOCK, NO_PARENT,
 };
-use test_store::*;
+use graph_test_support::TestStore;
 
 // The ancestor count we use for chain head updates. We keep this very small
 // to make setting up the tests easier
 const ANCESTOR_COUNT: u64 = 3;
 
+lazy_static! {
+    static ref TEST_STORE: TestStore = TestStore::from_env();
+}
+
 /// Test harness for running database integration tests.
 fn run_test<R, F>(chain: Chain, test: F)
 where
@@ -27,26 +38,13 @@ where
     R::Error: Send + Debug,
     R::Future: Send,
 {
-    let store = STORE.clone();
-
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
-
-    runtime
-        .block_on(async {
-            // Reset state before starting
-            block_store::remove();
-
-            // Seed database with test data
-            block_store::insert(chain, NETWORK_NAME);
-
-            // Run test
-            test(store).into_future().compat().await
-        })
-        .unwrap_or_else(|e| panic!("Failed to run ChainHead test: {:?}", e));
+    TEST_STORE.run_chain_test(chain, |store| async move {
+        test(store)
+            .into_future()
+            .compat()
+            .await
+            .unwrap_or_else(|e| panic!("Failed to run ChainHead test: {:?}", e))
+    });
 }
 
 /// Check that `attempt_chain_head_update` works as expected on the given
@@ -157,7 +155,7 @@ fn block_number() {
     let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
     let subgraph = SubgraphDeploymentId::new("nonExistentSubgraph").unwrap();
 
-    create_test_subgraph(subgraph.as_str(), "type Dummy @entity { id: ID! }");
+    TEST_STORE.create_test_subgraph(subgraph.as_str(), "type Dummy @entity { id: ID! }");
 
     run_test(chain, move |store| -> Result<(), ()> {
         let block = store
@@ -191,14 +189,28 @@ fn block_hashes_by_number() {
         let hashes = store.block_hashes_by_block_number(1).unwrap();
         assert_eq!(vec![BLOCK_ONE.block_hash()], hashes);
 
+        // There is no chain head, so the hashes are ordered lexicographically
         let hashes = store.block_hashes_by_block_number(2).unwrap();
-        assert_eq!(2, hashes.len());
-        assert!(hashes.contains(&BLOCK_TWO.block_hash()));
-        assert!(hashes.contains(&BLOCK_TWO_NO_PARENT.block_hash()));
+        assert_eq!(
+            vec![BLOCK_TWO_NO_PARENT.block_hash(), BLOCK_TWO.block_hash()],
+            hashes
+        );
 
         let hashes = store.block_hashes_by_block_number(127).unwrap();
         assert_eq!(0, hashes.len());
 
+        // Without a chain head, there is no telling which of the blocks
+        // with number 2 is canonical
+        assert_eq!(
+            Some(BLOCK_ONE.block_hash()),
+            store.canonical_block_hash_by_block_number(1).unwrap()
+        );
+        assert!(store.canonical_block_hash_by_block_number(2).is_err());
+        assert_eq!(
+            None,
+            store.canonical_block_hash_by_block_number(127).unwrap()
+        );
+
         let deleted = store
             .confirm_block_hash(1, &BLOCK_ONE.block_hash())
             .unwrap();
@@ -223,3 +235,365 @@ fn block_hashes_by_number() {
         Ok(())
     })
 }
+
+#[test]
+fn block_hashes_by_number_canonical_first() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+        &*BLOCK_THREE,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        store
+            .attempt_chain_head_update(ANCESTOR_COUNT)
+            .expect("attempt_chain_head_update failed");
+        assert_eq!(
+            Some(BLOCK_THREE.block_hash()),
+            store.chain_head_ptr().unwrap().map(|ptr| ptr.hash)
+        );
+
+        // BLOCK_TWO is an ancestor of the chain head and comes first even
+        // though it sorts after BLOCK_TWO_NO_PARENT
+        let hashes = store.block_hashes_by_block_number(2).unwrap();
+        assert_eq!(
+            vec![BLOCK_TWO.block_hash(), BLOCK_TWO_NO_PARENT.block_hash()],
+            hashes
+        );
+
+        let blocks = store.block_hashes_and_parents_by_block_number(2).unwrap();
+        assert_eq!(
+            vec![
+                (BLOCK_TWO.block_hash(), BLOCK_ONE.block_hash()),
+                (BLOCK_TWO_NO_PARENT.block_hash(), H256::zero()),
+            ],
+            blocks
+        );
+
+        let blocks = store.block_hashes_and_parents_by_block_number(3).unwrap();
+        assert_eq!(
+            vec![(BLOCK_THREE.block_hash(), BLOCK_TWO.block_hash())],
+            blocks
+        );
+
+        let blocks = store.block_hashes_and_parents_by_block_number(127).unwrap();
+        assert!(blocks.is_empty());
+
+        assert_eq!(
+            Some(BLOCK_TWO.block_hash()),
+            store.canonical_block_hash_by_block_number(2).unwrap()
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn canonical_block_hash_with_gap() {
+    // BLOCK_TWO_NO_PARENT is an ommer of BLOCK_TWO, and BLOCK_THREE is
+    // missing between the chain head and block 2
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO,
+        &*BLOCK_TWO_NO_PARENT,
+        &*BLOCK_FOUR,
+        &*BLOCK_FIVE,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        store
+            .attempt_chain_head_update(1)
+            .expect("attempt_chain_head_update failed");
+        assert_eq!(
+            Some(BLOCK_FIVE.block_hash()),
+            store.chain_head_ptr().unwrap().map(|ptr| ptr.hash)
+        );
+
+        // Both blocks with number 2 are candidates, and we can not tell
+        // which of them is canonical
+        let hashes = store.block_hashes_by_block_number(2).unwrap();
+        assert_eq!(2, hashes.len());
+        assert!(store.canonical_block_hash_by_block_number(2).is_err());
+
+        // Blocks without an ommer are fine, even across the gap
+        assert_eq!(
+            Some(BLOCK_ONE.block_hash()),
+            store.canonical_block_hash_by_block_number(1).unwrap()
+        );
+        assert_eq!(
+            Some(BLOCK_FOUR.block_hash()),
+            store.canonical_block_hash_by_block_number(4).unwrap()
+        );
+        assert_eq!(None, store.canonical_block_hash_by_block_number(3).unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn find_gaps() {
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_THREE,
+        &*BLOCK_FOUR,
+        &*BLOCK_FIVE,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        assert_eq!(vec![BlockGap::Missing(2)], store.find_gaps(0, 5).unwrap());
+        assert_eq!(
+            vec![BlockGap::Missing(2), BlockGap::Missing(6)],
+            store.find_gaps(0, 6).unwrap()
+        );
+        assert!(store.find_gaps(3, 5).unwrap().is_empty());
+        Ok(())
+    });
+
+    let chain = vec![
+        &*GENESIS_BLOCK,
+        &*BLOCK_ONE,
+        &*BLOCK_TWO_NO_PARENT,
+        &*BLOCK_THREE,
+    ];
+    run_test(chain, move |store| -> Result<(), ()> {
+        assert_eq!(
+            vec![
+                BlockGap::BrokenLink {
+                    number: 2,
+                    hash: BLOCK_TWO_NO_PARENT.block_hash(),
+                    parent_hash: H256::zero(),
+                },
+                BlockGap::BrokenLink {
+                    number: 3,
+                    hash: BLOCK_THREE.block_hash(),
+                    parent_hash: BLOCK_TWO.block_hash(),
+                }
+            ],
+            store.find_gaps(0, 3).unwrap()
+        );
+        // We do not check the parent of the first block
+        assert!(store.find_gaps(3, 3).unwrap().is_empty());
+        Ok(())
+    })
+}
+
+fn light_block(block: &FakeBlock) -> LightEthereumBlock {
+    let mut light = LightEthereumBlock::default();
+    light.hash = Some(block.block_hash());
+    light.number = Some(block.number.into());
+    light.parent_hash = block.parent_hash.parse().unwrap();
+    light
+}
+
+#[test]
+fn cached_block_provenance() {
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE];
+    run_test(chain, move |store| -> Result<(), ()> {
+        // Blocks that were cached before we recorded provenance
+        let block = store
+            .cached_block(BLOCK_ONE.block_hash())
+            .unwrap()
+            .expect("BLOCK_ONE is cached");
+        assert_eq!(BLOCK_ONE.number, block.ptr.number);
+        assert_eq!(BLOCK_ONE.block_hash(), block.ptr.hash);
+        assert_eq!(Some(GENESIS_BLOCK.block_hash()), block.parent_hash);
+        assert_eq!(Provenance::new(None, None), block.provenance);
+        assert_eq!(Provenance::UNKNOWN_PROVIDER, block.provenance.provider);
+
+        store
+            .upsert_light_blocks("0123456789abcdef", vec![light_block(&*BLOCK_TWO)])
+            .unwrap();
+        let block = store
+            .cached_block(BLOCK_TWO.block_hash())
+            .unwrap()
+            .expect("BLOCK_TWO is cached");
+        assert_eq!(BLOCK_TWO.number, block.ptr.number);
+        assert_eq!("0123456789abcdef", block.provenance.provider);
+        assert!(block.provenance.fetched_at.is_some());
+
+        // Light blocks do not overwrite blocks we already have
+        store
+            .upsert_light_blocks("fedcba9876543210", vec![light_block(&*BLOCK_ONE)])
+            .unwrap();
+        let block = store.cached_block(BLOCK_ONE.block_hash()).unwrap().unwrap();
+        assert_eq!(Provenance::UNKNOWN_PROVIDER, block.provenance.provider);
+
+        assert_eq!(None, store.cached_block(BLOCK_THREE.block_hash()).unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn head_announcements() {
+    fn announcement(block: &FakeBlock, received_at: u64) -> HeadAnnouncement {
+        HeadAnnouncement {
+            block: (block.block_hash(), block.number).into(),
+            received_at,
+        }
+    }
+
+    let chain = vec![&*GENESIS_BLOCK];
+    run_test(chain, move |store| -> Result<(), ()> {
+        let one = announcement(&*BLOCK_ONE, 1_600_000_000);
+        let two = announcement(&*BLOCK_TWO, 1_600_000_015);
+        let three = announcement(&*BLOCK_THREE, 1_600_000_030);
+
+        store
+            .save_head_announcements("a.example.com", &[one.clone(), two.clone()])
+            .unwrap();
+        store
+            .save_head_announcements("b.example.com", &[one.clone()])
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("a.example.com".to_owned(), vec![one.clone(), two.clone()]),
+                ("b.example.com".to_owned(), vec![one.clone()]),
+            ],
+            store.head_announcements().unwrap()
+        );
+
+        // Saving replaces what we saved for the provider before
+        store
+            .save_head_announcements("a.example.com", &[two.clone(), three.clone()])
+            .unwrap();
+        store.save_head_announcements("b.example.com", &[]).unwrap();
+        assert_eq!(
+            vec![("a.example.com".to_owned(), vec![two, three])],
+            store.head_announcements().unwrap()
+        );
+        Ok(())
+    })
+}
+
+/// Run `repair_chain` for the blocks from `from` to `to` against an
+/// Ethereum node that knows the blocks in `provider`
+fn check_repair(
+    chain: Chain,
+    provider: Chain,
+    from: u64,
+    to: u64,
+    repair_exp: ChainRepair,
+    gaps_exp: Vec<BlockGap>,
+) {
+    let by_number = provider.clone();
+    let by_hash = provider;
+    let mut adapter = MockEthereumAdapter::new();
+    adapter
+        .expect_url_hostname()
+        .return_const("localhost".to_owned());
+    adapter
+        .expect_block_by_number()
+        .returning(move |_, number| {
+            let block = by_number
+                .iter()
+                .find(|block| block.number == number)
+                .map(|block| light_block(block));
+            Box::new(future::ok::<_, Error>(block))
+        });
+    adapter.expect_block_by_hash().returning(move |_, hash| {
+        let block = by_hash
+            .iter()
+            .find(|block| block.block_hash() == hash)
+            .map(|block| light_block(block));
+        Box::new(future::ok::<_, Error>(block))
+    });
+    let adapter = Arc::new(adapter);
+
+    run_test(chain, move |store| {
+        async move {
+            let logger = Logger::root(slog::Discard, o!());
+            let repair = repair_chain(&logger, store.clone(), adapter, from, to)
+                .await
+                .expect("repair_chain failed");
+            assert_eq!(repair_exp, repair);
+            assert_eq!(gaps_exp, store.find_gaps(from, to).unwrap());
+            Ok::<(), ()>(())
+        }
+        .boxed()
+        .compat()
+    })
+}
+
+#[test]
+fn repair_missing_block() {
+    check_repair(
+        vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_THREE, &*BLOCK_FOUR],
+        vec![&*BLOCK_TWO],
+        0,
+        4,
+        ChainRepair {
+            gaps: 1,
+            repaired: vec![2],
+            ..Default::default()
+        },
+        vec![],
+    );
+}
+
+#[test]
+fn repair_rejects_blocks_from_other_fork() {
+    // The provider is on a different fork than BLOCK_THREE, and does not
+    // have block 4 at all
+    check_repair(
+        vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_THREE],
+        vec![&*BLOCK_TWO_NO_PARENT],
+        0,
+        4,
+        ChainRepair {
+            gaps: 2,
+            mismatched: vec![2],
+            unavailable: vec![4],
+            ..Default::default()
+        },
+        vec![BlockGap::Missing(2), BlockGap::Missing(4)],
+    );
+}
+
+#[test]
+fn repair_broken_link() {
+    // BLOCK_THREE's parent is missing, and there is no block with the
+    // (zero) parent hash of BLOCK_TWO_NO_PARENT
+    check_repair(
+        vec![
+            &*GENESIS_BLOCK,
+            &*BLOCK_ONE,
+            &*BLOCK_TWO_NO_PARENT,
+            &*BLOCK_THREE,
+        ],
+        vec![&*BLOCK_TWO],
+        0,
+        3,
+        ChainRepair {
+            gaps: 2,
+            repaired: vec![2],
+            unavailable: vec![1],
+            ..Default::default()
+        },
+        vec![BlockGap::BrokenLink {
+            number: 2,
+            hash: BLOCK_TWO_NO_PARENT.block_hash(),
+            parent_hash: H256::zero(),
+        }],
+    );
+}
+
+#[test]
+fn repair_rejects_large_and_empty_ranges() {
+    // The adapter must not be asked for anything
+    let adapter = Arc::new(MockEthereumAdapter::new());
+
+    run_test(vec![&*GENESIS_BLOCK, &*BLOCK_ONE], move |store| {
+        async move {
+            let logger = Logger::root(slog::Discard, o!());
+            let err = repair_chain(&logger, store.clone(), adapter.clone(), 0, 100_000)
+                .await
+                .expect_err("ranges above GRAPH_CHAIN_REPAIR_MAX_BLOCKS are rejected");
+            assert!(err.to_string().contains("contains 100001 blocks"));
+            assert!(repair_chain(&logger, store.clone(), adapter, 5, 4)
+                .await
+                .is_err());
+            Ok::<(), ()>(())
+        }
+        .boxed()
+        .compat()
+    })
+}
@@ -3,6 +3,7 @@ use diesel::*;
 use hex_literal::hex;
 use lazy_static::lazy_static;
 use std::str::FromStr;
+use std::time::Duration;
 use test_store::*;
 
 use graph::components::store::{EntityKey, EntityOrder, EntityQuery};
@@ -93,11 +94,8 @@ where
 {
     let store = STORE.clone();
 
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
+    // This forces sequential test execution
+    let mut runtime = TEST_STORE.lock_runtime();
 
     runtime
         .block_on(async {
@@ -127,6 +125,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        history_blocks: None,
+        strict_bytes: false,
+        computed: None,
         templates: vec![],
     };
 
@@ -237,7 +238,7 @@ fn create_test_entity(
     EntityOperation::Set {
         key: EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: entity_type.to_owned(),
+            entity_type: entity_type.into(),
             entity_id: id.to_owned(),
         },
         data: test_entity,
@@ -277,10 +278,7 @@ fn graft() {
             BLOCK_NUMBER_MAX,
             EntityCollection::All(vec![USER.to_owned()]),
         )
-        .order(EntityOrder::Descending(
-            "name".to_string(),
-            ValueType::String,
-        ));
+        .order(EntityOrder::Descending("name".into(), ValueType::String));
 
         let entities = store
             .find(query)
@@ -303,7 +301,7 @@ fn graft() {
         let op = EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: subgraph_id.clone(),
-                entity_type: USER.to_owned(),
+                entity_type: USER.into(),
                 entity_id: "3".to_owned(),
             },
             data: shaq,
@@ -323,3 +321,194 @@ fn graft() {
         Ok(())
     })
 }
+
+/// The deployments that are currently recorded as unused
+fn unused_deployments() -> Vec<String> {
+    use diesel::sql_types::{Array, Text};
+
+    let conn =
+        PgConnection::establish(&postgres_test_url()).expect("Failed to connect to Postgres");
+    diesel::select(diesel::dsl::sql::<Array<Text>>(
+        "(select coalesce(array_agg(deployment order by deployment), '{}')
+            from unused_deployments)",
+    ))
+    .get_result::<Vec<String>>(&conn)
+    .unwrap()
+}
+
+#[test]
+fn unused_graft_base() {
+    run_test(move |store| -> Result<(), ()> {
+        const SUBGRAPH: &str = "grafted";
+        const DAY: Duration = Duration::from_secs(24 * 3600);
+        const NO_TIME: Duration = Duration::from_secs(0);
+
+        let subgraph_id = SubgraphDeploymentId::new(SUBGRAPH).unwrap();
+        let res = test_store::create_grafted_subgraph(
+            SUBGRAPH,
+            GRAFT_GQL,
+            TEST_SUBGRAPH_ID.as_str(),
+            BLOCKS[1],
+        );
+        if !*USING_RELATIONAL_STORAGE {
+            assert!(res.is_err());
+            return Ok(());
+        }
+        res.unwrap();
+
+        let logger = Logger::root(slog::Discard, o!());
+        let diesel_store = store.store();
+
+        // While the grafted deployment is assigned, neither it nor its
+        // graft base are unused
+        let ops = SubgraphDeploymentAssignmentEntity::new(NodeId::new("test").unwrap())
+            .write_operations(&subgraph_id);
+        store.apply_metadata_operations(ops).unwrap();
+        diesel_store
+            .remove_unused_deployments(&logger, NO_TIME, DAY)
+            .unwrap();
+        assert!(unused_deployments().is_empty());
+
+        // Once it is unassigned, it is unused, but its graft base is not
+        // as long as the grafted deployment exists
+        let ops = vec![MetadataOperation::Remove {
+            entity: SubgraphDeploymentAssignmentEntity::TYPENAME,
+            id: subgraph_id.to_string(),
+        }];
+        store.apply_metadata_operations(ops).unwrap();
+        diesel_store
+            .remove_unused_deployments(&logger, NO_TIME, DAY)
+            .unwrap();
+        assert_eq!(vec![SUBGRAPH.to_owned()], unused_deployments());
+
+        diesel_store
+            .remove_unused_deployments(&logger, DAY, NO_TIME)
+            .unwrap();
+        assert!(unused_deployments().is_empty());
+        assert!(!store.is_deployed(&subgraph_id).unwrap());
+        assert!(store.is_deployed(&*TEST_SUBGRAPH_ID).unwrap());
+
+        // With the grafted deployment gone, the graft base is unused, too
+        diesel_store
+            .remove_unused_deployments(&logger, NO_TIME, DAY)
+            .unwrap();
+        assert_eq!(vec![TEST_SUBGRAPH_ID.to_string()], unused_deployments());
+
+        // Restoring the graft base keeps it from being removed
+        assert!(store.restore_unused_deployment(&*TEST_SUBGRAPH_ID).unwrap());
+        assert!(!store.restore_unused_deployment(&*TEST_SUBGRAPH_ID).unwrap());
+        assert!(unused_deployments().is_empty());
+
+        Ok(())
+    })
+}
+
+/// Create the deployments `middle`, grafted onto `testsubgraph`, and
+/// `last`, grafted onto `middle`. Return `false` if the store does not
+/// support grafting
+fn create_graft_chain() -> bool {
+    let res = test_store::create_grafted_subgraph(
+        "middle",
+        GRAFT_GQL,
+        TEST_SUBGRAPH_ID.as_str(),
+        BLOCKS[1],
+    );
+    if !*USING_RELATIONAL_STORAGE {
+        assert!(res.is_err());
+        return false;
+    }
+    res.unwrap();
+    test_store::create_grafted_subgraph("last", GRAFT_GQL, "middle", BLOCKS[2]).unwrap();
+    true
+}
+
+#[test]
+fn graft_lineage() {
+    run_test(move |store| -> Result<(), ()> {
+        if !create_graft_chain() {
+            return Ok(());
+        }
+        let middle = SubgraphDeploymentId::new("middle").unwrap();
+        let last = SubgraphDeploymentId::new("last").unwrap();
+
+        let lineage = store.graft_lineage(&last).unwrap().unwrap();
+        assert_eq!(
+            vec![
+                GraftBase {
+                    deployment: middle.clone(),
+                    block: BLOCKS[2],
+                },
+                GraftBase {
+                    deployment: TEST_SUBGRAPH_ID.clone(),
+                    block: BLOCKS[1],
+                }
+            ],
+            lineage.bases
+        );
+        assert!(lineage.dependents.is_empty());
+
+        let lineage = store.graft_lineage(&middle).unwrap().unwrap();
+        assert_eq!(1, lineage.bases.len());
+        assert_eq!(vec![last.clone()], lineage.dependents);
+
+        let lineage = store.graft_lineage(&*TEST_SUBGRAPH_ID).unwrap().unwrap();
+        assert!(lineage.bases.is_empty());
+        assert_eq!(vec![middle.clone(), last.clone()], lineage.dependents);
+
+        let unknown = SubgraphDeploymentId::new("unknown").unwrap();
+        assert_eq!(None, store.graft_lineage(&unknown).unwrap());
+
+        // Deployments that others are grafted onto can't be removed
+        let diesel_store = store.store();
+        assert!(diesel_store.remove_deployment(&*TEST_SUBGRAPH_ID).is_err());
+        assert!(diesel_store.remove_deployment(&middle).is_err());
+        assert!(diesel_store.remove_deployment(&last).unwrap());
+        assert!(diesel_store.remove_deployment(&middle).unwrap());
+
+        let lineage = store.graft_lineage(&*TEST_SUBGRAPH_ID).unwrap().unwrap();
+        assert!(lineage.dependents.is_empty());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn graft_lineage_with_cycle() {
+    run_test(move |store| -> Result<(), ()> {
+        if !create_graft_chain() {
+            return Ok(());
+        }
+
+        // Graft `testsubgraph` onto `last`, which the store would never
+        // do by itself
+        let conn =
+            PgConnection::establish(&postgres_test_url()).expect("Failed to connect to Postgres");
+        diesel::sql_query(
+            "update subgraphs.subgraph_deployment d
+                set graft_base = 'last',
+                    graft_block_hash = l.graft_block_hash,
+                    graft_block_number = l.graft_block_number
+               from subgraphs.subgraph_deployment l
+              where d.id = $1 and l.id = 'last'",
+        )
+        .bind::<diesel::sql_types::Text, _>(TEST_SUBGRAPH_ID.as_str())
+        .execute(&conn)
+        .unwrap();
+
+        for id in &["testsubgraph", "middle", "last"] {
+            let id = SubgraphDeploymentId::new(*id).unwrap();
+            let err = store.graft_lineage(&id).unwrap_err();
+            assert!(err.to_string().contains("form a cycle"));
+        }
+
+        // None of the deployments in the cycle are ever unused
+        let logger = Logger::root(slog::Discard, o!());
+        store
+            .store()
+            .remove_unused_deployments(&logger, Duration::from_secs(0), Duration::from_secs(0))
+            .unwrap();
+        assert!(unused_deployments().is_empty());
+
+        Ok(())
+    })
+}
@@ -0,0 +1,167 @@
+//! Test that notifications on different channels, which all share the
+//! same connection, get to the right listeners
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use lazy_static::lazy_static;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use graph::prelude::{serde_json, EventProducer, Stream};
+use graph_store_postgres::notification_listener_for_tests::{
+    JsonNotification, NotificationListener, SafeChannelName, LISTENER_APPLICATION_NAME,
+};
+use test_store::*;
+
+lazy_static! {
+    /// Tests that disturb the shared connection must not run concurrently
+    /// with the others, which would lose notifications
+    static ref TEST_MUTEX: Mutex<()> = Mutex::new(());
+}
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn connect() -> PgConnection {
+    PgConnection::establish(&postgres_test_url()).expect("Failed to connect to Postgres")
+}
+
+/// Start a listener on `channel` and forward what it receives to a
+/// channel that we can read from with a timeout
+fn listen(channel: &str) -> (NotificationListener, mpsc::Receiver<u64>) {
+    let mut listener = NotificationListener::new(
+        &*LOGGER,
+        postgres_test_url(),
+        SafeChannelName::i_promise_this_is_safe(channel),
+    );
+    let stream = listener.take_event_stream().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for notification in stream.wait() {
+            let n = notification.unwrap().payload["n"].as_u64().unwrap();
+            if sender.send(n).is_err() {
+                break;
+            }
+        }
+    });
+    listener.start();
+    (listener, receiver)
+}
+
+fn notify(conn: &PgConnection, channel: &str, n: u64) {
+    JsonNotification::send(channel, &serde_json::json!({ "n": n }), conn)
+        .expect("Failed to send notification");
+}
+
+fn receive(receiver: &mpsc::Receiver<u64>, count: usize) -> Vec<u64> {
+    (0..count)
+        .map(|_| {
+            receiver
+                .recv_timeout(TIMEOUT)
+                .expect("missing notification")
+        })
+        .collect()
+}
+
+fn assert_nothing_more(receiver: &mpsc::Receiver<u64>) {
+    assert_eq!(
+        Err(mpsc::RecvTimeoutError::Timeout),
+        receiver.recv_timeout(Duration::from_millis(500))
+    );
+}
+
+#[test]
+fn dispatches_interleaved_notifications() {
+    let _lock = TEST_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+    let conn = connect();
+
+    let (_a1, a1) = listen("notification_test_a");
+    let (_a2, a2) = listen("notification_test_a");
+    let (_b, b) = listen("notification_test_b");
+
+    for n in 0..20 {
+        let channel = if n % 2 == 0 {
+            "notification_test_a"
+        } else {
+            "notification_test_b"
+        };
+        notify(&conn, channel, n);
+    }
+
+    let evens: Vec<_> = (0..20).filter(|n| n % 2 == 0).collect();
+    let odds: Vec<_> = (0..20).filter(|n| n % 2 == 1).collect();
+    assert_eq!(evens, receive(&a1, 10));
+    assert_eq!(evens, receive(&a2, 10));
+    assert_eq!(odds, receive(&b, 10));
+    assert_nothing_more(&a1);
+    assert_nothing_more(&a2);
+    assert_nothing_more(&b);
+}
+
+#[test]
+fn registers_channels_dynamically() {
+    let _lock = TEST_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+    let conn = connect();
+
+    let (first, first_rx) = listen("notification_test_first");
+
+    // Nobody listens on the second channel yet
+    notify(&conn, "notification_test_second", 0);
+
+    let (_second, second_rx) = listen("notification_test_second");
+    notify(&conn, "notification_test_second", 1);
+    notify(&conn, "notification_test_first", 2);
+    assert_eq!(vec![1], receive(&second_rx, 1));
+    assert_eq!(vec![2], receive(&first_rx, 1));
+    assert_nothing_more(&second_rx);
+
+    // Once the only listener for a channel is gone, the channel is not
+    // dispatched anymore, but the others are
+    drop(first);
+    notify(&conn, "notification_test_first", 3);
+    notify(&conn, "notification_test_second", 4);
+    assert_eq!(vec![4], receive(&second_rx, 1));
+    assert!(first_rx.recv_timeout(TIMEOUT).is_err());
+
+    // Listening on a channel again works
+    let (_first, first_rx) = listen("notification_test_first");
+    notify(&conn, "notification_test_first", 5);
+    assert_eq!(vec![5], receive(&first_rx, 1));
+    assert_nothing_more(&first_rx);
+}
+
+#[test]
+fn relistens_after_reconnect() {
+    let _lock = TEST_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+    let conn = connect();
+
+    let (_one, one) = listen("notification_test_one");
+    let (_two, two) = listen("notification_test_two");
+    notify(&conn, "notification_test_one", 0);
+    assert_eq!(vec![0], receive(&one, 1));
+
+    diesel::sql_query(format!(
+        "select pg_terminate_backend(pid) from pg_stat_activity \
+         where application_name = '{}'",
+        LISTENER_APPLICATION_NAME
+    ))
+    .execute(&conn)
+    .expect("Failed to terminate the listener connection");
+
+    // Notifications sent before the dispatcher has reconnected are lost,
+    // and we keep sending until both channels receive notifications again
+    let start = Instant::now();
+    for (channel, receiver) in vec![
+        ("notification_test_one", &one),
+        ("notification_test_two", &two),
+    ] {
+        let mut n = 1;
+        loop {
+            assert!(start.elapsed() < TIMEOUT, "listener did not reconnect");
+            notify(&conn, channel, n);
+            if receiver.recv_timeout(Duration::from_millis(200)).is_ok() {
+                break;
+            }
+            n += 1;
+        }
+    }
+}
@@ -5,13 +5,14 @@ use diesel::prelude::*;
 use futures::future::IntoFuture;
 use hex_literal::hex;
 use lazy_static::lazy_static;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::str::FromStr;
 
-use graph::data::store::scalar::{BigDecimal, BigInt, Bytes};
+use graph::data::store::scalar::{BigDecimal, BigInt, Bytes, Timestamp};
 use graph::prelude::{
-    web3::types::H256, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder, EntityQuery,
-    EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
+    web3::types::H256, BlockNumber, Entity, EntityCollection, EntityFilter, EntityKey, EntityOrder,
+    EntityQuery, EntityRange, Future01CompatExt, Schema, SubgraphDeploymentId, Value, ValueType,
     BLOCK_NUMBER_MAX,
 };
 use graph_store_postgres::layout_for_tests::{Layout, STRING_PREFIX_SIZE};
@@ -68,6 +69,8 @@ const THINGS_GQL: &str = r#"
         bigInt: BigInt,
         bigIntArray: [BigInt!]!
         color: Color,
+        timestamp: Timestamp,
+        timestamps: [Timestamp!],
     }
 
     interface Pet {
@@ -109,6 +112,11 @@ const THINGS_GQL: &str = r#"
         description: String,
         test: String
     }
+
+    type Offer @entity @ttl(blocks: 100) {
+        id: ID!,
+        price: Int!
+    }
 "#;
 
 const SCHEMA_NAME: &str = "layout";
@@ -128,6 +136,8 @@ lazy_static! {
     static ref BYTES_VALUE3: H256 = H256::from(hex!(
         "977c084229c72a0fa377cae304eda9099b6a2cb5d83b25cdf0f0969b69874255"
     ));
+    static ref TIMESTAMP_VALUE: Timestamp =
+        Timestamp::from_str("2020-11-01T05:30:00.123456Z").unwrap();
     static ref SCALAR_ENTITY: Entity = {
         let mut entity = Entity::new();
         let strings = Value::from(
@@ -162,6 +172,14 @@ lazy_static! {
             vec![big_int.clone(), (big_int + 1.into()).clone()],
         );
         entity.set("color", "yellow");
+        entity.set("timestamp", *TIMESTAMP_VALUE);
+        entity.set(
+            "timestamps",
+            vec![
+                Value::from(*TIMESTAMP_VALUE),
+                Value::from(Timestamp::from_micros(-1).unwrap()),
+            ],
+        );
         entity.set("__typename", "Scalar");
         entity
     };
@@ -183,7 +201,7 @@ fn remove_test_data(conn: &PgConnection) {
 fn insert_entity(conn: &PgConnection, layout: &Layout, entity_type: &str, entity: Entity) {
     let key = EntityKey {
         subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-        entity_type: entity_type.to_owned(),
+        entity_type: entity_type.into(),
         entity_id: entity.id().unwrap(),
     };
     let errmsg = format!("Failed to insert entity {}[{}]", entity_type, key.entity_id);
@@ -193,7 +211,7 @@ fn insert_entity(conn: &PgConnection, layout: &Layout, entity_type: &str, entity
 fn update_entity(conn: &PgConnection, layout: &Layout, entity_type: &str, entity: Entity) {
     let key = EntityKey {
         subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-        entity_type: entity_type.to_owned(),
+        entity_type: entity_type.into(),
         entity_id: entity.id().unwrap(),
     };
     let errmsg = format!("Failed to update entity {}[{}]", entity_type, key.entity_id);
@@ -395,11 +413,8 @@ where
     let url = postgres_test_url();
     let conn = PgConnection::establish(url.as_str()).expect("Failed to connect to Postgres");
 
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
+    // This forces sequential test execution
+    let mut runtime = TEST_STORE.lock_runtime();
 
     runtime
         .block_on(async {
@@ -479,7 +494,7 @@ fn update() {
         entity.set("bool", Value::Null);
         let key = EntityKey {
             subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-            entity_type: "Scalar".to_owned(),
+            entity_type: "Scalar".into(),
             entity_id: entity.id().unwrap().clone(),
         };
         layout
@@ -514,7 +529,7 @@ fn serialize_bigdecimal() {
 
             let key = EntityKey {
                 subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-                entity_type: "Scalar".to_owned(),
+                entity_type: "Scalar".into(),
                 entity_id: entity.id().unwrap().clone(),
             };
             layout
@@ -548,6 +563,7 @@ fn count_scalar_entities(conn: &PgConnection, layout: &Layout) -> usize {
                 first: None,
                 skip: 0,
             },
+            BTreeMap::new(),
             BLOCK_NUMBER_MAX,
             None,
         )
@@ -566,7 +582,7 @@ fn delete() {
         // Delete where nothing is getting deleted
         let mut key = EntityKey {
             subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-            entity_type: "Scalar".to_owned(),
+            entity_type: "Scalar".into(),
             entity_id: "no such entity".to_owned(),
         };
         let count = layout.delete(&conn, &key, 1).expect("Failed to delete");
@@ -582,6 +598,212 @@ fn delete() {
     });
 }
 
+#[test]
+fn find_timestamp_range() {
+    fn find_ids(conn: &PgConnection, layout: &Layout, filter: EntityFilter) -> Vec<String> {
+        layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                EntityCollection::All(vec!["Scalar".to_owned()]),
+                Some(filter),
+                EntityOrder::Ascending("id".into(), ValueType::String),
+                EntityRange {
+                    first: None,
+                    skip: 0,
+                },
+                BTreeMap::new(),
+                BLOCK_NUMBER_MAX,
+                None,
+            )
+            .expect("Timestamp query failed")
+            .into_iter()
+            .map(|entity| entity.id().unwrap())
+            .collect()
+    }
+
+    fn ts(s: &str) -> Value {
+        Value::from(Timestamp::from_str(s).unwrap())
+    }
+
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_entity(&conn, &layout, "Scalar", SCALAR_ENTITY.clone());
+        let mut two = SCALAR_ENTITY.clone();
+        two.set("id", "two");
+        two.set("timestamp", ts("2020-11-01T06:30:00Z"));
+        insert_entity(&conn, &layout, "Scalar", two);
+
+        // 01:30 in New York happened twice on 2020-11-01, once before and
+        // once after the end of daylight saving time
+        let filter =
+            EntityFilter::GreaterOrEqual("timestamp".into(), ts("2020-11-01T01:30:00-05:00"));
+        assert_eq!(vec!["two"], find_ids(conn, layout, filter));
+        let filter = EntityFilter::LessThan("timestamp".into(), ts("2020-11-01T01:30:00-05:00"));
+        assert_eq!(vec!["one"], find_ids(conn, layout, filter));
+        let filter = EntityFilter::GreaterThan("timestamp".into(), ts("2020-11-01T01:30:00-04:00"));
+        assert_eq!(vec!["one", "two"], find_ids(conn, layout, filter));
+        let filter =
+            EntityFilter::LessOrEqual("timestamp".into(), ts("2020-11-01T05:30:00.123456Z"));
+        assert_eq!(vec!["one"], find_ids(conn, layout, filter));
+        let filter = EntityFilter::Equal("timestamp".into(), Value::from(*TIMESTAMP_VALUE));
+        assert_eq!(vec!["one"], find_ids(conn, layout, filter));
+        Ok(())
+    });
+}
+
+#[test]
+fn repair_block_ranges() {
+    fn name_at(conn: &PgConnection, layout: &Layout, block: BlockNumber) -> Value {
+        layout
+            .find(conn, "Cat", "garfield", block)
+            .expect("Failed to read Cat[garfield]")
+            .unwrap()
+            .get("name")
+            .unwrap()
+            .clone()
+    }
+
+    run_test(|conn, layout| -> Result<(), ()> {
+        insert_pets(&conn, &layout);
+
+        // Simulate what an interrupted revert could leave behind: several
+        // versions that are visible at the same block, two of which start
+        // at the same block. The exclusion constraint would prevent that
+        // in the tables we create now
+        conn.batch_execute(&format!(
+            "alter table {schema}.cat drop constraint cat_id_block_range_excl;
+             insert into {schema}.cat(id, name, block_range)
+             values ('garfield', 'Garfield 3', '[3,)'),
+                    ('garfield', 'Garfield 5', '[5,)'),
+                    ('garfield', 'Garfield 5b', '[5,)')",
+            schema = SCHEMA_NAME
+        ))
+        .expect("Failed to insert overlapping versions");
+
+        let overlaps = layout.check_block_ranges(conn).unwrap();
+        assert_eq!(1, overlaps.len());
+        assert_eq!("Cat", overlaps[0].entity_type);
+        assert_eq!("garfield", overlaps[0].entity_id);
+        assert_eq!(4, overlaps[0].versions);
+
+        // One version is removed and two are clamped
+        assert_eq!(3, layout.repair_block_ranges(conn).unwrap());
+        assert!(layout.check_block_ranges(conn).unwrap().is_empty());
+        assert_eq!(0, layout.repair_block_ranges(conn).unwrap());
+
+        assert_eq!(Value::from("Garfield"), name_at(conn, layout, 2));
+        assert_eq!(Value::from("Garfield 3"), name_at(conn, layout, 4));
+        assert_eq!(Value::from("Garfield 5b"), name_at(conn, layout, 5));
+        assert_eq!(
+            Value::from("Garfield 5b"),
+            name_at(conn, layout, BLOCK_NUMBER_MAX)
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn expire_entities() {
+    fn offers_at(conn: &PgConnection, layout: &Layout, block: BlockNumber) -> Vec<String> {
+        let query = EntityQuery::new(
+            THINGS_SUBGRAPH_ID.clone(),
+            block,
+            EntityCollection::All(vec!["Offer".to_owned()]),
+        );
+        let mut ids: Vec<_> = layout
+            .query::<Entity>(
+                &*LOGGER,
+                conn,
+                query.collection,
+                query.filter,
+                query.order,
+                query.range,
+                query.attributes,
+                block,
+                None,
+            )
+            .expect("layout.query failed to execute query")
+            .into_iter()
+            .map(|entity| entity.id().unwrap())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn visible(conn: &PgConnection, layout: &Layout, id: &str, block: BlockNumber) -> bool {
+        layout
+            .find(conn, "Offer", id, block)
+            .expect("Failed to read Offer")
+            .is_some()
+    }
+
+    run_test(|conn, layout| -> Result<(), ()> {
+        // `stale` was last changed at block 0, `fresh` at block 50, and
+        // `gone` was deleted at block 20
+        conn.batch_execute(&format!(
+            "insert into {schema}.offer(id, price, block_range)
+             values ('stale', 1, '[0,)'),
+                    ('fresh', 2, '[0,50)'),
+                    ('fresh', 3, '[50,)'),
+                    ('gone', 4, '[0,20)')",
+            schema = SCHEMA_NAME
+        ))
+        .expect("Failed to insert offers");
+
+        // Offers expire when they were not changed for more than 100 blocks
+        assert!(visible(conn, layout, "stale", 100));
+        assert!(!visible(conn, layout, "stale", 101));
+        assert!(visible(conn, layout, "fresh", 150));
+        assert!(!visible(conn, layout, "fresh", 151));
+        assert_eq!(vec!["fresh", "gone", "stale"], offers_at(conn, layout, 19));
+        assert_eq!(vec!["fresh", "stale"], offers_at(conn, layout, 100));
+        assert_eq!(vec!["fresh"], offers_at(conn, layout, 101));
+        assert!(offers_at(conn, layout, 151).is_empty());
+
+        // Reads of the latest version still see expired entities, which is
+        // why mappings read at the block they process
+        assert!(visible(conn, layout, "stale", BLOCK_NUMBER_MAX));
+
+        // Expiring at block 120 removes `stale`, but not `gone` which was
+        // last changed at block 20
+        assert_eq!(1, layout.expire(conn, 120).unwrap());
+        assert!(!visible(conn, layout, "stale", BLOCK_NUMBER_MAX));
+        assert!(visible(conn, layout, "gone", 19));
+        assert_eq!(1, layout.expire(conn, 121).unwrap());
+        assert!(!visible(conn, layout, "gone", 19));
+        assert_eq!(vec!["fresh"], offers_at(conn, layout, 120));
+
+        // Removing `fresh` removes all its versions
+        assert_eq!(2, layout.expire(conn, 151).unwrap());
+        assert_eq!(0, layout.expire(conn, 151).unwrap());
+
+        // Setting an offer that expired but was not removed yet creates a
+        // new offer whose range does not overlap the expired one
+        conn.batch_execute(&format!(
+            "insert into {schema}.offer(id, price, block_range)
+             values ('stale', 1, '[0,)')",
+            schema = SCHEMA_NAME
+        ))
+        .expect("Failed to insert offer");
+        let key = EntityKey {
+            subgraph_id: THINGS_SUBGRAPH_ID.clone(),
+            entity_type: "Offer".into(),
+            entity_id: "stale".to_owned(),
+        };
+        let offer = Entity::from(vec![
+            ("id", Value::from("stale")),
+            ("price", Value::from(5)),
+        ]);
+        layout
+            .insert(conn, &key, offer, 200)
+            .expect("Failed to insert new offer");
+        assert!(visible(conn, layout, "stale", 200));
+        assert!(!visible(conn, layout, "stale", 199));
+        assert!(layout.check_block_ranges(conn).unwrap().is_empty());
+        Ok(())
+    })
+}
+
 #[test]
 fn conflicting_entity() {
     run_test(|conn, layout| -> Result<(), ()> {
@@ -646,6 +868,7 @@ fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
                 query.filter,
                 query.order,
                 query.range,
+                query.attributes,
                 BLOCK_NUMBER_MAX,
                 None,
             )
@@ -692,12 +915,12 @@ trait EasyOrder {
 impl EasyOrder for EntityQuery {
     fn asc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Ascending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Ascending(attr.into(), ValueType::String))
     }
 
     fn desc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Descending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Descending(attr.into(), ValueType::String))
     }
 
     fn unordered(self) -> Self {
@@ -784,14 +1007,14 @@ fn find_list_contains() {
 fn find_string_equal() {
     test_find(
         vec!["2"],
-        user_query().filter(EntityFilter::Equal("name".to_owned(), "Cindini".into())),
+        user_query().filter(EntityFilter::Equal("name".into(), "Cindini".into())),
     );
 
     // Test that we can order by id
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::Equal("name".to_owned(), "Cindini".into()))
+            .filter(EntityFilter::Equal("name".into(), "Cindini".into()))
             .desc("id"),
     )
 }
@@ -801,7 +1024,7 @@ fn find_string_not_equal() {
     test_find(
         vec!["1", "3"],
         user_query()
-            .filter(EntityFilter::Not("name".to_owned(), "Cindini".into()))
+            .filter(EntityFilter::Not("name".into(), "Cindini".into()))
             .asc("name"),
     )
 }
@@ -810,7 +1033,7 @@ fn find_string_not_equal() {
 fn find_string_greater_than() {
     test_find(
         vec!["3"],
-        user_query().filter(EntityFilter::GreaterThan("name".to_owned(), "Kundi".into())),
+        user_query().filter(EntityFilter::GreaterThan("name".into(), "Kundi".into())),
     )
 }
 
@@ -819,7 +1042,7 @@ fn find_string_less_than_order_by_asc() {
     test_find(
         vec!["2", "1"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "Kundi".into()))
+            .filter(EntityFilter::LessThan("name".into(), "Kundi".into()))
             .asc("name"),
     )
 }
@@ -829,7 +1052,7 @@ fn find_string_less_than_order_by_desc() {
     test_find(
         vec!["1", "2"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "Kundi".into()))
+            .filter(EntityFilter::LessThan("name".into(), "Kundi".into()))
             .desc("name"),
     )
 }
@@ -839,7 +1062,7 @@ fn find_string_less_than_range() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "ZZZ".into()))
+            .filter(EntityFilter::LessThan("name".into(), "ZZZ".into()))
             .desc("name")
             .first(1)
             .skip(1),
@@ -852,8 +1075,8 @@ fn find_string_multiple_and() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::And(vec![
-                EntityFilter::LessThan("name".to_owned(), "Cz".into()),
-                EntityFilter::Equal("name".to_owned(), "Cindini".into()),
+                EntityFilter::LessThan("name".into(), "Cz".into()),
+                EntityFilter::Equal("name".into(), "Cindini".into()),
             ]))
             .desc("name"),
     )
@@ -864,7 +1087,7 @@ fn find_string_ends_with() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::EndsWith("name".to_owned(), "ini".into()))
+            .filter(EntityFilter::EndsWith("name".into(), "ini".into()))
             .desc("name"),
     )
 }
@@ -874,7 +1097,7 @@ fn find_string_not_ends_with() {
     test_find(
         vec!["3", "1"],
         user_query()
-            .filter(EntityFilter::NotEndsWith("name".to_owned(), "ini".into()))
+            .filter(EntityFilter::NotEndsWith("name".into(), "ini".into()))
             .desc("name"),
     )
 }
@@ -885,7 +1108,7 @@ fn find_string_in() {
         vec!["1"],
         user_query()
             .filter(EntityFilter::In(
-                "name".to_owned(),
+                "name".into(),
                 vec!["Jono".into(), "Nobody".into(), "Still nobody".into()],
             ))
             .desc("name"),
@@ -896,7 +1119,7 @@ fn find_string_in() {
 fn find_empty_in() {
     test_find(
         vec![],
-        user_query().filter(EntityFilter::In("name".to_owned(), vec![])),
+        user_query().filter(EntityFilter::In("name".into(), vec![])),
     )
 }
 
@@ -906,7 +1129,7 @@ fn find_string_not_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "name".to_owned(),
+                "name".into(),
                 vec!["Shaqueeena".into()],
             ))
             .desc("name"),
@@ -918,7 +1141,7 @@ fn find_float_equal() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::Equal(
-            "weight".to_owned(),
+            "weight".into(),
             Value::BigDecimal(184.4.into()),
         )),
     )
@@ -930,7 +1153,7 @@ fn find_float_not_equal() {
         vec!["3", "2"],
         user_query()
             .filter(EntityFilter::Not(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(184.4.into()),
             ))
             .desc("name"),
@@ -942,7 +1165,7 @@ fn find_float_greater_than() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::GreaterThan(
-            "weight".to_owned(),
+            "weight".into(),
             Value::BigDecimal(160.0.into()),
         )),
     )
@@ -954,7 +1177,7 @@ fn find_float_less_than() {
         vec!["2", "3"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(160.0.into()),
             ))
             .asc("name"),
@@ -967,7 +1190,7 @@ fn find_float_less_than_order_by_desc() {
         vec!["3", "2"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(160.0.into()),
             ))
             .desc("name"),
@@ -980,7 +1203,7 @@ fn find_float_less_than_range() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(161.0.into()),
             ))
             .desc("name")
@@ -995,7 +1218,7 @@ fn find_float_in() {
         vec!["3", "1"],
         user_query()
             .filter(EntityFilter::In(
-                "weight".to_owned(),
+                "weight".into(),
                 vec![
                     Value::BigDecimal(184.4.into()),
                     Value::BigDecimal(111.7.into()),
@@ -1012,7 +1235,7 @@ fn find_float_not_in() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "weight".to_owned(),
+                "weight".into(),
                 vec![
                     Value::BigDecimal(184.4.into()),
                     Value::BigDecimal(111.7.into()),
@@ -1028,7 +1251,7 @@ fn find_int_equal() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::Equal("age".to_owned(), Value::Int(67 as i32)))
+            .filter(EntityFilter::Equal("age".into(), Value::Int(67 as i32)))
             .desc("name"),
     )
 }
@@ -1038,7 +1261,7 @@ fn find_int_not_equal() {
     test_find(
         vec!["3", "2"],
         user_query()
-            .filter(EntityFilter::Not("age".to_owned(), Value::Int(67 as i32)))
+            .filter(EntityFilter::Not("age".into(), Value::Int(67 as i32)))
             .desc("name"),
     )
 }
@@ -1048,7 +1271,7 @@ fn find_int_greater_than() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::GreaterThan(
-            "age".to_owned(),
+            "age".into(),
             Value::Int(43 as i32),
         )),
     )
@@ -1060,7 +1283,7 @@ fn find_int_greater_or_equal() {
         vec!["2", "1"],
         user_query()
             .filter(EntityFilter::GreaterOrEqual(
-                "age".to_owned(),
+                "age".into(),
                 Value::Int(43 as i32),
             ))
             .asc("name"),
@@ -1072,10 +1295,7 @@ fn find_int_less_than() {
     test_find(
         vec!["2", "3"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(50 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(50 as i32)))
             .asc("name"),
     )
 }
@@ -1086,7 +1306,7 @@ fn find_int_less_or_equal() {
         vec!["2", "3"],
         user_query()
             .filter(EntityFilter::LessOrEqual(
-                "age".to_owned(),
+                "age".into(),
                 Value::Int(43 as i32),
             ))
             .asc("name"),
@@ -1098,10 +1318,7 @@ fn find_int_less_than_order_by_desc() {
     test_find(
         vec!["3", "2"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(50 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(50 as i32)))
             .desc("name"),
     )
 }
@@ -1111,10 +1328,7 @@ fn find_int_less_than_range() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(67 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(67 as i32)))
             .desc("name")
             .first(1)
             .skip(1),
@@ -1127,7 +1341,7 @@ fn find_int_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::In(
-                "age".to_owned(),
+                "age".into(),
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             ))
             .desc("name")
@@ -1141,7 +1355,7 @@ fn find_int_not_in() {
         vec!["3"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "age".to_owned(),
+                "age".into(),
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             ))
             .desc("name")
@@ -1154,7 +1368,7 @@ fn find_bool_equal() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::Equal("coffee".to_owned(), Value::Bool(true)))
+            .filter(EntityFilter::Equal("coffee".into(), Value::Bool(true)))
             .desc("name"),
     )
 }
@@ -1164,7 +1378,7 @@ fn find_bool_not_equal() {
     test_find(
         vec!["1", "3"],
         user_query()
-            .filter(EntityFilter::Not("coffee".to_owned(), Value::Bool(true)))
+            .filter(EntityFilter::Not("coffee".into(), Value::Bool(true)))
             .asc("name"),
     )
 }
@@ -1174,10 +1388,7 @@ fn find_bool_in() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::In(
-                "coffee".to_owned(),
-                vec![Value::Bool(true)],
-            ))
+            .filter(EntityFilter::In("coffee".into(), vec![Value::Bool(true)]))
             .desc("name")
             .first(5),
     )
@@ -1189,7 +1400,7 @@ fn find_bool_not_in() {
         vec!["3", "1"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "coffee".to_owned(),
+                "coffee".into(),
                 vec![Value::Bool(true)],
             ))
             .desc("name")
@@ -1203,7 +1414,7 @@ fn find_bytes_equal() {
         vec!["1"],
         user_query()
             .filter(EntityFilter::Equal(
-                "bin_name".to_owned(),
+                "bin_name".into(),
                 Value::Bytes("Jono".as_bytes().into()),
             ))
             .desc("name"),
@@ -1215,10 +1426,7 @@ fn find_null_equal() {
     test_find(
         vec!["3"],
         user_query()
-            .filter(EntityFilter::Equal(
-                "favorite_color".to_owned(),
-                Value::Null,
-            ))
+            .filter(EntityFilter::Equal("favorite_color".into(), Value::Null))
             .desc("name"),
     )
 }
@@ -1228,7 +1436,7 @@ fn find_null_not_equal() {
     test_find(
         vec!["1", "2"],
         user_query()
-            .filter(EntityFilter::Not("favorite_color".to_owned(), Value::Null))
+            .filter(EntityFilter::Not("favorite_color".into(), Value::Null))
             .desc("name"),
     )
 }
@@ -1239,7 +1447,7 @@ fn find_null_not_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "favorite_color".to_owned(),
+                "favorite_color".into(),
                 vec![Value::Null],
             ))
             .desc("name"),
@@ -1249,7 +1457,7 @@ fn find_null_not_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "favorite_color".to_owned(),
+                "favorite_color".into(),
                 vec!["red".into(), Value::Null],
             ))
             .desc("name"),
@@ -1282,14 +1490,85 @@ fn find_order_by_string() {
     test_find(vec!["3", "1", "2"], user_query().desc("name"));
 }
 
+#[test]
+fn paginate_order_by_nullable() {
+    run_test(|conn, layout| -> Result<(), ()> {
+        let names = vec![
+            ("a", Some("x")),
+            ("b", None),
+            ("c", Some("y")),
+            ("d", None),
+            ("e", Some("x")),
+            ("f", None),
+            ("g", Some("z")),
+            ("h", None),
+        ];
+        for (id, name) in names {
+            let mut entity = Entity::new();
+            entity.set("id", id);
+            if let Some(name) = name {
+                entity.set("name", name);
+            }
+            insert_entity(conn, layout, "NullableStrings", entity);
+        }
+
+        let query = |order: &EntityOrder, first: Option<u32>, skip: u32| -> Vec<String> {
+            layout
+                .query::<Entity>(
+                    &*LOGGER,
+                    conn,
+                    EntityCollection::All(vec!["NullableStrings".to_owned()]),
+                    None,
+                    order.clone(),
+                    EntityRange { first, skip },
+                    BTreeMap::new(),
+                    BLOCK_NUMBER_MAX,
+                    None,
+                )
+                .expect("layout.query failed to execute query")
+                .into_iter()
+                .map(|entity| entity.id().unwrap())
+                .collect()
+        };
+
+        // `NULL` sorts last in both directions, and entities with the same
+        // name are sorted by `id`
+        let asc = EntityOrder::Ascending("name".into(), ValueType::String);
+        let desc = EntityOrder::Descending("name".into(), ValueType::String);
+        for (order, expected) in vec![
+            (asc, vec!["a", "e", "c", "g", "b", "d", "f", "h"]),
+            (desc, vec!["g", "c", "a", "e", "b", "d", "f", "h"]),
+        ] {
+            assert_eq!(expected, query(&order, None, 0));
+
+            // Paging through the entities visits every one of them exactly
+            // once, no matter where pages start and end relative to the
+            // entities whose name is `NULL`
+            for page_size in 1..=9 {
+                let mut ids = Vec::new();
+                loop {
+                    let page = query(&order, Some(page_size), ids.len() as u32);
+                    assert!(page.len() <= page_size as usize);
+                    if page.is_empty() {
+                        break;
+                    }
+                    ids.extend(page);
+                }
+                assert_eq!(expected, ids, "page size {}", page_size);
+            }
+        }
+        Ok(())
+    });
+}
+
 #[test]
 fn find_where_nested_and_or() {
     test_find(
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::And(vec![EntityFilter::Or(vec![
-                EntityFilter::Equal("id".to_owned(), Value::from("1")),
-                EntityFilter::Equal("id".to_owned(), Value::from("2")),
+                EntityFilter::Equal("id".into(), Value::from("1")),
+                EntityFilter::Equal("id".into(), Value::from("2")),
             ])]))
             .asc("id"),
     )
@@ -1300,10 +1579,7 @@ fn find_enum_equal() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::Equal(
-                "favorite_color".to_owned(),
-                "red".into(),
-            ))
+            .filter(EntityFilter::Equal("favorite_color".into(), "red".into()))
             .desc("name"),
     )
 }
@@ -1313,7 +1589,7 @@ fn find_enum_not_equal() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::Not("favorite_color".to_owned(), "red".into()))
+            .filter(EntityFilter::Not("favorite_color".into(), "red".into()))
             .asc("name"),
     )
 }
@@ -1324,7 +1600,7 @@ fn find_enum_in() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::In(
-                "favorite_color".to_owned(),
+                "favorite_color".into(),
                 vec!["red".into()],
             ))
             .desc("name")
@@ -1338,7 +1614,7 @@ fn find_enum_not_in() {
         vec!["1"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "favorite_color".to_owned(),
+                "favorite_color".into(),
                 vec!["red".into()],
             ))
             .desc("name")
@@ -1383,6 +1659,7 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
                 query.filter,
                 query.order,
                 query.range,
+                query.attributes,
                 BLOCK_NUMBER_MAX,
                 None,
             )
@@ -1407,7 +1684,7 @@ fn text_find(expected_entity_ids: Vec<&str>, filter: EntityFilter) {
 fn text_equal() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::Equal("name".to_owned(), name.into())
+        EntityFilter::Equal("name".into(), name.into())
     }
     text_find(vec!["a1"], filter(a1));
     text_find(vec!["a2"], filter(a2));
@@ -1419,7 +1696,7 @@ fn text_equal() {
 fn text_not_equal() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::Not("name".to_owned(), name.into())
+        EntityFilter::Not("name".into(), name.into())
     }
     text_find(vec!["a2", "a2b", "a3"], filter(a1));
     text_find(vec!["a1", "a2b", "a3"], filter(a2));
@@ -1431,7 +1708,7 @@ fn text_not_equal() {
 fn text_less_than() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::LessThan("name".to_owned(), name.into())
+        EntityFilter::LessThan("name".into(), name.into())
     }
     text_find(vec![], filter(a1));
     text_find(vec!["a1"], filter(a2));
@@ -1443,7 +1720,7 @@ fn text_less_than() {
 fn text_less_or_equal() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::LessOrEqual("name".to_owned(), name.into())
+        EntityFilter::LessOrEqual("name".into(), name.into())
     }
     text_find(vec!["a1"], filter(a1));
     text_find(vec!["a1", "a2"], filter(a2));
@@ -1455,7 +1732,7 @@ fn text_less_or_equal() {
 fn text_greater_than() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::GreaterThan("name".to_owned(), name.into())
+        EntityFilter::GreaterThan("name".into(), name.into())
     }
     text_find(vec!["a2", "a2b", "a3"], filter(a1));
     text_find(vec!["a2b", "a3"], filter(a2));
@@ -1467,7 +1744,7 @@ fn text_greater_than() {
 fn text_greater_or_equal() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(name: String) -> EntityFilter {
-        EntityFilter::GreaterOrEqual("name".to_owned(), name.into())
+        EntityFilter::GreaterOrEqual("name".into(), name.into())
     }
     text_find(vec!["a1", "a2", "a2b", "a3"], filter(a1));
     text_find(vec!["a2", "a2b", "a3"], filter(a2));
@@ -1480,7 +1757,7 @@ fn text_in() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(names: Vec<&str>) -> EntityFilter {
         EntityFilter::In(
-            "name".to_owned(),
+            "name".into(),
             names
                 .into_iter()
                 .map(|name| Value::from(name.to_owned()))
@@ -1501,7 +1778,7 @@ fn text_not_in() {
     let (a1, a2, a2b, a3) = ferrets();
     fn filter(names: Vec<&str>) -> EntityFilter {
         EntityFilter::NotIn(
-            "name".to_owned(),
+            "name".into(),
             names
                 .into_iter()
                 .map(|name| Value::from(name.to_owned()))
@@ -77,7 +77,7 @@ fn remove_test_data(conn: &PgConnection) {
 fn insert_entity(conn: &PgConnection, layout: &Layout, entity_type: &str, entity: Entity) {
     let key = EntityKey {
         subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-        entity_type: entity_type.to_owned(),
+        entity_type: entity_type.into(),
         entity_id: entity.id().unwrap(),
     };
     let errmsg = format!("Failed to insert entity {}[{}]", entity_type, key.entity_id);
@@ -161,11 +161,8 @@ where
     let url = postgres_test_url();
     let conn = PgConnection::establish(url.as_str()).expect("Failed to connect to Postgres");
 
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
+    // This forces sequential test execution
+    let mut runtime = TEST_STORE.lock_runtime();
 
     runtime
         .block_on(async {
@@ -283,7 +280,7 @@ fn update() {
         entity.set("name", "Moo");
         let key = EntityKey {
             subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-            entity_type: "Thing".to_owned(),
+            entity_type: "Thing".into(),
             entity_id: entity.id().unwrap().clone(),
         };
         layout
@@ -312,7 +309,7 @@ fn delete() {
         // Delete where nothing is getting deleted
         let mut key = EntityKey {
             subgraph_id: THINGS_SUBGRAPH_ID.clone(),
-            entity_type: "Thing".to_owned(),
+            entity_type: "Thing".into(),
             entity_id: "ffff".to_owned(),
         };
         let count = layout.delete(&conn, &key, 1).expect("Failed to delete");
@@ -392,6 +389,7 @@ fn query() {
                 None,
                 EntityOrder::Default,
                 EntityRange::first(10),
+                BTreeMap::new(),
                 BLOCK_NUMBER_MAX,
                 None,
             )
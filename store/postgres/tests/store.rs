@@ -15,7 +15,8 @@ use graph::data::subgraph::schema::*;
 use graph::data::subgraph::*;
 use graph::prelude::*;
 use graph_store_postgres::layout_for_tests::STRING_PREFIX_SIZE;
-use graph_store_postgres::NetworkStore as DieselStore;
+use graph_store_postgres::transact_metrics::{Phase, TransactMetrics};
+use graph_store_postgres::{ConsistencyPolicy, NetworkStore as DieselStore};
 use web3::types::{Address, H256};
 
 const USER_GQL: &str = "
@@ -126,11 +127,8 @@ where
 {
     let store = STORE.clone();
 
-    // Lock regardless of poisoning. This also forces sequential test execution.
-    let mut runtime = match STORE_RUNTIME.lock() {
-        Ok(guard) => guard,
-        Err(err) => err.into_inner(),
-    };
+    // This forces sequential test execution
+    let mut runtime = TEST_STORE.lock_runtime();
 
     runtime
         .block_on(async {
@@ -160,6 +158,9 @@ fn insert_test_data(store: Arc<DieselStore>) {
         schema: TEST_SUBGRAPH_SCHEMA.clone(),
         data_sources: vec![],
         graft: None,
+        history_blocks: None,
+        strict_bytes: false,
+        computed: None,
         templates: vec![],
     };
 
@@ -270,7 +271,7 @@ fn create_test_entity(
     EntityOperation::Set {
         key: EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: entity_type.to_owned(),
+            entity_type: entity_type.into(),
             entity_id: id.to_owned(),
         },
         data: test_entity,
@@ -302,7 +303,7 @@ fn delete_entity() {
     run_test(|store| -> Result<(), ()> {
         let entity_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "3".to_owned(),
         };
 
@@ -337,7 +338,7 @@ fn get_entity_1() {
     run_test(|store| -> Result<(), ()> {
         let key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "1".to_owned(),
         };
         let result = store.get(key).unwrap();
@@ -374,7 +375,7 @@ fn get_entity_3() {
     run_test(|store| -> Result<(), ()> {
         let key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "3".to_owned(),
         };
         let result = store.get(key).unwrap();
@@ -410,7 +411,7 @@ fn insert_entity() {
     run_test(|store| -> Result<(), ()> {
         let entity_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "7".to_owned(),
         };
         let test_entity = create_test_entity(
@@ -443,12 +444,61 @@ fn insert_entity() {
     })
 }
 
+#[test]
+fn transact_metrics() {
+    run_test(|store| -> Result<(), ()> {
+        let inner = store.store();
+        let metrics = inner.transact_metrics();
+        let bucket = TransactMetrics::deployment_bucket(&TEST_SUBGRAPH_ID);
+        let counts: Vec<_> = Phase::ALL
+            .iter()
+            .map(|phase| metrics.histogram(*phase, &bucket).get_sample_count())
+            .collect();
+        let user_count = metrics.table_histogram(USER).get_sample_count();
+
+        let test_entity = create_test_entity(
+            "7",
+            USER,
+            "Wanjon",
+            "wanawana@email.com",
+            76 as i32,
+            111.7,
+            true,
+            Some("green"),
+        );
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            vec![test_entity],
+        )
+        .unwrap();
+
+        // Every phase was observed exactly once for the one block we wrote,
+        // and none of them took an absurd amount of time
+        for (phase, count) in Phase::ALL.iter().zip(counts) {
+            let histogram = metrics.histogram(*phase, &bucket);
+            assert_eq!(count + 1, histogram.get_sample_count(), "{:?}", phase);
+            assert!(histogram.get_sample_sum() >= 0.0, "{:?}", phase);
+            assert!(histogram.get_sample_sum() < 600.0, "{:?}", phase);
+        }
+
+        // The one statement for the `User` table was timed
+        let histogram = metrics.table_histogram(USER);
+        assert_eq!(user_count + 1, histogram.get_sample_count());
+        assert!(histogram.get_sample_sum() > 0.0);
+        assert!(histogram.get_sample_sum() < 600.0);
+
+        Ok(())
+    })
+}
+
 #[test]
 fn update_existing() {
     run_test(|store| -> Result<(), ()> {
         let entity_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "1".to_owned(),
         };
 
@@ -500,7 +550,7 @@ fn partially_update_existing() {
     run_test(|store| -> Result<(), ()> {
         let entity_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "1".to_owned(),
         };
 
@@ -569,6 +619,36 @@ fn test_find(expected_entity_ids: Vec<&str>, query: EntityQuery) {
     })
 }
 
+#[test]
+fn query_store_rejects_system_entity_types() {
+    run_test(|store| -> Result<(), ()> {
+        let poi_query = || {
+            EntityQuery::new(
+                TEST_SUBGRAPH_ID.clone(),
+                BLOCK_NUMBER_MAX,
+                EntityCollection::All(vec![POI_OBJECT.to_owned()]),
+            )
+        };
+
+        // graph-node can still read its own entities
+        store
+            .find(poi_query())
+            .expect("store.find failed to query the Proof of Indexing");
+
+        match store
+            .clone()
+            .query_store(false)
+            .find_query_values(poi_query())
+        {
+            Err(QueryExecutionError::SystemEntityType(entity_type)) => {
+                assert_eq!(POI_OBJECT, entity_type)
+            }
+            other => panic!("expected a SystemEntityType error, got {:?}", other),
+        }
+        Ok(())
+    })
+}
+
 fn user_query() -> EntityQuery {
     EntityQuery::new(
         TEST_SUBGRAPH_ID.clone(),
@@ -585,12 +665,12 @@ trait EasyOrder {
 impl EasyOrder for EntityQuery {
     fn asc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Ascending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Ascending(attr.into(), ValueType::String))
     }
 
     fn desc(self, attr: &str) -> Self {
         // The ValueType doesn't matter since relational layouts ignore it
-        self.order(EntityOrder::Descending(attr.to_owned(), ValueType::String))
+        self.order(EntityOrder::Descending(attr.into(), ValueType::String))
     }
 }
 
@@ -606,7 +686,7 @@ fn find_string_contains() {
 fn find_string_equal() {
     test_find(
         vec!["2"],
-        user_query().filter(EntityFilter::Equal("name".to_owned(), "Cindini".into())),
+        user_query().filter(EntityFilter::Equal("name".into(), "Cindini".into())),
     )
 }
 
@@ -615,7 +695,7 @@ fn find_string_not_equal() {
     test_find(
         vec!["1", "3"],
         user_query()
-            .filter(EntityFilter::Not("name".to_owned(), "Cindini".into()))
+            .filter(EntityFilter::Not("name".into(), "Cindini".into()))
             .asc("name"),
     )
 }
@@ -624,7 +704,7 @@ fn find_string_not_equal() {
 fn find_string_greater_than() {
     test_find(
         vec!["3"],
-        user_query().filter(EntityFilter::GreaterThan("name".to_owned(), "Kundi".into())),
+        user_query().filter(EntityFilter::GreaterThan("name".into(), "Kundi".into())),
     )
 }
 
@@ -633,7 +713,7 @@ fn find_string_less_than_order_by_asc() {
     test_find(
         vec!["2", "1"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "Kundi".into()))
+            .filter(EntityFilter::LessThan("name".into(), "Kundi".into()))
             .asc("name"),
     )
 }
@@ -643,7 +723,7 @@ fn find_string_less_than_order_by_desc() {
     test_find(
         vec!["1", "2"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "Kundi".into()))
+            .filter(EntityFilter::LessThan("name".into(), "Kundi".into()))
             .desc("name"),
     )
 }
@@ -653,7 +733,7 @@ fn find_string_less_than_range() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::LessThan("name".to_owned(), "ZZZ".into()))
+            .filter(EntityFilter::LessThan("name".into(), "ZZZ".into()))
             .desc("name")
             .first(1)
             .skip(1),
@@ -666,8 +746,8 @@ fn find_string_multiple_and() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::And(vec![
-                EntityFilter::LessThan("name".to_owned(), "Cz".into()),
-                EntityFilter::Equal("name".to_owned(), "Cindini".into()),
+                EntityFilter::LessThan("name".into(), "Cz".into()),
+                EntityFilter::Equal("name".into(), "Cindini".into()),
             ]))
             .desc("name"),
     )
@@ -678,7 +758,7 @@ fn find_string_ends_with() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::EndsWith("name".to_owned(), "ini".into()))
+            .filter(EntityFilter::EndsWith("name".into(), "ini".into()))
             .desc("name"),
     )
 }
@@ -688,7 +768,7 @@ fn find_string_not_ends_with() {
     test_find(
         vec!["3", "1"],
         user_query()
-            .filter(EntityFilter::NotEndsWith("name".to_owned(), "ini".into()))
+            .filter(EntityFilter::NotEndsWith("name".into(), "ini".into()))
             .desc("name"),
     )
 }
@@ -698,7 +778,7 @@ fn find_string_in() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::In("name".to_owned(), vec!["Johnton".into()]))
+            .filter(EntityFilter::In("name".into(), vec!["Johnton".into()]))
             .desc("name"),
     )
 }
@@ -709,7 +789,7 @@ fn find_string_not_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "name".to_owned(),
+                "name".into(),
                 vec!["Shaqueeena".into()],
             ))
             .desc("name"),
@@ -721,7 +801,7 @@ fn find_float_equal() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::Equal(
-            "weight".to_owned(),
+            "weight".into(),
             Value::BigDecimal(184.4.into()),
         )),
     )
@@ -733,7 +813,7 @@ fn find_float_not_equal() {
         vec!["3", "2"],
         user_query()
             .filter(EntityFilter::Not(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(184.4.into()),
             ))
             .desc("name"),
@@ -745,7 +825,7 @@ fn find_float_greater_than() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::GreaterThan(
-            "weight".to_owned(),
+            "weight".into(),
             Value::BigDecimal(160.0.into()),
         )),
     )
@@ -757,7 +837,7 @@ fn find_float_less_than() {
         vec!["2", "3"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(160.0.into()),
             ))
             .asc("name"),
@@ -770,7 +850,7 @@ fn find_float_less_than_order_by_desc() {
         vec!["3", "2"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(160.0.into()),
             ))
             .desc("name"),
@@ -783,7 +863,7 @@ fn find_float_less_than_range() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::LessThan(
-                "weight".to_owned(),
+                "weight".into(),
                 Value::BigDecimal(161.0.into()),
             ))
             .desc("name")
@@ -798,7 +878,7 @@ fn find_float_in() {
         vec!["3", "1"],
         user_query()
             .filter(EntityFilter::In(
-                "weight".to_owned(),
+                "weight".into(),
                 vec![
                     Value::BigDecimal(184.4.into()),
                     Value::BigDecimal(111.7.into()),
@@ -815,7 +895,7 @@ fn find_float_not_in() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "weight".to_owned(),
+                "weight".into(),
                 vec![
                     Value::BigDecimal(184.4.into()),
                     Value::BigDecimal(111.7.into()),
@@ -831,7 +911,7 @@ fn find_int_equal() {
     test_find(
         vec!["1"],
         user_query()
-            .filter(EntityFilter::Equal("age".to_owned(), Value::Int(67 as i32)))
+            .filter(EntityFilter::Equal("age".into(), Value::Int(67 as i32)))
             .desc("name"),
     )
 }
@@ -841,7 +921,7 @@ fn find_int_not_equal() {
     test_find(
         vec!["3", "2"],
         user_query()
-            .filter(EntityFilter::Not("age".to_owned(), Value::Int(67 as i32)))
+            .filter(EntityFilter::Not("age".into(), Value::Int(67 as i32)))
             .desc("name"),
     )
 }
@@ -851,7 +931,7 @@ fn find_int_greater_than() {
     test_find(
         vec!["1"],
         user_query().filter(EntityFilter::GreaterThan(
-            "age".to_owned(),
+            "age".into(),
             Value::Int(43 as i32),
         )),
     )
@@ -863,7 +943,7 @@ fn find_int_greater_or_equal() {
         vec!["2", "1"],
         user_query()
             .filter(EntityFilter::GreaterOrEqual(
-                "age".to_owned(),
+                "age".into(),
                 Value::Int(43 as i32),
             ))
             .asc("name"),
@@ -875,10 +955,7 @@ fn find_int_less_than() {
     test_find(
         vec!["2", "3"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(50 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(50 as i32)))
             .asc("name"),
     )
 }
@@ -889,7 +966,7 @@ fn find_int_less_or_equal() {
         vec!["2", "3"],
         user_query()
             .filter(EntityFilter::LessOrEqual(
-                "age".to_owned(),
+                "age".into(),
                 Value::Int(43 as i32),
             ))
             .asc("name"),
@@ -901,10 +978,7 @@ fn find_int_less_than_order_by_desc() {
     test_find(
         vec!["3", "2"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(50 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(50 as i32)))
             .desc("name"),
     )
 }
@@ -914,10 +988,7 @@ fn find_int_less_than_range() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::LessThan(
-                "age".to_owned(),
-                Value::Int(67 as i32),
-            ))
+            .filter(EntityFilter::LessThan("age".into(), Value::Int(67 as i32)))
             .desc("name")
             .first(1)
             .skip(1),
@@ -930,7 +1001,7 @@ fn find_int_in() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::In(
-                "age".to_owned(),
+                "age".into(),
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             ))
             .desc("name")
@@ -944,7 +1015,7 @@ fn find_int_not_in() {
         vec!["3"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "age".to_owned(),
+                "age".into(),
                 vec![Value::Int(67 as i32), Value::Int(43 as i32)],
             ))
             .desc("name")
@@ -957,7 +1028,7 @@ fn find_bool_equal() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::Equal("coffee".to_owned(), Value::Bool(true)))
+            .filter(EntityFilter::Equal("coffee".into(), Value::Bool(true)))
             .desc("name"),
     )
 }
@@ -967,7 +1038,7 @@ fn find_bool_not_equal() {
     test_find(
         vec!["1", "3"],
         user_query()
-            .filter(EntityFilter::Not("coffee".to_owned(), Value::Bool(true)))
+            .filter(EntityFilter::Not("coffee".into(), Value::Bool(true)))
             .asc("name"),
     )
 }
@@ -977,10 +1048,7 @@ fn find_bool_in() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::In(
-                "coffee".to_owned(),
-                vec![Value::Bool(true)],
-            ))
+            .filter(EntityFilter::In("coffee".into(), vec![Value::Bool(true)]))
             .desc("name")
             .first(5),
     )
@@ -992,7 +1060,7 @@ fn find_bool_not_in() {
         vec!["3", "1"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "coffee".to_owned(),
+                "coffee".into(),
                 vec![Value::Bool(true)],
             ))
             .desc("name")
@@ -1006,7 +1074,7 @@ fn find_bytes_equal() {
         vec!["1"],
         user_query()
             .filter(EntityFilter::Equal(
-                "bin_name".to_owned(),
+                "bin_name".into(),
                 Value::Bytes("Johnton".as_bytes().into()),
             ))
             .desc("name"),
@@ -1018,10 +1086,7 @@ fn find_null_equal() {
     test_find(
         vec!["3", "1"],
         user_query()
-            .filter(EntityFilter::Equal(
-                "favorite_color".to_owned(),
-                Value::Null,
-            ))
+            .filter(EntityFilter::Equal("favorite_color".into(), Value::Null))
             .desc("name"),
     )
 }
@@ -1031,7 +1096,7 @@ fn find_null_not_equal() {
     test_find(
         vec!["2"],
         user_query()
-            .filter(EntityFilter::Not("favorite_color".to_owned(), Value::Null))
+            .filter(EntityFilter::Not("favorite_color".into(), Value::Null))
             .desc("name"),
     )
 }
@@ -1042,7 +1107,7 @@ fn find_null_not_in() {
         vec!["2"],
         user_query()
             .filter(EntityFilter::NotIn(
-                "favorite_color".to_owned(),
+                "favorite_color".into(),
                 vec![Value::Null],
             ))
             .desc("name"),
@@ -1079,8 +1144,8 @@ fn find_where_nested_and_or() {
         vec!["1", "2"],
         user_query()
             .filter(EntityFilter::And(vec![EntityFilter::Or(vec![
-                EntityFilter::Equal("id".to_owned(), Value::from("1")),
-                EntityFilter::Equal("id".to_owned(), Value::from("2")),
+                EntityFilter::Equal("id".into(), Value::from("1")),
+                EntityFilter::Equal("id".into(), Value::from("2")),
             ])]))
             .asc("id"),
     )
@@ -1096,6 +1161,7 @@ fn make_entity_change(
         entity_type: entity_type.to_owned(),
         entity_id: entity_id.to_owned(),
         operation: op,
+        changed_fields: None,
     }
 }
 
@@ -1105,6 +1171,7 @@ fn make_deployment_change(entity_id: &str, op: EntityChangeOperation) -> EntityC
         entity_type: "SubgraphDeployment".to_owned(),
         entity_id: entity_id.to_owned(),
         operation: op,
+        changed_fields: None,
     }
 }
 
@@ -1201,7 +1268,7 @@ fn check_basic_revert(
 ) -> impl Future<Item = (), Error = tokio::time::Elapsed> {
     let this_query = user_query()
         .filter(EntityFilter::Equal(
-            "name".to_owned(),
+            "name".into(),
             Value::String("Shaqueeena".to_owned()),
         ))
         .desc("name");
@@ -1281,7 +1348,7 @@ fn revert_block_with_delete() {
     run_test(|store| {
         let this_query = user_query()
             .filter(EntityFilter::Equal(
-                "name".to_owned(),
+                "name".into(),
                 Value::String("Cindini".to_owned()),
             ))
             .desc("name");
@@ -1289,7 +1356,7 @@ fn revert_block_with_delete() {
         // Delete entity with id=2
         let del_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "2".to_owned(),
         };
 
@@ -1349,7 +1416,7 @@ fn revert_block_with_partial_update() {
     run_test(|store| {
         let entity_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "1".to_owned(),
         };
 
@@ -1416,6 +1483,7 @@ fn mock_data_source() -> DataSource {
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            addresses: vec![],
             abi: String::from("123123"),
             start_block: 0,
         },
@@ -1459,13 +1527,72 @@ fn mock_data_source() -> DataSource {
     }
 }
 
+#[test]
+fn scan_watermark() {
+    run_test(|store| -> Result<(), ()> {
+        let watermark = || store.scan_watermark(&TEST_SUBGRAPH_ID).unwrap();
+        assert_eq!(None, watermark());
+
+        // The scan that found block 3 found no triggers up to block 9
+        transact_entity_operations(&store, TEST_SUBGRAPH_ID.clone(), *TEST_BLOCK_3_PTR, vec![])
+            .unwrap();
+        let scanned = ScanWatermark {
+            block: 3,
+            scanned_up_to: 9,
+        };
+        store
+            .set_scan_watermark(&TEST_SUBGRAPH_ID, scanned)
+            .unwrap();
+        assert_eq!(Some(scanned), watermark());
+        assert_eq!(Some(10), scanned.resume_from(&TEST_BLOCK_3_PTR));
+
+        // Blocks after the block we revert to might be different now
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                *TEST_BLOCK_2_PTR,
+            )
+            .unwrap();
+        let reverted = watermark().unwrap();
+        assert_eq!(2, reverted.scanned_up_to);
+        assert_eq!(None, reverted.resume_from(&TEST_BLOCK_2_PTR));
+
+        // Creating a data source means there are more triggers to look for
+        store
+            .set_scan_watermark(
+                &TEST_SUBGRAPH_ID,
+                ScanWatermark {
+                    block: 2,
+                    scanned_up_to: 9,
+                },
+            )
+            .unwrap();
+        let dynamic_ds = DynamicEthereumContractDataSourceEntity::from((
+            &TEST_SUBGRAPH_ID.clone(),
+            &mock_data_source(),
+            &TEST_BLOCK_3_PTR.clone(),
+        ));
+        transact_entity_operations(
+            &store,
+            TEST_SUBGRAPH_ID.clone(),
+            *TEST_BLOCK_3_PTR,
+            dynamic_ds.write_entity_operations("dynamic-data-source"),
+        )
+        .unwrap();
+        assert_eq!(None, watermark());
+
+        Ok(())
+    })
+}
+
 #[test]
 fn revert_block_with_dynamic_data_source_operations() {
     run_test(|store| {
         // Create operations to add a user
         let user_key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: "1".to_owned(),
         };
         let partial_entity = Entity::from(vec![
@@ -1510,7 +1637,7 @@ fn revert_block_with_dynamic_data_source_operations() {
         // Verify that the dynamic data source exists afterwards
         let dynamic_ds_key = EntityKey {
             subgraph_id: SUBGRAPHS_ID.clone(),
-            entity_type: String::from(DynamicEthereumContractDataSourceEntity::TYPENAME),
+            entity_type: Word::from(DynamicEthereumContractDataSourceEntity::TYPENAME),
             entity_id: String::from("dynamic-data-source"),
         };
         store
@@ -1551,42 +1678,49 @@ fn revert_block_with_dynamic_data_source_operations() {
                         entity_type: USER.into(),
                         entity_id: "1".into(),
                         operation: EntityChangeOperation::Set,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "DynamicEthereumContractDataSource".into(),
                         entity_id: "dynamic-data-source".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "EthereumContractSource".into(),
                         entity_id: "dynamic-data-source-source".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "EthereumContractMapping".into(),
                         entity_id: "dynamic-data-source-mapping".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "EthereumContractDataSourceTemplate".into(),
                         entity_id: "dynamic-data-source-templates-0".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "EthereumContractDataSourceTemplateSource".into(),
                         entity_id: "dynamic-data-source-templates-0-source".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                     EntityChange {
                         subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                         entity_type: "EthereumContractMapping".into(),
                         entity_id: "dynamic-data-source-templates-0-mapping".into(),
                         operation: EntityChangeOperation::Removed,
+                        changed_fields: None,
                     },
                 ]
                 .into_iter(),
@@ -1611,6 +1745,9 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
             schema: schema.clone(),
             data_sources: vec![],
             graft: None,
+            history_blocks: None,
+            strict_bytes: false,
+            computed: None,
             templates: vec![],
         };
 
@@ -1650,7 +1787,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
                 .map(|(id, data)| EntityOperation::Set {
                     key: EntityKey {
                         subgraph_id: subgraph_id.clone(),
-                        entity_type: USER.to_owned(),
+                        entity_type: USER.into(),
                         entity_id: id.to_owned(),
                     },
                     data: data.to_owned(),
@@ -1667,7 +1804,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
         let update_op = EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: subgraph_id.clone(),
-                entity_type: USER.to_owned(),
+                entity_type: USER.into(),
                 entity_id: "1".to_owned(),
             },
             data: updated_entity.clone(),
@@ -1677,7 +1814,7 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
         let delete_op = EntityOperation::Remove {
             key: EntityKey {
                 subgraph_id: subgraph_id.clone(),
-                entity_type: USER.to_owned(),
+                entity_type: USER.into(),
                 entity_id: "2".to_owned(),
             },
         };
@@ -1698,12 +1835,14 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
                 entity_type: "SubgraphDeployment".to_owned(),
                 entity_id: "EntityChangeTestSubgraph".to_owned(),
                 operation: EntityChangeOperation::Set,
+                changed_fields: None,
             }]),
             StoreEvent::new(vec![EntityChange {
                 subgraph_id: SubgraphDeploymentId::new("subgraphs").unwrap(),
                 entity_type: "SubgraphDeployment".to_owned(),
                 entity_id: "EntityChangeTestSubgraph".to_owned(),
                 operation: EntityChangeOperation::Set,
+                changed_fields: None,
             }]),
         ];
 
@@ -1719,12 +1858,14 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
                     entity_type: USER.to_owned(),
                     entity_id: added_entities[0].clone().0,
                     operation: EntityChangeOperation::Set,
+                    changed_fields: None,
                 },
                 EntityChange {
                     subgraph_id: subgraph_id.clone(),
                     entity_type: USER.to_owned(),
                     entity_id: added_entities[1].clone().0,
                     operation: EntityChangeOperation::Set,
+                    changed_fields: None,
                 },
             ]),
             StoreEvent::new(vec![
@@ -1733,12 +1874,14 @@ fn entity_changes_are_fired_and_forwarded_to_subscriptions() {
                     entity_type: USER.to_owned(),
                     entity_id: "1".to_owned(),
                     operation: EntityChangeOperation::Set,
+                    changed_fields: None,
                 },
                 EntityChange {
                     subgraph_id: subgraph_id.clone(),
                     entity_type: USER.to_owned(),
                     entity_id: added_entities[1].clone().0,
                     operation: EntityChangeOperation::Removed,
+                    changed_fields: None,
                 },
             ]),
         ];
@@ -1907,7 +2050,7 @@ fn handle_large_string_with_index() {
 
         let key = EntityKey {
             subgraph_id: TEST_SUBGRAPH_ID.clone(),
-            entity_type: USER.to_owned(),
+            entity_type: USER.into(),
             entity_id: id.to_owned(),
         };
 
@@ -1957,10 +2100,7 @@ fn handle_large_string_with_index() {
 
         let query = user_query()
             .first(5)
-            .filter(EntityFilter::Equal(
-                NAME.to_owned(),
-                long_text.clone().into(),
-            ))
+            .filter(EntityFilter::Equal(NAME.into(), long_text.clone().into()))
             .asc(NAME);
 
         let ids = store
@@ -1978,7 +2118,7 @@ fn handle_large_string_with_index() {
         prefix.truncate(STRING_PREFIX_SIZE);
         let query = user_query()
             .first(5)
-            .filter(EntityFilter::LessOrEqual(NAME.to_owned(), prefix.into()))
+            .filter(EntityFilter::LessOrEqual(NAME.into(), prefix.into()))
             .asc(NAME);
 
         let ids = store
@@ -2048,7 +2188,7 @@ impl WindowQuery {
     fn asc(self, attr: &str) -> Self {
         WindowQuery(
             self.0
-                .order(EntityOrder::Ascending(attr.to_owned(), ValueType::String)),
+                .order(EntityOrder::Ascending(attr.into(), ValueType::String)),
             self.1,
         )
     }
@@ -2056,7 +2196,7 @@ impl WindowQuery {
     fn desc(self, attr: &str) -> Self {
         WindowQuery(
             self.0
-                .order(EntityOrder::Descending(attr.to_owned(), ValueType::String)),
+                .order(EntityOrder::Descending(attr.into(), ValueType::String)),
             self.1,
         )
     }
@@ -2112,7 +2252,7 @@ fn window() {
         EntityOperation::Set {
             key: EntityKey {
                 subgraph_id: TEST_SUBGRAPH_ID.clone(),
-                entity_type: entity_type.to_owned(),
+                entity_type: entity_type.into(),
                 entity_id: id.to_owned(),
             },
             data: entity,
@@ -2211,7 +2351,7 @@ fn find_at_block() {
     fn shaqueeena_at_block(block: BlockNumber, email: &'static str) {
         run_test(move |store| -> Result<(), ()> {
             let mut query = user_query()
-                .filter(EntityFilter::Equal("name".to_owned(), "Shaqueeena".into()))
+                .filter(EntityFilter::Equal("name".into(), "Shaqueeena".into()))
                 .desc("name");
             query.block = block;
 
@@ -2367,3 +2507,825 @@ fn reorg_tracking() {
         Ok(())
     })
 }
+
+#[test]
+fn revert_into_batched_blocks() {
+    fn john_at(age: i32) -> EntityModification {
+        match create_test_entity(
+            "1",
+            USER,
+            "Johnton",
+            "tonofjohn@email.com",
+            age,
+            184.4,
+            false,
+            None,
+        ) {
+            EntityOperation::Set { key, data } => EntityModification::Overwrite {
+                key,
+                data,
+                changed_fields: None,
+            },
+            EntityOperation::Remove { .. } => unreachable!(),
+        }
+    }
+
+    fn user_key(id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: USER.into(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    fn john_age(store: &Arc<DieselStore>) -> Value {
+        store
+            .get(user_key("1"))
+            .unwrap()
+            .expect("missing entity")
+            .get("age")
+            .cloned()
+            .unwrap()
+    }
+
+    fn block_number(store: &Arc<DieselStore>) -> u64 {
+        store
+            .block_ptr(TEST_SUBGRAPH_ID.clone())
+            .unwrap()
+            .expect("subgraph has a block pointer")
+            .number
+    }
+
+    run_test(|store| -> Result<(), ()> {
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            TEST_SUBGRAPH_ID.clone(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        let new_user = match create_test_entity(
+            "4",
+            USER,
+            "Batchy",
+            "batchy@email.com",
+            19,
+            72.5,
+            true,
+            None,
+        ) {
+            EntityOperation::Set { key, data } => EntityModification::Insert { key, data },
+            EntityOperation::Remove { .. } => unreachable!(),
+        };
+
+        // Write blocks 3 to 5 in one transaction
+        store
+            .transact_block_operations_batch(
+                TEST_SUBGRAPH_ID.clone(),
+                vec![
+                    (*TEST_BLOCK_3_PTR, vec![john_at(70)]),
+                    (*TEST_BLOCK_4_PTR, vec![john_at(71), new_user]),
+                    (
+                        *TEST_BLOCK_5_PTR,
+                        vec![EntityModification::Remove { key: user_key("2") }],
+                    ),
+                ],
+                stopwatch_metrics,
+            )
+            .unwrap();
+        assert_eq!(5, block_number(&store));
+        assert_eq!(Value::Int(71), john_age(&store));
+        assert!(store.get(user_key("2")).unwrap().is_none());
+        assert!(store.get(user_key("4")).unwrap().is_some());
+
+        // Each block in the batch can be reverted on its own
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_5_PTR,
+                *TEST_BLOCK_4_PTR,
+            )
+            .unwrap();
+        assert_eq!(4, block_number(&store));
+        assert_eq!(Value::Int(71), john_age(&store));
+        assert!(store.get(user_key("2")).unwrap().is_some());
+        assert!(store.get(user_key("4")).unwrap().is_some());
+
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        assert_eq!(3, block_number(&store));
+        assert_eq!(Value::Int(70), john_age(&store));
+        assert!(store.get(user_key("4")).unwrap().is_none());
+
+        // Processing continues normally from the middle of the batch
+        transact_entity_operations(&store, TEST_SUBGRAPH_ID.clone(), *TEST_BLOCK_4_PTR, vec![])
+            .unwrap();
+        assert_eq!(4, block_number(&store));
+        assert_eq!(Value::Int(70), john_age(&store));
+
+        Ok(())
+    })
+}
+
+#[test]
+fn remove_many() {
+    fn user_key(id: &str) -> EntityKey {
+        EntityKey {
+            subgraph_id: TEST_SUBGRAPH_ID.clone(),
+            entity_type: USER.into(),
+            entity_id: id.to_owned(),
+        }
+    }
+
+    run_test(|store| -> Result<(), ()> {
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            TEST_SUBGRAPH_ID.clone(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        let count = get_entity_count(store.clone(), &TEST_SUBGRAPH_ID);
+
+        // Removing an entity that does not exist is not an error
+        store
+            .transact_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                vec![EntityModification::RemoveMany {
+                    keys: vec![user_key("1"), user_key("3"), user_key("no such user")],
+                }],
+                stopwatch_metrics,
+            )
+            .unwrap();
+        assert_eq!(
+            count - 2,
+            get_entity_count(store.clone(), &TEST_SUBGRAPH_ID)
+        );
+        assert!(store.get(user_key("1")).unwrap().is_none());
+        assert!(store.get(user_key("2")).unwrap().is_some());
+        assert!(store.get(user_key("3")).unwrap().is_none());
+
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                *TEST_BLOCK_2_PTR,
+            )
+            .unwrap();
+        assert_eq!(count, get_entity_count(store.clone(), &TEST_SUBGRAPH_ID));
+        assert!(store.get(user_key("1")).unwrap().is_some());
+        assert!(store.get(user_key("3")).unwrap().is_some());
+
+        Ok(())
+    })
+}
+
+#[test]
+fn batch_must_advance_block_pointer() {
+    run_test(|store| -> Result<(), ()> {
+        let stopwatch_metrics = StopwatchMetrics::new(
+            Logger::root(slog::Discard, o!()),
+            TEST_SUBGRAPH_ID.clone(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+
+        // Blocks in a batch must come in order
+        let res = store.transact_block_operations_batch(
+            TEST_SUBGRAPH_ID.clone(),
+            vec![(*TEST_BLOCK_4_PTR, vec![]), (*TEST_BLOCK_3_PTR, vec![])],
+            stopwatch_metrics,
+        );
+        match res {
+            Err(StoreError::DuplicateBlockProcessing(_, 3)) => (),
+            _ => panic!("expected DuplicateBlockProcessing, got {:?}", res),
+        }
+
+        // Nothing was written
+        assert_eq!(
+            Some(*TEST_BLOCK_2_PTR),
+            store.block_ptr(TEST_SUBGRAPH_ID.clone()).unwrap()
+        );
+
+        Ok(())
+    })
+}
+
+fn subgraph_error(message: &str, block_ptr: &EthereumBlockPointer) -> SubgraphError {
+    SubgraphError {
+        subgraph_id: TEST_SUBGRAPH_ID.clone(),
+        message: message.to_owned(),
+        block_ptr: Some(*block_ptr),
+        handler: Some("handleTransfer".to_owned()),
+        deterministic: false,
+        count: 1,
+    }
+}
+
+fn fatal_error(store: &Arc<DieselStore>) -> Option<(String, u64, u32)> {
+    store
+        .deployment_state_from_id(TEST_SUBGRAPH_ID.clone())
+        .expect("can get deployment state")
+        .fatal_error
+        .map(|error| (error.message, error.block_ptr.unwrap().number, error.count))
+}
+
+fn stored_error_count() -> i64 {
+    let url = postgres_test_url();
+    let conn = PgConnection::establish(url.as_str()).expect("Failed to connect to Postgres");
+    diesel::select(diesel::dsl::sql::<diesel::sql_types::BigInt>(&format!(
+        "(select count(*) from subgraphs.subgraph_error where subgraph_id = '{}')",
+        TEST_SUBGRAPH_ID.as_str()
+    )))
+    .get_result::<i64>(&conn)
+    .unwrap()
+}
+
+#[test]
+fn fail_subgraph_deduplicates_errors() {
+    run_test(|store| -> Result<(), ()> {
+        assert_eq!(None, fatal_error(&store));
+
+        store
+            .fail_subgraph(
+                &TEST_SUBGRAPH_ID,
+                subgraph_error("boom", &*TEST_BLOCK_1_PTR),
+            )
+            .unwrap();
+        assert_eq!(Some(("boom".to_owned(), 1, 1)), fatal_error(&store));
+
+        // The same error again, in the same and in later blocks
+        for block_ptr in &[*TEST_BLOCK_1_PTR, *TEST_BLOCK_2_PTR] {
+            store
+                .fail_subgraph(&TEST_SUBGRAPH_ID, subgraph_error("boom", block_ptr))
+                .unwrap();
+        }
+        assert_eq!(Some(("boom".to_owned(), 1, 3)), fatal_error(&store));
+        assert_eq!(1, stored_error_count());
+
+        // A different error is stored separately
+        store
+            .fail_subgraph(
+                &TEST_SUBGRAPH_ID,
+                subgraph_error("bang", &*TEST_BLOCK_2_PTR),
+            )
+            .unwrap();
+        assert_eq!(Some(("bang".to_owned(), 2, 1)), fatal_error(&store));
+        assert_eq!(2, stored_error_count());
+
+        // Long messages are truncated
+        let long = "x".repeat(100_000);
+        store
+            .fail_subgraph(&TEST_SUBGRAPH_ID, subgraph_error(&long, &*TEST_BLOCK_2_PTR))
+            .unwrap();
+        let (message, _, _) = fatal_error(&store).unwrap();
+        assert!(message.len() <= 10_000);
+        assert!(message.ends_with("(truncated)"));
+        Ok(())
+    })
+}
+
+#[test]
+fn revert_deduplicated_errors() {
+    run_test(|store| -> Result<(), ()> {
+        for block_ptr in &[*TEST_BLOCK_3_PTR, *TEST_BLOCK_4_PTR] {
+            transact_entity_operations(&store, TEST_SUBGRAPH_ID.clone(), *block_ptr, vec![])
+                .unwrap();
+        }
+        for block_ptr in &[*TEST_BLOCK_2_PTR, *TEST_BLOCK_3_PTR, *TEST_BLOCK_4_PTR] {
+            store
+                .fail_subgraph(&TEST_SUBGRAPH_ID, subgraph_error("boom", block_ptr))
+                .unwrap();
+        }
+        assert_eq!(Some(("boom".to_owned(), 2, 3)), fatal_error(&store));
+
+        // Reverting the last block in which the error happened removes one
+        // occurrence
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_4_PTR,
+                *TEST_BLOCK_3_PTR,
+            )
+            .unwrap();
+        assert_eq!(Some(("boom".to_owned(), 2, 2)), fatal_error(&store));
+
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_3_PTR,
+                *TEST_BLOCK_2_PTR,
+            )
+            .unwrap();
+        assert_eq!(Some(("boom".to_owned(), 2, 1)), fatal_error(&store));
+        assert_eq!(1, stored_error_count());
+
+        // Reverting the block in which the error happened first removes it
+        store
+            .revert_block_operations(
+                TEST_SUBGRAPH_ID.clone(),
+                *TEST_BLOCK_2_PTR,
+                *TEST_BLOCK_1_PTR,
+            )
+            .unwrap();
+        assert_eq!(None, fatal_error(&store));
+        assert_eq!(0, stored_error_count());
+        Ok(())
+    })
+}
+
+#[test]
+fn call_cache_stores_reverts() {
+    run_test(|store| -> Result<(), ()> {
+        let address = Address::from_low_u64_be(0xcafe);
+        let block = *TEST_BLOCK_3_PTR;
+
+        store
+            .set_call("provider", address, b"output", block, b"42")
+            .unwrap();
+        assert_eq!(
+            Some(Ok(b"42".to_vec())),
+            store.get_call(address, b"output", block).unwrap()
+        );
+
+        // A revert with the data the contract reverted with
+        let revert = EthereumCallRevert::from_data(hex!("cf479181").to_vec());
+        store
+            .set_call_revert("provider", address, b"custom error", block, &revert)
+            .unwrap();
+        assert_eq!(
+            Some(Err(revert)),
+            store.get_call(address, b"custom error", block).unwrap()
+        );
+
+        // A revert that the Ethereum node only gave a message for
+        let revert = EthereumCallRevert::with_reason("execution reverted");
+        store
+            .set_call_revert("provider", address, b"no data", block, &revert)
+            .unwrap();
+        assert_eq!(
+            Some(Err(revert)),
+            store.get_call(address, b"no data", block).unwrap()
+        );
+
+        assert_eq!(None, store.get_call(address, b"not cached", block).unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn call_cache_records_provenance() {
+    run_test(|store| -> Result<(), ()> {
+        let cafe = Address::from_low_u64_be(0xcafe);
+        let beef = Address::from_low_u64_be(0xbeef);
+        let block = *TEST_BLOCK_5_PTR;
+
+        // The call cache is not cleared between tests
+        let conn = PgConnection::establish(postgres_test_url().as_str())
+            .expect("Failed to connect to Postgres");
+        diesel::sql_query(format!(
+            "delete from eth_call_cache where block_number = {}",
+            block.number
+        ))
+        .execute(&conn)
+        .unwrap();
+
+        store
+            .set_call("0123456789abcdef", cafe, b"output", block, b"42")
+            .unwrap();
+        let revert = EthereumCallRevert::with_reason("execution reverted");
+        store
+            .set_call_revert("fedcba9876543210", beef, b"reverts", block, &revert)
+            .unwrap();
+
+        // Calls that were cached before we recorded where they came from
+        diesel::sql_query(format!(
+            "insert into eth_call_cache(id, return_value, contract_address, block_number)
+             values ('\\x01', '\\x2a', '\\x{}', {})",
+            hex::encode(beef.as_bytes()),
+            block.number
+        ))
+        .execute(&conn)
+        .unwrap();
+
+        let calls = store.cached_calls(block.number, None, 10).unwrap();
+        assert_eq!(3, calls.len());
+
+        let calls = store.cached_calls(block.number, Some(cafe), 10).unwrap();
+        assert_eq!(1, calls.len());
+        assert_eq!(cafe, calls[0].contract_address);
+        assert_eq!(block.number, calls[0].block_number);
+        assert_eq!(b"42".to_vec(), calls[0].return_value);
+        assert_eq!(None, calls[0].revert_reason);
+        assert_eq!("0123456789abcdef", calls[0].provenance.provider);
+        assert!(calls[0].provenance.fetched_at.is_some());
+
+        let calls = store.cached_calls(block.number, Some(beef), 10).unwrap();
+        let providers: Vec<_> = calls
+            .iter()
+            .map(|call| call.provenance.provider.as_str())
+            .collect();
+        assert_eq!(2, providers.len());
+        assert!(providers.contains(&"fedcba9876543210"));
+        assert!(providers.contains(&Provenance::UNKNOWN_PROVIDER));
+        let legacy = calls
+            .iter()
+            .find(|call| call.provenance.provider == Provenance::UNKNOWN_PROVIDER)
+            .unwrap();
+        assert_eq!(None, legacy.provenance.fetched_at);
+        let reverted = calls
+            .iter()
+            .find(|call| call.revert_reason.is_some())
+            .unwrap();
+        assert_eq!(
+            Some("execution reverted".to_owned()),
+            reverted.revert_reason
+        );
+
+        assert_eq!(1, store.cached_calls(block.number, None, 1).unwrap().len());
+        assert!(store
+            .cached_calls(block.number + 1, None, 10)
+            .unwrap()
+            .is_empty());
+        Ok(())
+    })
+}
+
+#[test]
+fn prune_history() {
+    // Pruning only works with relational storage
+    if !*USING_RELATIONAL_STORAGE {
+        return;
+    }
+
+    fn shaqueeenas_at(store: &DieselStore, block: BlockNumber) -> usize {
+        let query = EntityQuery::new(
+            TEST_SUBGRAPH_ID.clone(),
+            block,
+            EntityCollection::All(vec![USER.to_owned()]),
+        )
+        .filter(EntityFilter::Equal("id".into(), "3".into()));
+        store.find(query).expect("find succeeds").len()
+    }
+
+    run_test(|store| -> Result<(), ()> {
+        let logger = Logger::root(slog::Discard, o!());
+        let node = NodeId::new("test").unwrap();
+
+        assert!(store
+            .set_history_blocks(&*TEST_SUBGRAPH_ID, Some(0))
+            .unwrap());
+        assert!(!store
+            .set_history_blocks(&SubgraphDeploymentId::new("nonexistent").unwrap(), None)
+            .unwrap());
+
+        // Deployments that are not synced and assigned to this node are
+        // not pruned
+        store.store().prune_deployments(&logger, &node).unwrap();
+        assert_eq!(1, shaqueeenas_at(&store, 1));
+
+        let mut ops = SubgraphDeploymentEntity::update_synced_operations(&*TEST_SUBGRAPH_ID, true);
+        ops.extend(
+            SubgraphDeploymentAssignmentEntity::new(node.clone())
+                .write_operations(&*TEST_SUBGRAPH_ID),
+        );
+        store.apply_metadata_operations(ops).unwrap();
+
+        // The deployment is at block 2 and keeps no history; the version of
+        // user 3 that was current at block 1 is removed
+        store.store().prune_deployments(&logger, &node).unwrap();
+        assert_eq!(0, shaqueeenas_at(&store, 1));
+        assert_eq!(1, shaqueeenas_at(&store, 2));
+
+        let state = store
+            .deployment_state_from_id(TEST_SUBGRAPH_ID.clone())
+            .unwrap();
+        assert_eq!(2, state.earliest_block_number);
+
+        // Pruning again does not change anything
+        store.store().prune_deployments(&logger, &node).unwrap();
+        assert_eq!(1, shaqueeenas_at(&store, 2));
+        Ok(())
+    })
+}
+
+#[test]
+fn flag_deployment() {
+    run_test(|store| -> Result<(), ()> {
+        assert!(store.flagged_deployments().unwrap().is_empty());
+        assert!(!store
+            .set_deployment_flag(
+                &SubgraphDeploymentId::new("nonexistent").unwrap(),
+                Some("down".to_owned())
+            )
+            .unwrap());
+
+        assert!(store
+            .set_deployment_flag(&*TEST_SUBGRAPH_ID, Some("bad data".to_owned()))
+            .unwrap());
+        let flags = store.flagged_deployments().unwrap();
+        assert_eq!(1, flags.len());
+        assert_eq!("bad data", flags[&*TEST_SUBGRAPH_ID].note);
+        assert!(flags[&*TEST_SUBGRAPH_ID].flagged_at > 0);
+
+        let state = store
+            .deployment_state_from_id(TEST_SUBGRAPH_ID.clone())
+            .unwrap();
+        assert_eq!(
+            Some("bad data"),
+            state.flag.as_ref().map(|flag| flag.note.as_str())
+        );
+
+        assert!(store.set_deployment_flag(&*TEST_SUBGRAPH_ID, None).unwrap());
+        assert!(store.flagged_deployments().unwrap().is_empty());
+        let state = store
+            .deployment_state_from_id(TEST_SUBGRAPH_ID.clone())
+            .unwrap();
+        assert_eq!(None, state.flag);
+        Ok(())
+    })
+}
+
+/// Create one inconsistency in the subgraph metadata of each kind
+fn create_inconsistent_metadata(store: &DieselStore) {
+    let node = NodeId::new("test").unwrap();
+    let no_deployment = SubgraphDeploymentId::new("inconsistentNoDeployment").unwrap();
+    let no_schema = SubgraphDeploymentId::new("inconsistentNoSchema").unwrap();
+    let no_metadata = SubgraphDeploymentId::new("inconsistentNoMetadata").unwrap();
+
+    let manifest = |id: &SubgraphDeploymentId| SubgraphManifest {
+        id: id.clone(),
+        location: "/ipfs/test".to_owned(),
+        spec_version: "1".to_owned(),
+        description: None,
+        repository: None,
+        schema: Schema::parse(USER_GQL, id.clone()).expect("Failed to parse user schema"),
+        data_sources: vec![],
+        graft: None,
+        history_blocks: None,
+        strict_bytes: false,
+        computed: None,
+        templates: vec![],
+    };
+
+    // An assignment of a deployment that does not exist
+    let mut ops =
+        SubgraphDeploymentAssignmentEntity::new(node.clone()).write_operations(&no_deployment);
+
+    // An assigned deployment without a database schema
+    ops.extend(
+        SubgraphDeploymentEntity::new(&manifest(&no_schema), false, None)
+            .create_operations(&no_schema),
+    );
+    ops.extend(SubgraphDeploymentAssignmentEntity::new(node).write_operations(&no_schema));
+
+    // A version of a deployment that does not exist
+    ops.extend(
+        SubgraphEntity::new(
+            SubgraphName::new("inconsistent/version").unwrap(),
+            Some("inconsistentVersion".to_owned()),
+            None,
+            0,
+        )
+        .write_operations("inconsistentVersionSubgraph"),
+    );
+    ops.extend(
+        SubgraphVersionEntity::new(
+            "inconsistentVersionSubgraph".to_owned(),
+            SubgraphDeploymentId::new("inconsistentGone").unwrap(),
+            0,
+        )
+        .write_operations("inconsistentVersion"),
+    );
+
+    // A subgraph name whose pending version does not exist
+    ops.extend(
+        SubgraphEntity::new(
+            SubgraphName::new("inconsistent/name").unwrap(),
+            None,
+            Some("inconsistentMissingVersion".to_owned()),
+            0,
+        )
+        .write_operations("inconsistentNameSubgraph"),
+    );
+    store.apply_metadata_operations(ops).unwrap();
+
+    // A database schema for a deployment whose metadata is gone
+    let schema = Schema::parse(USER_GQL, no_metadata.clone()).unwrap();
+    let ops = SubgraphDeploymentEntity::new(&manifest(&no_metadata), false, None)
+        .create_operations(&no_metadata);
+    store.create_subgraph_deployment(&schema, ops).unwrap();
+    let conn = PgConnection::establish(postgres_test_url().as_str())
+        .expect("Failed to connect to Postgres");
+    diesel::sql_query("delete from subgraphs.subgraph_deployment where id = $1")
+        .bind::<diesel::sql_types::Text, _>(no_metadata.as_str())
+        .execute(&conn)
+        .unwrap();
+}
+
+/// Summarize `issues` as (kind, deployment, subgraph name, remediation)
+fn summarize_health_issues(
+    issues: &[HealthIssue],
+) -> Vec<(
+    HealthIssueKind,
+    Option<&str>,
+    Option<&str>,
+    HealthRemediation,
+)> {
+    issues
+        .iter()
+        .map(|issue| {
+            (
+                issue.kind,
+                issue.deployment.as_deref(),
+                issue.subgraph_name.as_deref(),
+                issue.remediation,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn consistency_check_skips_affected_deployments() {
+    use HealthIssueKind::*;
+    use HealthRemediation::{Repaired, Skipped};
+
+    run_test(|store| -> Result<(), ()> {
+        let logger = Logger::root(slog::Discard, o!());
+
+        // The test data is consistent
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Skip)
+            .unwrap();
+        assert!(issues.is_empty());
+
+        create_inconsistent_metadata(&store);
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Skip)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    AssignmentWithoutDeployment,
+                    Some("inconsistentNoDeployment"),
+                    None,
+                    Skipped
+                ),
+                (
+                    DeploymentWithoutSchema,
+                    Some("inconsistentNoSchema"),
+                    None,
+                    Skipped
+                ),
+                (
+                    SchemaWithoutDeployment,
+                    Some("inconsistentNoMetadata"),
+                    None,
+                    Skipped
+                ),
+                (
+                    VersionWithoutDeployment,
+                    Some("inconsistentGone"),
+                    Some("inconsistent/version"),
+                    Skipped
+                ),
+                (
+                    NameWithoutVersion,
+                    None,
+                    Some("inconsistent/name"),
+                    HealthRemediation::None
+                ),
+            ],
+            summarize_health_issues(&issues)
+        );
+        assert_eq!(issues, store.health_issues().unwrap());
+
+        // Skipped deployments can not be started
+        let err = store
+            .start_subgraph_deployment(
+                &logger,
+                &SubgraphDeploymentId::new("inconsistentNoMetadata").unwrap(),
+                vec![],
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("metadata is inconsistent"));
+        Ok(())
+    })
+}
+
+#[test]
+fn consistency_check_repairs_assignments() {
+    use HealthIssueKind::*;
+    use HealthRemediation::{Repaired, Skipped};
+
+    run_test(|store| -> Result<(), ()> {
+        let logger = Logger::root(slog::Discard, o!());
+
+        create_inconsistent_metadata(&store);
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Repair)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    AssignmentWithoutDeployment,
+                    Some("inconsistentNoDeployment"),
+                    None,
+                    Repaired
+                ),
+                (
+                    DeploymentWithoutSchema,
+                    Some("inconsistentNoSchema"),
+                    None,
+                    Repaired
+                ),
+                (
+                    SchemaWithoutDeployment,
+                    Some("inconsistentNoMetadata"),
+                    None,
+                    Skipped
+                ),
+                (
+                    VersionWithoutDeployment,
+                    Some("inconsistentGone"),
+                    Some("inconsistent/version"),
+                    Skipped
+                ),
+                (
+                    NameWithoutVersion,
+                    None,
+                    Some("inconsistent/name"),
+                    HealthRemediation::None
+                ),
+            ],
+            summarize_health_issues(&issues)
+        );
+
+        // The assignments are gone; the deployment without a schema is
+        // still there and is now skipped
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Repair)
+            .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    DeploymentWithoutSchema,
+                    Some("inconsistentNoSchema"),
+                    None,
+                    Skipped
+                ),
+                (
+                    SchemaWithoutDeployment,
+                    Some("inconsistentNoMetadata"),
+                    None,
+                    Skipped
+                ),
+                (
+                    VersionWithoutDeployment,
+                    Some("inconsistentGone"),
+                    Some("inconsistent/version"),
+                    Skipped
+                ),
+                (
+                    NameWithoutVersion,
+                    None,
+                    Some("inconsistent/name"),
+                    HealthRemediation::None
+                ),
+            ],
+            summarize_health_issues(&issues)
+        );
+        assert_eq!(issues, store.health_issues().unwrap());
+        Ok(())
+    })
+}
+
+#[test]
+fn consistency_check_only_reports_when_refusing() {
+    run_test(|store| -> Result<(), ()> {
+        let logger = Logger::root(slog::Discard, o!());
+
+        create_inconsistent_metadata(&store);
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Refuse)
+            .unwrap();
+        assert_eq!(5, issues.len());
+        assert!(issues
+            .iter()
+            .all(|issue| issue.remediation == HealthRemediation::None));
+
+        // Nothing was repaired
+        let issues = store
+            .store()
+            .check_consistency(&logger, ConsistencyPolicy::Refuse)
+            .unwrap();
+        assert_eq!(5, issues.len());
+        Ok(())
+    })
+}